@@ -71,6 +71,12 @@ fn main() {
         ]);
     }
 
+    // Link in the bonding driver if desired.
+    #[cfg(feature = "bonding")]
+    {
+        lib_names.push("rte_net_bond");
+    }
+
     // Step 1: Now that we've compiled and installed DPDK, point cargo to the libraries.
     println!(
         "cargo:rustc-link-search=native={}",
@@ -80,6 +86,24 @@ fn main() {
         println!("cargo:rustc-link-lib={}", lib_name);
     }
 
+    // Link in the external GPU matcher if desired.
+    #[cfg(feature = "gpu-match")]
+    {
+        println!("cargo:rerun-if-env-changed=RETINA_GPU_PATH");
+        if let Ok(gpu_path) = env::var("RETINA_GPU_PATH") {
+            println!("cargo:rustc-link-search=native={}", gpu_path);
+        }
+    }
+
+    // Link in the external YARA scanner if desired.
+    #[cfg(feature = "yara-match")]
+    {
+        println!("cargo:rerun-if-env-changed=RETINA_YARA_PATH");
+        if let Ok(yara_path) = env::var("RETINA_YARA_PATH") {
+            println!("cargo:rustc-link-search=native={}", yara_path);
+        }
+    }
+
     // Step 2: Generate bindings for the DPDK headers.
     let mut builder = Builder::default();
     for header_location in &header_locations {
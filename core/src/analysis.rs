@@ -0,0 +1,205 @@
+//! Offline analysis of rule sets on historical captures.
+//!
+//! [coverage](coverage) replays a pcap or pcapng file through a rule set, like
+//! [testing::run_pcap](crate::testing::run_pcap) does, and reports what the rule set would have
+//! caught: for every rule, the number of matching packets and flows, the timestamps of its first
+//! and last matches, and an estimate of the storage volume of its matched flows. A flow is taken
+//! to be stored from the packet it first matched on, the way packet callbacks usually capture
+//! matching flows. The estimate counts the packets' frame bytes, without file format overhead,
+//! compression or [storage quotas](crate::filter::quota).
+//!
+//! The [CoverageReport](CoverageReport) serializes to JSON, e.g. for QA dashboards.
+//!
+//! ## Example
+//! ```
+//! let rules = vec![Rule::new("(?i)user-agent: curl")];
+//! let report = analysis::coverage("traces/monday.pcap", rules, &default_config())?;
+//! std::fs::write("coverage.json", report.to_json()?)?;
+//! ```
+
+use crate::config::RuntimeConfig;
+use crate::filter::rule::Rule;
+use crate::filter::store::{StoreReader, StoredPacket};
+use crate::memory::mbuf::Mbuf;
+use crate::protocols::layer4::Flow;
+use crate::testing;
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// What one rule would have caught on a capture.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleCoverage {
+    /// Pattern of the rule.
+    pub pattern: String,
+    /// Number of packets the rule matched.
+    pub nb_packets: u64,
+    /// Number of flows the rule matched.
+    pub nb_flows: u64,
+    /// Timestamp of the first matching packet in the capture, `None` if the rule never matched.
+    pub first_match: Option<Duration>,
+    /// Timestamp of the last matching packet in the capture, `None` if the rule never matched.
+    pub last_match: Option<Duration>,
+    /// Estimated frame bytes stored for the flows the rule matched, from their first match.
+    pub nb_stored_bytes: u64,
+}
+
+impl RuleCoverage {
+    fn new(pattern: String) -> Self {
+        RuleCoverage {
+            pattern,
+            nb_packets: 0,
+            nb_flows: 0,
+            first_match: None,
+            last_match: None,
+            nb_stored_bytes: 0,
+        }
+    }
+}
+
+/// What a rule set would have caught on a capture.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CoverageReport {
+    /// Number of packets in the capture.
+    pub nb_packets: usize,
+    /// Number of packets that could not be parsed or did not fit in an mbuf.
+    pub nb_unparsed: usize,
+    /// Number of packets that matched any rule.
+    pub nb_matched_packets: u64,
+    /// Number of flows that matched any rule.
+    pub nb_matched_flows: u64,
+    /// Estimated frame bytes stored for all matched flows, from their first match.
+    pub nb_stored_bytes: u64,
+    /// Coverage of each rule, in rule set order, followed by the YARA rules that matched.
+    pub rules: Vec<RuleCoverage>,
+}
+
+impl CoverageReport {
+    /// Returns the coverage of the rule with `pattern`, `None` if it is not in the report.
+    pub fn rule(&self, pattern: &str) -> Option<&RuleCoverage> {
+        self.rules.iter().find(|rule| rule.pattern == pattern)
+    }
+
+    /// Returns the patterns of the rules that never matched.
+    pub fn unmatched_rules(&self) -> Vec<&str> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.nb_packets == 0)
+            .map(|rule| rule.pattern.as_str())
+            .collect()
+    }
+
+    /// Serializes the report to pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Replays the Ethernet capture `path`, pcap or pcapng, through a filter loaded with `rules` and
+/// configured with `config`, and reports what each rule would have caught.
+pub fn coverage(
+    path: impl AsRef<Path>,
+    rules: Vec<Rule>,
+    config: &RuntimeConfig,
+) -> Result<CoverageReport> {
+    let path = path.as_ref();
+    let mut coverage = Coverage::new(&rules);
+    let filter_ctx = testing::replay_filter(rules, config)?;
+    let mut mempool = testing::test_mempool(&config.mempool)?;
+    let mut reader = StoreReader::open(path)?;
+    while let Some(StoredPacket { ts, data, .. }) = reader.next_packet()? {
+        coverage.report.nb_packets += 1;
+        let mbuf = match Mbuf::from_bytes(&data, mempool.raw_mut()) {
+            Ok(mbuf) => mbuf,
+            Err(error) => {
+                let index = coverage.report.nb_packets - 1;
+                log::warn!("Packet {} of {} skipped: {}", index, path.display(), error);
+                coverage.report.nb_unparsed += 1;
+                continue;
+            }
+        };
+        let ctx = match filter_ctx.parse_l4(&mbuf) {
+            Ok(ctx) => ctx,
+            Err(_) => {
+                coverage.report.nb_unparsed += 1;
+                continue;
+            }
+        };
+        let flow = filter_ctx.track_flow(&ctx, mbuf.data_len());
+        let patterns = match mbuf.l4_payload(&ctx) {
+            Some(payload) => filter_ctx.flow_match_patterns(&flow, payload),
+            None => {
+                coverage.report.nb_unparsed += 1;
+                continue;
+            }
+        };
+        coverage.packet(ts, flow, mbuf.data_len() as u64, patterns.unwrap_or_default());
+    }
+    Ok(coverage.report)
+}
+
+/// Coverage of a replay in progress.
+struct Coverage {
+    report: CoverageReport,
+    /// Position of each pattern in the report.
+    indices: HashMap<String, usize>,
+    /// Positions of the rules that matched each flow, in the report.
+    flows: HashMap<Flow, BTreeSet<usize>>,
+}
+
+impl Coverage {
+    fn new(rules: &[Rule]) -> Self {
+        let mut coverage = Coverage {
+            report: CoverageReport::default(),
+            indices: HashMap::new(),
+            flows: HashMap::new(),
+        };
+        for rule in rules.iter() {
+            coverage.index(&rule.pattern);
+        }
+        coverage
+    }
+
+    /// Returns the position of `pattern` in the report, adding it if needed.
+    fn index(&mut self, pattern: &str) -> usize {
+        if let Some(index) = self.indices.get(pattern) {
+            return *index;
+        }
+        let index = self.report.rules.len();
+        self.report.rules.push(RuleCoverage::new(pattern.to_owned()));
+        self.indices.insert(pattern.to_owned(), index);
+        index
+    }
+
+    /// Counts a packet of `flow` with `nb_bytes` frame bytes, received at `ts`, that matched the
+    /// rules with `patterns`.
+    fn packet(&mut self, ts: Duration, flow: Flow, nb_bytes: u64, patterns: Vec<String>) {
+        if !patterns.is_empty() {
+            self.report.nb_matched_packets += 1;
+        }
+        for pattern in patterns.iter() {
+            let index = self.index(pattern);
+            let rule = &mut self.report.rules[index];
+            rule.nb_packets += 1;
+            rule.first_match.get_or_insert(ts);
+            rule.last_match = Some(ts);
+            let matched = self.flows.entry(flow).or_insert_with(|| {
+                self.report.nb_matched_flows += 1;
+                BTreeSet::new()
+            });
+            if matched.insert(index) {
+                rule.nb_flows += 1;
+            }
+        }
+        if let Some(matched) = self.flows.get(&flow) {
+            self.report.nb_stored_bytes += nb_bytes;
+            for index in matched.iter() {
+                self.report.rules[*index].nb_stored_bytes += nb_bytes;
+            }
+        }
+    }
+}
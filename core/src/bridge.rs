@@ -0,0 +1,366 @@
+//! Async bridge for downstream I/O.
+//!
+//! Async handlers push alerts and flow events to async services, such as HTTP APIs or databases,
+//! without blocking packet processing. They are registered on the [AsyncBridge](AsyncBridge) of a
+//! [FilterCtx](crate::filter::FilterCtx) before the runtime is created, and run on a tokio runtime
+//! owned by the Retina runtime, configured with the `[async_bridge]` options of the runtime
+//! configuration (see [AsyncBridgeConfig](crate::config::AsyncBridgeConfig)). The bridge requires
+//! the `async-bridge` feature.
+//!
+//! Events are queued in a bounded queue, and a dispatcher thread spawns the handlers of each event
+//! on the tokio runtime, up to a maximum number of handlers in flight. Queuing never blocks: if
+//! the queue is full, the event is dropped and counted, so slow services do not affect packet
+//! processing. Queue depth and delivery counters are reported by the monitor.
+//!
+//! Alert handlers receive every alert published to alert subscribers (see
+//! [alert](crate::filter::alert)), in the same JSON format, even if no subscriber socket is
+//! configured. Flow handlers receive new flows as they are added to the flow table, and the
+//! [FlowSummary](crate::hooks::FlowSummary) of flows pruned from it. On shutdown, queued events
+//! are dispatched, and handlers in flight are given the configured timeout to complete.
+//!
+//! ## Example
+//! ```
+//! let filter_ctx = FilterCtx::new(100_000, Duration::from_secs(60), regexes);
+//! let client = reqwest::Client::new();
+//! filter_ctx.async_bridge().on_alert(move |alert| {
+//!     let request = client.post("http://alerts.example.com/").json(&alert);
+//!     async move {
+//!         if let Err(error) = request.send().await {
+//!             log::error!("Alert delivery error: {}", error);
+//!         }
+//!     }
+//! });
+//! let mut runtime = Runtime::new(config, callback, &filter_ctx).unwrap();
+//! runtime.run();
+//! ```
+
+use crate::config::AsyncBridgeConfig;
+use crate::hooks::{FlowSummary, Hooks};
+use crate::protocols::layer4::Flow;
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+#[cfg(feature = "async-bridge")]
+use std::sync::Condvar;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+
+use anyhow::Result;
+use crossbeam_channel::Sender;
+use serde::Serialize;
+
+/// Future returned by an async handler.
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Handler<T> = Box<dyn Fn(T) -> BoxFuture + Send + Sync>;
+
+/// An event queued for async handlers.
+#[derive(Debug)]
+enum BridgeEvent {
+    Alert(serde_json::Value),
+    FlowNew(Flow),
+    FlowEnd(FlowSummary),
+}
+
+/// Queue depth and delivery counters of the async bridge.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct AsyncBridgeStats {
+    /// Number of events in the queue.
+    pub depth: usize,
+    /// Capacity of the queue.
+    pub capacity: usize,
+    /// Largest number of events in the queue.
+    pub max_depth: usize,
+    /// Number of events queued.
+    pub nb_queued: u64,
+    /// Number of events dropped because the queue was full.
+    pub nb_dropped: u64,
+    /// Number of handlers running.
+    pub in_flight: usize,
+    /// Number of handlers that completed.
+    pub nb_completed: u64,
+}
+
+/// Number of handlers in flight, bounded by a maximum.
+#[derive(Debug, Default)]
+struct InFlight {
+    count: Mutex<usize>,
+    #[cfg(feature = "async-bridge")]
+    released: Condvar,
+    max: AtomicUsize,
+    nb_completed: AtomicU64,
+}
+
+impl InFlight {
+    /// Waits until fewer than the maximum number of handlers are in flight, and counts a new one.
+    #[cfg(feature = "async-bridge")]
+    fn acquire(self: &Arc<Self>) -> Permit {
+        let max = self.max.load(Ordering::Relaxed);
+        let mut count = self.count.lock().unwrap();
+        while *count >= max {
+            count = self.released.wait(count).unwrap();
+        }
+        *count += 1;
+        Permit(Arc::clone(self))
+    }
+
+    fn count(&self) -> usize {
+        *self.count.lock().unwrap()
+    }
+}
+
+/// A handler in flight, released on drop.
+#[cfg(feature = "async-bridge")]
+struct Permit(Arc<InFlight>);
+
+#[cfg(feature = "async-bridge")]
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self.0.count.lock().unwrap() -= 1;
+        self.0.nb_completed.fetch_add(1, Ordering::Relaxed);
+        self.0.released.notify_one();
+    }
+}
+
+/// Registry of async handlers and queue to the tokio runtime that runs them.
+#[derive(Default)]
+pub struct AsyncBridge {
+    alert: RwLock<Vec<Handler<serde_json::Value>>>,
+    flow_new: RwLock<Vec<Handler<Flow>>>,
+    flow_end: RwLock<Vec<Handler<FlowSummary>>>,
+    /// Queue to the dispatcher thread, `None` until the bridge is started.
+    queue: RwLock<Option<Sender<BridgeEvent>>>,
+    capacity: AtomicUsize,
+    max_depth: AtomicUsize,
+    nb_queued: AtomicU64,
+    nb_dropped: AtomicU64,
+    in_flight: Arc<InFlight>,
+    dispatcher: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AsyncBridge {
+    /// Creates an empty async bridge.
+    pub fn new() -> Self {
+        AsyncBridge::default()
+    }
+
+    /// Registers an async handler invoked with every published alert.
+    pub fn on_alert<F, Fut>(&self, handler: F)
+    where
+        F: Fn(serde_json::Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.alert
+            .write()
+            .unwrap()
+            .push(Box::new(move |alert| Box::pin(handler(alert))));
+    }
+
+    /// Registers an async handler invoked when a new flow is added to the flow table.
+    pub fn on_flow_new<F, Fut>(&self, handler: F)
+    where
+        F: Fn(Flow) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.flow_new
+            .write()
+            .unwrap()
+            .push(Box::new(move |flow| Box::pin(handler(flow))));
+    }
+
+    /// Registers an async handler invoked with the summary of every flow pruned from the flow
+    /// table.
+    pub fn on_flow_end<F, Fut>(&self, handler: F)
+    where
+        F: Fn(FlowSummary) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.flow_end
+            .write()
+            .unwrap()
+            .push(Box::new(move |summary| Box::pin(handler(summary))));
+    }
+
+    /// Starts the tokio runtime and the dispatcher thread, and forwards the flow events of `hooks`
+    /// that have handlers. Has no effect without the `async-bridge` feature.
+    pub(crate) fn start(self: &Arc<Self>, config: &AsyncBridgeConfig, hooks: &Hooks) -> Result<()> {
+        self.in_flight
+            .max
+            .store(config.max_in_flight.max(1), Ordering::Relaxed);
+        let (sender, receiver) = crossbeam_channel::bounded(config.queue_size.max(1));
+        let dispatcher = match dispatch::spawn(Arc::clone(self), receiver, config)? {
+            Some(dispatcher) => dispatcher,
+            None => return Ok(()),
+        };
+        self.capacity.store(config.queue_size.max(1), Ordering::Relaxed);
+        *self.dispatcher.lock().unwrap() = Some(dispatcher);
+        *self.queue.write().unwrap() = Some(sender);
+        if !self.flow_new.read().unwrap().is_empty() {
+            let bridge = Arc::clone(self);
+            hooks.on_flow_new(move |flow| bridge.send(|| BridgeEvent::FlowNew(*flow)));
+        }
+        if !self.flow_end.read().unwrap().is_empty() {
+            let bridge = Arc::clone(self);
+            hooks.on_flow_end(move |summary| bridge.send(|| BridgeEvent::FlowEnd(summary.clone())));
+        }
+        log::info!(
+            "Async bridge started with {} worker threads, queue of {} events",
+            config.worker_threads,
+            config.queue_size
+        );
+        Ok(())
+    }
+
+    /// Dispatches the queued events and stops the tokio runtime once handlers in flight complete
+    /// or time out.
+    pub(crate) fn stop(&self) {
+        // The dispatcher returns once the queue is disconnected and drained
+        self.queue.write().unwrap().take();
+        if let Some(dispatcher) = self.dispatcher.lock().unwrap().take() {
+            if dispatcher.join().is_err() {
+                log::error!("Async bridge dispatcher panicked");
+            }
+        }
+    }
+
+    /// Returns whether published alerts are queued.
+    #[inline]
+    pub(crate) fn has_alert(&self) -> bool {
+        self.queue.read().unwrap().is_some() && !self.alert.read().unwrap().is_empty()
+    }
+
+    /// Queues the alert built by `alert`, if alert handlers are registered.
+    pub(crate) fn alert(&self, alert: impl FnOnce() -> serde_json::Value) {
+        if self.has_alert() {
+            self.send(|| BridgeEvent::Alert(alert()));
+        }
+    }
+
+    /// Queues the event built by `event` without blocking, dropping it if the queue is full.
+    fn send(&self, event: impl FnOnce() -> BridgeEvent) {
+        let queue = self.queue.read().unwrap();
+        let sender = match queue.as_ref() {
+            Some(sender) => sender,
+            None => return,
+        };
+        match sender.try_send(event()) {
+            Ok(_) => {
+                self.nb_queued.fetch_add(1, Ordering::Relaxed);
+                self.max_depth.fetch_max(sender.len(), Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.nb_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Returns the futures of the handlers of `event`.
+    #[cfg(feature = "async-bridge")]
+    fn handlers(&self, event: BridgeEvent) -> Vec<BoxFuture> {
+        match event {
+            BridgeEvent::Alert(alert) => Self::invoke(&self.alert, alert),
+            BridgeEvent::FlowNew(flow) => Self::invoke(&self.flow_new, flow),
+            BridgeEvent::FlowEnd(summary) => Self::invoke(&self.flow_end, summary),
+        }
+    }
+
+    #[cfg(feature = "async-bridge")]
+    fn invoke<T: Clone>(handlers: &RwLock<Vec<Handler<T>>>, arg: T) -> Vec<BoxFuture> {
+        handlers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|handler| handler(arg.clone()))
+            .collect()
+    }
+
+    /// Returns the queue depth and delivery counters, `None` if the bridge is not started.
+    pub fn stats(&self) -> Option<AsyncBridgeStats> {
+        let queue = self.queue.read().unwrap();
+        let sender = queue.as_ref()?;
+        Some(AsyncBridgeStats {
+            depth: sender.len(),
+            capacity: self.capacity.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+            nb_queued: self.nb_queued.load(Ordering::Relaxed),
+            nb_dropped: self.nb_dropped.load(Ordering::Relaxed),
+            in_flight: self.in_flight.count(),
+            nb_completed: self.in_flight.nb_completed.load(Ordering::Relaxed),
+        })
+    }
+}
+
+impl fmt::Debug for AsyncBridge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncBridge")
+            .field("alert", &self.alert.read().unwrap().len())
+            .field("flow_new", &self.flow_new.read().unwrap().len())
+            .field("flow_end", &self.flow_end.read().unwrap().len())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[cfg(feature = "async-bridge")]
+mod dispatch {
+    use super::{AsyncBridge, BridgeEvent};
+    use crate::config::AsyncBridgeConfig;
+
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use crossbeam_channel::Receiver;
+
+    /// Builds the tokio runtime and spawns the dispatcher thread, which owns it.
+    pub(super) fn spawn(
+        bridge: Arc<AsyncBridge>,
+        receiver: Receiver<BridgeEvent>,
+        config: &AsyncBridgeConfig,
+    ) -> Result<Option<JoinHandle<()>>> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(config.worker_threads.max(1))
+            .thread_name("retina-async")
+            .enable_all()
+            .build()?;
+        let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+        let dispatcher = thread::Builder::new()
+            .name("retina-async-dispatch".to_string())
+            .spawn(move || {
+                for event in receiver.iter() {
+                    for handler in bridge.handlers(event) {
+                        let permit = bridge.in_flight.acquire();
+                        runtime.spawn(async move {
+                            handler.await;
+                            drop(permit);
+                        });
+                    }
+                }
+                runtime.shutdown_timeout(shutdown_timeout);
+            })?;
+        Ok(Some(dispatcher))
+    }
+}
+
+#[cfg(not(feature = "async-bridge"))]
+mod dispatch {
+    use super::{AsyncBridge, BridgeEvent};
+    use crate::config::AsyncBridgeConfig;
+
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+
+    use anyhow::Result;
+    use crossbeam_channel::Receiver;
+
+    pub(super) fn spawn(
+        _bridge: Arc<AsyncBridge>,
+        _receiver: Receiver<BridgeEvent>,
+        _config: &AsyncBridgeConfig,
+    ) -> Result<Option<JoinHandle<()>>> {
+        log::warn!("Async handlers are not run without the `async-bridge` feature");
+        Ok(None)
+    }
+}
@@ -8,20 +8,151 @@
 //!  Retina can run in either "online" mode (reading packets from a live network interface) or
 //! "offline" mode (reading packets from a capture file). See
 //! [configs](https://github.com/stanford-esrg/retina/tree/main/configs) for examples.
-
+//!
+//! ## Overlays
+//! Deployments that share a base configuration keep their differences in overlays.
+//! [load_layered_config](load_layered_config) merges configuration files in order, each file
+//! overlaying the previous ones: tables are merged key by key, and any other value of a later file,
+//! including arrays such as `[[online.ports]]`, replaces the value of the earlier files. Overlay
+//! files can also be listed, separated by `:`, in the `RETINA_CONFIG_OVERLAY` environment variable,
+//! and are applied after the files passed to the function, including by
+//! [load_config](load_config).
+//!
+//! Single keys are then overridden by the environment variables named `RETINA_CONFIG__` followed
+//! by the path of the key, with `__` between its segments, in lexicographic order of the variable
+//! names. Segments are lowercased, numeric segments index arrays, and missing tables are created.
+//! Values are parsed as TOML values, or taken as strings if they do not parse. The files and
+//! variables the configuration was loaded from are logged with the effective configuration, and
+//! listed at the top of the `config.toml` file of the [monitor](MonitorConfig) logs.
+//!
+//! ## Example
+//! ```sh
+//! RETINA_CONFIG_OVERLAY=/etc/retina/site.toml \
+//! RETINA_CONFIG__ONLINE__PORTS__0__DEVICE=0000:3b:00.0 \
+//! RETINA_CONFIG__CAPTURE__MAX_FILES=50 \
+//!     ./retina-app --config /etc/retina/base.toml
+//! ```
+
+use crate::filter::backend::BackendKind;
 use crate::lcore::{CoreId, SocketId};
+use crate::protocols::app::AppProtocol;
 
+use std::collections::BTreeMap;
+use std::env;
 use std::fs;
-use std::path::Path;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 
-/// Loads a configuration file from `path`.
+/// Environment variable listing overlay files, separated by `:`.
+const OVERLAY_VAR: &str = "RETINA_CONFIG_OVERLAY";
+
+/// Prefix of the environment variables overriding single keys.
+const OVERRIDE_PREFIX: &str = "RETINA_CONFIG__";
+
+/// Loads a configuration file from `path`, with the overlays and overrides of the environment
+/// (see [overlays](self#overlays)).
 pub fn load_config<P: AsRef<Path>>(path: P) -> RuntimeConfig {
-    let config_str = fs::read_to_string(path).expect("ERROR: File read failed");
-    let config: RuntimeConfig = toml::from_str(&config_str).expect("Invalid config file");
+    load_layered_config(&[path])
+}
+
+/// Loads the configuration files of `paths`, each overlaying the previous ones, then the overlays
+/// and overrides of the environment (see [overlays](self#overlays)).
+pub fn load_layered_config<P: AsRef<Path>>(paths: &[P]) -> RuntimeConfig {
+    merge_layers(paths).expect("Invalid config file")
+}
+
+fn merge_layers<P: AsRef<Path>>(paths: &[P]) -> Result<RuntimeConfig> {
+    let mut files: Vec<PathBuf> = paths.iter().map(|path| path.as_ref().to_path_buf()).collect();
+    if let Ok(overlays) = env::var(OVERLAY_VAR) {
+        files.extend(overlays.split(':').filter(|path| !path.is_empty()).map(PathBuf::from));
+    }
+    let mut merged = toml::Value::Table(toml::value::Table::new());
+    let mut sources = vec![];
+    for path in files.iter() {
+        let config_str = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let layer: toml::Value = toml::from_str(&config_str)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        merge_value(&mut merged, layer);
+        sources.push(path.display().to_string());
+    }
+    let mut overrides: Vec<(String, String)> = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| name.starts_with(OVERRIDE_PREFIX))
+        .collect();
+    overrides.sort();
+    for (name, value) in overrides {
+        let keys: Vec<String> = name[OVERRIDE_PREFIX.len()..]
+            .split("__")
+            .map(|key| key.to_lowercase())
+            .collect();
+        set_value(&mut merged, &keys, parse_override(&value))
+            .with_context(|| format!("Invalid override {}", name))?;
+        sources.push(name);
+    }
+    let mut config: RuntimeConfig = merged.try_into().context("Invalid merged config")?;
+    config.mempool.validate()?;
+    config.sources = sources;
+    Ok(config)
+}
+
+/// Merges `layer` into `base`: tables are merged key by key, other values are replaced.
+fn merge_value(base: &mut toml::Value, layer: toml::Value) {
+    match (base, layer) {
+        (toml::Value::Table(base), toml::Value::Table(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Replaces the value at the path `keys` of `value` with `new`, creating missing tables. Numeric
+/// keys index arrays.
+fn set_value(value: &mut toml::Value, keys: &[String], new: toml::Value) -> Result<()> {
+    let (key, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => {
+            *value = new;
+            return Ok(());
+        }
+    };
+    if key.is_empty() {
+        bail!("Empty key");
+    }
+    let child = match value {
+        toml::Value::Table(table) => table
+            .entry(key.clone())
+            .or_insert_with(|| toml::Value::Table(toml::value::Table::new())),
+        toml::Value::Array(array) => {
+            let index: usize = key
+                .parse()
+                .with_context(|| format!("{} is not an array index", key))?;
+            match array.get_mut(index) {
+                Some(element) => element,
+                None => bail!("Index {} out of bounds", index),
+            }
+        }
+        _ => bail!("{} is not in a table", key),
+    };
+    set_value(child, rest, new)
+}
 
-    config
+/// Parses an override as a TOML value, or as a string if it is not one.
+fn parse_override(value: &str) -> toml::Value {
+    toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
 }
 
 /// Loads a default configuration file.
@@ -66,6 +197,29 @@ pub struct RuntimeConfig {
     #[serde(default = "default_suppress_dpdk_output")]
     pub suppress_dpdk_output: bool,
 
+    /// Name of this runtime instance, to run several Retina processes on the same host. Defaults
+    /// to `None`.
+    ///
+    /// If set, DPDK uses the instance name as its EAL file prefix (unless `--file-prefix` is passed
+    /// in [OnlineConfig::dpdk_supl_args](OnlineConfig::dpdk_supl_args)), memory pools are named
+    /// after the instance, and `{instance}` in file and socket paths of the configuration is
+    /// replaced by the name. Names are at most 12 ASCII letters, digits, `-` or `_`.
+    ///
+    /// ## Remarks
+    /// Each instance only probes the ports of its own configuration, so instances must be given
+    /// disjoint ports and cores.
+    ///
+    /// ## Example
+    /// ```toml
+    /// main_core = 10
+    /// instance = "east"
+    ///
+    /// [alert]
+    ///     subscribers = ["/run/retina/{instance}/alerts.sock"]
+    /// ```
+    #[serde(default = "default_instance")]
+    pub instance: Option<String>,
+
     /// Per-mempool settings.
     pub mempool: MempoolConfig,
 
@@ -73,6 +227,158 @@ pub struct RuntimeConfig {
     #[serde(default = "default_online")]
     pub online: Option<OnlineConfig>,
 
+    /// Flow key construction options.
+    #[serde(default = "default_flow_key")]
+    pub flow_key: FlowKeyConfig,
+
+    /// Payload scan depth options.
+    #[serde(default = "default_scan")]
+    pub scan: ScanConfig,
+
+    /// Match alert options. Defaults to `None` (no alerts).
+    #[serde(default = "default_alert")]
+    pub alert: Option<AlertConfig>,
+
+    /// Per-core packet tracing options.
+    #[serde(default = "default_trace")]
+    pub trace: TraceConfig,
+
+    /// Rule cost profiling options. Defaults to `None` (no profiling).
+    #[serde(default = "default_profile")]
+    pub profile: Option<ProfileConfig>,
+
+    /// Live packet tap options. Defaults to `None` (no tap).
+    #[serde(default = "default_tap")]
+    pub tap: Option<TapConfig>,
+
+    /// Rolling capture options of capture rules. Defaults to `None` (no capture).
+    #[serde(default = "default_capture")]
+    pub capture: Option<CaptureConfig>,
+
+    /// Pre-match packet backfill options. Defaults to `None` (packets preceding the first match
+    /// of a flow are not stored).
+    #[serde(default = "default_backfill")]
+    pub backfill: Option<BackfillConfig>,
+
+    /// Priority classes of the tap and capture queues.
+    #[serde(default = "default_storage_priority")]
+    pub storage_priority: StoragePriorityConfig,
+
+    /// Packet processing stage options. Defaults to `None` (all registered stages, in registration
+    /// order).
+    #[serde(default = "default_pipeline")]
+    pub pipeline: Option<PipelineConfig>,
+
+    /// Cross-flow verdict cache options. Defaults to `None` (no cache).
+    #[serde(default = "default_verdict_cache")]
+    pub verdict_cache: Option<VerdictCacheConfig>,
+
+    /// Heavy-hitter detection options. Defaults to `None` (no detection).
+    #[serde(default = "default_top_talkers")]
+    pub top_talkers: Option<TopTalkersConfig>,
+
+    /// Rules directory options. Defaults to `None` (no rules directory).
+    #[serde(default = "default_rule_watch")]
+    pub rule_watch: Option<RuleWatchConfig>,
+
+    /// Neighbor (IP to MAC address) table options. Defaults to `None` (no table).
+    #[serde(default = "default_neighbors")]
+    pub neighbors: Option<NeighborConfig>,
+
+    /// Counting rule export options. Defaults to `None` (no export).
+    #[serde(default = "default_counters")]
+    pub counters: Option<CounterConfig>,
+
+    /// Checksum validation options.
+    #[serde(default = "default_checksum")]
+    pub checksum: ChecksumConfig,
+
+    /// Shadow rule set options. Defaults to `None` (no shadow rules on startup).
+    #[serde(default = "default_shadow")]
+    pub shadow: Option<ShadowConfig>,
+
+    /// Flow table size options.
+    #[serde(default = "default_flow_table")]
+    pub flow_table: FlowTableConfig,
+
+    /// Asymmetric traffic detection options.
+    #[serde(default = "default_flow_directions")]
+    pub flow_directions: FlowDirectionConfig,
+
+    /// Regex flag defaults of rules.
+    #[serde(default = "default_regex")]
+    pub regex: RegexConfig,
+
+    /// Match rate safeguard options. Defaults to `None` (rules are never throttled).
+    #[serde(default = "default_throttle")]
+    pub throttle: Option<ThrottleConfig>,
+
+    /// Flow table snapshot options. Defaults to `None` (the flow table is lost on restart).
+    #[serde(default = "default_flow_snapshot")]
+    pub flow_snapshot: Option<FlowSnapshotConfig>,
+
+    /// Application protocols of server ports. Defaults to `[]` (protocols are identified from
+    /// signatures and well-known ports).
+    #[serde(default = "default_app_ports")]
+    pub app_ports: Vec<AppPortConfig>,
+
+    /// Write-ahead journal options. Defaults to `None` (no journal).
+    #[serde(default = "default_journal")]
+    pub journal: Option<JournalConfig>,
+
+    /// Policies of VLAN-tagged and untagged traffic. Defaults to `[]` (all traffic is fully
+    /// inspected).
+    #[serde(default = "default_vlan_policy")]
+    pub vlan_policy: Vec<VlanPolicyConfig>,
+
+    /// Per-tenant storage quotas of the tap, the capture and the collectors. Defaults to `[]` (no
+    /// quotas).
+    #[serde(default = "default_storage_quota")]
+    pub storage_quota: Vec<StorageQuotaConfig>,
+
+    /// Remote collectors matched packets are streamed to. Defaults to `[]` (no forwarding).
+    #[serde(default = "default_forward")]
+    pub forward: Vec<ForwardConfig>,
+
+    /// Destinations of the packets matched by rules with a `store_target`. Defaults to `[]` (all
+    /// packets are stored to the default rolling capture and collectors).
+    #[serde(default = "default_store_target")]
+    pub store_target: Vec<StoreTargetConfig>,
+
+    /// Async bridge options. Defaults to `None` (async handlers are not run).
+    #[serde(default = "default_async_bridge")]
+    pub async_bridge: Option<AsyncBridgeConfig>,
+
+    /// Matching backend options. Defaults to `None` (rules are matched on the CPU).
+    #[serde(default = "default_match_backend")]
+    pub match_backend: Option<MatchBackendConfig>,
+
+    /// YARA scanning options. Defaults to `None` (no YARA rules).
+    #[serde(default = "default_yara")]
+    pub yara: Option<YaraConfig>,
+
+    /// Throughput metrics of matched flows. Defaults to `None` (not tracked).
+    #[serde(default = "default_flow_rates")]
+    pub flow_rates: Option<FlowRateConfig>,
+
+    /// Service manager notification options. Defaults to `None` (no notifications).
+    #[serde(default = "default_notify")]
+    pub notify: Option<NotifyConfig>,
+
+    /// Resource guards of tunnel decapsulation.
+    #[serde(default = "default_decap")]
+    pub decap: DecapConfig,
+
+    /// Feature flags, by name (see [flags](crate::filter::flags)). Defaults to empty, which turns
+    /// all built-in flags on.
+    #[serde(default = "default_flags")]
+    pub flags: BTreeMap<String, bool>,
+
+    /// Files and environment variables the configuration was loaded from, in order (see
+    /// [overlays](self#overlays)). Not read from configuration files.
+    #[serde(skip)]
+    pub sources: Vec<String>,
+
     #[doc(hidden)]
     /// Runtime filter for testing purposes.
     #[serde(default = "default_filter")]
@@ -80,15 +386,100 @@ pub struct RuntimeConfig {
 }
 
 impl RuntimeConfig {
+    /// Serializes the effective configuration, preceded by comments listing its sources.
+    pub(crate) fn to_effective_toml(&self) -> Result<String> {
+        let mut toml = String::new();
+        for source in self.sources.iter() {
+            toml.push_str("# Source: ");
+            toml.push_str(source);
+            toml.push('\n');
+        }
+        toml.push_str(&toml::to_string(self)?);
+        Ok(toml)
+    }
+
+    /// Logs the sources of the configuration, and the effective configuration if it was merged
+    /// from several sources.
+    pub(crate) fn log_sources(&self) {
+        if self.sources.is_empty() {
+            return;
+        }
+        log::info!("Configuration loaded from {}", self.sources.join(", "));
+        if self.sources.len() > 1 {
+            match toml::to_string(self) {
+                Ok(toml) => log::info!("Effective configuration:\n{}", toml),
+                Err(error) => log::warn!("Failed to serialize config: {}", error),
+            }
+        }
+    }
+
+    /// Checks the instance name and replaces `{instance}` in file and socket paths with it.
+    pub(crate) fn apply_instance(&mut self) -> Result<()> {
+        let instance = match &self.instance {
+            Some(instance) => instance.clone(),
+            None => return Ok(()),
+        };
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if instance.is_empty() || instance.len() > MAX_INSTANCE_LEN || !instance.chars().all(valid)
+        {
+            bail!("Invalid instance name: {:?}", instance);
+        }
+        let expand = |path: &mut String| *path = path.replace("{instance}", &instance);
+        if let Some(log) = self
+            .online
+            .as_mut()
+            .and_then(|online| online.monitor.as_mut())
+            .and_then(|monitor| monitor.log.as_mut())
+        {
+            expand(&mut log.directory);
+        }
+        if let Some(alert) = &mut self.alert {
+            alert.subscribers.iter_mut().for_each(expand);
+        }
+        if let Some(tap) = &mut self.tap {
+            expand(&mut tap.path);
+        }
+        if let Some(capture) = &mut self.capture {
+            expand(&mut capture.directory);
+        }
+        if let Some(rule_watch) = &mut self.rule_watch {
+            expand(&mut rule_watch.directory);
+        }
+        if let Some(counters) = &mut self.counters {
+            expand(&mut counters.directory);
+        }
+        if let Some(rules) = self.shadow.as_mut().and_then(|shadow| shadow.rules.as_mut()) {
+            expand(rules);
+        }
+        if let Some(flow_snapshot) = &mut self.flow_snapshot {
+            expand(&mut flow_snapshot.state_file);
+        }
+        if let Some(journal) = &mut self.journal {
+            expand(&mut journal.directory);
+        }
+        self.forward
+            .iter_mut()
+            .filter_map(|forward| forward.spool_directory.as_mut())
+            .for_each(expand);
+        if let Some(online) = &mut self.online {
+            for port in online.ports.iter_mut() {
+                let sinks = port.sink.iter_mut().chain(port.sinks.iter_mut());
+                sinks.filter_map(|sink| sink.sample_path.as_mut()).for_each(expand);
+            }
+        }
+        Ok(())
+    }
+
     /// Returns a list of core IDs assigned to the runtime.
     fn get_all_core_ids(&self) -> Vec<CoreId> {
         let mut cores = vec![CoreId(self.main_core)];
         if let Some(online) = &self.online {
             for port in online.ports.iter() {
                 cores.extend(port.cores.iter().map(|c| CoreId(*c)));
-                if let Some(sink) = &port.sink {
-                    cores.push(CoreId(sink.core));
-                }
+                cores.extend(port.sink_configs().map(|sink| CoreId(sink.core)));
+            }
+            if let Some(software_rss) = &online.software_rss {
+                cores.extend(software_rss.workers.iter().map(|c| CoreId(*c)));
             }
         }
         cores.sort();
@@ -123,13 +514,26 @@ impl RuntimeConfig {
             .collect();
         eal_params.push(core_list.join(","));
 
+        if let Some(instance) = &self.instance {
+            let has_prefix = self.online.as_ref().map_or(false, |online| {
+                online
+                    .dpdk_supl_args
+                    .iter()
+                    .any(|arg| arg.starts_with("--file-prefix"))
+            });
+            if !has_prefix {
+                eal_params.push("--file-prefix".to_owned());
+                eal_params.push(instance.clone());
+            }
+        }
+
         if let Some(online) = &self.online {
             for supl_arg in online.dpdk_supl_args.iter() {
                 eal_params.push(supl_arg.to_string())
             }
-            for port in online.ports.iter() {
+            for device in online.ports.iter().flat_map(|port| port.pci_devices()) {
                 eal_params.push("-a".to_owned());
-                eal_params.push(port.device.to_string());
+                eal_params.push(device.to_string());
             }
         }
 
@@ -153,6 +557,13 @@ fn default_suppress_dpdk_output() -> bool {
     true
 }
 
+fn default_instance() -> Option<String> {
+    None
+}
+
+/// Maximum length of an instance name, bounded by the DPDK memory pool name size.
+const MAX_INSTANCE_LEN: usize = 12;
+
 fn default_online() -> Option<OnlineConfig> {
     None
 }
@@ -161,17 +572,196 @@ fn default_filter() -> Option<String> {
     None
 }
 
+fn default_flow_key() -> FlowKeyConfig {
+    FlowKeyConfig::default()
+}
+
+fn default_scan() -> ScanConfig {
+    ScanConfig::default()
+}
+
+fn default_alert() -> Option<AlertConfig> {
+    None
+}
+
+fn default_trace() -> TraceConfig {
+    TraceConfig::default()
+}
+
+fn default_profile() -> Option<ProfileConfig> {
+    None
+}
+
+fn default_tap() -> Option<TapConfig> {
+    None
+}
+
+fn default_capture() -> Option<CaptureConfig> {
+    None
+}
+
+fn default_backfill() -> Option<BackfillConfig> {
+    None
+}
+
+fn default_storage_priority() -> StoragePriorityConfig {
+    StoragePriorityConfig::default()
+}
+
+fn default_pipeline() -> Option<PipelineConfig> {
+    None
+}
+
+fn default_verdict_cache() -> Option<VerdictCacheConfig> {
+    None
+}
+
+fn default_top_talkers() -> Option<TopTalkersConfig> {
+    None
+}
+
+fn default_rule_watch() -> Option<RuleWatchConfig> {
+    None
+}
+
+fn default_neighbors() -> Option<NeighborConfig> {
+    None
+}
+
+fn default_counters() -> Option<CounterConfig> {
+    None
+}
+
+fn default_checksum() -> ChecksumConfig {
+    ChecksumConfig::default()
+}
+
+fn default_shadow() -> Option<ShadowConfig> {
+    None
+}
+
+fn default_flow_table() -> FlowTableConfig {
+    FlowTableConfig::default()
+}
+
+fn default_flow_directions() -> FlowDirectionConfig {
+    FlowDirectionConfig::default()
+}
+
+fn default_regex() -> RegexConfig {
+    RegexConfig::default()
+}
+
+fn default_throttle() -> Option<ThrottleConfig> {
+    None
+}
+
+fn default_flow_snapshot() -> Option<FlowSnapshotConfig> {
+    None
+}
+
+fn default_app_ports() -> Vec<AppPortConfig> {
+    vec![]
+}
+
+fn default_journal() -> Option<JournalConfig> {
+    None
+}
+
+fn default_vlan_policy() -> Vec<VlanPolicyConfig> {
+    vec![]
+}
+
+fn default_storage_quota() -> Vec<StorageQuotaConfig> {
+    vec![]
+}
+
+fn default_forward() -> Vec<ForwardConfig> {
+    vec![]
+}
+
+fn default_store_target() -> Vec<StoreTargetConfig> {
+    vec![]
+}
+
+fn default_async_bridge() -> Option<AsyncBridgeConfig> {
+    None
+}
+
+fn default_match_backend() -> Option<MatchBackendConfig> {
+    None
+}
+
+fn default_yara() -> Option<YaraConfig> {
+    None
+}
+
+fn default_flow_rates() -> Option<FlowRateConfig> {
+    None
+}
+
+fn default_notify() -> Option<NotifyConfig> {
+    None
+}
+
+fn default_decap() -> DecapConfig {
+    DecapConfig::default()
+}
+
+fn default_flags() -> BTreeMap<String, bool> {
+    BTreeMap::new()
+}
+
 impl Default for RuntimeConfig {
     fn default() -> Self {
         RuntimeConfig {
             main_core: 0,
             nb_memory_channels: 1,
             suppress_dpdk_output: true,
+            instance: None,
             mempool: MempoolConfig {
                 capacity: 8192,
                 cache_size: 512,
+                shed_watermark: None,
+                resume_watermark: 0.25,
             },
             online: None,
+            flow_key: FlowKeyConfig::default(),
+            scan: ScanConfig::default(),
+            alert: None,
+            trace: TraceConfig::default(),
+            profile: None,
+            tap: None,
+            capture: None,
+            backfill: None,
+            storage_priority: default_storage_priority(),
+            pipeline: None,
+            verdict_cache: None,
+            top_talkers: None,
+            rule_watch: None,
+            neighbors: None,
+            counters: None,
+            checksum: default_checksum(),
+            shadow: None,
+            flow_table: default_flow_table(),
+            flow_directions: default_flow_directions(),
+            regex: default_regex(),
+            throttle: None,
+            flow_snapshot: None,
+            app_ports: vec![],
+            journal: None,
+            vlan_policy: vec![],
+            storage_quota: vec![],
+            forward: vec![],
+            store_target: vec![],
+            async_bridge: None,
+            match_backend: None,
+            yara: None,
+            flow_rates: None,
+            notify: None,
+            decap: default_decap(),
+            flags: default_flags(),
+            sources: vec![],
             filter: None,
         }
     }
@@ -203,6 +793,49 @@ pub struct MempoolConfig {
     /// `capacity`. Defaults to `512`.
     #[serde(default = "default_cache_size")]
     pub cache_size: usize,
+
+    /// Fraction of available mbufs (between `0` and `1`) below which storage load is shed. Defaults
+    /// to `None` (never shed).
+    ///
+    /// ## Remarks
+    /// While shedding, packets are still matched and handed to the callback, but no longer copied
+    /// to the tap, the rolling capture or collectors, so that storage queues falling behind cannot
+    /// exhaust the memory pool and cause the NIC to drop all traffic. Mempool usage is checked
+    /// every 100ms by the main core. An alert is published when shedding starts and stops, and the
+    /// number of packets not stored is logged on exit.
+    #[serde(default = "default_shed_watermark")]
+    pub shed_watermark: Option<f64>,
+
+    /// Fraction of available mbufs above which storage load is no longer shed. Must be larger than
+    /// `shed_watermark`. Defaults to `0.25`.
+    #[serde(default = "default_resume_watermark")]
+    pub resume_watermark: f64,
+}
+
+impl MempoolConfig {
+    /// Checks that the watermarks are fractions and that shedding stops above the level it starts
+    /// at, so that RX cores neither flap between shedding and receiving nor shed forever.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.resume_watermark) {
+            bail!(
+                "mempool.resume_watermark must be between 0 and 1, got {}",
+                self.resume_watermark
+            );
+        }
+        if let Some(shed_watermark) = self.shed_watermark {
+            if !(0.0..=1.0).contains(&shed_watermark) {
+                bail!("mempool.shed_watermark must be between 0 and 1, got {}", shed_watermark);
+            }
+            if self.resume_watermark <= shed_watermark {
+                bail!(
+                    "mempool.resume_watermark ({}) must be larger than mempool.shed_watermark ({})",
+                    self.resume_watermark,
+                    shed_watermark
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 fn default_capacity() -> usize {
@@ -213,6 +846,14 @@ fn default_cache_size() -> usize {
     512
 }
 
+fn default_shed_watermark() -> Option<f64> {
+    None
+}
+
+fn default_resume_watermark() -> f64 {
+    0.25
+}
+
 /* --------------------------------------------------------------------------------- */
 
 /// Live traffic analysis options.
@@ -252,7 +893,8 @@ pub struct OnlineConfig {
     #[serde(default = "default_duration")]
     pub duration: Option<u64>,
 
-    /// Whether promiscuous mode is enabled for all ports. Defaults to `true`.
+    /// Whether promiscuous mode is enabled for all ports, unless overridden by
+    /// [PortMap::promiscuous](PortMap::promiscuous). Defaults to `true`.
     #[serde(default = "default_promiscuous")]
     pub promiscuous: bool,
 
@@ -264,7 +906,8 @@ pub struct OnlineConfig {
     #[serde(default = "default_portqueue_nb_rxd")]
     pub nb_rxd: usize,
 
-    /// Maximum transmission unit (in bytes) allowed for ingress packets. Defaults to `1500`.
+    /// Maximum transmission unit (in bytes) allowed for ingress packets, unless overridden by
+    /// [PortMap::mtu](PortMap::mtu). Defaults to `1500`.
     ///
     /// To capture jumbo frames, set this value higher (e.g., `9702`).
     #[serde(default = "default_mtu")]
@@ -285,6 +928,27 @@ pub struct OnlineConfig {
     #[serde(default = "default_monitor")]
     pub monitor: Option<MonitorConfig>,
 
+    /// NUMA placement options.
+    #[serde(default = "default_numa")]
+    pub numa: NumaConfig,
+
+    /// Replay of a pcap file on an online port. Defaults to `None`.
+    #[serde(default = "default_replay")]
+    pub replay: Option<ReplayConfig>,
+
+    /// Benchmark mode. Defaults to `false`.
+    ///
+    /// If set, RX cores only count the packets and bytes they receive, without parsing, filtering
+    /// or storing them, and the monitor reports the achieved rates. This gives the line-rate
+    /// baseline of the hardware, against which the cost of the pipeline can be measured. Sink
+    /// queues are not affected.
+    #[serde(default = "default_benchmark")]
+    pub benchmark: bool,
+
+    /// Software distribution of received packets to worker cores. Defaults to `None`.
+    #[serde(default = "default_software_rss")]
+    pub software_rss: Option<SoftwareRssConfig>,
+
     /// List of network interfaces to read from.
     pub ports: Vec<PortMap>,
 }
@@ -317,6 +981,124 @@ fn default_monitor() -> Option<MonitorConfig> {
     None
 }
 
+fn default_numa() -> NumaConfig {
+    NumaConfig::default()
+}
+
+fn default_replay() -> Option<ReplayConfig> {
+    None
+}
+
+fn default_benchmark() -> bool {
+    false
+}
+
+fn default_software_rss() -> Option<SoftwareRssConfig> {
+    None
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Software RSS options (test mode).
+///
+/// On NICs without usable RSS, e.g. with a single receive queue, all packets land on the queues of
+/// one or two cores while the others idle. With software RSS, the cores of the ports become I/O
+/// cores: they only poll their receive queues, and hand each packet off to one of the `workers`
+/// over a ring, chosen by the hash of its flow (see
+/// [FilterCtx::flow_hash](crate::filter::FilterCtx::flow_hash)) so that each flow is processed by
+/// a single worker. Workers run the processing pipeline on the packets they are handed. Packets
+/// that do not fit in the ring of their worker are counted as dropped (see
+/// [handoff](crate::lcore::handoff)).
+///
+/// ## Example
+/// ```toml
+/// [online.software_rss]
+///     workers = [3,4,5,6,7,8]
+///     ring_size = 4096
+///
+/// [[online.ports]]
+///     device = "0000:3b:00.0"
+///     cores = [1,2]
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SoftwareRssConfig {
+    /// IDs of the worker cores, which must not poll ports or sink queues.
+    pub workers: Vec<u32>,
+
+    /// Number of packets each handoff ring holds. Must be a power of 2. Defaults to `4096`.
+    ///
+    /// There is one ring per receive queue and worker.
+    #[serde(default = "default_handoff_ring_size")]
+    pub ring_size: u32,
+}
+
+fn default_handoff_ring_size() -> u32 {
+    4096
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Packet replay options.
+///
+/// The packets of the pcap file `file` are transmitted on the online port `port` while the runtime
+/// runs, e.g. to reproduce an incident from the files of the rolling capture (see
+/// [replay](crate::lcore::replay)). The port gets a dedicated TX queue for the replay.
+///
+/// ## Example
+/// ```toml
+/// [online.replay]
+///     file = "./capture/capture-20240101-120000-0.pcap"
+///     port = "0000:3b:00.1"
+///     pacing = "rate"
+///     rate = 100_000
+///     loops = 3
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReplayConfig {
+    /// Path of the pcap or pcapng file to replay.
+    pub file: String,
+
+    /// PCI address of the online port to transmit on.
+    pub port: String,
+
+    /// Pacing of the transmitted packets. Defaults to `"original"`.
+    #[serde(default = "default_replay_pacing")]
+    pub pacing: ReplayPacing,
+
+    /// Packet rate (in packets per second) of the `"rate"` pacing. Defaults to `10000`.
+    #[serde(default = "default_replay_rate")]
+    pub rate: u64,
+
+    /// Number of times the file is replayed, `0` to replay until the runtime stops. Defaults to
+    /// `1`.
+    #[serde(default = "default_replay_loops")]
+    pub loops: u32,
+}
+
+/// Pacing of replayed packets.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayPacing {
+    /// Packets keep the gaps between their capture timestamps.
+    Original,
+    /// Packets are transmitted at `rate` packets per second.
+    Rate,
+    /// Packets are transmitted as fast as the TX queue accepts them.
+    Max,
+}
+
+fn default_replay_pacing() -> ReplayPacing {
+    ReplayPacing::Original
+}
+
+fn default_replay_rate() -> u64 {
+    10000
+}
+
+fn default_replay_loops() -> u32 {
+    1
+}
+
 /* --------------------------------------------------------------------------------- */
 
 /// Sink core options.
@@ -325,6 +1107,11 @@ fn default_monitor() -> Option<MonitorConfig> {
 /// for connection sampling, as entire 4-tuples can be discarded by redirecting them to the sink
 /// core.
 ///
+/// Besides counting and dropping packets, a sink can write a sample of its packets to a pcap file
+/// or forward them to the TX queue of another port, e.g. to hand unsampled traffic to another
+/// tool. A port can have several sinks, in `sink` and `sinks`, each with its own receive queue:
+/// the RSS redirection table buckets not polled by the receive queues are spread across them.
+///
 /// ## Remarks
 /// Adding a sink core prevents ethtool counters from classifying intentionally discarded packets as
 /// packet loss. However, it can be quite wasteful of system resources, as it requires configuring
@@ -335,12 +1122,43 @@ fn default_monitor() -> Option<MonitorConfig> {
 /// [online.ports.sink]
 ///     core = 9
 ///     nb_buckets = 384   # drops 25% of 4-tuples
+///
+/// [[online.ports.sinks]]
+///     core = 10
+///     nb_buckets = 384
+///     behavior = "sample"
+///     sample_rate = 1000
+///     sample_path = "./sink_{instance}.pcap"
+///
+/// [[online.ports.sinks]]
+///     core = 11
+///     nb_buckets = 384
+///     behavior = "forward"
+///     forward_port = "0000:3b:00.1"
 /// ```
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct SinkConfig {
     /// Sink core identifier.
     pub core: u32,
 
+    /// What the sink does with its packets. Defaults to `count`.
+    #[serde(default = "default_sink_behavior")]
+    pub behavior: SinkBehavior,
+
+    /// With the `sample` behavior, one in `sample_rate` packets is written. Defaults to `1000`.
+    #[serde(default = "default_sink_sample_rate")]
+    pub sample_rate: u64,
+
+    /// Path of the pcap file written by the `sample` behavior, created or truncated on startup.
+    /// Required with the `sample` behavior.
+    #[serde(default = "default_sink_sample_path")]
+    pub sample_path: Option<String>,
+
+    /// PCI address of the port the `forward` behavior transmits packets on. It must be one of the
+    /// online ports. Required with the `forward` behavior.
+    #[serde(default = "default_sink_forward_port")]
+    pub forward_port: Option<String>,
+
     /// Number of RSS redirection table buckets to use for receive queues. Defaults to `512`, which
     /// indicates no sampling.
     ///
@@ -349,24 +1167,63 @@ pub struct SinkConfig {
     /// redirection buckets. `nb_buckets` must range from the number of cores polling the port (call
     /// this `n`) to `512`, which is the maximum number of buckets in the RSS redirection table. It
     /// is recommended that `nb_buckets` be a multiple of `n` for better load balancing. For
-    /// example, setting `nb_buckets = 256` would drop 50% of connections.
+    /// example, setting `nb_buckets = 256` would drop 50% of connections. With several sinks, the
+    /// smallest value of the port's sinks is used.
     #[serde(default = "default_nb_buckets")]
     pub nb_buckets: usize,
 }
 
+/// Handling of the packets received by a sink core.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkBehavior {
+    /// Packets are counted and dropped.
+    Count,
+    /// Packets are counted and dropped, and one in `sample_rate` is written to `sample_path`.
+    Sample,
+    /// Packets are transmitted on `forward_port`, and dropped if its TX queue is full.
+    Forward,
+}
+
 fn default_nb_buckets() -> usize {
     512
 }
 
+fn default_sink_behavior() -> SinkBehavior {
+    SinkBehavior::Count
+}
+
+fn default_sink_sample_rate() -> u64 {
+    1000
+}
+
+fn default_sink_sample_path() -> Option<String> {
+    None
+}
+
+fn default_sink_forward_port() -> Option<String> {
+    None
+}
+
 /* --------------------------------------------------------------------------------- */
 
 /// Network interface options.
 ///
+/// Port options that are not supported by the device are rejected when the port is initialized,
+/// except `rx_timestamp`, which falls back to software timestamps.
+///
 /// ## Example
 /// ```toml
 /// [[online.ports]]
 ///     device = "0000:3b:00.0"
 ///     cores = [1,2,3,4,5,6,7,8]
+///     promiscuous = false
+///     mtu = 9000
+///     rx_checksum = true
+///     vlan_strip = false
+///     scatter = true
+///     rx_timestamp = true
+///     rx_weight = 2
 /// ```
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct PortMap {
@@ -383,12 +1240,232 @@ pub struct PortMap {
     /// Sink core configuration. Defaults to `None`.
     #[serde(default = "default_sink")]
     pub sink: Option<SinkConfig>,
+
+    /// Additional sink cores, each polling its own sink queue. Defaults to none.
+    #[serde(default = "default_sinks")]
+    pub sinks: Vec<SinkConfig>,
+
+    /// Whether promiscuous mode is enabled on this port. Defaults to `None`, which uses
+    /// [OnlineConfig::promiscuous](OnlineConfig::promiscuous).
+    #[serde(default = "default_port_promiscuous")]
+    pub promiscuous: Option<bool>,
+
+    /// Maximum transmission unit (in bytes) of this port. Defaults to `None`, which uses
+    /// [OnlineConfig::mtu](OnlineConfig::mtu).
+    ///
+    /// ## Remarks
+    /// Mbufs are sized for [OnlineConfig::mtu](OnlineConfig::mtu). A larger MTU requires
+    /// `scatter`.
+    #[serde(default = "default_port_mtu")]
+    pub mtu: Option<usize>,
+
+    /// If set, IP and L4 checksums are validated by the NIC, and packets with bad checksums are
+    /// handled according to [ChecksumConfig::policy](ChecksumConfig::policy). Defaults to
+    /// `false`.
+    #[serde(default = "default_rx_checksum")]
+    pub rx_checksum: bool,
+
+    /// If set, the NIC strips the outer VLAN tag of received frames. Defaults to `false`.
+    ///
+    /// ## Remarks
+    /// The stripped tag is restored from the packet metadata when the frame is parsed, so VLAN
+    /// IDs, S-tags and C-tags are reported the same whether or not the tag was stripped.
+    #[serde(default = "default_vlan_strip")]
+    pub vlan_strip: bool,
+
+    /// If set, frames larger than an mbuf are received in multiple chained segments. Defaults to
+    /// `false`.
+    ///
+    /// ## Remarks
+    /// Packets are only parsed and delivered up to the end of their first segment.
+    #[serde(default = "default_scatter")]
+    pub scatter: bool,
+
+    /// If set, packets are timestamped by the NIC on reception if it supports RX timestamp
+    /// offload, and by software otherwise (see [timebase](crate::timebase)). Defaults to `true`.
+    #[serde(default = "default_rx_timestamp")]
+    pub rx_timestamp: bool,
+
+    /// Number of bursts each receive queue of this port may be polled per round, relative to the
+    /// other queues polled by the same core (see [queues](crate::lcore::queues#fairness)), at
+    /// least `1`. Defaults to `1`.
+    #[serde(default = "default_rx_weight")]
+    pub rx_weight: u32,
+
+    /// Rewrite of the packets that forwarding sinks transmit on this port. Defaults to `None` (no
+    /// rewrite).
+    #[serde(default = "default_egress_rewrite")]
+    pub egress_rewrite: Option<EgressRewriteConfig>,
+
+    /// If set, the port is a bonding device named `device`, created from the member devices of
+    /// `bond`. Defaults to `None`, where `device` is the PCI address of the port.
+    #[serde(default = "default_bond")]
+    pub bond: Option<BondConfig>,
+}
+
+impl PortMap {
+    /// Returns the sinks of the port, `sink` first.
+    pub fn sink_configs(&self) -> impl Iterator<Item = &SinkConfig> {
+        self.sink.iter().chain(self.sinks.iter())
+    }
+
+    /// Returns the PCI addresses of the port, i.e. its members if it is a bonding device.
+    pub fn pci_devices(&self) -> Vec<&str> {
+        match &self.bond {
+            Some(bond) => bond.members.iter().map(String::as_str).collect(),
+            None => vec![self.device.as_str()],
+        }
+    }
 }
 
 fn default_sink() -> Option<SinkConfig> {
     None
 }
 
+fn default_sinks() -> Vec<SinkConfig> {
+    vec![]
+}
+
+fn default_port_promiscuous() -> Option<bool> {
+    None
+}
+
+fn default_port_mtu() -> Option<usize> {
+    None
+}
+
+fn default_rx_checksum() -> bool {
+    false
+}
+
+fn default_vlan_strip() -> bool {
+    false
+}
+
+fn default_scatter() -> bool {
+    false
+}
+
+fn default_rx_timestamp() -> bool {
+    true
+}
+
+fn default_rx_weight() -> u32 {
+    1
+}
+
+fn default_egress_rewrite() -> Option<EgressRewriteConfig> {
+    None
+}
+
+fn default_bond() -> Option<BondConfig> {
+    None
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Bonded port options.
+///
+/// The member devices of a bond are aggregated into a single DPDK bonding device, e.g. capture
+/// NICs configured as an LACP bond on the switch. The bond is polled, filtered and reported like
+/// any other port, with the statistics of its members aggregated (see
+/// [bond](crate::port::bond)). Requires the `bonding` feature.
+///
+/// ## Example
+/// ```toml
+/// [[online.ports]]
+///     device = "net_bonding0"
+///     cores = [1,2,3,4]
+///
+/// [online.ports.bond]
+///     members = ["0000:3b:00.0", "0000:3b:00.1"]
+///     mode = "lacp"
+///     hash = "l34"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BondConfig {
+    /// PCI addresses of the member devices.
+    pub members: Vec<String>,
+
+    /// Bonding mode. Defaults to `lacp`.
+    #[serde(default = "default_bond_mode")]
+    pub mode: BondMode,
+
+    /// Header fields hashed to pick the member transmitting each packet in the `balance` and
+    /// `lacp` modes. Defaults to `l34`.
+    #[serde(default = "default_bond_hash")]
+    pub hash: BondHash,
+}
+
+/// Bonding mode of a bonded port.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BondMode {
+    /// Active-backup: a single member is active at a time.
+    Backup,
+    /// Static link aggregation, packets are spread across members by `hash`.
+    Balance,
+    /// 802.3ad (LACP) dynamic link aggregation, packets are spread across members by `hash`.
+    Lacp,
+}
+
+/// Transmit hash policy of a bonded port.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BondHash {
+    /// Source and destination MAC addresses.
+    L2,
+    /// MAC and IP addresses.
+    L23,
+    /// IP addresses and transport ports.
+    L34,
+}
+
+fn default_bond_mode() -> BondMode {
+    BondMode::Lacp
+}
+
+fn default_bond_hash() -> BondHash {
+    BondHash::L34
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Rewrite of the packets transmitted on a port by forwarding sinks, e.g. to reach the next hop of
+/// an inline deployment (see [rewrite](crate::filter::rewrite)).
+///
+/// ## Example
+/// ```toml
+/// [[online.ports]]
+///     device = "0000:3b:00.1"
+///     cores = [9]
+///
+/// [online.ports.egress_rewrite]
+///     dst_mac = "0c:42:a1:00:00:01"
+///     vlan = "200"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EgressRewriteConfig {
+    /// Destination MAC address written to transmitted frames. Defaults to `None`, which keeps the
+    /// destination address.
+    #[serde(default = "default_rewrite_dst_mac")]
+    pub dst_mac: Option<String>,
+
+    /// VLAN rewrite: `"strip"` removes the outer VLAN tag, a VLAN ID such as `"200"` replaces the
+    /// VLAN ID of the outer tag, or tags untagged frames with it. Defaults to `None`, which keeps
+    /// VLAN tags.
+    #[serde(default = "default_rewrite_vlan")]
+    pub vlan: Option<String>,
+}
+
+fn default_rewrite_dst_mac() -> Option<String> {
+    None
+}
+
+fn default_rewrite_vlan() -> Option<String> {
+    None
+}
+
 /* --------------------------------------------------------------------------------- */
 
 /// Statistics logging and live monitoring operations.
@@ -412,6 +1489,14 @@ pub struct MonitorConfig {
     /// Logging configuration. Defaults to `None` (no logs).
     #[serde(default = "default_log")]
     pub log: Option<LogConfig>,
+
+    /// Sampled packet export configuration. Defaults to `None` (no export).
+    #[serde(default = "default_sflow")]
+    pub sflow: Option<SflowConfig>,
+
+    /// Signal-driven state dumps and tracing. Defaults to `None` (signals not handled).
+    #[serde(default = "default_signals")]
+    pub signals: Option<SignalConfig>,
 }
 
 fn default_display() -> Option<DisplayConfig> {
@@ -422,6 +1507,14 @@ fn default_log() -> Option<LogConfig> {
     None
 }
 
+fn default_sflow() -> Option<SflowConfig> {
+    None
+}
+
+fn default_signals() -> Option<SignalConfig> {
+    None
+}
+
 /* --------------------------------------------------------------------------------- */
 
 /// Live statistics display options.
@@ -502,4 +1595,1904 @@ fn default_log_interval() -> u64 {
 
 fn default_log_port_stats() -> Vec<String> {
     vec!["rx".to_string()]
-}
\ No newline at end of file
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Sampled packet export options.
+///
+/// If enabled, each RX core randomly samples 1-in-`sampling_rate` received packets and exports
+/// their headers to an sFlow version 5 collector.
+///
+/// ## Example
+/// ```toml
+/// [online.monitor.sflow]
+///     collector = "10.0.0.1:6343"
+///     sampling_rate = 4096
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SflowConfig {
+    /// Collector address, as `host:port`.
+    pub collector: String,
+
+    /// Agent address reported to the collector. Defaults to the local address used to reach the
+    /// collector.
+    #[serde(default = "default_sflow_agent")]
+    pub agent: Option<IpAddr>,
+
+    /// Mean number of packets per sample. Defaults to `1024`.
+    #[serde(default = "default_sflow_sampling_rate")]
+    pub sampling_rate: u32,
+
+    /// Maximum number of bytes exported from the start of each sampled frame. Defaults to `128`.
+    #[serde(default = "default_sflow_header_size")]
+    pub header_size: usize,
+}
+
+fn default_sflow_agent() -> Option<IpAddr> {
+    None
+}
+
+fn default_sflow_sampling_rate() -> u32 {
+    1024
+}
+
+fn default_sflow_header_size() -> usize {
+    128
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Signal handling options.
+///
+/// If enabled, the main core handles `SIGUSR1` by dumping the current runtime state to the log,
+/// and to a `state-<timestamp>.json` file in the monitor log directory if logging (see
+/// [signals](crate::runtime::signals)). `SIGUSR2` toggles packet tracing on core `trace_core`
+/// (see [trace](crate::filter::trace)), and logs the recorded events when tracing is turned off.
+/// Requires the `signal-dump` feature.
+///
+/// ## Example
+/// ```toml
+/// [online.monitor.signals]
+///     trace_core = 2
+/// ```
+/// ```sh
+/// kill -USR1 $(pidof retina)
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SignalConfig {
+    /// Core whose tracing is toggled by `SIGUSR2`. Defaults to `None` (`SIGUSR2` ignored).
+    #[serde(default = "default_signals_trace_core")]
+    pub trace_core: Option<u32>,
+}
+
+fn default_signals_trace_core() -> Option<u32> {
+    None
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Flow key options.
+///
+/// Controls which packet fields [Flow](crate::protocols::layer4::Flow) keys are built from. By
+/// default, a flow is identified by its VLAN ID, address pair (including ports), and transport
+/// protocol. The runtime applies these options to the flow table through
+/// [FilterCtx::get_flow](crate::filter::FilterCtx::get_flow), so the same keys appear in alerts and
+/// hooks.
+///
+/// ## Example
+/// Track host pairs regardless of ports:
+/// ```toml
+/// [flow_key]
+///     include_ports = false
+///     include_dscp = true
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FlowKeyConfig {
+    /// Whether the IPv6 flow label is part of the flow key. Has no effect on IPv4 traffic.
+    /// Defaults to `false`.
+    #[serde(default = "default_include_flow_label")]
+    pub include_flow_label: bool,
+
+    /// Whether transport ports are part of the flow key. If `false`, all traffic between two hosts
+    /// with the same transport protocol is one flow. Defaults to `true`.
+    #[serde(default = "default_include_ports")]
+    pub include_ports: bool,
+
+    /// Whether the DSCP value (upper 6 bits of the IPv4 ToS or IPv6 traffic class) is part of the
+    /// flow key. Defaults to `false`.
+    #[serde(default = "default_include_dscp")]
+    pub include_dscp: bool,
+
+    /// Whether a hash of the full VLAN tag stack is part of the flow key, in addition to the
+    /// innermost VLAN ID. Defaults to `false`.
+    #[serde(default = "default_include_vlan_stack")]
+    pub include_vlan_stack: bool,
+
+    /// Seed of the flow hash (see [hash](crate::utils::hash)). Runs with the same seed map flows
+    /// to the same hashes. Defaults to `0`.
+    #[serde(default = "default_hash_seed")]
+    pub hash_seed: u64,
+}
+
+fn default_include_flow_label() -> bool {
+    false
+}
+
+fn default_include_ports() -> bool {
+    true
+}
+
+fn default_include_dscp() -> bool {
+    false
+}
+
+fn default_include_vlan_stack() -> bool {
+    false
+}
+
+fn default_hash_seed() -> u64 {
+    0
+}
+
+impl Default for FlowKeyConfig {
+    fn default() -> Self {
+        FlowKeyConfig {
+            include_flow_label: default_include_flow_label(),
+            include_ports: default_include_ports(),
+            include_dscp: default_include_dscp(),
+            include_vlan_stack: default_include_vlan_stack(),
+            hash_seed: default_hash_seed(),
+        }
+    }
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Payload scan depth options.
+///
+/// Limits how many payload bytes of each flow are matched against the rules. Matches tend to occur
+/// early in flows, so scanning only the beginning of each flow saves a lot of work on long
+/// transfers. The flow offset of every match is recorded and reported by the monitor.
+///
+/// ## Example
+/// ```toml
+/// [scan]
+///     adaptive = true
+///     adaptive_percentile = 0.999
+///     max_len = 9000
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ScanConfig {
+    /// Maximum number of payload bytes scanned per flow. Overrides adaptive mode if set. Defaults to
+    /// `None` (scan whole flows).
+    #[serde(default = "default_scan_depth")]
+    pub depth: Option<usize>,
+
+    /// If set, the scan depth is derived from the observed match offsets. Defaults to `false`.
+    ///
+    /// ## Remarks
+    /// The scan depth is set to the flow offset below which `adaptive_percentile` of matches
+    /// ended, rounded up to the next power of two. It is recomputed once per second by the main
+    /// core. Until `adaptive_min_samples` matches were seen, whole flows are scanned. A sample of
+    /// the flows is still scanned whole, so that the depth can grow back (see
+    /// [scan](crate::filter::scan)).
+    #[serde(default = "default_scan_adaptive")]
+    pub adaptive: bool,
+
+    /// Fraction of matches that must fall within the adaptive scan depth. Defaults to `0.99`.
+    #[serde(default = "default_scan_adaptive_percentile")]
+    pub adaptive_percentile: f64,
+
+    /// Number of matches required before the adaptive scan depth is applied. Defaults to `1000`.
+    #[serde(default = "default_scan_adaptive_min_samples")]
+    pub adaptive_min_samples: u64,
+
+    /// Application protocols whose flows are not scanned once identified, e.g. `["dtls",
+    /// "rtp"]`. Defaults to `[]` (all flows are scanned).
+    ///
+    /// ## Remarks
+    /// Payloads are scanned until the protocol of the flow is identified (see
+    /// [app](crate::protocols::app)). Skipped packets are counted as classified in the scan
+    /// statistics.
+    #[serde(default = "default_scan_skip_apps")]
+    pub skip_apps: Vec<AppProtocol>,
+
+    /// Minimum payload length, in bytes, of the packets matched against the rules. Defaults to
+    /// `None` (no minimum).
+    ///
+    /// ## Remarks
+    /// Payloads outside the length gates are not scanned at all, and are counted as gated in the
+    /// scan statistics. Rules can set their own gates, see [rule](crate::filter::rule).
+    #[serde(default = "default_scan_min_len")]
+    pub min_len: Option<usize>,
+
+    /// Maximum payload length, in bytes, of the packets matched against the rules. Defaults to
+    /// `None` (no maximum).
+    #[serde(default = "default_scan_max_len")]
+    pub max_len: Option<usize>,
+}
+
+fn default_scan_depth() -> Option<usize> {
+    None
+}
+
+fn default_scan_adaptive() -> bool {
+    false
+}
+
+fn default_scan_adaptive_percentile() -> f64 {
+    0.99
+}
+
+fn default_scan_adaptive_min_samples() -> u64 {
+    1000
+}
+
+fn default_scan_skip_apps() -> Vec<AppProtocol> {
+    vec![]
+}
+
+fn default_scan_min_len() -> Option<usize> {
+    None
+}
+
+fn default_scan_max_len() -> Option<usize> {
+    None
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            depth: default_scan_depth(),
+            adaptive: default_scan_adaptive(),
+            adaptive_percentile: default_scan_adaptive_percentile(),
+            adaptive_min_samples: default_scan_adaptive_min_samples(),
+            skip_apps: default_scan_skip_apps(),
+            min_len: default_scan_min_len(),
+            max_len: default_scan_max_len(),
+        }
+    }
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Match alert options.
+///
+/// Payload matches are published as JSON datagrams to each subscriber socket. Subscribers that are
+/// not listening or fall behind miss alerts, which are counted as dropped for that subscriber.
+///
+/// ## Example
+/// ```toml
+/// [alert]
+///     subscribers = ["/run/retina/alerts.sock", "@retina-alerts"]
+///     icmp_errors = true
+///     context_bytes = 128
+///     context_encoding = "hex"
+///     anonymize = true
+///     redact = ['acct=\d+']
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AlertConfig {
+    /// Unix datagram socket addresses of the alert subscribers. Addresses prefixed with `@` refer
+    /// to abstract sockets.
+    pub subscribers: Vec<String>,
+
+    /// Also publish ICMP errors correlated to tracked flows (see
+    /// [icmp](crate::protocols::icmp)). Defaults to `false`.
+    #[serde(default = "default_alert_icmp_errors")]
+    pub icmp_errors: bool,
+
+    /// Number of payload bytes captured before and after the match of each rule and attached to
+    /// its alert, along with the pattern of the rule. Defaults to `0` (no context).
+    ///
+    /// ## Remarks
+    /// Unless `anonymize` is set, captured payload bytes are published as is, so subscribers may
+    /// receive sensitive data. The match is located during the scan of the payload, with the
+    /// matching rule compiled on its own once per rule set.
+    #[serde(default = "default_alert_context_bytes")]
+    pub context_bytes: usize,
+
+    /// Encoding of the captured context. Defaults to `base64`.
+    #[serde(default = "default_alert_context_encoding")]
+    pub context_encoding: ContextEncoding,
+
+    /// Redact personal data from the captured context before it is published: IPv4, IPv6 and
+    /// email addresses, the values of HTTP `Authorization` and `Cookie` headers, and the bytes
+    /// matched by `redact` are replaced with `*`, keeping the length of the context. Personal data
+    /// that starts or ends outside the context is redacted as well. Defaults to `false`.
+    #[serde(default = "default_alert_anonymize")]
+    pub anonymize: bool,
+
+    /// Additional patterns redacted from the captured context when `anonymize` is set, e.g.
+    /// account numbers. Compiled when the configuration is applied. Defaults to none.
+    #[serde(default = "default_alert_redact")]
+    pub redact: Vec<String>,
+}
+
+/// Encoding of the payload context attached to alerts.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextEncoding {
+    /// Lowercase hexadecimal digits.
+    Hex,
+    /// Standard Base64 with padding.
+    Base64,
+}
+
+fn default_alert_icmp_errors() -> bool {
+    false
+}
+
+fn default_alert_context_bytes() -> usize {
+    0
+}
+
+fn default_alert_context_encoding() -> ContextEncoding {
+    ContextEncoding::Base64
+}
+
+fn default_alert_anonymize() -> bool {
+    false
+}
+
+fn default_alert_redact() -> Vec<String> {
+    vec![]
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Per-core packet tracing options.
+///
+/// Traced cores record their most recent packets, flow table lookups, filter verdicts and errors
+/// in a ring buffer (see [trace](crate::filter::trace)). Tracing can also be enabled and disabled
+/// while running with [FilterCtx::enable_trace](crate::filter::FilterCtx::enable_trace).
+///
+/// ## Example
+/// ```toml
+/// [trace]
+///     cores = [1]
+///     nb_records = 4096
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TraceConfig {
+    /// Cores traced from startup. Defaults to `[]`.
+    #[serde(default = "default_trace_cores")]
+    pub cores: Vec<u32>,
+
+    /// Number of records kept per core. Defaults to `1024`.
+    #[serde(default = "default_trace_nb_records")]
+    pub nb_records: usize,
+}
+
+fn default_trace_cores() -> Vec<u32> {
+    vec![]
+}
+
+fn default_trace_nb_records() -> usize {
+    1024
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        TraceConfig {
+            cores: default_trace_cores(),
+            nb_records: default_trace_nb_records(),
+        }
+    }
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Rule cost profiling options.
+///
+/// If enabled, sampled payloads are evaluated against each rule individually to measure its cost
+/// (see [profile](crate::filter::profile)), and the most expensive rules are logged periodically.
+/// Profiling adds work to the packet processing cores, so it should only be enabled temporarily.
+///
+/// ## Example
+/// ```toml
+/// [profile]
+///     sample_rate = 10000
+///     report_interval = 30
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ProfileConfig {
+    /// Profile 1-in-`sample_rate` scanned payloads. Defaults to `1000`.
+    #[serde(default = "default_profile_sample_rate")]
+    pub sample_rate: u64,
+
+    /// How often to log rule costs (in seconds). Defaults to `10`.
+    #[serde(default = "default_profile_report_interval")]
+    pub report_interval: u64,
+
+    /// Number of most expensive rules logged. Defaults to `10`.
+    #[serde(default = "default_profile_nb_reported")]
+    pub nb_reported: usize,
+}
+
+fn default_profile_sample_rate() -> u64 {
+    1000
+}
+
+fn default_profile_report_interval() -> u64 {
+    10
+}
+
+fn default_profile_nb_reported() -> usize {
+    10
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Live packet tap options.
+///
+/// Packets passed to [FilterCtx::tap_packet](crate::filter::FilterCtx::tap_packet) are written as a
+/// pcap stream to `path`, typically a named pipe read by a local tool such as `wireshark -k -i`
+/// (see [tap](crate::filter::tap)).
+///
+/// ## Example
+/// ```toml
+/// [tap]
+///     path = "/tmp/retina.pcap"
+///     max_pps = 2000
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TapConfig {
+    /// Path of the named pipe or file the pcap stream is written to. A regular file is created if
+    /// it does not exist.
+    pub path: String,
+
+    /// Maximum number of bytes written per packet, lowered by the `snaplen` of the rule a packet
+    /// matched, if set. Defaults to `65535`.
+    #[serde(default = "default_tap_snaplen")]
+    pub snaplen: usize,
+
+    /// Maximum number of packets tapped per second, `0` for no limit. Defaults to `10000`.
+    #[serde(default = "default_tap_max_pps")]
+    pub max_pps: u64,
+
+    /// Number of packets queued for writing before packets are dropped. Defaults to `4096`.
+    #[serde(default = "default_tap_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_tap_snaplen() -> usize {
+    65535
+}
+
+fn default_tap_max_pps() -> u64 {
+    10000
+}
+
+fn default_tap_queue_size() -> usize {
+    4096
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Rolling capture options.
+///
+/// Packets passed to [FilterCtx::capture_packet](crate::filter::FilterCtx::capture_packet) that
+/// match a rule with the `capture` action are written to pcap files in `directory` (see
+/// [capture](crate::filter::capture)). A new file is started when the current one exceeds
+/// `max_file_size` bytes or `max_file_age` seconds, and the oldest files are removed beyond
+/// `max_files`. With `dedup`, packets whose payload was already captured are stored as
+/// references to the first capture. Closed files can be handed to an external command or webhook
+/// with the `[capture.finalize]` options. With `format = "pcapng"`, packets are stored with their
+/// [metadata](crate::filter::store).
+///
+/// ## Example
+/// ```toml
+/// [capture]
+///     directory = "/var/lib/retina/capture"
+///     max_file_size = 100_000_000
+///     max_files = 20
+///     dedup = true
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CaptureConfig {
+    /// Directory the pcap files are written to. Created if it does not exist.
+    pub directory: String,
+
+    /// File format. Defaults to `pcap`.
+    #[serde(default = "default_capture_format")]
+    pub format: CaptureFormat,
+
+    /// Maximum number of bytes written per packet, lowered by the `snaplen` of the rule a packet
+    /// matched, if set. Defaults to `65535`.
+    #[serde(default = "default_capture_snaplen")]
+    pub snaplen: usize,
+
+    /// Size (in bytes) at which a new file is started. Defaults to `100_000_000`.
+    #[serde(default = "default_capture_max_file_size")]
+    pub max_file_size: u64,
+
+    /// Age (in seconds) at which a new file is started. Defaults to `3600`.
+    #[serde(default = "default_capture_max_file_age")]
+    pub max_file_age: u64,
+
+    /// Maximum number of files kept, `0` for no limit. Only files written by the running process
+    /// are removed. Defaults to `10`.
+    #[serde(default = "default_capture_max_files")]
+    pub max_files: usize,
+
+    /// Number of packets queued for writing before packets are dropped. Defaults to `4096`.
+    #[serde(default = "default_capture_queue_size")]
+    pub queue_size: usize,
+
+    /// Store packets whose payload was already captured without their payload, and reference the
+    /// first capture in a `.refs.csv` file next to the pcap file. Defaults to `false`.
+    #[serde(default = "default_capture_dedup")]
+    pub dedup: bool,
+
+    /// Memory (in bytes) used to remember captured payloads for deduplication, the oldest
+    /// payloads being forgotten first. Defaults to `67_108_864`.
+    #[serde(default = "default_capture_dedup_memory")]
+    pub dedup_memory: usize,
+
+    /// External actions run on each closed file. Defaults to `None`.
+    #[serde(default = "default_capture_finalize")]
+    pub finalize: Option<FinalizeConfig>,
+}
+
+/// Format of the rolling capture files.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureFormat {
+    /// Classic pcap, with microsecond timestamps and no metadata.
+    Pcap,
+    /// pcapng, with nanosecond timestamps and the metadata of each packet.
+    Pcapng,
+}
+
+fn default_capture_format() -> CaptureFormat {
+    CaptureFormat::Pcap
+}
+
+fn default_capture_snaplen() -> usize {
+    65535
+}
+
+fn default_capture_max_file_size() -> u64 {
+    100_000_000
+}
+
+fn default_capture_max_file_age() -> u64 {
+    3600
+}
+
+fn default_capture_max_files() -> usize {
+    10
+}
+
+fn default_capture_queue_size() -> usize {
+    4096
+}
+
+fn default_capture_dedup() -> bool {
+    false
+}
+
+fn default_capture_dedup_memory() -> usize {
+    67_108_864
+}
+
+fn default_capture_finalize() -> Option<FinalizeConfig> {
+    None
+}
+
+/// Capture file finalization options.
+///
+/// Each file closed by the rolling capture is described by a manifest entry, serialized as JSON,
+/// which is written to the standard input of `command` and posted to `webhook` (see
+/// [finalize](crate::filter::finalize)). Both run on a background thread, one file at a time.
+///
+/// ## Example
+/// ```toml
+/// [capture.finalize]
+///     command = ["/usr/local/bin/index-pcap", "--json"]
+///     webhook = "http://indexer.local:8080/captures"
+///     timeout_secs = 60
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FinalizeConfig {
+    /// Program and arguments run on each closed file. The path of the pcap file is also set in the
+    /// `RETINA_CAPTURE_FILE` environment variable. Defaults to `None`.
+    #[serde(default = "default_finalize_command")]
+    pub command: Option<Vec<String>>,
+
+    /// `http://` URL the manifest entry of each closed file is posted to. Defaults to `None`.
+    #[serde(default = "default_finalize_webhook")]
+    pub webhook: Option<String>,
+
+    /// Time (in seconds) after which the command is killed, or the webhook request abandoned.
+    /// Defaults to `30`.
+    #[serde(default = "default_finalize_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_finalize_command() -> Option<Vec<String>> {
+    None
+}
+
+fn default_finalize_webhook() -> Option<String> {
+    None
+}
+
+fn default_finalize_timeout_secs() -> u64 {
+    30
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Pre-match packet backfill options.
+///
+/// Flows that have not matched yet keep copies of their last `nb_packets` packets, which are
+/// queued to the [rolling capture](CaptureConfig) and to the [collectors](ForwardConfig) ahead of
+/// the first matching packet of the flow (see [backfill](crate::filter::backfill)).
+///
+/// ## Example
+/// ```toml
+/// [backfill]
+///     nb_packets = 32
+///     snaplen = 256
+///     max_bytes = 268_435_456
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BackfillConfig {
+    /// Number of packets buffered per flow, the oldest being discarded first. Defaults to `16`.
+    #[serde(default = "default_backfill_nb_packets")]
+    pub nb_packets: usize,
+
+    /// Maximum number of bytes buffered per packet. Defaults to `65535`.
+    #[serde(default = "default_backfill_snaplen")]
+    pub snaplen: usize,
+
+    /// Memory (in bytes) of the buffers of all flows, beyond which packets are not buffered.
+    /// Defaults to `67_108_864`.
+    #[serde(default = "default_backfill_max_bytes")]
+    pub max_bytes: usize,
+}
+
+fn default_backfill_nb_packets() -> usize {
+    16
+}
+
+fn default_backfill_snaplen() -> usize {
+    65535
+}
+
+fn default_backfill_max_bytes() -> usize {
+    67_108_864
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Storage queue priority options.
+///
+/// The queues of the [tap](TapConfig) and the [capture](CaptureConfig) have one priority class
+/// per weight, the first being the highest, each holding up to the `queue_size` of its queue (see
+/// [priority](crate::filter::priority)). Writer threads empty higher classes first with `strict`
+/// dequeue, or take up to `weight` packets of each non-empty class in turn with `weighted`
+/// dequeue.
+///
+/// ## Example
+/// ```toml
+/// [storage_priority]
+///     dequeue = "weighted"
+///     weights = [8, 2, 1]
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StoragePriorityConfig {
+    /// Order in which the classes are written. Defaults to `strict`.
+    #[serde(default = "default_storage_priority_dequeue")]
+    pub dequeue: Dequeue,
+
+    /// Weight of each class, highest class first. Only the number of weights matters with
+    /// `strict` dequeue. Defaults to `[4, 1]`.
+    #[serde(default = "default_storage_priority_weights")]
+    pub weights: Vec<u32>,
+}
+
+/// Dequeue order of priority classes.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Dequeue {
+    /// Lower classes are written only while higher classes are empty.
+    Strict,
+    /// Classes are written in turn, up to their weight in packets per turn.
+    Weighted,
+}
+
+fn default_storage_priority_dequeue() -> Dequeue {
+    Dequeue::Strict
+}
+
+fn default_storage_priority_weights() -> Vec<u32> {
+    vec![4, 1]
+}
+
+impl Default for StoragePriorityConfig {
+    fn default() -> Self {
+        StoragePriorityConfig {
+            dequeue: default_storage_priority_dequeue(),
+            weights: default_storage_priority_weights(),
+        }
+    }
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Cross-flow verdict cache options.
+///
+/// Flows that expire without matching clear their client, server name or address and server port,
+/// so that new flows between the same client and server skip payload scanning for a while (see
+/// [cache](crate::filter::cache)).
+///
+/// ## Example
+/// ```toml
+/// [verdict_cache]
+///     ttl = 600
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct VerdictCacheConfig {
+    /// How long a cleared key is trusted (in seconds). Defaults to `300`.
+    #[serde(default = "default_verdict_cache_ttl")]
+    pub ttl: u64,
+
+    /// Maximum number of cleared keys. Defaults to `100000`.
+    #[serde(default = "default_verdict_cache_capacity")]
+    pub capacity: usize,
+}
+
+fn default_verdict_cache_ttl() -> u64 {
+    300
+}
+
+fn default_verdict_cache_capacity() -> usize {
+    100_000
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Heavy-hitter detection options.
+///
+/// RX cores track the source addresses, destination addresses and flows that carry the most bytes
+/// in fixed-size sketches, which are merged and reported by the monitor (see
+/// [talkers](crate::filter::talkers)).
+///
+/// ## Example
+/// ```toml
+/// [top_talkers]
+///     sample_rate = 100
+///     nb_reported = 5
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TopTalkersConfig {
+    /// One in `sample_rate` packets is counted on each core. Defaults to `16`.
+    #[serde(default = "default_top_talkers_sample_rate")]
+    pub sample_rate: u64,
+
+    /// Number of counters per core for each of sources, destinations and flows. Defaults to
+    /// `256`.
+    ///
+    /// ## Remarks
+    /// Talkers carrying more than `1 / capacity` of the sampled bytes of a core are guaranteed to
+    /// be tracked. More counters reduce the estimation error at the cost of a slower update when a
+    /// new talker replaces the smallest one.
+    #[serde(default = "default_top_talkers_capacity")]
+    pub capacity: usize,
+
+    /// Number of talkers of each kind displayed and exported. Defaults to `10`.
+    #[serde(default = "default_top_talkers_nb_reported")]
+    pub nb_reported: usize,
+}
+
+fn default_top_talkers_sample_rate() -> u64 {
+    16
+}
+
+fn default_top_talkers_capacity() -> usize {
+    256
+}
+
+fn default_top_talkers_nb_reported() -> usize {
+    10
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Rules directory options.
+///
+/// The rules of all `.json` files in `directory` are loaded on initialization. With the
+/// `rule-watch` feature, the directory is watched and the rules are reloaded whenever a file is
+/// written, moved or removed (see [watch](crate::filter::watch)).
+///
+/// ## Example
+/// ```toml
+/// [rule_watch]
+///     directory = "/etc/retina/rules.d"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RuleWatchConfig {
+    /// Directory of the rule files.
+    pub directory: String,
+
+    /// How long to wait for more changes before reloading (in milliseconds). Defaults to `500`.
+    #[serde(default = "default_rule_watch_debounce")]
+    pub debounce: u64,
+}
+
+fn default_rule_watch_debounce() -> u64 {
+    500
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Neighbor table options.
+///
+/// RX cores record the MAC address each IP address is seen with, from ARP and IP traffic. The
+/// table is required by rules scoped to MAC addresses (see [neighbors](crate::filter::neighbors)).
+///
+/// ## Example
+/// ```toml
+/// [neighbors]
+///     ttl = 600
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NeighborConfig {
+    /// How long an address is kept after it was last seen (in seconds). Defaults to `3600`.
+    #[serde(default = "default_neighbor_ttl")]
+    pub ttl: u64,
+
+    /// Maximum number of addresses. Defaults to `65536`.
+    #[serde(default = "default_neighbor_capacity")]
+    pub capacity: usize,
+}
+
+fn default_neighbor_ttl() -> u64 {
+    3600
+}
+
+fn default_neighbor_capacity() -> usize {
+    65536
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// NUMA placement options.
+///
+/// On initialization, the runtime reports the socket of every port, of the core polling each of
+/// its receive queues, and of the mempool its mbufs are allocated from. A core or mempool on a
+/// different socket than the port it serves crosses the inter-socket link for every packet, and
+/// is reported as a warning, or as an error if `strict` is set.
+///
+/// ## Example
+/// ```toml
+/// [online.numa]
+///     strict = true
+///     auto_assign = true
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NumaConfig {
+    /// If set, cross-NUMA assignments fail initialization. Defaults to `false`.
+    #[serde(default = "default_numa_strict")]
+    pub strict: bool,
+
+    /// If set, cores that poll a port on a different socket are replaced by unused cores on the
+    /// socket of the port, when available. Defaults to `false`.
+    ///
+    /// ## Remarks
+    /// The sockets of cores and devices are read from sysfs before DPDK is initialized, so this
+    /// only applies to PCI devices.
+    #[serde(default = "default_numa_auto_assign")]
+    pub auto_assign: bool,
+}
+
+fn default_numa_strict() -> bool {
+    false
+}
+
+fn default_numa_auto_assign() -> bool {
+    false
+}
+
+impl Default for NumaConfig {
+    fn default() -> Self {
+        NumaConfig {
+            strict: default_numa_strict(),
+            auto_assign: default_numa_auto_assign(),
+        }
+    }
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Counting rule export options.
+///
+/// The counters of rules with the `count` action (see [rule](crate::filter::rule)) are written to
+/// `directory` every `interval` seconds, and once more when the runtime stops, in each of
+/// `formats`:
+/// - `csv`: a row per rule and export is appended to `counters.csv`.
+/// - `json`: the current counters are written to `counters.json`.
+/// - `prometheus`: the current counters are written to `counters.prom`, in the Prometheus text
+///   format, e.g. for the node exporter textfile collector.
+///
+/// ## Example
+/// ```toml
+/// [counters]
+///     directory = "./counters"
+///     interval = 10
+///     formats = ["csv", "prometheus"]
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CounterConfig {
+    /// Directory the counters are written to.
+    pub directory: String,
+
+    /// Export interval (in seconds). Defaults to `10`.
+    #[serde(default = "default_counter_interval")]
+    pub interval: u64,
+
+    /// Export formats. Defaults to `["csv"]`.
+    #[serde(default = "default_counter_formats")]
+    pub formats: Vec<CounterFormat>,
+}
+
+/// Export format of rule counters.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CounterFormat {
+    Csv,
+    Json,
+    Prometheus,
+}
+
+fn default_counter_interval() -> u64 {
+    10
+}
+
+fn default_counter_formats() -> Vec<CounterFormat> {
+    vec![CounterFormat::Csv]
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Checksum validation options.
+///
+/// Packets parsed with [FilterCtx::parse_l4](crate::filter::FilterCtx::parse_l4) are checked for
+/// bad IPv4 header, TCP and UDP checksums, found by the NIC if
+/// [PortMap::rx_checksum](PortMap::rx_checksum) is set, or in software if `software` is set. The
+/// outcome of each check is counted (see [checksum](crate::filter::checksum)).
+///
+/// ## Example
+/// ```toml
+/// [checksum]
+///     software = true
+///     policy = "flag"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ChecksumConfig {
+    /// If set, checksums are verified in software. Defaults to `false`.
+    ///
+    /// ## Remarks
+    /// Software verification reads every byte of the packet. Prefer NIC offload when the NIC
+    /// supports it.
+    #[serde(default = "default_checksum_software")]
+    pub software: bool,
+
+    /// What happens to packets with a bad checksum. Defaults to `drop`.
+    #[serde(default = "default_checksum_policy")]
+    pub policy: ChecksumPolicy,
+}
+
+/// Handling of packets with a bad checksum.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumPolicy {
+    /// The packet is dropped and counted as a
+    /// [BadChecksum](crate::filter::drops::DropReason::BadChecksum) drop.
+    Drop,
+    /// The packet is kept, with
+    /// [L4Context::bad_checksum](crate::protocols::layer4::L4Context::bad_checksum) set.
+    Flag,
+    /// The packet is kept as if its checksums were correct.
+    Ignore,
+}
+
+fn default_checksum_software() -> bool {
+    false
+}
+
+fn default_checksum_policy() -> ChecksumPolicy {
+    ChecksumPolicy::Drop
+}
+
+impl Default for ChecksumConfig {
+    fn default() -> Self {
+        ChecksumConfig {
+            software: default_checksum_software(),
+            policy: default_checksum_policy(),
+        }
+    }
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Shadow rule set options.
+///
+/// A candidate rule set is evaluated on sampled payloads next to the active rules, only to count
+/// its matches (see [shadow](crate::filter::shadow)). Shadow rules can also be loaded at runtime
+/// with [FilterCtx::load_shadow_rules](crate::filter::FilterCtx::load_shadow_rules).
+///
+/// ## Example
+/// ```toml
+/// [shadow]
+///     rules = "/etc/retina/candidate.json"
+///     sample_rate = 10
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ShadowConfig {
+    /// Path to a JSON rule file loaded as the shadow rule set on initialization. Defaults to
+    /// `None`.
+    #[serde(default = "default_shadow_rules")]
+    pub rules: Option<String>,
+
+    /// Evaluate shadow rules on 1-in-`sample_rate` checked payloads. Defaults to `1` (every
+    /// payload).
+    #[serde(default = "default_shadow_sample_rate")]
+    pub sample_rate: u64,
+}
+
+fn default_shadow_rules() -> Option<String> {
+    None
+}
+
+fn default_shadow_sample_rate() -> u64 {
+    1
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Flow table size options.
+///
+/// Flows are keyed by a packed, fixed-size copy of their
+/// [Flow](crate::protocols::layer4::Flow) key. If `max_entries` is set, the flow table stops
+/// tracking new flows when it is full, and with the `oldest` eviction policy,
+/// [FilterCtx::prune_flows](crate::filter::FilterCtx::prune_flows) also evicts the least recently
+/// seen flows to make room. Evicted flows are reported to the expiry hooks like expired flows.
+///
+/// Flows that matched at least once can be kept past the flow timeout, e.g. for C2 beacons with
+/// payloads minutes apart, by setting `sticky_timeout_secs`. At most `max_sticky` of the matched
+/// flows idle past the flow timeout are kept, the most recently seen first, until they are idle
+/// for `sticky_timeout_secs`. These flows are not evicted to make room, so `max_sticky` should be
+/// well below `max_entries`.
+///
+/// ## Example
+/// ```toml
+/// [flow_table]
+///     max_entries = 4000000
+///     eviction = "oldest"
+///     sticky_timeout_secs = 3600
+///     max_sticky = 100000
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FlowTableConfig {
+    /// Maximum number of tracked flows. Defaults to `None` (unlimited).
+    #[serde(default = "default_flow_table_max_entries")]
+    pub max_entries: Option<usize>,
+
+    /// What happens when the flow table is full. Defaults to `oldest`.
+    #[serde(default = "default_flow_table_eviction")]
+    pub eviction: FlowEviction,
+
+    /// Idle timeout (in seconds) of matched flows. Defaults to `None` (matched flows use the flow
+    /// timeout).
+    #[serde(default = "default_flow_table_sticky_timeout_secs")]
+    pub sticky_timeout_secs: Option<u64>,
+
+    /// Maximum number of matched flows kept past the flow timeout. Defaults to `10000`.
+    #[serde(default = "default_flow_table_max_sticky")]
+    pub max_sticky: usize,
+}
+
+/// Handling of new flows when the flow table is full.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowEviction {
+    /// New flows are not tracked until existing flows expire.
+    Reject,
+    /// New flows are not tracked until the next prune, which evicts the least recently seen flows
+    /// down to 90% of `max_entries`.
+    Oldest,
+}
+
+fn default_flow_table_max_entries() -> Option<usize> {
+    None
+}
+
+fn default_flow_table_eviction() -> FlowEviction {
+    FlowEviction::Oldest
+}
+
+fn default_flow_table_sticky_timeout_secs() -> Option<u64> {
+    None
+}
+
+fn default_flow_table_max_sticky() -> usize {
+    10_000
+}
+
+impl Default for FlowTableConfig {
+    fn default() -> Self {
+        FlowTableConfig {
+            max_entries: default_flow_table_max_entries(),
+            eviction: default_flow_table_eviction(),
+            sticky_timeout_secs: default_flow_table_sticky_timeout_secs(),
+            max_sticky: default_flow_table_max_sticky(),
+        }
+    }
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Asymmetric traffic detection options.
+///
+/// Ended flows are classified by the packets each endpoint sent (see
+/// [directions](crate::filter::directions)). The monitor warns when more than
+/// `max_unidirectional` of the flows classified over `interval_secs` are unidirectional, provided
+/// there are at least `min_flows` of them. Flows are flagged as unidirectional in their
+/// [FlowSummary](crate::hooks::FlowSummary) regardless.
+///
+/// ## Example
+/// ```toml
+/// [flow_directions]
+///     min_packets = 4
+///     min_flows = 1000
+///     max_unidirectional = 0.5
+///     interval_secs = 60
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FlowDirectionConfig {
+    /// Minimum number of recorded packets for a flow to be classified. Defaults to `4`.
+    #[serde(default = "default_flow_directions_min_packets")]
+    pub min_packets: u64,
+
+    /// Minimum number of classified flows per interval to warn. Defaults to `1000`.
+    #[serde(default = "default_flow_directions_min_flows")]
+    pub min_flows: u64,
+
+    /// Fraction of unidirectional flows above which the monitor warns, `1.0` to never warn.
+    /// Defaults to `0.5`.
+    #[serde(default = "default_flow_directions_max_unidirectional")]
+    pub max_unidirectional: f64,
+
+    /// Interval (in seconds) between checks. Defaults to `60`.
+    #[serde(default = "default_flow_directions_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_flow_directions_min_packets() -> u64 {
+    4
+}
+
+fn default_flow_directions_min_flows() -> u64 {
+    1000
+}
+
+fn default_flow_directions_max_unidirectional() -> f64 {
+    0.5
+}
+
+fn default_flow_directions_interval_secs() -> u64 {
+    60
+}
+
+impl Default for FlowDirectionConfig {
+    fn default() -> Self {
+        FlowDirectionConfig {
+            min_packets: default_flow_directions_min_packets(),
+            min_flows: default_flow_directions_min_flows(),
+            max_unidirectional: default_flow_directions_max_unidirectional(),
+            interval_secs: default_flow_directions_interval_secs(),
+        }
+    }
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Match rate safeguard options.
+///
+/// Rules whose matches exceed `max_match_rate` matches per second, or whose matching payloads
+/// exceed `max_byte_rate` bytes per second, over an `interval` are disabled until re-enabled with
+/// [FilterCtx::enable_rule](crate::filter::FilterCtx::enable_rule) (see
+/// [throttle](crate::filter::throttle)).
+///
+/// ## Example
+/// ```toml
+/// [throttle]
+///     interval = 10
+///     max_match_rate = 1000.0
+///     max_byte_rate = 10_000_000.0
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ThrottleConfig {
+    /// Measurement interval (in seconds). Defaults to `10`.
+    #[serde(default = "default_throttle_interval")]
+    pub interval: u64,
+
+    /// Maximum matches per second of a rule. Defaults to `None` (unlimited).
+    #[serde(default = "default_throttle_max_match_rate")]
+    pub max_match_rate: Option<f64>,
+
+    /// Maximum bytes of matching payloads per second of a rule. Defaults to `None` (unlimited).
+    #[serde(default = "default_throttle_max_byte_rate")]
+    pub max_byte_rate: Option<f64>,
+}
+
+fn default_throttle_interval() -> u64 {
+    10
+}
+
+fn default_throttle_max_match_rate() -> Option<f64> {
+    None
+}
+
+fn default_throttle_max_byte_rate() -> Option<f64> {
+    None
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Flow table snapshot options.
+///
+/// On shutdown, the flow table is saved to `state_file`. On initialization, flows saved there are
+/// restored and the file is removed, so that an upgraded binary resumes with the flow and match
+/// state of the previous run (see [state](crate::filter::state)). Flows that timed out while the
+/// runtime was down are not restored. Nothing else survives the restart: memory pools and ports
+/// are initialized again, and packets received in between are missed.
+///
+/// ## Example
+/// ```toml
+/// [flow_snapshot]
+///     state_file = "/var/lib/retina/flows.json"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FlowSnapshotConfig {
+    /// Path of the saved flow state.
+    pub state_file: String,
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Regex flag defaults and test policy of rules.
+///
+/// Rules loaded with [FilterCtx::load_rules](crate::filter::FilterCtx::load_rules), from the
+/// rules directory or as shadow rules, that leave `nocase`, `dotall` or `multiline` unset get the
+/// value of this section (see [rule](crate::filter::rule)). The regex set the filter is created
+/// with is already compiled, and is not affected. Rules loaded with
+/// [FilterCtx::load_rules](crate::filter::FilterCtx::load_rules) that fail their
+/// [test vectors](crate::filter::rule#test-vectors) are handled according to `failed_tests`.
+///
+/// ## Example
+/// ```toml
+/// [regex]
+///     nocase = true
+///     failed_tests = "flag"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct RegexConfig {
+    /// Letters match both cases. Defaults to `false`.
+    #[serde(default = "default_regex_nocase")]
+    pub nocase: bool,
+
+    /// `.` also matches line feeds. Defaults to `false`.
+    #[serde(default = "default_regex_dotall")]
+    pub dotall: bool,
+
+    /// `^` and `$` also match at line boundaries. Defaults to `false`.
+    #[serde(default = "default_regex_multiline")]
+    pub multiline: bool,
+
+    /// What happens to rules that fail their test vectors. Defaults to `reject`.
+    #[serde(default = "default_regex_failed_tests")]
+    pub failed_tests: FailedTests,
+}
+
+/// Handling of rules that fail their test vectors.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FailedTests {
+    /// The rules are not loaded, and the other rules are.
+    Reject,
+    /// The rules are loaded, and reported as failing.
+    Flag,
+}
+
+fn default_regex_nocase() -> bool {
+    false
+}
+
+fn default_regex_dotall() -> bool {
+    false
+}
+
+fn default_regex_multiline() -> bool {
+    false
+}
+
+fn default_regex_failed_tests() -> FailedTests {
+    FailedTests::Reject
+}
+
+impl Default for RegexConfig {
+    fn default() -> Self {
+        RegexConfig {
+            nocase: default_regex_nocase(),
+            dotall: default_regex_dotall(),
+            multiline: default_regex_multiline(),
+            failed_tests: default_regex_failed_tests(),
+        }
+    }
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Packet processing stage options.
+///
+/// Only the stages listed in `stages` run, in the listed order (see
+/// [pipeline](crate::subscription::pipeline)). Stages are referred to by
+/// [name](crate::subscription::Stage::name), and names of stages that are not registered are
+/// skipped.
+///
+/// ## Example
+/// ```toml
+/// [pipeline]
+///     stages = ["dedup", "decap", "prefilter"]
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PipelineConfig {
+    /// Names of the stages to run, in order.
+    pub stages: Vec<String>,
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Application protocol of a server port.
+///
+/// Flows to or from `port`, over TCP or UDP, are identified as `app` without checking protocol
+/// signatures or well-known ports, so that rules scoped to `app` apply to services on
+/// non-standard ports (see [app](crate::protocols::app)). Hints can be changed at runtime with
+/// [FilterCtx::set_app_port](crate::filter::FilterCtx::set_app_port).
+///
+/// ## Example
+/// ```toml
+/// [[app_ports]]
+///     port = 8080
+///     app = "http"
+///
+/// [[app_ports]]
+///     port = 8443
+///     app = "tls"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct AppPortConfig {
+    /// Server port.
+    pub port: u16,
+
+    /// Application protocol of the port.
+    pub app: AppProtocol,
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Write-ahead journal options.
+///
+/// Rule set updates, control actions and alerts are queued for the journal before they take
+/// effect or are published, and recorded in `directory` by a background thread, so that they can
+/// be replayed after a crash (see [journal](crate::filter::journal)).
+///
+/// ## Example
+/// ```toml
+/// [journal]
+///     directory = "/var/lib/retina/{instance}"
+///     restore_rules = true
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JournalConfig {
+    /// Directory of the journal files, created if needed.
+    pub directory: String,
+
+    /// Size of the journal file above which it is rotated to a new segment (in bytes). Segments
+    /// are removed once their records are acknowledged. Defaults to `16777216` (16 MiB).
+    #[serde(default = "default_journal_max_size")]
+    pub max_size: u64,
+
+    /// If set, records are synced to disk by the background thread after each batch it writes.
+    /// Defaults to `false` (written records survive a crash of the process, but not of the
+    /// host).
+    #[serde(default = "default_journal_sync")]
+    pub sync: bool,
+
+    /// If set, the last journaled rule set is loaded on initialization. Defaults to `false`.
+    #[serde(default = "default_journal_restore_rules")]
+    pub restore_rules: bool,
+}
+
+fn default_journal_max_size() -> u64 {
+    16 * 1024 * 1024
+}
+
+fn default_journal_sync() -> bool {
+    false
+}
+
+fn default_journal_restore_rules() -> bool {
+    false
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Policy of the packets of some VLANs.
+///
+/// Packets whose innermost VLAN ID is selected by `vlans` are tracked in the flow table, scanned
+/// and stored by the live packet tap and the rolling capture only if the corresponding options are
+/// set. The first entry that selects a packet applies, and packets selected by no entry are fully
+/// inspected (see [vlan](crate::filter::vlan)).
+///
+/// ## Example
+/// ```toml
+/// [[vlan_policy]]
+///     vlans = "untagged"
+///     track = false
+///     scan = false
+///     store = false
+///
+/// [[vlan_policy]]
+///     vlans = "100-199"
+///     store = false
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct VlanPolicyConfig {
+    /// Selected packets: `"untagged"`, `"tagged"`, a VLAN ID such as `"100"`, or an inclusive
+    /// range of VLAN IDs such as `"100-199"`.
+    pub vlans: String,
+
+    /// If set, flows are tracked in the flow table. Defaults to `true`.
+    #[serde(default = "default_vlan_track")]
+    pub track: bool,
+
+    /// If set, payloads are scanned against the rules. Defaults to `true`.
+    #[serde(default = "default_vlan_scan")]
+    pub scan: bool,
+
+    /// If set, packets are copied to the live packet tap and the rolling capture. Defaults to
+    /// `true`.
+    #[serde(default = "default_vlan_store")]
+    pub store: bool,
+}
+
+fn default_vlan_track() -> bool {
+    true
+}
+
+fn default_vlan_scan() -> bool {
+    true
+}
+
+fn default_vlan_store() -> bool {
+    true
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Storage quota of a tenant.
+///
+/// Packets whose innermost VLAN ID is selected by `vlans` belong to `tenant`, and are copied to the
+/// live packet tap and the rolling capture until `max_bytes` of them were queued for writing. The
+/// first entry that selects a packet applies, and packets selected by no entry are stored without
+/// limit (see [quota](crate::filter::quota)). Matching and alerts are not affected by quotas.
+///
+/// ## Example
+/// ```toml
+/// [[storage_quota]]
+///     tenant = "blue"
+///     vlans = "100-199"
+///     max_bytes = 50_000_000_000
+///     period_secs = 86400
+///
+/// [[storage_quota]]
+///     tenant = "green"
+///     vlans = "200"
+///     max_bytes = 10_000_000_000
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StorageQuotaConfig {
+    /// Name of the tenant, unique across entries.
+    pub tenant: String,
+
+    /// VLANs of the tenant: `"untagged"`, `"tagged"`, a VLAN ID such as `"100"`, or an inclusive
+    /// range of VLAN IDs such as `"100-199"`.
+    pub vlans: String,
+
+    /// Number of bytes the tenant may store, counting at most the snapshot length per packet.
+    pub max_bytes: u64,
+
+    /// Interval (in seconds) after which the usage of the tenant is reset. Defaults to `None`
+    /// (usage is only reset by
+    /// [FilterCtx::reset_storage_quota](crate::filter::FilterCtx::reset_storage_quota)).
+    #[serde(default = "default_quota_period_secs")]
+    pub period_secs: Option<u64>,
+}
+
+fn default_quota_period_secs() -> Option<u64> {
+    None
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Remote collector options.
+///
+/// Packets passed to [FilterCtx::forward_packet](crate::filter::FilterCtx::forward_packet) are
+/// streamed as pcapng to each `[[forward]]` collector over TLS, the collector being authenticated
+/// with the CA certificates of `ca_file` (see [forward](crate::filter::forward)). Broken
+/// connections are retried every `reconnect_secs` seconds, doubling up to a minute. With
+/// `spool_directory`, packets are buffered to disk while the collector is unreachable, up to
+/// `spool_max_bytes`, and sent once reconnected. Requires the `tls-forward` feature.
+///
+/// ## Example
+/// ```toml
+/// [[forward]]
+///     name = "central"
+///     address = "collector.example.net:6514"
+///     ca_file = "/etc/retina/collector-ca.pem"
+///     cert_file = "/etc/retina/sensor.pem"
+///     key_file = "/etc/retina/sensor.key"
+///     spool_directory = "/var/spool/retina"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ForwardConfig {
+    /// Name of the collector in statistics and spool files, unique across entries.
+    pub name: String,
+
+    /// Host and port of the collector.
+    pub address: String,
+
+    /// Name the certificate of the collector is checked against. Defaults to `None` (the host of
+    /// `address`).
+    #[serde(default = "default_forward_server_name")]
+    pub server_name: Option<String>,
+
+    /// PEM file of the CA certificates trusted to sign the certificate of the collector.
+    pub ca_file: String,
+
+    /// PEM files of the client certificate chain and private key the sensor authenticates with.
+    /// Defaults to `None` (no client authentication).
+    #[serde(default = "default_forward_cert_file")]
+    pub cert_file: Option<String>,
+    #[serde(default = "default_forward_key_file")]
+    pub key_file: Option<String>,
+
+    /// Maximum number of bytes sent per packet, lowered by the `snaplen` of the rule a packet
+    /// matched, if set. Defaults to `65535`.
+    #[serde(default = "default_forward_snaplen")]
+    pub snaplen: usize,
+
+    /// Number of packets queued for sending before packets are dropped. Defaults to `4096`.
+    #[serde(default = "default_forward_queue_size")]
+    pub queue_size: usize,
+
+    /// Initial interval (in seconds) between connection attempts. Defaults to `5`.
+    #[serde(default = "default_forward_reconnect_secs")]
+    pub reconnect_secs: u64,
+
+    /// Directory packets are buffered to while the collector is unreachable. Created if it does
+    /// not exist. Defaults to `None` (packets are dropped while disconnected).
+    #[serde(default = "default_forward_spool_directory")]
+    pub spool_directory: Option<String>,
+
+    /// Maximum number of bytes buffered to disk, newer packets being dropped beyond. Defaults to
+    /// `1_000_000_000`.
+    #[serde(default = "default_forward_spool_max_bytes")]
+    pub spool_max_bytes: u64,
+}
+
+fn default_forward_server_name() -> Option<String> {
+    None
+}
+
+fn default_forward_cert_file() -> Option<String> {
+    None
+}
+
+fn default_forward_key_file() -> Option<String> {
+    None
+}
+
+fn default_forward_snaplen() -> usize {
+    65535
+}
+
+fn default_forward_queue_size() -> usize {
+    4096
+}
+
+fn default_forward_reconnect_secs() -> u64 {
+    5
+}
+
+fn default_forward_spool_directory() -> Option<String> {
+    None
+}
+
+fn default_forward_spool_max_bytes() -> u64 {
+    1_000_000_000
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Store target options.
+///
+/// Rules name the store target of the flows they match with their `store_target` (see
+/// [store targets](crate::filter::rule#store-targets)). The packets routed to a `[[store_target]]`
+/// are written to its own rolling capture in `capture.directory`, sent to the `[[forward]]`
+/// collector named `collector`, or both (see [targets](crate::filter::targets)). Collectors named
+/// by a store target only receive the packets routed to it.
+///
+/// ## Example
+/// ```toml
+/// [[store_target]]
+///     name = "forensics"
+///     collector = "central"
+///
+/// [[store_target]]
+///     name = "web-team"
+///     [store_target.capture]
+///         directory = "/var/lib/retina/web"
+///         max_files = 50
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StoreTargetConfig {
+    /// Name of the target, referenced by the `store_target` of rules.
+    pub name: String,
+
+    /// Rolling capture of the target. Defaults to `None` (no capture).
+    #[serde(default)]
+    pub capture: Option<CaptureConfig>,
+
+    /// Name of the collector of the target. Defaults to `None` (no collector).
+    #[serde(default)]
+    pub collector: Option<String>,
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Async bridge options.
+///
+/// Alerts and flow events are queued for the async handlers registered on
+/// [FilterCtx::async_bridge](crate::filter::FilterCtx::async_bridge), which run on a tokio runtime
+/// with `worker_threads` threads (see [bridge](crate::bridge)). Requires the `async-bridge`
+/// feature.
+///
+/// ## Example
+/// ```toml
+/// [async_bridge]
+///     queue_size = 4096
+///     worker_threads = 2
+///     max_in_flight = 256
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AsyncBridgeConfig {
+    /// Number of events the queue holds before events are dropped. Defaults to `4096`.
+    #[serde(default = "default_bridge_queue_size")]
+    pub queue_size: usize,
+
+    /// Number of tokio worker threads. Defaults to `2`.
+    #[serde(default = "default_bridge_worker_threads")]
+    pub worker_threads: usize,
+
+    /// Maximum number of handlers running at once. Further events wait in the queue. Defaults to
+    /// `256`.
+    #[serde(default = "default_bridge_max_in_flight")]
+    pub max_in_flight: usize,
+
+    /// How long handlers in flight are given to complete on shutdown (in seconds). Defaults to
+    /// `5`.
+    #[serde(default = "default_bridge_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+}
+
+fn default_bridge_queue_size() -> usize {
+    4096
+}
+
+fn default_bridge_worker_threads() -> usize {
+    2
+}
+
+fn default_bridge_max_in_flight() -> usize {
+    256
+}
+
+fn default_bridge_shutdown_timeout() -> u64 {
+    5
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Matching backend options.
+///
+/// Batch scans of rules loaded after initialization are offloaded to `backend`, falling back to
+/// the CPU for unsupported patterns (see [backend](crate::filter::backend)). Payloads are batch
+/// scanned by the [PayloadWindow](crate::subscription::PayloadWindow) subscription, one burst at a
+/// time, and by callbacks that use
+/// [FilterCtx::check_flow_match_batch](crate::filter::FilterCtx::check_flow_match_batch). The
+/// `gpu` backend is experimental and requires the `gpu-match` feature.
+///
+/// ## Example
+/// ```toml
+/// [match_backend]
+///     backend = "gpu"
+///     min_batch = 128
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct MatchBackendConfig {
+    /// Backend batch scans are offloaded to. Defaults to `"cpu"`.
+    #[serde(default = "default_backend_kind")]
+    pub backend: BackendKind,
+
+    /// Smallest batch offloaded to the backend. Smaller batches are matched on the CPU. Defaults
+    /// to `64`.
+    #[serde(default = "default_backend_min_batch")]
+    pub min_batch: usize,
+}
+
+fn default_backend_kind() -> BackendKind {
+    BackendKind::Cpu
+}
+
+fn default_backend_min_batch() -> usize {
+    64
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// YARA scanning options.
+///
+/// The payload windows of flows are also scanned with the YARA rules in `rules`, cut at
+/// `max_window` bytes, and scans are aborted after `timeout_ms` milliseconds (see
+/// [yara](crate::filter::yara)). Requires the `yara-match` feature.
+///
+/// ## Example
+/// ```toml
+/// [yara]
+///     rules = "/etc/retina/rules.yarc"
+///     max_window = 4096
+///     timeout_ms = 2
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct YaraConfig {
+    /// Path of the YARA rules, either source or compiled with `yarac`.
+    pub rules: String,
+
+    /// Maximum number of bytes of each payload scanned. Defaults to `16384`.
+    #[serde(default = "default_yara_max_window")]
+    pub max_window: usize,
+
+    /// Time (in milliseconds) after which a scan is aborted without matching. Defaults to `5`.
+    #[serde(default = "default_yara_timeout_ms")]
+    pub timeout_ms: u32,
+}
+
+fn default_yara_max_window() -> usize {
+    16384
+}
+
+fn default_yara_timeout_ms() -> u32 {
+    5
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Throughput metrics of matched flows.
+///
+/// From its first match, the traffic of a flow tracked with
+/// [FilterCtx::track_flow](crate::filter::FilterCtx::track_flow) is counted in time buckets, and
+/// its peak rates are kept in the flow table. The metrics are reported in the
+/// [FlowSummary](crate::hooks::FlowSummary) of end-of-flow hooks (see
+/// [rates](crate::filter::rates)).
+///
+/// ## Example
+/// ```toml
+/// [flow_rates]
+///     bucket_ms = 100
+///     nb_buckets = 600
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct FlowRateConfig {
+    /// Time bucket resolution, in milliseconds. Peak rates are measured over one bucket. Defaults
+    /// to `1000`.
+    #[serde(default = "default_rate_bucket_ms")]
+    pub bucket_ms: u64,
+
+    /// Number of most recent buckets kept per flow. Older buckets still count towards the peak
+    /// rates. Defaults to `60`.
+    #[serde(default = "default_rate_nb_buckets")]
+    pub nb_buckets: usize,
+}
+
+fn default_rate_bucket_ms() -> u64 {
+    1000
+}
+
+fn default_rate_nb_buckets() -> usize {
+    60
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Service manager notification options.
+///
+/// When the runtime is started by systemd or another service manager implementing the
+/// `sd_notify` protocol, it reports when it is ready, pings the watchdog and reports when it is
+/// shutting down (see [Readiness](crate::Readiness)). Units should use `Type=notify`.
+///
+/// ## Example
+/// ```toml
+/// [notify]
+///     watchdog = true
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct NotifyConfig {
+    /// If set, the main core pings the watchdog when the service manager enables it with
+    /// `WatchdogSec=`. Defaults to `true`.
+    #[serde(default = "default_notify_watchdog")]
+    pub watchdog: bool,
+}
+
+fn default_notify_watchdog() -> bool {
+    true
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Tunnel decapsulation guards.
+///
+/// Crafted packets can nest encapsulations deeply to exhaust parsing resources. Tunnel parsers
+/// report every layer they decapsulate (see [parser](crate::protocols::parser#tunnels)), and
+/// frames beyond `max_depth` layers or `max_header_bytes` bytes of tunnel headers are rejected.
+///
+/// ## Example
+/// ```toml
+/// [decap]
+///     max_depth = 2
+///     max_header_bytes = 256
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct DecapConfig {
+    /// Maximum number of encapsulation layers per frame. Defaults to `4`.
+    #[serde(default = "default_decap_max_depth")]
+    pub max_depth: usize,
+
+    /// Maximum number of bytes of tunnel headers per frame. Defaults to `512`.
+    #[serde(default = "default_decap_max_header_bytes")]
+    pub max_header_bytes: usize,
+}
+
+fn default_decap_max_depth() -> usize {
+    4
+}
+
+fn default_decap_max_header_bytes() -> usize {
+    512
+}
+
+impl Default for DecapConfig {
+    fn default() -> Self {
+        DecapConfig {
+            max_depth: default_decap_max_depth(),
+            max_header_bytes: default_decap_max_header_bytes(),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 #include <rte_build_config.h>
 #include <rte_ethdev.h>
+#include <rte_eth_bond.h>
 #include <rte_common.h>
 #include <rte_cycles.h>
 #include <rte_eal.h>
@@ -8,5 +9,6 @@
 #include <rte_memcpy.h>
 #include <rte_udp.h>
 #include <rte_mbuf.h>
+#include <rte_mbuf_dyn.h>
 #include <rte_ring.h>
 #include <rte_errno.h>
\ No newline at end of file
@@ -45,6 +45,10 @@ int rte_pktmbuf_trim_(struct rte_mbuf* m, uint16_t len) {
     return rte_pktmbuf_trim(m, len);
 }
 
+char* rte_pktmbuf_prepend_(struct rte_mbuf* m, uint16_t len) {
+    return rte_pktmbuf_prepend(m, len);
+}
+
 unsigned rte_lcore_id_(void) {
     return rte_lcore_id();
 }
@@ -79,6 +83,14 @@ int rte_ring_mc_dequeue_(struct rte_ring* r, void** obj_p) {
     return rte_ring_mc_dequeue(r, obj_p);
 }
 
+unsigned rte_ring_sp_enqueue_burst_(struct rte_ring* r, void* const* obj_table, unsigned n, unsigned* free_space) {
+    return rte_ring_sp_enqueue_burst(r, obj_table, n, free_space);
+}
+
+unsigned rte_ring_sc_dequeue_burst_(struct rte_ring* r, void** obj_table, unsigned n, unsigned* available) {
+    return rte_ring_sc_dequeue_burst(r, obj_table, n, available);
+}
+
 unsigned rte_ring_count_(const struct rte_ring* r) {
     return rte_ring_count(r);
 }
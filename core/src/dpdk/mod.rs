@@ -32,6 +32,7 @@ extern "C" {
     fn rte_mbuf_refcnt_update_(m: *mut rte_mbuf, value: i16) -> u16;
     fn rte_pktmbuf_adj_(packet: *mut rte_mbuf, len: u16) -> *mut c_char;
     fn rte_pktmbuf_trim_(packet: *mut rte_mbuf, len: u16) -> c_int;
+    fn rte_pktmbuf_prepend_(packet: *mut rte_mbuf, len: u16) -> *mut c_char;
     fn rte_lcore_id_() -> u16;
     fn rte_rdtsc_() -> u64;
     fn rte_ring_enqueue_(ring: *mut rte_ring, obj: *mut c_void) -> c_int;
@@ -40,6 +41,18 @@ extern "C" {
     fn rte_ring_dequeue_(ring: *mut rte_ring, obj_p: *mut *mut c_void) -> c_int;
     fn rte_ring_sc_dequeue_(ring: *mut rte_ring, obj_p: *mut *mut c_void) -> c_int;
     fn rte_ring_mc_dequeue_(ring: *mut rte_ring, obj_p: *mut *mut c_void) -> c_int;
+    fn rte_ring_sp_enqueue_burst_(
+        ring: *mut rte_ring,
+        obj_table: *const *mut c_void,
+        n: c_uint,
+        free_space: *mut c_uint,
+    ) -> c_uint;
+    fn rte_ring_sc_dequeue_burst_(
+        ring: *mut rte_ring,
+        obj_table: *mut *mut c_void,
+        n: c_uint,
+        available: *mut c_uint,
+    ) -> c_uint;
     fn rte_ring_count_(ring: *const rte_ring) -> c_uint;
     fn rte_ring_free_count_(ring: *const rte_ring) -> c_uint;
     fn rte_ring_full_(ring: *const rte_ring) -> c_int;
@@ -118,6 +131,11 @@ pub unsafe fn rte_pktmbuf_trim(packet: *mut rte_mbuf, len: u16) -> c_int {
     rte_pktmbuf_trim_(packet, len)
 }
 
+#[inline]
+pub unsafe fn rte_pktmbuf_prepend(packet: *mut rte_mbuf, len: u16) -> *mut c_char {
+    rte_pktmbuf_prepend_(packet, len)
+}
+
 #[inline]
 pub unsafe fn rte_lcore_id() -> u16 {
     rte_lcore_id_()
@@ -167,6 +185,26 @@ pub unsafe fn rte_ring_mc_dequeue(ring: *mut rte_ring, obj_p: *mut *mut c_void)
     rte_ring_mc_dequeue_(ring, obj_p)
 }
 
+#[inline]
+pub unsafe fn rte_ring_sp_enqueue_burst(
+    ring: *mut rte_ring,
+    obj_table: *const *mut c_void,
+    n: c_uint,
+    free_space: *mut c_uint,
+) -> c_uint {
+    rte_ring_sp_enqueue_burst_(ring, obj_table, n, free_space)
+}
+
+#[inline]
+pub unsafe fn rte_ring_sc_dequeue_burst(
+    ring: *mut rte_ring,
+    obj_table: *mut *mut c_void,
+    n: c_uint,
+    available: *mut c_uint,
+) -> c_uint {
+    rte_ring_sc_dequeue_burst_(ring, obj_table, n, available)
+}
+
 #[inline]
 pub unsafe fn rte_ring_count(ring: *const rte_ring) -> c_uint {
     rte_ring_count_(ring)
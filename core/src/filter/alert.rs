@@ -0,0 +1,408 @@
+//! Match alert fan-out.
+//!
+//! Every payload that matches a rule is published as a compact JSON datagram to each configured
+//! Unix datagram socket. Sends never block: if a subscriber's receive queue is full or the
+//! subscriber is not listening, the alert is dropped and counted against that subscriber, so slow
+//! consumers do not affect packet processing. If the [journal](crate::filter::journal) is
+//! enabled, alerts are queued for it before they are sent, and dropped if it is behind. Alerts are
+//! also queued for the alert handlers of the [async bridge](crate::bridge), with or without
+//! subscribers.
+//!
+//! ## Example
+//! An alert datagram, with the flow encoded as
+//! `[vlan_id, addr1, addr2, protocol, flow_label, dscp, vlan_stack_hash, s_tag]`:
+//! ```json
+//! {"ts":1665480000123456789,"flow":[null,"10.0.0.2:443","10.0.0.1:51234",6,null,null,null,null],"offset":0}
+//! ```
+//!
+//! If enabled with [AlertConfig::context_bytes](crate::config::AlertConfig::context_bytes), match
+//! alerts also carry the pattern of the matching rule and the payload bytes around the match,
+//! starting at `context_offset` bytes into the flow, with personal data redacted if
+//! [AlertConfig::anonymize](crate::config::AlertConfig::anonymize) is set:
+//! ```json
+//! {"ts":1665480000123456789,"flow":[null,"10.0.0.2:443","10.0.0.1:51234",6,null,null,null,null],"offset":0,"rule":"evil\\.example\\.com","context":"SG9zdDogZXZpbC5leGFtcGxlLmNvbQ0K","context_offset":16}
+//! ```
+//!
+//! Memory pool pressure changes (see
+//! [MempoolConfig::shed_watermark](crate::config::MempoolConfig::shed_watermark)) are published on
+//! the same sockets:
+//! ```json
+//! {"ts":1665480000123456789,"shedding":true,"available":0.04}
+//! ```
+//!
+//! Rules disabled by the [match rate safeguard](crate::filter::throttle) are published as well:
+//! ```json
+//! {"ts":1665480000123456789,"throttled":".*","match_rate":84211.5,"byte_rate":61042087.3}
+//! ```
+//!
+//! Match alerts carry the merged [tags](crate::filter::rule#tags) of the matching rules, if any,
+//! the first matching rule winning on duplicate keys:
+//! ```json
+//! {"ts":1665480000123456789,"flow":[null,"10.0.0.2:443","10.0.0.1:51234",6,null,null,null,null],"offset":0,"tags":{"campaign":"winter","mitre":"T1059.001"}}
+//! ```
+//!
+//! If enabled with [AlertConfig::icmp_errors](crate::config::AlertConfig::icmp_errors), ICMP
+//! errors correlated to a tracked flow are published as well:
+//! ```json
+//! {"ts":1665480000123456789,"flow":[null,"10.0.0.2:443","10.0.0.1:51234",6,null,null,null,null],"icmp":"unreachable","code":3,"reporter":"10.0.0.254"}
+//! ```
+
+use super::journal::Journal;
+use super::rule::Rule;
+use super::throttle::ThrottledRule;
+use crate::bridge::AsyncBridge;
+use crate::config::{AlertConfig, ContextEncoding};
+use crate::protocols::icmp::IcmpError;
+use crate::protocols::layer4::Flow;
+use crate::protocols::packet::icmp::IcmpErrorKind;
+use crate::timebase;
+
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::ops::Range;
+
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result};
+use regex::bytes::Regex;
+use serde::Serialize;
+
+/// Personal data redacted from the context of anonymized alerts: IPv4 and IPv6 addresses, email
+/// addresses, and the values of HTTP authorization and cookie headers (the first group). IPv6
+/// addresses are matched in full or compressed form, with or without an embedded IPv4 address.
+const ANONYMIZED: [&str; 4] = [
+    r"(?i)(?:authorization|proxy-authorization|cookie|set-cookie):[ \t]*([^\r\n]*)",
+    r"\b(?:[0-9]{1,3}\.){3}[0-9]{1,3}\b",
+    concat!(
+        r"(?i)(?:\b[0-9a-f]{1,4}:){7}[0-9a-f]{1,4}\b",
+        r"|(?:\b[0-9a-f]{1,4}(?::[0-9a-f]{1,4}){0,6})?::",
+        r"(?:(?:[0-9a-f]{1,4}:){0,5}(?:[0-9]{1,3}\.){3}[0-9]{1,3}",
+        r"|[0-9a-f]{1,4}(?::[0-9a-f]{1,4}){0,6}\b)?",
+    ),
+    r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+];
+
+/// A match alert.
+#[derive(Debug, Serialize)]
+struct MatchEvent<'a> {
+    /// UNIX timestamp of the match, in nanoseconds.
+    ts: u64,
+    flow: &'a Flow,
+    /// Byte offset of the matching payload within the flow.
+    offset: usize,
+    /// Pattern of the matching rule, if context capture is enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule: Option<&'a str>,
+    /// Encoded payload bytes around the match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<String>,
+    /// Byte offset of the context within the flow.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context_offset: Option<usize>,
+    /// Tags of the matching rules.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<&'a str, &'a str>,
+}
+
+/// A memory pool pressure alert.
+#[derive(Debug, Serialize)]
+struct PressureEvent {
+    /// UNIX timestamp of the event, in nanoseconds.
+    ts: u64,
+    /// Whether RX cores started (`true`) or stopped (`false`) shedding.
+    shedding: bool,
+    /// Lowest fraction of available mbufs across memory pools.
+    available: f64,
+}
+
+/// A rule disabled by the match rate safeguard.
+#[derive(Debug, Serialize)]
+struct ThrottleEvent<'a> {
+    /// UNIX timestamp of the event, in nanoseconds.
+    ts: u64,
+    /// Pattern of the disabled rule.
+    throttled: &'a str,
+    match_rate: f64,
+    byte_rate: f64,
+}
+
+/// An ICMP error correlated to a flow.
+#[derive(Debug, Serialize)]
+struct IcmpEvent<'a> {
+    /// UNIX timestamp of the error, in nanoseconds.
+    ts: u64,
+    flow: &'a Flow,
+    icmp: IcmpErrorKind,
+    code: u8,
+    /// Address of the node that reported the error.
+    reporter: IpAddr,
+}
+
+/// Delivery counters of an alert subscriber.
+#[derive(Debug, Clone)]
+pub struct SubscriberStats {
+    /// Socket address of the subscriber, as configured.
+    pub address: String,
+    /// Number of alerts delivered.
+    pub nb_sent: u64,
+    /// Number of alerts dropped.
+    pub nb_dropped: u64,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    name: String,
+    addr: SocketAddr,
+    nb_sent: AtomicU64,
+    nb_dropped: AtomicU64,
+}
+
+impl Subscriber {
+    /// Parses a subscriber address. Addresses prefixed with `@` are abstract socket names.
+    fn new(name: &str) -> Result<Self> {
+        let addr = match name.strip_prefix('@') {
+            Some(abstract_name) => SocketAddr::from_abstract_name(abstract_name)?,
+            None => SocketAddr::from_pathname(name)?,
+        };
+        Ok(Subscriber {
+            name: name.to_owned(),
+            addr,
+            nb_sent: AtomicU64::new(0),
+            nb_dropped: AtomicU64::new(0),
+        })
+    }
+}
+
+/// Shared alert publisher of a filter.
+#[derive(Debug, Default)]
+pub(crate) struct AlertFanout {
+    socket: RwLock<Option<UnixDatagram>>,
+    subscribers: RwLock<Vec<Subscriber>>,
+    icmp_errors: AtomicBool,
+    /// Context bytes captured on each side of a match, `0` if disabled.
+    context_bytes: AtomicUsize,
+    context_hex: AtomicBool,
+    /// Personal data redacted from the context, `None` unless anonymized.
+    redaction: RwLock<Option<Regex>>,
+    /// Journal alerts are recorded to before they are sent.
+    journal: Arc<Journal>,
+    /// Async bridge alerts are queued to.
+    bridge: Arc<AsyncBridge>,
+}
+
+impl AlertFanout {
+    pub(crate) fn new(journal: Arc<Journal>, bridge: Arc<AsyncBridge>) -> Self {
+        AlertFanout {
+            journal,
+            bridge,
+            ..AlertFanout::default()
+        }
+    }
+
+    /// Opens the publishing socket and registers the configured subscribers. Fails if a redaction
+    /// pattern does not compile.
+    pub(crate) fn configure(&self, config: &AlertConfig) -> Result<()> {
+        let redaction = match config.anonymize {
+            true => Some(redaction(&config.redact)?),
+            false => None,
+        };
+        let subscribers = config
+            .subscribers
+            .iter()
+            .map(|name| Subscriber::new(name))
+            .collect::<Result<Vec<_>>>()?;
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+        log::info!("Publishing alerts to {} subscriber(s)", subscribers.len());
+        *self.subscribers.write().unwrap() = subscribers;
+        *self.socket.write().unwrap() = Some(socket);
+        self.icmp_errors.store(config.icmp_errors, Ordering::Relaxed);
+        self.context_bytes
+            .store(config.context_bytes, Ordering::Relaxed);
+        self.context_hex
+            .store(config.context_encoding == ContextEncoding::Hex, Ordering::Relaxed);
+        *self.redaction.write().unwrap() = redaction;
+        Ok(())
+    }
+
+    /// Returns whether match alerts carry the matching rule and its context.
+    pub(crate) fn captures_context(&self) -> bool {
+        self.context_bytes.load(Ordering::Relaxed) > 0 && self.socket.read().unwrap().is_some()
+    }
+
+    /// Publishes a match of `flow` at `offset` bytes into the flow to all subscribers, with the
+    /// tags of the matching `rules`. If context capture is enabled, the alert carries the bytes of
+    /// `payload` around `first`, the match of the first rule.
+    pub(crate) fn publish(
+        &self,
+        flow: &Flow,
+        offset: usize,
+        payload: &[u8],
+        rules: &[Rule],
+        first: Option<Range<usize>>,
+    ) {
+        let context_bytes = self.context_bytes.load(Ordering::Relaxed);
+        let mut tags = BTreeMap::new();
+        for rule in rules.iter() {
+            for (key, value) in rule.tags.iter() {
+                tags.entry(key.as_str()).or_insert(value.as_str());
+            }
+        }
+        let (rule, context) = match rules.first() {
+            Some(rule) if context_bytes > 0 => (Some(rule), first),
+            _ => (None, None),
+        };
+        let (context, context_offset) = match context {
+            Some(found) => {
+                let start = found.start.saturating_sub(context_bytes);
+                let end = cmp::min(found.end + context_bytes, payload.len());
+                let bytes = self.redact(payload, start..end);
+                (Some(self.encode(&bytes)), Some(offset + start))
+            }
+            None => (None, None),
+        };
+        self.send(&MatchEvent {
+            ts: timebase::now_ns(),
+            flow,
+            offset,
+            rule: rule.map(|rule| rule.pattern.as_str()),
+            context,
+            context_offset,
+            tags,
+        });
+    }
+
+    /// Returns the bytes of `payload` within `window`, with the personal data of anonymized
+    /// alerts replaced with `*`. The whole payload is searched, so that personal data cut by the
+    /// edges of the window is redacted as well.
+    fn redact<'a>(&self, payload: &'a [u8], window: Range<usize>) -> Cow<'a, [u8]> {
+        let bytes = &payload[window.clone()];
+        let redaction = self.redaction.read().unwrap();
+        let redaction = match redaction.as_ref() {
+            Some(redaction) => redaction,
+            None => return Cow::Borrowed(bytes),
+        };
+        let mut redacted = Cow::Borrowed(bytes);
+        for captures in redaction.captures_iter(payload) {
+            let found = match captures.get(1).or_else(|| captures.get(0)) {
+                Some(found) => found,
+                None => continue,
+            };
+            if found.start() >= window.end {
+                break;
+            }
+            let start = cmp::max(found.start(), window.start);
+            let end = cmp::min(found.end(), window.end);
+            if start < end {
+                redacted.to_mut()[start - window.start..end - window.start].fill(b'*');
+            }
+        }
+        redacted
+    }
+
+    /// Encodes captured context bytes with the configured encoding.
+    fn encode(&self, bytes: &[u8]) -> String {
+        if !self.context_hex.load(Ordering::Relaxed) {
+            return base64::encode(bytes);
+        }
+        let mut hex = String::with_capacity(2 * bytes.len());
+        for byte in bytes {
+            let _ = write!(hex, "{byte:02x}");
+        }
+        hex
+    }
+
+    /// Publishes an ICMP error correlated to `flow` to all subscribers, if enabled.
+    pub(crate) fn publish_icmp_error(&self, flow: &Flow, error: &IcmpError) {
+        if !self.icmp_errors.load(Ordering::Relaxed) {
+            return;
+        }
+        self.send(&IcmpEvent {
+            ts: timebase::now_ns(),
+            flow,
+            icmp: error.kind,
+            code: error.code,
+            reporter: error.reporter,
+        });
+    }
+
+    /// Publishes a rule disabled by the match rate safeguard to all subscribers.
+    pub(crate) fn publish_throttle(&self, throttled: &ThrottledRule) {
+        self.send(&ThrottleEvent {
+            ts: timebase::now_ns(),
+            throttled: &throttled.rule.pattern,
+            match_rate: throttled.match_rate,
+            byte_rate: throttled.byte_rate,
+        });
+    }
+
+    /// Publishes a change of the memory pool shedding state to all subscribers.
+    pub(crate) fn publish_pressure(&self, shedding: bool, available: f64) {
+        self.send(&PressureEvent {
+            ts: timebase::now_ns(),
+            shedding,
+            available,
+        });
+    }
+
+    fn send(&self, event: &impl Serialize) {
+        let socket = self.socket.read().unwrap();
+        if socket.is_none() && !self.bridge.has_alert() {
+            return;
+        }
+        let buf = match serde_json::to_vec(event) {
+            Ok(buf) => buf,
+            Err(error) => {
+                log::error!("Alert serialization error: {}", error);
+                return;
+            }
+        };
+        self.journal.append_alert(&buf);
+        self.bridge
+            .alert(|| serde_json::from_slice(&buf).unwrap_or_default());
+        let socket = match socket.as_ref() {
+            Some(socket) => socket,
+            None => return,
+        };
+        for subscriber in self.subscribers.read().unwrap().iter() {
+            match socket.send_to_addr(&buf, &subscriber.addr) {
+                Ok(_) => subscriber.nb_sent.fetch_add(1, Ordering::Relaxed),
+                Err(_) => subscriber.nb_dropped.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Vec<SubscriberStats> {
+        self.subscribers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|subscriber| SubscriberStats {
+                address: subscriber.name.clone(),
+                nb_sent: subscriber.nb_sent.load(Ordering::Relaxed),
+                nb_dropped: subscriber.nb_dropped.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+/// Compiles the personal data of anonymized alerts along with the `redact` patterns into one
+/// regex.
+fn redaction(redact: &[String]) -> Result<Regex> {
+    for pattern in redact.iter() {
+        Regex::new(pattern).with_context(|| format!("Invalid redaction pattern {}", pattern))?;
+    }
+    let alternatives: Vec<String> = ANONYMIZED
+        .iter()
+        .copied()
+        .chain(redact.iter().map(String::as_str))
+        .map(|pattern| format!("(?:{})", pattern))
+        .collect();
+    Ok(Regex::new(&alternatives.join("|"))?)
+}
@@ -0,0 +1,261 @@
+//! Pattern matching backends.
+//!
+//! Rules that share a payload window and regex flags are compiled into a group, whose patterns
+//! are matched by a [MatchBackend](MatchBackend). Single payloads are always matched on the CPU,
+//! with the group's regex set. Batches of payloads, scanned with
+//! [FilterCtx::check_flow_match_batch](crate::filter::FilterCtx::check_flow_match_batch), e.g. the
+//! payloads of each burst of the [PayloadWindow](crate::subscription::PayloadWindow)
+//! subscription, or [FilterCtx::check_match_batch](crate::filter::FilterCtx::check_match_batch),
+//! are matched one group at a time, so that a backend can dispatch the windows of a whole batch at
+//! once. Callbacks that call [check_flow_match](crate::filter::FilterCtx::check_flow_match) on
+//! each payload are always matched on the CPU.
+//!
+//! With the `[match_backend]` options of the runtime configuration (see
+//! [MatchBackendConfig](crate::config::MatchBackendConfig)) and the `gpu-match` feature, the
+//! groups of rules loaded after initialization are also compiled for an experimental GPU matcher,
+//! which batch scans are offloaded to. The matcher is an external CUDA or OpenCL library,
+//! `libretina_gpu`, found in `RETINA_GPU_PATH` at build time, with the following C interface:
+//! ```c
+//! // Compiles `patterns`, setting `supported[i]` for each pattern the device can match.
+//! // Returns NULL on failure.
+//! void *retina_gpu_compile(const char *const *patterns, size_t nb_patterns, uint8_t *supported);
+//! // Sets `matched[i]` to 1 if `windows[i]`, `lens[i]` bytes long, matches a supported pattern.
+//! // Returns 0 on success.
+//! int retina_gpu_scan(void *matcher, const uint8_t *const *windows, const size_t *lens,
+//!                     size_t nb_windows, uint8_t *matched);
+//! void retina_gpu_free(void *matcher);
+//! ```
+//! Scans may run concurrently from several cores. Patterns the device does not support, groups
+//! with non-default regex flags, batches smaller than
+//! [min_batch](crate::config::MatchBackendConfig::min_batch) and failed scans are matched on the
+//! CPU.
+
+use crate::config::MatchBackendConfig;
+
+use std::fmt;
+use std::sync::Arc;
+
+use regex::bytes::RegexSet;
+use serde::{Deserialize, Serialize};
+
+/// Matches payload windows against a group of patterns.
+pub trait MatchBackend: fmt::Debug + Send + Sync {
+    /// Returns whether `window` matches any pattern.
+    fn is_match(&self, window: &[u8]) -> bool;
+
+    /// Sets `matched[i]` to whether `windows[i]` matches any pattern. `matched` has the length of
+    /// `windows`.
+    fn is_match_batch(&self, windows: &[&[u8]], matched: &mut [bool]) {
+        for (window, matched) in windows.iter().zip(matched.iter_mut()) {
+            *matched = self.is_match(window);
+        }
+    }
+}
+
+impl MatchBackend for RegexSet {
+    #[inline]
+    fn is_match(&self, window: &[u8]) -> bool {
+        RegexSet::is_match(self, window)
+    }
+}
+
+/// Kind of matching backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Regex sets, on the CPU.
+    #[default]
+    Cpu,
+    /// Experimental GPU matcher, requires the `gpu-match` feature.
+    Gpu,
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendKind::Cpu => write!(f, "cpu"),
+            BackendKind::Gpu => write!(f, "gpu"),
+        }
+    }
+}
+
+/// Returns the backend batch scans of `regexes`, a group compiled from `patterns`, are offloaded
+/// to, `None` if they run on the CPU.
+pub(crate) fn offload(
+    config: Option<&MatchBackendConfig>,
+    patterns: &[&String],
+    regexes: &RegexSet,
+) -> Option<Arc<dyn MatchBackend>> {
+    match config?.backend {
+        BackendKind::Cpu => None,
+        BackendKind::Gpu => gpu::compile(config?, patterns, regexes),
+    }
+}
+
+/// Logs the configured backend, warning if it is not available in this build.
+pub(crate) fn check(config: &MatchBackendConfig) {
+    if config.backend == BackendKind::Cpu {
+        return;
+    }
+    if cfg!(feature = "gpu-match") {
+        log::info!(
+            "Offloading batch scans of {} payloads or more to the {} backend",
+            config.min_batch,
+            config.backend
+        );
+    } else {
+        log::warn!("GPU matching requires the `gpu-match` feature, rules are matched on the CPU");
+    }
+}
+
+#[cfg(feature = "gpu-match")]
+mod gpu {
+    use super::MatchBackend;
+    use crate::config::MatchBackendConfig;
+
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::sync::Arc;
+
+    use regex::bytes::{RegexSet, RegexSetBuilder};
+
+    #[link(name = "retina_gpu")]
+    extern "C" {
+        fn retina_gpu_compile(
+            patterns: *const *const c_char,
+            nb_patterns: usize,
+            supported: *mut u8,
+        ) -> *mut c_void;
+        fn retina_gpu_scan(
+            matcher: *mut c_void,
+            windows: *const *const u8,
+            lens: *const usize,
+            nb_windows: usize,
+            matched: *mut u8,
+        ) -> c_int;
+        fn retina_gpu_free(matcher: *mut c_void);
+    }
+
+    /// A group compiled for the GPU matcher.
+    #[derive(Debug)]
+    struct GpuMatcher {
+        matcher: *mut c_void,
+        /// All patterns of the group, for small batches and failed scans.
+        cpu: RegexSet,
+        /// Patterns the device does not support, `None` if it supports all of them.
+        unsupported: Option<RegexSet>,
+        min_batch: usize,
+    }
+
+    // The matcher library must support concurrent scans.
+    unsafe impl Send for GpuMatcher {}
+    unsafe impl Sync for GpuMatcher {}
+
+    impl MatchBackend for GpuMatcher {
+        #[inline]
+        fn is_match(&self, window: &[u8]) -> bool {
+            self.cpu.is_match(window)
+        }
+
+        fn is_match_batch(&self, windows: &[&[u8]], matched: &mut [bool]) {
+            if windows.len() < self.min_batch {
+                self.cpu.is_match_batch(windows, matched);
+                return;
+            }
+            let ptrs: Vec<*const u8> = windows.iter().map(|window| window.as_ptr()).collect();
+            let lens: Vec<usize> = windows.iter().map(|window| window.len()).collect();
+            let mut results = vec![0u8; windows.len()];
+            let ret = unsafe {
+                retina_gpu_scan(
+                    self.matcher,
+                    ptrs.as_ptr(),
+                    lens.as_ptr(),
+                    windows.len(),
+                    results.as_mut_ptr(),
+                )
+            };
+            if ret != 0 {
+                log::debug!("GPU scan error {}, matching on the CPU", ret);
+                self.cpu.is_match_batch(windows, matched);
+                return;
+            }
+            for ((window, matched), result) in windows.iter().zip(matched).zip(results) {
+                *matched = result != 0
+                    || self
+                        .unsupported
+                        .as_ref()
+                        .map_or(false, |unsupported| unsupported.is_match(window));
+            }
+        }
+    }
+
+    impl Drop for GpuMatcher {
+        fn drop(&mut self) {
+            unsafe { retina_gpu_free(self.matcher) };
+        }
+    }
+
+    /// Compiles `patterns` for the GPU matcher, `None` if no pattern is supported.
+    pub(super) fn compile(
+        config: &MatchBackendConfig,
+        patterns: &[&String],
+        regexes: &RegexSet,
+    ) -> Option<Arc<dyn MatchBackend>> {
+        let cstrings = patterns
+            .iter()
+            .map(|pattern| CString::new(pattern.as_str()).ok())
+            .collect::<Option<Vec<_>>>()?;
+        let ptrs: Vec<*const c_char> = cstrings.iter().map(|pattern| pattern.as_ptr()).collect();
+        let mut supported = vec![0u8; patterns.len()];
+        let matcher =
+            unsafe { retina_gpu_compile(ptrs.as_ptr(), ptrs.len(), supported.as_mut_ptr()) };
+        if matcher.is_null() {
+            log::warn!("GPU compilation of {} patterns failed", patterns.len());
+            return None;
+        }
+        let unsupported: Vec<&String> = patterns
+            .iter()
+            .zip(supported.iter())
+            .filter(|(_, supported)| **supported == 0)
+            .map(|(pattern, _)| *pattern)
+            .collect();
+        if unsupported.len() == patterns.len() {
+            unsafe { retina_gpu_free(matcher) };
+            return None;
+        }
+        let unsupported = if unsupported.is_empty() {
+            None
+        } else {
+            match RegexSetBuilder::new(unsupported).build() {
+                Ok(regexes) => Some(regexes),
+                Err(_) => {
+                    unsafe { retina_gpu_free(matcher) };
+                    return None;
+                }
+            }
+        };
+        Some(Arc::new(GpuMatcher {
+            matcher,
+            cpu: regexes.clone(),
+            unsupported,
+            min_batch: config.min_batch,
+        }))
+    }
+}
+
+#[cfg(not(feature = "gpu-match"))]
+mod gpu {
+    use super::MatchBackend;
+    use crate::config::MatchBackendConfig;
+
+    use std::sync::Arc;
+
+    use regex::bytes::RegexSet;
+
+    pub(super) fn compile(
+        _config: &MatchBackendConfig,
+        _patterns: &[&String],
+        _regexes: &RegexSet,
+    ) -> Option<Arc<dyn MatchBackend>> {
+        None
+    }
+}
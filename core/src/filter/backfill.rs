@@ -0,0 +1,189 @@
+//! Backfill of the packets that preceded the first match of a flow.
+//!
+//! When a rule matches mid-flow, the packets before the match, e.g. the handshake and the first
+//! requests, are missing from storage. With the `[backfill]` options (see
+//! [BackfillConfig](crate::config::BackfillConfig)), each flow that has not matched yet keeps
+//! owned copies of its last packets in a ring buffer, in its flow table entry. On the first match
+//! of the flow, the buffered packets are queued to the [store target](crate::filter::targets) of
+//! the rule it matched, or else to the [rolling capture](crate::filter::capture) and to the
+//! [collectors](crate::filter::forward), whichever are configured, ahead of the matching packet,
+//! which the callback then stores as usual. Buffering then stops for the flow.
+//! Buffers are dropped along with their flow when it is removed from the flow table, and as soon
+//! as the flow is no longer scanned, past the scan depth or cleared by the
+//! [verdict cache](crate::filter::cache), since it cannot match anymore.
+//!
+//! Copies are cut at the configured snapshot length, and the buffers of all flows together are
+//! bounded by a memory budget, beyond which packets are not buffered and are counted as skipped.
+//! Flushed packets are queued in the bulk [priority class](crate::filter::priority), and are
+//! subject to the [VLAN policy](crate::filter::vlan), the [storage quotas](crate::filter::quota)
+//! and load shedding like the other stored packets.
+//!
+//! The [PayloadWindow](crate::subscription::PayloadWindow) subscription buffers the packets that
+//! it does not deliver and flushes the buffer of a flow before delivering its first match.
+//! Callbacks that track flows themselves do the same with
+//! [FilterCtx::backfill_packet](crate::filter::FilterCtx::backfill_packet) and
+//! [FilterCtx::flush_backfill](crate::filter::FilterCtx::flush_backfill).
+
+use super::store::PacketMeta;
+use super::tap::TapRecord;
+use crate::config::BackfillConfig;
+use crate::memory::mbuf::Mbuf;
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+/// Buffering counters of the backfill.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BackfillStats {
+    /// Number of packets buffered.
+    pub nb_buffered: u64,
+    /// Number of buffered packets flushed to storage on the first match of their flow.
+    pub nb_flushed: u64,
+    /// Number of packets not buffered because the memory budget was reached.
+    pub nb_skipped: u64,
+    /// Number of bytes currently buffered.
+    pub nb_bytes: usize,
+}
+
+/// Counters shared by the backfill and the buffers of all flows.
+#[derive(Debug, Default)]
+struct BackfillCounters {
+    nb_buffered: AtomicU64,
+    nb_flushed: AtomicU64,
+    nb_skipped: AtomicU64,
+    nb_bytes: AtomicUsize,
+}
+
+/// Packets of a flow buffered until its first match, oldest first.
+#[derive(Debug)]
+pub(crate) struct BackfillRing {
+    records: VecDeque<TapRecord>,
+    nb_bytes: usize,
+    counters: Arc<BackfillCounters>,
+}
+
+impl Clone for BackfillRing {
+    fn clone(&self) -> Self {
+        self.counters.nb_bytes.fetch_add(self.nb_bytes, Ordering::Relaxed);
+        BackfillRing {
+            records: self.records.clone(),
+            nb_bytes: self.nb_bytes,
+            counters: Arc::clone(&self.counters),
+        }
+    }
+}
+
+impl Drop for BackfillRing {
+    fn drop(&mut self) {
+        self.counters.nb_bytes.fetch_sub(self.nb_bytes, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BackfillSettings {
+    nb_packets: usize,
+    snaplen: usize,
+    max_bytes: usize,
+}
+
+/// Backfill shared by all copies of a filter, buffering nothing until configured.
+#[derive(Debug, Default)]
+pub(crate) struct Backfill {
+    settings: RwLock<Option<BackfillSettings>>,
+    counters: Arc<BackfillCounters>,
+}
+
+impl Backfill {
+    pub(crate) fn new() -> Self {
+        Backfill::default()
+    }
+
+    pub(crate) fn configure(&self, config: &BackfillConfig) -> Result<()> {
+        if config.nb_packets == 0 || config.snaplen == 0 {
+            bail!("backfill.nb_packets and backfill.snaplen must be positive");
+        }
+        *self.settings.write().unwrap() = Some(BackfillSettings {
+            nb_packets: config.nb_packets,
+            snaplen: config.snaplen,
+            max_bytes: config.max_bytes,
+        });
+        log::info!(
+            "Backfilling the last {} packets before the first match of flows",
+            config.nb_packets
+        );
+        Ok(())
+    }
+
+    /// Returns whether packets are buffered.
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.settings.read().unwrap().is_some()
+    }
+
+    /// Copies `mbuf`, with metadata `meta`, to `ring`, discarding its oldest packets beyond the
+    /// configured number, unless the memory budget is reached.
+    pub(crate) fn buffer(
+        &self,
+        ring: &mut Option<Box<BackfillRing>>,
+        mbuf: &Mbuf,
+        meta: PacketMeta,
+    ) {
+        let settings = match *self.settings.read().unwrap() {
+            Some(settings) => settings,
+            None => return,
+        };
+        let len = mbuf.data_len().min(settings.snaplen);
+        if self.counters.nb_bytes.load(Ordering::Relaxed) + len > settings.max_bytes {
+            self.counters.nb_skipped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let ring = ring.get_or_insert_with(|| {
+            Box::new(BackfillRing {
+                records: VecDeque::with_capacity(settings.nb_packets),
+                nb_bytes: 0,
+                counters: Arc::clone(&self.counters),
+            })
+        });
+        let ts = Duration::from_nanos(mbuf.timestamp());
+        let record = TapRecord::new(ts, mbuf, settings.snaplen, meta);
+        ring.nb_bytes += record.data().len();
+        self.counters
+            .nb_bytes
+            .fetch_add(record.data().len(), Ordering::Relaxed);
+        ring.records.push_back(record);
+        while ring.records.len() > settings.nb_packets {
+            if let Some(oldest) = ring.records.pop_front() {
+                ring.nb_bytes -= oldest.data().len();
+                self.counters
+                    .nb_bytes
+                    .fetch_sub(oldest.data().len(), Ordering::Relaxed);
+            }
+        }
+        self.counters.nb_buffered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Empties `ring`, returning its packets, oldest first, which are counted as flushed.
+    pub(crate) fn flush(&self, ring: &mut BackfillRing) -> VecDeque<TapRecord> {
+        let records = mem::take(&mut ring.records);
+        self.counters
+            .nb_flushed
+            .fetch_add(records.len() as u64, Ordering::Relaxed);
+        records
+    }
+
+    pub(crate) fn stats(&self) -> Option<BackfillStats> {
+        self.settings.read().unwrap().as_ref()?;
+        Some(BackfillStats {
+            nb_buffered: self.counters.nb_buffered.load(Ordering::Relaxed),
+            nb_flushed: self.counters.nb_flushed.load(Ordering::Relaxed),
+            nb_skipped: self.counters.nb_skipped.load(Ordering::Relaxed),
+            nb_bytes: self.counters.nb_bytes.load(Ordering::Relaxed),
+        })
+    }
+}
@@ -0,0 +1,201 @@
+//! Support bundles.
+//!
+//! [FilterCtx::write_support_bundle](crate::filter::FilterCtx::write_support_bundle) writes a
+//! snapshot of a running deployment to a new directory, to be attached to bug reports. The bundle
+//! is assembled by the calling thread from the shared state of the filter, without pausing the RX
+//! cores, and contains:
+//! - `config.toml`: the effective runtime configuration.
+//! - `ports.txt`: the DPDK version, and the driver, queues and extended statistics of each port.
+//! - `rules.txt`: the rule set generation of each RX core, rule counts and recent compilations.
+//! - `state.txt`: the flow table occupancy, scan statistics and drop counts.
+//! - `stats/`: the last lines of each statistics CSV of the monitor log directory, if logging.
+//! - `log.txt`: the last log records, if the logger was installed with
+//!   [logging::init](crate::logging::init).
+//!
+//! ## Example
+//! ```
+//! let bundle = filter_ctx.write_support_bundle(Path::new("/tmp"))?;
+//! println!("Support bundle written to {}", bundle.display());
+//! ```
+
+use super::FilterCtx;
+use crate::config::RuntimeConfig;
+use crate::dpdk;
+use crate::logging;
+use crate::port::info::PortInfo;
+use crate::port::statistics::PortStats;
+use crate::port::PortId;
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::Result;
+use chrono::Local;
+
+/// Number of lines kept from the end of each statistics CSV, besides its header.
+const NB_CSV_LINES: usize = 600;
+
+/// Runtime state registered for support bundles once the runtime is initialized.
+#[derive(Debug, Default)]
+pub(crate) struct BundleSources {
+    /// Effective runtime configuration, serialized.
+    config: RwLock<Option<String>>,
+    /// Online ports and their devices.
+    ports: RwLock<Vec<(PortId, String)>>,
+    /// Log directory of the monitor.
+    stats_dir: RwLock<Option<PathBuf>>,
+}
+
+impl BundleSources {
+    pub(crate) fn new() -> Self {
+        BundleSources::default()
+    }
+
+    pub(crate) fn set_config(&self, config: &RuntimeConfig) {
+        let toml = match config.to_effective_toml() {
+            Ok(toml) => toml,
+            Err(error) => format!("# Failed to serialize config: {}\n", error),
+        };
+        *self.config.write().unwrap() = Some(toml);
+    }
+
+    pub(crate) fn set_ports(&self, ports: Vec<(PortId, String)>) {
+        *self.ports.write().unwrap() = ports;
+    }
+
+    pub(crate) fn set_stats_dir(&self, path: &Path) {
+        *self.stats_dir.write().unwrap() = Some(path.to_path_buf());
+    }
+
+    /// Writes a support bundle of `filter_ctx` to a new directory in `directory`, and returns its
+    /// path.
+    pub(crate) fn write(&self, filter_ctx: &FilterCtx, directory: &Path) -> Result<PathBuf> {
+        let name = format!("retina-support-{}", Local::now().format("%Y%m%dT%H%M%S%.3f"));
+        let path = directory.join(name);
+        fs::create_dir_all(&path)?;
+        if let Some(config) = self.config.read().unwrap().as_ref() {
+            fs::write(path.join("config.toml"), config)?;
+        }
+        fs::write(path.join("ports.txt"), self.ports())?;
+        fs::write(path.join("rules.txt"), rules(filter_ctx))?;
+        fs::write(path.join("state.txt"), state(filter_ctx))?;
+        if let Some(stats_dir) = self.stats_dir.read().unwrap().as_ref() {
+            let bundle_stats = path.join("stats");
+            fs::create_dir_all(&bundle_stats)?;
+            for entry in fs::read_dir(stats_dir)? {
+                let entry = entry?;
+                let file = entry.path();
+                if file.extension().map_or(false, |ext| ext == "csv") {
+                    tail_csv(&file, &bundle_stats.join(entry.file_name()))?;
+                }
+            }
+        }
+        let mut log = File::create(path.join("log.txt"))?;
+        for record in logging::recent_records() {
+            writeln!(log, "{}", record)?;
+        }
+        log::info!("Support bundle written to {}", path.display());
+        Ok(path)
+    }
+
+    fn ports(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "DPDK {}.{:02}.{}",
+            dpdk::RTE_VER_YEAR,
+            dpdk::RTE_VER_MONTH,
+            dpdk::RTE_VER_MINOR
+        );
+        for (port_id, device) in self.ports.read().unwrap().iter() {
+            let socket_id = port_id.socket_id();
+            let _ = writeln!(out, "\nPort {} ({}), socket {}", port_id, device, socket_id);
+            match PortInfo::collect(*port_id) {
+                Ok(info) => {
+                    let (max_rxq, max_txq) = info.max_queues();
+                    let _ = writeln!(out, "driver: {}", info.driver_name());
+                    let _ = writeln!(out, "max_rx_queues: {}", max_rxq);
+                    let _ = writeln!(out, "max_tx_queues: {}", max_txq);
+                }
+                Err(error) => {
+                    let _ = writeln!(out, "info: {}", error);
+                }
+            }
+            match PortStats::collect(*port_id) {
+                Ok(stats) => {
+                    for (label, value) in stats.stats.iter() {
+                        let _ = writeln!(out, "{}: {}", label, value);
+                    }
+                }
+                Err(error) => {
+                    let _ = writeln!(out, "stats: {}", error);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn rules(filter_ctx: &FilterCtx) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:#?}", filter_ctx.rule_stats());
+    let _ = writeln!(out, "{:#?}", filter_ctx.rule_generations());
+    for compile in filter_ctx.compile_stats() {
+        let _ = writeln!(out, "{:?}", compile);
+    }
+    for failure in filter_ctx.rule_test_failures() {
+        let _ = writeln!(out, "{:?}", failure);
+    }
+    for source in filter_ctx.rule_sources() {
+        let _ = writeln!(out, "{:?}", source);
+    }
+    for file in filter_ctx.rule_file_stats() {
+        let _ = writeln!(out, "{:?}", file);
+    }
+    out
+}
+
+fn state(filter_ctx: &FilterCtx) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:#?}", filter_ctx.flow_table_stats());
+    let _ = writeln!(out, "{:#?}", filter_ctx.scan_stats());
+    for (core, drops) in filter_ctx.drop_stats() {
+        let core = core.map_or("unattached".to_string(), |core| core.to_string());
+        for (reason, count) in drops.iter().filter(|(_, count)| *count > 0) {
+            let _ = writeln!(out, "drops core {} {}: {}", core, reason.name(), count);
+        }
+    }
+    for quota in filter_ctx.storage_quota_stats() {
+        let _ = writeln!(out, "{:?}", quota);
+    }
+    for flag in filter_ctx.flags() {
+        let _ = writeln!(out, "{:?}", flag);
+    }
+    out
+}
+
+/// Copies the header and last lines of the CSV at `from` to `to`.
+fn tail_csv(from: &Path, to: &Path) -> Result<()> {
+    let mut lines = BufReader::new(File::open(from)?).lines();
+    let header = match lines.next() {
+        Some(header) => header?,
+        None => String::new(),
+    };
+    let mut last = VecDeque::with_capacity(NB_CSV_LINES);
+    for line in lines {
+        if last.len() == NB_CSV_LINES {
+            last.pop_front();
+        }
+        last.push_back(line?);
+    }
+    let mut file = File::create(to)?;
+    writeln!(file, "{}", header)?;
+    for line in last {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
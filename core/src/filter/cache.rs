@@ -0,0 +1,165 @@
+//! Cross-flow verdict cache.
+//!
+//! Clients often connect to the same benign server many times. When enabled, a flow that expires
+//! from the flow table after its payloads were scanned without any match clears its key, made of
+//! the client address, the server, the server port and the transport protocol. New flows with a
+//! cleared key skip payload scanning from their first payload until the entry expires. Entries
+//! are invalidated when the rules change, and the first match of any flow of a key removes it from
+//! the cache.
+//!
+//! The server is identified by the name the client requested in the first payload of the flow, the
+//! SNI of a TLS ClientHello or the `Host` header of an HTTP request (see
+//! [server_name](crate::protocols::app::server_name)), so that the flows to a name served from
+//! many addresses share a key. Flows without a server name are identified by the server address.
+//!
+//! The client of a flow is the endpoint that sent its first packet, as tracked with
+//! [FilterCtx::track_flow](crate::filter::FilterCtx::track_flow). Flows whose packets are not
+//! tracked fall back to taking the endpoint with the lower port as the server.
+
+use crate::config::VerdictCacheConfig;
+use crate::protocols::layer4::Flow;
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+/// Statistics of the verdict cache.
+#[derive(Debug, Clone, Copy)]
+pub struct VerdictCacheStats {
+    /// Number of cleared keys in the cache.
+    pub nb_entries: usize,
+    /// Number of new flows that skipped scanning.
+    pub nb_hits: u64,
+}
+
+/// Server of a verdict key.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+enum Server {
+    Name(Box<str>),
+    Addr(IpAddr),
+}
+
+/// Key of the flows between a client and a server.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub(crate) struct VerdictKey {
+    client: IpAddr,
+    server: Server,
+    port: u16,
+    proto: usize,
+}
+
+impl VerdictKey {
+    /// Returns the key of `flow`, whose endpoint at index `client` in the flow key sent the first
+    /// packet, if known, and whose client requested `server_name`, if any.
+    pub(crate) fn new(flow: &Flow, client: Option<usize>, server_name: Option<&str>) -> Self {
+        let (a, b) = flow.addrs();
+        let (client, server) = match client {
+            Some(0) => (a, b),
+            Some(_) => (b, a),
+            None if (a.port(), a.ip()) < (b.port(), b.ip()) => (b, a),
+            None => (a, b),
+        };
+        VerdictKey {
+            client: client.ip(),
+            server: match server_name {
+                Some(name) => Server::Name(name.into()),
+                None => Server::Addr(server.ip()),
+            },
+            port: server.port(),
+            proto: flow.proto(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    deadline: Instant,
+    /// Rule set generation the flow was scanned with.
+    generation: u64,
+}
+
+/// Shared verdict cache of a filter. Disabled until configured.
+#[derive(Debug, Default)]
+pub(crate) struct VerdictCache {
+    entries: DashMap<VerdictKey, Entry>,
+    ttl_ms: AtomicU64,
+    capacity: AtomicUsize,
+    nb_hits: AtomicU64,
+}
+
+impl VerdictCache {
+    pub(crate) fn new() -> Self {
+        VerdictCache::default()
+    }
+
+    /// Applies verdict cache options from the runtime configuration.
+    pub(crate) fn configure(&self, config: &VerdictCacheConfig) {
+        self.ttl_ms.store(config.ttl * 1000, Ordering::Relaxed);
+        self.capacity.store(config.capacity, Ordering::Relaxed);
+        log::info!("Verdict cache enabled, ttl: {}s", config.ttl);
+    }
+
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.ttl_ms.load(Ordering::Relaxed) > 0
+    }
+
+    /// Returns whether `key`, of a flow scanning its first payload, was cleared with rule set
+    /// `generation`.
+    pub(crate) fn is_cleared(&self, key: &VerdictKey, generation: u64) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let valid = match self.entries.get(key) {
+            Some(entry) => entry.generation == generation && entry.deadline > Instant::now(),
+            None => return false,
+        };
+        if valid {
+            self.nb_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.entries.remove(key);
+        }
+        valid
+    }
+
+    /// Clears `key`, of a flow scanned without a match with rule set `generation`.
+    pub(crate) fn clear(&self, key: VerdictKey, generation: u64) {
+        if !self.is_enabled() || self.entries.len() >= self.capacity.load(Ordering::Relaxed) {
+            return;
+        }
+        let ttl = Duration::from_millis(self.ttl_ms.load(Ordering::Relaxed));
+        self.entries.insert(
+            key,
+            Entry {
+                deadline: Instant::now() + ttl,
+                generation,
+            },
+        );
+    }
+
+    /// Removes `key`, of a flow that matched a rule for the first time.
+    pub(crate) fn invalidate(&self, key: &VerdictKey) {
+        if self.is_enabled() {
+            self.entries.remove(key);
+        }
+    }
+
+    /// Removes expired entries and entries cleared with another rule set than `generation`.
+    pub(crate) fn prune(&self, generation: u64) {
+        let now = Instant::now();
+        self.entries
+            .retain(|_, entry| entry.generation == generation && entry.deadline > now);
+    }
+
+    pub(crate) fn stats(&self) -> Option<VerdictCacheStats> {
+        if !self.is_enabled() {
+            return None;
+        }
+        Some(VerdictCacheStats {
+            nb_entries: self.entries.len(),
+            nb_hits: self.nb_hits.load(Ordering::Relaxed),
+        })
+    }
+}
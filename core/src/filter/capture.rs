@@ -0,0 +1,584 @@
+//! Rolling packet capture of capture rules.
+//!
+//! Rules with the `capture` action (see [rule](crate::filter::rule)) select packets for quick
+//! triage, separately from the flows of matching rules. Packets handed to
+//! [FilterCtx::capture_packet](crate::filter::FilterCtx::capture_packet) whose payload matches a
+//! capture rule are written to a rolling set of pcap files in a directory. Capture rules never
+//! mark flows matched.
+//!
+//! Like the [tap](crate::filter::tap), files are written by a background thread: RX cores only
+//! copy the packet and enqueue it without blocking, in one of the
+//! [priority classes](crate::filter::priority) of the queue, and packets are dropped and counted
+//! when their class is full. A new file is started when the current one reaches the configured size or age,
+//! and the oldest files written by the runtime are removed beyond the configured number of files.
+//! Files are pcap by default, or pcapng with the [metadata](crate::filter::store) of each packet.
+//!
+//! ## Deduplication
+//! The same payload often shows up in many flows. With the `dedup` option of
+//! [CaptureConfig](crate::config::CaptureConfig), the writer thread remembers the payloads it
+//! wrote, within a memory budget. A packet whose payload is identical to a remembered one is
+//! written without its payload, keeping its headers and original length, and a line is appended to
+//! the `.refs.csv` file of the pcap file with the index of the packet in the file, the payload hash
+//! and length, and the file and index of the first capture of the payload. Payloads are compared
+//! byte for byte, so that a crafted hash collision cannot hide a payload. Packets cut at the
+//! snapshot length, and payloads that are not part of the packet, are never deduplicated.
+//!
+//! ## Finalization
+//! Each closed file is handed to the capture file hooks and to the external command or webhook of
+//! the `[capture.finalize]` options, off the writer thread (see
+//! [finalize](crate::filter::finalize)). Files that cannot be opened, written or removed are
+//! reported to the [storage error hooks](crate::hooks::Hooks::on_storage_error).
+
+use super::finalize::{CaptureFileEntry, FinalizeCounters, Finalizer};
+use super::priority::{self, ClassStats, Priority, PriorityReceiver, PrioritySender};
+use super::store::{self, PacketMeta};
+use super::tap::{self, TapRecord};
+use crate::config::{CaptureConfig, CaptureFormat, StoragePriorityConfig};
+use crate::hooks::{Hooks, StorageError, StorageTarget};
+use crate::memory::mbuf::Mbuf;
+use crate::timebase;
+use crate::utils::hash::stable_hash;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::Local;
+use crossbeam_channel::RecvTimeoutError;
+use csv::Writer;
+use serde::Serialize;
+
+/// Interval at which the current file is flushed while no packet is captured.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counters of the rolling capture.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CaptureStats {
+    /// Number of packets queued for writing.
+    pub nb_captured: u64,
+    /// Number of packets dropped because their priority class was full.
+    pub nb_dropped: u64,
+    /// Number of files started.
+    pub nb_files: u64,
+    /// Number of packets written without their payload, already captured.
+    pub nb_deduped: u64,
+    /// Number of payload bytes not written because of deduplication.
+    pub nb_deduped_bytes: u64,
+    /// Number of closed files finalized.
+    pub nb_finalized: u64,
+    /// Number of closed files whose finalization failed or was skipped.
+    pub nb_finalize_failed: u64,
+}
+
+/// A queued packet, and the offset of its payload in the packet if known.
+#[derive(Debug)]
+struct CaptureRecord {
+    record: TapRecord,
+    payload_offset: Option<usize>,
+}
+
+#[derive(Debug)]
+struct CaptureWriter {
+    tx: PrioritySender<CaptureRecord>,
+    snaplen: usize,
+}
+
+/// Counters of the writer thread.
+#[derive(Debug, Default)]
+struct WriterCounters {
+    nb_files: AtomicU64,
+    nb_deduped: AtomicU64,
+    nb_deduped_bytes: AtomicU64,
+}
+
+/// Shared rolling capture of a filter.
+#[derive(Debug, Default)]
+pub(crate) struct Capture {
+    writer: RwLock<Option<CaptureWriter>>,
+    nb_captured: AtomicU64,
+    nb_dropped: AtomicU64,
+    counters: Arc<WriterCounters>,
+    /// Sequence numbers and paths of the files written by the runtime that were not removed yet,
+    /// oldest first.
+    files: Arc<Mutex<VecDeque<(u64, PathBuf)>>>,
+    finalize_counters: Arc<FinalizeCounters>,
+}
+
+impl Capture {
+    pub(crate) fn new() -> Self {
+        Capture::default()
+    }
+
+    /// Creates the capture directory and starts the writer and finalization threads. Closed files
+    /// are handed to the capture file hooks of `hooks`, and write errors to its storage error
+    /// hooks.
+    pub(crate) fn configure(
+        &self,
+        config: &CaptureConfig,
+        priority: &StoragePriorityConfig,
+        hooks: Arc<Hooks>,
+    ) -> Result<()> {
+        fs::create_dir_all(&config.directory)?;
+        let finalizer = Finalizer::start(
+            config.finalize.as_ref(),
+            Arc::clone(&hooks),
+            Arc::clone(&self.finalize_counters),
+        )?;
+        let (tx, rx) = priority::channel("capture", config.queue_size, priority);
+        let writer_config = config.clone();
+        let counters = Arc::clone(&self.counters);
+        let files = Arc::clone(&self.files);
+        thread::Builder::new()
+            .name("retina-capture".into())
+            .spawn(move || write_loop(&writer_config, rx, &counters, &files, &finalizer, &hooks))?;
+        *self.writer.write().unwrap() = Some(CaptureWriter {
+            tx,
+            snaplen: config.snaplen,
+        });
+        log::info!("Capturing packets of capture rules to {}", config.directory);
+        Ok(())
+    }
+
+    /// Returns whether a capture directory is configured.
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.writer.read().unwrap().is_some()
+    }
+
+    /// Copies `mbuf`, whose payload is `payload`, with metadata `meta`, to the capture in the class
+    /// of `priority`, cut at `rule_snaplen` bytes if set, unless the class is full. The payload is
+    /// only deduplicated if `dedup` is set and the packet is not cut. Returns the number of bytes
+    /// queued, `0` if the packet was dropped.
+    pub(crate) fn capture(
+        &self,
+        mbuf: &Mbuf,
+        payload: &[u8],
+        meta: PacketMeta,
+        priority: Priority,
+        dedup: bool,
+        rule_snaplen: Option<usize>,
+    ) -> usize {
+        let writer = self.writer.read().unwrap();
+        let writer = match writer.as_ref() {
+            Some(writer) => writer,
+            None => return 0,
+        };
+        let ts = Duration::from_nanos(mbuf.timestamp());
+        let data = mbuf.data();
+        let payload_offset = (payload.as_ptr() as usize)
+            .checked_sub(data.as_ptr() as usize)
+            .filter(|offset| dedup && offset + payload.len() <= data.len());
+        let record = CaptureRecord {
+            record: TapRecord::new(ts, mbuf, tap::snaplen(writer.snaplen, rule_snaplen), meta),
+            payload_offset,
+        };
+        self.send(writer, record, priority)
+    }
+
+    /// Queues the copy of a packet returned by `copy` for the snapshot length of the capture, in
+    /// the class of `priority`, unless the class is full, without deduplicating its payload.
+    /// Returns the number of bytes queued, `0` if the packet was dropped.
+    pub(crate) fn capture_copy(
+        &self,
+        priority: Priority,
+        copy: impl FnOnce(usize) -> TapRecord,
+    ) -> usize {
+        let writer = self.writer.read().unwrap();
+        let writer = match writer.as_ref() {
+            Some(writer) => writer,
+            None => return 0,
+        };
+        let record = CaptureRecord {
+            record: copy(writer.snaplen),
+            payload_offset: None,
+        };
+        self.send(writer, record, priority)
+    }
+
+    fn send(&self, writer: &CaptureWriter, record: CaptureRecord, priority: Priority) -> usize {
+        let nb_bytes = record.record.data().len();
+        match writer.tx.try_send(record, priority) {
+            true => {
+                self.nb_captured.fetch_add(1, Ordering::Relaxed);
+                nb_bytes
+            }
+            false => {
+                self.nb_dropped.fetch_add(1, Ordering::Relaxed);
+                0
+            }
+        }
+    }
+
+    /// Returns the sequence number of the file packets queued now are written to, unless the
+    /// writer rolls over to a new file first.
+    #[inline]
+    pub(crate) fn file_seq(&self) -> u64 {
+        self.counters.nb_files.load(Ordering::Relaxed).saturating_sub(1)
+    }
+
+    /// Returns the paths of the files with sequence numbers in `first..=last` that were not
+    /// removed, oldest first.
+    pub(crate) fn file_paths(&self, first: u64, last: u64) -> Vec<PathBuf> {
+        self.files
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(seq, _)| (first..=last).contains(seq))
+            .map(|(_, path)| path.clone())
+            .collect()
+    }
+
+    pub(crate) fn stats(&self) -> Option<CaptureStats> {
+        self.writer.read().unwrap().as_ref()?;
+        Some(CaptureStats {
+            nb_captured: self.nb_captured.load(Ordering::Relaxed),
+            nb_dropped: self.nb_dropped.load(Ordering::Relaxed),
+            nb_files: self.counters.nb_files.load(Ordering::Relaxed),
+            nb_deduped: self.counters.nb_deduped.load(Ordering::Relaxed),
+            nb_deduped_bytes: self.counters.nb_deduped_bytes.load(Ordering::Relaxed),
+            nb_finalized: self.finalize_counters.nb_finalized.load(Ordering::Relaxed),
+            nb_finalize_failed: self.finalize_counters.nb_failed.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Returns the counters of each priority class, empty if no capture directory is configured.
+    pub(crate) fn class_stats(&self) -> Vec<ClassStats> {
+        match self.writer.read().unwrap().as_ref() {
+            Some(writer) => writer.tx.stats(),
+            None => vec![],
+        }
+    }
+}
+
+/// The pcap file being written.
+struct CaptureFile {
+    writer: BufWriter<File>,
+    format: CaptureFormat,
+    /// References of deduplicated packets, created on the first one.
+    refs: Option<Writer<File>>,
+    /// Sequence number of the file in the capture directory.
+    seq: u64,
+    path: PathBuf,
+    /// Number of packets written.
+    nb_packets: u64,
+    opened: Instant,
+    /// Wall-clock time the file was opened, in nanoseconds since the UNIX epoch.
+    opened_ts: u64,
+    size: u64,
+}
+
+impl CaptureFile {
+    /// Appends `record` to the file.
+    fn write(&mut self, record: &TapRecord) -> io::Result<()> {
+        match self.format {
+            CaptureFormat::Pcap => {
+                tap::write_record(&mut self.writer, record)?;
+                self.size += record.pcap_len() as u64;
+            }
+            CaptureFormat::Pcapng => {
+                let block = store::enhanced_packet_block(record);
+                self.writer.write_all(&block)?;
+                self.size += block.len() as u64;
+            }
+        }
+        self.nb_packets += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(refs) = self.refs.as_mut() {
+            refs.flush()?;
+        }
+        self.writer.flush()
+    }
+
+    /// Flushes and closes the file, and hands it to `finalizer`. Flush errors are reported to
+    /// `hooks`.
+    fn close(mut self, finalizer: &Finalizer, hooks: &Hooks) {
+        if let Err(error) = self.flush() {
+            log::warn!("Capture write error: {}", error);
+            report(hooks, &self.path, error);
+            return;
+        }
+        let entry = CaptureFileEntry {
+            refs: self.refs.is_some().then(|| refs_path(&self.path)),
+            path: self.path,
+            seq: self.seq,
+            nb_packets: self.nb_packets,
+            size: self.size,
+            opened_ts: self.opened_ts,
+            closed_ts: timebase::now_ns(),
+        };
+        drop(self.writer);
+        drop(self.refs);
+        finalizer.finalize(entry);
+    }
+}
+
+/// First capture of a payload.
+struct StoredPayload {
+    payload: Box<[u8]>,
+    /// Sequence number and name of the file of the capture.
+    seq: u64,
+    name: Arc<str>,
+    /// Index of the packet in the file.
+    index: u64,
+}
+
+/// Payloads written to the capture, within a memory budget.
+struct Dedup {
+    payloads: HashMap<u64, StoredPayload>,
+    /// Hashes of `payloads`, oldest first.
+    order: VecDeque<u64>,
+    nb_bytes: usize,
+    max_bytes: usize,
+}
+
+impl Dedup {
+    fn new(max_bytes: usize) -> Self {
+        Dedup {
+            payloads: HashMap::new(),
+            order: VecDeque::new(),
+            nb_bytes: 0,
+            max_bytes,
+        }
+    }
+
+    /// Returns the first capture of `payload`, or remembers `payload` as captured to packet
+    /// `index` of `file` and returns `None`.
+    fn lookup(&mut self, hash: u64, payload: &[u8], file: &CaptureFile) -> Option<&StoredPayload> {
+        if self.payloads.contains_key(&hash) {
+            // Keep the first capture on hash collisions
+            return self
+                .payloads
+                .get(&hash)
+                .filter(|stored| *stored.payload == *payload);
+        }
+        if payload.len() > self.max_bytes {
+            return None;
+        }
+        while self.nb_bytes + payload.len() > self.max_bytes {
+            match self.order.pop_front() {
+                Some(oldest) => self.forget(oldest),
+                None => break,
+            }
+        }
+        let stored = StoredPayload {
+            payload: payload.into(),
+            seq: file.seq,
+            name: file_name(&file.path).into(),
+            index: file.nb_packets,
+        };
+        self.nb_bytes += payload.len();
+        self.payloads.insert(hash, stored);
+        self.order.push_back(hash);
+        None
+    }
+
+    fn forget(&mut self, hash: u64) {
+        if let Some(stored) = self.payloads.remove(&hash) {
+            self.nb_bytes -= stored.payload.len();
+        }
+    }
+
+    /// Forgets the payloads captured to files up to `seq`, once removed.
+    fn remove_files(&mut self, seq: u64) {
+        self.payloads.retain(|_, stored| stored.seq > seq);
+        self.order.retain(|hash| self.payloads.contains_key(hash));
+        self.nb_bytes = self.payloads.values().map(|stored| stored.payload.len()).sum();
+    }
+}
+
+/// Writes queued packets to rolling pcap files in the capture directory, listing them in `files`,
+/// handing closed files to `finalizer` and reporting errors to `hooks`. Returns when all senders
+/// are dropped.
+fn write_loop(
+    config: &CaptureConfig,
+    mut rx: PriorityReceiver<CaptureRecord>,
+    counters: &WriterCounters,
+    files: &Mutex<VecDeque<(u64, PathBuf)>>,
+    finalizer: &Finalizer,
+    hooks: &Hooks,
+) {
+    let max_age = Duration::from_secs(config.max_file_age);
+    let mut current: Option<CaptureFile> = None;
+    let mut dedup = config.dedup.then(|| Dedup::new(config.dedup_memory));
+    loop {
+        let CaptureRecord {
+            mut record,
+            payload_offset,
+        } = match rx.recv_timeout(FLUSH_INTERVAL) {
+            Ok(record) => record,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(file) = current.as_mut() {
+                    if let Err(error) = file.flush() {
+                        log::warn!("Capture write error: {}", error);
+                        report(hooks, &file.path, error);
+                    }
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let full = current.as_ref().map_or(true, |file| {
+            file.size >= config.max_file_size || file.opened.elapsed() >= max_age
+        });
+        if full {
+            if let Some(file) = current.take() {
+                file.close(finalizer, hooks);
+            }
+            let seq = counters.nb_files.load(Ordering::Relaxed);
+            let path = file_path(Path::new(&config.directory), seq, config.format);
+            match create_file(&path, seq, config) {
+                Ok(file) => {
+                    log::debug!("Capturing to {}", path.display());
+                    counters.nb_files.fetch_add(1, Ordering::Relaxed);
+                    current = Some(file);
+                    files.lock().unwrap().push_back((seq, path));
+                }
+                Err(error) => {
+                    log::error!("Capture {} open error: {}", path.display(), error);
+                    report(hooks, &path, error);
+                    continue;
+                }
+            }
+            let mut removed = vec![];
+            {
+                let mut files = files.lock().unwrap();
+                while config.max_files > 0 && files.len() > config.max_files {
+                    removed.extend(files.pop_front());
+                }
+            }
+            for (seq, oldest) in removed {
+                if let Err(error) = fs::remove_file(&oldest) {
+                    log::warn!("Failed to remove {}: {}", oldest.display(), error);
+                    report(hooks, &oldest, error);
+                }
+                let refs = refs_path(&oldest);
+                if refs.exists() {
+                    let _ = fs::remove_file(refs);
+                }
+                if let Some(dedup) = dedup.as_mut() {
+                    dedup.remove_files(seq);
+                }
+            }
+        }
+        if let Some(file) = current.as_mut() {
+            if let (Some(dedup), Some(offset)) = (dedup.as_mut(), payload_offset) {
+                if !record.is_snapped() && offset < record.data().len() {
+                    if let Err(error) = deduplicate(dedup, &mut record, offset, file, counters) {
+                        log::error!("Capture reference write error: {}", error);
+                        report(hooks, &refs_path(&file.path), error);
+                    }
+                }
+            }
+            if let Err(error) = file.write(&record) {
+                log::error!("Capture write error: {}", error);
+                report(hooks, &file.path, error);
+                current = None;
+            }
+        }
+    }
+    if let Some(file) = current {
+        file.close(finalizer, hooks);
+    }
+}
+
+/// Reports an error on the capture file `path` to the storage error hooks of `hooks`.
+fn report(hooks: &Hooks, path: &Path, error: impl fmt::Display) {
+    hooks.storage_error(&StorageError::new(StorageTarget::Capture, path.display(), error));
+}
+
+/// Strips the payload of `record`, starting at `offset`, if it was already captured, and
+/// references the first capture in the `.refs.csv` file of `file`.
+fn deduplicate(
+    dedup: &mut Dedup,
+    record: &mut TapRecord,
+    offset: usize,
+    file: &mut CaptureFile,
+    counters: &WriterCounters,
+) -> Result<()> {
+    let payload = &record.data()[offset..];
+    let hash = stable_hash(payload, 0);
+    let stored = match dedup.lookup(hash, payload, file) {
+        Some(stored) => stored,
+        None => return Ok(()),
+    };
+    if file.refs.is_none() {
+        let mut refs = Writer::from_path(refs_path(&file.path))?;
+        refs.write_record(["index", "hash", "payload_len", "first_file", "first_index"])?;
+        file.refs = Some(refs);
+    }
+    if let Some(refs) = file.refs.as_mut() {
+        refs.write_record([
+            file.nb_packets.to_string(),
+            format!("{:016x}", hash),
+            payload.len().to_string(),
+            stored.name.to_string(),
+            stored.index.to_string(),
+        ])?;
+    }
+    counters.nb_deduped.fetch_add(1, Ordering::Relaxed);
+    counters
+        .nb_deduped_bytes
+        .fetch_add(payload.len() as u64, Ordering::Relaxed);
+    record.truncate(offset);
+    Ok(())
+}
+
+/// Returns the path of the `seq`-th file of the capture directory `directory`.
+fn file_path(directory: &Path, seq: u64, format: CaptureFormat) -> PathBuf {
+    let ts = Local::now().format("%Y%m%d-%H%M%S");
+    let extension = match format {
+        CaptureFormat::Pcap => "pcap",
+        CaptureFormat::Pcapng => "pcapng",
+    };
+    directory.join(format!("capture-{ts}-{seq}.{extension}"))
+}
+
+/// Returns the file name of `path`.
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+/// Returns the path of the references of deduplicated packets of the pcap file `path`.
+fn refs_path(path: &Path) -> PathBuf {
+    path.with_extension("refs.csv")
+}
+
+/// Creates the pcap file `path`, the `seq`-th of the capture directory, and writes its global
+/// header, or its section header and interface with `format = "pcapng"`.
+fn create_file(path: &Path, seq: u64, config: &CaptureConfig) -> io::Result<CaptureFile> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let size = match config.format {
+        CaptureFormat::Pcap => {
+            tap::write_header(&mut writer, config.snaplen)?;
+            24
+        }
+        CaptureFormat::Pcapng => {
+            let mut header = store::section_header();
+            header.extend(store::interface_description(config.snaplen));
+            writer.write_all(&header)?;
+            header.len() as u64
+        }
+    };
+    Ok(CaptureFile {
+        writer,
+        format: config.format,
+        refs: None,
+        seq,
+        path: path.to_path_buf(),
+        nb_packets: 0,
+        opened: Instant::now(),
+        opened_ts: timebase::now_ns(),
+        size,
+    })
+}
@@ -0,0 +1,206 @@
+//! Per-core checksum outcome accounting.
+//!
+//! [FilterCtx::parse_l4](crate::filter::FilterCtx::parse_l4) applies the configured
+//! [ChecksumPolicy](crate::config::ChecksumPolicy) to packets whose checksums were found bad by
+//! the NIC or, if enabled, by [software verification](crate::protocols::checksum), and counts the
+//! outcome of every check on the core that received the packet. Packets without a bad checksum
+//! are only counted when software verification is enabled.
+
+use crate::config::{ChecksumConfig, ChecksumPolicy};
+use crate::protocols::checksum::{self, ChecksumStatus};
+use crate::protocols::layer4::L4Context;
+use crate::protocols::packet::ethernet::Ethernet;
+use crate::protocols::packet::Packet;
+use crate::subscription::ZcFrame;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+
+/// Outcome of a checksum check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChecksumOutcome {
+    /// Verified in software and correct.
+    Good,
+    /// Not verifiable in software, e.g. a fragment.
+    Unchecked,
+    /// Bad and dropped.
+    Dropped,
+    /// Bad and kept with [L4Context::bad_checksum](L4Context::bad_checksum) set.
+    Flagged,
+    /// Bad and kept as correct.
+    Ignored,
+}
+
+/// Number of checksum outcomes.
+const NB_OUTCOMES: usize = 5;
+
+/// Checksum outcome counts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ChecksumStats {
+    /// Packets verified in software with correct checksums.
+    pub nb_good: u64,
+    /// Packets whose checksums could not be verified in software.
+    pub nb_unchecked: u64,
+    /// Packets with a bad checksum that were dropped.
+    pub nb_dropped: u64,
+    /// Packets with a bad checksum that were flagged.
+    pub nb_flagged: u64,
+    /// Packets with a bad checksum that were kept as correct.
+    pub nb_ignored: u64,
+}
+
+impl ChecksumStats {
+    /// Returns the number of packets with a bad checksum.
+    pub fn nb_bad(&self) -> u64 {
+        self.nb_dropped + self.nb_flagged + self.nb_ignored
+    }
+
+    /// Returns the number of counted packets.
+    pub fn total(&self) -> u64 {
+        self.nb_good + self.nb_unchecked + self.nb_bad()
+    }
+
+    /// Adds `other` to these counts.
+    pub fn add(&mut self, other: &ChecksumStats) {
+        self.nb_good += other.nb_good;
+        self.nb_unchecked += other.nb_unchecked;
+        self.nb_dropped += other.nb_dropped;
+        self.nb_flagged += other.nb_flagged;
+        self.nb_ignored += other.nb_ignored;
+    }
+}
+
+/// Checksum outcome counters of a single core.
+#[derive(Debug, Default)]
+pub(crate) struct ChecksumCounters([AtomicU64; NB_OUTCOMES]);
+
+impl ChecksumCounters {
+    #[inline]
+    fn record(&self, outcome: ChecksumOutcome) {
+        self.0[outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add_to(&self, stats: &mut ChecksumStats) {
+        let count = |outcome: ChecksumOutcome| self.0[outcome as usize].load(Ordering::Relaxed);
+        stats.nb_good += count(ChecksumOutcome::Good);
+        stats.nb_unchecked += count(ChecksumOutcome::Unchecked);
+        stats.nb_dropped += count(ChecksumOutcome::Dropped);
+        stats.nb_flagged += count(ChecksumOutcome::Flagged);
+        stats.nb_ignored += count(ChecksumOutcome::Ignored);
+    }
+}
+
+/// Checksum policy and registry of the checksum counters of all cores.
+#[derive(Debug, Default)]
+pub(crate) struct Checksums {
+    cores: RwLock<BTreeMap<u32, Arc<ChecksumCounters>>>,
+    /// Counters of contexts that are not attached to a core.
+    unattached: Arc<ChecksumCounters>,
+    software: AtomicBool,
+    /// Bad checksum policy, as a `ChecksumPolicy` discriminant.
+    policy: AtomicU8,
+}
+
+impl Checksums {
+    pub(crate) fn new() -> Self {
+        Checksums::default()
+    }
+
+    /// Applies checksum validation options from the runtime configuration.
+    pub(crate) fn configure(&self, config: &ChecksumConfig) {
+        self.software.store(config.software, Ordering::Relaxed);
+        self.policy.store(config.policy as u8, Ordering::Relaxed);
+        if config.software {
+            log::info!("Software checksum verification enabled, policy: {:?}", config.policy);
+        }
+    }
+
+    fn policy(&self) -> ChecksumPolicy {
+        match self.policy.load(Ordering::Relaxed) {
+            p if p == ChecksumPolicy::Flag as u8 => ChecksumPolicy::Flag,
+            p if p == ChecksumPolicy::Ignore as u8 => ChecksumPolicy::Ignore,
+            _ => ChecksumPolicy::Drop,
+        }
+    }
+
+    /// Returns the checksum counters of `core`, creating them if needed.
+    pub(crate) fn core(&self, core: u32) -> Arc<ChecksumCounters> {
+        if let Some(counters) = self.cores.read().unwrap().get(&core) {
+            return Arc::clone(counters);
+        }
+        let mut cores = self.cores.write().unwrap();
+        Arc::clone(cores.entry(core).or_default())
+    }
+
+    /// Returns the counters of contexts that are not attached to a core.
+    pub(crate) fn unattached(&self) -> Arc<ChecksumCounters> {
+        Arc::clone(&self.unattached)
+    }
+
+    /// Checks the checksums of `mbuf`, parsed as `ctx`, applies the policy and counts the outcome
+    /// on `counters`. Returns whether the packet is kept.
+    #[inline]
+    pub(crate) fn check(
+        &self,
+        mbuf: &ZcFrame,
+        ctx: &mut L4Context,
+        counters: &ChecksumCounters,
+    ) -> bool {
+        let mut bad = ctx.bad_checksum;
+        if self.software.load(Ordering::Relaxed) && !bad {
+            let status = match mbuf.parse_to::<Ethernet>() {
+                Ok(eth) => checksum::verify(&eth),
+                Err(_) => ChecksumStatus::Unchecked,
+            };
+            match status {
+                ChecksumStatus::Good => counters.record(ChecksumOutcome::Good),
+                ChecksumStatus::Unchecked => counters.record(ChecksumOutcome::Unchecked),
+                ChecksumStatus::Bad => bad = true,
+            }
+        }
+        if !bad {
+            return true;
+        }
+        match self.policy() {
+            ChecksumPolicy::Drop => {
+                counters.record(ChecksumOutcome::Dropped);
+                false
+            }
+            ChecksumPolicy::Flag => {
+                counters.record(ChecksumOutcome::Flagged);
+                ctx.bad_checksum = true;
+                true
+            }
+            ChecksumPolicy::Ignore => {
+                counters.record(ChecksumOutcome::Ignored);
+                ctx.bad_checksum = false;
+                true
+            }
+        }
+    }
+
+    /// Returns the checksum outcome counts of each core. Counts recorded by contexts that are not
+    /// attached to a core are reported under `None`, if any.
+    pub(crate) fn stats(&self) -> BTreeMap<Option<u32>, ChecksumStats> {
+        let mut stats: BTreeMap<_, _> = self
+            .cores
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(core, counters)| {
+                let mut stats = ChecksumStats::default();
+                counters.add_to(&mut stats);
+                (Some(*core), stats)
+            })
+            .collect();
+        let mut unattached = ChecksumStats::default();
+        self.unattached.add_to(&mut unattached);
+        if unattached.total() > 0 {
+            stats.insert(None, unattached);
+        }
+        stats
+    }
+}
@@ -0,0 +1,129 @@
+//! CPU cycles of the RX cores.
+//!
+//! Each RX core reads the TSC around every iteration of its RX loop, and counts the cycles of
+//! iterations that received packets as busy, and the others as idle. The counts tell how much
+//! headroom each core has: a core close to 100% busy is about to drop packets, while the cycles
+//! per packet show the cost of the subscription and rules. They are read with
+//! [FilterCtx::core_cycles](crate::filter::FilterCtx::core_cycles), and shown and logged by the
+//! monitor.
+//!
+//! ## Example
+//! Utilization over the last second, e.g. to scale out:
+//! ```
+//! let before = filter_ctx.core_cycles();
+//! thread::sleep(Duration::from_secs(1));
+//! for (now, before) in filter_ctx.core_cycles().iter().zip(before.iter()) {
+//!     let interval = now.since(before);
+//!     println!("Core {}: {:.1}% busy", interval.core, interval.utilization() * 100.0);
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+
+/// Cycle counters of an RX core, written by the core only.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+pub(crate) struct CycleCounters {
+    busy_cycles: AtomicU64,
+    idle_cycles: AtomicU64,
+    nb_pkts: AtomicU64,
+}
+
+impl CycleCounters {
+    /// Records an iteration of the RX loop that took `cycles` cycles and received `nb_pkts`
+    /// packets.
+    #[inline]
+    pub(crate) fn record(&self, cycles: u64, nb_pkts: usize) {
+        // Single writer: plain stores are enough and cheaper than read-modify-write operations
+        let counter = if nb_pkts > 0 {
+            let nb = self.nb_pkts.load(Ordering::Relaxed) + nb_pkts as u64;
+            self.nb_pkts.store(nb, Ordering::Relaxed);
+            &self.busy_cycles
+        } else {
+            &self.idle_cycles
+        };
+        counter.store(counter.load(Ordering::Relaxed) + cycles, Ordering::Relaxed);
+    }
+}
+
+/// Cycles spent by an RX core since it started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CoreCycles {
+    /// Core ID.
+    pub core: u32,
+    /// Cycles of RX loop iterations that received packets.
+    pub busy_cycles: u64,
+    /// Cycles of RX loop iterations that received no packet.
+    pub idle_cycles: u64,
+    /// Number of packets received.
+    pub nb_pkts: u64,
+}
+
+impl CoreCycles {
+    /// Returns the fraction of cycles spent on iterations that received packets.
+    pub fn utilization(&self) -> f64 {
+        let total = self.busy_cycles + self.idle_cycles;
+        if total == 0 {
+            return 0.0;
+        }
+        self.busy_cycles as f64 / total as f64
+    }
+
+    /// Returns the average number of busy cycles per received packet.
+    pub fn cycles_per_pkt(&self) -> f64 {
+        if self.nb_pkts == 0 {
+            return 0.0;
+        }
+        self.busy_cycles as f64 / self.nb_pkts as f64
+    }
+
+    /// Returns the cycles spent between the earlier counts `earlier` and these.
+    pub fn since(&self, earlier: &CoreCycles) -> CoreCycles {
+        CoreCycles {
+            core: self.core,
+            busy_cycles: self.busy_cycles.saturating_sub(earlier.busy_cycles),
+            idle_cycles: self.idle_cycles.saturating_sub(earlier.idle_cycles),
+            nb_pkts: self.nb_pkts.saturating_sub(earlier.nb_pkts),
+        }
+    }
+}
+
+/// Cycle counters of all RX cores, shared by all copies of a filter.
+#[derive(Debug, Default)]
+pub(crate) struct Cycles {
+    cores: RwLock<BTreeMap<u32, Arc<CycleCounters>>>,
+}
+
+impl Cycles {
+    pub(crate) fn new() -> Self {
+        Cycles::default()
+    }
+
+    /// Returns the cycle counters of `core`, creating them if needed.
+    pub(crate) fn core(&self, core: u32) -> Arc<CycleCounters> {
+        if let Some(counters) = self.cores.read().unwrap().get(&core) {
+            return Arc::clone(counters);
+        }
+        let mut cores = self.cores.write().unwrap();
+        Arc::clone(cores.entry(core).or_default())
+    }
+
+    /// Returns the cycles of each core, in core order.
+    pub(crate) fn stats(&self) -> Vec<CoreCycles> {
+        self.cores
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(core, counters)| CoreCycles {
+                core: *core,
+                busy_cycles: counters.busy_cycles.load(Ordering::Relaxed),
+                idle_cycles: counters.idle_cycles.load(Ordering::Relaxed),
+                nb_pkts: counters.nb_pkts.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,120 @@
+//! Flow direction statistics and asymmetric traffic detection.
+//!
+//! A tap or SPAN session that only mirrors one direction of the traffic, or a port that only
+//! receives one side of asymmetrically routed traffic, silently breaks TCP reassembly and rules
+//! that expect both directions of a flow. When a flow leaves the flow table, the packets tracked
+//! from each of its endpoints with [FilterCtx::track_flow](crate::filter::FilterCtx::track_flow)
+//! classify it as:
+//! - bidirectional, if both endpoints sent packets.
+//! - unidirectional, if all its packets came from one endpoint. The flow is flagged in its
+//!   [FlowSummary](crate::hooks::FlowSummary).
+//! - unclassified, if fewer than `min_packets` packets were recorded.
+//!
+//! Every `interval_secs`, the monitor looks at the flows classified since its previous check. When
+//! there are at least `min_flows` of them and the unidirectional fraction exceeds
+//! `max_unidirectional` (see [FlowDirectionConfig](crate::config::FlowDirectionConfig)), it warns
+//! of a likely tap or routing issue, and again once the fraction falls back. Some traffic is
+//! legitimately one-way, e.g. syslog or flow export, so the threshold should sit above its usual
+//! share.
+//!
+//! ## Example
+//! ```toml
+//! [flow_directions]
+//!     min_packets = 4
+//!     min_flows = 1000
+//!     max_unidirectional = 0.5
+//! ```
+
+use crate::config::FlowDirectionConfig;
+use crate::hooks::FlowDirection;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+/// Number of ended flows of each direction class.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DirectionStats {
+    /// Flows both endpoints sent packets on.
+    pub nb_bidirectional: u64,
+    /// Flows only one endpoint sent packets on.
+    pub nb_unidirectional: u64,
+    /// Flows with too few recorded packets to be classified.
+    pub nb_unclassified: u64,
+}
+
+impl DirectionStats {
+    /// Returns the number of classified flows.
+    pub fn nb_classified(&self) -> u64 {
+        self.nb_bidirectional + self.nb_unidirectional
+    }
+
+    /// Returns the fraction of the classified flows that are unidirectional, `0` if none are
+    /// classified.
+    pub fn unidirectional_ratio(&self) -> f64 {
+        match self.nb_classified() {
+            0 => 0.0,
+            nb_classified => self.nb_unidirectional as f64 / nb_classified as f64,
+        }
+    }
+
+    /// Returns the flows that ended since `prev`, an earlier snapshot.
+    pub fn since(&self, prev: &DirectionStats) -> DirectionStats {
+        DirectionStats {
+            nb_bidirectional: self.nb_bidirectional.saturating_sub(prev.nb_bidirectional),
+            nb_unidirectional: self
+                .nb_unidirectional
+                .saturating_sub(prev.nb_unidirectional),
+            nb_unclassified: self.nb_unclassified.saturating_sub(prev.nb_unclassified),
+        }
+    }
+}
+
+/// Direction counters of the ended flows, shared by all copies of a filter.
+#[derive(Debug)]
+pub(crate) struct FlowDirections {
+    min_packets: AtomicU64,
+    nb_bidirectional: AtomicU64,
+    nb_unidirectional: AtomicU64,
+    nb_unclassified: AtomicU64,
+}
+
+impl FlowDirections {
+    pub(crate) fn new() -> Self {
+        FlowDirections {
+            min_packets: AtomicU64::new(FlowDirectionConfig::default().min_packets),
+            nb_bidirectional: AtomicU64::new(0),
+            nb_unidirectional: AtomicU64::new(0),
+            nb_unclassified: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn configure(&self, config: &FlowDirectionConfig) {
+        self.min_packets
+            .store(config.min_packets, Ordering::Relaxed);
+    }
+
+    /// Counts an ended flow whose endpoints sent `directions`. Returns whether the flow is
+    /// unidirectional.
+    pub(crate) fn classify(&self, directions: &[FlowDirection; 2]) -> bool {
+        let nb_pkts = directions[0].nb_pkts + directions[1].nb_pkts;
+        if nb_pkts < self.min_packets.load(Ordering::Relaxed).max(1) {
+            self.nb_unclassified.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let unidirectional = directions.iter().any(|direction| direction.nb_pkts == 0);
+        match unidirectional {
+            true => self.nb_unidirectional.fetch_add(1, Ordering::Relaxed),
+            false => self.nb_bidirectional.fetch_add(1, Ordering::Relaxed),
+        };
+        unidirectional
+    }
+
+    pub(crate) fn stats(&self) -> DirectionStats {
+        DirectionStats {
+            nb_bidirectional: self.nb_bidirectional.load(Ordering::Relaxed),
+            nb_unidirectional: self.nb_unidirectional.load(Ordering::Relaxed),
+            nb_unclassified: self.nb_unclassified.load(Ordering::Relaxed),
+        }
+    }
+}
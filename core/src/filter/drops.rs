@@ -0,0 +1,227 @@
+//! Per-core drop reason accounting.
+//!
+//! Every packet that is received but not inspected, because it could not be parsed or nests too
+//! many tunnels (see [tunnels](crate::protocols::parser#tunnels)), had a bad checksum (see
+//! [checksum](crate::filter::checksum)), was dropped by a
+//! [pipeline stage](crate::subscription::pipeline), was beyond the scan depth of its flow, belongs
+//! to a flow cleared by the [verdict cache](crate::filter::cache) or to a flow of an application
+//! protocol that is not scanned, is excluded by the [VLAN policy](crate::filter::vlan), was
+//! received while [paused](crate::filter::pause), or did not fit in the ring of its
+//! [software RSS](crate::lcore::handoff) worker, is
+//! counted against a [DropReason](DropReason) on the core that received it. The counters are
+//! aggregated by the monitor, which displays them and exports them along with the other runtime
+//! statistics.
+//!
+//! Parsing errors returned by [L4Context::new](crate::protocols::layer4::L4Context::new) carry a
+//! [ParseError](crate::protocols::parser::ParseError), whose drop reason can be recovered with
+//! [DropReason::of](DropReason::of). Callbacks that parse packets themselves should use
+//! [FilterCtx::parse_l4](crate::filter::FilterCtx::parse_l4) or report failures with
+//! [FilterCtx::record_drop](crate::filter::FilterCtx::record_drop).
+
+use crate::protocols::parser::ParseError;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use thiserror::Error;
+
+/// Reason a packet was not inspected.
+#[derive(Error, Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DropReason {
+    #[error("Not Ethernet")]
+    NotEthernet,
+
+    #[error("Not IP")]
+    NotIp,
+
+    #[error("Not TCP or UDP")]
+    NotTcpOrUdp,
+
+    #[error("Malformed Packet")]
+    Malformed,
+
+    #[error("Bad checksum")]
+    BadChecksum,
+
+    #[error("Dropped by a pipeline stage")]
+    Stage,
+
+    #[error("Beyond scan depth")]
+    ScanDepth,
+
+    #[error("Cleared by verdict cache")]
+    VerdictCache,
+
+    #[error("Skipped by classification")]
+    Classified,
+
+    #[error("Excluded by VLAN policy")]
+    VlanPolicy,
+
+    #[error("Outside payload length gates")]
+    PayloadLength,
+
+    #[error("Beyond decapsulation limits")]
+    DecapLimit,
+
+    #[error("Received while paused")]
+    Paused,
+
+    #[error("Handoff ring full")]
+    HandoffFull,
+
+    #[error("Other")]
+    Other,
+}
+
+/// Number of drop reasons.
+const NB_REASONS: usize = 15;
+
+impl DropReason {
+    /// All drop reasons, in counter order.
+    pub const ALL: [DropReason; NB_REASONS] = [
+        DropReason::NotEthernet,
+        DropReason::NotIp,
+        DropReason::NotTcpOrUdp,
+        DropReason::Malformed,
+        DropReason::BadChecksum,
+        DropReason::Stage,
+        DropReason::ScanDepth,
+        DropReason::VerdictCache,
+        DropReason::Classified,
+        DropReason::VlanPolicy,
+        DropReason::PayloadLength,
+        DropReason::DecapLimit,
+        DropReason::Paused,
+        DropReason::HandoffFull,
+        DropReason::Other,
+    ];
+
+    /// Returns the drop reason carried by `error`, either directly or as a
+    /// [ParseError](ParseError), `Other` if it does not carry one.
+    pub fn of(error: &anyhow::Error) -> DropReason {
+        if let Some(reason) = error.downcast_ref::<DropReason>() {
+            return *reason;
+        }
+        ParseError::of(error)
+            .map(|error| error.drop_reason())
+            .unwrap_or(DropReason::Other)
+    }
+
+    /// Returns the name of the reason, as used in logs and exported statistics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DropReason::NotEthernet => "not_ethernet",
+            DropReason::NotIp => "not_ip",
+            DropReason::NotTcpOrUdp => "not_tcp_or_udp",
+            DropReason::Malformed => "malformed",
+            DropReason::BadChecksum => "bad_checksum",
+            DropReason::Stage => "stage",
+            DropReason::ScanDepth => "scan_depth",
+            DropReason::VerdictCache => "verdict_cache",
+            DropReason::Classified => "classified",
+            DropReason::VlanPolicy => "vlan_policy",
+            DropReason::PayloadLength => "payload_length",
+            DropReason::DecapLimit => "decap_limit",
+            DropReason::Paused => "paused",
+            DropReason::HandoffFull => "handoff_full",
+            DropReason::Other => "other",
+        }
+    }
+}
+
+/// Drop counts by reason.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DropCounts([u64; NB_REASONS]);
+
+impl DropCounts {
+    /// Returns the number of packets dropped for `reason`.
+    pub fn get(&self, reason: DropReason) -> u64 {
+        self.0[reason as usize]
+    }
+
+    /// Returns the number of packets dropped for any reason.
+    pub fn total(&self) -> u64 {
+        self.0.iter().sum()
+    }
+
+    /// Returns the non-zero counts, in reason order.
+    pub fn iter(&self) -> impl Iterator<Item = (DropReason, u64)> + '_ {
+        DropReason::ALL
+            .iter()
+            .map(|reason| (*reason, self.get(*reason)))
+            .filter(|(_, count)| *count > 0)
+    }
+
+    /// Adds `other` to these counts.
+    pub fn add(&mut self, other: &DropCounts) {
+        for (count, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *count += other;
+        }
+    }
+}
+
+/// Drop counters of a single core.
+#[derive(Debug, Default)]
+pub(crate) struct DropCounters([AtomicU64; NB_REASONS]);
+
+impl DropCounters {
+    #[inline]
+    pub(crate) fn record(&self, reason: DropReason) {
+        self.0[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn counts(&self) -> DropCounts {
+        let mut counts = DropCounts::default();
+        for (count, counter) in counts.0.iter_mut().zip(self.0.iter()) {
+            *count = counter.load(Ordering::Relaxed);
+        }
+        counts
+    }
+}
+
+/// Registry of the drop counters of all cores.
+#[derive(Debug, Default)]
+pub(crate) struct Drops {
+    cores: RwLock<BTreeMap<u32, Arc<DropCounters>>>,
+    /// Counters of contexts that are not attached to a core.
+    unattached: Arc<DropCounters>,
+}
+
+impl Drops {
+    pub(crate) fn new() -> Self {
+        Drops::default()
+    }
+
+    /// Returns the drop counters of `core`, creating them if needed.
+    pub(crate) fn core(&self, core: u32) -> Arc<DropCounters> {
+        if let Some(counters) = self.cores.read().unwrap().get(&core) {
+            return Arc::clone(counters);
+        }
+        let mut cores = self.cores.write().unwrap();
+        Arc::clone(cores.entry(core).or_default())
+    }
+
+    /// Returns the counters of contexts that are not attached to a core.
+    pub(crate) fn unattached(&self) -> Arc<DropCounters> {
+        Arc::clone(&self.unattached)
+    }
+
+    /// Returns the drop counts of each core. Drops recorded by contexts that are not attached to a
+    /// core are reported under `None`, if any.
+    pub(crate) fn stats(&self) -> BTreeMap<Option<u32>, DropCounts> {
+        let mut stats: BTreeMap<_, _> = self
+            .cores
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(core, counters)| (Some(*core), counters.counts()))
+            .collect();
+        let unattached = self.unattached.counts();
+        if unattached.total() > 0 {
+            stats.insert(None, unattached);
+        }
+        stats
+    }
+}
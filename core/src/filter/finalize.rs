@@ -0,0 +1,298 @@
+//! Finalization of rolling capture files.
+//!
+//! Each time the [rolling capture](crate::filter::capture) closes a pcap file, because it is full
+//! or because the runtime stops, a [CaptureFileEntry](CaptureFileEntry) describing the file is
+//! handed to a background thread, so that enrichment (indexing, hashing, upload) can start on the
+//! file without polling the capture directory. The thread first invokes the hooks registered with
+//! [Hooks::on_capture_file](crate::hooks::Hooks::on_capture_file), then, with the
+//! `[capture.finalize]` options (see [FinalizeConfig](crate::config::FinalizeConfig)):
+//! - runs `command` with the entry as JSON on its standard input, and the path of the pcap file in
+//!   the `RETINA_CAPTURE_FILE` environment variable. The command is killed after `timeout_secs`.
+//! - posts the entry as JSON to `webhook`. Only plain `http://` URLs are supported.
+//!
+//! Files are finalized one at a time, in the order they are closed. Files closed while too many
+//! are waiting, and files abandoned on a write error, are not finalized. Failed commands and
+//! webhooks are logged and counted, and not retried. The oldest files are still removed beyond
+//! `max_files`, so finalizers slower than the file rotation should copy files rather than process
+//! them in place.
+//!
+//! ## Example
+//! ```toml
+//! [capture]
+//!     directory = "/var/lib/retina/capture"
+//!     [capture.finalize]
+//!         command = ["/usr/local/bin/index-pcap", "--json"]
+//!         webhook = "http://indexer.local:8080/captures"
+//! ```
+
+use crate::config::FinalizeConfig;
+use crate::hooks::Hooks;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use serde::Serialize;
+
+/// Number of closed files waiting to be finalized before files are skipped.
+const QUEUE_SIZE: usize = 256;
+
+/// Interval at which a finalize command is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Manifest entry of a closed capture file.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureFileEntry {
+    /// Path of the pcap file.
+    pub path: PathBuf,
+    /// Path of the references of deduplicated packets, `None` if no packet of the file was
+    /// deduplicated.
+    pub refs: Option<PathBuf>,
+    /// Sequence number of the file in the capture directory.
+    pub seq: u64,
+    /// Number of packets written.
+    pub nb_packets: u64,
+    /// Size of the pcap file, in bytes.
+    pub size: u64,
+    /// Wall-clock time the file was opened and closed, in nanoseconds since the UNIX epoch (see
+    /// [timebase](crate::timebase)).
+    pub opened_ts: u64,
+    pub closed_ts: u64,
+}
+
+/// Counters of the finalization thread.
+#[derive(Debug, Default)]
+pub(crate) struct FinalizeCounters {
+    pub(crate) nb_finalized: AtomicU64,
+    pub(crate) nb_failed: AtomicU64,
+}
+
+/// Sink of the closed files of a rolling capture.
+#[derive(Debug)]
+pub(crate) struct Finalizer {
+    tx: Sender<CaptureFileEntry>,
+    hooks: Arc<Hooks>,
+    /// Whether a command or a webhook is configured.
+    external: bool,
+    counters: Arc<FinalizeCounters>,
+}
+
+impl Finalizer {
+    /// Starts the finalization thread, running the external actions of `config` if set.
+    pub(crate) fn start(
+        config: Option<&FinalizeConfig>,
+        hooks: Arc<Hooks>,
+        counters: Arc<FinalizeCounters>,
+    ) -> Result<Self> {
+        let actions = match config {
+            Some(config) => Actions::from_config(config)?,
+            None => Actions::default(),
+        };
+        let external = actions.command.is_some() || actions.webhook.is_some();
+        let (tx, rx) = crossbeam_channel::bounded(QUEUE_SIZE);
+        let loop_hooks = Arc::clone(&hooks);
+        let loop_counters = Arc::clone(&counters);
+        thread::Builder::new()
+            .name("retina-finalize".into())
+            .spawn(move || finalize_loop(rx, &actions, &loop_hooks, &loop_counters))?;
+        Ok(Finalizer {
+            tx,
+            hooks,
+            external,
+            counters,
+        })
+    }
+
+    /// Queues `entry` for finalization, unless nothing is registered to finalize it.
+    pub(crate) fn finalize(&self, entry: CaptureFileEntry) {
+        if !self.external && !self.hooks.has_capture_file() {
+            return;
+        }
+        if let Err(error) = self.tx.try_send(entry) {
+            if let TrySendError::Full(entry) = error {
+                log::warn!(
+                    "Too many capture files waiting to be finalized, skipping {}",
+                    entry.path.display()
+                );
+            }
+            self.counters.nb_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// External actions run on each closed file.
+#[derive(Debug, Default)]
+struct Actions {
+    command: Option<Vec<String>>,
+    webhook: Option<Webhook>,
+    timeout: Duration,
+}
+
+impl Actions {
+    fn from_config(config: &FinalizeConfig) -> Result<Self> {
+        if matches!(&config.command, Some(command) if command.is_empty()) {
+            bail!("Empty capture finalize command");
+        }
+        let webhook = config.webhook.as_deref().map(Webhook::parse).transpose()?;
+        Ok(Actions {
+            command: config.command.clone(),
+            webhook,
+            timeout: Duration::from_secs(config.timeout_secs),
+        })
+    }
+}
+
+/// Finalizes closed files until the finalizer is dropped.
+fn finalize_loop(
+    rx: Receiver<CaptureFileEntry>,
+    actions: &Actions,
+    hooks: &Hooks,
+    counters: &FinalizeCounters,
+) {
+    for entry in rx.iter() {
+        hooks.capture_file(&entry);
+        let mut failed = false;
+        let payload = match serde_json::to_vec(&entry) {
+            Ok(payload) => payload,
+            Err(error) => {
+                log::error!("Failed to serialize {}: {}", entry.path.display(), error);
+                counters.nb_failed.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        if let Some(command) = &actions.command {
+            if let Err(error) = run_command(command, &entry, &payload, actions.timeout) {
+                log::warn!(
+                    "Finalize command {} failed on {}: {:#}",
+                    command[0],
+                    entry.path.display(),
+                    error
+                );
+                failed = true;
+            }
+        }
+        if let Some(webhook) = &actions.webhook {
+            if let Err(error) = webhook.post(&payload, actions.timeout) {
+                log::warn!(
+                    "Finalize webhook failed on {}: {:#}",
+                    entry.path.display(),
+                    error
+                );
+                failed = true;
+            }
+        }
+        match failed {
+            true => counters.nb_failed.fetch_add(1, Ordering::Relaxed),
+            false => counters.nb_finalized.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+}
+
+/// Runs `command` with `payload` on its standard input, killing it after `timeout`.
+fn run_command(
+    command: &[String],
+    entry: &CaptureFileEntry,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<()> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .env("RETINA_CAPTURE_FILE", &entry.path)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Commands are free to ignore the entry
+        match stdin.write_all(payload) {
+            Err(error) if error.kind() != io::ErrorKind::BrokenPipe => return Err(error.into()),
+            _ => {}
+        }
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                bail!("{}", status);
+            }
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("timed out after {} s", timeout.as_secs());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// HTTP endpoint the entries are posted to.
+#[derive(Debug)]
+struct Webhook {
+    /// Host and port to connect to.
+    addr: String,
+    /// Host, with the port if set in the URL.
+    host: String,
+    path: String,
+}
+
+impl Webhook {
+    fn parse(url: &str) -> Result<Self> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => bail!("Unsupported webhook URL {}, only http:// is supported", url),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            bail!("Webhook URL {} has no host", url);
+        }
+        let has_port = !host.ends_with(']')
+            && host
+                .rsplit_once(':')
+                .map_or(false, |(_, port)| port.parse::<u16>().is_ok());
+        let addr = match has_port {
+            true => host.to_string(),
+            false => format!("{}:80", host),
+        };
+        Ok(Webhook {
+            addr,
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// Posts `payload` as JSON, and checks for a successful status.
+    fn post(&self, payload: &[u8], timeout: Duration) -> Result<()> {
+        let addr = self
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .with_context(|| format!("{} does not resolve", self.addr))?;
+        let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let header = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            payload.len()
+        );
+        stream.write_all(header.as_bytes())?;
+        stream.write_all(payload)?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => bail!("unexpected response {:?}", status.trim_end()),
+        }
+    }
+}
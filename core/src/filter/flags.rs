@@ -0,0 +1,174 @@
+//! Runtime feature flags.
+//!
+//! Feature flags switch optional code paths on and off without recompiling or restarting. Each
+//! built-in [Flag](Flag) gates a path that is also configured by its own options: the path runs
+//! only if it is configured and its flag is on. Built-in flags are on by default, so that turning
+//! a flag off disables a path without editing its configuration, e.g. to rule out an experimental
+//! path while investigating an issue.
+//!
+//! Flags are set by the `[flags]` table of the runtime configuration, and overridden at runtime
+//! with [FilterCtx::set_flag](crate::filter::FilterCtx::set_flag). Names that are not built-in
+//! flags declare custom flags, which applications read with
+//! [FilterCtx::flag](crate::filter::FilterCtx::flag) to gate their own paths. Flags and their
+//! current values are shown and summarized by the monitor.
+//!
+//! ## Example
+//! ```toml
+//! [flags]
+//!     shadow_rules = false
+//!     my_experiment = true
+//! ```
+//! ```
+//! if filter_ctx.flag("my_experiment") == Some(true) {
+//!     experimental_analysis(&window);
+//! }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+const NB_FLAGS: usize = 3;
+
+/// A built-in feature flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    /// Adaptive scan depth, with the `adaptive` option of
+    /// [ScanConfig](crate::config::ScanConfig). When off, flows are scanned up to the fixed depth.
+    AdaptiveScan,
+    /// Payload deduplication of the [rolling capture](crate::filter::capture#deduplication). When
+    /// off, packets captured from then on are written in full.
+    CaptureDedup,
+    /// Sampling of payloads against the [shadow rule set](crate::filter::shadow).
+    ShadowRules,
+}
+
+impl Flag {
+    pub const ALL: [Flag; NB_FLAGS] = [Flag::AdaptiveScan, Flag::CaptureDedup, Flag::ShadowRules];
+
+    /// Returns the name of the flag in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Flag::AdaptiveScan => "adaptive_scan",
+            Flag::CaptureDedup => "capture_dedup",
+            Flag::ShadowRules => "shadow_rules",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Flag> {
+        Flag::ALL.iter().copied().find(|flag| flag.name() == name)
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Current value of a feature flag.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlagState {
+    pub name: String,
+    pub enabled: bool,
+    /// Whether the flag is a built-in [Flag](Flag), rather than a custom flag.
+    pub builtin: bool,
+    /// Whether the value was set at runtime, rather than by the configuration.
+    pub overridden: bool,
+}
+
+/// Feature flags shared by all copies of a filter.
+#[derive(Debug)]
+pub(crate) struct Flags {
+    builtin: [AtomicBool; NB_FLAGS],
+    custom: RwLock<BTreeMap<String, bool>>,
+    /// Names of the flags set at runtime.
+    overridden: RwLock<BTreeSet<String>>,
+}
+
+impl Flags {
+    pub(crate) fn new() -> Self {
+        Flags {
+            builtin: [(); NB_FLAGS].map(|_| AtomicBool::new(true)),
+            custom: RwLock::new(BTreeMap::new()),
+            overridden: RwLock::new(BTreeSet::new()),
+        }
+    }
+
+    /// Replaces the flags with the `[flags]` table of the runtime configuration, clearing runtime
+    /// overrides.
+    pub(crate) fn configure(&self, config: &BTreeMap<String, bool>) {
+        for flag in Flag::ALL {
+            let enabled = config.get(flag.name()).copied().unwrap_or(true);
+            self.builtin[flag as usize].store(enabled, Ordering::Relaxed);
+        }
+        let custom: BTreeMap<String, bool> = config
+            .iter()
+            .filter(|(name, _)| Flag::from_name(name).is_none())
+            .map(|(name, enabled)| (name.clone(), *enabled))
+            .collect();
+        for (name, enabled) in config.iter() {
+            log::info!("Feature flag {}: {}", name, on_off(*enabled));
+        }
+        *self.custom.write().unwrap() = custom;
+        self.overridden.write().unwrap().clear();
+    }
+
+    /// Returns whether built-in flag `flag` is on.
+    #[inline]
+    pub(crate) fn is_enabled(&self, flag: Flag) -> bool {
+        self.builtin[flag as usize].load(Ordering::Relaxed)
+    }
+
+    /// Returns the value of flag `name`, `None` if it is neither built-in nor configured.
+    pub(crate) fn get(&self, name: &str) -> Option<bool> {
+        match Flag::from_name(name) {
+            Some(flag) => Some(self.is_enabled(flag)),
+            None => self.custom.read().unwrap().get(name).copied(),
+        }
+    }
+
+    /// Sets flag `name`, which must be built-in or configured.
+    pub(crate) fn set(&self, name: &str, enabled: bool) -> Result<()> {
+        match Flag::from_name(name) {
+            Some(flag) => self.builtin[flag as usize].store(enabled, Ordering::Relaxed),
+            None => match self.custom.write().unwrap().get_mut(name) {
+                Some(value) => *value = enabled,
+                None => bail!("Unknown feature flag `{}`", name),
+            },
+        }
+        log::info!("Feature flag {} set {}", name, on_off(enabled));
+        self.overridden.write().unwrap().insert(name.to_string());
+        Ok(())
+    }
+
+    /// Returns the value of each flag, built-in flags first.
+    pub(crate) fn stats(&self) -> Vec<FlagState> {
+        let overridden = self.overridden.read().unwrap();
+        let builtin = Flag::ALL.iter().map(|flag| FlagState {
+            name: flag.name().to_string(),
+            enabled: self.is_enabled(*flag),
+            builtin: true,
+            overridden: overridden.contains(flag.name()),
+        });
+        let custom = self.custom.read().unwrap();
+        let custom = custom.iter().map(|(name, enabled)| FlagState {
+            name: name.clone(),
+            enabled: *enabled,
+            builtin: false,
+            overridden: overridden.contains(name),
+        });
+        builtin.chain(custom).collect()
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    match enabled {
+        true => "on",
+        false => "off",
+    }
+}
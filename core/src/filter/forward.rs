@@ -0,0 +1,679 @@
+//! Streaming of matched packets to remote collectors.
+//!
+//! Sites that must not store packets locally forward them to a central collector instead. Packets
+//! handed to [FilterCtx::forward_packet](crate::filter::FilterCtx::forward_packet), typically the
+//! packets of matching flows, are copied and streamed as pcapng to each collector of the
+//! `[[forward]]` entries of the runtime configuration (see
+//! [ForwardConfig](crate::config::ForwardConfig)), over a TLS connection. Each connection starts a
+//! new pcapng section, with nanosecond timestamps, and packets carry their
+//! [metadata](crate::filter::store).
+//!
+//! Like the [tap](crate::filter::tap), each collector has a background thread: RX cores only copy
+//! the packet and enqueue it without blocking, in one of the
+//! [priority classes](crate::filter::priority) of the queue of the collector, and packets are
+//! dropped and counted when their class is full. When the connection breaks or cannot be
+//! established, the thread retries at the configured interval, doubling it up to a minute. With a
+//! spool directory, packets are buffered to disk in the meantime, up to the configured size, and
+//! sent first once reconnected. Spool files left by a previous run are sent too. Without a spool
+//! directory, or once the spool is full, packets are lost and counted. Packets buffered in a
+//! connection when it breaks are lost without being counted.
+//!
+//! Failed connection attempts, broken connections and spool write errors are reported to the
+//! [storage error hooks](crate::hooks::Hooks::on_storage_error), along with the name of the
+//! collector. Per-collector counters and throughput are reported by the monitor. Requires the
+//! `tls-forward` feature.
+
+use super::priority::{self, Priority, PriorityReceiver, PrioritySender};
+use super::store::{enhanced_packet_block, interface_description, section_header, PacketMeta};
+use super::tap::{self, TapRecord};
+use crate::config::{ForwardConfig, StoragePriorityConfig};
+use crate::hooks::{Hooks, StorageError, StorageTarget};
+use crate::memory::mbuf::Mbuf;
+use crate::timebase;
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use crossbeam_channel::RecvTimeoutError;
+use serde::Serialize;
+
+use self::tls::{TlsConnector, TlsStream};
+
+/// Interval at which the connection and the spool are flushed while no packet is forwarded.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest interval between connection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Timeout of connection attempts and writes.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of the send buffer of a connection.
+const SEND_BUFFER: usize = 65536;
+
+/// Size at which a new spool file is started.
+const SPOOL_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Delivery counters of a collector.
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwardStats {
+    /// Name of the collector.
+    pub name: String,
+    /// Address of the collector, as `host:port`.
+    pub address: String,
+    /// Whether the collector is connected.
+    pub connected: bool,
+    /// Number of packets queued for sending.
+    pub nb_queued: u64,
+    /// Number of packets dropped because their priority class was full.
+    pub nb_dropped: u64,
+    /// Number of packets sent, including spooled packets.
+    pub nb_sent: u64,
+    /// Number of pcapng bytes sent.
+    pub nb_sent_bytes: u64,
+    /// Average throughput to the collector since forwarding started, in bits per second.
+    pub avg_sent_bps: f64,
+    /// Number of packets buffered to disk while disconnected.
+    pub nb_spooled: u64,
+    /// Number of bytes currently buffered to disk.
+    pub spool_bytes: u64,
+    /// Number of packets lost while disconnected.
+    pub nb_lost: u64,
+    /// Number of connections established.
+    pub nb_connects: u64,
+}
+
+/// Counters of a collector thread.
+#[derive(Debug, Default)]
+struct ForwardCounters {
+    connected: AtomicBool,
+    nb_sent: AtomicU64,
+    nb_sent_bytes: AtomicU64,
+    nb_spooled: AtomicU64,
+    spool_bytes: AtomicU64,
+    nb_lost: AtomicU64,
+    nb_connects: AtomicU64,
+}
+
+/// Queue of a collector.
+#[derive(Debug)]
+struct Destination {
+    name: String,
+    address: String,
+    /// Whether the collector only receives the packets of its store target.
+    dedicated: bool,
+    tx: PrioritySender<TapRecord>,
+    snaplen: usize,
+    started: Instant,
+    nb_queued: AtomicU64,
+    nb_dropped: AtomicU64,
+    counters: Arc<ForwardCounters>,
+}
+
+/// Collectors shared by all copies of a filter, forwarding nothing until configured.
+#[derive(Debug, Default)]
+pub(crate) struct Forwarders {
+    destinations: RwLock<Vec<Destination>>,
+    /// Whether any collector is configured, checked before taking the lock.
+    enabled: AtomicBool,
+}
+
+impl Forwarders {
+    pub(crate) fn new() -> Self {
+        Forwarders::default()
+    }
+
+    /// Returns whether any collector is configured.
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Starts a thread per collector of `config`. Collectors named in `dedicated` only receive the
+    /// packets of their store target. Errors are reported to the storage error hooks of `hooks`.
+    pub(crate) fn configure(
+        &self,
+        config: &[ForwardConfig],
+        dedicated: &HashSet<&str>,
+        priority: &StoragePriorityConfig,
+        hooks: Arc<Hooks>,
+    ) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut destinations = vec![];
+        for entry in config.iter() {
+            if !names.insert(entry.name.as_str()) {
+                bail!("Duplicate collector {}", entry.name);
+            }
+            let connector = TlsConnector::new(entry)?;
+            let spool = match &entry.spool_directory {
+                Some(directory) => Some(Spool::open(directory, entry)?),
+                None => None,
+            };
+            let counters = Arc::new(ForwardCounters::default());
+            if let Some(spool) = &spool {
+                counters
+                    .spool_bytes
+                    .store(spool.nb_bytes, Ordering::Relaxed);
+            }
+            let (tx, rx) = priority::channel("forward", entry.queue_size, priority);
+            let link = Link {
+                config: entry.clone(),
+                connector,
+                spool,
+                stream: None,
+                backoff: Duration::ZERO,
+                next_attempt: Instant::now(),
+                counters: Arc::clone(&counters),
+                hooks: Arc::clone(&hooks),
+            };
+            thread::Builder::new()
+                .name("retina-forward".into())
+                .spawn(move || forward_loop(link, rx))?;
+            log::info!("Forwarding packets to {} ({})", entry.name, entry.address);
+            destinations.push(Destination {
+                name: entry.name.clone(),
+                address: entry.address.clone(),
+                dedicated: dedicated.contains(entry.name.as_str()),
+                tx,
+                snaplen: entry.snaplen,
+                started: Instant::now(),
+                nb_queued: AtomicU64::new(0),
+                nb_dropped: AtomicU64::new(0),
+                counters,
+            });
+        }
+        self.enabled
+            .store(!destinations.is_empty(), Ordering::Relaxed);
+        *self.destinations.write().unwrap() = destinations;
+        Ok(())
+    }
+
+    /// Copies `mbuf`, with metadata `meta`, to each collector not dedicated to a store target in
+    /// the class of `priority`, cut at `rule_snaplen` bytes if set, unless the class is full.
+    /// Returns the number of bytes queued to the first collector that queued it, `0` if no
+    /// collector did.
+    #[inline]
+    pub(crate) fn forward(
+        &self,
+        mbuf: &Mbuf,
+        meta: PacketMeta,
+        priority: Priority,
+        rule_snaplen: Option<usize>,
+    ) -> usize {
+        if !self.is_enabled() {
+            return 0;
+        }
+        let ts = Duration::from_nanos(mbuf.timestamp());
+        self.forward_copy(None, priority, |snaplen| {
+            TapRecord::new(ts, mbuf, tap::snaplen(snaplen, rule_snaplen), meta)
+        })
+    }
+
+    /// Queues the copy of a packet returned by `copy` for the snapshot length of each collector
+    /// that is not dedicated to a store target, or only to the collector named `collector` if
+    /// set, like [forward](Self::forward).
+    pub(crate) fn forward_copy(
+        &self,
+        collector: Option<&str>,
+        priority: Priority,
+        copy: impl Fn(usize) -> TapRecord,
+    ) -> usize {
+        if !self.is_enabled() {
+            return 0;
+        }
+        let mut nb_bytes = 0;
+        let destinations = self.destinations.read().unwrap();
+        let selected = destinations.iter().filter(|destination| match collector {
+            Some(name) => destination.name == name,
+            None => !destination.dedicated,
+        });
+        for destination in selected {
+            let record = copy(destination.snaplen);
+            let len = record.data().len();
+            match destination.tx.try_send(record, priority) {
+                true => {
+                    destination.nb_queued.fetch_add(1, Ordering::Relaxed);
+                    if nb_bytes == 0 {
+                        nb_bytes = len;
+                    }
+                }
+                false => {
+                    destination.nb_dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        nb_bytes
+    }
+
+    /// Returns the counters of each collector, in configuration order.
+    pub(crate) fn stats(&self) -> Vec<ForwardStats> {
+        self.destinations
+            .read()
+            .unwrap()
+            .iter()
+            .map(|destination| {
+                let counters = &destination.counters;
+                let nb_sent_bytes = counters.nb_sent_bytes.load(Ordering::Relaxed);
+                let secs = destination.started.elapsed().as_secs_f64();
+                ForwardStats {
+                    name: destination.name.clone(),
+                    address: destination.address.clone(),
+                    connected: counters.connected.load(Ordering::Relaxed),
+                    nb_queued: destination.nb_queued.load(Ordering::Relaxed),
+                    nb_dropped: destination.nb_dropped.load(Ordering::Relaxed),
+                    nb_sent: counters.nb_sent.load(Ordering::Relaxed),
+                    nb_sent_bytes,
+                    avg_sent_bps: match secs > 0.0 {
+                        true => nb_sent_bytes as f64 * 8.0 / secs,
+                        false => 0.0,
+                    },
+                    nb_spooled: counters.nb_spooled.load(Ordering::Relaxed),
+                    spool_bytes: counters.spool_bytes.load(Ordering::Relaxed),
+                    nb_lost: counters.nb_lost.load(Ordering::Relaxed),
+                    nb_connects: counters.nb_connects.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Connection of a collector thread, and the packets buffered while disconnected.
+struct Link {
+    config: ForwardConfig,
+    connector: TlsConnector,
+    spool: Option<Spool>,
+    stream: Option<BufWriter<TlsStream>>,
+    /// Interval before the next attempt if the current one fails.
+    backoff: Duration,
+    next_attempt: Instant,
+    counters: Arc<ForwardCounters>,
+    hooks: Arc<Hooks>,
+}
+
+impl Link {
+    /// Connects to the collector if disconnected and the retry interval elapsed.
+    fn reconnect(&mut self) {
+        if self.stream.is_some() || Instant::now() < self.next_attempt {
+            return;
+        }
+        match self.connect() {
+            Ok(stream) => {
+                log::info!("Connected to collector {}", self.config.name);
+                self.counters.nb_connects.fetch_add(1, Ordering::Relaxed);
+                self.counters.connected.store(true, Ordering::Relaxed);
+                self.backoff = Duration::ZERO;
+                self.stream = Some(stream);
+            }
+            Err(error) => {
+                let initial = Duration::from_secs(self.config.reconnect_secs.max(1));
+                self.backoff = (self.backoff * 2)
+                    .max(initial)
+                    .min(MAX_BACKOFF.max(initial));
+                log::warn!(
+                    "Collector {} connection error, retrying in {} s: {:#}",
+                    self.config.name,
+                    self.backoff.as_secs(),
+                    error
+                );
+                self.report(error);
+                self.next_attempt = Instant::now() + self.backoff;
+            }
+        }
+    }
+
+    /// Opens a connection and a new pcapng section, and sends the spooled packets.
+    fn connect(&mut self) -> Result<BufWriter<TlsStream>> {
+        let mut stream = BufWriter::with_capacity(SEND_BUFFER, self.connector.connect(IO_TIMEOUT)?);
+        stream.write_all(&section_header())?;
+        stream.write_all(&interface_description(self.config.snaplen))?;
+        if let Some(spool) = self.spool.as_mut() {
+            spool.replay(&mut stream, &self.counters)?;
+        }
+        stream.flush()?;
+        Ok(stream)
+    }
+
+    fn disconnect(&mut self, error: io::Error) {
+        log::warn!("Collector {} disconnected: {}", self.config.name, error);
+        self.report(error);
+        self.stream = None;
+        self.counters.connected.store(false, Ordering::Relaxed);
+    }
+
+    /// Sends `record`, or spools it while disconnected.
+    fn send(&mut self, record: &TapRecord) {
+        let block = enhanced_packet_block(record);
+        if let Some(stream) = self.stream.as_mut() {
+            match stream.write_all(&block) {
+                Ok(_) => {
+                    self.counters.nb_sent.fetch_add(1, Ordering::Relaxed);
+                    self.counters
+                        .nb_sent_bytes
+                        .fetch_add(block.len() as u64, Ordering::Relaxed);
+                    return;
+                }
+                Err(error) => self.disconnect(error),
+            }
+        }
+        let spooled = match self.spool.as_mut() {
+            Some(spool) => match spool.push(&block) {
+                Ok(spooled) => spooled,
+                Err(error) => {
+                    log::error!(
+                        "Collector {} spool write error: {}",
+                        self.config.name,
+                        error
+                    );
+                    self.report(error);
+                    false
+                }
+            },
+            None => false,
+        };
+        match spooled {
+            true => {
+                self.counters.nb_spooled.fetch_add(1, Ordering::Relaxed);
+                self.counters
+                    .spool_bytes
+                    .fetch_add(block.len() as u64, Ordering::Relaxed);
+            }
+            false => {
+                self.counters.nb_lost.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(Err(error)) = self.stream.as_mut().map(|stream| stream.flush()) {
+            self.disconnect(error);
+        }
+        if let Some(Err(error)) = self.spool.as_mut().map(|spool| spool.flush()) {
+            log::error!(
+                "Collector {} spool write error: {}",
+                self.config.name,
+                error
+            );
+            self.report(error);
+        }
+    }
+
+    /// Reports `error` to the storage error hooks.
+    fn report(&self, error: impl fmt::Display) {
+        let error = StorageError::new(StorageTarget::Forward, &self.config.name, error);
+        self.hooks.storage_error(&error);
+    }
+}
+
+/// Sends queued packets to the collector of `link`. Returns when all senders are dropped.
+fn forward_loop(mut link: Link, mut rx: PriorityReceiver<TapRecord>) {
+    loop {
+        link.reconnect();
+        match rx.recv_timeout(FLUSH_INTERVAL) {
+            Ok(record) => link.send(&record),
+            Err(RecvTimeoutError::Timeout) => link.flush(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    link.flush();
+}
+
+/// Packets buffered to disk while the collector is unreachable, as a sequence of files of pcapng
+/// enhanced packet blocks.
+struct Spool {
+    directory: PathBuf,
+    /// Prefix of the spool files of the collector.
+    prefix: String,
+    max_bytes: u64,
+    /// Spool files and their size, oldest first, the last one being written.
+    files: VecDeque<(PathBuf, u64)>,
+    current: Option<BufWriter<File>>,
+    nb_bytes: u64,
+}
+
+impl Spool {
+    /// Opens the spool of the collector of `config` in `directory`, with the files left by a
+    /// previous run.
+    fn open(directory: &str, config: &ForwardConfig) -> Result<Self> {
+        fs::create_dir_all(directory)?;
+        let prefix = format!("{}-", config.name);
+        let mut files = vec![];
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(&prefix) && name.ends_with(".spool") {
+                files.push((entry.path(), entry.metadata()?.len()));
+            }
+        }
+        files.sort();
+        let nb_bytes = files.iter().map(|(_, size)| size).sum();
+        if !files.is_empty() {
+            log::info!(
+                "Collector {} has {} bytes spooled by a previous run",
+                config.name,
+                nb_bytes
+            );
+        }
+        Ok(Spool {
+            directory: PathBuf::from(directory),
+            prefix,
+            max_bytes: config.spool_max_bytes,
+            files: files.into(),
+            current: None,
+            nb_bytes,
+        })
+    }
+
+    /// Appends `block` to the spool, unless it is full. Returns whether `block` was appended.
+    fn push(&mut self, block: &[u8]) -> io::Result<bool> {
+        if self.nb_bytes + block.len() as u64 > self.max_bytes {
+            return Ok(false);
+        }
+        let full = self
+            .files
+            .back()
+            .map_or(true, |(_, size)| *size >= SPOOL_FILE_SIZE);
+        if self.current.is_none() || full {
+            self.flush()?;
+            // Names sort in order of creation
+            let name = format!("{}{:020}.spool", self.prefix, timebase::now_ns());
+            let path = self.directory.join(name);
+            self.current = Some(BufWriter::new(File::create(&path)?));
+            self.files.push_back((path, 0));
+        }
+        if let (Some(current), Some((_, size))) = (self.current.as_mut(), self.files.back_mut()) {
+            current.write_all(block)?;
+            *size += block.len() as u64;
+            self.nb_bytes += block.len() as u64;
+        }
+        Ok(true)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.current.as_mut() {
+            Some(current) => current.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the spooled packets to `stream`, oldest first, removing each file once sent. Blocks
+    /// cut short by a crash are skipped.
+    fn replay(&mut self, stream: &mut impl Write, counters: &ForwardCounters) -> io::Result<()> {
+        self.flush()?;
+        self.current = None;
+        while let Some((path, size)) = self.files.front() {
+            let blocks = fs::read(path)?;
+            let (len, nb_blocks) = complete_blocks(&blocks);
+            stream.write_all(&blocks[..len])?;
+            fs::remove_file(path)?;
+            counters.nb_sent.fetch_add(nb_blocks, Ordering::Relaxed);
+            counters
+                .nb_sent_bytes
+                .fetch_add(len as u64, Ordering::Relaxed);
+            self.nb_bytes = self.nb_bytes.saturating_sub(*size);
+            counters.spool_bytes.store(self.nb_bytes, Ordering::Relaxed);
+            self.files.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// Returns the length and the number of the complete pcapng blocks at the start of `blocks`.
+fn complete_blocks(blocks: &[u8]) -> (usize, u64) {
+    let mut len = 0;
+    let mut nb_blocks = 0;
+    while blocks.len() - len >= 12 {
+        let mut total = [0; 4];
+        total.copy_from_slice(&blocks[len + 4..len + 8]);
+        let total = u32::from_le_bytes(total) as usize;
+        if total < 12 || len + total > blocks.len() {
+            break;
+        }
+        len += total;
+        nb_blocks += 1;
+    }
+    (len, nb_blocks)
+}
+
+#[cfg(feature = "tls-forward")]
+mod tls {
+    use crate::config::ForwardConfig;
+
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::net::{TcpStream, ToSocketAddrs};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::{bail, Context, Result};
+    use rustls::{
+        Certificate, ClientConfig, ClientConnection, PrivateKey, RootCertStore, ServerName,
+        StreamOwned,
+    };
+    use rustls_pemfile::Item;
+
+    pub(super) type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+    /// TLS client settings of a collector.
+    pub(super) struct TlsConnector {
+        address: String,
+        server_name: ServerName,
+        config: Arc<ClientConfig>,
+    }
+
+    impl TlsConnector {
+        pub(super) fn new(config: &ForwardConfig) -> Result<Self> {
+            let mut roots = RootCertStore::empty();
+            let (nb_added, _) = roots.add_parsable_certificates(&read_certs(&config.ca_file)?);
+            if nb_added == 0 {
+                bail!("No CA certificate in {}", config.ca_file);
+            }
+            let builder = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots);
+            let tls = match (&config.cert_file, &config.key_file) {
+                (Some(cert_file), Some(key_file)) => builder
+                    .with_client_auth_cert(
+                        read_certs(cert_file)?
+                            .into_iter()
+                            .map(Certificate)
+                            .collect(),
+                        read_key(key_file)?,
+                    )
+                    .with_context(|| format!("Invalid client certificate {}", cert_file))?,
+                (None, None) => builder.with_no_client_auth(),
+                _ => bail!(
+                    "Collector {} needs both cert_file and key_file",
+                    config.name
+                ),
+            };
+            let host = match &config.server_name {
+                Some(server_name) => server_name.as_str(),
+                None => config
+                    .address
+                    .rsplit_once(':')
+                    .map_or(config.address.as_str(), |(host, _)| host)
+                    .trim_start_matches('[')
+                    .trim_end_matches(']'),
+            };
+            let server_name = ServerName::try_from(host)
+                .with_context(|| format!("Invalid collector name {}", host))?;
+            Ok(TlsConnector {
+                address: config.address.clone(),
+                server_name,
+                config: Arc::new(tls),
+            })
+        }
+
+        /// Connects to the collector and completes the TLS handshake.
+        pub(super) fn connect(&self, timeout: Duration) -> Result<TlsStream> {
+            let addr = self
+                .address
+                .to_socket_addrs()?
+                .next()
+                .with_context(|| format!("{} does not resolve", self.address))?;
+            let tcp = TcpStream::connect_timeout(&addr, timeout)?;
+            tcp.set_read_timeout(Some(timeout))?;
+            tcp.set_write_timeout(Some(timeout))?;
+            tcp.set_nodelay(true)?;
+            let conn = ClientConnection::new(Arc::clone(&self.config), self.server_name.clone())?;
+            let mut stream = StreamOwned::new(conn, tcp);
+            // Report certificate errors on connect rather than on the first packet
+            while stream.conn.is_handshaking() {
+                stream.conn.complete_io(&mut stream.sock)?;
+            }
+            Ok(stream)
+        }
+    }
+
+    fn read_certs(path: &str) -> Result<Vec<Vec<u8>>> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+        let mut reader = BufReader::new(file);
+        Ok(rustls_pemfile::certs(&mut reader)?)
+    }
+
+    fn read_key(path: &str) -> Result<PrivateKey> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+        let mut reader = BufReader::new(file);
+        for item in rustls_pemfile::read_all(&mut reader)? {
+            match item {
+                Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => {
+                    return Ok(PrivateKey(key))
+                }
+                _ => {}
+            }
+        }
+        bail!("No private key in {}", path)
+    }
+}
+
+#[cfg(not(feature = "tls-forward"))]
+mod tls {
+    use crate::config::ForwardConfig;
+
+    use std::io;
+    use std::time::Duration;
+
+    use anyhow::{bail, Result};
+
+    pub(super) type TlsStream = io::Sink;
+
+    pub(super) struct TlsConnector;
+
+    impl TlsConnector {
+        pub(super) fn new(_config: &ForwardConfig) -> Result<Self> {
+            bail!("Forwarding to collectors requires the `tls-forward` feature")
+        }
+
+        pub(super) fn connect(&self, _timeout: Duration) -> Result<TlsStream> {
+            bail!("Forwarding to collectors requires the `tls-forward` feature")
+        }
+    }
+}
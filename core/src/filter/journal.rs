@@ -0,0 +1,548 @@
+//! Write-ahead journal of rule updates, control actions and alerts.
+//!
+//! With the `[journal]` options of the runtime configuration (see
+//! [JournalConfig](crate::config::JournalConfig)), every rule set update, control action and alert
+//! is queued for the journal before it takes effect or is published, and appended to
+//! `journal.jsonl` in the journal directory by a background thread, as one JSON record per line
+//! with an increasing sequence number:
+//! ```json
+//! {"seq":41,"ts":1665480000123456789,"kind":"rules","generation":7,"rules":[{"pattern":"evil\\.example\\.com"}]}
+//! {"seq":42,"ts":1665480000223456789,"kind":"control","command":"enable_tap"}
+//! {"seq":43,"ts":1665480000323456789,"kind":"alert","alert":{"ts":1665480000323456789,"flow":[null,"10.0.0.2:443","10.0.0.1:51234",6,null,null,null,null],"offset":0}}
+//! ```
+//!
+//! The RX cores never wait for the journal: alerts queued while the background thread is behind
+//! are dropped and counted (see [JournalStats](JournalStats)). Rule set updates and control
+//! actions wait for room in the queue, so that none is lost.
+//!
+//! Written records survive a crash of the process. Exporters that need at-least-once delivery read
+//! the records they have not acknowledged with
+//! [FilterCtx::journal_replay](crate::filter::FilterCtx::journal_replay) on startup, deliver them,
+//! and acknowledge them with [FilterCtx::ack_journal](crate::filter::FilterCtx::ack_journal). The
+//! acknowledged sequence number is kept in `journal.ack`. If
+//! [restore_rules](crate::config::JournalConfig::restore_rules) is set, the last journaled rule
+//! set is loaded again on initialization.
+//!
+//! When `journal.jsonl` exceeds the configured size, it is rotated to a segment named after its
+//! last sequence number, e.g. `journal-43.jsonl`. Segments are only removed once all their records
+//! are acknowledged, so the journal grows while an exporter falls behind. Records are written to
+//! the OS as they are appended, and only synced to disk with
+//! [sync](crate::config::JournalConfig::sync) set.
+
+use super::rule::Rule;
+use crate::config::JournalConfig;
+use crate::timebase;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+const SEGMENT_PREFIX: &str = "journal-";
+const SEGMENT_SUFFIX: &str = ".jsonl";
+const ACK_FILE: &str = "journal.ack";
+
+/// Number of entries waiting to be written before alerts are dropped.
+const QUEUE_SIZE: usize = 4096;
+
+/// A journaled event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum JournalEntry {
+    /// A rule set update, with the full list of active rules.
+    Rules { generation: u64, rules: Vec<Rule> },
+    /// A control action, with its arguments.
+    Control { command: String },
+    /// A published alert, as sent to alert subscribers.
+    Alert { alert: serde_json::Value },
+}
+
+/// A journal record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalRecord {
+    /// Sequence number, starting at `1`.
+    pub seq: u64,
+    /// UNIX timestamp of the record, in nanoseconds.
+    pub ts: u64,
+    #[serde(flatten)]
+    pub entry: JournalEntry,
+}
+
+/// Journal statistics.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct JournalStats {
+    /// Number of records written.
+    pub nb_written: u64,
+    /// Number of alerts dropped because the journal was behind.
+    pub nb_dropped: u64,
+    /// Number of entries waiting to be written.
+    pub nb_queued: usize,
+}
+
+/// An entry waiting to be written.
+#[derive(Debug)]
+enum Queued {
+    Entry(JournalEntry),
+    /// An alert as serialized for subscribers, parsed back by the background thread.
+    Alert(Vec<u8>),
+}
+
+impl Queued {
+    fn into_entry(self) -> JournalEntry {
+        match self {
+            Queued::Entry(entry) => entry,
+            // Parsing our own serialization back cannot fail
+            Queued::Alert(alert) => JournalEntry::Alert {
+                alert: serde_json::from_slice(&alert).unwrap_or_default(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct JournalCounters {
+    nb_written: AtomicU64,
+    nb_dropped: AtomicU64,
+}
+
+#[derive(Debug)]
+struct JournalFile {
+    directory: PathBuf,
+    file: File,
+    /// Sequence number of the last record.
+    seq: u64,
+    size: u64,
+    max_size: u64,
+}
+
+impl JournalFile {
+    fn append(&mut self, entry: JournalEntry) -> Result<()> {
+        let record = JournalRecord {
+            seq: self.seq + 1,
+            ts: timebase::now_ns(),
+            entry,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(&line)?;
+        self.seq = record.seq;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Moves the current file to a segment named after its last record.
+    fn rotate(&mut self) -> Result<()> {
+        fs::rename(
+            self.directory.join(JOURNAL_FILE),
+            self.directory.join(segment_name(self.seq)),
+        )?;
+        self.file = open_append(&self.directory.join(JOURNAL_FILE))?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Journal shared by all copies of a filter, disabled until configured.
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    tx: RwLock<Option<Sender<Queued>>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+    directory: RwLock<Option<PathBuf>>,
+    enabled: Arc<AtomicBool>,
+    counters: Arc<JournalCounters>,
+}
+
+impl Journal {
+    pub(crate) fn new() -> Self {
+        Journal::default()
+    }
+
+    /// Opens the journal in the configured directory, continuing the sequence numbers of the
+    /// records already there, and starts its background thread.
+    pub(crate) fn configure(&self, config: &JournalConfig) -> Result<()> {
+        self.stop();
+        let directory = PathBuf::from(&config.directory);
+        fs::create_dir_all(&directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+        let acked = read_ack(&directory)?;
+        remove_acked(&directory, acked)?;
+        let seq = last_seq(&directory)?;
+        let path = directory.join(JOURNAL_FILE);
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        log::info!(
+            "Journaling to {} from sequence number {}, {} unacknowledged records",
+            path.display(),
+            seq + 1,
+            seq.saturating_sub(acked)
+        );
+        let file = JournalFile {
+            directory: directory.clone(),
+            file,
+            seq,
+            size,
+            max_size: config.max_size,
+        };
+        let (tx, rx) = crossbeam_channel::bounded(QUEUE_SIZE);
+        let sync = config.sync;
+        let enabled = Arc::clone(&self.enabled);
+        let counters = Arc::clone(&self.counters);
+        let writer = thread::Builder::new()
+            .name("retina-journal".into())
+            .spawn(move || write_loop(rx, file, sync, &enabled, &counters))?;
+        *self.directory.write().unwrap() = Some(directory);
+        *self.tx.write().unwrap() = Some(tx);
+        *self.writer.lock().unwrap() = Some(writer);
+        self.enabled.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Stops the background thread after it wrote the queued entries, if it is running.
+    fn stop(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        self.tx.write().unwrap().take();
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            let _ = writer.join();
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Queues `entry` for the journal, if enabled, waiting for room in the queue. The journal is
+    /// disabled on write errors.
+    pub(crate) fn append(&self, entry: impl FnOnce() -> JournalEntry) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Some(tx) = self.tx.read().unwrap().as_ref() {
+            let _ = tx.send(Queued::Entry(entry()));
+        }
+    }
+
+    /// Queues `alert`, serialized as sent to subscribers, for the journal, if enabled. Dropped and
+    /// counted if the queue is full.
+    #[inline]
+    pub(crate) fn append_alert(&self, alert: &[u8]) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Some(tx) = self.tx.read().unwrap().as_ref() {
+            if tx.try_send(Queued::Alert(alert.to_vec())).is_err() {
+                self.counters.nb_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Records the control action `command`.
+    pub(crate) fn control(&self, command: impl FnOnce() -> String) {
+        self.append(|| JournalEntry::Control { command: command() })
+    }
+
+    /// Returns the journal statistics, `None` if the journal is not configured.
+    pub(crate) fn stats(&self) -> Option<JournalStats> {
+        let tx = self.tx.read().unwrap();
+        let tx = tx.as_ref()?;
+        Some(JournalStats {
+            nb_written: self.counters.nb_written.load(Ordering::Relaxed),
+            nb_dropped: self.counters.nb_dropped.load(Ordering::Relaxed),
+            nb_queued: tx.len(),
+        })
+    }
+
+    fn directory(&self) -> Option<PathBuf> {
+        self.directory.read().unwrap().clone()
+    }
+
+    /// Returns the records after the acknowledged sequence number, in order. Empty if the
+    /// journal is not enabled.
+    pub(crate) fn replay(&self) -> Result<Vec<JournalRecord>> {
+        let directory = match self.directory() {
+            Some(directory) => directory,
+            None => return Ok(vec![]),
+        };
+        let acked = read_ack(&directory)?;
+        let mut records = vec![];
+        visit_records(&directory, acked, |record| {
+            if record.seq > acked {
+                records.push(record);
+            }
+        })?;
+        Ok(records)
+    }
+
+    /// Records that records up to `seq` were delivered, and removes the segments they cover.
+    pub(crate) fn ack(&self, seq: u64) -> Result<()> {
+        let directory = match self.directory() {
+            Some(directory) => directory,
+            None => return Ok(()),
+        };
+        // Written to a temporary file first, so that the acknowledgement is never partial
+        let tmp = directory.join(format!("{}.tmp", ACK_FILE));
+        fs::write(&tmp, seq.to_string())?;
+        fs::rename(&tmp, directory.join(ACK_FILE))?;
+        remove_acked(&directory, seq)
+    }
+
+    /// Returns the rules of the last journaled rule set update, `None` if there is none.
+    pub(crate) fn last_rules(&self) -> Result<Option<Vec<Rule>>> {
+        let directory = match self.directory() {
+            Some(directory) => directory,
+            None => return Ok(None),
+        };
+        let mut last = None;
+        visit_records(&directory, 0, |record| {
+            if let JournalEntry::Rules { rules, .. } = record.entry {
+                last = Some(rules);
+            }
+        })?;
+        Ok(last)
+    }
+}
+
+/// Writes the queued entries to `file` until the journal stops or fails, syncing each batch of
+/// entries to disk if `sync` is set.
+fn write_loop(
+    rx: Receiver<Queued>,
+    mut file: JournalFile,
+    sync: bool,
+    enabled: &AtomicBool,
+    counters: &JournalCounters,
+) {
+    while let Ok(queued) = rx.recv() {
+        if let Err(error) = write_batch(&mut file, queued, &rx, sync, counters) {
+            log::error!("Journal write error, journaling disabled: {:?}", error);
+            enabled.store(false, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// Writes `first` and the entries queued after it to `file`, then syncs it if `sync` is set.
+fn write_batch(
+    file: &mut JournalFile,
+    first: Queued,
+    rx: &Receiver<Queued>,
+    sync: bool,
+    counters: &JournalCounters,
+) -> Result<()> {
+    for queued in std::iter::once(first).chain(rx.try_iter()) {
+        file.append(queued.into_entry())?;
+        counters.nb_written.fetch_add(1, Ordering::Relaxed);
+    }
+    if sync {
+        file.file.sync_data()?;
+    }
+    Ok(())
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// Returns the file name of the segment ending with sequence number `seq`.
+fn segment_name(seq: u64) -> String {
+    format!("{}{}{}", SEGMENT_PREFIX, seq, SEGMENT_SUFFIX)
+}
+
+/// Returns the segments of `directory` with the sequence number they end with, oldest first.
+fn segments(directory: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let seq = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(SEGMENT_PREFIX))
+            .and_then(|name| name.strip_suffix(SEGMENT_SUFFIX))
+            .and_then(|seq| seq.parse().ok());
+        if let Some(seq) = seq {
+            segments.push((seq, entry.path()));
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// Removes the segments of `directory` whose records are all acknowledged by `acked`.
+fn remove_acked(directory: &Path, acked: u64) -> Result<()> {
+    for (seq, path) in segments(directory)? {
+        if seq <= acked {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the sequence number of the last record in `directory`, `0` if there is none.
+fn last_seq(directory: &Path) -> Result<u64> {
+    let mut seq = segments(directory)?.last().map_or(0, |(seq, _)| *seq);
+    visit_file(&directory.join(JOURNAL_FILE), |record| seq = record.seq)?;
+    Ok(seq)
+}
+
+/// Calls `visit` on the records of the segments and current journal file of `directory`, in
+/// order, skipping the segments that end at or before sequence number `after`.
+fn visit_records(directory: &Path, after: u64, mut visit: impl FnMut(JournalRecord)) -> Result<()> {
+    for (seq, path) in segments(directory)? {
+        if seq > after {
+            visit_file(&path, &mut visit)?;
+        }
+    }
+    visit_file(&directory.join(JOURNAL_FILE), visit)
+}
+
+/// Calls `visit` on the records of the journal file `path`, one line at a time. A truncated last
+/// line, left by a crash during a write, is ignored.
+fn visit_file(path: &Path, mut visit: impl FnMut(JournalRecord)) -> Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error.into()),
+    };
+    for line in BufReader::new(file).lines() {
+        match serde_json::from_str::<JournalRecord>(&line?) {
+            Ok(record) => visit(record),
+            Err(error) => log::warn!("Skipped journal record in {}: {}", path.display(), error),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the acknowledged sequence number of the journal in `directory`, `0` if none.
+fn read_ack(directory: &Path) -> Result<u64> {
+    match fs::read_to_string(directory.join(ACK_FILE)) {
+        Ok(ack) => Ok(ack.trim().parse().context("Invalid journal acknowledgement")?),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(0),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    /// Returns an empty scratch directory for the test `name`.
+    fn scratch(name: &str) -> PathBuf {
+        let name = format!("retina-journal-{}-{}", name, process::id());
+        let directory = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn config(directory: &Path, max_size: u64) -> JournalConfig {
+        JournalConfig {
+            directory: directory.display().to_string(),
+            max_size,
+            sync: false,
+            restore_rules: false,
+        }
+    }
+
+    fn control(n: u64) -> JournalEntry {
+        JournalEntry::Control {
+            command: format!("command {}", n),
+        }
+    }
+
+    /// Returns the first sequence numbers of the rotated segments in `directory`.
+    fn segment_seqs(directory: &Path) -> Vec<u64> {
+        segments(directory).unwrap().into_iter().map(|(seq, _)| seq).collect()
+    }
+
+    fn seqs(records: &[JournalRecord]) -> Vec<u64> {
+        records.iter().map(|record| record.seq).collect()
+    }
+
+    #[test]
+    fn rotate() {
+        let directory = scratch("rotate");
+        let mut file = JournalFile {
+            directory: directory.clone(),
+            file: open_append(&directory.join(JOURNAL_FILE)).unwrap(),
+            seq: 0,
+            size: 0,
+            max_size: 1,
+        };
+        for n in 1..=3 {
+            file.append(control(n)).unwrap();
+        }
+        // Every record but the last fills its own segment
+        assert_eq!(segment_seqs(&directory), vec![1, 2]);
+        assert_eq!(last_seq(&directory).unwrap(), 3);
+
+        let mut records = vec![];
+        visit_records(&directory, 0, |record| records.push(record)).unwrap();
+        assert_eq!(seqs(&records), vec![1, 2, 3]);
+        let mut records = vec![];
+        visit_records(&directory, 1, |record| records.push(record)).unwrap();
+        assert_eq!(seqs(&records), vec![2, 3]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn ack_and_replay() {
+        let directory = scratch("ack");
+        let journal = Journal::new();
+        journal.configure(&config(&directory, 1)).unwrap();
+        for n in 1..=4 {
+            journal.append(|| control(n));
+        }
+        journal.stop();
+        assert_eq!(journal.stats().map(|stats| stats.nb_written), None);
+        assert_eq!(seqs(&journal.replay().unwrap()), vec![1, 2, 3, 4]);
+
+        journal.ack(2).unwrap();
+        assert_eq!(read_ack(&directory).unwrap(), 2);
+        assert_eq!(seqs(&journal.replay().unwrap()), vec![3, 4]);
+        assert_eq!(segment_seqs(&directory), vec![3]);
+
+        // A new journal continues the sequence numbers after the unacknowledged records
+        journal.configure(&config(&directory, 1)).unwrap();
+        journal.append(|| control(5));
+        journal.stop();
+        let records = journal.replay().unwrap();
+        assert_eq!(seqs(&records), vec![3, 4, 5]);
+        match &records[2].entry {
+            JournalEntry::Control { command } => assert_eq!(command, "command 5"),
+            entry => panic!("unexpected entry {:?}", entry),
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn truncated_record() {
+        let directory = scratch("truncated");
+        let mut file = JournalFile {
+            directory: directory.clone(),
+            file: open_append(&directory.join(JOURNAL_FILE)).unwrap(),
+            seq: 0,
+            size: 0,
+            max_size: u64::MAX,
+        };
+        file.append(control(1)).unwrap();
+        file.file.write_all(b"{\"seq\":2,\"ts\":").unwrap();
+        assert_eq!(last_seq(&directory).unwrap(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
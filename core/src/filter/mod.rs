@@ -1,58 +1,2373 @@
+pub mod alert;
+pub mod backend;
+pub mod backfill;
+pub mod bundle;
+pub mod cache;
+pub mod capture;
+pub mod checksum;
+pub mod cycles;
+pub mod directions;
+pub mod drops;
+pub mod finalize;
+pub mod flags;
+pub mod forward;
+pub mod journal;
+pub mod neighbors;
+pub mod pause;
+pub mod priority;
+pub mod profile;
+pub mod quota;
+pub mod rates;
+pub mod rewrite;
+pub mod rule;
+pub mod scan;
+pub mod shadow;
+pub mod sources;
+pub mod state;
+pub mod store;
+pub mod table;
+pub mod targets;
+pub mod talkers;
+pub mod throttle;
+pub mod tap;
+pub mod trace;
+pub mod vlan;
+pub mod watch;
+pub mod yara;
+
+use dashmap::try_result::TryResult;
 use dashmap::DashMap;
 
-use crate::protocols::layer4::Flow;
-use std::sync::{Arc, RwLock};
-use std::time::{Instant, Duration};
+use crate::bridge::AsyncBridge;
+use crate::config::{FailedTests, FlowKeyConfig, MatchBackendConfig, RegexConfig, RuntimeConfig};
+use crate::hooks::{FlowDirection, FlowSummary, Hooks};
+use crate::logging;
+use crate::memory::mbuf::Mbuf;
+use crate::output::Output;
+use crate::subscription::{Consumers, Pipeline, ZcFrame};
+use crate::timebase;
+use crate::protocols::app::{self, AppProtocol, PortHints, MAX_IDENTIFY_PAYLOADS};
+use crate::protocols::icmp::IcmpError;
+use crate::protocols::layer4::{Flow, L4Context, PackedFlow};
+use crate::protocols::packet::ethernet::Ethernet;
+use crate::protocols::packet::Packet;
+use crate::protocols::parser::{self, ParseError};
+use crate::utils::hash::{stable_hash, FlowHashState};
+use self::alert::{AlertFanout, SubscriberStats};
+use self::backfill::{Backfill, BackfillRing, BackfillStats};
+use self::bundle::BundleSources;
+use self::cache::{VerdictCache, VerdictCacheStats, VerdictKey};
+use self::capture::{Capture, CaptureStats};
+use self::checksum::{ChecksumCounters, ChecksumStats, Checksums};
+use self::cycles::{CoreCycles, CycleCounters, Cycles};
+use self::directions::{DirectionStats, FlowDirections};
+use self::drops::{DropCounters, DropCounts, DropReason, Drops};
+use self::flags::{Flag, FlagState, Flags};
+use self::forward::{ForwardStats, Forwarders};
+use self::journal::{Journal, JournalEntry, JournalRecord, JournalStats};
+use self::neighbors::{Neighbor, NeighborTable, SeenNeighbors};
+use self::pause::{Pause, PauseMode, PauseStats};
+use self::priority::{ClassStats, Priority};
+use self::profile::{Profiler, RuleCost};
+use self::quota::{QuotaStats, StorageQuotas};
+use self::rates::{FlowRates, RateSettings, RateTracker};
+use self::rewrite::{EgressRewrite, EgressRewrites, PortRewrite, RewriteStats};
+use self::rule::{
+    CompileStats, CoreGenerations, FlowScope, Rule, RuleCount, RuleGenerations, RuleMatches,
+    RuleSet, RuleTestFailure,
+};
+use self::scan::{ScanState, ScanStats};
+use self::shadow::{Shadow, ShadowStats};
+use self::sources::{RuleSourceStats, RuleSources};
+use self::store::PacketMeta;
+use self::table::{FlowCounters, FlowLimits, FlowRemoval, FlowTableStats};
+use self::talkers::{CoreTalkers, TalkerStats, TopTalkers};
+use self::targets::{StoreTargetStats, StoreTargets};
+use self::throttle::{Throttle, ThrottledRule};
+use self::tap::{Tap, TapRecord, TapStats};
+use self::trace::{TraceEvent, TraceRecord, TraceRing, Tracer};
+use self::vlan::{VlanActions, VlanPolicy};
+use self::watch::{RuleFileStats, RuleFiles};
+use self::yara::{Yara, YaraStats};
+use std::cmp;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+use std::mem;
+use std::net::IpAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Instant, Duration, SystemTime};
+use anyhow::{bail, Result};
+use pnet::datalink::MacAddr;
 use regex::bytes::RegexSet;
 
+/// Number of rule set compilations kept in the compile history.
+const MAX_COMPILE_HISTORY: usize = 64;
+/// Maximum number of labels of a flow.
+pub const MAX_FLOW_LABELS: usize = 16;
+/// Maximum length in bytes of the key or the value of a flow label.
+pub const MAX_FLOW_LABEL_LEN: usize = 128;
+
+/// Store target and snapshot length of a flow, see
+/// [store targets](crate::filter::rule#store-targets).
+#[derive(Debug, Clone)]
+struct StoreRoute {
+    /// Priority of the rule that selected the target.
+    priority: i32,
+    /// Name of the target, `None` for the default rolling capture and collectors.
+    target: Option<Box<str>>,
+    /// Snapshot length of the rule, `None` for the configured ones.
+    snaplen: Option<usize>,
+}
+
+/// Per-flow state kept in the flow table.
+#[derive(Debug, Clone)]
+pub(crate) struct FlowState {
+    /// Time the flow was added to the flow table.
+    first_seen: Instant,
+    /// Time the flow was last seen.
+    last_seen: Instant,
+    /// Recorded traffic of each endpoint, in flow key order.
+    directions: [FlowDirection; 2],
+    /// Patterns of the rules that matched, only collected while end-of-flow hooks are registered.
+    matched_rules: Vec<String>,
+    /// Tags of the rules of `matched_rules`.
+    matched_tags: BTreeMap<String, String>,
+    /// Labels set by the callback, see [set_flow_label](FilterCtx::set_flow_label).
+    labels: BTreeMap<String, String>,
+    /// Number of payload bytes of the flow seen by the filter.
+    bytes_seen: usize,
+    /// IPv6 flow label of the last recorded packet, `None` for IPv4 flows.
+    flow_label: Option<u32>,
+    /// Identified application protocol, `None` until identification completes.
+    app: Option<AppProtocol>,
+    /// Number of non-empty payloads checked for an application protocol signature.
+    nb_identify: u8,
+    /// Number of ICMP errors correlated to the flow.
+    nb_icmp_errors: u32,
+    /// Whether a payload of the flow matched.
+    matched: bool,
+    /// Whether the flow skips scanning, cleared by the verdict cache.
+    cached: bool,
+    /// Index in the flow key of the endpoint that sent the first recorded packet.
+    client: Option<usize>,
+    /// Server name requested in the first payload, only read while the verdict cache is enabled.
+    server_name: Option<Box<str>>,
+    /// Whether the flow is scanned whole to sample matches beyond the adaptive scan depth, see
+    /// [scan](crate::filter::scan).
+    sampled: bool,
+    /// Bucketed traffic since the first match, `None` until then or if rates are not tracked.
+    rates: Option<Box<RateTracker>>,
+    /// Sequence numbers of the capture files the first and the last captured packets were queued
+    /// to, `None` if no packet was captured, see [capture](crate::filter::capture).
+    capture_seqs: Option<(u64, u64)>,
+    /// Packets buffered before the first match, `None` if none is, see
+    /// [backfill](crate::filter::backfill).
+    backfill: Option<Box<BackfillRing>>,
+    /// Whether the flow no longer buffers packets, having flushed or dropped its buffer.
+    backfilled: bool,
+    /// Store target and snapshot length of the highest-priority rule that matched, `None` until
+    /// a rule matched, see [store targets](crate::filter::rule#store-targets).
+    store_route: Option<StoreRoute>,
+    /// Flow table counters of the core that added the flow.
+    counters: Arc<FlowCounters>,
+}
+
+impl FlowState {
+    fn new(sampled: bool, counters: Arc<FlowCounters>) -> Self {
+        let now = Instant::now();
+        FlowState {
+            first_seen: now,
+            last_seen: now,
+            directions: [FlowDirection::default(); 2],
+            matched_rules: vec![],
+            matched_tags: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            bytes_seen: 0,
+            flow_label: None,
+            app: None,
+            nb_identify: 0,
+            nb_icmp_errors: 0,
+            matched: false,
+            cached: false,
+            client: None,
+            server_name: None,
+            sampled,
+            rates: None,
+            capture_seqs: None,
+            backfill: None,
+            backfilled: false,
+            store_route: None,
+            counters,
+        }
+    }
+
+    /// Returns the verdict cache key of `flow`, see [cache](crate::filter::cache).
+    fn verdict_key(&self, flow: &Flow) -> VerdictKey {
+        VerdictKey::new(flow, self.client, self.server_name.as_deref())
+    }
+
+    /// Returns the application protocol of `flow`, identifying it from `hints` or `payload` if
+    /// needed.
+    fn identify(&mut self, flow: &Flow, payload: &[u8], hints: &PortHints) -> AppProtocol {
+        if let Some(app) = self.app {
+            return app;
+        }
+        if let Some(app) = hints.get(flow.ports()) {
+            self.app = Some(app);
+            return app;
+        }
+        if payload.is_empty() {
+            return AppProtocol::Unknown;
+        }
+        self.nb_identify += 1;
+        if let Some(app) = app::identify_signature(flow.proto(), payload) {
+            self.app = Some(app);
+        } else if self.nb_identify >= MAX_IDENTIFY_PAYLOADS {
+            self.app = Some(app::identify_port(flow.proto(), flow.ports()));
+        }
+        self.app
+            .unwrap_or_else(|| app::identify_port(flow.proto(), flow.ports()))
+    }
+}
+
+/// Rules a payload matched, see [scoped_matches](FilterCtx::scoped_matches).
+struct PayloadMatch {
+    /// Matched rules, empty unless collected.
+    rules: Vec<Rule>,
+    /// Offset in the payload of the end of the first match to end.
+    end: Option<usize>,
+    /// Span in the payload of the match of the first rule, which alert context is taken around.
+    first: Option<Range<usize>>,
+}
+
+impl PayloadMatch {
+    /// Returns the outcome of `matches`, cloning the matched rules if `collect` is set. `None` if
+    /// no rule matched.
+    fn new(matches: RuleMatches, collect: bool) -> Option<Self> {
+        if matches.rules.is_empty() {
+            return None;
+        }
+        let rules = match collect {
+            true => matches.rules.into_iter().cloned().collect(),
+            false => vec![],
+        };
+        Some(PayloadMatch {
+            rules,
+            end: matches.end,
+            first: matches.first,
+        })
+    }
+}
+
+/// Part of a payload to scan, selected by [flow_scan](FilterCtx::flow_scan).
+struct FlowScan {
+    /// Offset of the payload within its flow.
+    offset: usize,
+    /// End of the part of the payload within the scan depth.
+    end: usize,
+    scope: FlowScope,
+    /// Whether the flow is scanned past the scan depth.
+    sampled: bool,
+}
+
+/// Storage load shedding, set by the monitor under memory pool pressure (see
+/// [MempoolConfig::shed_watermark](crate::config::MempoolConfig::shed_watermark)).
+#[derive(Debug, Default)]
+struct Shedding {
+    active: AtomicBool,
+    /// Number of packet copies to the tap, the rolling capture or collectors skipped while
+    /// shedding.
+    nb_shed: AtomicU64,
+}
+
+/// A flow removed from the flow table, reported once the table locks are released.
+struct RemovedFlow {
+    flow: Flow,
+    /// Verdict cache key the flow clears, if it was scanned without matching, see
+    /// [cache](crate::filter::cache).
+    cleared: Option<VerdictKey>,
+    /// Summary for end-of-flow hooks, `None` if none is registered.
+    summary: Option<FlowSummary>,
+}
+
+impl RemovedFlow {
+    /// Counts the removal of `flow` on the counters of its core and in `directions`. The summary
+    /// lists the files of `capture` holding its captured packets.
+    fn new(
+        flow: Flow,
+        state: &mut FlowState,
+        directions: &FlowDirections,
+        capture: &Capture,
+        has_flow_end: bool,
+    ) -> Self {
+        state.counters.record_removed();
+        let unidirectional = directions.classify(&state.directions);
+        RemovedFlow {
+            flow,
+            cleared: (state.bytes_seen > 0 && !state.matched && !state.cached)
+                .then(|| state.verdict_key(&flow)),
+            summary: has_flow_end.then(|| FlowSummary {
+                flow,
+                duration: state.last_seen.duration_since(state.first_seen),
+                first_ts: timebase::instant_ns(state.first_seen),
+                last_ts: timebase::instant_ns(state.last_seen),
+                directions: state.directions,
+                unidirectional,
+                matched_rules: mem::take(&mut state.matched_rules),
+                matched_tags: mem::take(&mut state.matched_tags),
+                labels: mem::take(&mut state.labels),
+                rates: state.rates.as_ref().map(|rates| rates.rates()),
+                // The last packet may be written after a rollover
+                capture_files: state.capture_seqs.map_or_else(Vec::new, |(first, last)| {
+                    capture.file_paths(first, last + 1)
+                }),
+            }),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct FilterCtx {
-    flows: Arc<DashMap<Flow, Instant>>,
+    flows: Arc<DashMap<PackedFlow, FlowState, FlowHashState>>,
+    flow_limits: Arc<FlowLimits>,
+    /// Direction classes of the flows removed from the flow table.
+    directions: Arc<FlowDirections>,
+    /// Flow table counters of the core this context is attached to.
+    core_flows: Arc<FlowCounters>,
+    flow_hash: FlowHashState,
     timeout: Arc<Duration>,
-    regexes: RwLock<RegexSet>
+    /// Local copy of the shared rule set.
+    rule_set: RwLock<Arc<RuleSet>>,
+    /// Generation of the rule set `rule_set` was copied from.
+    local_generation: AtomicU64,
+    rules: Arc<RwLock<Arc<RuleSet>>>,
+    /// Incremented every time the shared rule set changes.
+    generation: Arc<AtomicU64>,
+    /// Active rule set generation of each core.
+    generations: Arc<CoreGenerations>,
+    /// Active rule set generation of the core this context is attached to.
+    core_generation: Option<Arc<AtomicU64>>,
+    /// RX queue the packets being processed were received on, plus one, `0` if unknown. Not
+    /// shared between copies.
+    rx_queue: AtomicU32,
+    /// Number of payloads checked, to sample shadow rule evaluation. Not shared between copies, so
+    /// that cores do not contend on it.
+    nb_shadow_checked: AtomicU64,
+    /// Neighbors recorded by this copy, see [neighbors](crate::filter::neighbors). Not shared
+    /// between copies.
+    seen_neighbors: Mutex<SeenNeighbors>,
+    /// Serializes rule updates, which compile outside of the `rules` lock.
+    update_lock: Arc<Mutex<()>>,
+    /// Compilation statistics of the last loaded rule sets, oldest first.
+    compiles: Arc<Mutex<VecDeque<CompileStats>>>,
+    /// Rules of the last loaded rule set that failed their test vectors.
+    rule_tests: Arc<Mutex<Vec<RuleTestFailure>>>,
+    flow_key: Arc<RwLock<FlowKeyConfig>>,
+    /// Regex flag defaults of loaded rules.
+    regex: Arc<RwLock<RegexConfig>>,
+    scan: Arc<ScanState>,
+    alerts: Arc<AlertFanout>,
+    journal: Arc<Journal>,
+    tracer: Arc<Tracer>,
+    /// Trace ring buffer of the core this context is attached to.
+    trace: Option<Arc<TraceRing>>,
+    drops: Arc<Drops>,
+    /// Drop counters of the core this context is attached to.
+    core_drops: Arc<DropCounters>,
+    checksums: Arc<Checksums>,
+    /// Checksum counters of the core this context is attached to.
+    core_checksums: Arc<ChecksumCounters>,
+    consumers: Arc<Consumers>,
+    pipeline: Arc<Pipeline>,
+    profiler: Arc<Profiler>,
+    shadow: Arc<Shadow>,
+    throttle: Arc<Throttle>,
+    tap: Arc<Tap>,
+    capture: Arc<Capture>,
+    backfill: Arc<Backfill>,
+    forwarders: Arc<Forwarders>,
+    targets: Arc<StoreTargets>,
+    verdicts: Arc<VerdictCache>,
+    talkers: Arc<TopTalkers>,
+    /// Talker counters of the core this context is attached to.
+    core_talkers: Option<Arc<CoreTalkers>>,
+    cycles: Arc<Cycles>,
+    bundle: Arc<BundleSources>,
+    yara: Arc<Yara>,
+    flags: Arc<Flags>,
+    rewrites: Arc<EgressRewrites>,
+    pause: Arc<Pause>,
+    shedding: Arc<Shedding>,
+    rule_files: Arc<RuleFiles>,
+    rule_sources: Arc<RuleSources>,
+    neighbors: Arc<NeighborTable>,
+    /// Application protocols of server ports.
+    app_ports: Arc<PortHints>,
+    /// Policy of VLAN-tagged and untagged traffic.
+    vlans: Arc<VlanPolicy>,
+    /// Per-tenant storage quotas.
+    quotas: Arc<StorageQuotas>,
+    /// Bucket settings of matched flow rates.
+    rates: Arc<RateSettings>,
+    bridge: Arc<AsyncBridge>,
+    output: Arc<Output>,
+    hooks: Arc<Hooks>
+}
+
+/// Statistics about the rules loaded in a [FilterCtx](FilterCtx).
+#[derive(Debug, Clone, Copy)]
+pub struct RuleStats {
+    /// Number of active rules.
+    pub nb_rules: usize,
+    /// Number of active counting rules, included in `nb_rules`.
+    pub nb_counting: usize,
+    /// Number of active capture rules, included in `nb_rules`.
+    pub nb_capturing: usize,
+    /// Number of rules removed by expiry.
+    pub nb_expired: u64,
+    /// Number of rules disabled by the match rate safeguard, not included in `nb_rules`.
+    pub nb_throttled: usize,
+    /// Number of rules of the last loaded rule set that failed their test vectors.
+    pub nb_failed_tests: usize,
+    /// Number of rules rejected for failing their test vectors, included in `nb_failed_tests`.
+    pub nb_rejected: usize,
+    /// Rule set generation, incremented on every update.
+    pub generation: u64,
+    /// Regex flag defaults of loaded rules.
+    pub regex_defaults: RegexConfig,
+    /// Compilation statistics of the active rule set, `None` until rules are loaded.
+    pub last_compile: Option<CompileStats>,
 }
 
 impl FilterCtx {
     pub fn new(reserve_capacity: usize, timeout: Duration, regexes: RegexSet) -> FilterCtx {
+        let rule_set = Arc::new(RuleSet::from_regexes(regexes));
+        let drops = Arc::new(Drops::new());
+        let checksums = Arc::new(Checksums::new());
+        let flow_hash = FlowHashState::default();
+        let flow_limits = Arc::new(FlowLimits::new());
+        let journal = Arc::new(Journal::new());
+        let bridge = Arc::new(AsyncBridge::new());
         FilterCtx {
-            flows: Arc::new(DashMap::with_capacity(reserve_capacity)),
+            flows: Arc::new(DashMap::with_capacity_and_hasher(reserve_capacity, flow_hash.clone())),
+            core_flows: flow_limits.unattached(),
+            flow_limits,
+            directions: Arc::new(FlowDirections::new()),
+            flow_hash,
             timeout: Arc::new(timeout),
-            regexes: RwLock::new(regexes)
+            rule_set: RwLock::new(Arc::clone(&rule_set)),
+            local_generation: AtomicU64::new(0),
+            rx_queue: AtomicU32::new(0),
+            nb_shadow_checked: AtomicU64::new(0),
+            seen_neighbors: Mutex::new(SeenNeighbors::default()),
+            rules: Arc::new(RwLock::new(rule_set)),
+            generation: Arc::new(AtomicU64::new(0)),
+            generations: Arc::new(CoreGenerations::new()),
+            core_generation: None,
+            update_lock: Arc::new(Mutex::new(())),
+            compiles: Arc::new(Mutex::new(VecDeque::new())),
+            rule_tests: Arc::new(Mutex::new(vec![])),
+            flow_key: Arc::new(RwLock::new(FlowKeyConfig::default())),
+            regex: Arc::new(RwLock::new(RegexConfig::default())),
+            scan: Arc::new(ScanState::new()),
+            alerts: Arc::new(AlertFanout::new(Arc::clone(&journal), Arc::clone(&bridge))),
+            journal,
+            tracer: Arc::new(Tracer::new()),
+            trace: None,
+            core_drops: drops.unattached(),
+            drops,
+            core_checksums: checksums.unattached(),
+            checksums,
+            consumers: Arc::new(Consumers::new()),
+            pipeline: Arc::new(Pipeline::new()),
+            profiler: Arc::new(Profiler::new()),
+            shadow: Arc::new(Shadow::new()),
+            throttle: Arc::new(Throttle::new()),
+            tap: Arc::new(Tap::new()),
+            capture: Arc::new(Capture::new()),
+            backfill: Arc::new(Backfill::new()),
+            forwarders: Arc::new(Forwarders::new()),
+            targets: Arc::new(StoreTargets::new()),
+            verdicts: Arc::new(VerdictCache::new()),
+            talkers: Arc::new(TopTalkers::new()),
+            core_talkers: None,
+            cycles: Arc::new(Cycles::new()),
+            bundle: Arc::new(BundleSources::new()),
+            yara: Arc::new(Yara::new()),
+            flags: Arc::new(Flags::new()),
+            rewrites: Arc::new(EgressRewrites::new()),
+            pause: Arc::new(Pause::new()),
+            shedding: Arc::new(Shedding::default()),
+            rule_files: Arc::new(RuleFiles::new()),
+            rule_sources: Arc::new(RuleSources::new()),
+            neighbors: Arc::new(NeighborTable::new()),
+            app_ports: Arc::new(PortHints::new()),
+            vlans: Arc::new(VlanPolicy::new()),
+            quotas: Arc::new(StorageQuotas::new()),
+            rates: Arc::new(RateSettings::new()),
+            bridge,
+            output: Arc::new(Output::new()),
+            hooks: Arc::new(Hooks::new())
+        }
+    }
+
+    /// Applies the filter options of the runtime configuration. Called by the runtime on
+    /// initialization.
+    pub(crate) fn configure(&self, config: &RuntimeConfig) -> Result<()> {
+        *self.flow_key.write().unwrap() = config.flow_key.clone();
+        *self.regex.write().unwrap() = config.regex;
+        if config.flow_key.hash_seed != self.flow_hash.seed() {
+            if !self.flows.is_empty() {
+                bail!("Cannot change the flow hash seed of a non-empty flow table");
+            }
+            self.flow_hash.set_seed(config.flow_key.hash_seed);
+        }
+        self.flow_limits.configure(&config.flow_table);
+        self.directions.configure(&config.flow_directions);
+        self.flags.configure(&config.flags);
+        self.scan.configure(&config.scan, self.flags.is_enabled(Flag::AdaptiveScan));
+        self.tracer.configure(&config.trace);
+        self.checksums.configure(&config.checksum);
+        self.app_ports.configure(&config.app_ports);
+        self.vlans.configure(&config.vlan_policy)?;
+        self.quotas.configure(&config.storage_quota)?;
+        self.rates.configure(config.flow_rates.as_ref());
+        parser::set_decap_limits(&config.decap);
+        if let Some(match_backend) = &config.match_backend {
+            backend::check(match_backend);
+            self.set_match_backend(*match_backend);
+        }
+        if let Some(yara) = &config.yara {
+            self.yara.configure(yara)?;
+        }
+        if let Some(online) = &config.online {
+            self.rewrites.configure(&online.ports)?;
+        }
+        if let Some(journal) = &config.journal {
+            self.journal.configure(journal)?;
+            if journal.restore_rules {
+                if let Some(rules) = self.journal.last_rules()? {
+                    log::info!("Restoring {} journaled rules", rules.len());
+                    self.load_rules(rules)?;
+                }
+            }
+        }
+        if let Some(alert) = &config.alert {
+            self.alerts.configure(alert)?;
+        }
+        if let Some(profile) = &config.profile {
+            self.profiler.configure(profile);
+            self.update_rule_profiles();
+        }
+        if let Some(shadow) = &config.shadow {
+            self.shadow.configure(shadow, &config.regex)?;
+        }
+        if let Some(throttle) = &config.throttle {
+            self.throttle.configure(throttle);
+        }
+        if let Some(tap) = &config.tap {
+            self.tap
+                .configure(tap, &config.storage_priority, Arc::clone(&self.hooks))?;
+        }
+        if let Some(capture) = &config.capture {
+            self.capture
+                .configure(capture, &config.storage_priority, Arc::clone(&self.hooks))?;
+        }
+        if let Some(backfill) = &config.backfill {
+            self.backfill.configure(backfill)?;
+        }
+        let dedicated: HashSet<&str> = config
+            .store_target
+            .iter()
+            .filter_map(|target| target.collector.as_deref())
+            .collect();
+        self.forwarders.configure(
+            &config.forward,
+            &dedicated,
+            &config.storage_priority,
+            Arc::clone(&self.hooks),
+        )?;
+        self.targets.configure(
+            &config.store_target,
+            &config.forward,
+            &config.storage_priority,
+            &self.hooks,
+        )?;
+        if let Some(pipeline) = &config.pipeline {
+            self.pipeline.configure(pipeline);
+        }
+        if let Some(verdict_cache) = &config.verdict_cache {
+            self.verdicts.configure(verdict_cache);
         }
+        if let Some(top_talkers) = &config.top_talkers {
+            self.talkers.configure(top_talkers);
+        }
+        if let Some(neighbors) = &config.neighbors {
+            self.neighbors.configure(neighbors);
+        }
+        if let Some(rule_watch) = &config.rule_watch {
+            watch::start(self, rule_watch)?;
+        }
+        if let Some(async_bridge) = &config.async_bridge {
+            self.bridge.start(async_bridge, &self.hooks)?;
+        }
+        Ok(())
+    }
+
+    /// Attaches this context to `core`, so that its events are recorded in the trace ring buffer,
+    /// drop counters, checksum counters, talker counters and flow table counters of that core,
+    /// and its rule set generation is reported for that core.
+    pub(crate) fn attach_core(&mut self, core: u32) {
+        let generation = self.local_generation.load(Ordering::Relaxed);
+        self.core_generation = Some(self.generations.core(core, generation));
+        self.trace = Some(self.tracer.ring(core));
+        self.core_flows = self.flow_limits.core(core);
+        self.core_drops = self.drops.core(core);
+        self.core_checksums = self.checksums.core(core);
+        self.core_talkers = Some(self.talkers.core(core));
+    }
+
+    /// Records that the packets about to be processed were received on RX queue `queue`, so that
+    /// it is stored with their [metadata](crate::filter::store).
+    #[inline]
+    pub(crate) fn set_rx_queue(&self, queue: u16) {
+        self.rx_queue.store(queue as u32 + 1, Ordering::Relaxed);
+    }
+
+    /// Returns the metadata stored with `mbuf`.
+    #[inline]
+    fn packet_meta(&self, mbuf: &Mbuf) -> PacketMeta {
+        let queue = match self.rx_queue.load(Ordering::Relaxed) {
+            0 => None,
+            queue => Some((queue - 1) as u16),
+        };
+        PacketMeta::new(mbuf, queue)
+    }
+
+    /// Records the event built by `event` if tracing is enabled for the attached core.
+    #[inline]
+    pub(crate) fn trace(&self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(trace) = &self.trace {
+            trace.record(event);
+        }
+    }
+
+    /// Starts recording trace events on `core`.
+    pub fn enable_trace(&self, core: u32) {
+        self.journal.control(|| format!("enable_trace {}", core));
+        self.tracer.set_enabled(core, true);
+    }
+
+    /// Stops recording trace events on `core`. Recorded events are kept.
+    pub fn disable_trace(&self, core: u32) {
+        self.journal.control(|| format!("disable_trace {}", core));
+        self.tracer.set_enabled(core, false);
+    }
+
+    /// Returns whether trace events are recorded on `core`.
+    pub fn is_tracing(&self, core: u32) -> bool {
+        self.tracer.is_enabled(core)
+    }
+
+    /// Returns the trace records of `core`, oldest first. Does not interrupt packet processing
+    /// beyond briefly locking the ring buffer.
+    pub fn dump_trace(&self, core: u32) -> Vec<TraceRecord> {
+        self.tracer.dump(core)
+    }
+
+    /// Records an error in the trace of the core this context is attached to, e.g. a parsing error
+    /// in the callback.
+    pub fn trace_error(&self, error: impl fmt::Display) {
+        self.trace(|| TraceEvent::Error(error.to_string()));
+    }
+
+    /// Counts a packet dropped for `reason` on the core this context is attached to.
+    #[inline]
+    pub fn record_drop(&self, reason: DropReason) {
+        self.core_drops.record(reason);
+    }
+
+    /// Parses the transport-layer context of `mbuf`, counting the packet as dropped if it cannot
+    /// be parsed. Checksums are checked according to the `[checksum]` options of the runtime
+    /// configuration, see [checksum](crate::filter::checksum). Packets that fail with a
+    /// [ParseError](ParseError) are delivered to the
+    /// [parse error hooks](crate::hooks::Hooks::on_parse_error).
+    pub fn parse_l4(&self, mbuf: &ZcFrame) -> Result<L4Context> {
+        let mut ctx = L4Context::new(mbuf).map_err(|error| {
+            self.record_drop(DropReason::of(&error));
+            if let Some(parse_error) = ParseError::of(&error) {
+                self.hooks.parse_error(&parse_error, mbuf);
+            }
+            error
+        })?;
+        if !self.checksums.check(mbuf, &mut ctx, &self.core_checksums) {
+            self.record_drop(DropReason::BadChecksum);
+            self.hooks.parse_error(&ParseError::BadChecksum, mbuf);
+            bail!(ParseError::BadChecksum);
+        }
+        Ok(ctx)
+    }
+
+    /// Returns the drop counts of each core, see [drops](crate::filter::drops).
+    pub fn drop_stats(&self) -> BTreeMap<Option<u32>, DropCounts> {
+        self.drops.stats()
+    }
+
+    /// Returns the checksum outcome counts of each core, see [checksum](crate::filter::checksum).
+    pub fn checksum_stats(&self) -> BTreeMap<Option<u32>, ChecksumStats> {
+        self.checksums.stats()
+    }
+
+    /// Counts `mbuf` against its talkers if it is sampled, see [talkers](crate::filter::talkers).
+    /// Has no effect unless detection is enabled and this context is attached to a core.
+    #[inline]
+    pub(crate) fn sample_talkers(&self, mbuf: &Mbuf) {
+        let core = match &self.core_talkers {
+            Some(core) if self.talkers.is_sampled(core) => core,
+            _ => return,
+        };
+        if let Ok(ctx) = L4Context::new(mbuf) {
+            self.talkers
+                .record(core, &ctx, self.get_flow(&ctx), mbuf.data_len());
+        }
+    }
+
+    /// Records the sender addresses of the packet with Ethernet header `eth` in the neighbor
+    /// table, see [neighbors](crate::filter::neighbors). Has no effect unless the table is
+    /// enabled.
+    #[inline]
+    pub(crate) fn observe_neighbors(&self, eth: &Ethernet) {
+        self.neighbors.observe(eth, &self.seen_neighbors);
+    }
+
+    /// Returns the MAC address `addr` was last seen with, `None` if it was not seen or the
+    /// neighbor table is not enabled.
+    pub fn neighbor_mac(&self, addr: &IpAddr) -> Option<MacAddr> {
+        self.neighbors.lookup(addr)
+    }
+
+    /// Returns all entries of the neighbor table.
+    pub fn neighbors(&self) -> Vec<Neighbor> {
+        self.neighbors.neighbors()
+    }
+
+    /// Removes neighbor table entries that expired. Called periodically by the main core.
+    pub(crate) fn prune_neighbors(&self) {
+        self.neighbors.prune();
+    }
+
+    /// Returns the largest talkers across all cores, `None` if heavy-hitter detection is not
+    /// enabled.
+    pub fn top_talkers(&self) -> Option<TalkerStats> {
+        self.talkers.stats()
+    }
+
+    /// Returns the cycles spent by each RX core since it started, see
+    /// [cycles](crate::filter::cycles).
+    pub fn core_cycles(&self) -> Vec<CoreCycles> {
+        self.cycles.stats()
+    }
+
+    /// Returns the cycle counters of RX core `core`.
+    pub(crate) fn cycle_counters(&self, core: u32) -> Arc<CycleCounters> {
+        self.cycles.core(core)
+    }
+
+    /// Returns what the [VLAN policy](crate::filter::vlan) does with the flows of `mbuf`. Checked
+    /// by the RX cores right after reception, which drop the packets that are ignored.
+    #[inline]
+    pub fn vlan_actions(&self, mbuf: &Mbuf) -> VlanActions {
+        self.vlans.mbuf_actions(mbuf)
+    }
+
+    /// Parses the Ethernet header of `mbuf` once for the RX cores. `None` if neither the
+    /// [VLAN policy](crate::filter::vlan) nor the [neighbor table](crate::filter::neighbors) is
+    /// enabled, or if `mbuf` is not Ethernet.
+    #[inline]
+    pub(crate) fn rx_ethernet<'a>(&self, mbuf: &'a Mbuf) -> Option<Ethernet<'a>> {
+        if !self.vlans.is_enabled() && !self.neighbors.is_enabled() {
+            return None;
+        }
+        mbuf.parse_to::<Ethernet>().ok()
+    }
+
+    /// Like [vlan_actions](Self::vlan_actions), from the header returned by
+    /// [rx_ethernet](Self::rx_ethernet).
+    #[inline]
+    pub(crate) fn rx_vlan_actions(&self, eth: Option<&Ethernet>) -> VlanActions {
+        self.vlans.eth_actions(eth)
+    }
+
+    /// Copies `mbuf` to the live packet tap (see [tap](crate::filter::tap)), typically for packets
+    /// of matching flows. Has no effect unless a tap is configured and enabled, if the
+    /// [VLAN policy](crate::filter::vlan) does not store the packet, or if its tenant reached its
+    /// [storage quota](crate::filter::quota). Copies are cut at the `snaplen` of the
+    /// highest-priority rule the flow of `mbuf` matched, if set.
+    #[inline]
+    pub fn tap_packet(&self, mbuf: &Mbuf) {
+        self.tap_packet_as(mbuf, Priority::BULK);
+    }
+
+    /// Like [tap_packet](Self::tap_packet), queueing `mbuf` in the class of `priority`, e.g.
+    /// [Priority::ALERT](Priority::ALERT) for the packet that triggered an alert (see
+    /// [priority](crate::filter::priority)).
+    #[inline]
+    pub fn tap_packet_as(&self, mbuf: &Mbuf, priority: Priority) {
+        if self.tap.is_enabled() && self.vlans.mbuf_actions(mbuf).store && !self.shed() {
+            let snaplen = self.mbuf_snaplen(mbuf);
+            self.quotas
+                .store_mbuf(mbuf, || self.tap.tap(mbuf, priority, snaplen));
+        }
+    }
+
+    /// Resumes copying packets to the live packet tap.
+    pub fn enable_tap(&self) {
+        self.journal.control(|| "enable_tap".to_string());
+        self.tap.set_enabled(true);
+    }
+
+    /// Pauses copying packets to the live packet tap.
+    pub fn disable_tap(&self) {
+        self.journal.control(|| "disable_tap".to_string());
+        self.tap.set_enabled(false);
+    }
+
+    /// Returns the delivery counters of the live packet tap, `None` if no tap is configured.
+    pub fn tap_stats(&self) -> Option<TapStats> {
+        self.tap.stats()
+    }
+
+    /// Streams `mbuf` to the remote collectors (see [forward](crate::filter::forward)), typically
+    /// for packets of matching flows. Has no effect unless collectors are configured, if the
+    /// [VLAN policy](crate::filter::vlan) does not store the packet, or if its tenant reached its
+    /// [storage quota](crate::filter::quota). Copies are cut like those of
+    /// [tap_packet](Self::tap_packet).
+    #[inline]
+    pub fn forward_packet(&self, mbuf: &Mbuf) {
+        self.forward_packet_as(mbuf, Priority::BULK);
+    }
+
+    /// Like [forward_packet](Self::forward_packet), queueing `mbuf` in the class of `priority`.
+    #[inline]
+    pub fn forward_packet_as(&self, mbuf: &Mbuf, priority: Priority) {
+        if self.forwarders.is_enabled() && self.vlans.mbuf_actions(mbuf).store && !self.shed() {
+            let meta = self.packet_meta(mbuf);
+            let snaplen = self.mbuf_snaplen(mbuf);
+            self.quotas
+                .store_mbuf(mbuf, || self.forwarders.forward(mbuf, meta, priority, snaplen));
+        }
+    }
+
+    /// Returns the delivery counters of each remote collector, in configuration order.
+    pub fn forward_stats(&self) -> Vec<ForwardStats> {
+        self.forwarders.stats()
+    }
+
+    /// Stores `mbuf`, a packet of `flow`, to the store target of the highest-priority rule the
+    /// flow matched, or to the default rolling capture and collectors if the rule has no
+    /// configured target or the flow did not match, cut at the `snaplen` of that rule if set (see
+    /// [store targets](crate::filter::rule#store-targets)). Has no effect if the
+    /// [VLAN policy](crate::filter::vlan) does not store the flow, or if its tenant reached its
+    /// [storage quota](crate::filter::quota).
+    pub fn store_packet(&self, flow: &Flow, mbuf: &Mbuf) {
+        self.store_packet_as(flow, mbuf, Priority::BULK);
+    }
+
+    /// Like [store_packet](Self::store_packet), queueing `mbuf` in the class of `priority`.
+    pub fn store_packet_as(&self, flow: &Flow, mbuf: &Mbuf, priority: Priority) {
+        if !self.vlans.actions(flow.c_tag()).store || self.shed() {
+            return;
+        }
+        let route = self.store_route(flow);
+        let ts = Duration::from_nanos(mbuf.timestamp());
+        let meta = self.packet_meta(mbuf);
+        let captured = self.store_copy(flow, route.as_ref(), priority, |snaplen| {
+            TapRecord::new(ts, mbuf, snaplen, meta)
+        });
+        if captured {
+            self.record_capture_seq(flow);
+        }
+    }
+
+    /// Returns the routing counters of each store target, in configuration order.
+    pub fn store_target_stats(&self) -> Vec<StoreTargetStats> {
+        self.targets.stats()
+    }
+
+    /// Returns the store target and snapshot length of `flow`, `None` if it did not match or
+    /// neither store targets nor rule snapshot lengths are configured.
+    fn store_route(&self, flow: &Flow) -> Option<StoreRoute> {
+        if !self.targets.is_enabled() && !self.rule_set.read().unwrap().has_snaplens() {
+            return None;
+        }
+        self.flows.get(&PackedFlow::from(flow))?.store_route.clone()
+    }
+
+    /// Returns the snapshot length of the flow of `mbuf`, `None` if it has none or is not
+    /// tracked.
+    fn mbuf_snaplen(&self, mbuf: &Mbuf) -> Option<usize> {
+        if !self.rule_set.read().unwrap().has_snaplens() {
+            return None;
+        }
+        let ctx = L4Context::new(mbuf).ok()?;
+        let state = self.flows.get(&PackedFlow::from(&self.get_flow(&ctx)))?;
+        state.store_route.as_ref()?.snaplen
+    }
+
+    /// Queues the copy of a packet of `flow` returned by `copy` to the store target named
+    /// `target`, cut at `rule_snaplen` bytes if set, under the storage quota of the flow. Returns
+    /// whether the target is configured.
+    fn route_copy(
+        &self,
+        flow: &Flow,
+        target: &str,
+        rule_snaplen: Option<usize>,
+        priority: Priority,
+        copy: impl Fn(usize) -> TapRecord,
+    ) -> bool {
+        let copy = |snaplen| copy(tap::snaplen(snaplen, rule_snaplen));
+        self.targets
+            .route(target, |target| {
+                if let Some(capture) = target.capture() {
+                    self.quotas
+                        .store(flow.c_tag(), || capture.capture_copy(priority, copy));
+                }
+                if let Some(collector) = target.collector() {
+                    self.quotas.store(flow.c_tag(), || {
+                        self.forwarders.forward_copy(Some(collector), priority, copy)
+                    });
+                }
+            })
+            .is_some()
+    }
+
+    /// Queues the copy of a packet of `flow` returned by `copy` to the store target of `route` if
+    /// it is configured, otherwise to the default rolling capture and collectors, cut at the
+    /// snapshot length of `route` if set, under the storage quota of the flow. Returns whether the
+    /// copy was queued to the default rolling capture.
+    fn store_copy(
+        &self,
+        flow: &Flow,
+        route: Option<&StoreRoute>,
+        priority: Priority,
+        copy: impl Fn(usize) -> TapRecord,
+    ) -> bool {
+        let rule_snaplen = route.and_then(|route| route.snaplen);
+        if let Some(target) = route.and_then(|route| route.target.as_deref()) {
+            if self.route_copy(flow, target, rule_snaplen, priority, &copy) {
+                return false;
+            }
+        }
+        let copy = |snaplen| copy(tap::snaplen(snaplen, rule_snaplen));
+        let mut captured = false;
+        if self.capture.is_enabled() {
+            self.quotas.store(flow.c_tag(), || {
+                let nb_bytes = self.capture.capture_copy(priority, copy);
+                captured = nb_bytes > 0;
+                nb_bytes
+            });
+        }
+        if self.forwarders.is_enabled() {
+            self.quotas.store(flow.c_tag(), || {
+                self.forwarders.forward_copy(None, priority, copy)
+            });
+        }
+        captured
+    }
+
+    /// Records that a packet of `flow` was queued to the current file of the rolling capture.
+    fn record_capture_seq(&self, flow: &Flow) {
+        if let Some(mut state) = self.flows.get_mut(&PackedFlow::from(flow)) {
+            let seq = self.capture.file_seq();
+            let first = state.capture_seqs.map_or(seq, |(first, _)| first);
+            state.capture_seqs = Some((first, seq));
+        }
+    }
+
+    /// Writes `mbuf`, a packet of `flow` with payload `payload`, to the rolling capture if the
+    /// payload matches a capture rule (see [capture](crate::filter::capture)), or to the store
+    /// target of the highest-priority capture rule it matches, if configured (see
+    /// [store targets](crate::filter::rule#store-targets)). Returns whether the packet matched.
+    /// Has no effect unless a capture directory or a store target is configured, if the
+    /// [VLAN policy](crate::filter::vlan) does not store the flow, or if its tenant reached its
+    /// [storage quota](crate::filter::quota).
+    pub fn capture_packet(&self, flow: &Flow, payload: &[u8], mbuf: &Mbuf) -> bool {
+        self.capture_packet_as(flow, payload, mbuf, Priority::BULK)
+    }
+
+    /// Like [capture_packet](Self::capture_packet), queueing `mbuf` in the class of `priority`
+    /// (see [priority](crate::filter::priority)).
+    pub fn capture_packet_as(
+        &self,
+        flow: &Flow,
+        payload: &[u8],
+        mbuf: &Mbuf,
+        priority: Priority,
+    ) -> bool {
+        if !(self.capture.is_enabled() || self.targets.is_enabled())
+            || !self.vlans.actions(flow.c_tag()).store
+        {
+            return false;
+        }
+        self.refresh_rules();
+        let (app, flow_label) = match self.flows.get(&PackedFlow::from(flow)) {
+            Some(state) => (state.app, state.flow_label.or(flow.flow_label())),
+            None => (None, flow.flow_label()),
+        };
+        let scope = self.flow_scope(flow, app.unwrap_or(AppProtocol::Unknown), flow_label);
+        let rule = self
+            .rule_set
+            .read()
+            .unwrap()
+            .capture_rule(payload, &scope)
+            .map(|rule| (rule.store_target.clone(), rule.snaplen));
+        let (target, rule_snaplen) = match rule {
+            Some(rule) => rule,
+            None => return false,
+        };
+        if self.shed() {
+            return true;
+        }
+        let meta = self.packet_meta(mbuf);
+        if let Some(target) = &target {
+            let ts = Duration::from_nanos(mbuf.timestamp());
+            let copy = |snaplen| TapRecord::new(ts, mbuf, snaplen, meta);
+            if self.route_copy(flow, target, rule_snaplen, priority, copy) {
+                return true;
+            }
+        }
+        if self.capture.is_enabled() {
+            let dedup = self.flags.is_enabled(Flag::CaptureDedup);
+            let mut queued = false;
+            self.quotas.store(flow.c_tag(), || {
+                let nb_bytes = self
+                    .capture
+                    .capture(mbuf, payload, meta, priority, dedup, rule_snaplen);
+                queued = nb_bytes > 0;
+                nb_bytes
+            });
+            if queued {
+                self.record_capture_seq(flow);
+            }
+        }
+        true
+    }
+
+    /// Returns the delivery counters of the rolling capture, `None` if no capture directory is
+    /// configured.
+    pub fn capture_stats(&self) -> Option<CaptureStats> {
+        self.capture.stats()
+    }
+
+    /// Buffers a copy of `mbuf`, a packet of `flow` that did not match, to be stored ahead of the
+    /// first match of the flow (see [backfill](crate::filter::backfill)). Does nothing unless
+    /// backfill is configured, or if the flow is not in the flow table, already flushed its buffer
+    /// or is no longer scanned.
+    pub fn backfill_packet(&self, flow: &Flow, mbuf: &Mbuf) {
+        if !self.backfill.is_enabled() {
+            return;
+        }
+        let meta = self.packet_meta(mbuf);
+        if let Some(mut state) = self.flows.get_mut(&PackedFlow::from(flow)) {
+            if state.backfilled {
+                return;
+            }
+            let unscanned = state.cached
+                || state.app.map_or(false, |app| self.scan.skips(app))
+                || (!state.sampled && state.bytes_seen >= self.scan.depth());
+            if unscanned {
+                state.backfilled = true;
+                state.backfill = None;
+                return;
+            }
+            self.backfill.buffer(&mut state.backfill, mbuf, meta);
+        }
+    }
+
+    /// Queues the packets buffered for `flow` to the rolling capture and the collectors, and stops
+    /// buffering its packets. Called on the first match of the flow, before its matching packet
+    /// is stored (see [backfill](crate::filter::backfill)).
+    pub fn flush_backfill(&self, flow: &Flow) {
+        if !self.backfill.is_enabled() {
+            return;
+        }
+        let ring = match self.flows.get_mut(&PackedFlow::from(flow)) {
+            Some(mut state) => {
+                state.backfilled = true;
+                state.backfill.take()
+            }
+            None => None,
+        };
+        let records = match ring {
+            Some(mut ring) => self.backfill.flush(&mut ring),
+            None => return,
+        };
+        if !self.vlans.actions(flow.c_tag()).store {
+            return;
+        }
+        let route = self.store_route(flow);
+        let mut captured = false;
+        for record in records.iter() {
+            if !self.shed() {
+                captured |= self.store_copy(flow, route.as_ref(), Priority::BULK, |snaplen| {
+                    record.snapped(snaplen)
+                });
+            }
+        }
+        if captured {
+            self.record_capture_seq(flow);
+        }
+    }
+
+    /// Returns the buffering counters of the backfill, `None` if backfill is not configured.
+    pub fn backfill_stats(&self) -> Option<BackfillStats> {
+        self.backfill.stats()
+    }
+
+    /// Resets the storage usage of `tenant`, so that its packets are stored again (see
+    /// [quota](crate::filter::quota)).
+    pub fn reset_storage_quota(&self, tenant: &str) -> Result<()> {
+        self.journal.control(|| format!("reset_storage_quota {}", tenant));
+        self.quotas.reset(tenant)
+    }
+
+    /// Returns the storage usage of each tenant with a quota.
+    pub fn storage_quota_stats(&self) -> Vec<QuotaStats> {
+        self.quotas.stats()
+    }
+
+    /// Returns the counters of each priority class of the tap and capture queues, empty if neither
+    /// is configured.
+    pub fn storage_class_stats(&self) -> Vec<ClassStats> {
+        let mut stats = self.tap.class_stats();
+        stats.extend(self.capture.class_stats());
+        stats
+    }
+
+    /// Returns the packet consumer registry shared by all copies of this context.
+    pub fn consumers(&self) -> &Consumers {
+        &self.consumers
+    }
+
+    /// Returns the packet processing stage registry shared by all copies of this context, see
+    /// [pipeline](crate::subscription::pipeline).
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    /// Returns the event hook registry shared by all copies of this context.
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    pub(crate) fn hooks_arc(&self) -> Arc<Hooks> {
+        self.hooks.clone()
+    }
+
+    /// Returns the async handler registry shared by all copies of this context, see
+    /// [bridge](crate::bridge).
+    pub fn async_bridge(&self) -> &AsyncBridge {
+        &self.bridge
+    }
+
+    pub(crate) fn async_bridge_arc(&self) -> Arc<AsyncBridge> {
+        self.bridge.clone()
+    }
+
+    /// Returns the output sink registry shared by all copies of this context, see
+    /// [output](crate::output).
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    pub(crate) fn output_arc(&self) -> Arc<Output> {
+        self.output.clone()
+    }
+
+    /// Returns the flow key of `ctx`, built according to the `[flow_key]` options of the runtime
+    /// configuration.
+    pub fn get_flow(&self, ctx: &L4Context) -> Flow {
+        ctx.get_flow_with(&self.flow_key.read().unwrap())
+    }
+
+    /// Returns the stable hash of `flow`, seeded with the `[flow_key]` hash seed. Use this to
+    /// distribute flows reproducibly across runs, e.g. to shard exports.
+    pub fn flow_hash(&self, flow: &Flow) -> u64 {
+        stable_hash(flow, self.flow_hash.seed())
     }
 
     pub fn check_if_existing_flow(&self, flow: &Flow) -> bool {
+        // Flows excluded from tracking by the VLAN policy are never in the table
+        if !self.vlans.actions(flow.c_tag()).track {
+            return false;
+        }
         // This function also updates the timeout when a match is made
-        match self.flows.get_mut(flow) {
-            Some(mut timestamp) => {
-                *timestamp = Instant::now();
+        let packed = PackedFlow::from(flow);
+        let (state, contended) = match self.flows.try_get_mut(&packed) {
+            TryResult::Present(state) => (Some(state), false),
+            TryResult::Absent => (None, false),
+            TryResult::Locked => (self.flows.get_mut(&packed), true),
+        };
+        let existing = match state {
+            Some(mut state) => {
+                state.last_seen = Instant::now();
                 true
             },
             None => false
-        }
+        };
+        self.core_flows.record_lookup(existing, contended);
+        self.trace(|| TraceEvent::FlowLookup { flow: *flow, existing });
+        existing
     }
 
+    /// Adds `flow` to the flow table, unless the table is full (see
+    /// [table](crate::filter::table)) or the [VLAN policy](crate::filter::vlan) does not track the
+    /// flow.
     pub fn add_flow(&self, flow: &Flow) {
-        self.flows.insert(flow.clone(), Instant::now());
+        if !self.vlans.actions(flow.c_tag()).track {
+            return;
+        }
+        self.trace(|| TraceEvent::FlowAdd { flow: *flow });
+        if !self.flow_limits.admit(self.flows.len()) {
+            return;
+        }
+        let state = FlowState::new(self.scan.samples(flow), Arc::clone(&self.core_flows));
+        self.core_flows.record_inserted();
+        match self.flows.insert(PackedFlow::from(flow), state) {
+            Some(replaced) => replaced.counters.record_removed(),
+            None => self.hooks.flow_new(flow),
+        }
     }
 
+    /// Removes `flow` from the flow table, e.g. when a callback sees its TCP FIN or RST, and
+    /// reports it to the expiry hooks. Returns whether the flow was tracked.
+    pub fn remove_flow(&self, flow: &Flow) -> bool {
+        let (_, mut state) = match self.flows.remove(&PackedFlow::from(flow)) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let removed = RemovedFlow::new(
+            *flow,
+            &mut state,
+            &self.directions,
+            &self.capture,
+            self.hooks.has_flow_end(),
+        );
+        self.report_removed(&[removed]);
+        self.flow_limits.record_removed(FlowRemoval::Closed, 1);
+        true
+    }
+
+    /// Removes flows that timed out from the flow table and, if it is full and the eviction
+    /// policy is `oldest`, the least recently seen flows. Matched flows are kept past the flow
+    /// timeout in the sticky class, if configured (see [table](crate::filter::table)).
     pub fn prune_flows(&self) {
-        self.flows.retain(|_, timestamp| timestamp.elapsed() < *self.timeout);
+        let timeout = *self.timeout;
+        let now = Instant::now();
+        let idle = |state: &FlowState| now.saturating_duration_since(state.last_seen);
+        let sticky = self.flow_limits.sticky();
+        let is_candidate = |state: &FlowState| match sticky {
+            Some(sticky) => state.matched && (timeout..sticky.timeout).contains(&idle(state)),
+            None => false,
+        };
+        // Keep the most recently seen candidates, up to the size of the sticky class
+        let mut sticky_cutoff = None;
+        if let Some(sticky) = sticky {
+            let mut last_seen: Vec<Instant> = self
+                .flows
+                .iter()
+                .filter(|entry| is_candidate(entry.value()))
+                .map(|entry| entry.last_seen)
+                .collect();
+            let nb_overflow = last_seen.len().saturating_sub(sticky.max_flows);
+            if nb_overflow > 0 {
+                let (_, cutoff, _) = last_seen.select_nth_unstable(nb_overflow - 1);
+                sticky_cutoff = Some(*cutoff);
+            }
+            self.flow_limits
+                .record_sticky(last_seen.len() - nb_overflow, nb_overflow);
+        }
+        let is_sticky = |state: &FlowState| {
+            is_candidate(state) && sticky_cutoff.map_or(true, |cutoff| state.last_seen > cutoff)
+        };
+        let nb_expired = self.remove_flows(|state| idle(state) >= timeout && !is_sticky(state));
+        self.flow_limits.record_removed(FlowRemoval::Idle, nb_expired);
+        let nb_to_evict = self.flow_limits.nb_to_evict(self.flows.len());
+        if nb_to_evict > 0 {
+            let mut last_seen: Vec<Instant> = self
+                .flows
+                .iter()
+                .filter(|entry| !is_sticky(entry.value()))
+                .map(|entry| entry.last_seen)
+                .collect();
+            if !last_seen.is_empty() {
+                let idx = cmp::min(nb_to_evict, last_seen.len()) - 1;
+                let (_, cutoff, _) = last_seen.select_nth_unstable(idx);
+                let cutoff = *cutoff;
+                let nb_evicted =
+                    self.remove_flows(|state| state.last_seen <= cutoff && !is_sticky(state));
+                self.flow_limits.record_removed(FlowRemoval::Capacity, nb_evicted);
+                log::debug!("Evicted {} flows from the full flow table", nb_evicted);
+            }
+        }
+        let generation = self.generation.load(Ordering::Acquire);
+        self.verdicts.prune(generation);
+    }
+
+    /// Removes the flows whose state satisfies `remove` and reports them to the expiry hooks.
+    /// Returns the number of removed flows.
+    fn remove_flows(&self, mut remove: impl FnMut(&FlowState) -> bool) -> usize {
+        let has_flow_end = self.hooks.has_flow_end();
+        let mut removed = vec![];
+        self.flows.retain(|packed, state| {
+            let keep = !remove(state);
+            if !keep {
+                removed.push(RemovedFlow::new(
+                    Flow::from(packed),
+                    state,
+                    &self.directions,
+                    &self.capture,
+                    has_flow_end,
+                ));
+            }
+            keep
+        });
+        // Hooks are invoked after `retain` releases the shard locks.
+        self.report_removed(&removed);
+        removed.len()
+    }
+
+    /// Reports `removed` flows to the verdict cache and the expiry hooks.
+    fn report_removed(&self, removed: &[RemovedFlow]) {
+        let generation = self.generation.load(Ordering::Acquire);
+        for removed in removed.iter() {
+            if let Some(key) = &removed.cleared {
+                self.verdicts.clear(key.clone(), generation);
+            }
+            self.hooks.flow_expire(&removed.flow);
+        }
+        for summary in removed.iter().filter_map(|removed| removed.summary.as_ref()) {
+            self.hooks.flow_end(summary);
+        }
+    }
+
+    /// Saves the flow table to `path`, see [state](crate::filter::state). Returns the number of
+    /// saved flows.
+    pub fn save_flows(&self, path: &Path) -> Result<usize> {
+        let entries: Vec<(Flow, FlowState)> = self
+            .flows
+            .iter()
+            .map(|entry| (Flow::from(entry.key()), entry.value().clone()))
+            .collect();
+        state::save(path, &entries)?;
+        Ok(entries.len())
+    }
+
+    /// Adds the flows saved in `path` to the flow table, skipping those that timed out since and
+    /// those that do not fit in the table. Flow creation hooks are not invoked. Returns the number
+    /// of restored flows.
+    pub fn restore_flows(&self, path: &Path) -> Result<usize> {
+        let mut nb_restored = 0;
+        for (flow, state) in state::restore(path, *self.timeout, &self.core_flows)? {
+            if !self.flow_limits.admit(self.flows.len()) {
+                break;
+            }
+            self.core_flows.record_inserted();
+            if let Some(replaced) = self.flows.insert(PackedFlow::from(&flow), state) {
+                replaced.counters.record_removed();
+            }
+            nb_restored += 1;
+        }
+        Ok(nb_restored)
+    }
+
+    /// Returns the occupancy statistics of the flow table.
+    pub fn flow_table_stats(&self) -> FlowTableStats {
+        self.flow_limits.stats(self.flows.len())
+    }
+
+    /// Returns the direction classes of the flows removed from the flow table so far (see
+    /// [directions](crate::filter::directions)).
+    pub fn direction_stats(&self) -> DirectionStats {
+        self.directions.stats()
+    }
+
+    /// Returns the flow of the packet parsed as `ctx`, of `nb_bytes` bytes, adding the flow to the
+    /// flow table if it is new and recording the packet in the traffic counters of its sender.
+    /// This is the per-packet flow tracking of the built-in subscriptions, combining
+    /// [check_if_existing_flow](Self::check_if_existing_flow), [add_flow](Self::add_flow) and
+    /// [record_flow_packet](Self::record_flow_packet).
+    pub fn track_flow(&self, ctx: &L4Context, nb_bytes: usize) -> Flow {
+        let flow = self.get_flow(ctx);
+        if !self.check_if_existing_flow(&flow) {
+            self.add_flow(&flow);
+        }
+        self.record_flow_packet(&flow, ctx, nb_bytes);
+        flow
+    }
+
+    /// Records a packet of `flow`, parsed as `ctx`, of `nb_bytes` bytes in the traffic counters of
+    /// its sender reported to end-of-flow hooks. Does nothing if the flow is not in the flow table.
+    /// Only needed by callbacks that track flows without [track_flow](Self::track_flow).
+    pub fn record_flow_packet(&self, flow: &Flow, ctx: &L4Context, nb_bytes: usize) {
+        if let Some(mut state) = self.flows.get_mut(&PackedFlow::from(flow)) {
+            let direction = flow.direction(ctx);
+            state.client.get_or_insert(direction);
+            state.flow_label = ctx.flow_label;
+            state.directions[direction].nb_pkts += 1;
+            state.directions[direction].nb_bytes += nb_bytes as u64;
+            if let Some(rates) = &mut state.rates {
+                rates.record(Instant::now(), nb_bytes);
+            }
+        }
+    }
+
+    /// Returns the throughput metrics of `flow` since its first match, `None` if the flow is not
+    /// in the flow table, has not matched, or rates are not tracked (see
+    /// [rates](crate::filter::rates)).
+    pub fn flow_rates(&self, flow: &Flow) -> Option<FlowRates> {
+        let state = self.flows.get(&PackedFlow::from(flow))?;
+        state.rates.as_ref().map(|rates| rates.rates())
     }
 
     pub fn check_match(&self, payload: &[u8]) -> bool{
-        self.regexes.read().unwrap().is_match(payload)
+        self.check_app_match(payload, AppProtocol::Unknown)
+    }
+
+    /// Checks whether `payload` of a flow identified as `app` matches any rule. Rules scoped to
+    /// other application protocols or to VLAN tags are ignored.
+    pub fn check_app_match(&self, payload: &[u8], app: AppProtocol) -> bool {
+        if !self.admits_len(payload) {
+            return false;
+        }
+        self.check_scoped_match(payload, &FlowScope::app(app))
+    }
+
+    /// Checks whether each of `payloads` matches any rule, like
+    /// [check_match](FilterCtx::check_match) but scanning the payloads in batches, which can be
+    /// offloaded to the configured [matching backend](crate::filter::backend). Payloads outside
+    /// the length gates never match. Like `check_match`, the payloads are not tied to flows, so
+    /// scoped rules are ignored: payloads of tracked flows are checked with
+    /// [check_flow_match_batch](FilterCtx::check_flow_match_batch).
+    pub fn check_match_batch(&self, payloads: &[&[u8]]) -> Vec<bool> {
+        let scope = FlowScope::app(AppProtocol::Unknown);
+        let (idxs, admitted): (Vec<usize>, Vec<&[u8]>) = payloads
+            .iter()
+            .enumerate()
+            .filter(|(_, payload)| self.admits_len(payload))
+            .unzip();
+        let mut matched = vec![false; payloads.len()];
+        for (idx, result) in idxs.iter().zip(self.scoped_match_batch(&admitted, &scope)) {
+            matched[*idx] = result;
+        }
+        matched
+    }
+
+    /// Scans `payloads` of flows with properties `scope` in batches, like
+    /// [check_scoped_match](Self::check_scoped_match) does one at a time.
+    fn scoped_match_batch(&self, payloads: &[&[u8]], scope: &FlowScope) -> Vec<bool> {
+        self.refresh_rules();
+        let rule_set = self.rule_set.read().unwrap();
+        for payload in payloads.iter() {
+            self.profiler.sample(payload, scope);
+        }
+        let matched = rule_set.is_match_batch(payloads, scope);
+        for (payload, result) in payloads.iter().zip(matched.iter()) {
+            self.sample_shadow(payload, scope, *result);
+            rule_set.count(payload, scope);
+        }
+        matched
+    }
+
+    /// Returns whether `payload` is within the scan length gates, recording it as gated
+    /// otherwise.
+    #[inline]
+    fn admits_len(&self, payload: &[u8]) -> bool {
+        if !self.scan.admits(payload.len()) {
+            self.scan.record_gated();
+            self.record_drop(DropReason::PayloadLength);
+            return false;
+        }
+        true
+    }
+
+    /// Compiles the rules loaded from now on for `config` as well.
+    fn set_match_backend(&self, config: MatchBackendConfig) {
+        let mut rules = self.rules.write().unwrap();
+        let mut rule_set = (**rules).clone();
+        rule_set.set_backend(Some(config));
+        *rules = Arc::new(rule_set);
+        *self.rule_set.write().unwrap() = Arc::clone(&rules);
+    }
+
+    fn check_scoped_match(&self, payload: &[u8], scope: &FlowScope) -> bool {
+        self.refresh_rules();
+        self.profiler.sample(payload, scope);
+        let rule_set = self.rule_set.read().unwrap();
+        let matched = rule_set.is_match(payload, scope);
+        rule_set.count(payload, scope);
+        self.sample_shadow(payload, scope, matched);
+        matched
+    }
+
+    /// Evaluates `payload` of a flow with properties `scope` against the shadow rules, if enabled
+    /// and sampled. `matched` is the verdict of the active rules.
+    #[inline]
+    fn sample_shadow(&self, payload: &[u8], scope: &FlowScope, matched: bool) {
+        if self.flags.is_enabled(Flag::ShadowRules) {
+            self.shadow
+                .sample(payload, scope, matched, &self.nb_shadow_checked);
+        }
+    }
+
+    /// Scans `payload` of a flow with properties `scope` like
+    /// [check_scoped_match](Self::check_scoped_match), returning in the same pass the matched
+    /// rules, cloned only if `collect` is set or rules have tags or snapshot lengths, and where
+    /// their matches are.
+    /// `None` if no rule matched.
+    fn scoped_matches(
+        &self,
+        payload: &[u8],
+        scope: &FlowScope,
+        collect: bool,
+    ) -> Option<PayloadMatch> {
+        self.refresh_rules();
+        self.profiler.sample(payload, scope);
+        let rule_set = self.rule_set.read().unwrap();
+        let matches = rule_set.matches(payload, scope);
+        let matched = !matches.rules.is_empty();
+        rule_set.count(payload, scope);
+        self.sample_shadow(payload, scope, matched);
+        PayloadMatch::new(matches, collect || rule_set.has_tags() || rule_set.has_snaplens())
+    }
+
+    /// Returns the rules that `payload` of a flow with properties `scope` matches, found to match
+    /// by a batch scan, without counting it again. The rules are cloned only if `collect` is set
+    /// or rules have tags or snapshot lengths.
+    fn rescan_matches(
+        &self,
+        payload: &[u8],
+        scope: &FlowScope,
+        collect: bool,
+    ) -> Option<PayloadMatch> {
+        let rule_set = self.rule_set.read().unwrap();
+        let matches = rule_set.matches(payload, scope);
+        PayloadMatch::new(matches, collect || rule_set.has_tags() || rule_set.has_snaplens())
+    }
+
+    /// Replaces the log level and per-module filters, e.g. `info,retina_core::filter=debug`. See
+    /// [logging](crate::logging).
+    pub fn set_log_filter(&self, filter: &str) -> Result<()> {
+        self.journal.control(|| format!("set_log_filter {}", filter));
+        logging::set_filter(filter)
+    }
+
+    /// Writes a support bundle with the effective configuration, port information, rule and flow
+    /// table state, recent statistics and log records to a new directory in `directory`, without
+    /// interrupting processing. Returns the path of the bundle, see
+    /// [bundle](crate::filter::bundle).
+    pub fn write_support_bundle(&self, directory: &Path) -> Result<PathBuf> {
+        self.journal.control(|| format!("write_support_bundle {}", directory.display()));
+        self.bundle.write(self, directory)
+    }
+
+    pub(crate) fn bundle_sources(&self) -> &BundleSources {
+        &self.bundle
+    }
+
+    /// Pauses inspection on all RX cores, handling the packets received until
+    /// [resume](Self::resume) according to `mode`. Fails if already paused, see
+    /// [pause](crate::filter::pause).
+    pub fn pause(&self, mode: PauseMode) -> Result<()> {
+        self.journal.control(|| format!("pause {}", mode));
+        self.pause.pause(mode)
+    }
+
+    /// Resumes inspection from the next burst of each RX core. Fails if not paused.
+    pub fn resume(&self) -> Result<()> {
+        self.journal.control(|| "resume".to_string());
+        self.pause.resume()
+    }
+
+    /// Returns the current pause mode, `None` if processing.
+    #[inline]
+    pub fn pause_mode(&self) -> Option<PauseMode> {
+        self.pause.mode()
+    }
+
+    /// Returns the number of pauses and the total paused time.
+    pub fn pause_stats(&self) -> PauseStats {
+        self.pause.stats()
+    }
+
+    pub(crate) fn pause_state(&self) -> &Pause {
+        &self.pause
+    }
+
+    /// Identifies flows to or from `port` as `app` from now on, returning the previous hint of the
+    /// port. Flows that are already identified keep their protocol.
+    pub fn set_app_port(&self, port: u16, app: AppProtocol) -> Option<AppProtocol> {
+        log::info!("Port {} hinted as {}", port, app);
+        self.journal.control(|| format!("set_app_port {} {}", port, app));
+        self.app_ports.set(port, app)
+    }
+
+    /// Removes the hint of `port`, returning it, `None` if the port has no hint.
+    pub fn clear_app_port(&self, port: u16) -> Option<AppProtocol> {
+        self.journal.control(|| format!("clear_app_port {}", port));
+        self.app_ports.remove(port)
+    }
+
+    /// Returns the application protocol hints, by port.
+    pub fn app_ports(&self) -> BTreeMap<u16, AppProtocol> {
+        self.app_ports.list()
+    }
+
+    /// Returns the identified application protocol of `flow`, `None` if the flow is not in the
+    /// flow table or identification has not completed.
+    pub fn flow_app(&self, flow: &Flow) -> Option<AppProtocol> {
+        self.flows.get(&PackedFlow::from(flow)).and_then(|state| state.app)
+    }
+
+    /// Correlates the ICMP error `error` to the flow of its embedded datagram. If that flow is in
+    /// the flow table, it is marked and the error is published to alert subscribers if enabled.
+    /// Returns the flow, `None` if it is not in the flow table.
+    pub fn correlate_icmp_error(&self, error: &IcmpError) -> Option<Flow> {
+        let flow = self.get_flow(&error.original);
+        match self.flows.get_mut(&PackedFlow::from(&flow)) {
+            Some(mut state) => state.nb_icmp_errors += 1,
+            None => return None,
+        }
+        self.alerts.publish_icmp_error(&flow, error);
+        Some(flow)
+    }
+
+    /// Attaches label `key` with `value` to `flow`, replacing any previous value, e.g. a
+    /// classification of the flow by the callback. Labels are kept in the flow table, so that they
+    /// can be read back on later packets with [flow_labels](FilterCtx::flow_labels), and are
+    /// reported in the [flow summary](crate::hooks::FlowSummary::labels) when the flow ends. Fails
+    /// if the flow is not in the flow table, if the key or the value is longer than
+    /// [MAX_FLOW_LABEL_LEN](MAX_FLOW_LABEL_LEN) bytes, or if the flow already has
+    /// [MAX_FLOW_LABELS](MAX_FLOW_LABELS) other labels.
+    pub fn set_flow_label(&self, flow: &Flow, key: &str, value: &str) -> Result<()> {
+        if key.len() > MAX_FLOW_LABEL_LEN || value.len() > MAX_FLOW_LABEL_LEN {
+            bail!("Flow labels are limited to {} bytes", MAX_FLOW_LABEL_LEN);
+        }
+        let mut state = match self.flows.get_mut(&PackedFlow::from(flow)) {
+            Some(state) => state,
+            None => bail!("Flow is not in the flow table"),
+        };
+        if state.labels.len() >= MAX_FLOW_LABELS && !state.labels.contains_key(key) {
+            bail!("Flows are limited to {} labels", MAX_FLOW_LABELS);
+        }
+        state.labels.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// Removes label `key` from `flow`. Returns its value, `None` if the flow has no such label
+    /// or is not in the flow table.
+    pub fn remove_flow_label(&self, flow: &Flow, key: &str) -> Option<String> {
+        self.flows.get_mut(&PackedFlow::from(flow))?.labels.remove(key)
+    }
+
+    /// Returns the value of label `key` of `flow`, `None` if the flow has no such label or is not
+    /// in the flow table.
+    pub fn flow_label(&self, flow: &Flow, key: &str) -> Option<String> {
+        self.flows.get(&PackedFlow::from(flow))?.labels.get(key).cloned()
+    }
+
+    /// Returns the labels of `flow`, `None` if the flow is not in the flow table.
+    pub fn flow_labels(&self, flow: &Flow) -> Option<BTreeMap<String, String>> {
+        self.flows
+            .get(&PackedFlow::from(flow))
+            .map(|state| state.labels.clone())
+    }
+
+    /// Returns the number of ICMP errors correlated to `flow`, `None` if the flow is not in the
+    /// flow table.
+    pub fn flow_icmp_errors(&self, flow: &Flow) -> Option<u32> {
+        self.flows.get(&PackedFlow::from(flow)).map(|state| state.nb_icmp_errors)
+    }
+
+    /// Checks whether `payload`, the next payload of `flow`, matches any rule.
+    ///
+    /// Only the part of the payload within the configured scan depth of the flow is scanned. Flows
+    /// that are not in the flow table are scanned in full. The application protocol of the flow is
+    /// identified from its first payloads and stored in the flow table, and rules scoped to other
+    /// application protocols, VLAN tags or MAC addresses than the flow's are ignored. Flows that
+    /// the [VLAN policy](crate::filter::vlan) does not scan never match.
+    pub fn check_flow_match(&self, flow: &Flow, payload: &[u8]) -> bool {
+        self.flow_matches(flow, payload, false).is_some()
+    }
+
+    /// Like [check_flow_match](Self::check_flow_match), returning the patterns of the rules that
+    /// matched `payload`, `None` if it did not match.
+    pub(crate) fn flow_match_patterns(&self, flow: &Flow, payload: &[u8]) -> Option<Vec<String>> {
+        let rules = self.flow_matches(flow, payload, true)?;
+        Some(rules.into_iter().map(|rule| rule.pattern).collect())
+    }
+
+    /// Checks each of `payloads`, with its flow, like [check_flow_match](Self::check_flow_match)
+    /// does in order, but scanning the payloads of flows with the same scope in batches, which can
+    /// be offloaded to the configured [matching backend](crate::filter::backend). Payloads that
+    /// match are scanned again on the CPU if the matched rules are needed, e.g. for end-of-flow
+    /// summaries or alert context.
+    pub fn check_flow_match_batch(&self, payloads: &[(Flow, &[u8])]) -> Vec<bool> {
+        let scans: Vec<Option<FlowScan>> = payloads
+            .iter()
+            .map(|(flow, payload)| self.flow_scan(flow, payload))
+            .collect();
+        let mut groups: Vec<(FlowScope, Vec<usize>)> = vec![];
+        for (idx, scan) in scans.iter().enumerate() {
+            let scan = match scan {
+                Some(scan) => scan,
+                None => continue,
+            };
+            match groups.iter_mut().find(|(scope, _)| *scope == scan.scope) {
+                Some((_, idxs)) => idxs.push(idx),
+                None => groups.push((scan.scope, vec![idx])),
+            }
+        }
+        let mut found = vec![false; payloads.len()];
+        for (scope, idxs) in groups.iter() {
+            let windows: Vec<&[u8]> = idxs
+                .iter()
+                .filter_map(|idx| Some(&payloads[*idx].1[..scans[*idx].as_ref()?.end]))
+                .collect();
+            for (idx, matched) in idxs.iter().zip(self.scoped_match_batch(&windows, scope)) {
+                found[*idx] = matched;
+            }
+        }
+        let needs_rules = self.needs_rules();
+        payloads
+            .iter()
+            .zip(scans)
+            .zip(found)
+            .map(|(((flow, payload), scan), found)| {
+                let scan = match scan {
+                    Some(scan) => scan,
+                    None => return false,
+                };
+                let found = match found {
+                    true => self.rescan_matches(&payload[..scan.end], &scan.scope, needs_rules),
+                    false => None,
+                };
+                self.flow_verdict(flow, payload, scan, found).is_some()
+            })
+            .collect()
+    }
+
+    /// Checks `payload` of `flow` like [check_flow_match](Self::check_flow_match), returning the
+    /// matched rules, collected only if `collect` is set or the matched rules are needed anyway.
+    /// `None` if `payload` did not match.
+    fn flow_matches(&self, flow: &Flow, payload: &[u8], collect: bool) -> Option<Vec<Rule>> {
+        let scan = self.flow_scan(flow, payload)?;
+        let found = self.scoped_matches(
+            &payload[..scan.end],
+            &scan.scope,
+            collect || self.needs_rules(),
+        );
+        self.flow_verdict(flow, payload, scan, found)
+    }
+
+    /// Returns whether the matched rules of payloads are needed, for end-of-flow summaries, the
+    /// match rate safeguard, alert context or store targets.
+    fn needs_rules(&self) -> bool {
+        self.hooks.has_flow_end()
+            || self.throttle.is_enabled()
+            || self.alerts.captures_context()
+            || self.targets.is_enabled()
+    }
+
+    /// Updates the flow table state of `flow` with its next payload `payload`, and returns the
+    /// part of it to scan with the scope of the flow. `None` if the payload is not scanned.
+    fn flow_scan(&self, flow: &Flow, payload: &[u8]) -> Option<FlowScan> {
+        if !self.vlans.actions(flow.c_tag()).scan {
+            self.record_drop(DropReason::VlanPolicy);
+            return None;
+        }
+        let (offset, app, cached, sampled, flow_label) = match self
+            .flows
+            .get_mut(&PackedFlow::from(flow))
+        {
+            Some(mut state) => {
+                let offset = state.bytes_seen;
+                if offset == 0 && !payload.is_empty() && self.verdicts.is_enabled() {
+                    state.server_name = app::server_name(payload).map(String::into_boxed_str);
+                    let generation = self.generation.load(Ordering::Acquire);
+                    state.cached = self.verdicts.is_cleared(&state.verdict_key(flow), generation);
+                }
+                state.bytes_seen += payload.len();
+                let app = state.identify(flow, payload, &self.app_ports);
+                let flow_label = state.flow_label.or(flow.flow_label());
+                (offset, app, state.cached, state.sampled, flow_label)
+            }
+            None => {
+                let app = self
+                    .app_ports
+                    .get(flow.ports())
+                    .unwrap_or_else(|| app::identify(flow.proto(), flow.ports(), payload));
+                (0, app, false, false, flow.flow_label())
+            }
+        };
+        if cached {
+            self.record_drop(DropReason::VerdictCache);
+            return None;
+        }
+        if self.scan.skips(app) {
+            self.scan.record_classified(payload.len());
+            self.record_drop(DropReason::Classified);
+            return None;
+        }
+        if !self.admits_len(payload) {
+            return None;
+        }
+        let depth = match sampled {
+            true => usize::MAX,
+            false => self.scan.depth(),
+        };
+        if offset >= depth {
+            self.scan.record_skipped();
+            self.record_drop(DropReason::ScanDepth);
+            self.trace(|| TraceEvent::Skipped { flow: *flow, offset });
+            return None;
+        }
+        Some(FlowScan {
+            offset,
+            end: cmp::min(payload.len(), depth - offset),
+            scope: self.flow_scope(flow, app, flow_label),
+            sampled,
+        })
+    }
+
+    /// Completes the check of `payload` of `flow` once the rules that `scan` selected were
+    /// scanned, finding `found`: scans it with YARA rules, records a match in the flow table, and
+    /// publishes it. Returns the matched rules, `None` if `payload` did not match.
+    fn flow_verdict(
+        &self,
+        flow: &Flow,
+        payload: &[u8],
+        scan: FlowScan,
+        found: Option<PayloadMatch>,
+    ) -> Option<Vec<Rule>> {
+        let FlowScan {
+            offset,
+            end,
+            sampled,
+            ..
+        } = scan;
+        let has_flow_end = self.hooks.has_flow_end();
+        let throttled = self.throttle.is_enabled();
+        let yara_rules = self.yara.scan(&payload[..end]);
+        let matched = found.is_some() || !yara_rules.is_empty();
+        self.trace(|| TraceEvent::Verdict { flow: *flow, offset, matched });
+        if matched {
+            // YARA matches are taken to end with the scanned bytes
+            let (mut rules, match_end, first) = match found {
+                Some(found) => (found.rules, found.end.unwrap_or(end), found.first),
+                None => (vec![], end, None),
+            };
+            let nb_regex = rules.len();
+            rules.extend(yara_rules);
+            let mut invalidated = None;
+            if let Some(mut state) = self.flows.get_mut(&PackedFlow::from(flow)) {
+                if !state.matched {
+                    invalidated = Some(state.verdict_key(flow));
+                }
+                state.matched = true;
+                if state.rates.is_none() {
+                    state.rates = self.rates.tracker(Instant::now());
+                }
+                if let Some(rule) = Rule::highest(rules.iter()) {
+                    let outranked = match &state.store_route {
+                        Some(route) => rule.priority > route.priority,
+                        None => true,
+                    };
+                    if outranked {
+                        state.store_route = Some(StoreRoute {
+                            priority: rule.priority,
+                            target: rule.store_target.as_deref().map(Box::from),
+                            snaplen: rule.snaplen,
+                        });
+                    }
+                }
+                if has_flow_end {
+                    for rule in rules.iter() {
+                        if !state.matched_rules.contains(&rule.pattern) {
+                            state.matched_rules.push(rule.pattern.clone());
+                        }
+                        for (key, value) in rule.tags.iter() {
+                            if !state.matched_tags.contains_key(key) {
+                                state.matched_tags.insert(key.clone(), value.clone());
+                            }
+                        }
+                    }
+                }
+            }
+            if throttled {
+                self.throttle.record(&rules[..nb_regex], payload.len());
+            }
+            if let Some(key) = invalidated {
+                self.verdicts.invalidate(&key);
+            }
+            self.scan.record_match(offset + match_end, sampled);
+            self.alerts.publish(flow, offset, payload, &rules, first);
+            return Some(rules);
+        }
+        None
+    }
+
+    /// Returns the properties of `flow`, identified as `app` and carrying IPv6 flow label
+    /// `flow_label`, checked against the scope of rules.
+    fn flow_scope(&self, flow: &Flow, app: AppProtocol, flow_label: Option<u32>) -> FlowScope {
+        FlowScope {
+            app,
+            s_tag: flow.s_tag(),
+            c_tag: flow.c_tag(),
+            macs: self.neighbors.flow_macs(flow),
+            flow_label,
+        }
+    }
+
+    /// Returns YARA scanning statistics, `None` if no YARA rules are loaded, see
+    /// [yara](crate::filter::yara).
+    pub fn yara_stats(&self) -> Option<YaraStats> {
+        self.yara.stats()
+    }
+
+    /// Sets feature flag `name`, which must be built-in or configured, see
+    /// [flags](crate::filter::flags).
+    pub fn set_flag(&self, name: &str, enabled: bool) -> Result<()> {
+        self.journal.control(|| format!("set_flag {} {}", name, enabled));
+        self.flags.set(name, enabled)
+    }
+
+    /// Returns the value of feature flag `name`, `None` if it is neither built-in nor configured.
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        self.flags.get(name)
+    }
+
+    /// Returns the value of each feature flag, built-in flags first.
+    pub fn flags(&self) -> Vec<FlagState> {
+        self.flags.stats()
+    }
+
+    /// Replaces the rewrite of the packets forwarded to online port `device`, `None` to forward
+    /// them unmodified. Applies from the next burst of each forwarding sink, see
+    /// [rewrite](crate::filter::rewrite).
+    pub fn set_egress_rewrite(&self, device: &str, rewrite: Option<EgressRewrite>) -> Result<()> {
+        self.journal.control(|| match &rewrite {
+            Some(rewrite) => format!("set_egress_rewrite {} {}", device, rewrite),
+            None => format!("clear_egress_rewrite {}", device),
+        });
+        self.rewrites.set(device, rewrite)
+    }
+
+    /// Returns the egress rewrite counters of each online port.
+    pub fn egress_rewrite_stats(&self) -> Vec<RewriteStats> {
+        self.rewrites.stats()
+    }
+
+    pub(crate) fn egress_rewrite(&self, device: &str) -> Option<Arc<PortRewrite>> {
+        self.rewrites.port(device)
+    }
+
+    /// Returns verdict cache statistics, `None` if the cache is not enabled.
+    pub fn verdict_cache_stats(&self) -> Option<VerdictCacheStats> {
+        self.verdicts.stats()
+    }
+
+    /// Recomputes the adaptive scan depth from the recorded match offsets.
+    pub fn update_scan_depth(&self) {
+        self.scan.update_depth(self.flags.is_enabled(Flag::AdaptiveScan));
+    }
+
+    /// Returns scan depth and match offset statistics.
+    pub fn scan_stats(&self) -> ScanStats {
+        let mut stats = self.scan.stats();
+        stats.nb_rule_gated = self.rules.read().unwrap().nb_gated();
+        stats
+    }
+
+    /// Starts or stops shedding storage load, and publishes the state change to the alert
+    /// subscribers. While shedding, packets are still matched and handed to the callback, but not
+    /// copied to the tap, the rolling capture or collectors, whose queues hold on to mbufs.
+    pub(crate) fn set_shedding(&self, shedding: bool, available: f64) {
+        self.shedding.active.store(shedding, Ordering::Relaxed);
+        self.alerts.publish_pressure(shedding, available);
+    }
+
+    /// Returns whether storage load is shed, counting the packet that is not stored if so.
+    #[inline]
+    fn shed(&self) -> bool {
+        let shedding = self.shedding.active.load(Ordering::Relaxed);
+        if shedding {
+            self.shedding.nb_shed.fetch_add(1, Ordering::Relaxed);
+        }
+        shedding
+    }
+
+    /// Returns the number of packet copies to the tap, the rolling capture or collectors skipped
+    /// because storage load was shed under memory pool pressure.
+    pub fn nb_shed(&self) -> u64 {
+        self.shedding.nb_shed.load(Ordering::Relaxed)
+    }
+
+    /// Returns delivery counters of the alert subscribers.
+    pub fn alert_stats(&self) -> Vec<SubscriberStats> {
+        self.alerts.stats()
+    }
+
+    /// Copies the shared rule set into this context if it changed since the last copy, and
+    /// records its generation as active on the attached core.
+    pub(crate) fn refresh_rules(&self) {
+        let generation = self.generation.load(Ordering::Acquire);
+        if generation != self.local_generation.load(Ordering::Relaxed) {
+            let rules = Arc::clone(&self.rules.read().unwrap());
+            let generation = rules.generation();
+            *self.rule_set.write().unwrap() = rules;
+            self.local_generation.store(generation, Ordering::Relaxed);
+            if let Some(core_generation) = &self.core_generation {
+                core_generation.store(generation, Ordering::Release);
+            }
+        }
+    }
+
+    /// Returns the compilation statistics of the last loaded rule sets, oldest first.
+    pub fn compile_stats(&self) -> Vec<CompileStats> {
+        self.compiles.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Returns the rules of the last [loaded](FilterCtx::load_rules) rule set that failed their
+    /// [test vectors](crate::filter::rule#test-vectors).
+    pub fn rule_test_failures(&self) -> Vec<RuleTestFailure> {
+        self.rule_tests.lock().unwrap().clone()
+    }
+
+    /// Returns the journal records that were not acknowledged with
+    /// [ack_journal](FilterCtx::ack_journal), oldest first, so that exporters can deliver them
+    /// again after a restart. Empty if the journal is not enabled.
+    pub fn journal_replay(&self) -> Result<Vec<JournalRecord>> {
+        self.journal.replay()
+    }
+
+    /// Acknowledges the delivery of the journal records up to sequence number `seq`, removing the
+    /// journal segments they cover.
+    pub fn ack_journal(&self, seq: u64) -> Result<()> {
+        self.journal.ack(seq)
+    }
+
+    /// Returns the journal statistics, `None` if the journal is not enabled, see
+    /// [journal](crate::filter::journal).
+    pub fn journal_stats(&self) -> Option<JournalStats> {
+        self.journal.stats()
+    }
+
+    /// Returns the generation of the loaded rule set and the generation each RX core matches
+    /// against. Cores pick up a new rule set on their next poll, so an update has reached all
+    /// cores once [RuleGenerations::is_propagated](RuleGenerations::is_propagated) holds.
+    pub fn rule_generations(&self) -> RuleGenerations {
+        let generation = self.rules.read().unwrap().generation();
+        self.generations.snapshot(generation)
+    }
+
+    /// Replaces the rule set with non-expiring rules from `regexes` and invokes the rule update
+    /// hooks. The update is picked up by all copies of this context.
+    pub fn update_regexes(&self, regexes: RegexSet) {
+        let _update = self.update_lock.lock().unwrap();
+        let mut rule_set = RuleSet::from_regexes(regexes);
+        rule_set.inherit(&self.rules.read().unwrap());
+        self.replace_rules(rule_set);
+    }
+
+    /// Replaces the active rules with `rules`. The update is picked up by all copies of this
+    /// context.
+    ///
+    /// ## Remarks
+    /// Only the rule shards that changed since the last update are recompiled. Packets keep being
+    /// matched against the previous rules while compiling. Regex flags the rules leave unset are
+    /// set to their `[regex]` default, see [rule](crate::filter::rule). Rules that fail their
+    /// [test vectors](crate::filter::rule#test-vectors) are rejected or flagged as set by the
+    /// `[regex]` section.
+    pub fn load_rules(&self, mut rules: Vec<Rule>) -> Result<()> {
+        let _update = self.update_lock.lock().unwrap();
+        let regex = *self.regex.read().unwrap();
+        rules.iter_mut().for_each(|rule| rule.resolve_flags(&regex));
+        let mut failures = vec![];
+        rules.retain(|rule| match rule.run_tests() {
+            Some(mut failure) => {
+                failure.rejected = regex.failed_tests == FailedTests::Reject;
+                log::warn!(
+                    "Rule {} failed its tests ({} missed, {} false positive(s)){}",
+                    rule.pattern,
+                    failure.missed.len(),
+                    failure.false_positives.len(),
+                    if failure.rejected { ", rejected" } else { "" }
+                );
+                let keep = !failure.rejected;
+                failures.push(failure);
+                keep
+            }
+            None => true,
+        });
+        self.throttle.retain_enabled(&mut rules);
+        let current = Arc::clone(&self.rules.read().unwrap());
+        let (rule_set, nb_compiled) = current.update(rules)?;
+        log::info!(
+            "Loaded {} rules, compiled {} shard(s) in {:?}",
+            rule_set.len(),
+            nb_compiled,
+            rule_set.compile_stats().compile_time
+        );
+        self.replace_rules(rule_set);
+        *self.rule_tests.lock().unwrap() = failures;
+        Ok(())
+    }
+
+    /// Swaps in `rule_set`. Must be called with `update_lock` held.
+    fn replace_rules(&self, mut rule_set: RuleSet) {
+        let regexes = rule_set.regexes();
+        let generation = self.generation.load(Ordering::Relaxed) + 1;
+        rule_set.set_generation(generation);
+        {
+            let mut compiles = self.compiles.lock().unwrap();
+            if compiles.len() >= MAX_COMPILE_HISTORY {
+                compiles.pop_front();
+            }
+            compiles.push_back(rule_set.compile_stats());
+        }
+        self.journal.append(|| JournalEntry::Rules {
+            generation,
+            rules: rule_set.rules().cloned().collect(),
+        });
+        *self.rules.write().unwrap() = Arc::new(rule_set);
+        self.generation.store(generation, Ordering::Release);
+        self.hooks.rule_update(&regexes);
+    }
+
+    /// Removes rules that reached their expiry time. Returns the number of removed rules.
+    pub fn expire_rules(&self) -> Result<usize> {
+        let _update = self.update_lock.lock().unwrap();
+        let current = Arc::clone(&self.rules.read().unwrap());
+        let (rule_set, expired) = match current.remove_expired(SystemTime::now())? {
+            Some(update) => update,
+            None => return Ok(0),
+        };
+        for rule in expired.iter() {
+            log::info!("Rule expired: {}", rule.pattern);
+        }
+        self.replace_rules(rule_set);
+        Ok(expired.len())
+    }
+
+    /// Compiles profiling regexes for rules loaded since the last call. Called periodically by the
+    /// main core.
+    pub(crate) fn update_rule_profiles(&self) {
+        if self.profiler.is_enabled() {
+            let rules = Arc::clone(&self.rules.read().unwrap());
+            self.profiler.update(&rules, self.generation.load(Ordering::Acquire));
+        }
+    }
+
+    /// Returns the measured cost of every rule, most expensive first. Empty unless profiling is
+    /// enabled.
+    pub fn rule_costs(&self) -> Vec<RuleCost> {
+        self.profiler.costs()
+    }
+
+    /// Returns the load status of each file of the rules directory, see
+    /// [watch](crate::filter::watch). Empty if no rules directory is configured.
+    pub fn rule_file_stats(&self) -> Vec<RuleFileStats> {
+        self.rule_files.stats()
+    }
+
+    /// Sets the rules of source `source` to `rules`, with priority `priority`, and replaces the
+    /// active rules with the composition of all sources, see [sources](crate::filter::sources).
+    /// On error, the source keeps its previous rules.
+    pub fn load_source_rules(&self, source: &str, priority: i32, rules: Vec<Rule>) -> Result<()> {
+        self.journal
+            .control(|| format!("load_source_rules {} {} {}", source, priority, rules.len()));
+        let nb_rules = rules.len();
+        self.rule_sources
+            .update(source, Some((priority, rules)), |rules| self.load_rules(rules))?;
+        log::info!("Rule source {} loaded with {} rules, priority {}", source, nb_rules, priority);
+        Ok(())
+    }
+
+    /// Removes source `source` and replaces the active rules with the composition of the other
+    /// sources. Returns whether the source existed.
+    pub fn clear_source_rules(&self, source: &str) -> Result<bool> {
+        self.journal.control(|| format!("clear_source_rules {}", source));
+        let existed = self
+            .rule_sources
+            .update(source, None, |rules| self.load_rules(rules))?;
+        if existed {
+            log::info!("Rule source {} cleared", source);
+        }
+        Ok(existed)
+    }
+
+    /// Returns the status of each rule source, by name.
+    pub fn rule_sources(&self) -> Vec<RuleSourceStats> {
+        self.rule_sources.stats()
+    }
+
+    /// Returns the counters of the active counting rules, see [rule](crate::filter::rule).
+    pub fn rule_counts(&self) -> Vec<RuleCount> {
+        self.rules.read().unwrap().counts()
+    }
+
+    /// Loads `rules` as the shadow rule set, replacing the previous one and resetting its counters.
+    /// Shadow rules are only counted on sampled payloads, see [shadow](crate::filter::shadow).
+    pub fn load_shadow_rules(&self, rules: Vec<Rule>) -> Result<()> {
+        self.journal.control(|| format!("load_shadow_rules {}", rules.len()));
+        self.shadow.load(rules, &self.regex.read().unwrap())
+    }
+
+    /// Removes the shadow rule set.
+    pub fn clear_shadow_rules(&self) {
+        self.journal.control(|| "clear_shadow_rules".to_string());
+        self.shadow.clear();
+    }
+
+    /// Returns statistics of the shadow rule set, `None` if none is loaded.
+    pub fn shadow_stats(&self) -> Option<ShadowStats> {
+        self.shadow.stats()
+    }
+
+    /// Disables the rules that exceeded the match rate limits over the last measurement interval,
+    /// see [throttle](crate::filter::throttle). Called periodically by the main core.
+    pub(crate) fn throttle_rules(&self) -> Result<()> {
+        let throttled = self.throttle.check();
+        if throttled.is_empty() {
+            return Ok(());
+        }
+        for rule in throttled.iter() {
+            log::error!(
+                "RULE DISABLED: {} matched {:.1} times/s, {:.0} bytes/s, above the throttle limits",
+                rule.rule.pattern,
+                rule.match_rate,
+                rule.byte_rate
+            );
+            self.alerts.publish_throttle(rule);
+        }
+        let _update = self.update_lock.lock().unwrap();
+        let current = Arc::clone(&self.rules.read().unwrap());
+        let mut rules: Vec<Rule> = current.rules().cloned().collect();
+        self.throttle.retain_enabled(&mut rules);
+        let (rule_set, _) = current.update(rules)?;
+        self.replace_rules(rule_set);
+        Ok(())
+    }
+
+    /// Re-enables the rules with pattern `pattern` disabled by the match rate safeguard, adding
+    /// them back to the active rules. Returns the number of re-enabled rules.
+    pub fn enable_rule(&self, pattern: &str) -> Result<usize> {
+        self.journal.control(|| format!("enable_rule {}", pattern));
+        let enabled = self.throttle.enable(pattern);
+        if enabled.is_empty() {
+            return Ok(0);
+        }
+        let _update = self.update_lock.lock().unwrap();
+        let current = Arc::clone(&self.rules.read().unwrap());
+        let mut rules: Vec<Rule> = current.rules().cloned().collect();
+        let nb_enabled = enabled.len();
+        rules.extend(enabled);
+        let (rule_set, _) = current.update(rules)?;
+        self.replace_rules(rule_set);
+        log::warn!("Rule re-enabled: {}", pattern);
+        Ok(nb_enabled)
+    }
+
+    /// Returns the rules disabled by the match rate safeguard.
+    pub fn throttled_rules(&self) -> Vec<ThrottledRule> {
+        self.throttle.disabled()
+    }
+
+    /// Returns statistics about the loaded rules.
+    pub fn rule_stats(&self) -> RuleStats {
+        let rules = self.rules.read().unwrap();
+        let rule_tests = self.rule_tests.lock().unwrap();
+        RuleStats {
+            nb_rules: rules.len(),
+            nb_counting: rules.nb_counting(),
+            nb_capturing: rules.nb_capturing(),
+            nb_throttled: self.throttle.disabled().len(),
+            nb_failed_tests: rule_tests.len(),
+            nb_rejected: rule_tests.iter().filter(|failure| failure.rejected).count(),
+            nb_expired: rules.nb_expired(),
+            generation: rules.generation(),
+            regex_defaults: *self.regex.read().unwrap(),
+            last_compile: self.compiles.lock().unwrap().back().copied(),
+        }
     }
-    
 }
 
 impl Clone for FilterCtx {
     fn clone(&self) -> Self {
-        Self { 
-            flows: self.flows.clone(), 
-            timeout: self.timeout.clone(), 
-            regexes: RwLock::new(self.regexes.read().unwrap().clone())
+        Self {
+            flows: self.flows.clone(),
+            flow_limits: self.flow_limits.clone(),
+            directions: self.directions.clone(),
+            core_flows: self.core_flows.clone(),
+            flow_hash: self.flow_hash.clone(),
+            timeout: self.timeout.clone(),
+            rule_set: RwLock::new(Arc::clone(&self.rule_set.read().unwrap())),
+            local_generation: AtomicU64::new(self.local_generation.load(Ordering::Relaxed)),
+            rules: self.rules.clone(),
+            generation: self.generation.clone(),
+            generations: self.generations.clone(),
+            core_generation: self.core_generation.clone(),
+            rx_queue: AtomicU32::new(0),
+            nb_shadow_checked: AtomicU64::new(0),
+            seen_neighbors: Mutex::new(SeenNeighbors::default()),
+            update_lock: self.update_lock.clone(),
+            compiles: self.compiles.clone(),
+            rule_tests: self.rule_tests.clone(),
+            flow_key: self.flow_key.clone(),
+            regex: self.regex.clone(),
+            scan: self.scan.clone(),
+            alerts: self.alerts.clone(),
+            journal: self.journal.clone(),
+            tracer: self.tracer.clone(),
+            trace: self.trace.clone(),
+            drops: self.drops.clone(),
+            core_drops: self.core_drops.clone(),
+            checksums: self.checksums.clone(),
+            core_checksums: self.core_checksums.clone(),
+            consumers: self.consumers.clone(),
+            pipeline: self.pipeline.clone(),
+            profiler: self.profiler.clone(),
+            shadow: self.shadow.clone(),
+            throttle: self.throttle.clone(),
+            tap: self.tap.clone(),
+            forwarders: self.forwarders.clone(),
+            targets: self.targets.clone(),
+            capture: self.capture.clone(),
+            backfill: self.backfill.clone(),
+            verdicts: self.verdicts.clone(),
+            talkers: self.talkers.clone(),
+            core_talkers: self.core_talkers.clone(),
+            cycles: self.cycles.clone(),
+            bundle: self.bundle.clone(),
+            yara: self.yara.clone(),
+            flags: self.flags.clone(),
+            rewrites: self.rewrites.clone(),
+            pause: self.pause.clone(),
+            shedding: self.shedding.clone(),
+            rule_files: self.rule_files.clone(),
+            rule_sources: self.rule_sources.clone(),
+            neighbors: self.neighbors.clone(),
+            app_ports: self.app_ports.clone(),
+            vlans: self.vlans.clone(),
+            quotas: self.quotas.clone(),
+            rates: self.rates.clone(),
+            bridge: self.bridge.clone(),
+            output: self.output.clone(),
+            hooks: self.hooks.clone()
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,241 @@
+//! IP to MAC address observation table.
+//!
+//! When enabled, RX cores record the MAC address every IP address is seen with: the sender
+//! addresses of ARP packets, and the source addresses of IPv4 and IPv6 packets, which include the
+//! neighbor discovery messages of IPv6 hosts. Hosts behind a router are observed with the MAC
+//! address of the router. Entries expire when an address is not seen for the configured TTL.
+//!
+//! ARP and neighbor discovery packets always update the shared table. For other IP packets, each
+//! core remembers the addresses it recorded, and only updates the shared table when an address is
+//! seen with a new MAC address, or to refresh it once per quarter of the TTL.
+//!
+//! The table backs the MAC constraints of [rules](crate::filter::rule::Rule::mac) and can be
+//! queried with [FilterCtx::neighbor_mac](crate::filter::FilterCtx::neighbor_mac). The MAC
+//! addresses of individual packets are available on
+//! [L4Context](crate::protocols::layer4::L4Context).
+
+use crate::config::NeighborConfig;
+use crate::protocols::layer4::Flow;
+use crate::protocols::packet::ethernet::Ethernet;
+use crate::protocols::packet::ipv4::Ipv4;
+use crate::protocols::packet::ipv6::Ipv6;
+use crate::protocols::packet::Packet;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use pnet::datalink::MacAddr;
+
+/// ARP Ethernet type.
+const ARP_PROTOCOL: usize = 0x0806;
+/// Length of an ARP packet for IPv4 over Ethernet.
+const ARP_LEN: usize = 28;
+/// Minimum time between two refreshes of an unchanged entry.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// ICMPv6 protocol number.
+const ICMPV6_PROTOCOL: u8 = 58;
+/// ICMPv6 types of neighbor discovery messages, router solicitation to redirect.
+const NDP_TYPES: std::ops::RangeInclusive<u8> = 133..=137;
+
+/// An observed IP address.
+#[derive(Debug, Clone, Copy)]
+pub struct Neighbor {
+    /// IP address.
+    pub addr: IpAddr,
+    /// MAC address the IP address was last seen with.
+    pub mac: MacAddr,
+    /// Time since the IP address was last seen.
+    pub age: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    mac: MacAddr,
+    last_seen: Instant,
+}
+
+/// Addresses recently recorded by one core, with their MAC address and the time they were
+/// recorded.
+#[derive(Debug, Default)]
+pub(crate) struct SeenNeighbors {
+    entries: HashMap<IpAddr, (MacAddr, Instant)>,
+}
+
+impl SeenNeighbors {
+    /// Returns whether `addr` was recorded with `mac` less than `interval` ago. Otherwise
+    /// remembers it as recorded now, first forgetting all addresses if there are `capacity`.
+    fn is_fresh(
+        &mut self,
+        addr: IpAddr,
+        mac: MacAddr,
+        interval: Duration,
+        capacity: usize,
+    ) -> bool {
+        let now = Instant::now();
+        match self.entries.get_mut(&addr) {
+            Some((seen_mac, seen)) if *seen_mac == mac && now.duration_since(*seen) < interval => {
+                return true;
+            }
+            Some(entry) => *entry = (mac, now),
+            None => {
+                if self.entries.len() >= capacity {
+                    self.entries.clear();
+                }
+                self.entries.insert(addr, (mac, now));
+            }
+        }
+        false
+    }
+}
+
+/// Shared neighbor table of a filter. Disabled until configured.
+#[derive(Debug, Default)]
+pub(crate) struct NeighborTable {
+    entries: DashMap<IpAddr, Entry>,
+    ttl_ms: AtomicU64,
+    capacity: AtomicUsize,
+}
+
+impl NeighborTable {
+    pub(crate) fn new() -> Self {
+        NeighborTable::default()
+    }
+
+    /// Applies neighbor table options from the runtime configuration.
+    pub(crate) fn configure(&self, config: &NeighborConfig) {
+        self.ttl_ms.store(config.ttl * 1000, Ordering::Relaxed);
+        self.capacity.store(config.capacity, Ordering::Relaxed);
+        log::info!("Neighbor table enabled, ttl: {}s", config.ttl);
+    }
+
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.ttl_ms.load(Ordering::Relaxed) > 0
+    }
+
+    /// Records the sender of `eth` if it is an ARP, IPv4 or IPv6 packet. `seen` holds the
+    /// addresses recorded by the calling core.
+    #[inline]
+    pub(crate) fn observe(&self, eth: &Ethernet, seen: &Mutex<SeenNeighbors>) {
+        if !self.is_enabled() {
+            return;
+        }
+        if eth.next_header() == Some(ARP_PROTOCOL) {
+            if let Some((addr, mac)) = arp_sender(eth) {
+                self.record(addr, mac);
+            }
+            return;
+        }
+        let addr = if let Ok(ipv4) = eth.parse_to::<Ipv4>() {
+            IpAddr::V4(ipv4.src_addr())
+        } else if let Ok(ipv6) = eth.parse_to::<Ipv6>() {
+            if is_ndp(&ipv6) {
+                self.record(IpAddr::V6(ipv6.src_addr()), eth.src());
+                return;
+            }
+            IpAddr::V6(ipv6.src_addr())
+        } else {
+            return;
+        };
+        let interval = cmp::max(
+            Duration::from_millis(self.ttl_ms.load(Ordering::Relaxed) / 4),
+            REFRESH_INTERVAL,
+        );
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if !seen
+            .lock()
+            .unwrap()
+            .is_fresh(addr, eth.src(), interval, capacity)
+        {
+            self.record(addr, eth.src());
+        }
+    }
+
+    /// Records that `addr` was seen with `mac`. Unspecified addresses (e.g. of ARP probes),
+    /// multicast addresses and multicast MAC addresses are ignored.
+    fn record(&self, addr: IpAddr, mac: MacAddr) {
+        if addr.is_unspecified() || addr.is_multicast() || mac.0 & 0x01 != 0 {
+            return;
+        }
+        if let Some(entry) = self.entries.get(&addr) {
+            if entry.mac == mac && entry.last_seen.elapsed() < REFRESH_INTERVAL {
+                return;
+            }
+            if entry.mac != mac {
+                log::debug!("Neighbor {} moved from {} to {}", addr, entry.mac, mac);
+            }
+        } else if self.entries.len() >= self.capacity.load(Ordering::Relaxed) {
+            return;
+        }
+        self.entries.insert(
+            addr,
+            Entry {
+                mac,
+                last_seen: Instant::now(),
+            },
+        );
+    }
+
+    /// Returns the MAC address `addr` was last seen with.
+    pub(crate) fn lookup(&self, addr: &IpAddr) -> Option<MacAddr> {
+        if !self.is_enabled() {
+            return None;
+        }
+        self.entries.get(addr).map(|entry| entry.mac)
+    }
+
+    /// Returns the MAC addresses the endpoints of `flow` were last seen with.
+    #[inline]
+    pub(crate) fn flow_macs(&self, flow: &Flow) -> [Option<MacAddr>; 2] {
+        let (a, b) = flow.addrs();
+        [self.lookup(&a.ip()), self.lookup(&b.ip())]
+    }
+
+    /// Removes entries that were not seen for the configured TTL.
+    pub(crate) fn prune(&self) {
+        let ttl = Duration::from_millis(self.ttl_ms.load(Ordering::Relaxed));
+        self.entries
+            .retain(|_, entry| entry.last_seen.elapsed() < ttl);
+    }
+
+    pub(crate) fn neighbors(&self) -> Vec<Neighbor> {
+        self.entries
+            .iter()
+            .map(|entry| Neighbor {
+                addr: *entry.key(),
+                mac: entry.mac,
+                age: entry.last_seen.elapsed(),
+            })
+            .collect()
+    }
+}
+
+/// Returns whether `ipv6` is a neighbor discovery message.
+fn is_ndp(ipv6: &Ipv6) -> bool {
+    ipv6.next_header() == ICMPV6_PROTOCOL
+        && ipv6
+            .mbuf()
+            .get_data_slice(ipv6.next_header_offset(), 1)
+            .map_or(false, |icmp_type| NDP_TYPES.contains(&icmp_type[0]))
+}
+
+/// Returns the sender addresses of the ARP packet (IPv4 over Ethernet) in `eth`. `None` for
+/// malformed packets.
+fn arp_sender(eth: &Ethernet) -> Option<(IpAddr, MacAddr)> {
+    let arp = eth
+        .mbuf()
+        .get_data_slice(eth.next_header_offset(), ARP_LEN)
+        .ok()?;
+    // Hardware type Ethernet, protocol type IPv4, address lengths 6 and 4.
+    if arp[0..6] != [0x00, 0x01, 0x08, 0x00, 6, 4] {
+        return None;
+    }
+    let mac = MacAddr(arp[8], arp[9], arp[10], arp[11], arp[12], arp[13]);
+    let addr = Ipv4Addr::new(arp[14], arp[15], arp[16], arp[17]);
+    Some((IpAddr::V4(addr), mac))
+}
@@ -0,0 +1,195 @@
+//! Pausing inspection.
+//!
+//! [FilterCtx::pause](crate::filter::FilterCtx::pause) stops inspection during maintenance without
+//! stopping the ports. The RX cores keep polling their receive queues and picking up rule updates,
+//! but no longer run the consumers, pipeline stages, filter or callback on the packets they
+//! receive, until [FilterCtx::resume](crate::filter::FilterCtx::resume). Ports, memory pools, the
+//! flow table and the rule set are left untouched, so that processing resumes cleanly from the
+//! next burst. The packets received while paused are handled according to the
+//! [PauseMode](PauseMode):
+//! - `drop`: packets are freed, and only counted by the RX queue counters.
+//! - `count`: packets are also counted as dropped with
+//!   [DropReason::Paused](crate::filter::drops::DropReason::Paused).
+//! - `sink`: the RSS redirection table of each port with sink queues spreads all buckets across
+//!   its sink queues, so that packets are handled by the [sink behaviors](crate::lcore::sink).
+//!   Packets of ports without sink queues, or already queued on receive queues, are counted like
+//!   with `count`. The redirection tables are restored on resume.
+//!
+//! Pause and resume commands are journaled, and the number of pauses and the total paused time are
+//! reported by the monitor.
+//!
+//! ## Example
+//! ```
+//! filter_ctx.pause(PauseMode::Sink)?;
+//! upgrade_storage()?;
+//! filter_ctx.resume()?;
+//! ```
+
+use crate::port::{self, Port, PortId, Reta};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+/// Handling of the packets received while paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PauseMode {
+    /// Packets are freed.
+    Drop,
+    /// Packets are counted as dropped and freed.
+    Count,
+    /// Packets are diverted to the sink queues of their port.
+    Sink,
+}
+
+impl PauseMode {
+    const ALL: [PauseMode; 3] = [PauseMode::Drop, PauseMode::Count, PauseMode::Sink];
+}
+
+impl fmt::Display for PauseMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PauseMode::Drop => write!(f, "drop"),
+            PauseMode::Count => write!(f, "count"),
+            PauseMode::Sink => write!(f, "sink"),
+        }
+    }
+}
+
+/// Pause state and paused time.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PauseStats {
+    /// Current mode, `None` if processing.
+    pub mode: Option<PauseMode>,
+    /// Number of pauses.
+    pub nb_pauses: u64,
+    /// Total time spent paused, including the current pause.
+    pub paused_time: Duration,
+}
+
+/// Redirection tables of an online port.
+#[derive(Debug)]
+struct PortRetas {
+    port_id: PortId,
+    reta: Reta,
+    /// Redirection table to the sink queues, `None` if the port has no sink queues.
+    sink_reta: Option<Reta>,
+}
+
+#[derive(Debug, Default)]
+struct PauseHistory {
+    /// Start of the current pause.
+    since: Option<Instant>,
+    nb_pauses: u64,
+    /// Time spent paused by the previous pauses.
+    paused_time: Duration,
+}
+
+/// Pause state shared by all copies of a filter, processing until paused.
+#[derive(Debug, Default)]
+pub(crate) struct Pause {
+    /// Current mode, as a `PauseMode` discriminant plus one, `0` if processing.
+    mode: AtomicU8,
+    history: Mutex<PauseHistory>,
+    ports: Mutex<Vec<PortRetas>>,
+}
+
+impl Pause {
+    pub(crate) fn new() -> Self {
+        Pause::default()
+    }
+
+    /// Registers the redirection tables of the online ports.
+    pub(crate) fn set_ports(&self, ports: &BTreeMap<PortId, Port>) {
+        *self.ports.lock().unwrap() = ports
+            .values()
+            .map(|port| PortRetas {
+                port_id: port.id,
+                reta: port.reta,
+                sink_reta: port.sink_reta(),
+            })
+            .collect();
+    }
+
+    /// Returns the current mode, `None` if processing.
+    #[inline]
+    pub(crate) fn mode(&self) -> Option<PauseMode> {
+        match self.mode.load(Ordering::Relaxed) {
+            0 => None,
+            mode => PauseMode::ALL.get(mode as usize - 1).copied(),
+        }
+    }
+
+    pub(crate) fn pause(&self, mode: PauseMode) -> Result<()> {
+        let mut history = self.history.lock().unwrap();
+        if history.since.is_some() {
+            bail!("Processing is already paused");
+        }
+        if mode == PauseMode::Sink {
+            for port in self.ports.lock().unwrap().iter() {
+                match &port.sink_reta {
+                    Some(sink_reta) => update_reta(port.port_id, sink_reta),
+                    None => log::warn!(
+                        "Port {} has no sink queues, its paused packets are counted",
+                        port.port_id
+                    ),
+                }
+            }
+        }
+        self.mode.store(mode as u8 + 1, Ordering::Relaxed);
+        history.since = Some(Instant::now());
+        history.nb_pauses += 1;
+        log::warn!("Processing paused, packets: {}", mode);
+        Ok(())
+    }
+
+    pub(crate) fn resume(&self) -> Result<()> {
+        let mut history = self.history.lock().unwrap();
+        let since = match history.since.take() {
+            Some(since) => since,
+            None => bail!("Processing is not paused"),
+        };
+        if self.mode() == Some(PauseMode::Sink) {
+            for port in self.ports.lock().unwrap().iter() {
+                if port.sink_reta.is_some() {
+                    update_reta(port.port_id, &port.reta);
+                }
+            }
+        }
+        self.mode.store(0, Ordering::Relaxed);
+        let elapsed = since.elapsed();
+        history.paused_time += elapsed;
+        log::warn!("Processing resumed after {:.3} s", elapsed.as_secs_f64());
+        Ok(())
+    }
+
+    pub(crate) fn stats(&self) -> PauseStats {
+        let history = self.history.lock().unwrap();
+        let current = history
+            .since
+            .map_or(Duration::ZERO, |since| since.elapsed());
+        PauseStats {
+            mode: self.mode(),
+            nb_pauses: history.nb_pauses,
+            paused_time: history.paused_time + current,
+        }
+    }
+}
+
+/// Replaces the redirection table of `port_id`, logging failures.
+fn update_reta(port_id: PortId, reta: &Reta) {
+    let ret = port::update_rss_reta(port_id, reta);
+    if ret != 0 {
+        log::error!(
+            "Failed to set RSS redirection table for Port {} ({})",
+            port_id,
+            ret
+        );
+    }
+}
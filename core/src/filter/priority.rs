@@ -0,0 +1,218 @@
+//! Priority classes of the storage queues.
+//!
+//! The queues between the RX cores and the writer threads of the [tap](crate::filter::tap) and the
+//! [capture](crate::filter::capture) are split into priority classes, so that the packets that
+//! trigger alerts are not dropped because bulk traffic of matched flows filled the queue. Each
+//! class has its own bounded queue of the configured size: a full class drops its packets without
+//! taking room from the others.
+//!
+//! With the `[storage_priority]` options of the runtime configuration (see
+//! [StoragePriorityConfig](crate::config::StoragePriorityConfig)), writer threads dequeue either
+//! strictly, always emptying higher classes first, or by weight, taking up to the weight of a class
+//! in packets before moving on to the next non-empty class. There is one class per configured
+//! weight, class `0` being the highest. Packets are queued with a [Priority](Priority), e.g. with
+//! [FilterCtx::tap_packet_as](crate::filter::FilterCtx::tap_packet_as), and the default methods
+//! queue packets as [Priority::BULK](Priority::BULK).
+//!
+//! ## Example
+//! ```
+//! if filter_ctx.check_flow_match(&flow, payload) {
+//!     filter_ctx.tap_packet_as(mbuf, Priority::ALERT);
+//! } else if matched_flows.contains(&flow) {
+//!     filter_ctx.tap_packet(mbuf);
+//! }
+//! ```
+
+use crate::config::{Dequeue, StoragePriorityConfig};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{
+    bounded, Receiver, RecvError, RecvTimeoutError, Select, Sender, TryRecvError,
+};
+use serde::Serialize;
+
+/// Priority class of a stored packet, `0` being the highest. Classes beyond the configured number
+/// of classes are queued in the lowest one.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(pub usize);
+
+impl Priority {
+    /// Highest class, for packets that trigger alerts.
+    pub const ALERT: Priority = Priority(0);
+    /// Lowest class, for other packets of matched flows.
+    pub const BULK: Priority = Priority(usize::MAX);
+}
+
+/// Queue counters of a priority class.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ClassStats {
+    /// Storage queue, `tap` or `capture`.
+    pub queue: &'static str,
+    /// Priority class.
+    pub class: usize,
+    /// Number of packets queued.
+    pub nb_enqueued: u64,
+    /// Number of packets dropped because the class was full.
+    pub nb_dropped: u64,
+    /// Number of packets waiting to be written.
+    pub backlog: usize,
+}
+
+#[derive(Debug, Default)]
+struct ClassCounters {
+    nb_enqueued: AtomicU64,
+    nb_dropped: AtomicU64,
+}
+
+/// Sending half of a storage queue.
+#[derive(Debug)]
+pub(crate) struct PrioritySender<T> {
+    queue: &'static str,
+    txs: Vec<Sender<T>>,
+    counters: Arc<[ClassCounters]>,
+}
+
+impl<T> PrioritySender<T> {
+    /// Queues `item` in the class of `priority` without blocking. Returns whether it was queued.
+    #[inline]
+    pub(crate) fn try_send(&self, item: T, priority: Priority) -> bool {
+        let class = priority.0.min(self.txs.len() - 1);
+        let counters = &self.counters[class];
+        match self.txs[class].try_send(item) {
+            Ok(_) => {
+                counters.nb_enqueued.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(_) => {
+                counters.nb_dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Returns the counters of each class, highest first.
+    pub(crate) fn stats(&self) -> Vec<ClassStats> {
+        self.txs
+            .iter()
+            .zip(self.counters.iter())
+            .enumerate()
+            .map(|(class, (tx, counters))| ClassStats {
+                queue: self.queue,
+                class,
+                nb_enqueued: counters.nb_enqueued.load(Ordering::Relaxed),
+                nb_dropped: counters.nb_dropped.load(Ordering::Relaxed),
+                backlog: tx.len(),
+            })
+            .collect()
+    }
+}
+
+/// Receiving half of a storage queue, owned by its writer thread.
+#[derive(Debug)]
+pub(crate) struct PriorityReceiver<T> {
+    rxs: Vec<Receiver<T>>,
+    dequeue: Dequeue,
+    weights: Vec<u32>,
+    /// Class served by weighted dequeue, and the number of packets it may still take.
+    current: usize,
+    credit: u32,
+}
+
+impl<T> PriorityReceiver<T> {
+    /// Returns the next packet to write without blocking.
+    pub(crate) fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let nb_classes = self.rxs.len();
+        let mut nb_disconnected = 0;
+        for class in 0..nb_classes {
+            let class = match self.dequeue {
+                Dequeue::Strict => class,
+                Dequeue::Weighted => {
+                    if self.credit == 0 {
+                        self.current = (self.current + 1) % nb_classes;
+                        self.credit = self.weights[self.current].max(1);
+                    }
+                    self.current
+                }
+            };
+            match self.rxs[class].try_recv() {
+                Ok(item) => {
+                    self.credit = self.credit.saturating_sub(1);
+                    return Ok(item);
+                }
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Disconnected) => nb_disconnected += 1,
+            }
+            // An empty class gives up the rest of its turn
+            self.credit = 0;
+        }
+        if nb_disconnected == nb_classes {
+            return Err(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    /// Blocks until a packet is queued, and returns the next packet to write. Fails when the
+    /// sender is dropped and all classes are empty.
+    pub(crate) fn recv(&mut self) -> Result<T, RecvError> {
+        loop {
+            match self.try_recv() {
+                Ok(item) => return Ok(item),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => self.select().ready(),
+            };
+        }
+    }
+
+    /// Like [recv](Self::recv), waiting at most `timeout`.
+    pub(crate) fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.try_recv() {
+                Ok(item) => return Ok(item),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {
+                    if self.select().ready_deadline(deadline).is_err() {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                }
+            }
+        }
+    }
+
+    fn select(&self) -> Select<'_> {
+        let mut select = Select::new();
+        for rx in self.rxs.iter() {
+            select.recv(rx);
+        }
+        select
+    }
+}
+
+/// Creates the storage queue `queue`, with `capacity` packets per priority class.
+pub(crate) fn channel<T>(
+    queue: &'static str,
+    capacity: usize,
+    config: &StoragePriorityConfig,
+) -> (PrioritySender<T>, PriorityReceiver<T>) {
+    let weights = match config.weights.is_empty() {
+        true => vec![1],
+        false => config.weights.clone(),
+    };
+    let (txs, rxs) = weights.iter().map(|_| bounded(capacity)).unzip();
+    let sender = PrioritySender {
+        queue,
+        txs,
+        counters: weights.iter().map(|_| ClassCounters::default()).collect(),
+    };
+    let receiver = PriorityReceiver {
+        rxs,
+        dequeue: config.dequeue,
+        credit: weights[0].max(1),
+        weights,
+        current: 0,
+    };
+    (sender, receiver)
+}
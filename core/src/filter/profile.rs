@@ -0,0 +1,157 @@
+//! Per-rule regex cost profiling.
+//!
+//! When profiling is enabled, 1-in-N scanned payloads are additionally evaluated against every
+//! active rule with an individually compiled `Regex`, and the evaluation time of each rule is
+//! recorded. Rules are compiled for profiling by the main core when the rule set changes, never
+//! in the packet processing path. The main core periodically logs the most expensive rules, which
+//! helps operators prune slow patterns.
+
+use super::rule::{window, FlowScope, Rule, RuleSet};
+use crate::config::ProfileConfig;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use regex::bytes::Regex;
+
+/// Measured cost of a rule.
+#[derive(Debug, Clone)]
+pub struct RuleCost {
+    /// Pattern of the rule.
+    pub pattern: String,
+    /// Number of sampled payloads the rule was evaluated on.
+    pub nb_samples: u64,
+    /// Number of sampled payloads the rule matched.
+    pub nb_matches: u64,
+    /// Average evaluation time, in nanoseconds.
+    pub avg_ns: f64,
+}
+
+#[derive(Debug)]
+struct RuleProfile {
+    rule: Rule,
+    regex: Regex,
+    nb_samples: AtomicU64,
+    nb_matches: AtomicU64,
+    total_ns: AtomicU64,
+}
+
+/// Shared rule profiler of a filter.
+#[derive(Debug)]
+pub(crate) struct Profiler {
+    /// Sample 1-in-`sample_rate` payloads, `0` if disabled.
+    sample_rate: AtomicU64,
+    counter: AtomicU64,
+    /// Rule set generation `profiles` were compiled from.
+    generation: AtomicU64,
+    profiles: RwLock<Arc<Vec<Arc<RuleProfile>>>>,
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Profiler {
+            sample_rate: AtomicU64::new(0),
+            counter: AtomicU64::new(0),
+            generation: AtomicU64::new(u64::MAX),
+            profiles: RwLock::new(Arc::new(vec![])),
+        }
+    }
+
+    /// Applies profiling options from the runtime configuration.
+    pub(crate) fn configure(&self, config: &ProfileConfig) {
+        self.sample_rate.store(config.sample_rate, Ordering::Relaxed);
+    }
+
+    /// Returns whether profiling is enabled.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.sample_rate.load(Ordering::Relaxed) > 0
+    }
+
+    /// Evaluates `payload` against every profiled rule if it is sampled.
+    #[inline]
+    pub(crate) fn sample(&self, payload: &[u8], scope: &FlowScope) {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        if sample_rate == 0 || self.counter.fetch_add(1, Ordering::Relaxed) % sample_rate != 0 {
+            return;
+        }
+        let profiles = Arc::clone(&self.profiles.read().unwrap());
+        for profile in profiles.iter() {
+            if !profile.rule.applies_to(scope) {
+                continue;
+            }
+            let payload = window(payload, profile.rule.offset, profile.rule.depth);
+            let start = Instant::now();
+            let matched = profile.regex.is_match(payload);
+            let elapsed = start.elapsed().as_nanos() as u64;
+            profile.nb_samples.fetch_add(1, Ordering::Relaxed);
+            profile.total_ns.fetch_add(elapsed, Ordering::Relaxed);
+            if matched {
+                profile.nb_matches.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Compiles profiles for the rules of `rule_set` if it changed since the last update. Profiles
+    /// of rules that are still active are kept.
+    pub(crate) fn update(&self, rule_set: &RuleSet, generation: u64) {
+        if !self.is_enabled() || self.generation.swap(generation, Ordering::Relaxed) == generation
+        {
+            return;
+        }
+        let previous: HashMap<_, _> = self
+            .profiles
+            .read()
+            .unwrap()
+            .iter()
+            .map(|profile| (profile.rule.clone(), Arc::clone(profile)))
+            .collect();
+        let profiles = rule_set
+            .rules()
+            .filter_map(|rule| match previous.get(rule) {
+                Some(profile) => Some(Arc::clone(profile)),
+                None => match rule.regex() {
+                    Ok(regex) => Some(Arc::new(RuleProfile {
+                        rule: rule.clone(),
+                        regex,
+                        nb_samples: AtomicU64::new(0),
+                        nb_matches: AtomicU64::new(0),
+                        total_ns: AtomicU64::new(0),
+                    })),
+                    Err(error) => {
+                        log::error!("Failed to compile {} for profiling: {}", rule.pattern, error);
+                        None
+                    }
+                },
+            })
+            .collect();
+        *self.profiles.write().unwrap() = Arc::new(profiles);
+    }
+
+    /// Returns the cost of every profiled rule, most expensive first.
+    pub(crate) fn costs(&self) -> Vec<RuleCost> {
+        let mut costs = self
+            .profiles
+            .read()
+            .unwrap()
+            .iter()
+            .map(|profile| {
+                let nb_samples = profile.nb_samples.load(Ordering::Relaxed);
+                let total_ns = profile.total_ns.load(Ordering::Relaxed);
+                RuleCost {
+                    pattern: profile.rule.pattern.clone(),
+                    nb_samples,
+                    nb_matches: profile.nb_matches.load(Ordering::Relaxed),
+                    avg_ns: if nb_samples > 0 {
+                        total_ns as f64 / nb_samples as f64
+                    } else {
+                        0.0
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        costs.sort_by(|a, b| b.avg_ns.total_cmp(&a.avg_ns));
+        costs
+    }
+}
@@ -0,0 +1,246 @@
+//! Per-tenant storage quotas.
+//!
+//! In deployments shared by several tenants, each on its own VLANs, the `[[storage_quota]]`
+//! entries of the runtime configuration (see
+//! [StorageQuotaConfig](crate::config::StorageQuotaConfig)) bound the number of bytes the packets
+//! of each tenant may take in the [live packet tap](crate::filter::tap), the
+//! [rolling capture](crate::filter::capture) and the [collector queues](crate::filter::forward). Like the [VLAN policy](crate::filter::vlan), packets
+//! are keyed by their innermost VLAN ID, and the first entry that selects a packet applies.
+//! Packets selected by no entry are stored without limit.
+//!
+//! The usage of a tenant is the number of bytes queued for writing, i.e. at most the snapshot
+//! length per packet. Once a tenant reaches its quota, its packets are no longer stored and are
+//! counted as refused, until its usage is reset: every `period_secs` if set, or with
+//! [FilterCtx::reset_storage_quota](crate::filter::FilterCtx::reset_storage_quota). Matching,
+//! alerts and flow tracking are not affected, e.g.
+//! [FilterCtx::capture_packet](crate::filter::FilterCtx::capture_packet) still reports matching
+//! packets. Usage is reported by the monitor.
+//!
+//! ## Example
+//! ```toml
+//! [[storage_quota]]
+//!     tenant = "blue"
+//!     vlans = "100-199"
+//!     max_bytes = 50_000_000_000
+//!     period_secs = 86400
+//! ```
+
+use super::vlan::VlanSelector;
+use crate::config::StorageQuotaConfig;
+use crate::memory::mbuf::Mbuf;
+use crate::protocols::packet::ethernet::Ethernet;
+use crate::protocols::packet::Packet;
+use crate::timebase;
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+/// Storage usage of a tenant.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaStats {
+    pub tenant: String,
+    /// VLANs of the tenant.
+    pub vlans: String,
+    /// Quota, in bytes.
+    pub max_bytes: u64,
+    /// Number of bytes stored since the last reset.
+    pub nb_bytes: u64,
+    /// Number of packets stored.
+    pub nb_stored: u64,
+    /// Number of packets not stored because the quota was reached.
+    pub nb_refused: u64,
+    /// Whether the quota is currently reached.
+    pub exceeded: bool,
+}
+
+/// Quota and usage of a tenant.
+#[derive(Debug)]
+pub(crate) struct TenantQuota {
+    tenant: String,
+    selector: VlanSelector,
+    max_bytes: u64,
+    period: Option<Duration>,
+    /// Start of the current period, in nanoseconds since the UNIX epoch.
+    period_start: AtomicU64,
+    nb_bytes: AtomicU64,
+    nb_stored: AtomicU64,
+    nb_refused: AtomicU64,
+    exceeded: AtomicBool,
+}
+
+impl TenantQuota {
+    fn from_config(config: &StorageQuotaConfig) -> Result<Self> {
+        let selector = VlanSelector::parse(&config.vlans)
+            .with_context(|| format!("Storage quota of tenant {}", config.tenant))?;
+        Ok(TenantQuota {
+            tenant: config.tenant.clone(),
+            selector,
+            max_bytes: config.max_bytes,
+            period: config.period_secs.map(Duration::from_secs),
+            period_start: AtomicU64::new(timebase::now_ns()),
+            nb_bytes: AtomicU64::new(0),
+            nb_stored: AtomicU64::new(0),
+            nb_refused: AtomicU64::new(0),
+            exceeded: AtomicBool::new(false),
+        })
+    }
+
+    /// Stores a packet with `store`, which returns the number of bytes queued, unless the quota is
+    /// reached.
+    #[inline]
+    fn store(&self, store: impl FnOnce() -> usize) {
+        if let Some(period) = self.period {
+            let now = timebase::now_ns();
+            let start = self.period_start.load(Ordering::Relaxed);
+            if now.saturating_sub(start) >= period.as_nanos() as u64
+                && self
+                    .period_start
+                    .compare_exchange(start, now, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+            {
+                self.reset();
+            }
+        }
+        if self.nb_bytes.load(Ordering::Relaxed) >= self.max_bytes {
+            if !self.exceeded.swap(true, Ordering::Relaxed) {
+                log::warn!(
+                    "Tenant {} reached its storage quota of {} bytes, its packets are no longer \
+                     stored",
+                    self.tenant,
+                    self.max_bytes
+                );
+            }
+            self.nb_refused.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let nb_bytes = store();
+        if nb_bytes > 0 {
+            self.nb_bytes.fetch_add(nb_bytes as u64, Ordering::Relaxed);
+            self.nb_stored.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn reset(&self) {
+        self.nb_bytes.store(0, Ordering::Relaxed);
+        if self.exceeded.swap(false, Ordering::Relaxed) {
+            log::info!("Storage quota of tenant {} reset", self.tenant);
+        }
+    }
+
+    fn stats(&self) -> QuotaStats {
+        QuotaStats {
+            tenant: self.tenant.clone(),
+            vlans: self.selector.to_string(),
+            max_bytes: self.max_bytes,
+            nb_bytes: self.nb_bytes.load(Ordering::Relaxed),
+            nb_stored: self.nb_stored.load(Ordering::Relaxed),
+            nb_refused: self.nb_refused.load(Ordering::Relaxed),
+            exceeded: self.exceeded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Storage quotas shared by all copies of a filter, storing all packets until configured.
+#[derive(Debug, Default)]
+pub(crate) struct StorageQuotas {
+    tenants: RwLock<Vec<Arc<TenantQuota>>>,
+    /// Whether any quota is set, checked before taking the lock.
+    enabled: AtomicBool,
+}
+
+impl StorageQuotas {
+    pub(crate) fn new() -> Self {
+        StorageQuotas::default()
+    }
+
+    /// Replaces the quotas with the entries of the runtime configuration, resetting usage.
+    pub(crate) fn configure(&self, config: &[StorageQuotaConfig]) -> Result<()> {
+        let mut names = HashSet::new();
+        let tenants = config
+            .iter()
+            .map(|entry| {
+                if !names.insert(entry.tenant.as_str()) {
+                    bail!("Duplicate storage quota for tenant {}", entry.tenant);
+                }
+                Ok(Arc::new(TenantQuota::from_config(entry)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for tenant in tenants.iter() {
+            log::info!(
+                "Storage quota of tenant {} (VLANs {}): {} bytes",
+                tenant.tenant,
+                tenant.selector,
+                tenant.max_bytes
+            );
+        }
+        self.enabled.store(!tenants.is_empty(), Ordering::Relaxed);
+        *self.tenants.write().unwrap() = tenants;
+        Ok(())
+    }
+
+    /// Stores a packet with innermost VLAN ID `vlan_id` with `store`, which returns the number of
+    /// bytes queued, unless the quota of its tenant is reached.
+    #[inline]
+    pub(crate) fn store(&self, vlan_id: Option<u16>, store: impl FnOnce() -> usize) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            store();
+            return;
+        }
+        let tenant = self
+            .tenants
+            .read()
+            .unwrap()
+            .iter()
+            .find(|tenant| tenant.selector.selects(vlan_id))
+            .cloned();
+        match tenant {
+            Some(tenant) => tenant.store(store),
+            None => {
+                store();
+            }
+        }
+    }
+
+    /// Like [store](Self::store), keying `mbuf` by its Ethernet header. Packets that are not
+    /// Ethernet are stored without limit.
+    #[inline]
+    pub(crate) fn store_mbuf(&self, mbuf: &Mbuf, store: impl FnOnce() -> usize) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            store();
+            return;
+        }
+        match mbuf.parse_to::<Ethernet>() {
+            Ok(eth) => self.store(eth.get_last_vlan_id(), store),
+            Err(_) => {
+                store();
+            }
+        }
+    }
+
+    /// Resets the usage of `tenant`.
+    pub(crate) fn reset(&self, tenant: &str) -> Result<()> {
+        let tenants = self.tenants.read().unwrap();
+        match tenants.iter().find(|quota| quota.tenant == tenant) {
+            Some(quota) => {
+                quota.reset();
+                Ok(())
+            }
+            None => bail!("No storage quota for tenant {}", tenant),
+        }
+    }
+
+    /// Returns the usage of each tenant, in configuration order.
+    pub(crate) fn stats(&self) -> Vec<QuotaStats> {
+        self.tenants
+            .read()
+            .unwrap()
+            .iter()
+            .map(|tenant| tenant.stats())
+            .collect()
+    }
+}
@@ -0,0 +1,150 @@
+//! Throughput and burst metrics of matched flows.
+//!
+//! With the `[flow_rates]` options of the runtime configuration (see
+//! [FlowRateConfig](crate::config::FlowRateConfig)), the traffic of each flow is counted in fixed
+//! time buckets from its first match on, to measure how much data suspicious flows moved and how
+//! fast. Only packets tracked with [FilterCtx::track_flow](crate::filter::FilterCtx::track_flow)
+//! are counted, starting with the packet after the first match.
+//!
+//! Each flow keeps the most recent buckets, and its peak packet and byte rates over a single
+//! bucket since the first match. The metrics are included in the
+//! [FlowSummary](crate::hooks::FlowSummary) of end-of-flow hooks, and can be read for flows still
+//! in the flow table with [FilterCtx::flow_rates](crate::filter::FilterCtx::flow_rates).
+
+use crate::config::FlowRateConfig;
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Traffic of a flow during one time bucket.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RateBucket {
+    /// Number of packets.
+    pub nb_pkts: u64,
+    /// Number of bytes.
+    pub nb_bytes: u64,
+}
+
+/// Throughput metrics of a matched flow.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowRates {
+    /// Duration of a bucket.
+    pub bucket: Duration,
+    /// Time between the first match and the start of the first bucket of `buckets`.
+    pub offset: Duration,
+    /// Traffic of the most recent buckets, oldest first, including empty buckets.
+    pub buckets: Vec<RateBucket>,
+    /// Traffic since the first match.
+    pub total: RateBucket,
+    /// Highest packet rate over a bucket, in packets per second.
+    pub peak_pps: f64,
+    /// Highest byte rate over a bucket, in bytes per second.
+    pub peak_bps: f64,
+}
+
+/// Bucketed traffic of a flow since its first match.
+#[derive(Debug, Clone)]
+pub(crate) struct RateTracker {
+    /// Time of the first match.
+    start: Instant,
+    bucket: Duration,
+    nb_buckets: usize,
+    /// Index since `start` of the first bucket of `buckets`.
+    first: u64,
+    buckets: VecDeque<RateBucket>,
+    total: RateBucket,
+    /// Highest counts of a single bucket.
+    peak: RateBucket,
+}
+
+impl RateTracker {
+    /// Records a packet of `nb_bytes` bytes seen at `now`.
+    pub(crate) fn record(&mut self, now: Instant, nb_bytes: usize) {
+        let idx = (now.saturating_duration_since(self.start).as_nanos()
+            / self.bucket.as_nanos()) as u64;
+        let last = self.first + self.buckets.len() as u64;
+        if idx >= last + self.nb_buckets as u64 {
+            // Idle for longer than the kept history
+            self.buckets.clear();
+            self.first = idx;
+        }
+        while self.first + (self.buckets.len() as u64) <= idx {
+            if self.buckets.len() == self.nb_buckets {
+                self.buckets.pop_front();
+                self.first += 1;
+            }
+            self.buckets.push_back(RateBucket::default());
+        }
+        let bucket = self.buckets.back_mut().expect("tracker has no bucket");
+        bucket.nb_pkts += 1;
+        bucket.nb_bytes += nb_bytes as u64;
+        self.peak.nb_pkts = self.peak.nb_pkts.max(bucket.nb_pkts);
+        self.peak.nb_bytes = self.peak.nb_bytes.max(bucket.nb_bytes);
+        self.total.nb_pkts += 1;
+        self.total.nb_bytes += nb_bytes as u64;
+    }
+
+    /// Returns the metrics of the flow.
+    pub(crate) fn rates(&self) -> FlowRates {
+        let secs = self.bucket.as_secs_f64();
+        FlowRates {
+            bucket: self.bucket,
+            offset: self.bucket.mul_f64(self.first as f64),
+            buckets: self.buckets.iter().copied().collect(),
+            total: self.total,
+            peak_pps: self.peak.nb_pkts as f64 / secs,
+            peak_bps: self.peak.nb_bytes as f64 / secs,
+        }
+    }
+}
+
+/// Bucket settings shared by all copies of a filter, not tracking flows until configured.
+#[derive(Debug, Default)]
+pub(crate) struct RateSettings {
+    /// Bucket duration in microseconds, `0` if disabled.
+    bucket_us: AtomicU64,
+    nb_buckets: AtomicUsize,
+}
+
+impl RateSettings {
+    pub(crate) fn new() -> Self {
+        RateSettings::default()
+    }
+
+    /// Applies the `[flow_rates]` options of the runtime configuration.
+    pub(crate) fn configure(&self, config: Option<&FlowRateConfig>) {
+        let (bucket_us, nb_buckets) = match config {
+            Some(config) => (config.bucket_ms.max(1) * 1000, config.nb_buckets.max(1)),
+            None => (0, 0),
+        };
+        if let Some(config) = config {
+            log::info!(
+                "Tracking rates of matched flows over {} buckets of {} ms",
+                nb_buckets,
+                config.bucket_ms.max(1)
+            );
+        }
+        self.nb_buckets.store(nb_buckets, Ordering::Relaxed);
+        self.bucket_us.store(bucket_us, Ordering::Relaxed);
+    }
+
+    /// Returns a tracker starting at `now`, `None` if rates are not tracked.
+    pub(crate) fn tracker(&self, now: Instant) -> Option<Box<RateTracker>> {
+        let bucket_us = self.bucket_us.load(Ordering::Relaxed);
+        if bucket_us == 0 {
+            return None;
+        }
+        Some(Box::new(RateTracker {
+            start: now,
+            bucket: Duration::from_micros(bucket_us),
+            nb_buckets: self.nb_buckets.load(Ordering::Relaxed),
+            first: 0,
+            buckets: VecDeque::new(),
+            total: RateBucket::default(),
+            peak: RateBucket::default(),
+        }))
+    }
+}
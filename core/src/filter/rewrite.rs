@@ -0,0 +1,266 @@
+//! Egress rewrites of the inline TX path.
+//!
+//! Forwarding sinks (see [SinkBehavior::Forward](crate::config::SinkBehavior::Forward)) transmit
+//! the packets of their queues on another port. With the `egress_rewrite` options of that port (see
+//! [EgressRewriteConfig](crate::config::EgressRewriteConfig)), or with
+//! [FilterCtx::set_egress_rewrite](crate::filter::FilterCtx::set_egress_rewrite) at runtime, the
+//! destination MAC address of each packet is replaced and its outer VLAN tag stripped or replaced
+//! right before transmission, e.g. for the next hop of an inline deployment. Untagged frames are
+//! tagged with an 802.1Q tag by a VLAN replacement. Frames that cannot be rewritten, i.e. truncated
+//! frames or frames without headroom for a tag, are dropped rather than transmitted unmodified.
+//!
+//! Tags stripped by the NIC on reception (see [PortMap::vlan_strip](crate::config::PortMap)) are
+//! not in the frame, and are not restored on transmission.
+//!
+//! ## Example
+//! Move forwarded traffic to a new next hop on VLAN 300:
+//! ```
+//! let rewrite = EgressRewrite {
+//!     dst_mac: Some("0c:42:a1:00:00:02".parse()?),
+//!     vlan: VlanRewrite::Set(300),
+//! };
+//! filter_ctx.set_egress_rewrite("0000:3b:00.1", Some(rewrite))?;
+//! ```
+
+use crate::config::{EgressRewriteConfig, PortMap};
+use crate::memory::mbuf::Mbuf;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, bail, Context, Result};
+use pnet::datalink::MacAddr;
+
+/// Largest VLAN ID.
+const MAX_VLAN_ID: u16 = 4095;
+/// Length of the destination and source MAC addresses.
+const ADDRS_LEN: usize = 12;
+const TAG_LEN: usize = 4;
+const TPID_802_1Q: u16 = 0x8100;
+const TPID_802_1AD: u16 = 0x88a8;
+
+/// Rewrite of the outer VLAN tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VlanRewrite {
+    /// VLAN tags are kept.
+    #[default]
+    Keep,
+    /// The outer VLAN tag is removed.
+    Strip,
+    /// The VLAN ID of the outer tag is replaced, keeping its priority, and untagged frames are
+    /// tagged.
+    Set(u16),
+}
+
+impl VlanRewrite {
+    /// Parses `strip` or a VLAN ID such as `200`.
+    pub fn parse(rewrite: &str) -> Result<Self> {
+        match rewrite.trim() {
+            "strip" => Ok(VlanRewrite::Strip),
+            id => {
+                let id: u16 = id
+                    .parse()
+                    .with_context(|| format!("Invalid VLAN rewrite `{}`", id))?;
+                if id > MAX_VLAN_ID {
+                    bail!("VLAN ID {} is above {}", id, MAX_VLAN_ID);
+                }
+                Ok(VlanRewrite::Set(id))
+            }
+        }
+    }
+}
+
+impl fmt::Display for VlanRewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VlanRewrite::Keep => write!(f, "keep"),
+            VlanRewrite::Strip => write!(f, "strip"),
+            VlanRewrite::Set(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+/// Rewrite of the packets transmitted on a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EgressRewrite {
+    /// New destination MAC address, `None` to keep it.
+    pub dst_mac: Option<MacAddr>,
+    pub vlan: VlanRewrite,
+}
+
+impl EgressRewrite {
+    pub fn from_config(config: &EgressRewriteConfig) -> Result<Self> {
+        let dst_mac = match &config.dst_mac {
+            Some(mac) => Some(
+                mac.parse()
+                    .map_err(|_| anyhow!("Invalid destination MAC address `{}`", mac))?,
+            ),
+            None => None,
+        };
+        let vlan = match &config.vlan {
+            Some(vlan) => VlanRewrite::parse(vlan)?,
+            None => VlanRewrite::Keep,
+        };
+        Ok(EgressRewrite { dst_mac, vlan })
+    }
+
+    /// Rewrites the frame in `mbuf`.
+    fn apply(&self, mbuf: &mut Mbuf) -> Result<()> {
+        if mbuf.data_len() < ADDRS_LEN + 2 {
+            bail!("Truncated Ethernet header");
+        }
+        let tpid = u16::from_be_bytes([mbuf.data()[ADDRS_LEN], mbuf.data()[ADDRS_LEN + 1]]);
+        let tagged = tpid == TPID_802_1Q || tpid == TPID_802_1AD;
+        if tagged && mbuf.data_len() < ADDRS_LEN + TAG_LEN + 2 {
+            bail!("Truncated VLAN tag");
+        }
+        match self.vlan {
+            VlanRewrite::Keep => (),
+            VlanRewrite::Strip if tagged => {
+                mbuf.data_mut().copy_within(..ADDRS_LEN, TAG_LEN);
+                mbuf.adj(TAG_LEN as u16)?;
+            }
+            VlanRewrite::Strip => (),
+            VlanRewrite::Set(id) if tagged => {
+                let data = mbuf.data_mut();
+                let tci = u16::from_be_bytes([data[ADDRS_LEN + 2], data[ADDRS_LEN + 3]]);
+                let tci = (tci & !MAX_VLAN_ID) | id;
+                data[ADDRS_LEN + 2..ADDRS_LEN + 4].copy_from_slice(&tci.to_be_bytes());
+            }
+            VlanRewrite::Set(id) => {
+                mbuf.prepend(TAG_LEN as u16)?;
+                let data = mbuf.data_mut();
+                data.copy_within(TAG_LEN..TAG_LEN + ADDRS_LEN, 0);
+                data[ADDRS_LEN..ADDRS_LEN + 2].copy_from_slice(&TPID_802_1Q.to_be_bytes());
+                data[ADDRS_LEN + 2..ADDRS_LEN + 4].copy_from_slice(&id.to_be_bytes());
+            }
+        }
+        if let Some(MacAddr(a, b, c, d, e, f)) = self.dst_mac {
+            mbuf.data_mut()[..6].copy_from_slice(&[a, b, c, d, e, f]);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for EgressRewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.dst_mac {
+            Some(mac) => write!(f, "dst_mac {}", mac)?,
+            None => write!(f, "dst_mac keep")?,
+        }
+        write!(f, ", vlan {}", self.vlan)
+    }
+}
+
+/// Rewrite counters of a port.
+#[derive(Debug, Clone)]
+pub struct RewriteStats {
+    /// PCI address of the port.
+    pub device: String,
+    /// Current rewrite, `None` if packets are transmitted unmodified.
+    pub rewrite: Option<EgressRewrite>,
+    /// Number of packets rewritten.
+    pub nb_rewritten: u64,
+    /// Number of packets dropped because they could not be rewritten.
+    pub nb_dropped: u64,
+}
+
+/// Egress rewrite of a port, shared with the sinks forwarding to it.
+#[derive(Debug, Default)]
+pub(crate) struct PortRewrite {
+    rewrite: RwLock<Option<EgressRewrite>>,
+    nb_rewritten: AtomicU64,
+    nb_dropped: AtomicU64,
+}
+
+impl PortRewrite {
+    /// Rewrites the packets of a burst to transmit, dropping those that cannot be rewritten.
+    pub(crate) fn apply(&self, mut mbufs: Vec<Mbuf>) -> Vec<Mbuf> {
+        let rewrite = match *self.rewrite.read().unwrap() {
+            Some(rewrite) => rewrite,
+            None => return mbufs,
+        };
+        let nb_pkts = mbufs.len();
+        mbufs.retain_mut(|mbuf| match rewrite.apply(mbuf) {
+            Ok(_) => true,
+            Err(error) => {
+                log::debug!("Egress rewrite error: {}", error);
+                false
+            }
+        });
+        self.nb_rewritten.fetch_add(mbufs.len() as u64, Ordering::Relaxed);
+        self.nb_dropped.fetch_add((nb_pkts - mbufs.len()) as u64, Ordering::Relaxed);
+        mbufs
+    }
+}
+
+/// Egress rewrites of the online ports, shared by all copies of a filter.
+#[derive(Debug, Default)]
+pub(crate) struct EgressRewrites {
+    ports: RwLock<BTreeMap<String, Arc<PortRewrite>>>,
+}
+
+impl EgressRewrites {
+    pub(crate) fn new() -> Self {
+        EgressRewrites::default()
+    }
+
+    /// Registers the online ports and their configured rewrites.
+    pub(crate) fn configure(&self, port_maps: &[PortMap]) -> Result<()> {
+        let mut ports = self.ports.write().unwrap();
+        ports.clear();
+        for port_map in port_maps {
+            let rewrite = match &port_map.egress_rewrite {
+                Some(config) => {
+                    let rewrite = EgressRewrite::from_config(config)
+                        .with_context(|| format!("Egress rewrite of {}", port_map.device))?;
+                    log::info!("Egress rewrite of {}: {}", port_map.device, rewrite);
+                    Some(rewrite)
+                }
+                None => None,
+            };
+            let port = PortRewrite {
+                rewrite: RwLock::new(rewrite),
+                ..Default::default()
+            };
+            ports.insert(port_map.device.clone(), Arc::new(port));
+        }
+        Ok(())
+    }
+
+    /// Returns the rewrite of online port `device`.
+    pub(crate) fn port(&self, device: &str) -> Option<Arc<PortRewrite>> {
+        self.ports.read().unwrap().get(device).cloned()
+    }
+
+    /// Replaces the rewrite of online port `device`, `None` to transmit packets unmodified.
+    pub(crate) fn set(&self, device: &str, rewrite: Option<EgressRewrite>) -> Result<()> {
+        let port = match self.port(device) {
+            Some(port) => port,
+            None => bail!("{} is not an online port", device),
+        };
+        match &rewrite {
+            Some(rewrite) => log::info!("Egress rewrite of {}: {}", device, rewrite),
+            None => log::info!("Egress rewrite of {} cleared", device),
+        }
+        *port.rewrite.write().unwrap() = rewrite;
+        Ok(())
+    }
+
+    /// Returns the counters of each online port, in device order.
+    pub(crate) fn stats(&self) -> Vec<RewriteStats> {
+        self.ports
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(device, port)| RewriteStats {
+                device: device.clone(),
+                rewrite: *port.rewrite.read().unwrap(),
+                nb_rewritten: port.nb_rewritten.load(Ordering::Relaxed),
+                nb_dropped: port.nb_dropped.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,1609 @@
+//! Payload matching rules.
+//!
+//! A rule is a regular expression matched against packet payloads, along with metadata that
+//! controls its lifetime. Rules are loaded into a [FilterCtx](crate::filter::FilterCtx), which
+//! shares them with all packet processing cores.
+//!
+//! Rules are spread over a fixed number of shards by pattern hash, and each shard is compiled into
+//! its own `RegexSet`. When a new rule list is loaded, only the shards whose rules changed are
+//! recompiled, in parallel, so small updates to a large rule set are fast. Packet processing keeps
+//! using the previous rule set until compilation is done.
+//!
+//! ## Example
+//! A rule that ages out one day after it is loaded:
+//! ```json
+//! { "pattern": "evil\\.example\\.com", "ttl_seconds": 86400 }
+//! ```
+//! A rule that only applies to flows identified as HTTP:
+//! ```json
+//! { "pattern": "(?i)user-agent: sqlmap", "app": "http" }
+//! ```
+//! A rule that only looks at bytes 4 to 11 of each payload:
+//! ```json
+//! { "pattern": "\\xffSMB", "offset": 4, "depth": 8 }
+//! ```
+//! A rule that only applies to flows whose frames carry the service tag (outer VLAN) 120:
+//! ```json
+//! { "pattern": "evil\\.example\\.com", "s_tag": 120 }
+//! ```
+//! A rule that only applies to IPv6 flows whose packets carry a flow label from `0x100` to
+//! `0x1ff`:
+//! ```json
+//! { "pattern": "evil\\.example\\.com", "flow_label": "256-511" }
+//! ```
+//! A rule that only applies to flows with an endpoint whose MAC address has the OUI `00:1b:21`:
+//! ```json
+//! { "pattern": "(?i)firmware", "mac": "00:1b:21" }
+//! ```
+//! A rule that only counts the packets containing a marker, without matching their flows:
+//! ```json
+//! { "pattern": "X-Canary: 1", "action": "count" }
+//! ```
+//! A rule that writes the packets containing a marker to the rolling capture, for triage:
+//! ```json
+//! { "pattern": "\\x16\\x03\\x00", "action": "capture" }
+//! ```
+//! A rule that only looks at payloads of 40 to 1500 bytes:
+//! ```json
+//! { "pattern": "\\xffSMB", "min_len": 40, "max_len": 1500 }
+//! ```
+//! A case-insensitive rule whose `.` also matches line feeds:
+//! ```json
+//! { "pattern": "<script>.*</script>", "nocase": true, "dotall": true }
+//! ```
+//! A rule whose matched flows are stored to the `forensics` store target, configured with the
+//! `[[store_target]]` options, ahead of lower-priority rules:
+//! ```json
+//! { "pattern": "(?i)x-exfil-token", "store_target": "forensics", "priority": 10 }
+//! ```
+//! A rule whose matched flows are stored with headers and the first 256 payload bytes of their
+//! TCP packets only:
+//! ```json
+//! { "pattern": "(?i)^get /download", "snaplen": 310 }
+//! ```
+//! A rule labeled with the technique and campaign it detects:
+//! ```json
+//! { "pattern": "(?i)powershell -enc", "tags": { "mitre": "T1059.001", "campaign": "winter" } }
+//! ```
+//!
+//! ## Regex flags
+//! The `nocase`, `dotall` and `multiline` flags of a rule are applied as regex builder options
+//! when its regex set is compiled, instead of being written as inline flags in the pattern. Rules
+//! that leave a flag unset get its default from the `[regex]` options of the runtime
+//! configuration (see [RegexConfig](crate::config::RegexConfig)) when they are loaded.
+//!
+//! ## Counting rules
+//! Rules with the `count` action are compiled into their own regex sets, which never affect whether
+//! a payload matches. They are evaluated after the matching rules have decided, in a single pass
+//! over each of their regex sets. Their counters (see [RuleCount](RuleCount)) are kept across rule
+//! updates as long as the rule is unchanged.
+//!
+//! ## Capture rules
+//! Rules with the `capture` action are also compiled into their own regex sets. They are only
+//! evaluated by [FilterCtx::capture_packet](crate::filter::FilterCtx::capture_packet), which
+//! writes the packets they match to the [rolling capture](crate::filter::capture).
+//!
+//! ## Generations
+//! Every rule set loaded into the filter is tagged with a generation number, incremented on every
+//! update. Each RX core records the generation of the rule set it matches against, so that
+//! [FilterCtx::rule_generations](crate::filter::FilterCtx::rule_generations) can confirm that an
+//! update reached all cores (see [RuleGenerations](RuleGenerations)). The compile time and
+//! estimated size of every loaded rule set are recorded as [CompileStats](CompileStats).
+//!
+//! ## Length gates
+//! Rules with `min_len` or `max_len` are grouped with the rules that share their gates, and the
+//! regex sets of a group are only evaluated on payloads within them. Payloads bypassing a group
+//! are counted as gated in the [scan statistics](crate::filter::scan::ScanStats::nb_rule_gated).
+//!
+//! Batches of payloads can be scanned one group at a time, on the CPU or offloaded to another
+//! [matching backend](crate::filter::backend).
+//!
+//! ## Store targets
+//! The packets of matched flows, and of payloads matching capture rules, are stored to the
+//! `store_target` of the highest-priority rule they matched (see
+//! [StoreTargetConfig](crate::config::StoreTargetConfig)). Rules of equal priority are ranked by
+//! pattern, and a flow keeps the target of its first match unless a later payload matches a rule
+//! of strictly higher priority. Rules without a target, or with a target that is not configured,
+//! leave their packets to the default rolling capture and collectors. See
+//! [FilterCtx::store_packet](crate::filter::FilterCtx::store_packet).
+//!
+//! The `snaplen` of the same rule cuts the copies of the flow's packets queued to the rolling
+//! captures, the live packet [tap](crate::filter::tap) and the collectors in place of their
+//! configured snapshot lengths, to the smaller of both since stored files declare the configured
+//! one as their maximum. The original length of truncated packets is kept in their record
+//! headers.
+//!
+//! ## Tags
+//! Rules can carry arbitrary key/value `tags`, which do not affect matching. They are kept with the
+//! compiled rules and copied verbatim into the [match alerts](crate::filter::alert) and the
+//! [flow summaries](crate::hooks::FlowSummary::matched_tags) of the flows the rule matches.
+//!
+//! ## Test vectors
+//! Rules can carry examples of payloads they must match, `should_match`, and must not match,
+//! `should_not_match`. Every rule loaded with
+//! [FilterCtx::load_rules](crate::filter::FilterCtx::load_rules) is checked against its examples,
+//! with its regex flags, window and length gates, but regardless of its scope. Rules that fail are
+//! rejected, or loaded and flagged, depending on the `failed_tests` option of the `[regex]`
+//! section (see [RegexConfig](crate::config::RegexConfig)), and reported by
+//! [FilterCtx::rule_test_failures](crate::filter::FilterCtx::rule_test_failures):
+//! ```json
+//! {
+//!     "pattern": "(?i)user-agent: sqlmap",
+//!     "should_match": ["User-Agent: sqlmap/1.7"],
+//!     "should_not_match": ["User-Agent: curl/8.0"]
+//! }
+//! ```
+
+use super::backend::{self, MatchBackend};
+use crate::config::{MatchBackendConfig, RegexConfig};
+use crate::protocols::app::AppProtocol;
+use crate::utils::hash::stable_hash;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use pnet::datalink::MacAddr;
+use regex::bytes::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use serde::{Deserialize, Serialize};
+
+/// A payload matching rule.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rule {
+    /// Regular expression matched against packet payloads.
+    pub pattern: String,
+
+    /// UNIX timestamp (in seconds) at which the rule expires. Defaults to `None` (never).
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+
+    /// Lifetime of the rule (in seconds), counted from when it is loaded. Defaults to `None`
+    /// (never). If both `expires_at` and `ttl_seconds` are set, the earliest deadline applies.
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+
+    /// Application protocol the rule is scoped to. Defaults to `None` (all flows). See
+    /// [app](crate::protocols::app) for how flows are identified.
+    #[serde(default)]
+    pub app: Option<AppProtocol>,
+
+    /// Number of bytes skipped at the start of each packet payload before matching. Defaults to
+    /// `0`.
+    #[serde(default)]
+    pub offset: usize,
+
+    /// Number of bytes matched, starting at `offset`. Defaults to `None` (up to the end of the
+    /// payload).
+    #[serde(default)]
+    pub depth: Option<usize>,
+
+    /// Minimum payload length, in bytes, of the packets the rule is matched against. Defaults to
+    /// `None` (no minimum).
+    #[serde(default)]
+    pub min_len: Option<usize>,
+
+    /// Maximum payload length, in bytes, of the packets the rule is matched against. Defaults to
+    /// `None` (no maximum).
+    #[serde(default)]
+    pub max_len: Option<usize>,
+
+    /// Service tag (outermost VLAN ID of frames with stacked tags) the rule is scoped to. Defaults
+    /// to `None` (all flows).
+    #[serde(default)]
+    pub s_tag: Option<u16>,
+
+    /// Customer tag (innermost VLAN ID) the rule is scoped to. Defaults to `None` (all flows).
+    #[serde(default)]
+    pub c_tag: Option<u16>,
+
+    /// MAC address or OUI the rule is scoped to. Defaults to `None` (all flows).
+    ///
+    /// ## Remarks
+    /// The rule applies to flows with an endpoint observed with a matching MAC address in the
+    /// [neighbor table](crate::filter::neighbors), so it never applies unless the table is
+    /// enabled. Hosts behind a router are observed with the MAC address of the router.
+    #[serde(default)]
+    pub mac: Option<MacPattern>,
+
+    /// Range of IPv6 flow labels the rule is scoped to, e.g. `"256-511"`, or a single flow label.
+    /// Defaults to `None` (all flows).
+    ///
+    /// ## Remarks
+    /// The rule applies to flows whose last packet tracked in the flow table carried a flow label
+    /// within the range, so it never applies to IPv4 flows.
+    #[serde(default)]
+    pub flow_label: Option<FlowLabelRange>,
+
+    /// Whether letters match both cases. Defaults to `None` (the `[regex]` default).
+    #[serde(default)]
+    pub nocase: Option<bool>,
+
+    /// Whether `.` also matches line feeds. Defaults to `None` (the `[regex]` default).
+    #[serde(default)]
+    pub dotall: Option<bool>,
+
+    /// Whether `^` and `$` also match at line boundaries. Defaults to `None` (the `[regex]`
+    /// default).
+    #[serde(default)]
+    pub multiline: Option<bool>,
+
+    /// What happens to payloads matching the rule. Defaults to `match`.
+    #[serde(default)]
+    pub action: RuleAction,
+
+    /// Name of the store target the packets the rule matches are stored to, see
+    /// [store targets](crate::filter::rule#store-targets). Defaults to `None` (the default rolling
+    /// capture and collectors).
+    #[serde(default)]
+    pub store_target: Option<String>,
+
+    /// Priority of the rule when several rules with different store targets match, the highest
+    /// winning. Defaults to `0`.
+    #[serde(default)]
+    pub priority: i32,
+
+    /// Maximum number of bytes stored of each packet the rule routes to storage, see
+    /// [store targets](crate::filter::rule#store-targets). Defaults to `None` (the snapshot
+    /// lengths of the rolling captures, the tap and the collectors).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snaplen: Option<usize>,
+
+    /// Labels of the rule, e.g. a MITRE technique or campaign name, copied into alerts and flow
+    /// summaries. Defaults to no tags.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+
+    /// Example payloads the rule must match, checked when it is loaded. Defaults to none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub should_match: Vec<String>,
+
+    /// Example payloads the rule must not match, checked when it is loaded. Defaults to none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub should_not_match: Vec<String>,
+}
+
+/// Action taken on payloads matching a rule.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// The payload matches: its flow is marked matched and alerts are published.
+    #[default]
+    Match,
+    /// The packet is only counted against the rule, and does not match.
+    Count,
+    /// The packet is written to the rolling capture, and does not match.
+    Capture,
+}
+
+/// A rule whose test vectors failed, see [test vectors](crate::filter::rule#test-vectors).
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleTestFailure {
+    /// Pattern of the rule.
+    pub pattern: String,
+    /// Examples of `should_match` that the rule does not match.
+    pub missed: Vec<String>,
+    /// Examples of `should_not_match` that the rule matches.
+    pub false_positives: Vec<String>,
+    /// Whether the rule was rejected, rather than loaded and flagged.
+    pub rejected: bool,
+}
+
+/// Match counters of a counting rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleCount {
+    /// The counting rule.
+    pub rule: Rule,
+    /// Number of matching packets.
+    pub nb_pkts: u64,
+    /// Number of payload bytes of the matching packets.
+    pub nb_bytes: u64,
+}
+
+impl Rule {
+    /// Creates a rule from `pattern` that never expires.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Rule {
+            pattern: pattern.into(),
+            expires_at: None,
+            ttl_seconds: None,
+            app: None,
+            offset: 0,
+            depth: None,
+            min_len: None,
+            max_len: None,
+            s_tag: None,
+            c_tag: None,
+            mac: None,
+            flow_label: None,
+            nocase: None,
+            dotall: None,
+            multiline: None,
+            action: RuleAction::Match,
+            store_target: None,
+            priority: 0,
+            snaplen: None,
+            tags: BTreeMap::new(),
+            should_match: vec![],
+            should_not_match: vec![],
+        }
+    }
+
+    /// Returns whether the rule takes precedence over `other` for routing stored packets: it has
+    /// a higher priority, or the same priority and a smaller pattern.
+    pub(crate) fn outranks(&self, other: &Rule) -> bool {
+        (self.priority, Reverse(&self.pattern)) > (other.priority, Reverse(&other.pattern))
+    }
+
+    /// Returns the highest-ranked of `rules`, `None` if empty.
+    pub(crate) fn highest<'a>(rules: impl IntoIterator<Item = &'a Rule>) -> Option<&'a Rule> {
+        rules.into_iter().fold(None, |best, rule| match best {
+            Some(best) if !rule.outranks(best) => Some(best),
+            _ => Some(rule),
+        })
+    }
+
+    /// Sets the regex flags the rule leaves unset to their value in `defaults`.
+    pub(crate) fn resolve_flags(&mut self, defaults: &RegexConfig) {
+        self.nocase.get_or_insert(defaults.nocase);
+        self.dotall.get_or_insert(defaults.dotall);
+        self.multiline.get_or_insert(defaults.multiline);
+    }
+
+    /// Returns the regex flags of the rule, unset flags being disabled.
+    pub(crate) fn flags(&self) -> RegexFlags {
+        RegexFlags {
+            nocase: self.nocase.unwrap_or(false),
+            dotall: self.dotall.unwrap_or(false),
+            multiline: self.multiline.unwrap_or(false),
+        }
+    }
+
+    /// Compiles the pattern of the rule with its regex flags.
+    pub(crate) fn regex(&self) -> Result<Regex, regex::Error> {
+        let flags = self.flags();
+        RegexBuilder::new(&self.pattern)
+            .case_insensitive(flags.nocase)
+            .dot_matches_new_line(flags.dotall)
+            .multi_line(flags.multiline)
+            .build()
+    }
+
+    /// Checks the rule against its test vectors. Returns the examples that failed, `None` if all
+    /// passed or the pattern does not compile.
+    pub(crate) fn run_tests(&self) -> Option<RuleTestFailure> {
+        if self.should_match.is_empty() && self.should_not_match.is_empty() {
+            return None;
+        }
+        let regex = self.regex().ok()?;
+        let matches = |example: &&String| {
+            let len = example.len();
+            self.min_len.map_or(true, |min_len| len >= min_len)
+                && self.max_len.map_or(true, |max_len| len <= max_len)
+                && regex.is_match(window(example.as_bytes(), self.offset, self.depth))
+        };
+        let missed: Vec<String> = self
+            .should_match
+            .iter()
+            .filter(|example| !matches(example))
+            .cloned()
+            .collect();
+        let false_positives: Vec<String> = self
+            .should_not_match
+            .iter()
+            .filter(matches)
+            .cloned()
+            .collect();
+        if missed.is_empty() && false_positives.is_empty() {
+            return None;
+        }
+        Some(RuleTestFailure {
+            pattern: self.pattern.clone(),
+            missed,
+            false_positives,
+            rejected: false,
+        })
+    }
+
+    /// Returns whether the rule is restricted to some flows.
+    fn is_scoped(&self) -> bool {
+        self.app.is_some()
+            || self.s_tag.is_some()
+            || self.c_tag.is_some()
+            || self.mac.is_some()
+            || self.flow_label.is_some()
+    }
+
+    /// Returns whether the rule applies to payloads of a flow with properties `scope`.
+    #[inline]
+    pub(crate) fn applies_to(&self, scope: &FlowScope) -> bool {
+        self.app.map_or(true, |app| app == scope.app)
+            && self.s_tag.map_or(true, |tag| Some(tag) == scope.s_tag)
+            && self.c_tag.map_or(true, |tag| Some(tag) == scope.c_tag)
+            && self.mac.map_or(true, |mac| {
+                scope.macs.iter().flatten().any(|addr| mac.matches(addr))
+            })
+            && self.flow_label.map_or(true, |range| {
+                scope.flow_label.map_or(false, |label| range.contains(label))
+            })
+    }
+
+    /// Returns the time at which the rule expires if it was loaded at `loaded`.
+    fn deadline(&self, loaded: SystemTime) -> Option<SystemTime> {
+        let expires_at = self
+            .expires_at
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        let ttl = self.ttl_seconds.map(|secs| loaded + Duration::from_secs(secs));
+        match (expires_at, ttl) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Regex builder options of a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub(crate) struct RegexFlags {
+    nocase: bool,
+    dotall: bool,
+    multiline: bool,
+}
+
+impl RegexFlags {
+    /// Compiles `patterns` into a regex set with these options.
+    fn regex_set<'a>(
+        &self,
+        patterns: impl IntoIterator<Item = &'a String>,
+    ) -> Result<RegexSet, regex::Error> {
+        RegexSetBuilder::new(patterns)
+            .case_insensitive(self.nocase)
+            .dot_matches_new_line(self.dotall)
+            .multi_line(self.multiline)
+            .build()
+    }
+}
+
+/// Properties of the flow a payload belongs to, checked against the scope of rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FlowScope {
+    pub(crate) app: AppProtocol,
+    pub(crate) s_tag: Option<u16>,
+    pub(crate) c_tag: Option<u16>,
+    /// Observed MAC addresses of the flow endpoints.
+    pub(crate) macs: [Option<MacAddr>; 2],
+    /// IPv6 flow label of the flow, `None` for IPv4 flows.
+    pub(crate) flow_label: Option<u32>,
+}
+
+impl FlowScope {
+    /// Returns the scope of a flow identified as `app`, without VLAN tags or MAC addresses.
+    pub(crate) fn app(app: AppProtocol) -> Self {
+        FlowScope {
+            app,
+            s_tag: None,
+            c_tag: None,
+            macs: [None, None],
+            flow_label: None,
+        }
+    }
+}
+
+/// A MAC address, or an OUI that matches all MAC addresses starting with it. Written as
+/// colon-separated hexadecimal octets, e.g. `"00:1b:21:3a:4f:10"` or `"00:1b:21"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MacPattern {
+    octets: [u8; 6],
+    /// Number of significant octets, `3` for an OUI and `6` for a MAC address.
+    len: usize,
+}
+
+impl MacPattern {
+    /// Returns whether `mac` is the address or starts with the OUI.
+    #[inline]
+    pub fn matches(&self, mac: &MacAddr) -> bool {
+        let octets = [mac.0, mac.1, mac.2, mac.3, mac.4, mac.5];
+        octets[..self.len] == self.octets[..self.len]
+    }
+}
+
+impl FromStr for MacPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid MAC address or OUI: {}", s);
+        let mut octets = [0; 6];
+        let mut len = 0;
+        for part in s.split(|c| c == ':' || c == '-') {
+            if len == octets.len() || part.len() != 2 {
+                return Err(invalid());
+            }
+            octets[len] = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+            len += 1;
+        }
+        if len != 3 && len != 6 {
+            return Err(invalid());
+        }
+        Ok(MacPattern { octets, len })
+    }
+}
+
+impl TryFrom<String> for MacPattern {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for MacPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let octets: Vec<_> = self.octets[..self.len]
+            .iter()
+            .map(|octet| format!("{octet:02x}"))
+            .collect();
+        write!(f, "{}", octets.join(":"))
+    }
+}
+
+impl From<MacPattern> for String {
+    fn from(mac: MacPattern) -> Self {
+        mac.to_string()
+    }
+}
+
+/// An inclusive range of IPv6 flow labels. Written as `"low-high"`, e.g. `"256-511"`, or as a
+/// single flow label, in decimal or with a `0x` prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FlowLabelRange {
+    low: u32,
+    high: u32,
+}
+
+impl FlowLabelRange {
+    /// Returns whether `label` is within the range.
+    #[inline]
+    pub fn contains(&self, label: u32) -> bool {
+        (self.low..=self.high).contains(&label)
+    }
+}
+
+impl FromStr for FlowLabelRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse_label = |label: &str| -> Result<u32> {
+            let label = label.trim();
+            let parsed = match label.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => label.parse(),
+            };
+            match parsed {
+                Ok(label) if label <= MAX_FLOW_LABEL => Ok(label),
+                _ => Err(anyhow!("Invalid IPv6 flow label: {}", label)),
+            }
+        };
+        let (low, high) = match s.split_once('-') {
+            Some((low, high)) => (parse_label(low)?, parse_label(high)?),
+            None => (parse_label(s)?, parse_label(s)?),
+        };
+        if low > high {
+            return Err(anyhow!("Empty flow label range: {}", s));
+        }
+        Ok(FlowLabelRange { low, high })
+    }
+}
+
+impl TryFrom<String> for FlowLabelRange {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for FlowLabelRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.low == self.high {
+            true => write!(f, "{}", self.low),
+            false => write!(f, "{}-{}", self.low, self.high),
+        }
+    }
+}
+
+impl From<FlowLabelRange> for String {
+    fn from(range: FlowLabelRange) -> Self {
+        range.to_string()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    nb_pkts: AtomicU64,
+    nb_bytes: AtomicU64,
+}
+
+/// A rule loaded into the filter.
+#[derive(Debug, Clone)]
+struct ActiveRule {
+    rule: Rule,
+    deadline: Option<SystemTime>,
+    /// Match counters, for counting rules.
+    counter: Option<Arc<Counter>>,
+    /// Regex of the rule alone, compiled on its first match to locate its matches.
+    regex: Arc<OnceLock<Option<Regex>>>,
+}
+
+impl ActiveRule {
+    /// Loads `rule` at `loaded`. A rule already in `previous`, the rules being replaced, keeps its
+    /// deadline and counter, so that reloading it does not restart its lifetime or its counts.
+    fn new(rule: Rule, loaded: SystemTime, previous: &HashMap<&Rule, &ActiveRule>) -> Self {
+        if let Some(active) = previous.get(&rule) {
+            return ActiveRule {
+                rule,
+                deadline: active.deadline,
+                counter: active.counter.clone(),
+                regex: Arc::clone(&active.regex),
+            };
+        }
+        let counter = match rule.action {
+            RuleAction::Match | RuleAction::Capture => None,
+            RuleAction::Count => Some(Arc::default()),
+        };
+        ActiveRule {
+            deadline: rule.deadline(loaded),
+            rule,
+            counter,
+            regex: Arc::default(),
+        }
+    }
+
+    /// Returns the regex of the rule alone, `None` if it does not compile on its own.
+    fn regex(&self) -> Option<&Regex> {
+        self.regex.get_or_init(|| self.rule.regex().ok()).as_ref()
+    }
+}
+
+/// Matching rules a payload matches.
+#[derive(Debug, Default)]
+pub(crate) struct RuleMatches<'a> {
+    pub(crate) rules: Vec<&'a Rule>,
+    /// Offset in the payload of the end of the first match to end, `None` if no rule matched or
+    /// none could be compiled alone.
+    pub(crate) end: Option<usize>,
+    /// Span in the payload of the first match of the first rule of `rules`, `None` if it could not
+    /// be compiled alone.
+    pub(crate) first: Option<Range<usize>>,
+}
+
+/// Largest IPv6 flow label, which is 20 bits long.
+const MAX_FLOW_LABEL: u32 = 0xfffff;
+/// Number of shards of a rule set. Each shard is compiled into its own `RegexSet`.
+const NB_SHARDS: usize = 16;
+/// Maximum number of threads used to compile shards.
+const MAX_COMPILE_THREADS: usize = 4;
+/// Estimated heap bytes of a compiled regex per byte of pattern.
+const HEAP_PER_PATTERN_BYTE: usize = 64;
+/// Estimated fixed heap bytes of a compiled regex set.
+const HEAP_PER_REGEX_SET: usize = 16 * 1024;
+
+/// Returns the shard of `pattern`.
+fn shard_of(pattern: &str) -> usize {
+    stable_hash(pattern, 0) as usize % NB_SHARDS
+}
+
+/// Rules of a shard that share the same payload window, length gates and regex flags, compiled
+/// into one regex set.
+#[derive(Debug, Clone)]
+struct Group {
+    offset: usize,
+    depth: Option<usize>,
+    /// Inclusive payload length range the group is evaluated on.
+    min_len: usize,
+    max_len: usize,
+    /// Index in the shard rules of each pattern of `regexes`.
+    rules: Vec<usize>,
+    regexes: RegexSet,
+    /// Backend batch scans are offloaded to, `None` if they run on `regexes`.
+    offload: Option<Arc<dyn MatchBackend>>,
+    /// Whether any rule of the group is scoped to an application protocol or VLAN tag.
+    scoped: bool,
+}
+
+impl Group {
+    /// Returns the part of `payload` within the window of the group.
+    #[inline]
+    fn window<'a>(&self, payload: &'a [u8]) -> &'a [u8] {
+        window(payload, self.offset, self.depth)
+    }
+
+    /// Returns whether payloads of `len` bytes are within the length gates of the group.
+    #[inline]
+    fn in_gates(&self, len: usize) -> bool {
+        (self.min_len..=self.max_len).contains(&len)
+    }
+
+    /// Returns whether the group is evaluated on payloads of `len` bytes, counting the payloads
+    /// that bypass it in `gated`.
+    #[inline]
+    fn admits(&self, len: usize, gated: &AtomicU64) -> bool {
+        if !self.in_gates(len) {
+            gated.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Returns the backend of batch scans.
+    #[inline]
+    fn batch_backend(&self) -> &dyn MatchBackend {
+        match &self.offload {
+            Some(offload) => offload.as_ref(),
+            None => &self.regexes,
+        }
+    }
+}
+
+/// Returns the part of `payload` starting at `offset`, at most `depth` bytes long.
+#[inline]
+pub(crate) fn window(payload: &[u8], offset: usize, depth: Option<usize>) -> &[u8] {
+    let start = offset.min(payload.len());
+    let end = match depth {
+        Some(depth) => start.saturating_add(depth).min(payload.len()),
+        None => payload.len(),
+    };
+    &payload[start..end]
+}
+
+/// A subset of the rules and its compiled regex sets.
+#[derive(Debug, Clone)]
+struct Shard {
+    rules: Vec<ActiveRule>,
+    groups: Vec<Group>,
+    /// Groups of counting rules.
+    count_groups: Vec<Group>,
+    /// Groups of capture rules.
+    capture_groups: Vec<Group>,
+    /// Whether any rule of the shard has tags.
+    has_tags: bool,
+    /// Whether any rule of the shard has a snapshot length.
+    has_snaplens: bool,
+}
+
+impl Shard {
+    /// Compiles `rules`, offloading the batch scans of groups of matching rules to `backend`.
+    fn new(rules: Vec<ActiveRule>, backend: Option<&MatchBackendConfig>) -> Result<Self> {
+        type Key = (usize, Option<usize>, usize, usize, RuleAction, RegexFlags);
+        let mut windows: Vec<(Key, Vec<usize>)> = vec![];
+        for (idx, active) in rules.iter().enumerate() {
+            let rule = &active.rule;
+            let key = (
+                rule.offset,
+                rule.depth,
+                rule.min_len.unwrap_or(0),
+                rule.max_len.unwrap_or(usize::MAX),
+                rule.action,
+                rule.flags(),
+            );
+            match windows.iter_mut().find(|w| w.0 == key) {
+                Some(window) => window.1.push(idx),
+                None => windows.push((key, vec![idx])),
+            }
+        }
+        let mut groups = vec![];
+        let mut count_groups = vec![];
+        let mut capture_groups = vec![];
+        for ((offset, depth, min_len, max_len, action, flags), idxs) in windows {
+            let patterns: Vec<&String> = idxs.iter().map(|idx| &rules[*idx].rule.pattern).collect();
+            let regexes = flags.regex_set(patterns.iter().copied())?;
+            let offload = if action == RuleAction::Match && flags == RegexFlags::default() {
+                backend::offload(backend, &patterns, &regexes)
+            } else {
+                None
+            };
+            let group = Group {
+                offset,
+                depth,
+                min_len,
+                max_len,
+                regexes,
+                offload,
+                scoped: idxs.iter().any(|idx| rules[*idx].rule.is_scoped()),
+                rules: idxs,
+            };
+            match action {
+                RuleAction::Match => groups.push(group),
+                RuleAction::Count => count_groups.push(group),
+                RuleAction::Capture => capture_groups.push(group),
+            }
+        }
+        Ok(Shard {
+            has_tags: rules.iter().any(|active| !active.rule.tags.is_empty()),
+            has_snaplens: rules.iter().any(|active| active.rule.snaplen.is_some()),
+            rules,
+            groups,
+            count_groups,
+            capture_groups,
+        })
+    }
+
+    /// Returns whether `payload` of a flow with properties `scope` matches any rule of the shard.
+    /// Groups bypassed by the payload are counted in `gated`.
+    #[inline]
+    fn is_match(&self, payload: &[u8], scope: &FlowScope, gated: &AtomicU64) -> bool {
+        self.groups.iter().any(|group| {
+            if !group.admits(payload.len(), gated) {
+                return false;
+            }
+            let window = group.window(payload);
+            if !group.scoped {
+                return group.regexes.is_match(window);
+            }
+            group
+                .regexes
+                .matches(window)
+                .iter()
+                .any(|idx| self.rules[group.rules[idx]].rule.applies_to(scope))
+        })
+    }
+
+    /// Sets `matched[i]` if `payloads[i]` of a flow with properties `scope` matches any rule of the
+    /// shard. Each group scans the windows of the payloads that did not match yet in one batch.
+    fn is_match_batch(
+        &self,
+        payloads: &[&[u8]],
+        scope: &FlowScope,
+        matched: &mut [bool],
+        gated: &AtomicU64,
+    ) {
+        let mut idxs = Vec::with_capacity(payloads.len());
+        let mut windows = Vec::with_capacity(payloads.len());
+        let mut results = Vec::with_capacity(payloads.len());
+        for group in self.groups.iter() {
+            idxs.clear();
+            windows.clear();
+            for (idx, payload) in payloads.iter().enumerate() {
+                if !matched[idx] && group.admits(payload.len(), gated) {
+                    idxs.push(idx);
+                    windows.push(group.window(payload));
+                }
+            }
+            if idxs.is_empty() {
+                continue;
+            }
+            if group.scoped {
+                for (idx, window) in idxs.iter().zip(windows.iter()) {
+                    matched[*idx] = group
+                        .regexes
+                        .matches(window)
+                        .iter()
+                        .any(|rule| self.rules[group.rules[rule]].rule.applies_to(scope));
+                }
+                continue;
+            }
+            results.clear();
+            results.resize(idxs.len(), false);
+            group.batch_backend().is_match_batch(&windows, &mut results);
+            for (idx, result) in idxs.iter().zip(results.iter()) {
+                matched[*idx] = *result;
+            }
+        }
+    }
+
+    /// Adds the matching rules of the shard that `payload` of a flow with properties `scope`
+    /// matches to `matches`, counting the payloads that bypass a group in `gated`.
+    fn matches<'a>(
+        &'a self,
+        payload: &[u8],
+        scope: &FlowScope,
+        matches: &mut RuleMatches<'a>,
+        gated: &AtomicU64,
+    ) {
+        for group in self.groups.iter() {
+            if !group.admits(payload.len(), gated) {
+                continue;
+            }
+            let start = group.offset.min(payload.len());
+            let window = group.window(payload);
+            for idx in group.regexes.matches(window).iter() {
+                let active = &self.rules[group.rules[idx]];
+                if !active.rule.applies_to(scope) {
+                    continue;
+                }
+                let is_first = matches.rules.is_empty();
+                matches.rules.push(&active.rule);
+                if let Some(found) = active.regex().and_then(|regex| regex.find(window)) {
+                    let end = start + found.end();
+                    matches.end = Some(matches.end.map_or(end, |first| first.min(end)));
+                    if is_first {
+                        matches.first = Some(start + found.start()..end);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Counts `payload` of a flow with properties `scope` against the counting rules it matches.
+    /// Returns whether any counting rule matched.
+    #[inline]
+    fn count(&self, payload: &[u8], scope: &FlowScope, gated: &AtomicU64) -> bool {
+        let mut counted = false;
+        for group in self.count_groups.iter() {
+            if !group.admits(payload.len(), gated) {
+                continue;
+            }
+            for idx in group.regexes.matches(group.window(payload)).iter() {
+                let active = &self.rules[group.rules[idx]];
+                if !active.rule.applies_to(scope) {
+                    continue;
+                }
+                if let Some(counter) = &active.counter {
+                    counter.nb_pkts.fetch_add(1, Ordering::Relaxed);
+                    counter
+                        .nb_bytes
+                        .fetch_add(payload.len() as u64, Ordering::Relaxed);
+                    counted = true;
+                }
+            }
+        }
+        counted
+    }
+
+    /// Returns the highest-ranked capture rule of the shard that `payload` of a flow with
+    /// properties `scope` matches, `None` if none does.
+    #[inline]
+    fn capture_rule(&self, payload: &[u8], scope: &FlowScope, gated: &AtomicU64) -> Option<&Rule> {
+        let matched = self.capture_groups.iter().flat_map(|group| {
+            let indices = match group.admits(payload.len(), gated) {
+                true => group.regexes.matches(group.window(payload)).into_iter().collect(),
+                false => vec![],
+            };
+            indices
+                .into_iter()
+                .map(|idx| &self.rules[group.rules[idx]].rule)
+                .filter(|rule| rule.applies_to(scope))
+        });
+        Rule::highest(matched)
+    }
+}
+
+/// Compiles each rule list in `jobs` into a shard, spread over a few threads.
+fn compile(jobs: Vec<Vec<ActiveRule>>, backend: Option<&MatchBackendConfig>) -> Result<Vec<Shard>> {
+    let nb_threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .clamp(1, MAX_COMPILE_THREADS)
+        .min(jobs.len());
+    if nb_threads <= 1 {
+        return jobs.into_iter().map(|job| Shard::new(job, backend)).collect();
+    }
+    let mut chunks: Vec<Vec<(usize, Vec<ActiveRule>)>> = vec![vec![]; nb_threads];
+    for (idx, job) in jobs.into_iter().enumerate() {
+        chunks[idx % nb_threads].push((idx, job));
+    }
+    let mut shards = thread::scope(|scope| {
+        let handles = chunks
+            .into_iter()
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .into_iter()
+                        .map(|(idx, job)| Ok((idx, Shard::new(job, backend)?)))
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("rule compilation thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    shards.sort_by_key(|(idx, _)| *idx);
+    Ok(shards.into_iter().map(|(_, shard)| shard).collect())
+}
+
+/// The set of active rules, compiled into independently updatable shards.
+#[derive(Debug, Clone)]
+pub(crate) struct RuleSet {
+    /// `NB_SHARDS` shards if `sharded`, otherwise a single shard.
+    shards: Vec<Shard>,
+    sharded: bool,
+    /// Number of rules removed by expiry since the filter was created.
+    nb_expired: u64,
+    /// Generation the rule set was loaded as, `0` until it is loaded.
+    generation: u64,
+    /// Number of shards compiled to build the rule set from the previous one.
+    nb_compiled: usize,
+    /// Wall time of the compilation.
+    compile_time: Duration,
+    /// Backend batch scans are offloaded to, `None` for the CPU.
+    backend: Option<MatchBackendConfig>,
+    /// Number of group evaluations bypassed by length gates, shared with the rule sets derived
+    /// from this one.
+    nb_gated: Arc<AtomicU64>,
+}
+
+impl RuleSet {
+    /// Creates a rule set of non-expiring rules from an already compiled regex set.
+    pub(crate) fn from_regexes(regexes: RegexSet) -> Self {
+        let rules = regexes
+            .patterns()
+            .iter()
+            .map(|pattern| ActiveRule {
+                rule: Rule::new(pattern.clone()),
+                deadline: None,
+                counter: None,
+                regex: Arc::default(),
+            })
+            .collect::<Vec<_>>();
+        let group = Group {
+            offset: 0,
+            depth: None,
+            min_len: 0,
+            max_len: usize::MAX,
+            rules: (0..rules.len()).collect(),
+            regexes,
+            offload: None,
+            scoped: false,
+        };
+        RuleSet {
+            shards: vec![Shard {
+                rules,
+                groups: vec![group],
+                count_groups: vec![],
+                capture_groups: vec![],
+                has_tags: false,
+                has_snaplens: false,
+            }],
+            sharded: false,
+            nb_expired: 0,
+            generation: 0,
+            nb_compiled: 0,
+            compile_time: Duration::ZERO,
+            backend: None,
+            nb_gated: Arc::default(),
+        }
+    }
+
+    /// Returns a rule set with `rules` as active rules. Only shards whose rules changed are
+    /// recompiled, unchanged rules keep their original load time. Returns the number of compiled
+    /// shards along with the new rule set.
+    pub(crate) fn update(&self, rules: Vec<Rule>) -> Result<(RuleSet, usize)> {
+        let start = Instant::now();
+        let loaded = SystemTime::now();
+        let mut incoming: Vec<Vec<Rule>> = vec![vec![]; NB_SHARDS];
+        for rule in rules {
+            incoming[shard_of(&rule.pattern)].push(rule);
+        }
+        let previous = self.active_rules();
+        let mut shards: Vec<Option<Shard>> = vec![None; NB_SHARDS];
+        let mut jobs = vec![];
+        let mut job_shards = vec![];
+        for (idx, rules) in incoming.into_iter().enumerate() {
+            if self.sharded && self.shards[idx].rules.iter().map(|r| &r.rule).eq(rules.iter()) {
+                shards[idx] = Some(self.shards[idx].clone());
+                continue;
+            }
+            jobs.push(
+                rules
+                    .into_iter()
+                    .map(|rule| ActiveRule::new(rule, loaded, &previous))
+                    .collect(),
+            );
+            job_shards.push(idx);
+        }
+        let nb_compiled = jobs.len();
+        for (idx, shard) in job_shards.into_iter().zip(compile(jobs, self.backend.as_ref())?) {
+            shards[idx] = Some(shard);
+        }
+        let rule_set = RuleSet {
+            shards: shards.into_iter().flatten().collect(),
+            sharded: true,
+            nb_expired: self.nb_expired,
+            generation: 0,
+            nb_compiled,
+            compile_time: start.elapsed(),
+            backend: self.backend,
+            nb_gated: Arc::clone(&self.nb_gated),
+        };
+        Ok((rule_set, nb_compiled))
+    }
+
+    /// Returns the active rules by rule.
+    fn active_rules(&self) -> HashMap<&Rule, &ActiveRule> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.rules.iter())
+            .map(|active| (&active.rule, active))
+            .collect()
+    }
+
+    /// Returns the compiled regex sets of the matching rules of all shards.
+    pub(crate) fn regexes(&self) -> Vec<RegexSet> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.groups.iter().map(|group| group.regexes.clone()))
+            .collect()
+    }
+
+    /// Returns whether `payload` of a flow with properties `scope` matches any rule.
+    #[inline]
+    pub(crate) fn is_match(&self, payload: &[u8], scope: &FlowScope) -> bool {
+        self.shards
+            .iter()
+            .any(|shard| shard.is_match(payload, scope, &self.nb_gated))
+    }
+
+    /// Returns for each of `payloads`, of flows with properties `scope`, whether it matches any
+    /// rule, scanning the payloads in batches.
+    pub(crate) fn is_match_batch(&self, payloads: &[&[u8]], scope: &FlowScope) -> Vec<bool> {
+        let mut matched = vec![false; payloads.len()];
+        for shard in self.shards.iter() {
+            shard.is_match_batch(payloads, scope, &mut matched, &self.nb_gated);
+        }
+        matched
+    }
+
+    /// Returns whether any rule has tags.
+    pub(crate) fn has_tags(&self) -> bool {
+        self.shards.iter().any(|shard| shard.has_tags)
+    }
+
+    /// Returns whether any rule has a snapshot length.
+    pub(crate) fn has_snaplens(&self) -> bool {
+        self.shards.iter().any(|shard| shard.has_snaplens)
+    }
+
+    /// Returns the matching rules that `payload` of a flow with properties `scope` matches, and
+    /// where their first match ends. Slower than [is_match](RuleSet::is_match), which stops at the
+    /// first match, so only used when the matched rules are needed.
+    pub(crate) fn matches(&self, payload: &[u8], scope: &FlowScope) -> RuleMatches {
+        let mut matches = RuleMatches::default();
+        for shard in self.shards.iter() {
+            shard.matches(payload, scope, &mut matches, &self.nb_gated);
+        }
+        matches
+    }
+
+    /// Counts `payload` of a flow with properties `scope` against the counting rules it matches.
+    /// Returns whether any counting rule matched.
+    #[inline]
+    pub(crate) fn count(&self, payload: &[u8], scope: &FlowScope) -> bool {
+        let mut counted = false;
+        for shard in self.shards.iter() {
+            counted |= shard.count(payload, scope, &self.nb_gated);
+        }
+        counted
+    }
+
+    /// Returns the highest-ranked capture rule that `payload` of a flow with properties `scope`
+    /// matches, `None` if none does.
+    #[inline]
+    pub(crate) fn capture_rule(&self, payload: &[u8], scope: &FlowScope) -> Option<&Rule> {
+        Rule::highest(
+            self.shards
+                .iter()
+                .filter_map(|shard| shard.capture_rule(payload, scope, &self.nb_gated)),
+        )
+    }
+
+    /// Returns the counters of the active counting rules.
+    pub(crate) fn counts(&self) -> Vec<RuleCount> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.rules.iter())
+            .filter_map(|active| {
+                let counter = active.counter.as_ref()?;
+                Some(RuleCount {
+                    rule: active.rule.clone(),
+                    nb_pkts: counter.nb_pkts.load(Ordering::Relaxed),
+                    nb_bytes: counter.nb_bytes.load(Ordering::Relaxed),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the active rules.
+    pub(crate) fn rules(&self) -> impl Iterator<Item = &Rule> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.rules.iter().map(|r| &r.rule))
+    }
+
+    /// Returns the number of active rules.
+    pub(crate) fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.rules.len()).sum()
+    }
+
+    /// Returns the number of active counting rules.
+    pub(crate) fn nb_counting(&self) -> usize {
+        self.rules()
+            .filter(|rule| rule.action == RuleAction::Count)
+            .count()
+    }
+
+    /// Returns the number of active capture rules.
+    pub(crate) fn nb_capturing(&self) -> usize {
+        self.rules()
+            .filter(|rule| rule.action == RuleAction::Capture)
+            .count()
+    }
+
+    /// Returns the number of rules removed by expiry.
+    pub(crate) fn nb_expired(&self) -> u64 {
+        self.nb_expired
+    }
+
+    /// Returns the number of group evaluations bypassed by length gates.
+    pub(crate) fn nb_gated(&self) -> u64 {
+        self.nb_gated.load(Ordering::Relaxed)
+    }
+
+    /// Returns the generation the rule set was loaded as.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the compilation statistics of the rule set.
+    pub(crate) fn compile_stats(&self) -> CompileStats {
+        let groups = self.shards.iter().flat_map(|shard| {
+            shard
+                .groups
+                .iter()
+                .chain(shard.count_groups.iter())
+                .chain(shard.capture_groups.iter())
+        });
+        let est_heap_bytes = groups
+            .map(|group| {
+                let pattern_bytes: usize = group.regexes.patterns().iter().map(String::len).sum();
+                HEAP_PER_REGEX_SET + HEAP_PER_PATTERN_BYTE * pattern_bytes
+            })
+            .sum();
+        CompileStats {
+            generation: self.generation,
+            nb_rules: self.len(),
+            nb_compiled: self.nb_compiled,
+            compile_time: self.compile_time,
+            est_heap_bytes,
+        }
+    }
+
+    /// Tags the rule set with the generation it is loaded as.
+    pub(crate) fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    /// Sets the backend batch scans of the shards compiled from this rule set are offloaded to.
+    pub(crate) fn set_backend(&mut self, backend: Option<MatchBackendConfig>) {
+        self.backend = backend;
+    }
+
+    /// Carries over counters from the rule set being replaced.
+    pub(crate) fn inherit(&mut self, previous: &RuleSet) {
+        self.nb_expired = previous.nb_expired;
+        self.nb_gated = Arc::clone(&previous.nb_gated);
+    }
+
+    /// Returns a rule set without the rules whose deadline has passed at `now`, recompiling only
+    /// the shards that contained them, along with the removed rules. Returns `None` if no rule
+    /// expired.
+    pub(crate) fn remove_expired(&self, now: SystemTime) -> Result<Option<(RuleSet, Vec<Rule>)>> {
+        let start = Instant::now();
+        let is_expired = |r: &ActiveRule| r.deadline.map_or(false, |d| d <= now);
+        let mut expired = vec![];
+        let mut jobs = vec![];
+        let mut job_shards = vec![];
+        for (idx, shard) in self.shards.iter().enumerate() {
+            if shard.rules.iter().any(is_expired) {
+                let (removed, active): (Vec<_>, Vec<_>) =
+                    shard.rules.iter().cloned().partition(is_expired);
+                expired.extend(removed.into_iter().map(|r| r.rule));
+                jobs.push(active);
+                job_shards.push(idx);
+            }
+        }
+        if expired.is_empty() {
+            return Ok(None);
+        }
+        let mut rule_set = self.clone();
+        rule_set.nb_compiled = jobs.len();
+        for (idx, shard) in job_shards.into_iter().zip(compile(jobs, self.backend.as_ref())?) {
+            rule_set.shards[idx] = shard;
+        }
+        rule_set.compile_time = start.elapsed();
+        rule_set.nb_expired += expired.len() as u64;
+        Ok(Some((rule_set, expired)))
+    }
+}
+
+/// Compilation statistics of a loaded rule set.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CompileStats {
+    /// Generation the rule set was loaded as.
+    pub generation: u64,
+    /// Number of active rules.
+    pub nb_rules: usize,
+    /// Number of shards compiled, the others were reused from the previous rule set. `0` for
+    /// rule sets built from an already compiled regex set.
+    pub nb_compiled: usize,
+    /// Wall time of the compilation.
+    pub compile_time: Duration,
+    /// Rough estimate of the heap usage of the compiled regex sets, in bytes, from the size of
+    /// their patterns. Lazy DFA caches, which grow while matching, are not included.
+    pub est_heap_bytes: usize,
+}
+
+/// Rule set generation each RX core matches against.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleGenerations {
+    /// Generation of the loaded rule set.
+    pub generation: u64,
+    /// Active generation of each core.
+    pub cores: BTreeMap<u32, u64>,
+}
+
+impl RuleGenerations {
+    /// Returns whether all cores match against the loaded rule set.
+    pub fn is_propagated(&self) -> bool {
+        self.cores.values().all(|active| *active == self.generation)
+    }
+
+    /// Returns the cores that still match against a previous rule set.
+    pub fn stale_cores(&self) -> Vec<u32> {
+        self.cores
+            .iter()
+            .filter(|(_, active)| **active != self.generation)
+            .map(|(core, _)| *core)
+            .collect()
+    }
+}
+
+/// Registry of the active rule set generation of each core.
+#[derive(Debug, Default)]
+pub(crate) struct CoreGenerations {
+    cores: RwLock<BTreeMap<u32, Arc<AtomicU64>>>,
+}
+
+impl CoreGenerations {
+    pub(crate) fn new() -> Self {
+        CoreGenerations::default()
+    }
+
+    /// Returns the active generation of `core`, registering it with `generation` if needed.
+    pub(crate) fn core(&self, core: u32, generation: u64) -> Arc<AtomicU64> {
+        let mut cores = self.cores.write().unwrap();
+        Arc::clone(
+            cores
+                .entry(core)
+                .or_insert_with(|| Arc::new(AtomicU64::new(generation))),
+        )
+    }
+
+    /// Returns the active generation of each registered core.
+    pub(crate) fn snapshot(&self, generation: u64) -> RuleGenerations {
+        let cores = self.cores.read().unwrap();
+        RuleGenerations {
+            generation,
+            cores: cores
+                .iter()
+                .map(|(core, active)| (*core, active.load(Ordering::Acquire)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a rule set of `rules`, compiled from an empty one.
+    fn rule_set(rules: Vec<Rule>) -> RuleSet {
+        RuleSet::from_regexes(RegexSet::empty())
+            .update(rules)
+            .unwrap()
+            .0
+    }
+
+    fn unscoped() -> FlowScope {
+        FlowScope::app(AppProtocol::Unknown)
+    }
+
+    #[test]
+    fn mac_pattern_parse() {
+        let mac: MacPattern = "00:1B:21:3a:4f:10".parse().unwrap();
+        assert_eq!(mac.to_string(), "00:1b:21:3a:4f:10");
+        assert!(mac.matches(&MacAddr(0x00, 0x1b, 0x21, 0x3a, 0x4f, 0x10)));
+        assert!(!mac.matches(&MacAddr(0x00, 0x1b, 0x21, 0x3a, 0x4f, 0x11)));
+
+        let oui: MacPattern = "00-1b-21".parse().unwrap();
+        assert_eq!(oui.to_string(), "00:1b:21");
+        assert!(oui.matches(&MacAddr(0x00, 0x1b, 0x21, 0xff, 0xff, 0xff)));
+        assert!(!oui.matches(&MacAddr(0x00, 0x1b, 0x22, 0x00, 0x00, 0x00)));
+
+        for invalid in ["", "00:1b", "00:1b:21:3a", "00:1b:21:3a:4f:10:00", "0:1b:21", "zz:1b:21"] {
+            assert!(invalid.parse::<MacPattern>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn flow_label_range_parse() {
+        let range: FlowLabelRange = "256-511".parse().unwrap();
+        assert_eq!(range.to_string(), "256-511");
+        assert!(range.contains(256) && range.contains(511));
+        assert!(!range.contains(255) && !range.contains(512));
+
+        let single: FlowLabelRange = "0x100".parse().unwrap();
+        assert_eq!(single.to_string(), "256");
+        assert!(single.contains(256) && !single.contains(257));
+
+        let max: FlowLabelRange = "0 - 0xfffff".parse().unwrap();
+        assert!(max.contains(MAX_FLOW_LABEL));
+
+        for invalid in ["", "511-256", "0x100000", "1048576", "1-x", "-1"] {
+            assert!(invalid.parse::<FlowLabelRange>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn rule_deserialize() {
+        let rule: Rule = serde_json::from_str(
+            r#"{
+                "pattern": "evil",
+                "mac": "00:1b:21",
+                "flow_label": "1-2",
+                "tags": { "mitre": "T1059.001", "campaign": "winter" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(rule.mac, Some("00:1b:21".parse().unwrap()));
+        assert_eq!(rule.flow_label, Some("1-2".parse().unwrap()));
+        assert_eq!(rule.tags.get("mitre").map(String::as_str), Some("T1059.001"));
+        assert_eq!(rule.tags.get("campaign").map(String::as_str), Some("winter"));
+        assert_eq!(rule.action, RuleAction::Match);
+
+        let json = serde_json::to_string(&rule).unwrap();
+        assert_eq!(serde_json::from_str::<Rule>(&json).unwrap(), rule);
+
+        let invalid = r#"{ "pattern": "evil", "flow_label": "2-1" }"#;
+        assert!(serde_json::from_str::<Rule>(invalid).is_err());
+    }
+
+    #[test]
+    fn rule_scope() {
+        let mut rule = Rule::new("evil");
+        rule.app = Some(AppProtocol::Http);
+        rule.c_tag = Some(20);
+        rule.flow_label = Some("256-511".parse().unwrap());
+        let mut scope = FlowScope {
+            app: AppProtocol::Http,
+            s_tag: None,
+            c_tag: Some(20),
+            macs: [None, None],
+            flow_label: Some(300),
+        };
+        assert!(rule.applies_to(&scope));
+        scope.flow_label = None;
+        assert!(!rule.applies_to(&scope));
+        scope.flow_label = Some(300);
+        scope.c_tag = Some(21);
+        assert!(!rule.applies_to(&scope));
+        assert!(Rule::new("evil").applies_to(&unscoped()));
+    }
+
+    #[test]
+    fn rule_ranking() {
+        let mut high = Rule::new("b");
+        high.priority = 10;
+        let low = Rule::new("a");
+        let tied = Rule::new("c");
+        assert!(high.outranks(&low));
+        assert!(low.outranks(&tied));
+        assert!(!tied.outranks(&low));
+        let rules = [tied.clone(), high.clone(), low.clone()];
+        assert_eq!(Rule::highest(rules.iter()), Some(&high));
+        assert_eq!(Rule::highest([&tied, &low]), Some(&low));
+        assert_eq!(Rule::highest([]), None);
+    }
+
+    #[test]
+    fn shard_compilation() {
+        let rules = vec![Rule::new("alpha"), Rule::new("beta")];
+        let (rule_set, nb_compiled) = RuleSet::from_regexes(RegexSet::empty())
+            .update(rules.clone())
+            .unwrap();
+        assert_eq!(nb_compiled, NB_SHARDS);
+        assert_eq!(rule_set.len(), 2);
+        assert!(rule_set.is_match(b"xx alpha xx", &unscoped()));
+        assert!(!rule_set.is_match(b"gamma", &unscoped()));
+
+        let (_, nb_compiled) = rule_set.update(rules.clone()).unwrap();
+        assert_eq!(nb_compiled, 0);
+
+        let mut more = rules;
+        more.push(Rule::new("gamma"));
+        let (updated, nb_compiled) = rule_set.update(more).unwrap();
+        assert_eq!(nb_compiled, 1);
+        assert!(updated.is_match(b"gamma", &unscoped()));
+    }
+
+    #[test]
+    fn group_windows_and_gates() {
+        let mut windowed = Rule::new("abc");
+        windowed.offset = 4;
+        windowed.depth = Some(3);
+        let mut gated = Rule::new("xyz");
+        gated.min_len = Some(8);
+        let rule_set = rule_set(vec![windowed, gated]);
+        assert!(rule_set.is_match(b"0123abc", &unscoped()));
+        assert!(!rule_set.is_match(b"abc0123", &unscoped()));
+        assert!(!rule_set.is_match(b"0123_abc", &unscoped()));
+
+        let nb_gated = rule_set.nb_gated();
+        assert!(!rule_set.is_match(b"xyz", &unscoped()));
+        assert!(rule_set.nb_gated() > nb_gated);
+        assert!(rule_set.is_match(b"xyz_____", &unscoped()));
+    }
+
+    #[test]
+    fn action_groups() {
+        let mut counting = Rule::new("count");
+        counting.action = RuleAction::Count;
+        let mut capture = Rule::new("cap");
+        capture.action = RuleAction::Capture;
+        capture.store_target = Some("forensics".into());
+        let mut urgent = Rule::new("cap(ture)?");
+        urgent.action = RuleAction::Capture;
+        urgent.priority = 5;
+        let rule_set = rule_set(vec![Rule::new("match"), counting, capture, urgent.clone()]);
+        assert_eq!(rule_set.nb_counting(), 1);
+        assert_eq!(rule_set.nb_capturing(), 2);
+
+        assert!(!rule_set.is_match(b"count", &unscoped()));
+        assert!(rule_set.count(b"count", &unscoped()));
+        assert_eq!(rule_set.counts()[0].nb_pkts, 1);
+
+        assert!(!rule_set.is_match(b"capture", &unscoped()));
+        assert_eq!(rule_set.capture_rule(b"capture", &unscoped()), Some(&urgent));
+        assert_eq!(rule_set.capture_rule(b"match", &unscoped()), None);
+    }
+
+    #[test]
+    fn remove_expired() {
+        let loaded = SystemTime::now();
+        let mut expired = Rule::new("expired");
+        expired.expires_at = Some(1);
+        let mut ttl = Rule::new("ttl");
+        ttl.ttl_seconds = Some(60);
+        let rule_set = rule_set(vec![expired.clone(), ttl.clone(), Rule::new("forever")]);
+
+        let (rule_set, removed) = rule_set.remove_expired(loaded).unwrap().unwrap();
+        assert_eq!(removed, vec![expired]);
+        assert_eq!(rule_set.len(), 2);
+        assert_eq!(rule_set.nb_expired(), 1);
+        assert!(!rule_set.is_match(b"expired", &unscoped()));
+        assert!(rule_set.remove_expired(loaded).unwrap().is_none());
+
+        // Reloading an active rule keeps its deadline
+        let previous = rule_set.active_rules();
+        let deadline = previous[&ttl].deadline.unwrap();
+        assert!(deadline >= loaded + Duration::from_secs(60));
+        let reloaded = ActiveRule::new(ttl.clone(), deadline, &previous);
+        assert_eq!(reloaded.deadline, Some(deadline));
+
+        let later = deadline + Duration::from_secs(1);
+        let (rule_set, removed) = rule_set.remove_expired(later).unwrap().unwrap();
+        assert_eq!(removed, vec![ttl]);
+        assert_eq!(rule_set.nb_expired(), 2);
+        assert!(rule_set.is_match(b"forever", &unscoped()));
+    }
+}
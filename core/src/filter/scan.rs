@@ -0,0 +1,392 @@
+//! Payload scan depth and match position telemetry.
+//!
+//! The filter records the byte offset within the flow at which every match of a rule ended. The
+//! offsets are kept in a power-of-two histogram, which is used in adaptive mode to limit how deep
+//! into each flow payloads are scanned.
+//!
+//! Matches beyond an adaptive depth are never seen in the flows it applies to, so one in
+//! `FULL_DEPTH_SAMPLING` flows is still scanned whole, and its matches beyond the depth are
+//! recorded as many times to stand for the matches the other flows miss. The histogram halves
+//! once it holds `DECAY_SAMPLES` times `adaptive_min_samples` matches, so that the depth follows
+//! changes of the traffic and rules, and grows back when deeper matches appear.
+//!
+//! Flows identified as one of the [skipped application
+//! protocols](crate::config::ScanConfig::skip_apps) are not scanned at all, and their packets
+//! and bytes are counted as classified. Payloads outside the [length
+//! gates](crate::config::ScanConfig::min_len) are not scanned either, and counted as gated.
+
+use crate::config::ScanConfig;
+use crate::protocols::app::AppProtocol;
+use crate::protocols::layer4::Flow;
+use crate::utils::hash::stable_hash;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+/// Upper bound (exclusive) of the first histogram bucket, in bytes.
+const FIRST_BUCKET: usize = 64;
+/// Number of histogram buckets. The last bucket has no upper bound.
+const NB_BUCKETS: usize = 24;
+/// One in this many flows is scanned whole while the depth is adaptive.
+const FULL_DEPTH_SAMPLING: u64 = 64;
+/// Number of matches, in multiples of `adaptive_min_samples`, at which the histogram halves.
+const DECAY_SAMPLES: u64 = 64;
+
+/// Identifies whether the scan depth was configured or derived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanMode {
+    /// Whole flows are scanned.
+    Unlimited,
+    /// Scan depth set in the configuration.
+    Fixed,
+    /// Scan depth derived from observed match offsets.
+    Adaptive,
+}
+
+/// Snapshot of scan depth and match offset statistics.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanStats {
+    /// Current scan depth in bytes per flow, `None` if unlimited.
+    pub depth: Option<usize>,
+    /// How the current scan depth was chosen.
+    pub mode: ScanMode,
+    /// Number of matches recorded in the offset histogram, as weighted by full-depth sampling
+    /// and decay.
+    pub nb_matches: u64,
+    /// Flow offset below which 50% of matches ended, rounded up to a power of two.
+    pub p50_offset: Option<usize>,
+    /// Flow offset below which 99% of matches ended, rounded up to a power of two.
+    pub p99_offset: Option<usize>,
+    /// Number of packets not scanned because they were beyond the scan depth.
+    pub nb_skipped: u64,
+    /// Number of packets not scanned because of the application protocol of their flow.
+    pub nb_classified: u64,
+    /// Number of payload bytes of the packets counted in `nb_classified`.
+    pub nb_classified_bytes: u64,
+    /// Number of packets not scanned because their payload length is outside the length gates.
+    pub nb_gated: u64,
+    /// Number of rule group evaluations bypassed by the length gates of rules.
+    pub nb_rule_gated: u64,
+}
+
+/// Shared scan depth state of a filter.
+#[derive(Debug)]
+pub(crate) struct ScanState {
+    config: RwLock<ScanConfig>,
+    /// Effective scan depth read in the fast path, `usize::MAX` if unlimited.
+    depth: AtomicUsize,
+    /// Whether the scan depth is derived from the histogram.
+    adaptive: AtomicBool,
+    offsets: [AtomicU64; NB_BUCKETS],
+    nb_skipped: AtomicU64,
+    /// Skipped application protocols, one bit per protocol.
+    skip_apps: AtomicU64,
+    nb_classified: AtomicU64,
+    nb_classified_bytes: AtomicU64,
+    /// Inclusive payload length range of scanned packets.
+    min_len: AtomicUsize,
+    max_len: AtomicUsize,
+    nb_gated: AtomicU64,
+}
+
+impl ScanState {
+    pub(crate) fn new() -> Self {
+        ScanState {
+            config: RwLock::new(ScanConfig::default()),
+            depth: AtomicUsize::new(usize::MAX),
+            adaptive: AtomicBool::new(false),
+            offsets: Default::default(),
+            nb_skipped: AtomicU64::new(0),
+            skip_apps: AtomicU64::new(0),
+            nb_classified: AtomicU64::new(0),
+            nb_classified_bytes: AtomicU64::new(0),
+            min_len: AtomicUsize::new(0),
+            max_len: AtomicUsize::new(usize::MAX),
+            nb_gated: AtomicU64::new(0),
+        }
+    }
+
+    /// Applies scan options from the runtime configuration, with adaptive depth unless `adaptive`
+    /// is unset.
+    pub(crate) fn configure(&self, config: &ScanConfig, adaptive: bool) {
+        *self.config.write().unwrap() = config.clone();
+        let skip_apps = config.skip_apps.iter().fold(0, |mask, app| mask | app_bit(*app));
+        self.skip_apps.store(skip_apps, Ordering::Relaxed);
+        let min_len = config.min_len.unwrap_or(0);
+        let max_len = config.max_len.unwrap_or(usize::MAX);
+        if min_len > max_len {
+            log::warn!("Scan length gates {}-{} exclude all payloads", min_len, max_len);
+        }
+        self.min_len.store(min_len, Ordering::Relaxed);
+        self.max_len.store(max_len, Ordering::Relaxed);
+        self.update_depth(adaptive);
+    }
+
+    /// Returns the current scan depth in bytes per flow.
+    #[inline]
+    pub(crate) fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the new flow `flow` is scanned whole whatever the scan depth.
+    #[inline]
+    pub(crate) fn samples(&self, flow: &Flow) -> bool {
+        self.adaptive.load(Ordering::Relaxed) && stable_hash(flow, 0) % FULL_DEPTH_SAMPLING == 0
+    }
+
+    /// Records a match that ended at `offset` bytes into the flow, of a flow scanned whole if
+    /// `sampled` is set.
+    #[inline]
+    pub(crate) fn record_match(&self, offset: usize, sampled: bool) {
+        let weight = match sampled && offset > self.depth() {
+            true => FULL_DEPTH_SAMPLING,
+            false => 1,
+        };
+        self.offsets[bucket(offset)].fetch_add(weight, Ordering::Relaxed);
+    }
+
+    /// Records a packet that was not scanned because it was beyond the scan depth.
+    #[inline]
+    pub(crate) fn record_skipped(&self) {
+        self.nb_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns whether flows identified as `app` are not scanned.
+    #[inline]
+    pub(crate) fn skips(&self, app: AppProtocol) -> bool {
+        self.skip_apps.load(Ordering::Relaxed) & app_bit(app) != 0
+    }
+
+    /// Records a packet with `nb_bytes` of payload that was not scanned because of the
+    /// application protocol of its flow.
+    #[inline]
+    pub(crate) fn record_classified(&self, nb_bytes: usize) {
+        self.nb_classified.fetch_add(1, Ordering::Relaxed);
+        self.nb_classified_bytes
+            .fetch_add(nb_bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Returns whether payloads of `len` bytes are within the length gates.
+    #[inline]
+    pub(crate) fn admits(&self, len: usize) -> bool {
+        len >= self.min_len.load(Ordering::Relaxed) && len <= self.max_len.load(Ordering::Relaxed)
+    }
+
+    /// Records a packet that was not scanned because of its payload length.
+    #[inline]
+    pub(crate) fn record_gated(&self) {
+        self.nb_gated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Recomputes the scan depth. In adaptive mode, unless `adaptive` is unset, the depth is set to
+    /// the offset below which `adaptive_percentile` of the recorded matches ended, once enough
+    /// matches were seen.
+    pub(crate) fn update_depth(&self, adaptive: bool) {
+        let config = self.config.read().unwrap();
+        let depth = match (config.depth, config.adaptive && adaptive) {
+            (Some(depth), _) => depth,
+            (None, true) => {
+                let mut counts = self.counts();
+                let total: u64 = counts.iter().sum();
+                if total >= config.adaptive_min_samples.max(1).saturating_mul(DECAY_SAMPLES) {
+                    self.decay();
+                    counts = self.counts();
+                }
+                if total >= config.adaptive_min_samples {
+                    percentile(&counts, config.adaptive_percentile).unwrap_or(usize::MAX)
+                } else {
+                    usize::MAX
+                }
+            }
+            (None, false) => usize::MAX,
+        };
+        self.adaptive
+            .store(config.depth.is_none() && depth != usize::MAX, Ordering::Relaxed);
+        self.depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Halves the counts of the histogram.
+    fn decay(&self) {
+        for offsets in self.offsets.iter() {
+            let _ = offsets.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                Some(count / 2)
+            });
+        }
+    }
+
+    pub(crate) fn stats(&self) -> ScanStats {
+        let config = self.config.read().unwrap();
+        let depth = self.depth();
+        let counts = self.counts();
+        ScanStats {
+            depth: if depth == usize::MAX { None } else { Some(depth) },
+            mode: match (config.depth, config.adaptive) {
+                (Some(_), _) => ScanMode::Fixed,
+                (None, true) if depth != usize::MAX => ScanMode::Adaptive,
+                _ => ScanMode::Unlimited,
+            },
+            nb_matches: counts.iter().sum(),
+            p50_offset: percentile(&counts, 0.5),
+            p99_offset: percentile(&counts, 0.99),
+            nb_skipped: self.nb_skipped.load(Ordering::Relaxed),
+            nb_classified: self.nb_classified.load(Ordering::Relaxed),
+            nb_classified_bytes: self.nb_classified_bytes.load(Ordering::Relaxed),
+            nb_gated: self.nb_gated.load(Ordering::Relaxed),
+            nb_rule_gated: 0,
+        }
+    }
+
+    fn counts(&self) -> [u64; NB_BUCKETS] {
+        let mut counts = [0; NB_BUCKETS];
+        for (count, offsets) in counts.iter_mut().zip(self.offsets.iter()) {
+            *count = offsets.load(Ordering::Relaxed);
+        }
+        counts
+    }
+}
+
+/// Returns the bit of `app` in the skipped application protocols mask.
+fn app_bit(app: AppProtocol) -> u64 {
+    1 << app as u64
+}
+
+/// Returns the histogram bucket of `offset`.
+fn bucket(offset: usize) -> usize {
+    let mut bucket = 0;
+    while bucket < NB_BUCKETS - 1 && offset >= FIRST_BUCKET << bucket {
+        bucket += 1;
+    }
+    bucket
+}
+
+/// Returns the upper bound of the bucket containing the `p`-th percentile of `counts`, or `None`
+/// if the histogram is empty or the percentile falls into the unbounded last bucket.
+fn percentile(counts: &[u64; NB_BUCKETS], p: f64) -> Option<usize> {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let target = (total as f64 * p).ceil() as u64;
+    let mut cumulative = 0;
+    for (bucket, count) in counts.iter().enumerate().take(NB_BUCKETS - 1) {
+        cumulative += count;
+        if cumulative >= target {
+            return Some(FIRST_BUCKET << bucket);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a scan state with adaptive depth at `percentile`, after `min_samples` matches.
+    fn adaptive(percentile: f64, min_samples: u64) -> ScanState {
+        let scan = ScanState::new();
+        let config = ScanConfig {
+            depth: None,
+            adaptive: true,
+            adaptive_percentile: percentile,
+            adaptive_min_samples: min_samples,
+            ..ScanConfig::default()
+        };
+        scan.configure(&config, true);
+        scan
+    }
+
+    #[test]
+    fn bucket_bounds() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(FIRST_BUCKET - 1), 0);
+        assert_eq!(bucket(FIRST_BUCKET), 1);
+        assert_eq!(bucket(2 * FIRST_BUCKET - 1), 1);
+        assert_eq!(bucket(2 * FIRST_BUCKET), 2);
+        assert_eq!(bucket(FIRST_BUCKET << (NB_BUCKETS - 2)), NB_BUCKETS - 1);
+        assert_eq!(bucket(usize::MAX), NB_BUCKETS - 1);
+    }
+
+    #[test]
+    fn percentile_of_counts() {
+        let mut counts = [0; NB_BUCKETS];
+        assert_eq!(percentile(&counts, 0.5), None);
+
+        counts[0] = 50;
+        counts[3] = 49;
+        counts[5] = 1;
+        assert_eq!(percentile(&counts, 0.5), Some(FIRST_BUCKET));
+        assert_eq!(percentile(&counts, 0.51), Some(FIRST_BUCKET << 3));
+        assert_eq!(percentile(&counts, 0.99), Some(FIRST_BUCKET << 3));
+        assert_eq!(percentile(&counts, 1.0), Some(FIRST_BUCKET << 5));
+
+        counts[NB_BUCKETS - 1] = 100;
+        assert_eq!(percentile(&counts, 0.99), None);
+    }
+
+    #[test]
+    fn adaptive_depth() {
+        let scan = adaptive(0.99, 10);
+        for _ in 0..9 {
+            scan.record_match(100, false);
+        }
+        scan.update_depth(true);
+        assert_eq!(scan.depth(), usize::MAX);
+        assert_eq!(scan.stats().mode, ScanMode::Unlimited);
+
+        scan.record_match(100, false);
+        scan.update_depth(true);
+        assert_eq!(scan.depth(), 2 * FIRST_BUCKET);
+        assert_eq!(scan.stats().mode, ScanMode::Adaptive);
+        assert_eq!(scan.stats().depth, Some(2 * FIRST_BUCKET));
+
+        scan.update_depth(false);
+        assert_eq!(scan.depth(), usize::MAX);
+    }
+
+    #[test]
+    fn sampled_matches_weight() {
+        let scan = adaptive(0.5, 1);
+        scan.record_match(10, false);
+        scan.update_depth(true);
+        assert_eq!(scan.depth(), FIRST_BUCKET);
+
+        // Only matches beyond the depth of flows scanned whole stand for the missed ones
+        scan.record_match(10, true);
+        scan.record_match(1000, true);
+        assert_eq!(scan.stats().nb_matches, 2 + FULL_DEPTH_SAMPLING);
+        assert_eq!(scan.counts()[bucket(1000)], FULL_DEPTH_SAMPLING);
+    }
+
+    #[test]
+    fn decay_halves_histogram() {
+        let scan = adaptive(0.5, 1);
+        for _ in 0..(DECAY_SAMPLES - 1) {
+            scan.record_match(10, false);
+        }
+        scan.record_match(1000, false);
+        scan.record_match(1000, false);
+        scan.update_depth(true);
+        let counts = scan.counts();
+        assert_eq!(counts[bucket(10)], (DECAY_SAMPLES - 1) / 2);
+        assert_eq!(counts[bucket(1000)], 1);
+        assert_eq!(scan.depth(), FIRST_BUCKET);
+
+        // Below the threshold, the histogram is kept as is
+        scan.update_depth(true);
+        assert_eq!(scan.counts(), counts);
+    }
+
+    #[test]
+    fn fixed_depth() {
+        let scan = ScanState::new();
+        let config = ScanConfig {
+            depth: Some(512),
+            ..ScanConfig::default()
+        };
+        scan.configure(&config, true);
+        scan.record_match(100_000, true);
+        scan.update_depth(true);
+        assert_eq!(scan.depth(), 512);
+        assert_eq!(scan.stats().mode, ScanMode::Fixed);
+    }
+}
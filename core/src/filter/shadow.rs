@@ -0,0 +1,161 @@
+//! Shadow evaluation of a candidate rule set.
+//!
+//! A candidate rule set can be loaded in shadow mode next to the active rules, to assess its match
+//! volume on production traffic before deploying it. Shadow rules are evaluated on 1-in-N
+//! payloads checked by each core, after the active rules, and are only counted: they never mark
+//! flows matched, publish alerts or change the verdict of a payload. Every shadow rule is counted
+//! like a [counting rule](crate::filter::rule), whatever its action.
+//!
+//! Sampled payloads are also compared with the verdict of the active rules, to tell how many
+//! payloads would start or stop matching if the candidate replaced them. All counters are reset
+//! when a new candidate is loaded.
+
+use super::rule::{FlowScope, Rule, RuleAction, RuleCount, RuleSet};
+use crate::config::{RegexConfig, ShadowConfig};
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use regex::bytes::RegexSet;
+use serde::Serialize;
+
+/// Statistics of the shadow rule set.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowStats {
+    /// Number of shadow rules.
+    pub nb_rules: usize,
+    /// Number of payloads evaluated against the shadow rules.
+    pub nb_sampled: u64,
+    /// Number of sampled payloads that matched a shadow rule.
+    pub nb_matched: u64,
+    /// Number of sampled payloads that matched an active rule.
+    pub nb_live_matched: u64,
+    /// Number of sampled payloads that matched a shadow rule but no active rule.
+    pub nb_added: u64,
+    /// Number of sampled payloads that matched an active rule but no shadow rule.
+    pub nb_removed: u64,
+    /// Counters of each shadow rule.
+    pub rules: Vec<RuleCount>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    nb_sampled: AtomicU64,
+    nb_matched: AtomicU64,
+    nb_live_matched: AtomicU64,
+    nb_added: AtomicU64,
+    nb_removed: AtomicU64,
+}
+
+/// Shadow rule set of a filter.
+#[derive(Debug)]
+pub(crate) struct Shadow {
+    /// Evaluate 1-in-`sample_rate` payloads.
+    sample_rate: AtomicU64,
+    /// Whether a shadow rule set is loaded.
+    loaded: AtomicBool,
+    rules: RwLock<Option<(Arc<RuleSet>, Arc<Counters>)>>,
+}
+
+impl Shadow {
+    pub(crate) fn new() -> Self {
+        Shadow {
+            sample_rate: AtomicU64::new(1),
+            loaded: AtomicBool::new(false),
+            rules: RwLock::new(None),
+        }
+    }
+
+    /// Applies shadow options from the runtime configuration, loading the configured rule file
+    /// with the regex flag defaults `regex`.
+    pub(crate) fn configure(&self, config: &ShadowConfig, regex: &RegexConfig) -> Result<()> {
+        self.sample_rate
+            .store(config.sample_rate.max(1), Ordering::Relaxed);
+        if let Some(path) = &config.rules {
+            let rules = super::watch::read_rules(Path::new(path))?;
+            self.load(rules, regex)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the shadow rule set with `rules`, whose unset regex flags default to `regex`, and
+    /// resets all counters.
+    pub(crate) fn load(&self, rules: Vec<Rule>, regex: &RegexConfig) -> Result<()> {
+        let rules: Vec<Rule> = rules
+            .into_iter()
+            .map(|mut rule| {
+                rule.resolve_flags(regex);
+                Rule {
+                    action: RuleAction::Count,
+                    ..rule
+                }
+            })
+            .collect();
+        let nb_rules = rules.len();
+        let (rule_set, _) = RuleSet::from_regexes(RegexSet::empty()).update(rules)?;
+        *self.rules.write().unwrap() = Some((Arc::new(rule_set), Arc::default()));
+        self.loaded.store(true, Ordering::Release);
+        log::info!("Loaded {} shadow rules", nb_rules);
+        Ok(())
+    }
+
+    /// Removes the shadow rule set.
+    pub(crate) fn clear(&self) {
+        self.loaded.store(false, Ordering::Release);
+        *self.rules.write().unwrap() = None;
+    }
+
+    /// Evaluates `payload` of a flow with properties `scope` against the shadow rules if it is
+    /// sampled. `live_matched` is the verdict of the active rules, and `nb_checked` counts the
+    /// payloads checked by the calling core.
+    #[inline]
+    pub(crate) fn sample(
+        &self,
+        payload: &[u8],
+        scope: &FlowScope,
+        live_matched: bool,
+        nb_checked: &AtomicU64,
+    ) {
+        if !self.loaded.load(Ordering::Relaxed) {
+            return;
+        }
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        if nb_checked.fetch_add(1, Ordering::Relaxed) % sample_rate != 0 {
+            return;
+        }
+        let (rule_set, counters) = match &*self.rules.read().unwrap() {
+            Some((rule_set, counters)) => (Arc::clone(rule_set), Arc::clone(counters)),
+            None => return,
+        };
+        let matched = rule_set.count(payload, scope);
+        counters.nb_sampled.fetch_add(1, Ordering::Relaxed);
+        if matched {
+            counters.nb_matched.fetch_add(1, Ordering::Relaxed);
+        }
+        if live_matched {
+            counters.nb_live_matched.fetch_add(1, Ordering::Relaxed);
+        }
+        match (matched, live_matched) {
+            (true, false) => counters.nb_added.fetch_add(1, Ordering::Relaxed),
+            (false, true) => counters.nb_removed.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+
+    /// Returns statistics of the shadow rule set, `None` if none is loaded.
+    pub(crate) fn stats(&self) -> Option<ShadowStats> {
+        let rules = self.rules.read().unwrap();
+        let (rule_set, counters) = rules.as_ref()?;
+        Some(ShadowStats {
+            nb_rules: rule_set.len(),
+            nb_sampled: counters.nb_sampled.load(Ordering::Relaxed),
+            nb_matched: counters.nb_matched.load(Ordering::Relaxed),
+            nb_live_matched: counters.nb_live_matched.load(Ordering::Relaxed),
+            nb_added: counters.nb_added.load(Ordering::Relaxed),
+            nb_removed: counters.nb_removed.load(Ordering::Relaxed),
+            rules: rule_set.counts(),
+        })
+    }
+}
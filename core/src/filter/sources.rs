@@ -0,0 +1,137 @@
+//! Named rule sources.
+//!
+//! Rules often come from several producers, e.g. threat intelligence feeds, local analysts and
+//! automation, each of which maintains its own full list. Instead of calling
+//! [FilterCtx::load_rules](crate::filter::FilterCtx::load_rules), which replaces all rules,
+//! producers can load their list as a named source with
+//! [FilterCtx::load_source_rules](crate::filter::FilterCtx::load_source_rules), and remove it with
+//! [FilterCtx::clear_source_rules](crate::filter::FilterCtx::clear_source_rules), without touching
+//! the rules of other sources.
+//!
+//! The active rules are the composition of all sources, from the highest priority to the lowest,
+//! sources of equal priority being ordered by name. When several sources hold a rule with the same
+//! pattern, only the rule of the first source in that order is active, and the others are counted
+//! as shadowed in [RuleSourceStats](RuleSourceStats). A source update that fails to compile leaves
+//! all sources unchanged.
+//!
+//! ## Remarks
+//! Loading rules with [FilterCtx::load_rules](crate::filter::FilterCtx::load_rules) or from the
+//! [rules directory](crate::filter::watch) replaces the composed rules until the next source
+//! update.
+
+use crate::filter::rule::Rule;
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// Status of a rule source.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleSourceStats {
+    /// Name of the source.
+    pub name: String,
+    /// Priority of the source, higher priorities win.
+    pub priority: i32,
+    /// Number of rules of the source.
+    pub nb_rules: usize,
+    /// Number of rules of the source whose pattern is held by a source with a higher priority.
+    pub nb_shadowed: usize,
+    /// Time of the last update of the source.
+    pub updated_at: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+struct RuleSource {
+    priority: i32,
+    rules: Vec<Rule>,
+    updated_at: SystemTime,
+}
+
+/// Rules of the named sources.
+#[derive(Debug, Default)]
+pub(crate) struct RuleSources {
+    sources: Mutex<BTreeMap<String, RuleSource>>,
+}
+
+impl RuleSources {
+    pub(crate) fn new() -> Self {
+        RuleSources::default()
+    }
+
+    /// Sets the rules of source `name` to `update`, removing the source if `None`, and hands the
+    /// composed rules to `load`. The change is only kept if `load` succeeds. Returns whether the
+    /// source existed, removing a missing source does nothing.
+    pub(crate) fn update(
+        &self,
+        name: &str,
+        update: Option<(i32, Vec<Rule>)>,
+        load: impl FnOnce(Vec<Rule>) -> Result<()>,
+    ) -> Result<bool> {
+        let mut sources = self.sources.lock().unwrap();
+        let existed = sources.contains_key(name);
+        if update.is_none() && !existed {
+            return Ok(false);
+        }
+        let mut updated = sources.clone();
+        match update {
+            Some((priority, rules)) => {
+                let source = RuleSource {
+                    priority,
+                    rules,
+                    updated_at: SystemTime::now(),
+                };
+                updated.insert(name.to_string(), source);
+            }
+            None => {
+                updated.remove(name);
+            }
+        }
+        load(compose(&updated))?;
+        *sources = updated;
+        Ok(existed)
+    }
+
+    pub(crate) fn stats(&self) -> Vec<RuleSourceStats> {
+        let sources = self.sources.lock().unwrap();
+        let mut seen = HashSet::new();
+        let mut stats: Vec<RuleSourceStats> = by_priority(&sources)
+            .into_iter()
+            .map(|(name, source)| RuleSourceStats {
+                name: name.clone(),
+                priority: source.priority,
+                nb_rules: source.rules.len(),
+                nb_shadowed: source
+                    .rules
+                    .iter()
+                    .filter(|rule| !seen.insert(&rule.pattern))
+                    .count(),
+                updated_at: source.updated_at,
+            })
+            .collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+}
+
+/// Returns the sources from the highest priority to the lowest, then by name.
+fn by_priority(sources: &BTreeMap<String, RuleSource>) -> Vec<(&String, &RuleSource)> {
+    let mut sources: Vec<_> = sources.iter().collect();
+    sources.sort_by(|(a_name, a), (b_name, b)| {
+        b.priority.cmp(&a.priority).then(a_name.cmp(b_name))
+    });
+    sources
+}
+
+/// Returns the active rules of `sources`, keeping only the first rule of each pattern.
+fn compose(sources: &BTreeMap<String, RuleSource>) -> Vec<Rule> {
+    let mut seen = HashSet::new();
+    by_priority(sources)
+        .into_iter()
+        .flat_map(|(_, source)| source.rules.iter())
+        .filter(|rule| seen.insert(&rule.pattern))
+        .cloned()
+        .collect()
+}
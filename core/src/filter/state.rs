@@ -0,0 +1,157 @@
+//! Flow table snapshots across restarts.
+//!
+//! Restarting the runtime, e.g. to upgrade the binary, empties the flow table: flows that
+//! already matched are scanned again, and flows that were cleared by the scan depth or the verdict
+//! cache start over. With the `[flow_snapshot]` options of the runtime configuration (see
+//! [FlowSnapshotConfig](crate::config::FlowSnapshotConfig)), the flow table is saved on shutdown
+//! and restored on initialization, so that the new process resumes with the flow and match state
+//! of the previous one. The state of memory pools and ports is not preserved.
+//!
+//! Flow ages and idle times are saved relative to the time of the save. On restore, the time the
+//! runtime was down counts as idle time, so that flows that would have timed out in the meantime
+//! are dropped.
+//!
+//! ## Remarks
+//! Only the flow table is preserved. The EAL is torn down and initialized again, so packets are
+//! missed between the two processes, and flows are keyed with the flow key options of the new
+//! configuration: flows saved with other key options are not found again.
+
+use super::table::FlowCounters;
+use super::FlowState;
+use crate::hooks::FlowDirection;
+use crate::protocols::app::AppProtocol;
+use crate::protocols::layer4::Flow;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Saved state of a flow.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedFlow {
+    flow: Flow,
+    /// Time since the flow was added to the flow table.
+    age: Duration,
+    /// Time since the flow was last seen.
+    idle: Duration,
+    directions: [FlowDirection; 2],
+    matched_rules: Vec<String>,
+    #[serde(default)]
+    matched_tags: BTreeMap<String, String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    bytes_seen: usize,
+    app: Option<AppProtocol>,
+    nb_identify: u8,
+    nb_icmp_errors: u32,
+    matched: bool,
+    cached: bool,
+    #[serde(default)]
+    client: Option<usize>,
+    #[serde(default)]
+    server_name: Option<Box<str>>,
+}
+
+/// Saved flow table.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedState {
+    saved_at: SystemTime,
+    flows: Vec<SavedFlow>,
+}
+
+/// Writes `flows` to `path`. The state is written to a temporary file first, so that `path`
+/// never holds a partial state.
+pub(crate) fn save(path: &Path, flows: &[(Flow, FlowState)]) -> Result<()> {
+    let now = Instant::now();
+    let state = SavedState {
+        saved_at: SystemTime::now(),
+        flows: flows
+            .iter()
+            .map(|(flow, state)| SavedFlow {
+                flow: *flow,
+                age: now.saturating_duration_since(state.first_seen),
+                idle: now.saturating_duration_since(state.last_seen),
+                directions: state.directions,
+                matched_rules: state.matched_rules.clone(),
+                matched_tags: state.matched_tags.clone(),
+                labels: state.labels.clone(),
+                bytes_seen: state.bytes_seen,
+                app: state.app,
+                nb_identify: state.nb_identify,
+                nb_icmp_errors: state.nb_icmp_errors,
+                matched: state.matched,
+                cached: state.cached,
+                client: state.client,
+                server_name: state.server_name.clone(),
+            })
+            .collect(),
+    };
+    let tmp_path = path.with_extension("tmp");
+    let file = File::create(&tmp_path)
+        .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &state)?;
+    writer.flush()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Reads the flows saved in `path`, skipping those idle for `timeout` or more including the time
+/// since the save. Restored flows are attributed to `counters`.
+pub(crate) fn restore(
+    path: &Path,
+    timeout: Duration,
+    counters: &Arc<FlowCounters>,
+) -> Result<Vec<(Flow, FlowState)>> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let state: SavedState = serde_json::from_reader(BufReader::new(file))?;
+    let downtime = state.saved_at.elapsed().unwrap_or_default();
+    let now = Instant::now();
+    let flows = state
+        .flows
+        .into_iter()
+        .filter_map(|saved| {
+            let idle = saved.idle + downtime;
+            if idle >= timeout {
+                return None;
+            }
+            let last_seen = now.checked_sub(idle)?;
+            let first_seen = now.checked_sub(saved.age + downtime).unwrap_or(last_seen);
+            Some((
+                saved.flow,
+                FlowState {
+                    first_seen,
+                    last_seen,
+                    directions: saved.directions,
+                    matched_rules: saved.matched_rules,
+                    matched_tags: saved.matched_tags,
+                    labels: saved.labels,
+                    bytes_seen: saved.bytes_seen,
+                    flow_label: saved.flow.flow_label(),
+                    app: saved.app,
+                    nb_identify: saved.nb_identify,
+                    nb_icmp_errors: saved.nb_icmp_errors,
+                    matched: saved.matched,
+                    cached: saved.cached,
+                    client: saved.client,
+                    server_name: saved.server_name,
+                    sampled: false,
+                    rates: None,
+                    capture_seqs: None,
+                    backfill: None,
+                    backfilled: saved.matched,
+                    store_route: None,
+                    counters: Arc::clone(counters),
+                },
+            ))
+        })
+        .collect();
+    Ok(flows)
+}
@@ -0,0 +1,507 @@
+//! Stored packet records and their metadata.
+//!
+//! The NIC attaches metadata to the packets it receives, which is lost when only the frame is
+//! stored. Packets stored as pcapng, by the [rolling capture](crate::filter::capture) with
+//! `format = "pcapng"` and by the [collectors](crate::filter::forward), keep a
+//! [PacketMeta](PacketMeta) with each packet: the port and RX queue it was received on, its RSS
+//! hash and flow rule mark, its RX offload flags and stripped VLAN tag, and whether its timestamp
+//! is a hardware RX timestamp. Timestamps are stored with nanosecond resolution.
+//!
+//! The metadata is stored in the comment option of each enhanced packet block, as `key=value`
+//! fields after a `retina` tag, so that it also shows in Wireshark:
+//! ```text
+//! retina port=0 queue=3 rss_hash=0x5e0c11a7 mark=0x0 ol_flags=0x182 vlan_tci=100 hw_ts=1
+//! ```
+//!
+//! [StoreReader](StoreReader) reads the packets of pcap and pcapng files with their metadata, e.g.
+//! for offline analytics. Packets of pcap files, and of pcapng files written by other tools, have
+//! no metadata.
+//!
+//! ## Example
+//! ```
+//! let mut reader = StoreReader::open("/var/lib/retina/capture/capture-20240101-120000-0.pcapng")?;
+//! while let Some(packet) = reader.next_packet()? {
+//!     if let Some(meta) = packet.meta {
+//!         println!("{:?} on queue {:?}, RSS hash {:#x}", packet.ts, meta.queue, meta.rss_hash);
+//!     }
+//! }
+//! ```
+
+use super::tap::{TapRecord, LINKTYPE_ETHERNET};
+use crate::memory::mbuf::Mbuf;
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+/// pcapng block types.
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+
+/// pcapng option codes.
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_TSRESOL: u16 = 9;
+
+/// Tag of the comments carrying the metadata of a packet.
+const META_TAG: &str = "retina";
+
+/// Largest packet read, so that a corrupt length cannot allocate gigabytes.
+const MAX_PACKET_LEN: usize = 256 * 1024;
+
+/// Largest pcapng block read: a packet of the largest size with room for its options.
+const MAX_BLOCK_LEN: usize = MAX_PACKET_LEN + 64 * 1024;
+
+/// Metadata of a packet set by the NIC on reception.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PacketMeta {
+    /// Port the packet was received on.
+    pub port: u16,
+    /// RX queue the packet was received on, `None` if it was not stored by an RX core.
+    pub queue: Option<u16>,
+    /// RSS hash computed by the NIC.
+    pub rss_hash: u32,
+    /// Mark set by a flow rule of the NIC.
+    pub mark: u32,
+    /// RX offload flags (DPDK `ol_flags`), e.g. whether a VLAN tag was stripped or checksums
+    /// were checked.
+    pub ol_flags: u64,
+    /// TCI of the VLAN tag stripped from the frame by the NIC, if any.
+    pub vlan_tci: Option<u16>,
+    /// Whether the timestamp of the packet is its hardware RX timestamp.
+    pub hw_timestamp: bool,
+}
+
+impl PacketMeta {
+    /// Returns the metadata of `mbuf`, received on RX queue `queue` if known.
+    #[inline]
+    pub(crate) fn new(mbuf: &Mbuf, queue: Option<u16>) -> Self {
+        let raw = mbuf.raw();
+        PacketMeta {
+            port: raw.port,
+            queue,
+            rss_hash: mbuf.rss_hash(),
+            mark: mbuf.mark(),
+            ol_flags: raw.ol_flags,
+            vlan_tci: mbuf.stripped_vlan_tci(),
+            hw_timestamp: mbuf.hw_timestamp().is_some(),
+        }
+    }
+
+    /// Returns the comment the metadata is stored in.
+    fn to_comment(self) -> String {
+        let mut comment = format!("{} port={}", META_TAG, self.port);
+        if let Some(queue) = self.queue {
+            let _ = write!(comment, " queue={}", queue);
+        }
+        let _ = write!(
+            comment,
+            " rss_hash={:#x} mark={:#x} ol_flags={:#x}",
+            self.rss_hash, self.mark, self.ol_flags
+        );
+        if let Some(vlan_tci) = self.vlan_tci {
+            let _ = write!(comment, " vlan_tci={}", vlan_tci);
+        }
+        let _ = write!(comment, " hw_ts={}", self.hw_timestamp as u8);
+        comment
+    }
+
+    /// Parses the metadata of a comment, `None` if it carries none. Unknown fields are ignored.
+    fn from_comment(comment: &str) -> Option<Self> {
+        let mut fields = comment.split_whitespace();
+        if fields.next() != Some(META_TAG) {
+            return None;
+        }
+        let mut meta = PacketMeta::default();
+        for field in fields {
+            let (key, value) = match field.split_once('=') {
+                Some(field) => field,
+                None => continue,
+            };
+            match key {
+                "port" => meta.port = value.parse().ok()?,
+                "queue" => meta.queue = Some(value.parse().ok()?),
+                "rss_hash" => meta.rss_hash = parse_hex(value)? as u32,
+                "mark" => meta.mark = parse_hex(value)? as u32,
+                "ol_flags" => meta.ol_flags = parse_hex(value)?,
+                "vlan_tci" => meta.vlan_tci = Some(value.parse().ok()?),
+                "hw_ts" => meta.hw_timestamp = value == "1",
+                _ => {}
+            }
+        }
+        Some(meta)
+    }
+}
+
+fn parse_hex(value: &str) -> Option<u64> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+/// A packet read from a stored file.
+#[derive(Debug, Clone)]
+pub struct StoredPacket {
+    /// Reception time of the packet (UNIX time).
+    pub ts: Duration,
+    /// Original length of the packet, which may exceed the stored data.
+    pub orig_len: usize,
+    /// Stored bytes of the packet.
+    pub data: Vec<u8>,
+    /// Metadata of the packet, `None` if it was not stored.
+    pub meta: Option<PacketMeta>,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+        /// Largest packet of the file.
+        max_len: usize,
+    },
+    Pcapng {
+        big_endian: bool,
+        /// Timestamp resolution of each interface of the section.
+        resolutions: Vec<u8>,
+    },
+}
+
+/// Reader of the Ethernet packets of pcap and pcapng files, in either byte order.
+#[derive(Debug)]
+pub struct StoreReader<R> {
+    reader: R,
+    format: Format,
+}
+
+impl StoreReader<BufReader<File>> {
+    /// Opens the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        StoreReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> StoreReader<R> {
+    /// Reads the header of the file.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if u32::from_le_bytes(magic) == SECTION_HEADER {
+            let mut store = StoreReader {
+                reader,
+                format: Format::Pcapng {
+                    big_endian: false,
+                    resolutions: vec![],
+                },
+            };
+            store.read_section_header()?;
+            return Ok(store);
+        }
+        let mut header = [0; 24];
+        header[..4].copy_from_slice(&magic);
+        reader.read_exact(&mut header[4..])?;
+        let (big_endian, nanos) = match u32::from_le_bytes(magic) {
+            0xa1b2_c3d4 => (false, false),
+            0xa1b2_3c4d => (false, true),
+            0xd4c3_b2a1 => (true, false),
+            0x4d3c_b2a1 => (true, true),
+            magic => bail!("Not a pcap or pcapng file (magic {:#x})", magic),
+        };
+        let linktype = u32_at(big_endian, &header, 20);
+        if linktype != LINKTYPE_ETHERNET {
+            bail!("Unsupported pcap link type {}", linktype);
+        }
+        let max_len = match u32_at(big_endian, &header, 16) as usize {
+            0 => MAX_PACKET_LEN,
+            snaplen => snaplen.min(MAX_PACKET_LEN),
+        };
+        Ok(StoreReader {
+            reader,
+            format: Format::Pcap {
+                big_endian,
+                nanos,
+                max_len,
+            },
+        })
+    }
+
+    /// Returns the next packet, `None` at the end of the file.
+    pub fn next_packet(&mut self) -> Result<Option<StoredPacket>> {
+        match self.format {
+            Format::Pcap {
+                big_endian,
+                nanos,
+                max_len,
+            } => self.next_pcap_packet(big_endian, nanos, max_len),
+            Format::Pcapng { .. } => self.next_pcapng_packet(),
+        }
+    }
+
+    fn next_pcap_packet(
+        &mut self,
+        big_endian: bool,
+        nanos: bool,
+        max_len: usize,
+    ) -> Result<Option<StoredPacket>> {
+        let mut header = [0; 16];
+        if !read_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let secs = u32_at(big_endian, &header, 0) as u64;
+        let frac = u32_at(big_endian, &header, 4);
+        let ts = match nanos {
+            true => Duration::new(secs, frac),
+            false => Duration::new(secs, frac.saturating_mul(1000)),
+        };
+        let incl_len = u32_at(big_endian, &header, 8) as usize;
+        let orig_len = u32_at(big_endian, &header, 12) as usize;
+        if incl_len > max_len {
+            bail!("Bad pcap packet length {}", incl_len);
+        }
+        let mut data = vec![0; incl_len];
+        self.reader.read_exact(&mut data)?;
+        Ok(Some(StoredPacket {
+            ts,
+            orig_len,
+            data,
+            meta: None,
+        }))
+    }
+
+    /// Reads the rest of a section header block, whose type was read, and starts a new section.
+    fn read_section_header(&mut self) -> Result<()> {
+        let mut fields = [0; 8];
+        self.reader.read_exact(&mut fields)?;
+        let big_endian = match u32::from_le_bytes([fields[4], fields[5], fields[6], fields[7]]) {
+            0x1a2b_3c4d => false,
+            0x4d3c_2b1a => true,
+            magic => bail!("Bad pcapng byte-order magic {:#x}", magic),
+        };
+        let total = u32_at(big_endian, &fields, 0) as usize;
+        if total < 28 || total % 4 != 0 || total > MAX_BLOCK_LEN {
+            bail!("Bad pcapng section header length {}", total);
+        }
+        let mut rest = vec![0; total - 12];
+        self.reader.read_exact(&mut rest)?;
+        self.format = Format::Pcapng {
+            big_endian,
+            resolutions: vec![],
+        };
+        Ok(())
+    }
+
+    fn next_pcapng_packet(&mut self) -> Result<Option<StoredPacket>> {
+        loop {
+            let mut block_type = [0; 4];
+            if !read_or_eof(&mut self.reader, &mut block_type)? {
+                return Ok(None);
+            }
+            if u32::from_le_bytes(block_type) == SECTION_HEADER {
+                self.read_section_header()?;
+                continue;
+            }
+            let (big_endian, resolutions) = match &mut self.format {
+                Format::Pcapng {
+                    big_endian,
+                    resolutions,
+                } => (*big_endian, resolutions),
+                Format::Pcap { .. } => bail!("Not a pcapng file"),
+            };
+            let block_type = u32_at(big_endian, &block_type, 0);
+            let mut total = [0; 4];
+            self.reader.read_exact(&mut total)?;
+            let total = u32_at(big_endian, &total, 0) as usize;
+            if total < 12 || total % 4 != 0 || total > MAX_BLOCK_LEN {
+                bail!("Bad pcapng block length {}", total);
+            }
+            let mut body = vec![0; total - 8];
+            self.reader.read_exact(&mut body)?;
+            // Without the trailing length
+            body.truncate(total - 12);
+            match block_type {
+                INTERFACE_DESCRIPTION => {
+                    if body.len() < 8 {
+                        bail!("Truncated pcapng interface description");
+                    }
+                    let linktype = u16_at(big_endian, &body, 0) as u32;
+                    if linktype != LINKTYPE_ETHERNET {
+                        bail!("Unsupported pcapng link type {}", linktype);
+                    }
+                    let mut resolution = 6;
+                    for (code, value) in options(big_endian, &body[8..]) {
+                        if code == IF_TSRESOL && !value.is_empty() {
+                            resolution = value[0];
+                        }
+                    }
+                    resolutions.push(resolution);
+                }
+                ENHANCED_PACKET => {
+                    if body.len() < 20 {
+                        bail!("Truncated pcapng enhanced packet");
+                    }
+                    let interface = u32_at(big_endian, &body, 0) as usize;
+                    let resolution = match resolutions.get(interface) {
+                        Some(resolution) => *resolution,
+                        None => bail!("Packet of unknown pcapng interface {}", interface),
+                    };
+                    let high = u32_at(big_endian, &body, 4) as u64;
+                    let low = u32_at(big_endian, &body, 8) as u64;
+                    let incl_len = u32_at(big_endian, &body, 12) as usize;
+                    let orig_len = u32_at(big_endian, &body, 16) as usize;
+                    let padded = (incl_len + 3) & !3;
+                    if body.len() < 20 + padded {
+                        bail!("Truncated pcapng enhanced packet");
+                    }
+                    let meta = options(big_endian, &body[20 + padded..])
+                        .filter(|(code, _)| *code == OPT_COMMENT)
+                        .find_map(|(_, value)| {
+                            PacketMeta::from_comment(&String::from_utf8_lossy(value))
+                        });
+                    return Ok(Some(StoredPacket {
+                        ts: ts_of((high << 32) | low, resolution),
+                        orig_len,
+                        data: body[20..20 + incl_len].to_vec(),
+                        meta,
+                    }));
+                }
+                // Other blocks carry no packets of interest
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for StoreReader<R> {
+    type Item = Result<StoredPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+/// Fills `buf`, returning `false` at the end of the file.
+fn read_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(_) => Ok(true),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn u16_at(big_endian: bool, buf: &[u8], offset: usize) -> u16 {
+    let bytes = [buf[offset], buf[offset + 1]];
+    match big_endian {
+        true => u16::from_be_bytes(bytes),
+        false => u16::from_le_bytes(bytes),
+    }
+}
+
+fn u32_at(big_endian: bool, buf: &[u8], offset: usize) -> u32 {
+    let bytes = [
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ];
+    match big_endian {
+        true => u32::from_be_bytes(bytes),
+        false => u32::from_le_bytes(bytes),
+    }
+}
+
+/// Returns the code and value of the pcapng options of `buf`.
+fn options(big_endian: bool, buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> + '_ {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        if offset + 4 > buf.len() {
+            return None;
+        }
+        let code = u16_at(big_endian, buf, offset);
+        let len = u16_at(big_endian, buf, offset + 2) as usize;
+        let value = buf.get(offset + 4..offset + 4 + len)?;
+        if code == OPT_ENDOFOPT {
+            return None;
+        }
+        offset += 4 + ((len + 3) & !3);
+        Some((code, value))
+    })
+}
+
+/// Converts a pcapng timestamp of `units` at resolution `resolution` (`if_tsresol`).
+fn ts_of(units: u64, resolution: u8) -> Duration {
+    let exponent = (resolution & 0x7f) as u32;
+    let nanos = match resolution & 0x80 {
+        0 if exponent <= 9 => units as u128 * 10_u128.pow(9 - exponent),
+        0 => units as u128 / 10_u128.pow((exponent - 9).min(38)),
+        _ => (units as u128 * 1_000_000_000) >> exponent.min(127),
+    };
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+/// Returns a pcapng block of type `block_type`, padding `body` to 32 bits.
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let padded = (body.len() + 3) & !3;
+    let total = (12 + padded) as u32;
+    let mut buf = Vec::with_capacity(total as usize);
+    buf.extend_from_slice(&block_type.to_le_bytes());
+    buf.extend_from_slice(&total.to_le_bytes());
+    buf.extend_from_slice(body);
+    buf.resize(8 + padded, 0);
+    buf.extend_from_slice(&total.to_le_bytes());
+    buf
+}
+
+/// Appends a pcapng option to `buf`, padding its value to 32 bits.
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+/// Returns the pcapng section header block, of unspecified section length.
+pub(crate) fn section_header() -> Vec<u8> {
+    let mut body = Vec::with_capacity(16);
+    body.extend_from_slice(&0x1a2b_3c4d_u32.to_le_bytes());
+    body.extend_from_slice(&1_u16.to_le_bytes());
+    body.extend_from_slice(&0_u16.to_le_bytes());
+    body.extend_from_slice(&(-1_i64).to_le_bytes());
+    block(SECTION_HEADER, &body)
+}
+
+/// Returns the pcapng description of the Ethernet interface of a section, with nanosecond
+/// timestamps.
+pub(crate) fn interface_description(snaplen: usize) -> Vec<u8> {
+    let mut body = Vec::with_capacity(20);
+    body.extend_from_slice(&(LINKTYPE_ETHERNET as u16).to_le_bytes());
+    body.extend_from_slice(&0_u16.to_le_bytes());
+    body.extend_from_slice(&(snaplen as u32).to_le_bytes());
+    push_option(&mut body, IF_TSRESOL, &[9]);
+    push_option(&mut body, OPT_ENDOFOPT, &[]);
+    block(INTERFACE_DESCRIPTION, &body)
+}
+
+/// Returns the pcapng enhanced packet block of `record`, on the first interface of the section.
+pub(crate) fn enhanced_packet_block(record: &TapRecord) -> Vec<u8> {
+    let ts = record.ts().as_nanos() as u64;
+    let data = record.data();
+    let comment = record.meta().to_comment();
+    let mut body = Vec::with_capacity(32 + data.len() + comment.len());
+    body.extend_from_slice(&0_u32.to_le_bytes());
+    body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(ts as u32).to_le_bytes());
+    body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(record.orig_len() as u32).to_le_bytes());
+    body.extend_from_slice(data);
+    body.resize((body.len() + 3) & !3, 0);
+    push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+    push_option(&mut body, OPT_ENDOFOPT, &[]);
+    block(ENHANCED_PACKET, &body)
+}
@@ -0,0 +1,321 @@
+//! Flow table size limits and metrics.
+//!
+//! The flow table is keyed by packed flows, fixed-size copies of each
+//! [Flow](crate::protocols::layer4::Flow) key that take half its size. The table size can be
+//! capped with the `[flow_table]` options of the runtime configuration (see
+//! [FlowTableConfig](crate::config::FlowTableConfig)). New flows are not tracked while the table
+//! is full, and the `oldest` eviction policy evicts the least recently seen flows on every prune
+//! of a full table, so that eviction never runs in the packet processing path.
+//!
+//! With `sticky_timeout_secs`, flows that matched at least once are kept past the flow timeout in a
+//! bounded sticky class, so that flows with payloads far apart, e.g. periodic beacons, keep their
+//! matched state. Sticky flows are not evicted to make room.
+//!
+//! The table is shared by all RX cores. Lookups, insertions and current entries are counted on
+//! the core that performed them or added the flow, and removals by
+//! [FlowRemoval](FlowRemoval) reason. A lookup is counted as contended when another core held the
+//! lock of its table shard, which indicates cores competing for the same part of the table.
+//!
+//! ## Storage
+//! The table is a single sharded map, with one lock per shard over open-addressed buckets, rather
+//! than one table per core. Per-core tables require every packet of a flow, in both directions,
+//! to reach the same core, and the ports do not configure a symmetric RSS key yet (see
+//! `SYMMETRIC_RSS_KEY` in the port module). Flows are also looked up from other cores:
+//! [correlate_icmp_error](crate::filter::FilterCtx::correlate_icmp_error) looks up the flow of an
+//! ICMP error, which RSS steers by the address of the router that sent it,
+//! [prune_flows](crate::filter::FilterCtx::prune_flows) walks the whole table from any thread,
+//! and the runtime saves and restores it from the main core.
+
+use crate::config::{FlowEviction, FlowTableConfig};
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Fraction of `max_entries` a full table is evicted down to.
+const EVICTION_TARGET: f64 = 0.9;
+
+/// Reason a flow was removed from the flow table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowRemoval {
+    /// The flow was not seen for the flow timeout.
+    Idle,
+    /// The flow was closed, see [FilterCtx::remove_flow](crate::filter::FilterCtx::remove_flow).
+    Closed,
+    /// The flow was evicted to make room in a full table.
+    Capacity,
+}
+
+/// Flow table counters of a single core.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CoreFlowStats {
+    /// Core of the counters, `None` for contexts that are not attached to a core.
+    pub core: Option<u32>,
+    /// Number of tracked flows added by the core.
+    pub nb_entries: u64,
+    /// Number of flows added by the core.
+    pub nb_inserted: u64,
+    /// Number of lookups.
+    pub nb_lookups: u64,
+    /// Number of lookups that found the flow.
+    pub nb_hits: u64,
+    /// Number of lookups that waited for another core holding the same table shard.
+    pub nb_contended: u64,
+}
+
+/// Flow table occupancy statistics.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowTableStats {
+    /// Number of tracked flows.
+    pub nb_entries: usize,
+    /// Maximum number of tracked flows, `None` if unlimited.
+    pub max_entries: Option<usize>,
+    /// Number of flows added.
+    pub nb_inserted: u64,
+    /// Number of flows removed after the flow timeout.
+    pub nb_expired: u64,
+    /// Number of flows removed because they were closed.
+    pub nb_closed: u64,
+    /// Number of flows evicted to make room.
+    pub nb_evicted: u64,
+    /// Number of new flows not tracked because the table was full.
+    pub nb_rejected: u64,
+    /// Number of matched flows kept past the flow timeout at the last prune.
+    pub nb_sticky: usize,
+    /// Maximum number of matched flows kept past the flow timeout, `None` if matched flows are not
+    /// kept.
+    pub max_sticky: Option<usize>,
+    /// Number of matched flows removed at the flow timeout because the sticky class was full.
+    pub nb_sticky_overflow: u64,
+    /// Counters of each core, in core order.
+    pub cores: Vec<CoreFlowStats>,
+}
+
+impl FlowTableStats {
+    /// Returns the fraction of `max_entries` in use, `None` if unlimited.
+    pub fn occupancy(&self) -> Option<f64> {
+        self.max_entries
+            .map(|max_entries| self.nb_entries as f64 / max_entries.max(1) as f64)
+    }
+
+    /// Returns the number of lookups of all cores.
+    pub fn nb_lookups(&self) -> u64 {
+        self.cores.iter().map(|core| core.nb_lookups).sum()
+    }
+
+    /// Returns the number of contended lookups of all cores.
+    pub fn nb_contended(&self) -> u64 {
+        self.cores.iter().map(|core| core.nb_contended).sum()
+    }
+}
+
+/// Flow table counters of a single core.
+#[derive(Debug, Default)]
+pub(crate) struct FlowCounters {
+    nb_entries: AtomicU64,
+    nb_inserted: AtomicU64,
+    nb_lookups: AtomicU64,
+    nb_hits: AtomicU64,
+    nb_contended: AtomicU64,
+}
+
+impl FlowCounters {
+    #[inline]
+    pub(crate) fn record_lookup(&self, hit: bool, contended: bool) {
+        self.nb_lookups.fetch_add(1, Ordering::Relaxed);
+        if hit {
+            self.nb_hits.fetch_add(1, Ordering::Relaxed);
+        }
+        if contended {
+            self.nb_contended.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a flow added by this core.
+    #[inline]
+    pub(crate) fn record_inserted(&self) {
+        self.nb_inserted.fetch_add(1, Ordering::Relaxed);
+        self.nb_entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the removal of a flow added by this core, or of a flow replaced by another core.
+    #[inline]
+    pub(crate) fn record_removed(&self) {
+        self.nb_entries.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self, core: Option<u32>) -> CoreFlowStats {
+        CoreFlowStats {
+            core,
+            nb_entries: self.nb_entries.load(Ordering::Relaxed),
+            nb_inserted: self.nb_inserted.load(Ordering::Relaxed),
+            nb_lookups: self.nb_lookups.load(Ordering::Relaxed),
+            nb_hits: self.nb_hits.load(Ordering::Relaxed),
+            nb_contended: self.nb_contended.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Retention of the matched flows idle past the flow timeout.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sticky {
+    /// Idle timeout of sticky flows.
+    pub(crate) timeout: Duration,
+    /// Maximum number of sticky flows.
+    pub(crate) max_flows: usize,
+}
+
+/// Size limit and counters of a flow table.
+#[derive(Debug, Default)]
+pub(crate) struct FlowLimits {
+    /// Maximum number of entries, `0` if unlimited.
+    max_entries: AtomicUsize,
+    evict: AtomicBool,
+    /// Idle timeout of sticky flows in seconds, `0` if matched flows are not kept.
+    sticky_timeout: AtomicU64,
+    max_sticky: AtomicUsize,
+    nb_sticky: AtomicUsize,
+    nb_sticky_overflow: AtomicU64,
+    nb_expired: AtomicU64,
+    nb_closed: AtomicU64,
+    nb_evicted: AtomicU64,
+    nb_rejected: AtomicU64,
+    cores: RwLock<BTreeMap<u32, Arc<FlowCounters>>>,
+    /// Counters of contexts that are not attached to a core.
+    unattached: Arc<FlowCounters>,
+}
+
+impl FlowLimits {
+    pub(crate) fn new() -> Self {
+        FlowLimits::default()
+    }
+
+    /// Applies flow table options from the runtime configuration.
+    pub(crate) fn configure(&self, config: &FlowTableConfig) {
+        self.max_entries
+            .store(config.max_entries.unwrap_or(0), Ordering::Relaxed);
+        self.evict
+            .store(config.eviction == FlowEviction::Oldest, Ordering::Relaxed);
+        if let Some(max_entries) = config.max_entries {
+            log::info!(
+                "Flow table limited to {} entries, eviction: {:?}",
+                max_entries,
+                config.eviction
+            );
+        }
+        let sticky_timeout = match config.max_sticky {
+            0 => 0,
+            _ => config.sticky_timeout_secs.unwrap_or(0),
+        };
+        self.sticky_timeout.store(sticky_timeout, Ordering::Relaxed);
+        self.max_sticky.store(config.max_sticky, Ordering::Relaxed);
+        if sticky_timeout > 0 {
+            log::info!(
+                "Matched flows kept for {}s, at most {} past the flow timeout",
+                sticky_timeout,
+                config.max_sticky
+            );
+        }
+    }
+
+    /// Returns the retention of matched flows, `None` if they use the flow timeout.
+    pub(crate) fn sticky(&self) -> Option<Sticky> {
+        match self.sticky_timeout.load(Ordering::Relaxed) {
+            0 => None,
+            secs => Some(Sticky {
+                timeout: Duration::from_secs(secs),
+                max_flows: self.max_sticky.load(Ordering::Relaxed),
+            }),
+        }
+    }
+
+    /// Records the number of sticky flows kept by a prune, and of the matched flows it removed
+    /// because the sticky class was full.
+    pub(crate) fn record_sticky(&self, nb_sticky: usize, nb_overflow: usize) {
+        self.nb_sticky.store(nb_sticky, Ordering::Relaxed);
+        self.nb_sticky_overflow
+            .fetch_add(nb_overflow as u64, Ordering::Relaxed);
+    }
+
+    /// Returns whether a new flow can be added to a table of `nb_entries` entries, counting it as
+    /// rejected otherwise.
+    #[inline]
+    pub(crate) fn admit(&self, nb_entries: usize) -> bool {
+        let max_entries = self.max_entries.load(Ordering::Relaxed);
+        if max_entries == 0 || nb_entries < max_entries {
+            return true;
+        }
+        self.nb_rejected.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    /// Returns the number of flows to evict from a table of `nb_entries` entries, `0` unless the
+    /// table is full and the eviction policy is `oldest`.
+    pub(crate) fn nb_to_evict(&self, nb_entries: usize) -> usize {
+        let max_entries = self.max_entries.load(Ordering::Relaxed);
+        if max_entries == 0 || nb_entries < max_entries || !self.evict.load(Ordering::Relaxed) {
+            return 0;
+        }
+        nb_entries - (max_entries as f64 * EVICTION_TARGET) as usize
+    }
+
+    /// Counts `nb_removed` flows removed for `reason`.
+    pub(crate) fn record_removed(&self, reason: FlowRemoval, nb_removed: usize) {
+        let counter = match reason {
+            FlowRemoval::Idle => &self.nb_expired,
+            FlowRemoval::Closed => &self.nb_closed,
+            FlowRemoval::Capacity => &self.nb_evicted,
+        };
+        counter.fetch_add(nb_removed as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the flow table counters of `core`, creating them if needed.
+    pub(crate) fn core(&self, core: u32) -> Arc<FlowCounters> {
+        if let Some(counters) = self.cores.read().unwrap().get(&core) {
+            return Arc::clone(counters);
+        }
+        let mut cores = self.cores.write().unwrap();
+        Arc::clone(cores.entry(core).or_default())
+    }
+
+    /// Returns the counters of contexts that are not attached to a core.
+    pub(crate) fn unattached(&self) -> Arc<FlowCounters> {
+        Arc::clone(&self.unattached)
+    }
+
+    /// Returns the occupancy statistics of a table of `nb_entries` entries.
+    pub(crate) fn stats(&self, nb_entries: usize) -> FlowTableStats {
+        let max_entries = self.max_entries.load(Ordering::Relaxed);
+        let mut cores: Vec<CoreFlowStats> = self
+            .cores
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(core, counters)| counters.stats(Some(*core)))
+            .collect();
+        let unattached = self.unattached.stats(None);
+        if unattached.nb_inserted > 0 || unattached.nb_lookups > 0 {
+            cores.push(unattached);
+        }
+        FlowTableStats {
+            nb_entries,
+            max_entries: if max_entries > 0 {
+                Some(max_entries)
+            } else {
+                None
+            },
+            nb_inserted: cores.iter().map(|core| core.nb_inserted).sum(),
+            nb_expired: self.nb_expired.load(Ordering::Relaxed),
+            nb_closed: self.nb_closed.load(Ordering::Relaxed),
+            nb_evicted: self.nb_evicted.load(Ordering::Relaxed),
+            nb_rejected: self.nb_rejected.load(Ordering::Relaxed),
+            nb_sticky: self.nb_sticky.load(Ordering::Relaxed),
+            max_sticky: self.sticky().map(|sticky| sticky.max_flows),
+            nb_sticky_overflow: self.nb_sticky_overflow.load(Ordering::Relaxed),
+            cores,
+        }
+    }
+}
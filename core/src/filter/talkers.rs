@@ -0,0 +1,264 @@
+//! Heavy-hitter (top talker) detection.
+//!
+//! When enabled, every RX core counts one in `sample_rate` received packets against its source
+//! address, destination address and flow. Counts are kept with the Space-Saving algorithm: each
+//! core keeps a fixed number of counters per kind, and a talker that is not tracked when the
+//! counters are full replaces the smallest one, inheriting its count. The counters form a min-heap
+//! on their byte counts, so that finding and updating the smallest one takes logarithmic time.
+//! Memory is therefore bounded regardless of the size of the address space, and the count of a
+//! talker is overestimated by at most its reported error. The monitor merges the counters of all
+//! cores and reports the largest talkers, with counts scaled by the sample rate.
+
+use crate::config::TopTalkersConfig;
+use crate::protocols::layer4::{Flow, L4Context};
+use crate::utils::hash::StableHasher;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasherDefault, Hash};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::Serialize;
+
+
+/// Estimated traffic of a talker.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Talker<K> {
+    /// Source address, destination address or flow.
+    pub key: K,
+    /// Estimated number of bytes.
+    pub bytes: u64,
+    /// Estimated number of packets.
+    pub pkts: u64,
+    /// Maximum overestimation of `bytes`.
+    pub error: u64,
+}
+
+/// Largest talkers across all cores, largest first.
+#[derive(Debug, Clone, Serialize)]
+pub struct TalkerStats {
+    /// Largest source addresses.
+    pub sources: Vec<Talker<IpAddr>>,
+    /// Largest destination addresses.
+    pub destinations: Vec<Talker<IpAddr>>,
+    /// Largest flows.
+    pub flows: Vec<Talker<Flow>>,
+}
+
+/// Space-Saving counters of one kind of talker.
+#[derive(Debug)]
+struct Sketch<K> {
+    /// Counters, as a binary min-heap on `bytes`.
+    heap: Vec<Talker<K>>,
+    /// Index in `heap` of the counter of each key.
+    index: HashMap<K, usize, BuildHasherDefault<StableHasher>>,
+}
+
+impl<K: Hash + Eq + Copy> Sketch<K> {
+    fn new() -> Self {
+        Sketch {
+            heap: vec![],
+            index: HashMap::default(),
+        }
+    }
+
+    fn record(&mut self, key: K, bytes: u64, capacity: usize) {
+        if let Some(&idx) = self.index.get(&key) {
+            self.heap[idx].bytes += bytes;
+            self.heap[idx].pkts += 1;
+            self.sift_down(idx);
+            return;
+        }
+        let mut talker = Talker {
+            key,
+            bytes,
+            pkts: 1,
+            error: 0,
+        };
+        if self.heap.len() < capacity {
+            self.index.insert(key, self.heap.len());
+            self.heap.push(talker);
+            self.sift_up(self.heap.len() - 1);
+            return;
+        }
+        let min = match self.heap.first() {
+            Some(min) => *min,
+            None => return,
+        };
+        self.index.remove(&min.key);
+        talker.bytes += min.bytes;
+        talker.pkts += min.pkts;
+        talker.error = min.bytes;
+        self.heap[0] = talker;
+        self.index.insert(key, 0);
+        self.sift_down(0);
+    }
+
+    /// Moves the counter at `idx` up the heap until its parent is not larger.
+    fn sift_up(&mut self, mut idx: usize) {
+        while idx > 0 {
+            let parent = (idx - 1) / 2;
+            if self.heap[parent].bytes <= self.heap[idx].bytes {
+                break;
+            }
+            self.swap(idx, parent);
+            idx = parent;
+        }
+    }
+
+    /// Moves the counter at `idx` down the heap until its children are not smaller.
+    fn sift_down(&mut self, mut idx: usize) {
+        loop {
+            let mut smallest = idx;
+            for child in [2 * idx + 1, 2 * idx + 2] {
+                if child < self.heap.len() && self.heap[child].bytes < self.heap[smallest].bytes {
+                    smallest = child;
+                }
+            }
+            if smallest == idx {
+                break;
+            }
+            self.swap(idx, smallest);
+            idx = smallest;
+        }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        self.index.insert(self.heap[a].key, a);
+        self.index.insert(self.heap[b].key, b);
+    }
+
+    /// Adds the counters of this sketch to `merged`.
+    fn merge_into(&self, merged: &mut HashMap<K, Talker<K>>) {
+        for talker in self.heap.iter() {
+            let key = &talker.key;
+            let total = merged.entry(*key).or_insert(Talker {
+                key: *key,
+                bytes: 0,
+                pkts: 0,
+                error: 0,
+            });
+            total.bytes += talker.bytes;
+            total.pkts += talker.pkts;
+            total.error += talker.error;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Sketches {
+    sources: Sketch<IpAddr>,
+    destinations: Sketch<IpAddr>,
+    flows: Sketch<Flow>,
+}
+
+/// Talker counters of a single core.
+#[derive(Debug)]
+pub(crate) struct CoreTalkers {
+    nb_pkts: AtomicU64,
+    sketches: Mutex<Sketches>,
+}
+
+impl Default for CoreTalkers {
+    fn default() -> Self {
+        CoreTalkers {
+            nb_pkts: AtomicU64::new(0),
+            sketches: Mutex::new(Sketches {
+                sources: Sketch::new(),
+                destinations: Sketch::new(),
+                flows: Sketch::new(),
+            }),
+        }
+    }
+}
+
+/// Registry of the talker counters of all cores. Disabled until configured.
+#[derive(Debug, Default)]
+pub(crate) struct TopTalkers {
+    cores: RwLock<BTreeMap<u32, Arc<CoreTalkers>>>,
+    sample_rate: AtomicU64,
+    capacity: AtomicUsize,
+    nb_reported: AtomicUsize,
+}
+
+impl TopTalkers {
+    pub(crate) fn new() -> Self {
+        TopTalkers::default()
+    }
+
+    /// Applies heavy-hitter detection options from the runtime configuration.
+    pub(crate) fn configure(&self, config: &TopTalkersConfig) {
+        self.capacity.store(config.capacity, Ordering::Relaxed);
+        self.nb_reported.store(config.nb_reported, Ordering::Relaxed);
+        self.sample_rate
+            .store(config.sample_rate.max(1), Ordering::Relaxed);
+        log::info!(
+            "Top talker detection enabled, sample rate: 1/{}",
+            config.sample_rate.max(1)
+        );
+    }
+
+    /// Returns the talker counters of `core`, creating them if needed.
+    pub(crate) fn core(&self, core: u32) -> Arc<CoreTalkers> {
+        if let Some(talkers) = self.cores.read().unwrap().get(&core) {
+            return Arc::clone(talkers);
+        }
+        let mut cores = self.cores.write().unwrap();
+        Arc::clone(cores.entry(core).or_default())
+    }
+
+    /// Returns whether the next packet received by `core` is sampled.
+    #[inline]
+    pub(crate) fn is_sampled(&self, core: &CoreTalkers) -> bool {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        sample_rate > 0 && core.nb_pkts.fetch_add(1, Ordering::Relaxed) % sample_rate == 0
+    }
+
+    /// Counts a sampled packet of `len` bytes with context `ctx` and flow `flow` on `core`.
+    pub(crate) fn record(&self, core: &CoreTalkers, ctx: &L4Context, flow: Flow, len: usize) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        let mut sketches = core.sketches.lock().unwrap();
+        sketches.sources.record(ctx.src.ip(), len as u64, capacity);
+        sketches.destinations.record(ctx.dst.ip(), len as u64, capacity);
+        sketches.flows.record(flow, len as u64, capacity);
+    }
+
+    /// Merges the counters of all cores. `None` if detection is not enabled.
+    pub(crate) fn stats(&self) -> Option<TalkerStats> {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        if sample_rate == 0 {
+            return None;
+        }
+        let mut sources = HashMap::new();
+        let mut destinations = HashMap::new();
+        let mut flows = HashMap::new();
+        for core in self.cores.read().unwrap().values() {
+            let sketches = core.sketches.lock().unwrap();
+            sketches.sources.merge_into(&mut sources);
+            sketches.destinations.merge_into(&mut destinations);
+            sketches.flows.merge_into(&mut flows);
+        }
+        let nb_reported = self.nb_reported.load(Ordering::Relaxed);
+        Some(TalkerStats {
+            sources: top(sources, nb_reported, sample_rate),
+            destinations: top(destinations, nb_reported, sample_rate),
+            flows: top(flows, nb_reported, sample_rate),
+        })
+    }
+}
+
+/// Returns the `n` largest talkers of `merged`, with counts scaled by `sample_rate`.
+fn top<K>(merged: HashMap<K, Talker<K>>, n: usize, sample_rate: u64) -> Vec<Talker<K>> {
+    let mut talkers: Vec<_> = merged.into_values().collect();
+    talkers.sort_unstable_by_key(|talker| Reverse(talker.bytes));
+    talkers.truncate(n);
+    for talker in talkers.iter_mut() {
+        talker.bytes *= sample_rate;
+        talker.pkts *= sample_rate;
+        talker.error *= sample_rate;
+    }
+    talkers
+}
@@ -0,0 +1,302 @@
+//! Live packet tap.
+//!
+//! Packets handed to [FilterCtx::tap_packet](crate::filter::FilterCtx::tap_packet), typically the
+//! packets of matching flows, are copied and written as a pcap stream to a named pipe (FIFO) or
+//! file, so that local tools can watch them in real time:
+//! ```sh
+//! mkfifo /tmp/retina.pcap
+//! wireshark -k -i /tmp/retina.pcap
+//! ```
+//!
+//! The stream is written by a background thread. RX cores only copy the packet and enqueue it
+//! without blocking, in one of the [priority classes](crate::filter::priority) of the queue;
+//! packets beyond the configured rate or queue size are dropped and counted. If
+//! the reader goes away, the writer reopens the pipe and starts a new stream for the next reader.
+//! The tap can be paused and resumed while running with
+//! [FilterCtx::enable_tap](crate::filter::FilterCtx::enable_tap). Failures to open the pipe or to
+//! start a stream are reported to the
+//! [storage error hooks](crate::hooks::Hooks::on_storage_error).
+
+use super::priority::{self, ClassStats, Priority, PriorityReceiver, PrioritySender};
+use super::store::PacketMeta;
+use crate::config::{StoragePriorityConfig, TapConfig};
+use crate::hooks::{Hooks, StorageError, StorageTarget};
+use crate::memory::mbuf::Mbuf;
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+/// pcap link type of Ethernet frames.
+pub(crate) const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Delivery counters of the tap.
+#[derive(Debug, Clone, Copy)]
+pub struct TapStats {
+    /// Whether the tap is enabled.
+    pub enabled: bool,
+    /// Number of packets queued for writing.
+    pub nb_tapped: u64,
+    /// Number of packets dropped by the rate limit or because their priority class was full.
+    pub nb_dropped: u64,
+}
+
+/// A copied packet.
+#[derive(Debug, Clone)]
+pub(crate) struct TapRecord {
+    ts: Duration,
+    orig_len: usize,
+    data: Vec<u8>,
+    meta: PacketMeta,
+}
+
+impl TapRecord {
+    /// Copies at most `snaplen` bytes of the first segment of `mbuf`, received at `ts` (UNIX time),
+    /// with its metadata `meta`. The original length is the length of the whole packet.
+    pub(crate) fn new(ts: Duration, mbuf: &Mbuf, snaplen: usize, meta: PacketMeta) -> Self {
+        let data = mbuf.data();
+        TapRecord {
+            ts,
+            orig_len: mbuf.pkt_len(),
+            data: data[..data.len().min(snaplen)].to_vec(),
+            meta,
+        }
+    }
+
+    /// Returns the reception time of the packet (UNIX time).
+    pub(crate) fn ts(&self) -> Duration {
+        self.ts
+    }
+
+    /// Returns the original length of the packet.
+    pub(crate) fn orig_len(&self) -> usize {
+        self.orig_len
+    }
+
+    /// Returns the captured bytes of the packet.
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the metadata of the packet.
+    pub(crate) fn meta(&self) -> PacketMeta {
+        self.meta
+    }
+
+    /// Returns whether the packet was cut at the snapshot length.
+    pub(crate) fn is_snapped(&self) -> bool {
+        self.data.len() < self.orig_len
+    }
+
+    /// Keeps the first `len` captured bytes of the packet.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+
+    /// Returns a copy of the packet cut at `snaplen` bytes.
+    pub(crate) fn snapped(&self, snaplen: usize) -> TapRecord {
+        TapRecord {
+            data: self.data[..self.data.len().min(snaplen)].to_vec(),
+            ..*self
+        }
+    }
+
+    /// Returns the size of the record in a pcap file, including its header.
+    pub(crate) fn pcap_len(&self) -> usize {
+        16 + self.data.len()
+    }
+}
+
+#[derive(Debug)]
+struct TapWriter {
+    tx: PrioritySender<TapRecord>,
+    snaplen: usize,
+    max_pps: u64,
+}
+
+/// Shared packet tap of a filter.
+#[derive(Debug, Default)]
+pub(crate) struct Tap {
+    writer: RwLock<Option<TapWriter>>,
+    enabled: AtomicBool,
+    /// Second (UNIX time) of the current rate limit window.
+    window: AtomicU64,
+    /// Number of packets tapped in the current rate limit window.
+    window_count: AtomicU64,
+    nb_tapped: AtomicU64,
+    nb_dropped: AtomicU64,
+}
+
+impl Tap {
+    pub(crate) fn new() -> Self {
+        Tap::default()
+    }
+
+    /// Starts the writer thread and enables the tap. Write errors are reported to the storage error
+    /// hooks of `hooks`.
+    pub(crate) fn configure(
+        &self,
+        config: &TapConfig,
+        priority: &StoragePriorityConfig,
+        hooks: Arc<Hooks>,
+    ) -> Result<()> {
+        let (tx, rx) = priority::channel("tap", config.queue_size, priority);
+        let path = config.path.clone();
+        let snaplen = config.snaplen;
+        thread::Builder::new()
+            .name("retina-tap".into())
+            .spawn(move || write_loop(&path, snaplen, rx, &hooks))?;
+        *self.writer.write().unwrap() = Some(TapWriter {
+            tx,
+            snaplen,
+            max_pps: config.max_pps,
+        });
+        self.set_enabled(true);
+        log::info!("Tapping packets to {}", config.path);
+        Ok(())
+    }
+
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns whether the tap is enabled.
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Copies `mbuf` to the tap in the class of `priority`, cut at `rule_snaplen` bytes if set,
+    /// unless the tap is disabled, the rate limit is reached or the class is full. Returns the
+    /// number of bytes queued, `0` if the packet was not queued.
+    #[inline]
+    pub(crate) fn tap(
+        &self,
+        mbuf: &Mbuf,
+        priority: Priority,
+        rule_snaplen: Option<usize>,
+    ) -> usize {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return 0;
+        }
+        let writer = self.writer.read().unwrap();
+        let writer = match writer.as_ref() {
+            Some(writer) => writer,
+            None => return 0,
+        };
+        let ts = Duration::from_nanos(mbuf.timestamp());
+        if writer.max_pps > 0 {
+            let window = ts.as_secs();
+            if self.window.swap(window, Ordering::Relaxed) != window {
+                self.window_count.store(0, Ordering::Relaxed);
+            }
+            if self.window_count.fetch_add(1, Ordering::Relaxed) >= writer.max_pps {
+                self.nb_dropped.fetch_add(1, Ordering::Relaxed);
+                return 0;
+            }
+        }
+        // The tap writes pcap, which has no room for metadata
+        let snaplen = snaplen(writer.snaplen, rule_snaplen);
+        let record = TapRecord::new(ts, mbuf, snaplen, PacketMeta::default());
+        let nb_bytes = record.data().len();
+        match writer.tx.try_send(record, priority) {
+            true => {
+                self.nb_tapped.fetch_add(1, Ordering::Relaxed);
+                nb_bytes
+            }
+            false => {
+                self.nb_dropped.fetch_add(1, Ordering::Relaxed);
+                0
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Option<TapStats> {
+        self.writer.read().unwrap().as_ref()?;
+        Some(TapStats {
+            enabled: self.enabled.load(Ordering::Relaxed),
+            nb_tapped: self.nb_tapped.load(Ordering::Relaxed),
+            nb_dropped: self.nb_dropped.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Returns the counters of each priority class, empty if no tap is configured.
+    pub(crate) fn class_stats(&self) -> Vec<ClassStats> {
+        match self.writer.read().unwrap().as_ref() {
+            Some(writer) => writer.tx.stats(),
+            None => vec![],
+        }
+    }
+}
+
+/// Writes queued packets to `path` as a pcap stream, reopening it whenever the reader goes away,
+/// and reports errors to `hooks`. Returns when all senders are dropped.
+fn write_loop(path: &str, snaplen: usize, mut rx: PriorityReceiver<TapRecord>, hooks: &Hooks) {
+    loop {
+        // Opening a FIFO for writing blocks until a reader opens it.
+        let mut file = match OpenOptions::new().write(true).create(true).truncate(true).open(path) {
+            Ok(file) => file,
+            Err(error) => {
+                log::error!("Tap {} open error: {}", path, error);
+                hooks.storage_error(&StorageError::new(StorageTarget::Tap, path, error));
+                return;
+            }
+        };
+        if let Err(error) = write_header(&mut file, snaplen) {
+            log::warn!("Tap {} write error: {}", path, error);
+            hooks.storage_error(&StorageError::new(StorageTarget::Tap, path, error));
+            thread::sleep(Duration::from_secs(1));
+            continue;
+        }
+        log::info!("Tap reader connected to {}", path);
+        loop {
+            let record = match rx.recv() {
+                Ok(record) => record,
+                Err(_) => return,
+            };
+            if let Err(error) = write_record(&mut file, &record) {
+                log::info!("Tap reader disconnected from {}: {}", path, error);
+                break;
+            }
+        }
+        // Discard the backlog of the previous reader.
+        while rx.try_recv().is_ok() {}
+    }
+}
+
+/// Writes the pcap global header.
+/// Returns the snapshot length of a copy for a destination configured with `snaplen`, cut at
+/// `rule_snaplen` if set. Never above `snaplen`, which the headers of stored files declare.
+#[inline]
+pub(crate) fn snaplen(snaplen: usize, rule_snaplen: Option<usize>) -> usize {
+    rule_snaplen.map_or(snaplen, |rule_snaplen| rule_snaplen.min(snaplen))
+}
+
+pub(crate) fn write_header(file: &mut impl Write, snaplen: usize) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(24);
+    buf.extend_from_slice(&0xa1b2_c3d4_u32.to_le_bytes());
+    buf.extend_from_slice(&2_u16.to_le_bytes());
+    buf.extend_from_slice(&4_u16.to_le_bytes());
+    buf.extend_from_slice(&0_i32.to_le_bytes());
+    buf.extend_from_slice(&0_u32.to_le_bytes());
+    buf.extend_from_slice(&(snaplen as u32).to_le_bytes());
+    buf.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    file.write_all(&buf)?;
+    file.flush()
+}
+
+/// Writes a pcap packet record.
+pub(crate) fn write_record(file: &mut impl Write, record: &TapRecord) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(16 + record.data.len());
+    buf.extend_from_slice(&(record.ts.as_secs() as u32).to_le_bytes());
+    buf.extend_from_slice(&record.ts.subsec_micros().to_le_bytes());
+    buf.extend_from_slice(&(record.data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(record.orig_len as u32).to_le_bytes());
+    buf.extend_from_slice(&record.data);
+    file.write_all(&buf)
+}
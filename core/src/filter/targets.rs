@@ -0,0 +1,154 @@
+//! Per-rule destinations of stored packets.
+//!
+//! Different teams want the packets of different rules in different places. Each
+//! `[[store_target]]` entry of the runtime configuration (see
+//! [StoreTargetConfig](crate::config::StoreTargetConfig)) names a destination with its own
+//! [rolling capture](crate::filter::capture) directory, a [collector](crate::filter::forward), or
+//! both. Rules pick a destination with their `store_target`, and the packets stored with
+//! [FilterCtx::store_packet](crate::filter::FilterCtx::store_packet), the packets matching capture
+//! rules and the [backfill](crate::filter::backfill) of matched flows are routed to the target of
+//! the highest-priority rule they matched (see
+//! [store targets](crate::filter::rule#store-targets)).
+//!
+//! The rolling capture of a target has its own writer thread, files and finalization, like the
+//! default one, except that payloads are never deduplicated. Collectors named by a target are
+//! dedicated to it, and no longer receive the packets stored to the default collectors. Packets
+//! routed to a target are subject to the [VLAN policy](crate::filter::vlan), the
+//! [storage quotas](crate::filter::quota) and load shedding like the other stored packets.
+//! Per-target counters are reported by the monitor.
+
+use super::capture::{Capture, CaptureStats};
+use crate::config::{ForwardConfig, StoragePriorityConfig, StoreTargetConfig};
+use crate::hooks::Hooks;
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+/// Routing counters of a store target.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoreTargetStats {
+    /// Name of the target.
+    pub name: String,
+    /// Number of packets routed to the target.
+    pub nb_routed: u64,
+    /// Delivery counters of the rolling capture of the target, `None` if it has none.
+    pub capture: Option<CaptureStats>,
+    /// Name of the collector of the target, `None` if it has none.
+    pub collector: Option<String>,
+}
+
+/// A configured store target.
+#[derive(Debug)]
+pub(crate) struct StoreTarget {
+    name: String,
+    capture: Option<Capture>,
+    collector: Option<String>,
+    nb_routed: AtomicU64,
+}
+
+impl StoreTarget {
+    /// Returns the rolling capture of the target, `None` if it has none.
+    pub(crate) fn capture(&self) -> Option<&Capture> {
+        self.capture.as_ref()
+    }
+
+    /// Returns the name of the collector of the target, `None` if it has none.
+    pub(crate) fn collector(&self) -> Option<&str> {
+        self.collector.as_deref()
+    }
+}
+
+/// Store targets shared by all copies of a filter, routing nothing until configured.
+#[derive(Debug, Default)]
+pub(crate) struct StoreTargets {
+    targets: RwLock<Vec<StoreTarget>>,
+    /// Whether any target is configured, checked before taking the lock.
+    enabled: AtomicBool,
+}
+
+impl StoreTargets {
+    pub(crate) fn new() -> Self {
+        StoreTargets::default()
+    }
+
+    /// Starts the rolling captures of the targets of `config`, whose collectors must be among
+    /// `collectors`. Errors of the captures are reported to the storage error hooks of `hooks`.
+    pub(crate) fn configure(
+        &self,
+        config: &[StoreTargetConfig],
+        collectors: &[ForwardConfig],
+        priority: &StoragePriorityConfig,
+        hooks: &Arc<Hooks>,
+    ) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut targets = vec![];
+        for entry in config.iter() {
+            if !names.insert(entry.name.as_str()) {
+                bail!("Duplicate store target {}", entry.name);
+            }
+            if entry.capture.is_none() && entry.collector.is_none() {
+                bail!("Store target {} has neither a capture nor a collector", entry.name);
+            }
+            if let Some(collector) = &entry.collector {
+                if !collectors.iter().any(|forward| &forward.name == collector) {
+                    bail!("Store target {}: unknown collector {}", entry.name, collector);
+                }
+            }
+            let capture = match &entry.capture {
+                Some(capture_config) => {
+                    let capture = Capture::new();
+                    capture.configure(capture_config, priority, Arc::clone(hooks))?;
+                    Some(capture)
+                }
+                None => None,
+            };
+            log::info!("Routing packets of store target {}", entry.name);
+            targets.push(StoreTarget {
+                name: entry.name.clone(),
+                capture,
+                collector: entry.collector.clone(),
+                nb_routed: AtomicU64::new(0),
+            });
+        }
+        self.enabled.store(!targets.is_empty(), Ordering::Relaxed);
+        *self.targets.write().unwrap() = targets;
+        Ok(())
+    }
+
+    /// Returns whether any target is configured.
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Counts a packet routed to the target named `name` and hands the target to `route`. Returns
+    /// `None` without calling `route` if no such target is configured.
+    pub(crate) fn route<R>(&self, name: &str, route: impl FnOnce(&StoreTarget) -> R) -> Option<R> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let targets = self.targets.read().unwrap();
+        let target = targets.iter().find(|target| target.name == name)?;
+        target.nb_routed.fetch_add(1, Ordering::Relaxed);
+        Some(route(target))
+    }
+
+    /// Returns the counters of each target, in configuration order.
+    pub(crate) fn stats(&self) -> Vec<StoreTargetStats> {
+        self.targets
+            .read()
+            .unwrap()
+            .iter()
+            .map(|target| StoreTargetStats {
+                name: target.name.clone(),
+                nb_routed: target.nb_routed.load(Ordering::Relaxed),
+                capture: target.capture.as_ref().and_then(|capture| capture.stats()),
+                collector: target.collector.clone(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,156 @@
+//! Match rate safeguard.
+//!
+//! A bad rule, e.g. one that matches every payload, can flood alert subscribers and whatever the
+//! callback stores for matching flows. When enabled, the matches of every rule are counted along
+//! with the bytes of the matching payloads, and rules whose match rate or matched byte rate exceed
+//! the configured limits over a measurement interval are disabled: they are removed from the active
+//! rules, logged as errors and published to alert subscribers.
+//!
+//! Disabled rules stay disabled across rule updates, including rule file reloads, until they are
+//! re-enabled with [FilterCtx::enable_rule](crate::filter::FilterCtx::enable_rule).
+
+use super::rule::Rule;
+use crate::config::ThrottleConfig;
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+
+/// A rule disabled for exceeding the match rate limits.
+#[derive(Debug, Clone, Serialize)]
+pub struct ThrottledRule {
+    /// The disabled rule.
+    pub rule: Rule,
+    /// Time the rule was disabled.
+    pub disabled_at: SystemTime,
+    /// Matches per second over the interval that exceeded the limits.
+    pub match_rate: f64,
+    /// Bytes of matching payloads per second over the interval that exceeded the limits.
+    pub byte_rate: f64,
+}
+
+#[derive(Debug, Default)]
+struct RuleRate {
+    nb_matches: AtomicU64,
+    nb_bytes: AtomicU64,
+}
+
+/// Match rate limits and disabled rules of a filter.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    config: RwLock<Option<ThrottleConfig>>,
+    /// Matches of each rule since `window_start`.
+    rates: RwLock<HashMap<Rule, Arc<RuleRate>>>,
+    window_start: Mutex<Instant>,
+    disabled: Mutex<Vec<ThrottledRule>>,
+}
+
+impl Throttle {
+    pub(crate) fn new() -> Self {
+        Throttle {
+            config: RwLock::new(None),
+            rates: RwLock::new(HashMap::new()),
+            window_start: Mutex::new(Instant::now()),
+            disabled: Mutex::new(vec![]),
+        }
+    }
+
+    /// Applies match rate limits from the runtime configuration.
+    pub(crate) fn configure(&self, config: &ThrottleConfig) {
+        log::info!(
+            "Throttling rules above {:?} matches/s or {:?} bytes/s",
+            config.max_match_rate,
+            config.max_byte_rate
+        );
+        *self.config.write().unwrap() = Some(config.clone());
+        *self.window_start.lock().unwrap() = Instant::now();
+    }
+
+    /// Returns whether match rates are measured.
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config.read().unwrap().is_some()
+    }
+
+    /// Counts a match of a payload of `nb_bytes` bytes against each of `rules`.
+    pub(crate) fn record(&self, rules: &[Rule], nb_bytes: usize) {
+        for rule in rules {
+            let rate = self.rates.read().unwrap().get(rule).cloned();
+            let rate = match rate {
+                Some(rate) => rate,
+                None => Arc::clone(self.rates.write().unwrap().entry(rule.clone()).or_default()),
+            };
+            rate.nb_matches.fetch_add(1, Ordering::Relaxed);
+            rate.nb_bytes.fetch_add(nb_bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Ends the measurement interval if it elapsed, and disables the rules that exceeded the
+    /// limits over it. Returns the newly disabled rules.
+    pub(crate) fn check(&self) -> Vec<ThrottledRule> {
+        let config = match self.config.read().unwrap().clone() {
+            Some(config) => config,
+            None => return vec![],
+        };
+        let elapsed = {
+            let mut window_start = self.window_start.lock().unwrap();
+            let elapsed = window_start.elapsed();
+            if elapsed < Duration::from_secs(config.interval) {
+                return vec![];
+            }
+            *window_start = Instant::now();
+            elapsed.as_secs_f64()
+        };
+        let rates = mem::take(&mut *self.rates.write().unwrap());
+        let throttled: Vec<ThrottledRule> = rates
+            .into_iter()
+            .filter_map(|(rule, rate)| {
+                let match_rate = rate.nb_matches.load(Ordering::Relaxed) as f64 / elapsed;
+                let byte_rate = rate.nb_bytes.load(Ordering::Relaxed) as f64 / elapsed;
+                let exceeded = config.max_match_rate.map_or(false, |max| match_rate > max)
+                    || config.max_byte_rate.map_or(false, |max| byte_rate > max);
+                if !exceeded {
+                    return None;
+                }
+                Some(ThrottledRule {
+                    rule,
+                    disabled_at: SystemTime::now(),
+                    match_rate,
+                    byte_rate,
+                })
+            })
+            .collect();
+        self.disabled
+            .lock()
+            .unwrap()
+            .extend(throttled.iter().cloned());
+        throttled
+    }
+
+    /// Removes the disabled rules from `rules`.
+    pub(crate) fn retain_enabled(&self, rules: &mut Vec<Rule>) {
+        let disabled = self.disabled.lock().unwrap();
+        if !disabled.is_empty() {
+            rules.retain(|rule| !disabled.iter().any(|throttled| &throttled.rule == rule));
+        }
+    }
+
+    /// Re-enables the disabled rules with pattern `pattern`. Returns the re-enabled rules.
+    pub(crate) fn enable(&self, pattern: &str) -> Vec<Rule> {
+        let mut disabled = self.disabled.lock().unwrap();
+        let (enabled, kept): (Vec<_>, Vec<_>) = mem::take(&mut *disabled)
+            .into_iter()
+            .partition(|throttled| throttled.rule.pattern == pattern);
+        *disabled = kept;
+        enabled.into_iter().map(|throttled| throttled.rule).collect()
+    }
+
+    /// Returns the disabled rules.
+    pub(crate) fn disabled(&self) -> Vec<ThrottledRule> {
+        self.disabled.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,185 @@
+//! Per-core packet tracing.
+//!
+//! When tracing is enabled for a core, the packets it receives, its flow table lookups, its
+//! filter verdicts and errors reported by the callback are recorded in a fixed-size ring buffer.
+//! Only the most recent records are kept. The ring buffer of any core can be dumped from any
+//! thread while the runtime is running, which helps reproduce intermittent parsing bugs without
+//! logging every packet.
+//!
+//! ## Example
+//! ```
+//! let filter_ctx = FilterCtx::new(100_000, Duration::from_secs(60), regexes);
+//! filter_ctx.enable_trace(1);
+//! // ... later, from another thread holding a copy of `filter_ctx`:
+//! for record in filter_ctx.dump_trace(1) {
+//!     println!("{}", record);
+//! }
+//! ```
+
+use crate::config::TraceConfig;
+use crate::dpdk;
+use crate::protocols::layer4::Flow;
+use crate::timebase;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// A traced packet processing event.
+#[derive(Debug, Clone)]
+pub enum TraceEvent {
+    /// A packet was received.
+    Packet { port: u16, queue: u16, len: usize },
+    /// The flow table was looked up for a flow.
+    FlowLookup { flow: Flow, existing: bool },
+    /// A flow was added to the flow table.
+    FlowAdd { flow: Flow },
+    /// A payload of a flow was matched against the rules.
+    Verdict { flow: Flow, offset: usize, matched: bool },
+    /// A payload of a flow was beyond the scan depth and not matched.
+    Skipped { flow: Flow, offset: usize },
+    /// An error reported by the callback.
+    Error(String),
+}
+
+/// A trace ring buffer entry.
+#[derive(Debug, Clone)]
+pub struct TraceRecord {
+    /// Sequence number of the record on its core.
+    pub seq: u64,
+    /// TSC cycle count at the time of the event.
+    pub tsc: u64,
+    /// Wall-clock time of the event, in nanoseconds since the UNIX epoch (see
+    /// [timebase](crate::timebase)).
+    pub ts: u64,
+    pub event: TraceEvent,
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} [{} {}] ", self.seq, self.ts, self.tsc)?;
+        match &self.event {
+            TraceEvent::Packet { port, queue, len } => {
+                write!(f, "packet port={} queue={} len={}", port, queue, len)
+            }
+            TraceEvent::FlowLookup { flow, existing } => {
+                write!(f, "lookup {:?} existing={}", flow, existing)
+            }
+            TraceEvent::FlowAdd { flow } => write!(f, "add {:?}", flow),
+            TraceEvent::Verdict {
+                flow,
+                offset,
+                matched,
+            } => write!(f, "verdict {:?} offset={} matched={}", flow, offset, matched),
+            TraceEvent::Skipped { flow, offset } => {
+                write!(f, "skipped {:?} offset={}", flow, offset)
+            }
+            TraceEvent::Error(error) => write!(f, "error {}", error),
+        }
+    }
+}
+
+/// Trace ring buffer of a single core.
+#[derive(Debug)]
+pub(crate) struct TraceRing {
+    enabled: AtomicBool,
+    capacity: AtomicUsize,
+    seq: AtomicU64,
+    records: Mutex<VecDeque<TraceRecord>>,
+}
+
+impl TraceRing {
+    fn new(capacity: usize) -> Self {
+        TraceRing {
+            enabled: AtomicBool::new(false),
+            capacity: AtomicUsize::new(capacity),
+            seq: AtomicU64::new(0),
+            records: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Records the event built by `event` if tracing is enabled. The event is not built otherwise.
+    #[inline]
+    pub(crate) fn record(&self, event: impl FnOnce() -> TraceEvent) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let tsc = unsafe { dpdk::rte_rdtsc() };
+        let record = TraceRecord {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            tsc,
+            ts: timebase::tsc_to_ns(tsc),
+            event: event(),
+        };
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        let mut records = self.records.lock().unwrap();
+        while records.len() >= capacity.max(1) {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+}
+
+/// Registry of the trace ring buffers of all cores.
+#[derive(Debug)]
+pub(crate) struct Tracer {
+    nb_records: AtomicUsize,
+    rings: RwLock<BTreeMap<u32, Arc<TraceRing>>>,
+}
+
+impl Tracer {
+    pub(crate) fn new() -> Self {
+        Tracer {
+            nb_records: AtomicUsize::new(TraceConfig::default().nb_records),
+            rings: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Applies trace options from the runtime configuration.
+    pub(crate) fn configure(&self, config: &TraceConfig) {
+        self.nb_records.store(config.nb_records, Ordering::Relaxed);
+        for ring in self.rings.read().unwrap().values() {
+            ring.capacity.store(config.nb_records, Ordering::Relaxed);
+        }
+        for core in config.cores.iter() {
+            self.set_enabled(*core, true);
+        }
+    }
+
+    /// Returns the trace ring buffer of `core`, creating it if needed.
+    pub(crate) fn ring(&self, core: u32) -> Arc<TraceRing> {
+        if let Some(ring) = self.rings.read().unwrap().get(&core) {
+            return Arc::clone(ring);
+        }
+        let mut rings = self.rings.write().unwrap();
+        let ring = rings
+            .entry(core)
+            .or_insert_with(|| Arc::new(TraceRing::new(self.nb_records.load(Ordering::Relaxed))));
+        Arc::clone(ring)
+    }
+
+    pub(crate) fn set_enabled(&self, core: u32, enabled: bool) {
+        self.ring(core).enabled.store(enabled, Ordering::Relaxed);
+        log::info!(
+            "Tracing {} on core {}",
+            if enabled { "enabled" } else { "disabled" },
+            core
+        );
+    }
+
+    pub(crate) fn is_enabled(&self, core: u32) -> bool {
+        match self.rings.read().unwrap().get(&core) {
+            Some(ring) => ring.enabled.load(Ordering::Relaxed),
+            None => false,
+        }
+    }
+
+    /// Returns a copy of the records of `core`, oldest first.
+    pub(crate) fn dump(&self, core: u32) -> Vec<TraceRecord> {
+        match self.rings.read().unwrap().get(&core) {
+            Some(ring) => ring.records.lock().unwrap().iter().cloned().collect(),
+            None => vec![],
+        }
+    }
+}
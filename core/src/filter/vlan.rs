@@ -0,0 +1,216 @@
+//! VLAN traffic policies.
+//!
+//! The `[[vlan_policy]]` entries of the runtime configuration (see
+//! [VlanPolicyConfig](crate::config::VlanPolicyConfig)) decide, by VLAN presence and ID, whether
+//! the flows of a packet are tracked in the flow table, scanned against the rules, and stored by
+//! the [live packet tap](crate::filter::tap) and the [rolling capture](crate::filter::capture).
+//! Packets are keyed by their innermost VLAN ID (C-tag), the same ID as the VLAN of their flow.
+//! The first entry that selects a packet applies, and packets selected by no entry are fully
+//! inspected.
+//!
+//! The policy is checked right after the Ethernet header of a packet is parsed. Packets that are
+//! neither tracked, scanned nor stored are dropped before any further work, i.e. before sFlow
+//! sampling, packet consumers, pipeline stages and the callback, and counted as
+//! [DropReason::VlanPolicy](crate::filter::drops::DropReason::VlanPolicy). Payloads of flows
+//! that are not scanned are counted against the same reason.
+//!
+//! ## Example
+//! Ignore untagged management traffic, and fully inspect tagged traffic:
+//! ```toml
+//! [[vlan_policy]]
+//!     vlans = "untagged"
+//!     track = false
+//!     scan = false
+//!     store = false
+//! ```
+
+use crate::config::VlanPolicyConfig;
+use crate::memory::mbuf::Mbuf;
+use crate::protocols::packet::ethernet::Ethernet;
+use crate::protocols::packet::Packet;
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+/// Largest VLAN ID.
+const MAX_VLAN_ID: u16 = 4095;
+
+/// What is done with the flows of selected packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VlanActions {
+    /// Whether flows are tracked in the flow table.
+    pub track: bool,
+    /// Whether payloads are scanned against the rules.
+    pub scan: bool,
+    /// Whether packets are copied to the live packet tap and the rolling capture.
+    pub store: bool,
+}
+
+impl VlanActions {
+    /// Actions of packets selected by no policy entry.
+    pub const INSPECT: VlanActions = VlanActions {
+        track: true,
+        scan: true,
+        store: true,
+    };
+
+    /// Returns whether packets are dropped on reception.
+    #[inline]
+    pub fn is_ignored(&self) -> bool {
+        !self.track && !self.scan && !self.store
+    }
+}
+
+impl Default for VlanActions {
+    fn default() -> Self {
+        VlanActions::INSPECT
+    }
+}
+
+/// Packets selected by a policy entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VlanSelector {
+    /// Packets without VLAN tags.
+    Untagged,
+    /// Packets with any VLAN tag.
+    Tagged,
+    /// Packets with an innermost VLAN ID in the inclusive range.
+    Range(u16, u16),
+}
+
+impl VlanSelector {
+    /// Parses `untagged`, `tagged`, a VLAN ID such as `100`, or an inclusive range of VLAN IDs
+    /// such as `100-199`.
+    pub fn parse(selector: &str) -> Result<Self> {
+        let parse_id = |id: &str| -> Result<u16> {
+            let id: u16 = id
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid VLAN ID `{}`", id))?;
+            if id > MAX_VLAN_ID {
+                bail!("VLAN ID {} is above {}", id, MAX_VLAN_ID);
+            }
+            Ok(id)
+        };
+        match selector.trim() {
+            "untagged" => Ok(VlanSelector::Untagged),
+            "tagged" => Ok(VlanSelector::Tagged),
+            range => {
+                let (low, high) = match range.split_once('-') {
+                    Some((low, high)) => (parse_id(low)?, parse_id(high)?),
+                    None => (parse_id(range)?, parse_id(range)?),
+                };
+                if low > high {
+                    bail!("Empty VLAN ID range `{}`", range);
+                }
+                Ok(VlanSelector::Range(low, high))
+            }
+        }
+    }
+
+    /// Returns whether the selector selects packets with innermost VLAN ID `vlan_id`.
+    #[inline]
+    pub fn selects(&self, vlan_id: Option<u16>) -> bool {
+        match (self, vlan_id) {
+            (VlanSelector::Untagged, vlan_id) => vlan_id.is_none(),
+            (VlanSelector::Tagged, vlan_id) => vlan_id.is_some(),
+            (VlanSelector::Range(low, high), Some(id)) => (*low..=*high).contains(&id),
+            (VlanSelector::Range(..), None) => false,
+        }
+    }
+}
+
+impl fmt::Display for VlanSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VlanSelector::Untagged => write!(f, "untagged"),
+            VlanSelector::Tagged => write!(f, "tagged"),
+            VlanSelector::Range(low, high) if low == high => write!(f, "{}", low),
+            VlanSelector::Range(low, high) => write!(f, "{}-{}", low, high),
+        }
+    }
+}
+
+/// VLAN policy shared by all copies of a filter, inspecting all packets until configured.
+#[derive(Debug, Default)]
+pub(crate) struct VlanPolicy {
+    entries: RwLock<Vec<(VlanSelector, VlanActions)>>,
+    /// Whether any entry is set, checked before taking the lock.
+    enabled: AtomicBool,
+}
+
+impl VlanPolicy {
+    pub(crate) fn new() -> Self {
+        VlanPolicy::default()
+    }
+
+    /// Replaces the policy with the entries of the runtime configuration.
+    pub(crate) fn configure(&self, config: &[VlanPolicyConfig]) -> Result<()> {
+        let entries = config
+            .iter()
+            .map(|entry| {
+                let actions = VlanActions {
+                    track: entry.track,
+                    scan: entry.scan,
+                    store: entry.store,
+                };
+                Ok((VlanSelector::parse(&entry.vlans)?, actions))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (selector, actions) in entries.iter() {
+            log::info!(
+                "VLAN policy for {}: track {}, scan {}, store {}",
+                selector,
+                actions.track,
+                actions.scan,
+                actions.store
+            );
+        }
+        self.enabled.store(!entries.is_empty(), Ordering::Relaxed);
+        *self.entries.write().unwrap() = entries;
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the actions of packets with innermost VLAN ID `vlan_id`.
+    #[inline]
+    pub(crate) fn actions(&self, vlan_id: Option<u16>) -> VlanActions {
+        if !self.is_enabled() {
+            return VlanActions::INSPECT;
+        }
+        self.entries
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(selector, _)| selector.selects(vlan_id))
+            .map_or(VlanActions::INSPECT, |(_, actions)| *actions)
+    }
+
+    /// Returns the actions of `mbuf`, from its Ethernet header. Packets that are not Ethernet are
+    /// inspected, and dropped later by the parser.
+    #[inline]
+    pub(crate) fn mbuf_actions(&self, mbuf: &Mbuf) -> VlanActions {
+        if !self.is_enabled() {
+            return VlanActions::INSPECT;
+        }
+        self.eth_actions(mbuf.parse_to::<Ethernet>().ok().as_ref())
+    }
+
+    /// Like [mbuf_actions](Self::mbuf_actions), from an already parsed Ethernet header, `None`
+    /// for packets that are not Ethernet.
+    #[inline]
+    pub(crate) fn eth_actions(&self, eth: Option<&Ethernet>) -> VlanActions {
+        match eth {
+            Some(eth) => self.actions(eth.get_last_vlan_id()),
+            None => VlanActions::INSPECT,
+        }
+    }
+}
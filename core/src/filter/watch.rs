@@ -0,0 +1,242 @@
+//! Rules directory.
+//!
+//! Rules can be provisioned by dropping files in a directory instead of calling
+//! [FilterCtx::load_rules](crate::filter::FilterCtx::load_rules). Every `.json` file of the
+//! directory holds a list of [rules](crate::filter::rule):
+//! ```json
+//! [
+//!     { "pattern": "evil\\.example\\.com", "ttl_seconds": 86400 },
+//!     { "pattern": "(?i)user-agent: sqlmap", "app": "http" }
+//! ]
+//! ```
+//! The rules of all files are composed, in file name order, into the rule set of the filter,
+//! replacing any rules loaded by other means. A file that cannot be read, parsed or compiled is
+//! reported in [RuleFileStats](RuleFileStats) and its previously loaded rules, if any, are kept, so
+//! that a bad edit does not silently drop rules.
+//!
+//! The directory is loaded once on initialization. With the `rule-watch` feature, it is watched
+//! with inotify in a background thread and reloaded whenever a rule file is written, moved or
+//! removed.
+
+use crate::config::RuleWatchConfig;
+use crate::filter::rule::Rule;
+use crate::filter::FilterCtx;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::Result;
+
+/// Load status of a rule file.
+#[derive(Debug, Clone)]
+pub struct RuleFileStats {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Number of active rules from the file.
+    pub nb_rules: usize,
+    /// Time the active rules were read from the file.
+    pub loaded_at: Option<SystemTime>,
+    /// Error of the last attempt to load the file, if it failed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+struct RuleFile {
+    rules: Vec<Rule>,
+    stats: RuleFileStats,
+}
+
+/// Rules of the files of a rules directory.
+#[derive(Debug, Default)]
+pub(crate) struct RuleFiles {
+    files: Mutex<BTreeMap<PathBuf, RuleFile>>,
+}
+
+impl RuleFiles {
+    pub(crate) fn new() -> Self {
+        RuleFiles::default()
+    }
+
+    /// Rereads the rule files of `directory` and returns the composed rules.
+    fn reload(&self, directory: &Path) -> Result<Vec<Rule>> {
+        let mut paths = vec![];
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "json") && path.is_file() {
+                paths.push(path);
+            }
+        }
+        let mut files = self.files.lock().unwrap();
+        files.retain(|path, _| paths.contains(path));
+        for path in paths {
+            match read_rules(&path) {
+                Ok(rules) => {
+                    let stats = RuleFileStats {
+                        path: path.clone(),
+                        nb_rules: rules.len(),
+                        loaded_at: Some(SystemTime::now()),
+                        error: None,
+                    };
+                    files.insert(path, RuleFile { rules, stats });
+                }
+                Err(error) => {
+                    log::error!("Rule file {:?} not loaded: {}", path, error);
+                    let file = files.entry(path.clone()).or_insert_with(|| RuleFile {
+                        rules: vec![],
+                        stats: RuleFileStats {
+                            path,
+                            nb_rules: 0,
+                            loaded_at: None,
+                            error: None,
+                        },
+                    });
+                    file.stats.error = Some(error.to_string());
+                }
+            }
+        }
+        Ok(files
+            .values()
+            .flat_map(|file| file.rules.iter().cloned())
+            .collect())
+    }
+
+    pub(crate) fn stats(&self) -> Vec<RuleFileStats> {
+        self.files
+            .lock()
+            .unwrap()
+            .values()
+            .map(|file| file.stats.clone())
+            .collect()
+    }
+}
+
+/// Reads and validates the rules of `path`.
+pub(crate) fn read_rules(path: &Path) -> Result<Vec<Rule>> {
+    let rules: Vec<Rule> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    for rule in rules.iter() {
+        rule.regex()?;
+    }
+    Ok(rules)
+}
+
+/// Loads the rule files of `directory` into `filter_ctx`.
+fn load(filter_ctx: &FilterCtx, directory: &Path) -> Result<()> {
+    let rules = filter_ctx.rule_files.reload(directory)?;
+    filter_ctx.load_rules(rules)
+}
+
+/// Loads the rules directory of `config`, and starts watching it if the `rule-watch` feature is
+/// enabled.
+pub(crate) fn start(filter_ctx: &FilterCtx, config: &RuleWatchConfig) -> Result<()> {
+    let directory = PathBuf::from(&config.directory);
+    load(filter_ctx, &directory)?;
+    #[cfg(feature = "rule-watch")]
+    inotify::spawn(filter_ctx.clone(), directory, config.debounce)?;
+    #[cfg(not(feature = "rule-watch"))]
+    log::warn!(
+        "Loaded rules from {}, changes are not watched without the `rule-watch` feature",
+        config.directory
+    );
+    Ok(())
+}
+
+#[cfg(feature = "rule-watch")]
+mod inotify {
+    use super::load;
+    use crate::filter::FilterCtx;
+
+    use std::ffi::CString;
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use std::ptr;
+    use std::thread;
+
+    use anyhow::{bail, Result};
+
+    const EVENT_MASK: u32 =
+        libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_DELETE;
+
+    /// Watches `directory` in a background thread.
+    pub(super) fn spawn(filter_ctx: FilterCtx, directory: PathBuf, debounce: u64) -> Result<()> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            bail!("inotify_init1: {}", io::Error::last_os_error());
+        }
+        let cpath = CString::new(directory.as_os_str().as_bytes())?;
+        if unsafe { libc::inotify_add_watch(fd, cpath.as_ptr(), EVENT_MASK) } < 0 {
+            let error = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            bail!("Failed to watch {:?}: {}", directory, error);
+        }
+        thread::Builder::new()
+            .name("retina-rule-watch".into())
+            .spawn(move || {
+                log::info!("Watching rules in {:?}", directory);
+                loop {
+                    match wait_change(fd, debounce as i32) {
+                        Ok(true) => (),
+                        Ok(false) => continue,
+                        Err(error) => {
+                            log::error!("Rule watch on {:?} stopped: {}", directory, error);
+                            break;
+                        }
+                    }
+                    match load(&filter_ctx, &directory) {
+                        Ok(_) => log::info!("Reloaded rules from {:?}", directory),
+                        Err(error) => log::error!("Rule reload error: {}", error),
+                    }
+                }
+                unsafe { libc::close(fd) };
+            })?;
+        Ok(())
+    }
+
+    /// Blocks until a rule file changes, then waits until no event is received for `debounce`
+    /// milliseconds. Returns whether any event concerned a rule file.
+    fn wait_change(fd: i32, debounce: i32) -> io::Result<bool> {
+        let mut changed = read_events(fd)?;
+        loop {
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ret = unsafe { libc::poll(&mut pollfd, 1, debounce) };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if ret == 0 {
+                return Ok(changed);
+            }
+            changed |= read_events(fd)?;
+        }
+    }
+
+    /// Reads pending events. Returns whether any event concerned a rule file.
+    fn read_events(fd: i32) -> io::Result<bool> {
+        let mut buf = [0_u8; 4096];
+        let len = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut changed = false;
+        let mut offset = 0;
+        while offset + mem::size_of::<libc::inotify_event>() <= len as usize {
+            let event: libc::inotify_event =
+                unsafe { ptr::read_unaligned(buf.as_ptr().add(offset) as *const _) };
+            let name_start = offset + mem::size_of::<libc::inotify_event>();
+            let name = &buf[name_start..name_start + event.len as usize];
+            let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+            if event.mask & libc::IN_Q_OVERFLOW != 0 || name.ends_with(b".json") {
+                changed = true;
+            }
+            offset = name_start + event.len as usize;
+        }
+        Ok(changed)
+    }
+}
@@ -0,0 +1,268 @@
+//! YARA payload scanning.
+//!
+//! With the `[yara]` options of the runtime configuration (see
+//! [YaraConfig](crate::config::YaraConfig)) and the `yara-match` feature, the payload windows that
+//! [FilterCtx::check_flow_match](crate::filter::FilterCtx::check_flow_match) scans with the regex
+//! rules are also scanned with a set of YARA rules. A window matching a YARA rule matches like a
+//! regex rule: its flow is marked matched, and alerts and end-of-flow summaries report the rule as
+//! `yara:<rule name>`. YARA rules are not subject to the match rate safeguard.
+//!
+//! Each scan is bounded: windows are cut at `max_window` bytes, and scans running longer than
+//! `timeout_ms` are aborted without matching. Truncated windows, timeouts and errors are counted.
+//!
+//! The rules are scanned by an external library, `libretina_yara`, a thin wrapper around
+//! `libyara` found in `RETINA_YARA_PATH` at build time, with the following C interface:
+//! ```c
+//! // Loads YARA rules from `path`, source or compiled with `yarac`. Returns NULL on failure.
+//! void *retina_yara_load(const char *path);
+//! // Returns the number of rules, and the name of rule `idx`.
+//! size_t retina_yara_nb_rules(void *rules);
+//! const char *retina_yara_rule_name(void *rules, size_t idx);
+//! // Sets `matched[i]` to 1 for each rule `i` matching the `len` bytes of `data`, scanning for at
+//! // most `timeout_ms` milliseconds. Returns 0 on success, 1 on timeout, < 0 on error.
+//! int retina_yara_scan(void *rules, const uint8_t *data, size_t len, int timeout_ms,
+//!                      uint8_t *matched);
+//! void retina_yara_free(void *rules);
+//! ```
+//! Scans may run concurrently from several cores.
+
+use super::rule::Rule;
+use crate::config::YaraConfig;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+
+/// Counters of YARA scanning.
+#[derive(Debug, Clone, Copy)]
+pub struct YaraStats {
+    /// Number of loaded YARA rules.
+    pub nb_rules: usize,
+    /// Number of scanned windows.
+    pub nb_scans: u64,
+    /// Number of windows that matched a YARA rule.
+    pub nb_matches: u64,
+    /// Number of windows cut at the maximum window size.
+    pub nb_truncated: u64,
+    /// Number of scans aborted by the timeout.
+    pub nb_timeouts: u64,
+    /// Number of failed scans.
+    pub nb_errors: u64,
+}
+
+/// Outcome of a scan.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "yara-match"), allow(dead_code))]
+enum ScanOutcome {
+    /// Indexes of the matching rules.
+    Matched(Vec<usize>),
+    Timeout,
+    Error(i32),
+}
+
+/// Loaded YARA rules.
+trait CompiledRules: fmt::Debug + Send + Sync {
+    /// Returns the name of each rule, by index.
+    fn names(&self) -> &[String];
+
+    /// Scans `window` for at most `timeout_ms` milliseconds.
+    fn scan(&self, window: &[u8], timeout_ms: u32) -> ScanOutcome;
+}
+
+#[derive(Debug)]
+struct Scanner {
+    rules: Arc<dyn CompiledRules>,
+    max_window: usize,
+    timeout_ms: u32,
+}
+
+/// Shared YARA scanner of a filter.
+#[derive(Debug, Default)]
+pub(crate) struct Yara {
+    scanner: RwLock<Option<Scanner>>,
+    nb_scans: AtomicU64,
+    nb_matches: AtomicU64,
+    nb_truncated: AtomicU64,
+    nb_timeouts: AtomicU64,
+    nb_errors: AtomicU64,
+}
+
+impl Yara {
+    pub(crate) fn new() -> Self {
+        Yara::default()
+    }
+
+    /// Loads the rules of `config`.
+    pub(crate) fn configure(&self, config: &YaraConfig) -> Result<()> {
+        if !cfg!(feature = "yara-match") {
+            log::warn!("YARA rules require the `yara-match` feature, payloads are not scanned");
+            return Ok(());
+        }
+        let rules = ffi::load(&config.rules)?;
+        log::info!("Loaded {} YARA rules from {}", rules.names().len(), config.rules);
+        *self.scanner.write().unwrap() = Some(Scanner {
+            rules,
+            max_window: config.max_window,
+            timeout_ms: config.timeout_ms,
+        });
+        Ok(())
+    }
+
+    /// Returns the YARA rules matching `window`, as `yara:<rule name>` rules. Empty if no YARA
+    /// rules are loaded.
+    pub(crate) fn scan(&self, window: &[u8]) -> Vec<Rule> {
+        let scanner = self.scanner.read().unwrap();
+        let scanner = match scanner.as_ref() {
+            Some(scanner) => scanner,
+            None => return vec![],
+        };
+        self.nb_scans.fetch_add(1, Ordering::Relaxed);
+        let window = if window.len() > scanner.max_window {
+            self.nb_truncated.fetch_add(1, Ordering::Relaxed);
+            &window[..scanner.max_window]
+        } else {
+            window
+        };
+        match scanner.rules.scan(window, scanner.timeout_ms) {
+            ScanOutcome::Matched(matched) if matched.is_empty() => vec![],
+            ScanOutcome::Matched(matched) => {
+                self.nb_matches.fetch_add(1, Ordering::Relaxed);
+                let names = scanner.rules.names();
+                matched
+                    .into_iter()
+                    .filter_map(|idx| names.get(idx))
+                    .map(|name| Rule::new(format!("yara:{}", name)))
+                    .collect()
+            }
+            ScanOutcome::Timeout => {
+                self.nb_timeouts.fetch_add(1, Ordering::Relaxed);
+                vec![]
+            }
+            ScanOutcome::Error(error) => {
+                self.nb_errors.fetch_add(1, Ordering::Relaxed);
+                log::debug!("YARA scan error {}", error);
+                vec![]
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Option<YaraStats> {
+        let scanner = self.scanner.read().unwrap();
+        Some(YaraStats {
+            nb_rules: scanner.as_ref()?.rules.names().len(),
+            nb_scans: self.nb_scans.load(Ordering::Relaxed),
+            nb_matches: self.nb_matches.load(Ordering::Relaxed),
+            nb_truncated: self.nb_truncated.load(Ordering::Relaxed),
+            nb_timeouts: self.nb_timeouts.load(Ordering::Relaxed),
+            nb_errors: self.nb_errors.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(feature = "yara-match")]
+mod ffi {
+    use super::{CompiledRules, ScanOutcome};
+
+    use std::ffi::{c_char, c_int, c_void, CStr, CString};
+    use std::sync::Arc;
+
+    use anyhow::{bail, Result};
+
+    #[link(name = "retina_yara")]
+    extern "C" {
+        fn retina_yara_load(path: *const c_char) -> *mut c_void;
+        fn retina_yara_nb_rules(rules: *mut c_void) -> usize;
+        fn retina_yara_rule_name(rules: *mut c_void, idx: usize) -> *const c_char;
+        fn retina_yara_scan(
+            rules: *mut c_void,
+            data: *const u8,
+            len: usize,
+            timeout_ms: c_int,
+            matched: *mut u8,
+        ) -> c_int;
+        fn retina_yara_free(rules: *mut c_void);
+    }
+
+    #[derive(Debug)]
+    struct YaraRules {
+        rules: *mut c_void,
+        names: Vec<String>,
+    }
+
+    // The scanning library must support concurrent scans.
+    unsafe impl Send for YaraRules {}
+    unsafe impl Sync for YaraRules {}
+
+    impl CompiledRules for YaraRules {
+        fn names(&self) -> &[String] {
+            &self.names
+        }
+
+        fn scan(&self, window: &[u8], timeout_ms: u32) -> ScanOutcome {
+            let mut matched = vec![0u8; self.names.len()];
+            let timeout_ms = timeout_ms.min(c_int::MAX as u32) as c_int;
+            let ret = unsafe {
+                retina_yara_scan(
+                    self.rules,
+                    window.as_ptr(),
+                    window.len(),
+                    timeout_ms,
+                    matched.as_mut_ptr(),
+                )
+            };
+            match ret {
+                0 => ScanOutcome::Matched(
+                    matched
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, matched)| **matched != 0)
+                        .map(|(idx, _)| idx)
+                        .collect(),
+                ),
+                1 => ScanOutcome::Timeout,
+                error => ScanOutcome::Error(error),
+            }
+        }
+    }
+
+    impl Drop for YaraRules {
+        fn drop(&mut self) {
+            unsafe { retina_yara_free(self.rules) };
+        }
+    }
+
+    /// Loads the YARA rules in `path`.
+    pub(super) fn load(path: &str) -> Result<Arc<dyn CompiledRules>> {
+        let cpath = CString::new(path)?;
+        let rules = unsafe { retina_yara_load(cpath.as_ptr()) };
+        if rules.is_null() {
+            bail!("Failed to load YARA rules from {}", path);
+        }
+        let nb_rules = unsafe { retina_yara_nb_rules(rules) };
+        let names = (0..nb_rules)
+            .map(|idx| {
+                let name = unsafe { retina_yara_rule_name(rules, idx) };
+                if name.is_null() {
+                    return format!("rule{}", idx);
+                }
+                unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned()
+            })
+            .collect();
+        Ok(Arc::new(YaraRules { rules, names }))
+    }
+}
+
+#[cfg(not(feature = "yara-match"))]
+mod ffi {
+    use super::CompiledRules;
+
+    use std::sync::Arc;
+
+    use anyhow::{bail, Result};
+
+    pub(super) fn load(_path: &str) -> Result<Arc<dyn CompiledRules>> {
+        bail!("YARA rules require the `yara-match` feature")
+    }
+}
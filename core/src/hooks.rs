@@ -0,0 +1,277 @@
+//! Runtime event hooks.
+//!
+//! Hooks are user-defined closures invoked on runtime lifecycle events, in addition to the
+//! per-packet subscription callback. They are registered on the [Hooks](Hooks) registry of a
+//! [FilterCtx](crate::filter::FilterCtx), which is shared by all packet processing cores.
+//!
+//! ## Remarks
+//! Flow creation hooks run on the packet processing core that inserted the flow, and parse error
+//! hooks on the core that received the frame, so they should be kept cheap. All other hooks run
+//! outside of the packet processing loop.
+//!
+//! Capture file hooks receive the [CaptureFileEntry](CaptureFileEntry) of each file closed by the
+//! [rolling capture](crate::filter::capture), on its finalization thread (see
+//! [finalize](crate::filter::finalize)). They may take time, but delay the finalization of the
+//! next files.
+//!
+//! End-of-flow hooks receive a [FlowSummary](FlowSummary) of each flow pruned from the flow table.
+//! Packet and byte counts cover the packets tracked with
+//! [FilterCtx::track_flow](crate::filter::FilterCtx::track_flow), as the built-in subscriptions
+//! do, and matched rules are only collected while an end-of-flow hook is registered. Matched flows
+//! also carry their throughput metrics if the `[flow_rates]` options are set, flows only one
+//! endpoint sent packets on are flagged as unidirectional, and flows with packets in the
+//! [rolling capture](crate::filter::capture) list the files they were written to.
+//!
+//! Parse error hooks receive the [ParseError](ParseError) and the frame of every packet rejected
+//! by [FilterCtx::parse_l4](crate::filter::FilterCtx::parse_l4), to analyze unparseable traffic.
+//!
+//! Storage error hooks receive a [StorageError](StorageError) for every failure of the
+//! [rolling capture](crate::filter::capture), [tap](crate::filter::tap) and
+//! [collector](crate::filter::forward) writers to open, write or remove a file or to reach a
+//! collector, on the thread of the failed writer. Writers keep going after an error: the capture
+//! opens a new file for the next packet, the tap retries, and collectors reconnect.
+//!
+//! ## Example
+//! ```
+//! let filter_ctx = FilterCtx::new(100_000, Duration::from_secs(60), regexes);
+//! filter_ctx.hooks().on_flow_expire(|flow| println!("Expired: {}", flow));
+//! let mut runtime = Runtime::new(config, callback, &filter_ctx).unwrap();
+//! runtime.run();
+//! ```
+
+use crate::filter::finalize::CaptureFileEntry;
+use crate::filter::rates::FlowRates;
+use crate::protocols::layer4::Flow;
+use crate::protocols::parser::ParseError;
+use crate::subscription::ZcFrame;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use regex::bytes::RegexSet;
+use serde::{Deserialize, Serialize};
+
+type Hook<T> = Box<dyn Fn(&T) + Send + Sync>;
+type ParseErrorHook = Box<dyn Fn(&ParseError, &ZcFrame) + Send + Sync>;
+
+/// Packet storage writer that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageTarget {
+    /// The [rolling capture](crate::filter::capture).
+    Capture,
+    /// The [tap](crate::filter::tap).
+    Tap,
+    /// A [collector](crate::filter::forward).
+    Forward,
+}
+
+/// Failure of a packet storage writer.
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageError {
+    /// Writer that failed.
+    pub target: StorageTarget,
+    /// File the writer failed on, or name of the collector.
+    pub location: String,
+    /// Description of the error.
+    pub error: String,
+}
+
+impl StorageError {
+    pub(crate) fn new(
+        target: StorageTarget,
+        location: impl fmt::Display,
+        error: impl fmt::Display,
+    ) -> Self {
+        StorageError {
+            target,
+            location: location.to_string(),
+            error: format!("{:#}", error),
+        }
+    }
+}
+
+/// Traffic sent by one endpoint of a flow.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct FlowDirection {
+    /// Number of packets.
+    pub nb_pkts: u64,
+    /// Number of bytes, including headers.
+    pub nb_bytes: u64,
+}
+
+/// Summary of a flow that ended, i.e. was pruned from the flow table.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowSummary {
+    /// Flow key.
+    pub flow: Flow,
+    /// Time between the first and the last packet of the flow.
+    pub duration: Duration,
+    /// Wall-clock time of the first and the last packet of the flow, in nanoseconds since the UNIX
+    /// epoch (see [timebase](crate::timebase)).
+    pub first_ts: u64,
+    pub last_ts: u64,
+    /// Traffic sent by each endpoint, in the order of [Flow::addrs](Flow::addrs).
+    pub directions: [FlowDirection; 2],
+    /// Whether only one endpoint sent packets, the flow having enough recorded packets to tell
+    /// (see [directions](crate::filter::directions)).
+    pub unidirectional: bool,
+    /// Patterns of the rules that matched payloads of the flow, in order of first match.
+    pub matched_rules: Vec<String>,
+    /// Tags of the rules of `matched_rules`, the first matched rule winning on duplicate keys (see
+    /// [tags](crate::filter::rule#tags)).
+    pub matched_tags: BTreeMap<String, String>,
+    /// Labels attached to the flow with
+    /// [FilterCtx::set_flow_label](crate::filter::FilterCtx::set_flow_label).
+    pub labels: BTreeMap<String, String>,
+    /// Throughput metrics since the first match, `None` if the flow did not match or rates are
+    /// not tracked (see [rates](crate::filter::rates)).
+    pub rates: Option<FlowRates>,
+    /// Files of the [rolling capture](crate::filter::capture) the captured packets of the flow
+    /// were queued to, oldest first, empty if none was captured. Packets queued while the capture
+    /// rolls over are written to the next file, which is listed too, and files removed since are
+    /// not listed.
+    pub capture_files: Vec<PathBuf>,
+}
+
+/// Registry of runtime event hooks.
+#[derive(Default)]
+pub struct Hooks {
+    start: RwLock<Vec<Hook<()>>>,
+    stop: RwLock<Vec<Hook<()>>>,
+    flow_new: RwLock<Vec<Hook<Flow>>>,
+    flow_expire: RwLock<Vec<Hook<Flow>>>,
+    flow_end: RwLock<Vec<Hook<FlowSummary>>>,
+    parse_error: RwLock<Vec<ParseErrorHook>>,
+    rule_update: RwLock<Vec<Hook<[RegexSet]>>>,
+    capture_file: RwLock<Vec<Hook<CaptureFileEntry>>>,
+    storage_error: RwLock<Vec<Hook<StorageError>>>,
+}
+
+impl Hooks {
+    /// Creates an empty hook registry.
+    pub fn new() -> Self {
+        Hooks::default()
+    }
+
+    /// Registers a hook invoked when the runtime starts processing packets.
+    pub fn on_start(&self, hook: impl Fn() + Send + Sync + 'static) {
+        self.start.write().unwrap().push(Box::new(move |_| hook()));
+    }
+
+    /// Registers a hook invoked when the runtime stops processing packets.
+    pub fn on_stop(&self, hook: impl Fn() + Send + Sync + 'static) {
+        self.stop.write().unwrap().push(Box::new(move |_| hook()));
+    }
+
+    /// Registers a hook invoked when a new flow is added to the flow table.
+    pub fn on_flow_new(&self, hook: impl Fn(&Flow) + Send + Sync + 'static) {
+        self.flow_new.write().unwrap().push(Box::new(hook));
+    }
+
+    /// Registers a hook invoked when a flow is pruned from the flow table.
+    pub fn on_flow_expire(&self, hook: impl Fn(&Flow) + Send + Sync + 'static) {
+        self.flow_expire.write().unwrap().push(Box::new(hook));
+    }
+
+    /// Registers a hook invoked with the summary of every flow pruned from the flow table.
+    pub fn on_flow_end(&self, hook: impl Fn(&FlowSummary) + Send + Sync + 'static) {
+        self.flow_end.write().unwrap().push(Box::new(hook));
+    }
+
+    /// Registers a hook invoked with the error and the frame of every packet that could not be
+    /// parsed.
+    pub fn on_parse_error(&self, hook: impl Fn(&ParseError, &ZcFrame) + Send + Sync + 'static) {
+        self.parse_error.write().unwrap().push(Box::new(hook));
+    }
+
+    /// Registers a hook invoked with the new compiled regex sets when the filter rules are updated.
+    /// Rules are compiled into several regex sets, by shard and payload window.
+    pub fn on_rule_update(&self, hook: impl Fn(&[RegexSet]) + Send + Sync + 'static) {
+        self.rule_update.write().unwrap().push(Box::new(hook));
+    }
+
+    /// Registers a hook invoked with the manifest entry of every file closed by the rolling
+    /// capture.
+    pub fn on_capture_file(&self, hook: impl Fn(&CaptureFileEntry) + Send + Sync + 'static) {
+        self.capture_file.write().unwrap().push(Box::new(hook));
+    }
+
+    /// Registers a hook invoked on every error of the capture, tap and collector writers.
+    pub fn on_storage_error(&self, hook: impl Fn(&StorageError) + Send + Sync + 'static) {
+        self.storage_error.write().unwrap().push(Box::new(hook));
+    }
+
+    pub(crate) fn start(&self) {
+        Self::invoke(&self.start, &());
+    }
+
+    pub(crate) fn stop(&self) {
+        Self::invoke(&self.stop, &());
+    }
+
+    pub(crate) fn flow_new(&self, flow: &Flow) {
+        Self::invoke(&self.flow_new, flow);
+    }
+
+    pub(crate) fn flow_expire(&self, flow: &Flow) {
+        Self::invoke(&self.flow_expire, flow);
+    }
+
+    pub(crate) fn flow_end(&self, summary: &FlowSummary) {
+        Self::invoke(&self.flow_end, summary);
+    }
+
+    /// Returns whether any end-of-flow hook is registered.
+    pub(crate) fn has_flow_end(&self) -> bool {
+        !self.flow_end.read().unwrap().is_empty()
+    }
+
+    pub(crate) fn parse_error(&self, error: &ParseError, mbuf: &ZcFrame) {
+        for hook in self.parse_error.read().unwrap().iter() {
+            hook(error, mbuf);
+        }
+    }
+
+    pub(crate) fn rule_update(&self, regexes: &[RegexSet]) {
+        Self::invoke(&self.rule_update, regexes);
+    }
+
+    pub(crate) fn capture_file(&self, entry: &CaptureFileEntry) {
+        Self::invoke(&self.capture_file, entry);
+    }
+
+    /// Returns whether any capture file hook is registered.
+    pub(crate) fn has_capture_file(&self) -> bool {
+        !self.capture_file.read().unwrap().is_empty()
+    }
+
+    pub(crate) fn storage_error(&self, error: &StorageError) {
+        Self::invoke(&self.storage_error, error);
+    }
+
+    fn invoke<T: ?Sized>(hooks: &RwLock<Vec<Hook<T>>>, arg: &T) {
+        for hook in hooks.read().unwrap().iter() {
+            hook(arg);
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("start", &self.start.read().unwrap().len())
+            .field("stop", &self.stop.read().unwrap().len())
+            .field("flow_new", &self.flow_new.read().unwrap().len())
+            .field("flow_expire", &self.flow_expire.read().unwrap().len())
+            .field("flow_end", &self.flow_end.read().unwrap().len())
+            .field("parse_error", &self.parse_error.read().unwrap().len())
+            .field("rule_update", &self.rule_update.read().unwrap().len())
+            .field("capture_file", &self.capture_file.read().unwrap().len())
+            .field("storage_error", &self.storage_error.read().unwrap().len())
+            .finish()
+    }
+}
@@ -0,0 +1,135 @@
+//! Software RSS.
+//!
+//! With the `[online.software_rss]` options (see
+//! [SoftwareRssConfig](crate::config::SoftwareRssConfig)), the RX cores polling receive queues
+//! become I/O cores, which hand each packet off to a worker core instead of processing it. The
+//! worker of a packet is chosen by the hash of its flow, so that both directions of a flow are
+//! always processed by the same worker, and packets that cannot be parsed are spread by their RSS
+//! hash. Packets are handed off over single-producer, single-consumer rings, one per receive queue
+//! and worker, so that the order of the packets of a queue is kept and workers know the queue each
+//! packet was received on. Packets that do not fit in their ring are counted as dropped with
+//! [DropReason::HandoffFull](crate::filter::drops::DropReason::HandoffFull) on the I/O core.
+//!
+//! Workers run the same processing loop as RX cores, polling their rings instead of receive
+//! queues. The software RX queue counters only count what the I/O cores poll from the NIC.
+
+use super::ring::Ring;
+use super::CoreId;
+use crate::config::SoftwareRssConfig;
+use crate::dpdk;
+use crate::filter::FilterCtx;
+use crate::memory::mbuf::Mbuf;
+use crate::port::RxQueue;
+use crate::protocols::layer4::L4Context;
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+
+/// Handoff rings from the receive queues to the workers.
+#[derive(Debug)]
+pub(crate) struct Handoff {
+    workers: Vec<CoreId>,
+    /// Ring of each worker, by receive queue.
+    rings: BTreeMap<RxQueue, Vec<Ring>>,
+}
+
+impl Handoff {
+    /// Creates the rings from each receive queue of `rxqueues` to each worker of `config`.
+    /// `io_cores` are the cores polling ports, which cannot be workers.
+    pub(crate) fn new(
+        config: &SoftwareRssConfig,
+        rxqueues: &[RxQueue],
+        io_cores: &[CoreId],
+    ) -> Result<Self> {
+        let mut workers: Vec<CoreId> = config.workers.iter().map(|core| CoreId(*core)).collect();
+        workers.sort();
+        workers.dedup();
+        if workers.is_empty() {
+            bail!("Software RSS needs at least one worker");
+        }
+        if let Some(core) = workers.iter().find(|core| io_cores.contains(core)) {
+            bail!("Software RSS worker {} also polls a port", core);
+        }
+        let flags = dpdk::RING_F_SP_ENQ | dpdk::RING_F_SC_DEQ;
+        let mut rings = BTreeMap::new();
+        for rxqueue in rxqueues.iter() {
+            let queue_rings = workers
+                .iter()
+                .map(|worker| {
+                    let name = format!("handoff_{}_{}_{}", rxqueue.pid, rxqueue.qid, worker);
+                    Ring::with_name(&name, config.ring_size, worker.socket_id(), flags)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            rings.insert(*rxqueue, queue_rings);
+        }
+        log::info!(
+            "Software RSS: {} receive queues handed off to {} workers",
+            rxqueues.len(),
+            workers.len()
+        );
+        Ok(Handoff { workers, rings })
+    }
+
+    /// Returns the worker cores, in worker order.
+    pub(crate) fn workers(&self) -> &[CoreId] {
+        &self.workers
+    }
+
+    /// Returns the receive queues handed off to the workers.
+    pub(crate) fn rxqueues(&self) -> Vec<RxQueue> {
+        self.rings.keys().copied().collect()
+    }
+
+    /// Returns the index of the worker of `mbuf`.
+    #[inline]
+    fn worker_of(&self, mbuf: &Mbuf, filter_ctx: &FilterCtx) -> usize {
+        let hash = match L4Context::new(mbuf) {
+            Ok(ctx) => filter_ctx.flow_hash(&filter_ctx.get_flow(&ctx)),
+            Err(_) => mbuf.rss_hash() as u64,
+        };
+        (hash % self.workers.len() as u64) as usize
+    }
+
+    /// Hands the packets of a burst received on `rxqueue` off to their workers, and returns the
+    /// number of packets that did not fit in their ring.
+    pub(crate) fn dispatch(
+        &self,
+        rxqueue: &RxQueue,
+        mbufs: Vec<Mbuf>,
+        filter_ctx: &FilterCtx,
+    ) -> usize {
+        let rings = match self.rings.get(rxqueue) {
+            Some(rings) => rings,
+            None => return 0,
+        };
+        let mut buckets: Vec<Vec<Mbuf>> = rings.iter().map(|_| vec![]).collect();
+        for mbuf in mbufs.into_iter() {
+            buckets[self.worker_of(&mbuf, filter_ctx)].push(mbuf);
+        }
+        rings
+            .iter()
+            .zip(buckets)
+            .filter(|(_, bucket)| !bucket.is_empty())
+            .map(|(ring, bucket)| ring.sp_enqueue_burst(bucket).len())
+            .sum()
+    }
+
+    /// Dequeues up to `nb_mbufs` packets of `rxqueue` handed off to worker `worker`.
+    #[inline]
+    pub(crate) fn poll(&self, rxqueue: &RxQueue, worker: usize, nb_mbufs: u16) -> Vec<Mbuf> {
+        match self.rings.get(rxqueue) {
+            Some(rings) => rings[worker].sc_dequeue_burst(nb_mbufs),
+            None => vec![],
+        }
+    }
+}
+
+impl Drop for Handoff {
+    fn drop(&mut self) {
+        // Free the packets left in the rings
+        for ring in self.rings.values().flatten() {
+            while !ring.sc_dequeue_burst(64).is_empty() {}
+        }
+    }
+}
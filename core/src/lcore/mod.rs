@@ -1,6 +1,11 @@
+pub(crate) mod handoff;
 pub(crate) mod monitor;
+pub(crate) mod queues;
+pub(crate) mod replay;
 // pub(crate) mod ring;
 pub(crate) mod rx_core;
+pub(crate) mod sflow;
+pub(crate) mod sink;
 
 pub(crate) mod ring;
 
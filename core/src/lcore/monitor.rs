@@ -1,6 +1,33 @@
-use crate::config::RuntimeConfig;
+use crate::bridge::AsyncBridgeStats;
+use crate::config::{
+    CounterConfig, CounterFormat, FlowDirectionConfig, RuntimeConfig, SignalConfig, SinkBehavior,
+};
 use crate::dpdk;
-use crate::port::{statistics::PortStats, Port, PortId, RxQueue, RxQueueType};
+use crate::filter::alert::SubscriberStats;
+use crate::filter::checksum::ChecksumStats;
+use crate::filter::cycles::CoreCycles;
+use crate::filter::directions::DirectionStats;
+use crate::filter::priority::ClassStats;
+use crate::filter::quota::QuotaStats;
+use crate::filter::drops::{DropCounts, DropReason};
+use crate::filter::flags::FlagState;
+use crate::filter::forward::ForwardStats;
+use crate::filter::pause::PauseStats;
+use crate::filter::rule::{CompileStats, RuleCount, RuleGenerations};
+use crate::filter::scan::{ScanMode, ScanStats};
+use crate::filter::shadow::ShadowStats;
+use crate::filter::sources::RuleSourceStats;
+use crate::filter::table::FlowTableStats;
+use crate::filter::talkers::{Talker, TalkerStats};
+use crate::filter::watch::RuleFileStats;
+use crate::filter::{FilterCtx, RuleStats};
+use crate::memory::mempool::mempool_name;
+use crate::output::{Output, OutputKind};
+use crate::port::{bond, statistics::PortStats, Port, PortId, RxQueue, RxQueueType};
+use crate::runtime::notify::{Notifier, Readiness};
+use crate::runtime::signals::{self, StateDump};
+use crate::timebase;
+use super::queues::{QueueRegistry, QueueStats};
 
 use std::collections::{BTreeMap, HashMap};
 use std::ffi::CString;
@@ -16,6 +43,7 @@ use anyhow::{bail, Result};
 use chrono::Local;
 use crossbeam_channel::{tick, Receiver};
 use csv::Writer;
+use itertools::Itertools;
 use tabled::{Panel, col, row, Table};
 use tabled::{builder::Builder, Style};
 use serde::Serialize;
@@ -34,14 +62,31 @@ pub(crate) struct Monitor {
     display: Option<Display>,
     logger: Option<Logger>,
     ports: BTreeMap<PortId, Vec<RxQueue>>,
+    filter_ctx: FilterCtx,
+    rule_ticker: Receiver<Instant>,
+    timebase_ticker: Receiver<Instant>,
+    pressure: Option<Pressure>,
+    asymmetry: Asymmetry,
+    keepalive: Option<Keepalive>,
+    profile: Option<Profile>,
+    counters: Option<CounterExport>,
     is_running: Arc<AtomicBool>,
+    readiness: Readiness,
+    notifier: Notifier,
+    /// Software counters of the RX queues.
+    queues: Arc<QueueRegistry>,
+    benchmark: Option<Benchmark>,
+    signals: Option<SignalConfig>,
 }
 
 impl Monitor {
     pub(crate) fn new(
         config: &RuntimeConfig,
         ports: &BTreeMap<PortId, Port>,
+        filter_ctx: &FilterCtx,
         is_running: Arc<AtomicBool>,
+        readiness: Readiness,
+        queues: Arc<QueueRegistry>,
     ) -> Self {
         let date = Local::now();
         let online_cfg = config
@@ -56,6 +101,7 @@ impl Monitor {
                 if let Some(display_cfg) = &monitor_cfg.display {
                     return Some(Display {
                         ticker: tick(Duration::from_millis(1000)),
+                        instance: config.instance.clone(),
                         display_stats: display_cfg.display_stats,
                         keywords: display_cfg.port_stats.clone(),
                     });
@@ -71,8 +117,9 @@ impl Monitor {
                         .join(date.format("%Y-%m-%dT%H:%M:%S").to_string());
                     fs::create_dir_all(&path).expect("create log directory");
                     log::info!("Logging to {:?}", path);
+                    filter_ctx.bundle_sources().set_stats_dir(&path);
 
-                    let toml = toml::to_string(&config).expect("serialize config");
+                    let toml = config.to_effective_toml().expect("serialize config");
                     let mut config_file =
                         fs::File::create(path.join("config.toml")).expect("create config log");
                     config_file.write_all(toml.as_bytes()).expect("log config");
@@ -83,10 +130,26 @@ impl Monitor {
                         let wtr = Writer::from_path(&fname).expect("create portstat log");
                         port_wtrs.insert(*port_id, wtr);
                     }
+                    let drops_wtr =
+                        Writer::from_path(path.join("drops.csv")).expect("create drop log");
+                    let compiles_wtr = Writer::from_path(path.join("compiles.csv"))
+                        .expect("create compile log");
+                    let queues_wtr =
+                        Writer::from_path(path.join("queues.csv")).expect("create queue log");
+                    let cycles_wtr =
+                        Writer::from_path(path.join("cycles.csv")).expect("create cycle log");
+                    let classes_wtr = Writer::from_path(path.join("storage_classes.csv"))
+                        .expect("create storage class log");
                     return Some(Logger {
                         ticker: tick(Duration::from_millis(log_cfg.interval)),
                         path,
                         port_wtrs,
+                        drops_wtr,
+                        compiles_wtr,
+                        queues_wtr,
+                        cycles_wtr,
+                        classes_wtr,
+                        last_logged_generation: 0,
                         keywords: log_cfg.port_stats.clone(),
                     });
                 }
@@ -94,6 +157,50 @@ impl Monitor {
             None
         })();
 
+        let pressure = config.mempool.shed_watermark.map(|shed_watermark| Pressure {
+            ticker: tick(Duration::from_millis(100)),
+            instance: config.instance.clone(),
+            shed_watermark,
+            resume_watermark: config.mempool.resume_watermark,
+            is_shedding: false,
+            nb_episodes: 0,
+        });
+
+        let asymmetry = Asymmetry::new(&config.flow_directions);
+
+        let keepalive_txqs: Vec<(PortId, u16)> = ports
+            .values()
+            .filter_map(|port| Some((port.id, port.keepalive_txq?)))
+            .collect();
+        let keepalive = (!keepalive_txqs.is_empty()).then(|| Keepalive {
+            ticker: tick(bond::KEEPALIVE_INTERVAL),
+            txqs: keepalive_txqs,
+        });
+
+        let profile = config.profile.as_ref().map(|profile_cfg| Profile {
+            ticker: tick(Duration::from_secs(profile_cfg.report_interval)),
+            nb_reported: profile_cfg.nb_reported,
+        });
+
+        let counters = config.counters.as_ref().map(|counter_cfg| {
+            CounterExport::new(counter_cfg).expect("create counter export")
+        });
+
+        let benchmark = online_cfg.benchmark.then(Benchmark::new);
+
+        let signals = online_cfg
+            .monitor
+            .as_ref()
+            .and_then(|monitor_cfg| monitor_cfg.signals.clone());
+        if signals.is_some() {
+            signals::install().expect("install signal handlers");
+        }
+
+        let bundle = filter_ctx.bundle_sources();
+        bundle.set_config(config);
+        bundle.set_ports(ports.values().map(|port| (port.id, port.device.clone())).collect());
+        filter_ctx.pause_state().set_ports(ports);
+
         let mut monitor_ports: BTreeMap<PortId, Vec<RxQueue>> = BTreeMap::new();
         for (port_id, port) in ports.iter() {
             monitor_ports.insert(*port_id, port.queue_map.keys().cloned().collect());
@@ -104,7 +211,20 @@ impl Monitor {
             display,
             logger,
             ports: monitor_ports,
+            filter_ctx: filter_ctx.clone(),
+            rule_ticker: tick(Duration::from_millis(1000)),
+            timebase_ticker: tick(timebase::RESYNC_INTERVAL),
+            pressure,
+            asymmetry,
+            keepalive,
+            profile,
+            counters,
             is_running,
+            readiness,
+            notifier: Notifier::new(config.notify.as_ref()),
+            queues,
+            benchmark,
+            signals,
         }
     }
 
@@ -121,21 +241,97 @@ impl Monitor {
 
         let mut prev_rx = init_rx;
         let mut prev_ts = init_ts;
+        let mut prev_cycles = self.filter_ctx.core_cycles();
         let mut init = true;
         // Add a small delay to allow workers to start polling for packets
         std::thread::sleep(Duration::from_millis(1000));
+        if let Some(benchmark) = &mut self.benchmark {
+            benchmark.start(&self.queues.snapshot());
+        }
         while self.is_running.load(Ordering::Relaxed) {
+            if !self.readiness.is_ready() && self.readiness.all_polling() {
+                log::info!("All RX cores polling");
+                self.readiness.set_ready();
+                self.notifier.ready();
+            }
+            self.notifier.watchdog();
+
             if let Some(duration) = self.duration {
                 if start_ts.elapsed() >= duration {
                     self.is_running.store(false, Ordering::Relaxed);
                 }
             }
 
+            if self.timebase_ticker.try_recv().is_ok() {
+                timebase::resync();
+            }
+
+            if self.rule_ticker.try_recv().is_ok() {
+                if let Err(error) = self.filter_ctx.expire_rules() {
+                    log::error!("Rule expiry error: {}", error);
+                }
+                if let Err(error) = self.filter_ctx.throttle_rules() {
+                    log::error!("Rule throttling error: {}", error);
+                }
+                self.filter_ctx.update_scan_depth();
+                self.filter_ctx.update_rule_profiles();
+                self.filter_ctx.prune_neighbors();
+            }
+
+            if let Some(profile) = &self.profile {
+                if profile.ticker.try_recv().is_ok() {
+                    profile.report(&self.filter_ctx);
+                }
+            }
+
+            if let Some(counters) = &mut self.counters {
+                if counters.ticker.try_recv().is_ok() {
+                    if let Err(error) = counters.export(&self.filter_ctx) {
+                        log::error!("Counter export error: {}", error);
+                    }
+                }
+            }
+
+            if let Some(signals) = &self.signals {
+                if signals::take_dump_request() {
+                    self.dump_state();
+                }
+                if signals::take_trace_toggle() {
+                    self.toggle_trace(signals.trace_core);
+                }
+            }
+
+            if let Some(benchmark) = &mut self.benchmark {
+                if benchmark.ticker.try_recv().is_ok() {
+                    benchmark.update(&self.queues.snapshot());
+                }
+            }
+
+            if let Some(keepalive) = &self.keepalive {
+                if keepalive.ticker.try_recv().is_ok() {
+                    for (port_id, txq) in keepalive.txqs.iter() {
+                        bond::keepalive(*port_id, *txq);
+                    }
+                }
+            }
+
+            if let Some(pressure) = &mut self.pressure {
+                if pressure.ticker.try_recv().is_ok() {
+                    pressure.check(&self.ports, &self.filter_ctx);
+                }
+            }
+
+            if self.asymmetry.ticker.try_recv().is_ok() {
+                self.asymmetry.check(self.filter_ctx.direction_stats());
+            }
+
             if let Some(display) = &self.display {
                 if display.ticker.try_recv().is_ok() {
                     let curr_ts = Instant::now();
                     let delta = curr_ts - prev_ts;
-                    match AggRxStats::collect(&self.ports, &display.keywords) {
+                    let curr_cycles = self.filter_ctx.core_cycles();
+                    let output = self.filter_ctx.output();
+                    match AggRxStats::collect(&self.ports, &display.keywords, output) {
                         Ok(curr_rx) => {
                             let nms = delta.as_millis() as f64;
                             if init {
@@ -147,15 +343,81 @@ impl Monitor {
                                 let mempool_table = display.mempool_usage(&self.ports);
                                 let rates_table = AggRxStats::display_rates(curr_rx, prev_rx, nms);
                                 let dropped_table = AggRxStats::display_dropped(curr_rx, init_rx);
-                                let mut tmp_row = row![rates_table, dropped_table];
+                                let rules_table = display.rules(
+                                    self.filter_ctx.rule_stats(),
+                                    self.filter_ctx.rule_generations(),
+                                );
+                                let scan_table = display.scan(self.filter_ctx.scan_stats());
+                                let mut tmp_row = row![rates_table, dropped_table, rules_table, scan_table];
+                                if let Some(benchmark) = &self.benchmark {
+                                    tmp_row = row![display.benchmark(benchmark), tmp_row];
+                                }
+                                let drops = total_drops(&self.filter_ctx);
+                                if drops.total() > 0 {
+                                    tmp_row = row![tmp_row, display.drops(drops)];
+                                }
+                                let checksums = total_checksums(&self.filter_ctx);
+                                if checksums.total() > 0 {
+                                    tmp_row = row![tmp_row, display.checksums(checksums)];
+                                }
+                                let alert_stats = self.filter_ctx.alert_stats();
+                                if !alert_stats.is_empty() {
+                                    tmp_row = row![tmp_row, display.alerts(&alert_stats)];
+                                }
+                                let rule_files = self.filter_ctx.rule_file_stats();
+                                if !rule_files.is_empty() {
+                                    tmp_row = row![tmp_row, display.rule_files(&rule_files)];
+                                }
+                                let rule_sources = self.filter_ctx.rule_sources();
+                                if !rule_sources.is_empty() {
+                                    tmp_row = row![tmp_row, display.rule_sources(&rule_sources)];
+                                }
+                                if let Some(shadow) = self.filter_ctx.shadow_stats() {
+                                    tmp_row = row![tmp_row, display.shadow(&shadow)];
+                                }
+                                let flow_table = self.filter_ctx.flow_table_stats();
+                                if flow_table.nb_entries > 0 || flow_table.max_entries.is_some() {
+                                    tmp_row = row![tmp_row, display.flow_table(&flow_table)];
+                                }
+                                if let Some(bridge) = self.filter_ctx.async_bridge().stats() {
+                                    tmp_row = row![tmp_row, display.async_bridge(&bridge)];
+                                }
+                                let flags = self.filter_ctx.flags();
+                                if flags.iter().any(|flag| !flag.enabled || !flag.builtin || flag.overridden) {
+                                    tmp_row = row![tmp_row, display.flags(&flags)];
+                                }
                                 tmp_row.with(Style::modern());
                                 let mut overall = col![mempool_table, tmp_row];
+                                if let Some(talkers) = self.filter_ctx.top_talkers() {
+                                    overall = col![overall, display.talkers(&talkers)];
+                                }
+                                let queues = self.queues.snapshot();
+                                if !queues.is_empty() {
+                                    overall = col![overall, display.queues(&queues)];
+                                }
+                                if !curr_cycles.is_empty() {
+                                    let cycles = display.cycles(&curr_cycles, &prev_cycles);
+                                    overall = col![overall, cycles];
+                                }
+                                let classes = self.filter_ctx.storage_class_stats();
+                                if !classes.is_empty() {
+                                    overall = col![overall, display.storage_classes(&classes)];
+                                }
+                                let quotas = self.filter_ctx.storage_quota_stats();
+                                if !quotas.is_empty() {
+                                    overall = col![overall, display.storage_quotas(&quotas)];
+                                }
+                                let collectors = self.filter_ctx.forward_stats();
+                                if !collectors.is_empty() {
+                                    overall = col![overall, display.collectors(&collectors)];
+                                }
                                 overall.with(Panel::header(format!("Overall statistics\nCurrent time: {}s", (curr_ts - start_ts).as_secs())));
                                 overall.with(Style::modern());
-                                println!("{overall}");
+                                self.filter_ctx.output().write(OutputKind::Statistics, overall);
                             }
                             prev_rx = curr_rx;
                             prev_ts = curr_ts;
+                            prev_cycles = curr_cycles;
                         }
                         Err(error) => {
                             log::error!("Monitor display error: {}", error);
@@ -166,7 +428,9 @@ impl Monitor {
 
             if let Some(logger) = &mut self.logger {
                 if logger.ticker.try_recv().is_ok() {
-                    match logger.log_stats(init_ts.elapsed()) {
+                    let queues = self.queues.snapshot();
+                    let elapsed = init_ts.elapsed();
+                    match logger.log_stats(elapsed, timebase::now_ns(), &self.filter_ctx, &queues) {
                         Ok(_) => (),
                         Err(error) => log::error!("Monitor log error: {}", error),
                     }
@@ -174,10 +438,286 @@ impl Monitor {
             }
         }
 
+        self.notifier.stopping();
         std::thread::sleep(Duration::from_millis(100));
-        println!("----------------------------------------------");
-        let tputs = Throughputs::new(prev_rx, init_rx, (prev_ts - init_ts).as_millis() as f64);
-        println!("{}", tputs);
+        self.filter_ctx
+            .output()
+            .write(OutputKind::Summary, "----------------------------------------------");
+        if let Some(pressure) = &self.pressure {
+            if pressure.nb_episodes > 0 {
+                log::warn!(
+                    "Shed load {} time(s) under mempool pressure, {} packet copies skipped",
+                    pressure.nb_episodes,
+                    self.filter_ctx.nb_shed()
+                );
+            }
+        }
+        let directions = self.filter_ctx.direction_stats();
+        if directions.nb_classified() > 0 {
+            log::info!(
+                "Ended flows: {} bidirectional, {} unidirectional ({:.1}%), {} unclassified",
+                directions.nb_bidirectional,
+                directions.nb_unidirectional,
+                100.0 * directions.unidirectional_ratio(),
+                directions.nb_unclassified
+            );
+        }
+        if self.asymmetry.nb_episodes > 0 {
+            log::warn!(
+                "Mostly unidirectional flows {} time(s), check the taps and routing",
+                self.asymmetry.nb_episodes
+            );
+        }
+        let pause = self.filter_ctx.pause_stats();
+        if pause.nb_pauses > 0 {
+            log::info!(
+                "Paused {} time(s), {:.3} s in total",
+                pause.nb_pauses,
+                pause.paused_time.as_secs_f64()
+            );
+        }
+        for (core, counts) in self.filter_ctx.drop_stats() {
+            let core = core.map_or("other".into(), |core| format!("core {core}"));
+            for (reason, count) in counts.iter() {
+                log::info!("Dropped on {}: {} pkts {}", core, count, reason.name());
+            }
+        }
+        let checksums = total_checksums(&self.filter_ctx);
+        if checksums.total() > 0 {
+            log::info!(
+                "Checksums: {} good, {} unchecked, {} bad ({} dropped, {} flagged, {} ignored)",
+                checksums.nb_good,
+                checksums.nb_unchecked,
+                checksums.nb_bad(),
+                checksums.nb_dropped,
+                checksums.nb_flagged,
+                checksums.nb_ignored
+            );
+        }
+        if let Some(cache) = self.filter_ctx.verdict_cache_stats() {
+            log::info!(
+                "Verdict cache: {} hits, {} cleared keys",
+                cache.nb_hits,
+                cache.nb_entries
+            );
+        }
+        if let Some(tap) = self.filter_ctx.tap_stats() {
+            log::info!("Tapped {} pkts, {} dropped", tap.nb_tapped, tap.nb_dropped);
+        }
+        if let Some(capture) = self.filter_ctx.capture_stats() {
+            log::info!(
+                "Captured {} pkts to {} files, {} dropped",
+                capture.nb_captured,
+                capture.nb_files,
+                capture.nb_dropped
+            );
+            if capture.nb_deduped > 0 {
+                log::info!(
+                    "Deduplicated {} captured pkts, {} payload bytes saved",
+                    capture.nb_deduped,
+                    capture.nb_deduped_bytes
+                );
+            }
+            if capture.nb_finalized > 0 || capture.nb_finalize_failed > 0 {
+                log::info!(
+                    "Finalized {} capture files, {} failed",
+                    capture.nb_finalized,
+                    capture.nb_finalize_failed
+                );
+            }
+        }
+        if let Some(backfill) = self.filter_ctx.backfill_stats() {
+            log::info!(
+                "Backfill: buffered {} pkts, flushed {}, {} skipped over budget",
+                backfill.nb_buffered,
+                backfill.nb_flushed,
+                backfill.nb_skipped
+            );
+        }
+        for target in self.filter_ctx.store_target_stats().iter() {
+            log::info!(
+                "Store target {}: routed {} pkts, captured {}",
+                target.name,
+                target.nb_routed,
+                target.capture.map_or(0, |capture| capture.nb_captured)
+            );
+        }
+        if let Some(yara) = self.filter_ctx.yara_stats() {
+            log::info!(
+                "YARA: {} rules, {} scans, {} matched, {} truncated, {} timed out, {} errors",
+                yara.nb_rules,
+                yara.nb_scans,
+                yara.nb_matches,
+                yara.nb_truncated,
+                yara.nb_timeouts,
+                yara.nb_errors
+            );
+        }
+        for port in self.filter_ctx.egress_rewrite_stats() {
+            if port.nb_rewritten > 0 || port.nb_dropped > 0 {
+                log::info!(
+                    "Egress rewrite of {}: {} rewritten, {} dropped",
+                    port.device,
+                    port.nb_rewritten,
+                    port.nb_dropped
+                );
+            }
+        }
+        for collector in self.filter_ctx.forward_stats() {
+            log::info!(
+                "Forwarded {} pkts to {} ({:.3} bps), {} dropped, {} spooled, {} lost, \
+                 {} connections",
+                collector.nb_sent,
+                collector.name,
+                collector.avg_sent_bps,
+                collector.nb_dropped,
+                collector.nb_spooled,
+                collector.nb_lost,
+                collector.nb_connects
+            );
+        }
+        for quota in self.filter_ctx.storage_quota_stats() {
+            log::info!(
+                "Storage quota of tenant {}: {} of {} bytes, {} pkts stored, {} refused",
+                quota.tenant,
+                quota.nb_bytes,
+                quota.max_bytes,
+                quota.nb_stored,
+                quota.nb_refused
+            );
+        }
+        for flag in self.filter_ctx.flags() {
+            if !flag.enabled || flag.overridden {
+                log::info!(
+                    "Feature flag {}: {}{}",
+                    flag.name,
+                    if flag.enabled { "on" } else { "off" },
+                    if flag.overridden { " (set at runtime)" } else { "" }
+                );
+            }
+        }
+        for class in self.filter_ctx.storage_class_stats() {
+            if class.nb_dropped > 0 {
+                log::info!(
+                    "Storage queue {} class {}: {} pkts queued, {} dropped",
+                    class.queue,
+                    class.class,
+                    class.nb_enqueued,
+                    class.nb_dropped
+                );
+            }
+        }
+        for stage in self.filter_ctx.pipeline().stats() {
+            log::info!(
+                "Stage {} processed {} pkts, {} dropped",
+                stage.name,
+                stage.nb_pkts,
+                stage.nb_dropped
+            );
+        }
+        if let Some(counters) = &mut self.counters {
+            if let Err(error) = counters.export(&self.filter_ctx) {
+                log::error!("Counter export error: {}", error);
+            }
+        }
+        for count in self.filter_ctx.rule_counts() {
+            log::info!(
+                "Counted {} pkts, {} bytes: {}",
+                count.nb_pkts,
+                count.nb_bytes,
+                count.rule.pattern
+            );
+        }
+        for throttled in self.filter_ctx.throttled_rules() {
+            log::warn!("Rule still disabled by throttling: {}", throttled.rule.pattern);
+        }
+        let flow_table = self.filter_ctx.flow_table_stats();
+        if flow_table.nb_evicted > 0 || flow_table.nb_rejected > 0 {
+            log::warn!(
+                "Flow table full: {} flows evicted, {} new flows not tracked",
+                flow_table.nb_evicted,
+                flow_table.nb_rejected
+            );
+        }
+        if flow_table.nb_sticky_overflow > 0 {
+            log::warn!(
+                "Sticky flows full: {} matched flows expired at the flow timeout",
+                flow_table.nb_sticky_overflow
+            );
+        }
+        if flow_table.nb_contended() > 0 {
+            log::info!(
+                "Flow table: {} of {} lookups contended with another core",
+                flow_table.nb_contended(),
+                flow_table.nb_lookups()
+            );
+        }
+        if let Some(shadow) = self.filter_ctx.shadow_stats() {
+            log::info!(
+                "Shadow rules: {} of {} sampled pkts matched, {} added, {} removed",
+                shadow.nb_matched,
+                shadow.nb_sampled,
+                shadow.nb_added,
+                shadow.nb_removed
+            );
+            for count in shadow.rules.iter() {
+                log::info!(
+                    "Shadow counted {} pkts, {} bytes: {}",
+                    count.nb_pkts,
+                    count.nb_bytes,
+                    count.rule.pattern
+                );
+            }
+        }
+        if let Some(talkers) = self.filter_ctx.top_talkers() {
+            for talker in talkers.sources.iter() {
+                log::info!(
+                    "Top source {}: ~{} bytes, ~{} pkts",
+                    talker.key,
+                    talker.bytes,
+                    talker.pkts
+                );
+            }
+        }
+        for subscriber in self.filter_ctx.alert_stats() {
+            log::info!(
+                "Alerts to {}: {} sent, {} dropped",
+                subscriber.address,
+                subscriber.nb_sent,
+                subscriber.nb_dropped
+            );
+        }
+        if let Some(bridge) = self.filter_ctx.async_bridge().stats() {
+            log::info!(
+                "Async bridge: {} events queued, {} dropped, {} handled, max depth {}/{}",
+                bridge.nb_queued,
+                bridge.nb_dropped,
+                bridge.nb_completed,
+                bridge.max_depth,
+                bridge.capacity
+            );
+        }
+        let mut tputs = Throughputs::new(prev_rx, init_rx, (prev_ts - init_ts).as_millis() as f64);
+        tputs.drops = total_drops(&self.filter_ctx)
+            .iter()
+            .map(|(reason, count)| (reason.name(), count))
+            .collect();
+        tputs.top_talkers = self.filter_ctx.top_talkers();
+        tputs.checksums = total_checksums(&self.filter_ctx);
+        tputs.shadow = self.filter_ctx.shadow_stats();
+        tputs.flow_table = Some(flow_table);
+        tputs.queues = self.queues.snapshot();
+        tputs.cores = self.filter_ctx.core_cycles();
+        tputs.storage_classes = self.filter_ctx.storage_class_stats();
+        tputs.storage_quotas = self.filter_ctx.storage_quota_stats();
+        tputs.collectors = self.filter_ctx.forward_stats();
+        tputs.flags = self.filter_ctx.flags();
+        tputs.pause = pause;
+        tputs.benchmark = self
+            .benchmark
+            .as_ref()
+            .map(|benchmark| benchmark.stats(&tputs.queues));
+        self.filter_ctx.output().write(OutputKind::Summary, &tputs);
 
         if let Some(logger) = &self.logger {
             let json_fname = logger.path.join("throughputs.json");
@@ -186,9 +726,421 @@ impl Monitor {
     }
 }
 
+impl Monitor {
+    /// Logs the runtime state, and writes it to the log directory if logging
+    fn dump_state(&self) {
+        let dump = StateDump::collect(&self.filter_ctx, self.queues.snapshot());
+        dump.log();
+        if let Some(logger) = &self.logger {
+            match dump.write(&logger.path) {
+                Ok(path) => log::info!("State dump written to {}", path.display()),
+                Err(error) => log::error!("State dump error: {}", error),
+            }
+        }
+    }
+
+    /// Toggles tracing on `core`, logging the recorded events when turning it off
+    fn toggle_trace(&self, core: Option<u32>) {
+        let core = match core {
+            Some(core) => core,
+            None => {
+                log::warn!("SIGUSR2 ignored, no trace core configured");
+                return;
+            }
+        };
+        if self.filter_ctx.is_tracing(core) {
+            self.filter_ctx.disable_trace(core);
+            for record in self.filter_ctx.dump_trace(core) {
+                log::info!("core {} {}", core, record);
+            }
+        } else {
+            self.filter_ctx.enable_trace(core);
+        }
+    }
+}
+
+/// Aggregates the drop counts of all cores
+fn total_drops(filter_ctx: &FilterCtx) -> DropCounts {
+    let mut total = DropCounts::default();
+    for counts in filter_ctx.drop_stats().values() {
+        total.add(counts);
+    }
+    total
+}
+
+/// Aggregates the checksum outcome counts of all cores
+fn total_checksums(filter_ctx: &FilterCtx) -> ChecksumStats {
+    let mut total = ChecksumStats::default();
+    for stats in filter_ctx.checksum_stats().values() {
+        total.add(stats);
+    }
+    total
+}
+
+/// Periodic rule cost reporting
+#[derive(Debug)]
+struct Profile {
+    ticker: Receiver<Instant>,
+    nb_reported: usize,
+}
+
+impl Profile {
+    /// Logs the most expensive rules
+    fn report(&self, filter_ctx: &FilterCtx) {
+        let costs = filter_ctx.rule_costs();
+        if costs.is_empty() {
+            return;
+        }
+        log::info!("Most expensive rules ({} profiled):", costs.len());
+        for cost in costs.iter().take(self.nb_reported) {
+            log::info!(
+                "  {:.0} ns avg, {} samples, {} matches: {}",
+                cost.avg_ns,
+                cost.nb_samples,
+                cost.nb_matches,
+                cost.pattern
+            );
+        }
+    }
+}
+
+/// Periodic export of counting rule counters
+#[derive(Debug)]
+struct CounterExport {
+    ticker: Receiver<Instant>,
+    path: PathBuf,
+    formats: Vec<CounterFormat>,
+    csv_wtr: Option<Writer<fs::File>>,
+}
+
+impl CounterExport {
+    fn new(config: &CounterConfig) -> Result<Self> {
+        let path = PathBuf::from(&config.directory);
+        fs::create_dir_all(&path)?;
+        let csv_wtr = if config.formats.contains(&CounterFormat::Csv) {
+            let mut wtr = Writer::from_path(path.join("counters.csv"))?;
+            wtr.write_record(["ts", "pattern", "nb_pkts", "nb_bytes"])?;
+            wtr.flush()?;
+            Some(wtr)
+        } else {
+            None
+        };
+        log::info!("Exporting rule counters to {:?}", path);
+        Ok(CounterExport {
+            ticker: tick(Duration::from_secs(config.interval)),
+            path,
+            formats: config.formats.clone(),
+            csv_wtr,
+        })
+    }
+
+    /// Writes the current counters in each format
+    fn export(&mut self, filter_ctx: &FilterCtx) -> Result<()> {
+        let counts = filter_ctx.rule_counts();
+        let ts = timebase::now().as_secs();
+        for format in self.formats.iter() {
+            match format {
+                CounterFormat::Csv => {
+                    if let Some(wtr) = &mut self.csv_wtr {
+                        for count in counts.iter() {
+                            wtr.write_record([
+                                ts.to_string(),
+                                count.rule.pattern.clone(),
+                                count.nb_pkts.to_string(),
+                                count.nb_bytes.to_string(),
+                            ])?;
+                        }
+                        wtr.flush()?;
+                    }
+                }
+                CounterFormat::Json => {
+                    write_replace(&self.path.join("counters.json"), &serde_json::to_vec(&counts)?)?;
+                }
+                CounterFormat::Prometheus => {
+                    let contents = prometheus(&counts);
+                    write_replace(&self.path.join("counters.prom"), contents.as_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it, so that readers never see
+/// a partial file
+fn write_replace(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Formats rule counters in the Prometheus text format
+fn prometheus(counts: &[RuleCount]) -> String {
+    let escape = |value: String| {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+    let labels: Vec<String> = counts
+        .iter()
+        .map(|count| {
+            let rule = &count.rule;
+            let mut labels = vec![("pattern", rule.pattern.clone())];
+            if let Some(app) = rule.app {
+                labels.push(("app", app.to_string()));
+            }
+            if let Some(s_tag) = rule.s_tag {
+                labels.push(("s_tag", s_tag.to_string()));
+            }
+            if let Some(c_tag) = rule.c_tag {
+                labels.push(("c_tag", c_tag.to_string()));
+            }
+            if let Some(mac) = rule.mac {
+                labels.push(("mac", mac.to_string()));
+            }
+            labels
+                .into_iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect();
+    let mut out = String::new();
+    let mut write_metric = |name: &str, help: &str, value: fn(&RuleCount) -> u64| {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} counter\n", name, help, name));
+        for (count, labels) in counts.iter().zip(labels.iter()) {
+            out.push_str(&format!("{}{{{}}} {}\n", name, labels, value(count)));
+        }
+    };
+    write_metric(
+        "retina_rule_packets_total",
+        "Packets matching a counting rule.",
+        |count| count.nb_pkts,
+    );
+    write_metric(
+        "retina_rule_bytes_total",
+        "Payload bytes of the packets matching a counting rule.",
+        |count| count.nb_bytes,
+    );
+    out
+}
+
+/// Rates counted by the RX cores
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+struct Rates {
+    bps: f64,
+    pps: f64,
+}
+
+impl fmt::Display for Rates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.3} bps / {:.3} pps", self.bps, self.pps)
+    }
+}
+
+/// Outcome of a benchmark run
+#[derive(Debug, Clone, Copy, Serialize)]
+struct BenchmarkStats {
+    nb_pkts: u64,
+    nb_bytes: u64,
+    avg: Rates,
+    peak: Rates,
+}
+
+/// Line-rate benchmark, from the software counters of the RX queues
+#[derive(Debug)]
+struct Benchmark {
+    ticker: Receiver<Instant>,
+    /// Time and totals at the start of the run.
+    start: (Instant, QueueStats),
+    /// Time and totals at the previous update.
+    prev: (Instant, QueueStats),
+    current: Rates,
+    peak: Rates,
+}
+
+impl Benchmark {
+    fn new() -> Self {
+        let now = Instant::now();
+        Benchmark {
+            ticker: tick(Duration::from_millis(1000)),
+            start: (now, QueueStats::default()),
+            prev: (now, QueueStats::default()),
+            current: Rates::default(),
+            peak: Rates::default(),
+        }
+    }
+
+    /// Sums the counters of all queues
+    fn total(queues: &[QueueStats]) -> QueueStats {
+        queues.iter().fold(QueueStats::default(), |total, queue| QueueStats {
+            nb_pkts: total.nb_pkts + queue.nb_pkts,
+            nb_bytes: total.nb_bytes + queue.nb_bytes,
+            ..total
+        })
+    }
+
+    /// Rates between totals `prev` and `curr`, `elapsed` apart, with Ethernet framing overhead
+    fn rates(curr: QueueStats, prev: QueueStats, elapsed: Duration) -> Rates {
+        let secs = elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return Rates::default();
+        }
+        let nb_pkts = curr.nb_pkts - prev.nb_pkts;
+        let nb_bytes = curr.nb_bytes - prev.nb_bytes;
+        let nb_bits = (nb_bytes + (PSFD_SIZE + IPG_SIZE + FCS_SIZE) * nb_pkts) * 8;
+        Rates {
+            bps: nb_bits as f64 / secs,
+            pps: nb_pkts as f64 / secs,
+        }
+    }
+
+    /// Starts measuring, excluding the packets received while the cores started
+    fn start(&mut self, queues: &[QueueStats]) {
+        let start = (Instant::now(), Benchmark::total(queues));
+        self.start = start;
+        self.prev = start;
+    }
+
+    /// Updates the current and peak rates
+    fn update(&mut self, queues: &[QueueStats]) {
+        let curr = (Instant::now(), Benchmark::total(queues));
+        self.current = Benchmark::rates(curr.1, self.prev.1, curr.0 - self.prev.0);
+        if self.current.pps > self.peak.pps {
+            self.peak = self.current;
+        }
+        self.prev = curr;
+    }
+
+    fn stats(&self, queues: &[QueueStats]) -> BenchmarkStats {
+        let total = Benchmark::total(queues);
+        BenchmarkStats {
+            nb_pkts: total.nb_pkts - self.start.1.nb_pkts,
+            nb_bytes: total.nb_bytes - self.start.1.nb_bytes,
+            avg: Benchmark::rates(total, self.start.1, self.start.0.elapsed()),
+            peak: self.peak,
+        }
+    }
+}
+
+/// Detection of mostly unidirectional traffic, see [directions](crate::filter::directions)
+#[derive(Debug)]
+struct Asymmetry {
+    ticker: Receiver<Instant>,
+    min_flows: u64,
+    max_unidirectional: f64,
+    /// Direction classes at the previous check
+    prev: DirectionStats,
+    is_warning: bool,
+    nb_episodes: u64,
+}
+
+impl Asymmetry {
+    fn new(config: &FlowDirectionConfig) -> Self {
+        Asymmetry {
+            ticker: tick(Duration::from_secs(config.interval_secs.max(1))),
+            min_flows: config.min_flows,
+            max_unidirectional: config.max_unidirectional,
+            prev: DirectionStats::default(),
+            is_warning: false,
+            nb_episodes: 0,
+        }
+    }
+
+    /// Warns when the flows that ended since the previous check are mostly unidirectional, and
+    /// once they no longer are
+    fn check(&mut self, curr: DirectionStats) {
+        let interval = curr.since(&self.prev);
+        self.prev = curr;
+        if interval.nb_classified() < self.min_flows.max(1) {
+            return;
+        }
+        let ratio = interval.unidirectional_ratio();
+        if !self.is_warning && ratio > self.max_unidirectional {
+            self.is_warning = true;
+            self.nb_episodes += 1;
+            log::warn!(
+                "{:.1}% of {} ended flows are unidirectional, a tap or route may only deliver one \
+                 direction",
+                100.0 * ratio,
+                interval.nb_classified()
+            );
+        } else if self.is_warning && ratio <= self.max_unidirectional {
+            self.is_warning = false;
+            log::warn!(
+                "Unidirectional flows back to {:.1}% of {} ended flows",
+                100.0 * ratio,
+                interval.nb_classified()
+            );
+        }
+    }
+}
+
+/// LACP keepalive of the bonded ports
+#[derive(Debug)]
+struct Keepalive {
+    ticker: Receiver<Instant>,
+    /// TX queue of each LACP bond
+    txqs: Vec<(PortId, u16)>,
+}
+
+/// Mempool watermark monitoring and emergency load shedding
+#[derive(Debug)]
+struct Pressure {
+    ticker: Receiver<Instant>,
+    /// Runtime instance name, part of the mempool names.
+    instance: Option<String>,
+    shed_watermark: f64,
+    resume_watermark: f64,
+    is_shedding: bool,
+    nb_episodes: u64,
+}
+
+impl Pressure {
+    /// Starts or stops shedding based on the lowest fraction of available mbufs across mempools
+    fn check(&mut self, ports: &BTreeMap<PortId, Vec<RxQueue>>, filter_ctx: &FilterCtx) {
+        let mut available = 1.0_f64;
+        let instance = self.instance.as_deref();
+        for name in ports.keys().map(|id| mempool_name(instance, id.socket_id())) {
+            let cname = CString::new(name).expect("Invalid CString conversion");
+            let mempool_raw = unsafe { dpdk::rte_mempool_lookup(cname.as_ptr()) };
+            if mempool_raw.is_null() {
+                continue;
+            }
+            let avail_cnt = unsafe { dpdk::rte_mempool_avail_count(mempool_raw) };
+            let inuse_cnt = unsafe { dpdk::rte_mempool_in_use_count(mempool_raw) };
+            let total = avail_cnt + inuse_cnt;
+            if total > 0 {
+                available = available.min(avail_cnt as f64 / total as f64);
+            }
+        }
+
+        if !self.is_shedding && available < self.shed_watermark {
+            self.is_shedding = true;
+            self.nb_episodes += 1;
+            log::warn!(
+                "Mempool pressure: {:.1}% mbufs available, shedding load",
+                100.0 * available
+            );
+            filter_ctx.set_shedding(true, available);
+        } else if self.is_shedding && available > self.resume_watermark {
+            self.is_shedding = false;
+            log::warn!(
+                "Mempool pressure relieved: {:.1}% mbufs available, resuming",
+                100.0 * available
+            );
+            filter_ctx.set_shedding(false, available);
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Display {
     ticker: Receiver<Instant>,
+    /// Runtime instance name, part of the mempool names.
+    instance: Option<String>,
     display_stats: bool,
     keywords: Vec<String>,
 }
@@ -197,7 +1149,8 @@ impl Display {
     /// Display mempool usage
     fn mempool_usage(&self, ports: &BTreeMap<PortId, Vec<RxQueue>>) -> Table {
         let mut total = Builder::default();
-        for name in ports.keys().map(|id| format!("mempool_{}", id.socket_id())) {
+        let instance = self.instance.as_deref();
+        for name in ports.keys().map(|id| mempool_name(instance, id.socket_id())) {
             let cname = CString::new(name.clone()).expect("Invalid CString conversion");
             let mempool_raw = unsafe { dpdk::rte_mempool_lookup(cname.as_ptr()) };
             let avail_cnt = unsafe { dpdk::rte_mempool_avail_count(mempool_raw) };
@@ -219,6 +1172,441 @@ impl Display {
         total_table.with(Style::modern());
         return total_table;
     }
+
+    /// Display rule set statistics
+    fn rules(&self, stats: RuleStats, generations: RuleGenerations) -> Table {
+        let mut builder = Builder::default();
+        builder.add_record(["Active".into(), format!("{} rules", stats.nb_rules)]);
+        builder.add_record(["Counting".into(), format!("{} rules", stats.nb_counting)]);
+        builder.add_record(["Capturing".into(), format!("{} rules", stats.nb_capturing)]);
+        builder.add_record(["Expired".into(), format!("{} rules", stats.nb_expired)]);
+        builder.add_record(["Throttled".into(), format!("{} rules", stats.nb_throttled)]);
+        builder.add_record([
+            "Failed tests".into(),
+            format!("{} rules ({} rejected)", stats.nb_failed_tests, stats.nb_rejected),
+        ]);
+        let regex = stats.regex_defaults;
+        let flags: Vec<&str> = [
+            (regex.nocase, "nocase"),
+            (regex.dotall, "dotall"),
+            (regex.multiline, "multiline"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| *name)
+        .collect();
+        let flags = if flags.is_empty() {
+            "none".to_string()
+        } else {
+            flags.join(", ")
+        };
+        builder.add_record(["Default flags".into(), flags]);
+        builder.add_record(["Generation".into(), stats.generation.to_string()]);
+        if let Some(compile) = stats.last_compile {
+            builder.add_record([
+                "Last compile".into(),
+                format!(
+                    "{:.1} ms, ~{} KiB",
+                    compile.compile_time.as_secs_f64() * 1000.0,
+                    compile.est_heap_bytes / 1024
+                ),
+            ]);
+        }
+        if !generations.cores.is_empty() {
+            let stale = generations.stale_cores();
+            let propagated = if stale.is_empty() {
+                format!("all {} cores", generations.cores.len())
+            } else {
+                format!("pending on {}", stale.iter().format(", "))
+            };
+            builder.add_record(["Propagated".into(), propagated]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Rules"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display scan depth and match offset statistics
+    fn scan(&self, stats: ScanStats) -> Table {
+        let fmt_bytes = |bytes: Option<usize>| match bytes {
+            Some(bytes) => format!("{bytes} bytes"),
+            None => "-".into(),
+        };
+        let mode = match stats.mode {
+            ScanMode::Unlimited => "unlimited",
+            ScanMode::Fixed => "fixed",
+            ScanMode::Adaptive => "adaptive",
+        };
+        let mut builder = Builder::default();
+        builder.add_record(["Depth".into(), format!("{} ({mode})", fmt_bytes(stats.depth))]);
+        builder.add_record(["Matches".into(), stats.nb_matches.to_string()]);
+        builder.add_record(["p50 offset".into(), fmt_bytes(stats.p50_offset)]);
+        builder.add_record(["p99 offset".into(), fmt_bytes(stats.p99_offset)]);
+        builder.add_record(["Skipped".into(), format!("{} pkts", stats.nb_skipped)]);
+        if stats.nb_classified > 0 {
+            builder.add_record([
+                "Classified".into(),
+                format!("{} pkts, {} bytes", stats.nb_classified, stats.nb_classified_bytes),
+            ]);
+        }
+        if stats.nb_gated > 0 || stats.nb_rule_gated > 0 {
+            builder.add_record([
+                "Gated".into(),
+                format!("{} pkts, {} rule groups", stats.nb_gated, stats.nb_rule_gated),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Scan"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display drop counts by reason
+    fn drops(&self, counts: DropCounts) -> Table {
+        let mut builder = Builder::default();
+        for (reason, count) in counts.iter() {
+            builder.add_record([reason.to_string(), format!("{count} pkts")]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Drop reasons"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display checksum check outcomes
+    fn checksums(&self, stats: ChecksumStats) -> Table {
+        let mut builder = Builder::default();
+        builder.add_record(["Good".into(), format!("{} pkts", stats.nb_good)]);
+        builder.add_record(["Unchecked".into(), format!("{} pkts", stats.nb_unchecked)]);
+        builder.add_record(["Dropped".into(), format!("{} pkts", stats.nb_dropped)]);
+        builder.add_record(["Flagged".into(), format!("{} pkts", stats.nb_flagged)]);
+        builder.add_record(["Ignored".into(), format!("{} pkts", stats.nb_ignored)]);
+        let mut table = builder.build();
+        table.with(Panel::header("Checksums"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display shadow rule set statistics
+    fn shadow(&self, stats: &ShadowStats) -> Table {
+        let mut builder = Builder::default();
+        builder.add_record(["Rules".into(), stats.nb_rules.to_string()]);
+        builder.add_record(["Sampled".into(), format!("{} pkts", stats.nb_sampled)]);
+        builder.add_record(["Matched".into(), format!("{} pkts", stats.nb_matched)]);
+        builder.add_record(["Active matched".into(), format!("{} pkts", stats.nb_live_matched)]);
+        builder.add_record(["Added".into(), format!("{} pkts", stats.nb_added)]);
+        builder.add_record(["Removed".into(), format!("{} pkts", stats.nb_removed)]);
+        let mut table = builder.build();
+        table.with(Panel::header("Shadow rules"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display flow table occupancy, removals and per-core counters
+    fn flow_table(&self, stats: &FlowTableStats) -> Table {
+        let mut builder = Builder::default();
+        builder.add_record(["Entries".into(), stats.nb_entries.to_string()]);
+        if let (Some(max_entries), Some(occupancy)) = (stats.max_entries, stats.occupancy()) {
+            builder.add_record(["Capacity".into(), max_entries.to_string()]);
+            builder.add_record(["Occupancy".into(), format!("{:.1}%", occupancy * 100.0)]);
+        }
+        builder.add_record(["Inserted".into(), format!("{} flows", stats.nb_inserted)]);
+        builder.add_record(["Expired (idle)".into(), format!("{} flows", stats.nb_expired)]);
+        builder.add_record(["Closed".into(), format!("{} flows", stats.nb_closed)]);
+        builder.add_record(["Evicted (capacity)".into(), format!("{} flows", stats.nb_evicted)]);
+        builder.add_record(["Rejected".into(), format!("{} flows", stats.nb_rejected)]);
+        if let Some(max_sticky) = stats.max_sticky {
+            let sticky = format!("{}/{} flows", stats.nb_sticky, max_sticky);
+            builder.add_record(["Sticky".into(), sticky]);
+            builder.add_record([
+                "Sticky overflow".into(),
+                format!("{} flows", stats.nb_sticky_overflow),
+            ]);
+        }
+        for core in stats.cores.iter() {
+            let name = match core.core {
+                Some(id) => format!("Core {id}"),
+                None => "Unattached".into(),
+            };
+            builder.add_record([
+                name,
+                format!(
+                    "{} flows, {}/{} hits, {} contended",
+                    core.nb_entries, core.nb_hits, core.nb_lookups, core.nb_contended
+                ),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Flow table"));
+        table.with(Style::modern());
+        table
+    }
+
+    fn async_bridge(&self, stats: &AsyncBridgeStats) -> Table {
+        let mut builder = Builder::default();
+        builder.add_record(["Depth".into(), format!("{}/{}", stats.depth, stats.capacity)]);
+        builder.add_record(["Max depth".into(), stats.max_depth.to_string()]);
+        builder.add_record(["Queued".into(), format!("{} events", stats.nb_queued)]);
+        builder.add_record(["Dropped".into(), format!("{} events", stats.nb_dropped)]);
+        builder.add_record(["In flight".into(), format!("{} handlers", stats.in_flight)]);
+        builder.add_record(["Completed".into(), format!("{} handlers", stats.nb_completed)]);
+        let mut table = builder.build();
+        table.with(Panel::header("Async bridge"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the load status of each rule file
+    fn rule_files(&self, stats: &[RuleFileStats]) -> Table {
+        let mut builder = Builder::default();
+        builder.set_columns(["File", "Rules", "Status"]);
+        for file in stats {
+            let name = file.path.file_name().unwrap_or_default().to_string_lossy();
+            builder.add_record([
+                name.into_owned(),
+                file.nb_rules.to_string(),
+                file.error.clone().unwrap_or_else(|| "ok".into()),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Rule files"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the rules and priority of each rule source
+    fn rule_sources(&self, stats: &[RuleSourceStats]) -> Table {
+        let mut builder = Builder::default();
+        builder.set_columns(["Source", "Priority", "Rules", "Shadowed"]);
+        for source in stats {
+            builder.add_record([
+                source.name.clone(),
+                source.priority.to_string(),
+                source.nb_rules.to_string(),
+                source.nb_shadowed.to_string(),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Rule sources"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the software counters of each RX queue
+    fn queues(&self, stats: &[QueueStats]) -> Table {
+        let mut builder = Builder::default();
+        builder.set_columns([
+            "Queue",
+            "Pkts",
+            "Bytes",
+            "Bursts",
+            "Avg burst",
+            "Max fill",
+            "Saturated",
+        ]);
+        for queue in stats {
+            builder.add_record([
+                format!("p{}q{}", queue.port, queue.queue),
+                queue.nb_pkts.to_string(),
+                queue.nb_bytes.to_string(),
+                queue.nb_bursts.to_string(),
+                format!("{:.1}", queue.avg_burst()),
+                format!("{:.0}%", queue.max_fill() * 100.0),
+                queue.nb_saturated.to_string(),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("RX queues (software)"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the utilization of each RX core since the previous display
+    fn cycles(&self, curr: &[CoreCycles], prev: &[CoreCycles]) -> Table {
+        let mut builder = Builder::default();
+        builder.set_columns(["Core", "Busy", "Cycles/pkt", "Pkts"]);
+        for cycles in curr {
+            let interval = match prev.iter().find(|prev| prev.core == cycles.core) {
+                Some(prev) => cycles.since(prev),
+                None => *cycles,
+            };
+            builder.add_record([
+                cycles.core.to_string(),
+                format!("{:.1}%", interval.utilization() * 100.0),
+                format!("{:.0}", interval.cycles_per_pkt()),
+                interval.nb_pkts.to_string(),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("RX core cycles"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the counters of each priority class of the storage queues
+    fn storage_classes(&self, stats: &[ClassStats]) -> Table {
+        let mut builder = Builder::default();
+        builder.set_columns(["Queue", "Class", "Enqueued", "Dropped", "Backlog"]);
+        for class in stats {
+            builder.add_record([
+                class.queue.to_string(),
+                class.class.to_string(),
+                class.nb_enqueued.to_string(),
+                class.nb_dropped.to_string(),
+                class.backlog.to_string(),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Storage priority classes"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the storage usage of each tenant with a quota
+    fn storage_quotas(&self, stats: &[QuotaStats]) -> Table {
+        let mut builder = Builder::default();
+        builder.set_columns(["Tenant", "VLANs", "Used", "Quota", "Stored", "Refused"]);
+        for quota in stats {
+            builder.add_record([
+                quota.tenant.clone(),
+                quota.vlans.clone(),
+                format!(
+                    "{} ({:.1}%)",
+                    quota.nb_bytes,
+                    100.0 * quota.nb_bytes as f64 / quota.max_bytes.max(1) as f64
+                ),
+                quota.max_bytes.to_string(),
+                quota.nb_stored.to_string(),
+                quota.nb_refused.to_string(),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Storage quotas"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the delivery counters of each remote collector
+    fn collectors(&self, stats: &[ForwardStats]) -> Table {
+        let mut builder = Builder::default();
+        builder.set_columns([
+            "Collector",
+            "Connected",
+            "Sent",
+            "Avg bps",
+            "Dropped",
+            "Spooled",
+            "Lost",
+        ]);
+        for collector in stats {
+            builder.add_record([
+                collector.name.clone(),
+                collector.connected.to_string(),
+                collector.nb_sent.to_string(),
+                format!("{:.3}", collector.avg_sent_bps),
+                collector.nb_dropped.to_string(),
+                format!("{} ({} bytes)", collector.nb_spooled, collector.spool_bytes),
+                collector.nb_lost.to_string(),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Collectors"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the rates counted by the RX cores in benchmark mode
+    fn benchmark(&self, benchmark: &Benchmark) -> Table {
+        let mut builder = Builder::default();
+        builder.add_record(["Current".into(), benchmark.current.to_string()]);
+        builder.add_record(["Peak".into(), benchmark.peak.to_string()]);
+        let avg = Benchmark::rates(
+            benchmark.prev.1,
+            benchmark.start.1,
+            benchmark.prev.0 - benchmark.start.0,
+        );
+        builder.add_record(["Average".into(), avg.to_string()]);
+        let mut table = builder.build();
+        table.with(Panel::header("Benchmark (counted by RX cores)"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the feature flags and where their values were set
+    fn flags(&self, flags: &[FlagState]) -> Table {
+        let mut builder = Builder::default();
+        builder.set_columns(["Flag", "Enabled", "Source"]);
+        for flag in flags {
+            builder.add_record([
+                flag.name.clone(),
+                flag.enabled.to_string(),
+                match flag.overridden {
+                    true => "runtime".to_string(),
+                    false => "config".to_string(),
+                },
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Feature flags"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display the largest sources, destinations and flows
+    fn talkers(&self, stats: &TalkerStats) -> Table {
+        fn column<K: fmt::Display>(talkers: &[Talker<K>], header: &'static str) -> Table {
+            let mut builder = Builder::default();
+            builder.set_columns([header, "Bytes", "Pkts", "Error"]);
+            for talker in talkers {
+                builder.add_record([
+                    talker.key.to_string(),
+                    talker.bytes.to_string(),
+                    talker.pkts.to_string(),
+                    talker.error.to_string(),
+                ]);
+            }
+            builder.build()
+        }
+        let flows: Vec<_> = stats
+            .flows
+            .iter()
+            .map(|talker| {
+                let (a, b) = talker.key.addrs();
+                Talker {
+                    key: format!("{a} <-> {b}"),
+                    bytes: talker.bytes,
+                    pkts: talker.pkts,
+                    error: talker.error,
+                }
+            })
+            .collect();
+        let mut table = row![
+            column(&stats.sources, "Source"),
+            column(&stats.destinations, "Destination"),
+            column(&flows, "Flow")
+        ];
+        table.with(Panel::header("Top talkers (estimated)"));
+        table.with(Style::modern());
+        table
+    }
+
+    /// Display per-subscriber alert delivery statistics
+    fn alerts(&self, stats: &[SubscriberStats]) -> Table {
+        let mut builder = Builder::default();
+        builder.set_columns(["Subscriber", "Sent", "Dropped"]);
+        for subscriber in stats {
+            builder.add_record([
+                subscriber.address.clone(),
+                subscriber.nb_sent.to_string(),
+                subscriber.nb_dropped.to_string(),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Panel::header("Alerts"));
+        table.with(Style::modern());
+        table
+    }
 }
 
 #[derive(Debug)]
@@ -226,6 +1614,13 @@ struct Logger {
     ticker: Receiver<Instant>,
     path: PathBuf,
     port_wtrs: HashMap<PortId, Writer<std::fs::File>>,
+    drops_wtr: Writer<std::fs::File>,
+    compiles_wtr: Writer<std::fs::File>,
+    queues_wtr: Writer<std::fs::File>,
+    cycles_wtr: Writer<std::fs::File>,
+    classes_wtr: Writer<std::fs::File>,
+    /// Generation of the last rule set written to `compiles_wtr`.
+    last_logged_generation: u64,
     keywords: Vec<String>,
 }
 
@@ -235,6 +1630,7 @@ impl Logger {
         for (port_id, wtr) in self.port_wtrs.iter_mut() {
             let port_stats = PortStats::collect(*port_id)?;
             wtr.write_field("ts")?;
+            wtr.write_field("unix_ns")?;
             for label in port_stats.stats.keys() {
                 if self.keywords.iter().any(|k| label.contains(k)) {
                     wtr.write_field(label)?;
@@ -245,16 +1641,100 @@ impl Logger {
             wtr.write_record(None::<&[u8]>)?;
             wtr.flush()?;
         }
+        self.drops_wtr.write_field("ts")?;
+        self.drops_wtr.write_field("unix_ns")?;
+        self.drops_wtr.write_field("core")?;
+        for reason in DropReason::ALL.iter() {
+            self.drops_wtr.write_field(reason.name())?;
+        }
+        self.drops_wtr.write_record(None::<&[u8]>)?;
+        self.drops_wtr.flush()?;
+        self.compiles_wtr.write_record([
+            "ts",
+            "unix_ns",
+            "generation",
+            "nb_rules",
+            "nb_compiled",
+            "compile_time_us",
+            "est_heap_bytes",
+        ])?;
+        self.compiles_wtr.flush()?;
+        self.queues_wtr.write_record([
+            "ts",
+            "unix_ns",
+            "port",
+            "queue",
+            "nb_pkts",
+            "nb_bytes",
+            "nb_bursts",
+            "max_burst",
+            "nb_saturated",
+        ])?;
+        self.queues_wtr.flush()?;
+        self.cycles_wtr.write_record([
+            "ts",
+            "unix_ns",
+            "core",
+            "busy_cycles",
+            "idle_cycles",
+            "nb_pkts",
+        ])?;
+        self.cycles_wtr.flush()?;
+        self.classes_wtr.write_record([
+            "ts",
+            "unix_ns",
+            "queue",
+            "class",
+            "nb_enqueued",
+            "nb_dropped",
+            "backlog",
+        ])?;
+        self.classes_wtr.flush()?;
+        Ok(())
+    }
+
+    /// Logs the rule set compilations since the last call.
+    fn log_compiles(
+        &mut self,
+        elapsed: Duration,
+        unix_ns: u64,
+        compiles: &[CompileStats],
+    ) -> Result<()> {
+        for compile in compiles {
+            if compile.generation <= self.last_logged_generation {
+                continue;
+            }
+            self.compiles_wtr.write_record([
+                elapsed.as_millis().to_string(),
+                unix_ns.to_string(),
+                compile.generation.to_string(),
+                compile.nb_rules.to_string(),
+                compile.nb_compiled.to_string(),
+                compile.compile_time.as_micros().to_string(),
+                compile.est_heap_bytes.to_string(),
+            ])?;
+            self.last_logged_generation = compile.generation;
+        }
+        self.compiles_wtr.flush()?;
         Ok(())
     }
 
-    /// Logs per-port statistics and mempool statistics (per-socket statistics).
-    fn log_stats(&mut self, elapsed: Duration) -> Result<()> {
+    /// Logs per-port statistics, mempool statistics (per-socket statistics), per-core drop
+    /// counts and rule set compilations. Rows are timestamped with the time since the monitor
+    /// started, `elapsed`, and the wall-clock time `unix_ns`.
+    fn log_stats(
+        &mut self,
+        elapsed: Duration,
+        unix_ns: u64,
+        filter_ctx: &FilterCtx,
+        queues: &[QueueStats],
+    ) -> Result<()> {
         for (port_id, wtr) in self.port_wtrs.iter_mut() {
             let port_stats = PortStats::collect(*port_id);
             match port_stats {
                 Ok(port_stats) => {
                     wtr.write_field(elapsed.as_millis().to_string())?;
+                    wtr.write_field(unix_ns.to_string())?;
                     for label in port_stats.stats.keys() {
                         if self.keywords.iter().any(|k| label.contains(k)) {
                             if let Some(value) = port_stats.stats.get(label) {
@@ -279,6 +1759,54 @@ impl Logger {
         for wtr in self.port_wtrs.values_mut() {
             wtr.flush()?;
         }
+        for (core, counts) in filter_ctx.drop_stats() {
+            self.drops_wtr.write_field(elapsed.as_millis().to_string())?;
+            self.drops_wtr.write_field(unix_ns.to_string())?;
+            self.drops_wtr.write_field(core.map_or("other".into(), |core| core.to_string()))?;
+            for reason in DropReason::ALL.iter() {
+                self.drops_wtr.write_field(counts.get(*reason).to_string())?;
+            }
+            self.drops_wtr.write_record(None::<&[u8]>)?;
+        }
+        self.drops_wtr.flush()?;
+        for queue in queues {
+            self.queues_wtr.write_record([
+                elapsed.as_millis().to_string(),
+                unix_ns.to_string(),
+                queue.port.to_string(),
+                queue.queue.to_string(),
+                queue.nb_pkts.to_string(),
+                queue.nb_bytes.to_string(),
+                queue.nb_bursts.to_string(),
+                queue.max_burst.to_string(),
+                queue.nb_saturated.to_string(),
+            ])?;
+        }
+        self.queues_wtr.flush()?;
+        for cycles in filter_ctx.core_cycles() {
+            self.cycles_wtr.write_record([
+                elapsed.as_millis().to_string(),
+                unix_ns.to_string(),
+                cycles.core.to_string(),
+                cycles.busy_cycles.to_string(),
+                cycles.idle_cycles.to_string(),
+                cycles.nb_pkts.to_string(),
+            ])?;
+        }
+        self.cycles_wtr.flush()?;
+        for class in filter_ctx.storage_class_stats() {
+            self.classes_wtr.write_record([
+                elapsed.as_millis().to_string(),
+                unix_ns.to_string(),
+                class.queue.to_string(),
+                class.class.to_string(),
+                class.nb_enqueued.to_string(),
+                class.nb_dropped.to_string(),
+                class.backlog.to_string(),
+            ])?;
+        }
+        self.classes_wtr.flush()?;
+        self.log_compiles(elapsed, unix_ns, &filter_ctx.compile_stats())?;
         Ok(())
     }
 }
@@ -292,28 +1820,44 @@ struct AggRxStats {
     good_pkts: u64,
     process_bits: u64,
     process_pkts: u64,
+    /// Received by counting and sampling sinks.
+    bypass_bits: u64,
+    bypass_pkts: u64,
+    /// Received by forwarding sinks.
+    forward_bits: u64,
+    forward_pkts: u64,
     hw_dropped_pkts: u64,
     sw_dropped_pkts: u64,
 }
 
 impl AggRxStats {
-    /// Collect aggregate statistics, display keyword statistics if `keywords` is not `None`
-    fn collect(ports: &BTreeMap<PortId, Vec<RxQueue>>, keywords: &[String]) -> Result<Self> {
+    /// Collect aggregate statistics, display keyword statistics to `output` if `keywords` is not
+    /// `None`
+    fn collect(
+        ports: &BTreeMap<PortId, Vec<RxQueue>>,
+        keywords: &[String],
+        output: &Output,
+    ) -> Result<Self> {
         let mut ingress_bytes = 0;
         let mut ingress_pkts = 0;
         let mut good_bytes = 0;
         let mut good_pkts = 0;
         let mut process_bytes = 0;
         let mut process_pkts = 0;
+        let mut bypass_bytes = 0;
+        let mut bypass_pkts = 0;
+        let mut forward_bytes = 0;
+        let mut forward_pkts = 0;
         let mut hw_dropped_pkts = 0;
         let mut sw_dropped_pkts = 0;
         for (port_id, rx_queues) in ports.iter() {
-            let mut sink_queue = None;
-            for queue in rx_queues {
-                if queue.ty == RxQueueType::Sink {
-                    sink_queue = Some(queue.qid.raw());
-                }
-            }
+            let sink_queues: Vec<(u16, SinkBehavior)> = rx_queues
+                .iter()
+                .filter_map(|queue| match queue.ty {
+                    RxQueueType::Sink(behavior) => Some((queue.qid.raw(), behavior)),
+                    RxQueueType::Receive => None,
+                })
+                .collect();
 
             match PortStats::collect(*port_id) {
                 Ok(port_stats) => {
@@ -351,27 +1895,34 @@ impl AggRxStats {
                     good_bytes += good_bytes_temp;
                     good_pkts += good_pkts_temp;
 
-                    // Process (reached workers)
-                    process_bytes += if let Some(sink) = sink_queue {
+                    // Sinks (bypassed workers), by role
+                    let mut sink_bytes = 0;
+                    let mut sink_pkts = 0;
+                    for (sink, behavior) in sink_queues.iter() {
                         let label = format!("rx_q{}_bytes", sink);
-                        let sink_bytes = match port_stats.stats.get(&label) {
+                        let queue_bytes = match port_stats.stats.get(&label) {
                             Some(v) => *v,
                             None => bail!("Failed retrieving sink_bytes"),
                         };
-                        good_bytes_temp - sink_bytes
-                    } else {
-                        good_bytes_temp
-                    };
-                    process_pkts += if let Some(sink) = sink_queue {
                         let label = format!("rx_q{}_packets", sink);
-                        let sink_pkts = match port_stats.stats.get(&label) {
+                        let queue_pkts = match port_stats.stats.get(&label) {
                             Some(v) => *v,
                             None => bail!("Failed retrieving sink_pkts"),
                         };
-                        good_pkts_temp - sink_pkts
-                    } else {
-                        good_pkts_temp
-                    };
+                        sink_bytes += queue_bytes;
+                        sink_pkts += queue_pkts;
+                        if *behavior == SinkBehavior::Forward {
+                            forward_bytes += queue_bytes;
+                            forward_pkts += queue_pkts;
+                        } else {
+                            bypass_bytes += queue_bytes;
+                            bypass_pkts += queue_pkts;
+                        }
+                    }
+
+                    // Process (reached workers)
+                    process_bytes += good_bytes_temp - sink_bytes;
+                    process_pkts += good_pkts_temp - sink_pkts;
 
                     // dropped
                     hw_dropped_pkts += match port_stats.stats.get("rx_phy_discard_packets") {
@@ -386,7 +1937,7 @@ impl AggRxStats {
                         None => bail!("Failed retrieving sw_dropped_pkts"),
                     };
 
-                    port_stats.display(keywords);
+                    port_stats.display(keywords, output);
                 }
                 Err(error) => bail!(error),
             }
@@ -398,6 +1949,10 @@ impl AggRxStats {
             good_pkts,
             process_bits: (process_bytes + (PSFD_SIZE + IPG_SIZE + FCS_SIZE) * process_pkts) * 8,
             process_pkts,
+            bypass_bits: (bypass_bytes + (PSFD_SIZE + IPG_SIZE + FCS_SIZE) * bypass_pkts) * 8,
+            bypass_pkts,
+            forward_bits: (forward_bytes + (PSFD_SIZE + IPG_SIZE + FCS_SIZE) * forward_pkts) * 8,
+            forward_pkts,
             hw_dropped_pkts,
             sw_dropped_pkts,
         })
@@ -416,6 +1971,16 @@ impl AggRxStats {
         builder.add_record(["Process".into(), format!("{} bps / {} pps",
             (curr_rx.process_bits - prev_rx.process_bits) as f64 / nms * 1000.0,
             (curr_rx.process_pkts - prev_rx.process_pkts) as f64 / nms * 1000.0)]);
+        if curr_rx.bypass_pkts > 0 {
+            builder.add_record(["Bypass".into(), format!("{} bps / {} pps",
+                (curr_rx.bypass_bits - prev_rx.bypass_bits) as f64 / nms * 1000.0,
+                (curr_rx.bypass_pkts - prev_rx.bypass_pkts) as f64 / nms * 1000.0)]);
+        }
+        if curr_rx.forward_pkts > 0 {
+            builder.add_record(["Forward".into(), format!("{} bps / {} pps",
+                (curr_rx.forward_bits - prev_rx.forward_bits) as f64 / nms * 1000.0,
+                (curr_rx.forward_pkts - prev_rx.forward_pkts) as f64 / nms * 1000.0)]);
+        }
         builder.add_record(["Drop".into(), format!("{} pps ({}%)",
             (curr_rx.dropped_pkts() - prev_rx.dropped_pkts()) as f64 / nms * 1000.0,
             100.0
@@ -463,10 +2028,40 @@ struct Throughputs {
     avg_good_pps: f64,
     avg_process_bps: f64,
     avg_process_pps: f64,
+    avg_bypass_bps: f64,
+    avg_bypass_pps: f64,
+    avg_forward_bps: f64,
+    avg_forward_pps: f64,
     hw_dropped_pkts: u64,
     sw_dropped_pkts: u64,
     tot_dropped_pkts: u64,
     percent_dropped: f64,
+    /// Packets not inspected by software, by drop reason.
+    drops: BTreeMap<&'static str, u64>,
+    /// Largest talkers, if heavy-hitter detection is enabled.
+    top_talkers: Option<TalkerStats>,
+    /// Checksum check outcomes.
+    checksums: ChecksumStats,
+    /// Shadow rule set statistics, if one is loaded.
+    shadow: Option<ShadowStats>,
+    /// Flow table occupancy at the end of the run.
+    flow_table: Option<FlowTableStats>,
+    /// Software counters of each RX queue at the end of the run.
+    queues: Vec<QueueStats>,
+    /// Cycles spent by each RX core over the run.
+    cores: Vec<CoreCycles>,
+    /// Counters of each priority class of the storage queues.
+    storage_classes: Vec<ClassStats>,
+    /// Storage usage of each tenant with a quota.
+    storage_quotas: Vec<QuotaStats>,
+    /// Delivery counters of each remote collector.
+    collectors: Vec<ForwardStats>,
+    /// Feature flags at the end of the run.
+    flags: Vec<FlagState>,
+    /// Rates counted by the RX cores, in benchmark mode.
+    benchmark: Option<BenchmarkStats>,
+    /// Pauses of the run.
+    pause: PauseStats,
 }
 
 impl Throughputs {
@@ -479,12 +2074,29 @@ impl Throughputs {
             avg_good_pps: (curr_rx.good_pkts - init_rx.good_pkts) as f64 / ems * 1000.0,
             avg_process_bps: (curr_rx.process_bits - init_rx.process_bits) as f64 / ems * 1000.0,
             avg_process_pps: (curr_rx.process_pkts - init_rx.process_pkts) as f64 / ems * 1000.0,
+            avg_bypass_bps: (curr_rx.bypass_bits - init_rx.bypass_bits) as f64 / ems * 1000.0,
+            avg_bypass_pps: (curr_rx.bypass_pkts - init_rx.bypass_pkts) as f64 / ems * 1000.0,
+            avg_forward_bps: (curr_rx.forward_bits - init_rx.forward_bits) as f64 / ems * 1000.0,
+            avg_forward_pps: (curr_rx.forward_pkts - init_rx.forward_pkts) as f64 / ems * 1000.0,
             hw_dropped_pkts: (curr_rx.hw_dropped_pkts - init_rx.hw_dropped_pkts),
             sw_dropped_pkts: (curr_rx.sw_dropped_pkts - init_rx.sw_dropped_pkts),
             tot_dropped_pkts: (curr_rx.dropped_pkts() - init_rx.dropped_pkts()),
             percent_dropped: 100.0
                 * ((curr_rx.dropped_pkts() - init_rx.dropped_pkts()) as f64
                     / (curr_rx.ingress_pkts - init_rx.ingress_pkts) as f64),
+            drops: BTreeMap::new(),
+            top_talkers: None,
+            checksums: ChecksumStats::default(),
+            shadow: None,
+            flow_table: None,
+            queues: vec![],
+            cores: vec![],
+            storage_classes: vec![],
+            storage_quotas: vec![],
+            collectors: vec![],
+            flags: vec![],
+            benchmark: None,
+            pause: PauseStats::default(),
         }
     }
 
@@ -512,11 +2124,40 @@ impl fmt::Display for Throughputs {
             "AVERAGE Process: {:.3} bps / {:.3} pps",
             self.avg_process_bps, self.avg_process_pps,
         )?;
+        if self.avg_bypass_pps > 0.0 {
+            writeln!(
+                f,
+                "AVERAGE Bypass:  {:.3} bps / {:.3} pps",
+                self.avg_bypass_bps, self.avg_bypass_pps,
+            )?;
+        }
+        if self.avg_forward_pps > 0.0 {
+            writeln!(
+                f,
+                "AVERAGE Forward: {:.3} bps / {:.3} pps",
+                self.avg_forward_bps, self.avg_forward_pps,
+            )?;
+        }
         writeln!(
             f,
             "DROPPED: {} pkts ({}%)",
             self.tot_dropped_pkts, self.percent_dropped,
         )?;
+        if let Some(benchmark) = &self.benchmark {
+            writeln!(
+                f,
+                "BENCHMARK: {} pkts, {} bytes, average {}, peak {}",
+                benchmark.nb_pkts, benchmark.nb_bytes, benchmark.avg, benchmark.peak,
+            )?;
+        }
+        if self.pause.nb_pauses > 0 {
+            writeln!(
+                f,
+                "PAUSED: {} times, {:.3} s",
+                self.pause.nb_pauses,
+                self.pause.paused_time.as_secs_f64(),
+            )?;
+        }
         Ok(())
     }
 }
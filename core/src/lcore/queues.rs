@@ -0,0 +1,174 @@
+//! Software RX queue counters.
+//!
+//! Not all NICs expose per-queue extended statistics, so each RX core also counts what it polls
+//! from each of its queues: packets, bytes, non-empty bursts, and the fullest burst. Each queue is
+//! polled by a single core, whose counters sit on their own cache line so that cores never share
+//! one. The monitor reports the counters next to the hardware statistics.
+//!
+//! ## Fairness
+//! A core polling several queues services them in rounds: each round, every queue may be polled
+//! up to `rx_weight` bursts, its weight (see [PortMap](crate::config::PortMap)), and is left as
+//! soon as it returns a short burst. A hot queue therefore gets at most its share of the core
+//! while the other queues have packets, and unused shares are not carried over. Rounds in which a
+//! queue used its whole share, likely leaving packets queued, are counted as saturated.
+
+use crate::port::RxQueue;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+/// Maximum number of packets received from a queue per poll.
+pub(crate) const RX_BURST_SIZE: u16 = 32;
+
+/// Software counters of an RX queue, written by the core polling it.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+pub(crate) struct QueueCounters {
+    nb_pkts: AtomicU64,
+    nb_bytes: AtomicU64,
+    /// Number of polls that returned packets.
+    nb_bursts: AtomicU64,
+    /// Largest number of packets returned by a poll.
+    max_burst: AtomicU64,
+    /// Number of rounds in which the queue used its whole share.
+    nb_saturated: AtomicU64,
+}
+
+impl QueueCounters {
+    /// Records a poll that returned `nb_pkts` packets of `nb_bytes` bytes in total.
+    #[inline]
+    pub(crate) fn record_burst(&self, nb_pkts: usize, nb_bytes: u64) {
+        if nb_pkts == 0 {
+            return;
+        }
+        // Single writer: plain stores are enough and cheaper than read-modify-write operations
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        self.nb_pkts.store(load(&self.nb_pkts) + nb_pkts as u64, Ordering::Relaxed);
+        self.nb_bytes.store(load(&self.nb_bytes) + nb_bytes, Ordering::Relaxed);
+        self.nb_bursts.store(load(&self.nb_bursts) + 1, Ordering::Relaxed);
+        if nb_pkts as u64 > load(&self.max_burst) {
+            self.max_burst.store(nb_pkts as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a round in which the queue used its whole share.
+    #[inline]
+    pub(crate) fn record_saturated(&self) {
+        let nb_saturated = self.nb_saturated.load(Ordering::Relaxed);
+        self.nb_saturated.store(nb_saturated + 1, Ordering::Relaxed);
+    }
+}
+
+/// Weighted round-robin servicing of the queues polled by a core.
+#[derive(Debug)]
+pub(crate) struct QueueScheduler {
+    /// Number of packets each queue may receive per round.
+    quanta: Vec<usize>,
+    /// Number of packets each queue may still receive in the current round.
+    budgets: Vec<usize>,
+}
+
+impl QueueScheduler {
+    /// Creates the scheduler of queues of weights `weights`, in bursts per round.
+    pub(crate) fn new(weights: &[u32]) -> Self {
+        QueueScheduler {
+            quanta: weights
+                .iter()
+                .map(|weight| (*weight).max(1) as usize * RX_BURST_SIZE as usize)
+                .collect(),
+            budgets: vec![0; weights.len()],
+        }
+    }
+
+    /// Starts the round of queue `index`.
+    #[inline]
+    pub(crate) fn refill(&mut self, index: usize) {
+        self.budgets[index] = self.quanta[index];
+    }
+
+    /// Returns the size of the next burst to poll from queue `index`, `None` once its round is
+    /// over.
+    #[inline]
+    pub(crate) fn next_burst(&self, index: usize) -> Option<u16> {
+        match self.budgets[index] {
+            0 => None,
+            budget => Some(budget.min(RX_BURST_SIZE as usize) as u16),
+        }
+    }
+
+    /// Records that a poll of `burst_size` packets from queue `index` returned `nb_rx` packets.
+    /// Returns whether the queue used its whole share of the round.
+    #[inline]
+    pub(crate) fn consume(&mut self, index: usize, burst_size: u16, nb_rx: usize) -> bool {
+        if nb_rx < burst_size as usize {
+            // Drained, the rest of the share is not carried over
+            self.budgets[index] = 0;
+            return false;
+        }
+        self.budgets[index] = self.budgets[index].saturating_sub(nb_rx);
+        self.budgets[index] == 0
+    }
+}
+
+/// Snapshot of the software counters of an RX queue.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub(crate) struct QueueStats {
+    pub(crate) port: u16,
+    pub(crate) queue: u16,
+    pub(crate) nb_pkts: u64,
+    pub(crate) nb_bytes: u64,
+    pub(crate) nb_bursts: u64,
+    pub(crate) max_burst: u64,
+    pub(crate) nb_saturated: u64,
+}
+
+impl QueueStats {
+    /// Returns the average number of packets per non-empty burst.
+    pub(crate) fn avg_burst(&self) -> f64 {
+        if self.nb_bursts == 0 {
+            return 0.0;
+        }
+        self.nb_pkts as f64 / self.nb_bursts as f64
+    }
+
+    /// Returns the fill of the fullest burst, as a fraction of the burst size.
+    pub(crate) fn max_fill(&self) -> f64 {
+        self.max_burst as f64 / RX_BURST_SIZE as f64
+    }
+}
+
+/// Software counters of all RX queues, registered on initialization.
+#[derive(Debug, Default)]
+pub(crate) struct QueueRegistry {
+    queues: BTreeMap<RxQueue, Arc<QueueCounters>>,
+}
+
+impl QueueRegistry {
+    pub(crate) fn new() -> Self {
+        QueueRegistry::default()
+    }
+
+    /// Returns the counters of `rxqueue`, registering it if needed.
+    pub(crate) fn register(&mut self, rxqueue: RxQueue) -> Arc<QueueCounters> {
+        Arc::clone(self.queues.entry(rxqueue).or_default())
+    }
+
+    /// Returns the counters of every queue, in queue order.
+    pub(crate) fn snapshot(&self) -> Vec<QueueStats> {
+        self.queues
+            .iter()
+            .map(|(rxqueue, counters)| QueueStats {
+                port: rxqueue.pid.raw(),
+                queue: rxqueue.qid.raw(),
+                nb_pkts: counters.nb_pkts.load(Ordering::Relaxed),
+                nb_bytes: counters.nb_bytes.load(Ordering::Relaxed),
+                nb_bursts: counters.nb_bursts.load(Ordering::Relaxed),
+                max_burst: counters.max_burst.load(Ordering::Relaxed),
+                nb_saturated: counters.nb_saturated.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
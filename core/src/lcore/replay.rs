@@ -0,0 +1,206 @@
+//! Replay of pcap files.
+//!
+//! With the `[online.replay]` options of the runtime configuration (see
+//! [ReplayConfig](crate::config::ReplayConfig)), a thread of the main core transmits the packets
+//! of a pcap or pcapng file, e.g. written by the [capture](crate::filter::capture) or a sampling
+//! sink, on a dedicated TX queue of an online port while the runtime runs. Packets are paced with their
+//! original timing, at a fixed rate, or as fast as the TX queue accepts them, and the file can be
+//! replayed several times.
+//!
+//! Packets recorded shorter than their original length, e.g. cut at the snapshot length or
+//! [deduplicated](crate::filter::capture#deduplication), are transmitted as recorded. The replay
+//! stops early when the runtime stops, and does not stop the runtime when done.
+
+use crate::config::{ReplayConfig, ReplayPacing};
+use crate::dpdk;
+use crate::filter::store::StoreReader;
+use crate::memory::mbuf::Mbuf;
+use crate::memory::mempool::Mempool;
+use crate::port::{Port, PortId};
+
+use super::SocketId;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+
+/// Maximum number of packets transmitted at once.
+const TX_BURST_SIZE: usize = 32;
+
+/// Counters of a replay.
+#[derive(Debug, Default)]
+struct ReplayStats {
+    nb_pkts: u64,
+    nb_bytes: u64,
+    /// Number of packets recorded shorter than their original length.
+    nb_truncated: u64,
+    /// Number of packets that could not be copied to an mbuf.
+    nb_failed: u64,
+}
+
+/// Transmits the packets of a pcap file on a TX queue.
+pub(crate) struct Replayer {
+    config: ReplayConfig,
+    port: PortId,
+    queue: u16,
+    mempool: *mut dpdk::rte_mempool,
+    is_running: Arc<AtomicBool>,
+}
+
+// The mempool is thread-safe and outlives the replay thread, which is joined before the runtime
+// releases its mempools.
+unsafe impl Send for Replayer {}
+
+impl Replayer {
+    /// Resolves `config` against the initialized `ports`, transmitting on TX queue `queue` with
+    /// mbufs of the mempool of the port's socket.
+    pub(crate) fn new(
+        config: &ReplayConfig,
+        ports: &BTreeMap<PortId, Port>,
+        queue: u16,
+        mempools: &mut BTreeMap<SocketId, Mempool>,
+        is_running: Arc<AtomicBool>,
+    ) -> Result<Self> {
+        let port = match ports.values().find(|port| port.device == config.port) {
+            Some(port) => port.id,
+            None => bail!("Replay port {} is not an online port", config.port),
+        };
+        let mempool = match mempools.get_mut(&port.socket_id()) {
+            Some(mempool) => mempool.raw_mut() as *mut dpdk::rte_mempool,
+            None => bail!("No mempool on the socket of replay Port {}", port),
+        };
+        Ok(Replayer {
+            config: config.clone(),
+            port,
+            queue,
+            mempool,
+            is_running,
+        })
+    }
+
+    /// Starts replaying on a new thread.
+    pub(crate) fn spawn(self) -> Result<JoinHandle<()>> {
+        Ok(thread::Builder::new()
+            .name("retina-replay".into())
+            .spawn(move || self.run())?)
+    }
+
+    fn run(&self) {
+        log::info!(
+            "Replaying {} on Port {} queue {}, {:?} pacing",
+            self.config.file,
+            self.port,
+            self.queue,
+            self.config.pacing
+        );
+        let mut stats = ReplayStats::default();
+        let mut nb_loops = 0;
+        while self.is_running.load(Ordering::Relaxed)
+            && (self.config.loops == 0 || nb_loops < self.config.loops)
+        {
+            if let Err(error) = self.replay_file(&mut stats) {
+                log::error!("Replay of {} failed: {}", self.config.file, error);
+                break;
+            }
+            nb_loops += 1;
+        }
+        log::info!(
+            "Replayed {} pkts ({} bytes) of {} {} times, {} truncated, {} failed",
+            stats.nb_pkts,
+            stats.nb_bytes,
+            self.config.file,
+            nb_loops,
+            stats.nb_truncated,
+            stats.nb_failed
+        );
+    }
+
+    /// Transmits the packets of the file once.
+    fn replay_file(&self, stats: &mut ReplayStats) -> Result<()> {
+        let mut reader = StoreReader::open(&self.config.file)?;
+        let start = Instant::now();
+        let mut first_ts = None;
+        let mut nb_read: u64 = 0;
+        let mut burst = Vec::with_capacity(TX_BURST_SIZE);
+        while let Some(packet) = reader.next_packet()? {
+            if !self.is_running.load(Ordering::Relaxed) {
+                break;
+            }
+            let due = match self.config.pacing {
+                ReplayPacing::Original => {
+                    let first_ts = *first_ts.get_or_insert(packet.ts);
+                    start + packet.ts.saturating_sub(first_ts)
+                }
+                ReplayPacing::Rate => {
+                    start + Duration::from_secs_f64(nb_read as f64 / self.config.rate.max(1) as f64)
+                }
+                ReplayPacing::Max => start,
+            };
+            nb_read += 1;
+            if due > Instant::now() {
+                // Send what is due before waiting for the next packet
+                self.transmit(&mut burst);
+                wait_until(due);
+            }
+            if packet.data.len() < packet.orig_len {
+                stats.nb_truncated += 1;
+            }
+            match Mbuf::from_bytes(&packet.data, self.mempool) {
+                Ok(mbuf) => {
+                    stats.nb_pkts += 1;
+                    stats.nb_bytes += packet.data.len() as u64;
+                    burst.push(mbuf);
+                }
+                Err(error) => {
+                    log::debug!("Replay mbuf error: {}", error);
+                    stats.nb_failed += 1;
+                }
+            }
+            if burst.len() == TX_BURST_SIZE {
+                self.transmit(&mut burst);
+            }
+        }
+        self.transmit(&mut burst);
+        Ok(())
+    }
+
+    /// Transmits all packets of `burst`, waiting for room in the TX queue. Packets left when the
+    /// runtime stops are freed.
+    fn transmit(&self, burst: &mut Vec<Mbuf>) {
+        let mut ptrs: Vec<*mut dpdk::rte_mbuf> = burst.drain(..).map(Mbuf::into_raw).collect();
+        let mut nb_sent = 0;
+        while nb_sent < ptrs.len() && self.is_running.load(Ordering::Relaxed) {
+            let nb_tx = unsafe {
+                dpdk::rte_eth_tx_burst(
+                    self.port.raw(),
+                    self.queue,
+                    ptrs[nb_sent..].as_mut_ptr(),
+                    (ptrs.len() - nb_sent) as u16,
+                )
+            } as usize;
+            nb_sent += nb_tx;
+            if nb_tx == 0 {
+                thread::yield_now();
+            }
+        }
+        // Packets that were not transmitted are still owned by the replay
+        for ptr in ptrs.drain(nb_sent..) {
+            drop(Mbuf::new_unchecked(ptr));
+        }
+    }
+}
+
+/// Sleeps until shortly before `due`, then spins, so that short gaps keep their timing.
+fn wait_until(due: Instant) {
+    let now = Instant::now();
+    if due > now + Duration::from_millis(1) {
+        thread::sleep(due - now - Duration::from_millis(1));
+    }
+    while Instant::now() < due {
+        std::hint::spin_loop();
+    }
+}
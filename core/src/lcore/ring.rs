@@ -1,5 +1,6 @@
 use crate::dpdk;
 use crate::lcore::SocketId;
+use crate::memory::mbuf::Mbuf;
 
 use anyhow::{bail, Result};
 use std::ffi::{CStr, CString};
@@ -17,12 +18,21 @@ unsafe impl Sync for Ring {}
 
 impl Ring {
     pub(crate) fn new(size: u32, socket_id: SocketId, flags: u32) -> Result<Self> {
+        Ring::with_name(&format!("event_ring_{}", socket_id), size, socket_id, flags)
+    }
+
+    /// Creates a ring named `name`, which must be unique
+    pub(crate) fn with_name(
+        name: &str,
+        size: u32,
+        socket_id: SocketId,
+        flags: u32,
+    ) -> Result<Self> {
         if size == 0 || ((size & size - 1) != 0) {
             bail!("Ring size must be a power of 2");
         }
 
-        let name = format!("event_ring_{}", socket_id);
-        let cname = CString::new(name.clone()).unwrap();
+        let cname = CString::new(name).unwrap();
         log::debug!("Ring size: {}", size);
         let ring = unsafe {
             dpdk::rte_ring_create(
@@ -126,6 +136,44 @@ impl Ring {
         Ok(*obj)
     }
 
+    /// Enqueue as many of `mbufs` as fit onto the ring and return the others (NOT multi-producers
+    /// safe)
+    pub(crate) fn sp_enqueue_burst(&self, mbufs: Vec<Mbuf>) -> Vec<Mbuf> {
+        let ptrs: Vec<*mut c_void> = mbufs
+            .into_iter()
+            .map(|mbuf| mbuf.into_raw() as *mut c_void)
+            .collect();
+        let nb_enqueued = unsafe {
+            dpdk::rte_ring_sp_enqueue_burst(
+                self.raw.as_ptr(),
+                ptrs.as_ptr(),
+                ptrs.len() as c_uint,
+                std::ptr::null_mut(),
+            )
+        };
+        ptrs[nb_enqueued as usize..]
+            .iter()
+            .map(|ptr| Mbuf::new_unchecked(*ptr as *mut dpdk::rte_mbuf))
+            .collect()
+    }
+
+    /// Dequeue up to `nb_mbufs` mbufs from the ring (NOT multi-consumers safe)
+    pub(crate) fn sc_dequeue_burst(&self, nb_mbufs: u16) -> Vec<Mbuf> {
+        let mut ptrs: Vec<*mut c_void> = Vec::with_capacity(nb_mbufs as usize);
+        unsafe {
+            let nb_dequeued = dpdk::rte_ring_sc_dequeue_burst(
+                self.raw.as_ptr(),
+                ptrs.as_mut_ptr(),
+                nb_mbufs as c_uint,
+                std::ptr::null_mut(),
+            );
+            ptrs.set_len(nb_dequeued as usize);
+        }
+        ptrs.into_iter()
+            .map(|ptr| Mbuf::new_unchecked(ptr as *mut dpdk::rte_mbuf))
+            .collect()
+    }
+
     /// Dequeue one object from the ring and return it as `T` (NOT multi-consumers safe)
     pub(crate) fn sc_dequeue<T>(&mut self) -> Result<T> {
         let mut ptr: *mut c_void = std::ptr::null_mut();
@@ -1,10 +1,21 @@
+use super::handoff::Handoff;
+use super::queues::{QueueCounters, QueueScheduler, RX_BURST_SIZE};
+use super::sflow::SflowSampler;
+use super::sink::{SinkQueue, SinkTarget};
 use super::CoreId;
+use crate::config::SflowConfig;
 use crate::dpdk;
+use crate::filter::cycles::CycleCounters;
+use crate::filter::drops::DropReason;
+use crate::filter::pause::PauseMode;
+use crate::filter::trace::TraceEvent;
 use crate::filter::FilterCtx;
 use crate::memory::mbuf::Mbuf;
 use crate::port::{RxQueue, RxQueueType};
+use crate::runtime::notify::Readiness;
 use crate::subscription::*;
 
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
@@ -18,9 +29,28 @@ where
 {
     pub(crate) id: CoreId,
     pub(crate) rxqueues: Vec<RxQueue>,
+    /// Software counters of each queue in `rxqueues`, detached from the monitor if not set.
+    pub(crate) queue_counters: Vec<Arc<QueueCounters>>,
+    /// Servicing weight of each queue in `rxqueues`, `1` if not set (see
+    /// [QueueScheduler](super::queues::QueueScheduler)).
+    pub(crate) weights: Vec<u32>,
+    /// Busy and idle cycles of the RX loop.
+    cycles: Arc<CycleCounters>,
     pub(crate) subscription: Arc<Subscription<'a, S>>,
     pub(crate) filter_ctx: FilterCtx,
+    pub(crate) sflow: Option<SflowConfig>,
+    /// Behavior of each sink queue in `rxqueues`, counting if missing.
+    pub(crate) sinks: BTreeMap<RxQueue, SinkTarget>,
     pub(crate) is_running: Arc<AtomicBool>,
+    /// Notified once the core polls its queues, detached from the runtime if not set.
+    pub(crate) readiness: Readiness,
+    /// Whether receive queues are only counted, see
+    /// [OnlineConfig::benchmark](crate::config::OnlineConfig::benchmark).
+    pub(crate) benchmark: bool,
+    /// Software RSS rings, which receive cores hand their packets off to, or workers poll.
+    pub(crate) handoff: Option<Arc<Handoff>>,
+    /// Index of the worker, if the core processes the packets handed off by receive cores.
+    pub(crate) worker: Option<usize>,
 }
 
 impl<'a, S> RxCore<'a, S>
@@ -32,14 +62,26 @@ where
         rxqueues: Vec<RxQueue>,
         subscription: Arc<Subscription<'a, S>>,
         filter_ctx: &FilterCtx,
+        sflow: Option<SflowConfig>,
         is_running: Arc<AtomicBool>,
     ) -> Self {
+        let mut filter_ctx = filter_ctx.clone();
+        filter_ctx.attach_core(core_id.raw());
         RxCore {
             id: core_id,
+            queue_counters: rxqueues.iter().map(|_| Arc::default()).collect(),
+            weights: vec![1; rxqueues.len()],
+            cycles: filter_ctx.cycle_counters(core_id.raw()),
             rxqueues,
             subscription,
-            filter_ctx: filter_ctx.clone(),
+            filter_ctx,
+            sflow,
+            sinks: BTreeMap::new(),
             is_running,
+            readiness: Readiness::new(),
+            benchmark: false,
+            handoff: None,
+            worker: None,
         }
     }
 
@@ -61,10 +103,26 @@ where
         }
     }
 
+    /// Returns the next burst of at most `burst_size` packets of `rxqueue`, handed off by its
+    /// receive core if a worker.
+    #[inline]
+    fn poll(&self, rxqueue: &RxQueue, burst_size: u16) -> Vec<Mbuf> {
+        match (&self.handoff, self.worker) {
+            (Some(handoff), Some(worker)) => handoff.poll(rxqueue, worker, burst_size),
+            _ => self.rx_burst(rxqueue, burst_size),
+        }
+    }
+
     pub(crate) fn rx_loop(&self) {
         // TODO: need check to enforce that each core only has same queue types
         if self.rxqueues[0].ty == RxQueueType::Receive {
-            self.rx_process();
+            if self.benchmark {
+                self.rx_count();
+            } else if let (Some(handoff), None) = (&self.handoff, self.worker) {
+                self.rx_dispatch(handoff);
+            } else {
+                self.rx_process();
+            }
         } else {
             self.rx_sink();
         }
@@ -79,25 +137,113 @@ where
 
         let mut nb_pkts = 0;
         let mut nb_bytes = 0;
+        let mut nb_paused = 0;
+        let (mut consumers_generation, mut consumers) = self.filter_ctx.consumers().snapshot();
+        let (mut stages_generation, mut stages) = self.filter_ctx.pipeline().snapshot();
+        let mut scheduler = QueueScheduler::new(&self.weights);
+        let mut sampler = self.sflow.as_ref().and_then(|cfg| {
+            match SflowSampler::new(cfg, self.id) {
+                Ok(sampler) => Some(sampler),
+                Err(error) => {
+                    log::error!("Core {} sFlow export disabled: {}", self.id, error);
+                    None
+                }
+            }
+        });
 
+        self.readiness.core_polling();
+        let mut iter_start = unsafe { dpdk::rte_rdtsc() };
         while self.is_running.load(Ordering::Relaxed) {
-            for rxqueue in self.rxqueues.iter() {
-                let mbufs: Vec<Mbuf> = self.rx_burst(rxqueue, 32);
-                for mbuf in mbufs.into_iter() {
-                    log::debug!("{:#?}", mbuf);
-                    log::debug!("Mark: {}", mbuf.mark());
-                    log::debug!("RSS Hash: 0x{:x}", mbuf.rss_hash());
-                    log::debug!(
-                        "Queue ID: {}, Port ID: {}, Core ID: {}",
-                        rxqueue.qid,
-                        rxqueue.pid,
-                        self.id,
-                    );
-                    nb_pkts += 1;
-                    nb_bytes += mbuf.data_len() as u64;
-                    S::process_packet(mbuf, &self.filter_ctx, &self.subscription);
+            // Picked up even when idle, so that rule updates are confirmed on all cores
+            self.filter_ctx.refresh_rules();
+            if self.filter_ctx.consumers().generation() != consumers_generation {
+                (consumers_generation, consumers) = self.filter_ctx.consumers().snapshot();
+            }
+            if self.filter_ctx.pipeline().generation() != stages_generation {
+                (stages_generation, stages) = self.filter_ctx.pipeline().snapshot();
+            }
+            let paused = self.filter_ctx.pause_mode();
+            let mut nb_polled = 0;
+            for (index, (rxqueue, counters)) in
+                self.rxqueues.iter().zip(self.queue_counters.iter()).enumerate()
+            {
+                self.filter_ctx.set_rx_queue(rxqueue.qid.raw());
+                scheduler.refill(index);
+                while let Some(burst_size) = scheduler.next_burst(index) {
+                    let mbufs: Vec<Mbuf> = self.poll(rxqueue, burst_size);
+                    let nb_rx = mbufs.len();
+                    nb_polled += nb_rx;
+                    if scheduler.consume(index, burst_size, nb_rx) {
+                        counters.record_saturated();
+                    }
+                    if let Some(mode) = paused {
+                        let burst_bytes = mbufs.iter().map(|mbuf| mbuf.data_len() as u64).sum();
+                        counters.record_burst(nb_rx, burst_bytes);
+                        if mode != PauseMode::Drop {
+                            for _ in 0..nb_rx {
+                                self.filter_ctx.record_drop(DropReason::Paused);
+                            }
+                        }
+                        nb_paused += nb_rx as u64;
+                        continue;
+                    }
+                    let mut burst_bytes = 0;
+                    let mut batch = Vec::with_capacity(mbufs.len());
+                    for mbuf in mbufs.into_iter() {
+                        log::debug!("{:#?}", mbuf);
+                        log::debug!("Mark: {}", mbuf.mark());
+                        log::debug!("RSS Hash: 0x{:x}", mbuf.rss_hash());
+                        log::debug!(
+                            "Queue ID: {}, Port ID: {}, Core ID: {}",
+                            rxqueue.qid,
+                            rxqueue.pid,
+                            self.id,
+                        );
+                        nb_pkts += 1;
+                        nb_bytes += mbuf.data_len() as u64;
+                        burst_bytes += mbuf.data_len() as u64;
+                        self.filter_ctx.trace(|| TraceEvent::Packet {
+                            port: rxqueue.pid.raw(),
+                            queue: rxqueue.qid.raw(),
+                            len: mbuf.data_len(),
+                        });
+                        let eth = self.filter_ctx.rx_ethernet(&mbuf);
+                        if self.filter_ctx.rx_vlan_actions(eth.as_ref()).is_ignored() {
+                            self.filter_ctx.record_drop(DropReason::VlanPolicy);
+                            continue;
+                        }
+                        if let Some(sampler) = &mut sampler {
+                            sampler.sample(&mbuf, rxqueue);
+                        }
+                        self.filter_ctx.sample_talkers(&mbuf);
+                        if let Some(eth) = &eth {
+                            self.filter_ctx.observe_neighbors(eth);
+                        }
+                        alloc_start!(a0);
+                        for (_, consumer) in consumers.iter() {
+                            consumer(&mbuf);
+                        }
+                        alloc_record!(self.subscription.timers, "consumers", a0);
+                        alloc_start!(a1);
+                        let keep = stages.iter().all(|stage| stage.run(&mbuf, &self.filter_ctx));
+                        alloc_record!(self.subscription.timers, "pipeline", a1);
+                        if !keep {
+                            self.filter_ctx.record_drop(DropReason::Stage);
+                            continue;
+                        }
+                        batch.push(mbuf);
+                    }
+                    counters.record_burst(nb_rx, burst_bytes);
+                    if !batch.is_empty() {
+                        alloc_start!(a0);
+                        S::process_batch(batch, &self.filter_ctx, &self.subscription);
+                        alloc_record!(self.subscription.timers, "process", a0);
+                    }
                 }
             }
+            let now = unsafe { dpdk::rte_rdtsc() };
+            self.cycles.record(now - iter_start, nb_polled);
+            iter_start = now;
         }
 
         log::info!(
@@ -107,22 +253,138 @@ where
             nb_pkts,
             nb_bytes
         );
+        if let Some(sampler) = &sampler {
+            log::info!("Core {} sFlow samples dropped: {}", self.id, sampler.nb_dropped());
+        }
+        if nb_paused > 0 {
+            log::info!("Core {} received {} pkts while paused", self.id, nb_paused);
+        }
     }
 
-    fn rx_sink(&self) {
+    /// Hands received packets off to the software RSS workers, without processing them.
+    fn rx_dispatch(&self, handoff: &Handoff) {
         log::info!(
-            "Launched SINK on core {}, polling {}",
+            "Launched DISPATCH on core {}, polling {}",
+            self.id,
+            self.rxqueues.iter().format(", "),
+        );
+
+        let mut nb_pkts = 0;
+        let mut nb_dropped = 0;
+        let mut scheduler = QueueScheduler::new(&self.weights);
+        self.readiness.core_polling();
+        let mut iter_start = unsafe { dpdk::rte_rdtsc() };
+        while self.is_running.load(Ordering::Relaxed) {
+            let mut nb_polled = 0;
+            for (index, (rxqueue, counters)) in
+                self.rxqueues.iter().zip(self.queue_counters.iter()).enumerate()
+            {
+                scheduler.refill(index);
+                while let Some(burst_size) = scheduler.next_burst(index) {
+                    let mbufs: Vec<Mbuf> = self.rx_burst(rxqueue, burst_size);
+                    if scheduler.consume(index, burst_size, mbufs.len()) {
+                        counters.record_saturated();
+                    }
+                    if mbufs.is_empty() {
+                        continue;
+                    }
+                    nb_polled += mbufs.len();
+                    let burst_bytes = mbufs.iter().map(|mbuf| mbuf.data_len() as u64).sum();
+                    counters.record_burst(mbufs.len(), burst_bytes);
+                    nb_pkts += mbufs.len() as u64;
+                    let nb_full = handoff.dispatch(rxqueue, mbufs, &self.filter_ctx);
+                    for _ in 0..nb_full {
+                        self.filter_ctx.record_drop(DropReason::HandoffFull);
+                    }
+                    nb_dropped += nb_full as u64;
+                }
+            }
+            let now = unsafe { dpdk::rte_rdtsc() };
+            self.cycles.record(now - iter_start, nb_polled);
+            iter_start = now;
+        }
+
+        log::info!(
+            "Core {} handed off {} pkts from {}, {} dropped on full rings",
+            self.id,
+            nb_pkts,
+            self.rxqueues.iter().format(", "),
+            nb_dropped
+        );
+    }
+
+    /// Counts and frees received packets, without processing them.
+    fn rx_count(&self) {
+        log::info!(
+            "Launched benchmark RX on core {}, polling {}",
             self.id,
             self.rxqueues.iter().format(", "),
         );
 
         let mut nb_pkts = 0;
         let mut nb_bytes = 0;
+        let mut scheduler = QueueScheduler::new(&self.weights);
+        self.readiness.core_polling();
+        let mut iter_start = unsafe { dpdk::rte_rdtsc() };
+        while self.is_running.load(Ordering::Relaxed) {
+            let mut nb_polled = 0;
+            for (index, (rxqueue, counters)) in
+                self.rxqueues.iter().zip(self.queue_counters.iter()).enumerate()
+            {
+                scheduler.refill(index);
+                while let Some(burst_size) = scheduler.next_burst(index) {
+                    let mbufs: Vec<Mbuf> = self.rx_burst(rxqueue, burst_size);
+                    if scheduler.consume(index, burst_size, mbufs.len()) {
+                        counters.record_saturated();
+                    }
+                    nb_polled += mbufs.len();
+                    let burst_bytes = mbufs.iter().map(|mbuf| mbuf.data_len() as u64).sum();
+                    counters.record_burst(mbufs.len(), burst_bytes);
+                    nb_pkts += mbufs.len() as u64;
+                    nb_bytes += burst_bytes;
+                }
+            }
+            let now = unsafe { dpdk::rte_rdtsc() };
+            self.cycles.record(now - iter_start, nb_polled);
+            iter_start = now;
+        }
+
+        log::info!(
+            "Core {} total recv from {}: {} pkts, {} bytes",
+            self.id,
+            self.rxqueues.iter().format(", "),
+            nb_pkts,
+            nb_bytes
+        );
+    }
 
+    fn rx_sink(&self) {
+        log::info!(
+            "Launched SINK on core {}, polling {}",
+            self.id,
+            self.rxqueues.iter().format(", "),
+        );
+
+        let mut queues: Vec<(RxQueue, SinkQueue, &QueueCounters)> = self
+            .rxqueues
+            .iter()
+            .zip(self.queue_counters.iter())
+            .map(|(rxqueue, counters)| {
+                let target = self.sinks.get(rxqueue).unwrap_or(&SinkTarget::Count);
+                (*rxqueue, SinkQueue::new(target, &self.filter_ctx), counters.as_ref())
+            })
+            .collect();
+
+        self.readiness.core_polling();
+        let mut iter_start = unsafe { dpdk::rte_rdtsc() };
         while self.is_running.load(Ordering::Relaxed) {
-            for rxqueue in self.rxqueues.iter() {
-                let mbufs: Vec<Mbuf> = self.rx_burst(rxqueue, 32);
-                for mbuf in mbufs.into_iter() {
+            let mut nb_polled = 0;
+            for (rxqueue, queue, counters) in queues.iter_mut() {
+                let mbufs: Vec<Mbuf> = self.rx_burst(rxqueue, RX_BURST_SIZE);
+                nb_polled += mbufs.len();
+                let burst_bytes = mbufs.iter().map(|mbuf| mbuf.data_len() as u64).sum();
+                counters.record_burst(mbufs.len(), burst_bytes);
+                for mbuf in mbufs.iter() {
                     log::debug!("RSS Hash: 0x{:x}", mbuf.rss_hash());
                     log::debug!(
                         "Queue ID: {}, Port ID: {}, Core ID: {}",
@@ -130,17 +392,25 @@ where
                         rxqueue.pid,
                         self.id,
                     );
-                    nb_pkts += 1;
-                    nb_bytes += mbuf.data_len() as u64;
+                }
+                if !mbufs.is_empty() {
+                    queue.handle(mbufs);
                 }
             }
+            let now = unsafe { dpdk::rte_rdtsc() };
+            self.cycles.record(now - iter_start, nb_polled);
+            iter_start = now;
+        }
+        for (rxqueue, queue, _) in queues.iter_mut() {
+            queue.flush();
+            log::info!(
+                "Sink Core {} total recv from {}: {} pkts, {} bytes, {}",
+                self.id,
+                rxqueue,
+                queue.nb_pkts,
+                queue.nb_bytes,
+                queue.describe()
+            );
         }
-        log::info!(
-            "Sink Core {} total recv from {}: {} pkts, {} bytes",
-            self.id,
-            self.rxqueues.iter().format(", "),
-            nb_pkts,
-            nb_bytes
-        );
     }
 }
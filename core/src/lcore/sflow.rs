@@ -0,0 +1,159 @@
+//! Sampled raw packet export in sFlow version 5 format.
+//!
+//! Each RX core samples 1-in-N received packets at random and sends the first bytes of every
+//! sampled frame to the collector as a flow sample with a raw packet header record. The sub-agent
+//! ID of a datagram is the RX core ID, and the input interface is the DPDK port ID.
+
+use super::CoreId;
+use crate::config::SflowConfig;
+use crate::dpdk;
+use crate::memory::mbuf::Mbuf;
+use crate::port::RxQueue;
+
+use std::cmp;
+use std::net::{IpAddr, UdpSocket};
+use std::time::Instant;
+
+use anyhow::Result;
+
+const SFLOW_VERSION: u32 = 5;
+const ADDRESS_IPV4: u32 = 1;
+const ADDRESS_IPV6: u32 = 2;
+/// Enterprise 0, format 1
+const FLOW_SAMPLE: u32 = 1;
+/// Enterprise 0, format 1
+const RAW_PACKET_HEADER: u32 = 1;
+const HEADER_PROTOCOL_ETHERNET: u32 = 1;
+
+/// Per-core packet sampler and sFlow exporter.
+#[derive(Debug)]
+pub(crate) struct SflowSampler {
+    socket: UdpSocket,
+    agent: IpAddr,
+    sub_agent_id: u32,
+    sampling_rate: u32,
+    header_size: usize,
+    start: Instant,
+    rng: u64,
+    /// Packets remaining until the next sample.
+    skip: u32,
+    /// Total number of packets seen.
+    sample_pool: u32,
+    nb_samples: u32,
+    nb_datagrams: u32,
+    nb_dropped: u64,
+}
+
+impl SflowSampler {
+    pub(crate) fn new(config: &SflowConfig, core_id: CoreId) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(&config.collector)?;
+        socket.set_nonblocking(true)?;
+        let agent = match config.agent {
+            Some(agent) => agent,
+            None => socket.local_addr()?.ip(),
+        };
+        let mut sampler = SflowSampler {
+            socket,
+            agent,
+            sub_agent_id: core_id.raw(),
+            sampling_rate: cmp::max(config.sampling_rate, 1),
+            header_size: config.header_size,
+            start: Instant::now(),
+            rng: unsafe { dpdk::rte_rdtsc() } | 1,
+            skip: 0,
+            sample_pool: 0,
+            nb_samples: 0,
+            nb_datagrams: 0,
+            nb_dropped: 0,
+        };
+        sampler.skip = sampler.next_skip();
+        Ok(sampler)
+    }
+
+    /// Returns a random skip count with mean `sampling_rate`.
+    fn next_skip(&mut self) -> u32 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng % (2 * self.sampling_rate as u64 - 1)) as u32 + 1
+    }
+
+    /// Counts `mbuf` towards the sampling pool and exports it if it is sampled.
+    #[inline]
+    pub(crate) fn sample(&mut self, mbuf: &Mbuf, rxqueue: &RxQueue) {
+        self.sample_pool = self.sample_pool.wrapping_add(1);
+        self.skip -= 1;
+        if self.skip > 0 {
+            return;
+        }
+        self.skip = self.next_skip();
+        self.nb_samples = self.nb_samples.wrapping_add(1);
+        let datagram = self.encode(mbuf, rxqueue);
+        if self.socket.send(&datagram).is_err() {
+            self.nb_dropped += 1;
+        }
+    }
+
+    /// Returns the number of samples that could not be sent.
+    pub(crate) fn nb_dropped(&self) -> u64 {
+        self.nb_dropped
+    }
+
+    /// Encodes a datagram with a single flow sample of `mbuf`.
+    fn encode(&mut self, mbuf: &Mbuf, rxqueue: &RxQueue) -> Vec<u8> {
+        let data = mbuf.data();
+        let header = &data[..cmp::min(data.len(), self.header_size)];
+        let padding = (4 - header.len() % 4) % 4;
+        let input = rxqueue.pid.raw() as u32;
+
+        let mut record = Vec::with_capacity(16 + header.len() + padding);
+        put_u32(&mut record, HEADER_PROTOCOL_ETHERNET);
+        put_u32(&mut record, data.len() as u32);
+        // Frame check sequence is stripped by the NIC
+        put_u32(&mut record, 0);
+        put_u32(&mut record, header.len() as u32);
+        record.extend_from_slice(header);
+        record.resize(record.len() + padding, 0);
+
+        let mut sample = Vec::with_capacity(40 + record.len());
+        put_u32(&mut sample, self.nb_samples);
+        put_u32(&mut sample, input);
+        put_u32(&mut sample, self.sampling_rate);
+        put_u32(&mut sample, self.sample_pool);
+        put_u32(&mut sample, 0);
+        put_u32(&mut sample, input);
+        put_u32(&mut sample, 0);
+        put_u32(&mut sample, 1);
+        put_u32(&mut sample, RAW_PACKET_HEADER);
+        put_u32(&mut sample, record.len() as u32);
+        sample.extend_from_slice(&record);
+
+        self.nb_datagrams = self.nb_datagrams.wrapping_add(1);
+        let mut datagram = Vec::with_capacity(44 + sample.len());
+        put_u32(&mut datagram, SFLOW_VERSION);
+        match self.agent {
+            IpAddr::V4(addr) => {
+                put_u32(&mut datagram, ADDRESS_IPV4);
+                datagram.extend_from_slice(&addr.octets());
+            }
+            IpAddr::V6(addr) => {
+                put_u32(&mut datagram, ADDRESS_IPV6);
+                datagram.extend_from_slice(&addr.octets());
+            }
+        }
+        put_u32(&mut datagram, self.sub_agent_id);
+        put_u32(&mut datagram, self.nb_datagrams);
+        put_u32(&mut datagram, self.start.elapsed().as_millis() as u32);
+        put_u32(&mut datagram, 1);
+        put_u32(&mut datagram, FLOW_SAMPLE);
+        put_u32(&mut datagram, sample.len() as u32);
+        datagram.extend_from_slice(&sample);
+        datagram
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
@@ -0,0 +1,213 @@
+//! Sink queue behaviors.
+//!
+//! Sink queues receive the RSS buckets that are not polled by receive queues, and bypass the
+//! processing pipeline. Depending on its [SinkBehavior](crate::config::SinkBehavior), a sink
+//! counts and drops its packets, also writes one in `sample_rate` of them to a pcap file, or
+//! transmits them on a TX queue of another port. Each forwarding sink has its own TX queue on the
+//! destination port, so sink cores never share a TX queue. Forwarded packets are rewritten by the
+//! [egress rewrite](crate::filter::rewrite) of the destination port, if any.
+
+use crate::config::{PortMap, SinkBehavior, SinkConfig};
+use crate::dpdk;
+use crate::filter::rewrite::PortRewrite;
+use crate::filter::store::PacketMeta;
+use crate::filter::tap::{self, TapRecord};
+use crate::filter::FilterCtx;
+use crate::memory::mbuf::Mbuf;
+use crate::port::{Port, PortId};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+/// Snapshot length of sampled packets.
+const SAMPLE_SNAPLEN: usize = 65535;
+
+/// Returns the number of sinks of `port_maps` forwarding to `device`.
+pub(crate) fn nb_forwarding(port_maps: &[PortMap], device: &str) -> u16 {
+    port_maps
+        .iter()
+        .flat_map(|port_map| port_map.sink_configs())
+        .filter(|sink| {
+            sink.behavior == SinkBehavior::Forward && sink.forward_port.as_deref() == Some(device)
+        })
+        .count() as u16
+}
+
+/// Resolved behavior of a sink queue.
+#[derive(Debug, Clone)]
+pub(crate) enum SinkTarget {
+    Count,
+    Sample { path: PathBuf, rate: u64 },
+    Forward {
+        port: PortId,
+        queue: u16,
+        device: String,
+    },
+}
+
+impl SinkTarget {
+    /// Resolves `config` against the initialized `ports`, assigning the next TX queue of the
+    /// destination port from `next_txq` to forwarding sinks.
+    pub(crate) fn new(
+        config: &SinkConfig,
+        ports: &BTreeMap<PortId, Port>,
+        next_txq: &mut BTreeMap<PortId, u16>,
+    ) -> Result<Self> {
+        match config.behavior {
+            SinkBehavior::Count => Ok(SinkTarget::Count),
+            SinkBehavior::Sample => match &config.sample_path {
+                Some(path) => Ok(SinkTarget::Sample {
+                    path: PathBuf::from(path),
+                    rate: config.sample_rate.max(1),
+                }),
+                None => bail!("Sink on core {} samples without a `sample_path`", config.core),
+            },
+            SinkBehavior::Forward => {
+                let device = match &config.forward_port {
+                    Some(device) => device,
+                    None => bail!("Sink on core {} forwards without a `forward_port`", config.core),
+                };
+                let port = match ports.values().find(|port| &port.device == device) {
+                    Some(port) => port.id,
+                    None => bail!("Sink forward port {} is not an online port", device),
+                };
+                let txq = next_txq.entry(port).or_insert(0);
+                let queue = *txq;
+                *txq += 1;
+                Ok(SinkTarget::Forward {
+                    port,
+                    queue,
+                    device: device.clone(),
+                })
+            }
+        }
+    }
+}
+
+/// Per-queue state of a sink core.
+pub(crate) struct SinkQueue {
+    target: SinkTarget,
+    writer: Option<BufWriter<File>>,
+    /// Egress rewrite of the destination port of forwarding sinks.
+    rewrite: Option<Arc<PortRewrite>>,
+    pub(crate) nb_pkts: u64,
+    pub(crate) nb_bytes: u64,
+    /// Number of packets written to the sample file or transmitted.
+    nb_handled: u64,
+    /// Number of packets that could not be transmitted because the TX queue was full.
+    nb_dropped: u64,
+}
+
+impl SinkQueue {
+    /// Opens the sample file of sampling sinks. Falls back to counting if it cannot be created.
+    pub(crate) fn new(target: &SinkTarget, filter_ctx: &FilterCtx) -> Self {
+        let mut target = target.clone();
+        let mut writer = None;
+        let rewrite = match &target {
+            SinkTarget::Forward { device, .. } => filter_ctx.egress_rewrite(device),
+            _ => None,
+        };
+        if let SinkTarget::Sample { path, .. } = &target {
+            let file = File::create(path).map(BufWriter::new).and_then(|mut writer| {
+                tap::write_header(&mut writer, SAMPLE_SNAPLEN)?;
+                Ok(writer)
+            });
+            match file {
+                Ok(file) => writer = Some(file),
+                Err(error) => {
+                    log::error!("Sink sample {} open error: {}", path.display(), error);
+                    target = SinkTarget::Count;
+                }
+            }
+        }
+        SinkQueue {
+            target,
+            writer,
+            rewrite,
+            nb_pkts: 0,
+            nb_bytes: 0,
+            nb_handled: 0,
+            nb_dropped: 0,
+        }
+    }
+
+    /// Handles a burst of packets received on the sink queue.
+    pub(crate) fn handle(&mut self, mbufs: Vec<Mbuf>) {
+        self.nb_pkts += mbufs.len() as u64;
+        self.nb_bytes += mbufs.iter().map(|mbuf| mbuf.data_len() as u64).sum::<u64>();
+        match self.target {
+            SinkTarget::Count => (),
+            SinkTarget::Sample { rate, .. } => {
+                let first = self.nb_pkts - mbufs.len() as u64;
+                for (i, mbuf) in mbufs.iter().enumerate() {
+                    if (first + i as u64) % rate == 0 {
+                        self.sample(mbuf);
+                    }
+                }
+            }
+            SinkTarget::Forward { port, queue, .. } => {
+                let mbufs = match &self.rewrite {
+                    Some(rewrite) => rewrite.apply(mbufs),
+                    None => mbufs,
+                };
+                let mut ptrs: Vec<*mut dpdk::rte_mbuf> =
+                    mbufs.into_iter().map(Mbuf::into_raw).collect();
+                let nb_tx = unsafe {
+                    dpdk::rte_eth_tx_burst(port.raw(), queue, ptrs.as_mut_ptr(), ptrs.len() as u16)
+                } as usize;
+                self.nb_handled += nb_tx as u64;
+                self.nb_dropped += (ptrs.len() - nb_tx) as u64;
+                // Packets that were not transmitted are still owned by the sink
+                for ptr in ptrs.drain(nb_tx..) {
+                    drop(Mbuf::new_unchecked(ptr));
+                }
+            }
+        }
+    }
+
+    fn sample(&mut self, mbuf: &Mbuf) {
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => return,
+        };
+        let ts = Duration::from_nanos(mbuf.timestamp());
+        // Samples are pcap, which has no room for metadata
+        let record = TapRecord::new(ts, mbuf, SAMPLE_SNAPLEN, PacketMeta::default());
+        match tap::write_record(writer, &record) {
+            Ok(_) => self.nb_handled += 1,
+            Err(error) => {
+                log::error!("Sink sample write error: {}", error);
+                self.writer = None;
+            }
+        }
+    }
+
+    /// Flushes the sample file.
+    pub(crate) fn flush(&mut self) {
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(error) = writer.flush() {
+                log::error!("Sink sample write error: {}", error);
+            }
+        }
+    }
+
+    /// Returns a description of what the sink did with its packets, for logs.
+    pub(crate) fn describe(&self) -> String {
+        match &self.target {
+            SinkTarget::Count => "counted".to_string(),
+            SinkTarget::Sample { path, .. } => {
+                format!("{} sampled to {}", self.nb_handled, path.display())
+            }
+            SinkTarget::Forward { port, queue, .. } => format!(
+                "{} forwarded to Port {} queue {}, {} dropped",
+                self.nb_handled, port, queue, self.nb_dropped
+            ),
+        }
+    }
+}
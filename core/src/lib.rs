@@ -44,19 +44,26 @@
 
 #[macro_use]
 mod timing;
+pub mod analysis;
+pub mod bridge;
 pub mod config;
 #[doc(hidden)]
 #[allow(clippy::all)]
 mod dpdk;
+pub mod hooks;
 mod lcore;
+pub mod logging;
 mod memory;
+pub mod output;
 mod port;
 pub mod protocols;
 mod runtime;
 pub mod subscription;
+pub mod testing;
+pub mod timebase;
 pub mod utils;
 pub mod filter;
 pub use self::memory::mbuf::Mbuf;
-pub use self::runtime::Runtime;
+pub use self::runtime::{Readiness, Runtime};
 
 pub use dpdk::rte_rdtsc;
@@ -0,0 +1,304 @@
+//! Runtime log filtering.
+//!
+//! Debug logging on the packet path is unusable at line rate, and restarting to change the log
+//! level loses all flow state. Applications that install their logger with [init](init) can change
+//! the log level and per-module filters at any time with [set_filter](set_filter) or
+//! [FilterCtx::set_log_filter](crate::filter::FilterCtx::set_log_filter).
+//!
+//! Filters use the `env_logger` syntax: a comma-separated list of `module=level` directives and an
+//! optional default level, e.g. `warn,retina_core::filter=debug`. A record is filtered by the
+//! directive of the longest module path that prefixes its target, or by the default level, `error`
+//! if not set.
+//!
+//! Per-packet statements should use [log_limited](crate::log_limited), which emits at most one
+//! record per interval at each call site and reports how many records were suppressed.
+//!
+//! The last records let through are also kept in memory, and written to
+//! [support bundles](crate::filter::bundle).
+//!
+//! ## Example
+//! ```
+//! let logger = env_logger::Builder::new().filter_level(LevelFilter::Trace).build();
+//! logging::init(Box::new(logger), "info").unwrap();
+//! // Later, e.g. on operator request
+//! filter_ctx.set_log_filter("info,retina_core::filter=debug").unwrap();
+//! log_limited!(Duration::from_secs(1), Level::Debug, "Dropped packet on {}", rxqueue);
+//! ```
+//!
+//! ## Remarks
+//! The wrapped logger should not filter records itself, otherwise it still drops the records let
+//! through by the filter. Without [init](init), setting a filter only sets the maximum level. Debug
+//! and trace statements of Retina are compiled out of release builds, which enable the
+//! `release_max_level_info` feature of `log`.
+
+use crate::timebase;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{Local, TimeZone};
+use log::{LevelFilter, Log, Metadata, Record};
+
+#[doc(hidden)]
+pub use log as __log;
+pub use log::Level;
+
+static FILTER: RwLock<LogFilter> = RwLock::new(LogFilter::new());
+
+/// Number of records kept in memory.
+const NB_RECENT: usize = 1000;
+
+/// Last records let through, oldest first.
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Directive {
+    /// Module path prefix, `None` for the default level.
+    module: Option<String>,
+    level: LevelFilter,
+}
+
+/// Log level and per-module filters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogFilter {
+    directives: Vec<Directive>,
+}
+
+impl LogFilter {
+    /// Creates a filter letting through errors only.
+    pub const fn new() -> Self {
+        LogFilter { directives: Vec::new() }
+    }
+
+    /// Returns the level of records let through for `target`.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        // Length of the matched module, the default level matching with length 0
+        let mut best: Option<(usize, LevelFilter)> = None;
+        for directive in self.directives.iter() {
+            let len = match &directive.module {
+                Some(module) if is_prefix(module, target) => module.len() + 1,
+                Some(_) => continue,
+                None => 0,
+            };
+            // Later directives win over earlier ones of the same module
+            if best.map_or(true, |(longest, _)| len >= longest) {
+                best = Some((len, directive.level));
+            }
+        }
+        best.map_or(LevelFilter::Error, |(_, level)| level)
+    }
+
+    /// Returns whether records of `level` for `target` are let through.
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        level <= self.level(target)
+    }
+
+    /// Returns the most verbose level of the filter.
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|directive| directive.level)
+            .fold(LevelFilter::Error, Ord::max)
+    }
+}
+
+/// Returns whether `module` is `target` or one of its parent modules.
+fn is_prefix(module: &str, target: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut directives = vec![];
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let directive = match directive.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim();
+                    if module.is_empty() {
+                        bail!("Missing module in log directive {}", directive);
+                    }
+                    let level = LevelFilter::from_str(level.trim())
+                        .map_err(|_| anyhow!("Invalid level in log directive {}", directive))?;
+                    Directive {
+                        module: Some(module.to_string()),
+                        level,
+                    }
+                }
+                None => match LevelFilter::from_str(directive) {
+                    Ok(level) => Directive {
+                        module: None,
+                        level,
+                    },
+                    // A module without level enables all its records
+                    Err(_) => Directive {
+                        module: Some(directive.to_string()),
+                        level: LevelFilter::Trace,
+                    },
+                },
+            };
+            directives.push(directive);
+        }
+        Ok(LogFilter { directives })
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, directive) in self.directives.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ",")?;
+            }
+            let level = directive.level.as_str().to_lowercase();
+            match &directive.module {
+                Some(module) => write!(f, "{}={}", module, level)?,
+                None => write!(f, "{}", level)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Logger forwarding the records let through by the current filter.
+struct FilteredLogger {
+    inner: Box<dyn Log>,
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTER.read().unwrap().enabled(metadata.target(), metadata.level())
+            && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if FILTER.read().unwrap().enabled(record.target(), record.level()) {
+            self.inner.log(record);
+            let line = format!(
+                "{} {:<5} {}: {}",
+                Local
+                    .timestamp_nanos(timebase::now_ns() as i64)
+                    .format("%Y-%m-%dT%H:%M:%S%.3f"),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            let mut recent = RECENT.lock().unwrap();
+            if recent.len() == NB_RECENT {
+                recent.pop_front();
+            }
+            recent.push_back(line);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs `logger` as the global logger behind a filter, initially set to `filter`. Fails if the
+/// filter is invalid or a global logger is already installed.
+pub fn init(logger: Box<dyn Log>, filter: &str) -> Result<()> {
+    let filter: LogFilter = filter.parse()?;
+    let logger: &'static FilteredLogger = Box::leak(Box::new(FilteredLogger { inner: logger }));
+    log::set_logger(logger).map_err(|error| anyhow!("Failed to install logger: {}", error))?;
+    apply(filter);
+    Ok(())
+}
+
+/// Replaces the log filter with `filter`. The current filter is kept if `filter` is invalid.
+pub fn set_filter(filter: &str) -> Result<()> {
+    let filter: LogFilter = filter.parse()?;
+    log::info!("Log filter set to {}", filter);
+    apply(filter);
+    Ok(())
+}
+
+/// Returns the current log filter.
+pub fn filter() -> LogFilter {
+    FILTER.read().unwrap().clone()
+}
+
+/// Returns the last records let through, oldest first. Empty unless the logger was installed with
+/// [init](init).
+pub fn recent_records() -> Vec<String> {
+    RECENT.lock().unwrap().iter().cloned().collect()
+}
+
+fn apply(filter: LogFilter) {
+    log::set_max_level(filter.max_level());
+    *FILTER.write().unwrap() = filter;
+}
+
+/// Limits the rate of records emitted at a call site of [log_limited](crate::log_limited).
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    /// Time before which records are suppressed, in milliseconds since the Unix epoch.
+    next_ms: AtomicU64,
+    nb_suppressed: AtomicU64,
+}
+
+impl RateLimiter {
+    pub const fn new() -> Self {
+        RateLimiter {
+            next_ms: AtomicU64::new(0),
+            nb_suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of records suppressed since the last emitted one if a record can be
+    /// emitted now, `None` if the record is suppressed.
+    pub fn admit(&self, interval: Duration) -> Option<u64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let next = self.next_ms.load(Ordering::Relaxed);
+        let until = now + interval.as_millis() as u64;
+        if now < next
+            || self
+                .next_ms
+                .compare_exchange(next, until, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            self.nb_suppressed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(self.nb_suppressed.swap(0, Ordering::Relaxed))
+    }
+}
+
+/// Logs at most one record per `interval` at the call site, appending the number of records
+/// suppressed since the previous one.
+///
+/// ## Example
+/// ```
+/// log_limited!(Duration::from_secs(1), Level::Debug, "Unknown EtherType {:#x}", ether_type);
+/// ```
+#[macro_export]
+macro_rules! log_limited {
+    ($interval:expr, $lvl:expr, $($arg:tt)+) => {{
+        static LIMITER: $crate::logging::RateLimiter = $crate::logging::RateLimiter::new();
+        let lvl = $lvl;
+        if $crate::logging::__log::log_enabled!(lvl) {
+            match LIMITER.admit($interval) {
+                Some(0) => $crate::logging::__log::log!(lvl, $($arg)+),
+                Some(nb_suppressed) => $crate::logging::__log::log!(
+                    lvl,
+                    "{} ({} similar records suppressed)",
+                    format_args!($($arg)+),
+                    nb_suppressed
+                ),
+                None => (),
+            }
+        }
+    }};
+}
@@ -11,7 +11,9 @@
 
 use crate::dpdk;
 use crate::memory::mempool::MempoolError;
+use crate::protocols::layer4::L4Context;
 use crate::protocols::packet::{Packet, PacketHeader, PacketParseError};
+use crate::timebase;
 
 use std::fmt;
 use std::ptr::NonNull;
@@ -62,6 +64,14 @@ impl Mbuf {
         Ok(mbuf)
     }
 
+    /// Releases ownership of the inner rte_mbuf, e.g. to hand it to a TX queue. The caller is
+    /// responsible for freeing it.
+    pub(crate) fn into_raw(self) -> *mut dpdk::rte_mbuf {
+        let raw = self.raw.as_ptr();
+        std::mem::forget(self);
+        raw
+    }
+
     /// Returns a reference to the inner rte_mbuf for use with DPDK functions.
     pub(crate) fn raw(&self) -> &dpdk::rte_mbuf {
         unsafe { self.raw.as_ref() }
@@ -72,10 +82,19 @@ impl Mbuf {
         unsafe { self.raw.as_mut() }
     }
 
-    /// Returns the UNIX timestamp of the packet.
-    #[allow(dead_code)]
-    pub(crate) fn timestamp(&self) -> usize {
-        unimplemented!();
+    /// Returns the reception time of the packet, in nanoseconds since the UNIX epoch: its hardware
+    /// RX timestamp if the NIC timestamped it, and the current time otherwise (see
+    /// [timebase](crate::timebase#hardware-rx-timestamps)).
+    #[inline]
+    pub fn timestamp(&self) -> u64 {
+        self.hw_timestamp().unwrap_or_else(timebase::now_ns)
+    }
+
+    /// Returns the hardware RX timestamp of the packet, in nanoseconds since the UNIX epoch,
+    /// `None` if it was not timestamped by the NIC.
+    #[inline]
+    pub fn hw_timestamp(&self) -> Option<u64> {
+        timebase::rx_timestamp(self)
     }
 
     /// Returns the length of the data in the Mbuf.
@@ -83,27 +102,84 @@ impl Mbuf {
         self.raw().data_len as usize
     }
 
+    /// Returns the length of the packet, including the segments chained to the Mbuf when scatter
+    /// RX is enabled (see [PortMap](crate::config::PortMap)).
+    pub fn pkt_len(&self) -> usize {
+        self.raw().pkt_len as usize
+    }
+
     /// Returns the contents of the Mbuf as a byte slice.
     pub fn data(&self) -> &[u8] {
         let ptr = self.get_data_address(0) as *const u8;
         unsafe { slice::from_raw_parts(ptr, self.data_len()) as &[u8] }
     }
 
+    /// Returns the contents of the Mbuf as a mutable byte slice. The Mbuf must not be shared.
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        let ptr = self.get_data_address(0) as *mut u8;
+        unsafe { slice::from_raw_parts_mut(ptr, self.data_len()) }
+    }
+
+    /// Grows the data of the Mbuf by `len` bytes at its start, into the headroom.
+    pub(crate) fn prepend(&mut self, len: u16) -> Result<()> {
+        let ret = unsafe { dpdk::rte_pktmbuf_prepend(self.raw.as_ptr(), len) };
+        if ret.is_null() {
+            bail!(MbufError::WritePastBuffer);
+        }
+        Ok(())
+    }
+
+    /// Removes `len` bytes from the start of the data of the Mbuf.
+    pub(crate) fn adj(&mut self, len: u16) -> Result<()> {
+        let ret = unsafe { dpdk::rte_pktmbuf_adj(self.raw.as_ptr(), len) };
+        if ret.is_null() {
+            bail!(MbufError::BadOffset);
+        }
+        Ok(())
+    }
+
     /// Returns a byte slice of data with length count at offset.
     ///
     /// Errors if `offset` is greater than or equal to the buffer length or `count` exceeds the size
     /// of the data stored at `offset`.
     pub fn get_data_slice(&self, offset: usize, count: usize) -> Result<&[u8]> {
-        if offset < self.data_len() {
-            if offset + count <= self.data_len() {
-                let ptr = self.get_data_address(offset) as *const u8;
-                unsafe { Ok(slice::from_raw_parts(ptr, count) as &[u8]) }
-            } else {
-                bail!(MbufError::ReadPastBuffer)
-            }
-        } else {
+        if offset >= self.data_len() {
             bail!(MbufError::BadOffset)
         }
+        match self.slice(offset, count) {
+            Some(data) => Ok(data),
+            None => bail!(MbufError::ReadPastBuffer),
+        }
+    }
+
+    /// Returns the `count` bytes of data at `offset`, `None` if they are not all within the data
+    /// of the Mbuf. Unlike [get_data_slice](Mbuf::get_data_slice), an empty slice at the end of
+    /// the data is returned.
+    pub fn slice(&self, offset: usize, count: usize) -> Option<&[u8]> {
+        self.data().get(offset..offset.checked_add(count)?)
+    }
+
+    /// Returns the transport-layer payload of the packet, as parsed into `ctx`. `None` if the
+    /// payload is not within the data of the Mbuf, e.g. if `ctx` was parsed from another packet.
+    ///
+    /// ## Example
+    /// ```
+    /// let cb = |pkt: ZcFrame, filter_ctx: &FilterCtx| {
+    ///     if let Ok(ctx) = filter_ctx.parse_l4(&pkt) {
+    ///         let payload = pkt.l4_payload(&ctx).unwrap_or_default();
+    ///         println!("{} payload bytes", payload.len());
+    ///     }
+    /// };
+    /// ```
+    pub fn l4_payload(&self, ctx: &L4Context) -> Option<&[u8]> {
+        self.slice(ctx.offset, ctx.length)
+    }
+
+    /// Returns the headers of the packet, as parsed into `ctx`: the bytes from the start of the
+    /// Ethernet header to the end of the transport-layer header. `None` if they are not within
+    /// the data of the Mbuf.
+    pub fn headers(&self, ctx: &L4Context) -> Option<&[u8]> {
+        self.data().get(..ctx.offset)
     }
 
     /// Reads the data at `offset` as `T` and returns it as a raw pointer. Errors if `offset` is
@@ -138,6 +214,23 @@ impl Mbuf {
     pub(crate) fn mark(&self) -> u32 {
         unsafe { self.raw().__bindgen_anon_2.hash.fdir.hi }
     }
+
+    /// Returns the TCI of the VLAN tag stripped from the frame by the NIC, if any.
+    pub(crate) fn stripped_vlan_tci(&self) -> Option<u16> {
+        if self.raw().ol_flags & dpdk::PKT_RX_VLAN_STRIPPED as u64 != 0 {
+            Some(self.raw().vlan_tci)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the NIC found a bad IP or L4 checksum. Always `false` if checksum offload
+    /// is disabled.
+    pub(crate) fn has_bad_checksum(&self) -> bool {
+        let ol_flags = self.raw().ol_flags;
+        ol_flags & dpdk::PKT_RX_IP_CKSUM_MASK as u64 == dpdk::PKT_RX_IP_CKSUM_BAD as u64
+            || ol_flags & dpdk::PKT_RX_L4_CKSUM_MASK as u64 == dpdk::PKT_RX_L4_CKSUM_BAD as u64
+    }
 }
 
 impl<'a> Packet<'a> for Mbuf {
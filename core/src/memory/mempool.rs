@@ -20,15 +20,29 @@ pub(crate) struct Mempool {
     raw: NonNull<dpdk::rte_mempool>,
 }
 
+/// Returns the name of the mempool on `socket_id`, which includes the name of the runtime
+/// instance, if any.
+pub(crate) fn mempool_name(instance: Option<&str>, socket_id: SocketId) -> String {
+    match instance {
+        Some(instance) => format!("mempool_{}_{}", instance, socket_id),
+        None => format!("mempool_{}", socket_id),
+    }
+}
+
 impl Mempool {
-    /// Creates a new mbuf pool on socket_id
-    pub(crate) fn new(config: &MempoolConfig, socket_id: SocketId, mtu: usize) -> Result<Self> {
+    /// Creates a new mbuf pool on socket_id, for the runtime instance `instance`.
+    pub(crate) fn new(
+        config: &MempoolConfig,
+        instance: Option<&str>,
+        socket_id: SocketId,
+        mtu: usize,
+    ) -> Result<Self> {
         let data_room = crate::port::mtu_to_max_frame_len(mtu as u32);
         let data_room_aligned = round_up(data_room, RX_BUF_ALIGN);
         let mbuf_size = data_room_aligned + dpdk::RTE_PKTMBUF_HEADROOM;
         let mbuf_size = cmp::max(mbuf_size, dpdk::RTE_MBUF_DEFAULT_BUF_SIZE);
 
-        let name = format!("mempool_{}", socket_id);
+        let name = mempool_name(instance, socket_id);
         let cname = CString::new(name.clone()).expect("Invalid CString conversion");
         let mempool = unsafe {
             dpdk::rte_pktmbuf_pool_create(
@@ -55,6 +69,11 @@ impl Mempool {
         unsafe { self.raw.as_mut() }
     }
 
+    /// Socket the mempool is allocated on.
+    pub(crate) fn socket_id(&self) -> SocketId {
+        SocketId(self.raw().socket_id as u32)
+    }
+
     /// Mempool name.
     pub(crate) fn name(&self) -> &str {
         let cstr = unsafe { CStr::from_ptr(self.raw().name.as_ptr()) };
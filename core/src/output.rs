@@ -0,0 +1,130 @@
+//! Human-facing output.
+//!
+//! The runtime prints status lines, the statistics tables of the monitor and the end-of-run
+//! summary to stdout by default. Applications that embed Retina, e.g. as part of a daemon, can
+//! redirect this output by setting a sink on the [Output](Output) registry of a
+//! [FilterCtx](crate::filter::FilterCtx), before creating the runtime. Logs and the files written
+//! by the monitor logger are not affected.
+//!
+//! ## Example
+//! Forward the output to the logger, or to a closure:
+//! ```
+//! let filter_ctx = FilterCtx::new(100_000, Duration::from_secs(60), regexes);
+//! filter_ctx.output().set_sink(LogOutput);
+//! filter_ctx.output().set_sink(|kind: OutputKind, text: &str| {
+//!     if kind == OutputKind::Summary {
+//!         eprintln!("{}", text);
+//!     }
+//! });
+//! let mut runtime = Runtime::new(config, callback, &filter_ctx).unwrap();
+//! runtime.run();
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// Kind of human-facing output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputKind {
+    /// Runtime lifecycle messages.
+    Status,
+    /// Periodic statistics tables of the monitor, one per display interval.
+    Statistics,
+    /// End-of-run summary.
+    Summary,
+}
+
+impl fmt::Display for OutputKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputKind::Status => write!(f, "status"),
+            OutputKind::Statistics => write!(f, "statistics"),
+            OutputKind::Summary => write!(f, "summary"),
+        }
+    }
+}
+
+/// Destination of human-facing output.
+pub trait OutputSink: Send + Sync {
+    /// Writes `text`, which may span several lines and is not terminated by a line feed.
+    fn write(&self, kind: OutputKind, text: &str);
+}
+
+impl<F> OutputSink for F
+where
+    F: Fn(OutputKind, &str) + Send + Sync,
+{
+    fn write(&self, kind: OutputKind, text: &str) {
+        self(kind, text)
+    }
+}
+
+/// Prints output to stdout, the default sink.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsoleOutput;
+
+impl OutputSink for ConsoleOutput {
+    fn write(&self, _kind: OutputKind, text: &str) {
+        println!("{}", text);
+    }
+}
+
+/// Forwards output to the logger, at the info level with the `retina::output` target.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogOutput;
+
+impl OutputSink for LogOutput {
+    fn write(&self, kind: OutputKind, text: &str) {
+        log::info!(target: "retina::output", "[{}] {}", kind, text);
+    }
+}
+
+/// Discards output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullOutput;
+
+impl OutputSink for NullOutput {
+    fn write(&self, _kind: OutputKind, _text: &str) {}
+}
+
+/// Registry of the output sink, shared by all copies of a filter.
+pub struct Output {
+    sink: RwLock<Arc<dyn OutputSink>>,
+}
+
+impl Output {
+    /// Creates a registry printing to stdout.
+    pub fn new() -> Self {
+        Output {
+            sink: RwLock::new(Arc::new(ConsoleOutput)),
+        }
+    }
+
+    /// Sends all output to `sink` from now on.
+    pub fn set_sink(&self, sink: impl OutputSink + 'static) {
+        *self.sink.write().unwrap() = Arc::new(sink);
+    }
+
+    /// Discards all output from now on.
+    pub fn disable(&self) {
+        self.set_sink(NullOutput);
+    }
+
+    /// Writes `text` to the sink.
+    pub(crate) fn write(&self, kind: OutputKind, text: impl fmt::Display) {
+        let sink = Arc::clone(&self.sink.read().unwrap());
+        sink.write(kind, &text.to_string());
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output::new()
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Output").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,167 @@
+//! Bonded ports.
+//!
+//! A port with `bond` options (see [BondConfig](crate::config::BondConfig)) is a DPDK bonding
+//! device, created from its member devices before the port is configured. The bonding device is
+//! configured, polled and filtered like any other port: it receives from all its members, and
+//! forwarding sinks transmit on it. The member chosen for each transmitted packet depends on the
+//! `hash` of the bond.
+//!
+//! Drivers report hardware counters such as `rx_phy_packets` on the members only, so the extended
+//! statistics of a bond are the sums of those of its members (see
+//! [PortStats](crate::port::statistics::PortStats)). The monitor also shows how many members are
+//! active, e.g. aggregated by LACP.
+//!
+//! In `lacp` mode, the bonding driver exchanges LACPDUs with the switch from its RX and TX bursts,
+//! which must be called at least every 100 ms. RX cores poll continuously, and the port gets a
+//! dedicated TX queue that the main core flushes every [KEEPALIVE_INTERVAL](KEEPALIVE_INTERVAL).
+
+use super::PortId;
+use crate::config::{BondConfig, BondHash, BondMode};
+use crate::dpdk;
+
+#[cfg(feature = "bonding")]
+use std::ffi::CString;
+use std::ptr;
+use std::time::Duration;
+
+#[cfg(feature = "bonding")]
+use anyhow::Context;
+use anyhow::{bail, Result};
+
+/// Interval between two TX bursts of an LACP bond.
+pub(crate) const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Largest number of members of a bond.
+#[cfg(feature = "bonding")]
+const MAX_MEMBERS: usize = 16;
+
+impl BondMode {
+    /// Mode value of the DPDK bonding driver.
+    #[cfg_attr(not(feature = "bonding"), allow(dead_code))]
+    fn raw(&self) -> u8 {
+        match self {
+            BondMode::Backup => 1,
+            BondMode::Balance => 2,
+            BondMode::Lacp => 4,
+        }
+    }
+}
+
+impl BondHash {
+    /// Transmit policy value of the DPDK bonding driver.
+    #[cfg_attr(not(feature = "bonding"), allow(dead_code))]
+    fn raw(&self) -> u8 {
+        match self {
+            BondHash::L2 => 0,
+            BondHash::L23 => 1,
+            BondHash::L34 => 2,
+        }
+    }
+}
+
+/// Creates bonding device `name` from the members of `config`.
+#[cfg(feature = "bonding")]
+pub(crate) fn create(name: &str, config: &BondConfig) -> Result<()> {
+    if config.members.is_empty() {
+        bail!("Bond {} has no members", name);
+    }
+    if config.members.len() > MAX_MEMBERS {
+        bail!("Bond {} has more than {} members", name, MAX_MEMBERS);
+    }
+    let members = config
+        .members
+        .iter()
+        .map(|member| port_by_name(member).with_context(|| format!("Member of bond {}", name)))
+        .collect::<Result<Vec<_>>>()?;
+    // Bonding devices are allocated on the socket of their first member
+    let socket_id = unsafe { dpdk::rte_eth_dev_socket_id(members[0].raw()) }.max(0) as u8;
+    let cname = CString::new(name)?;
+    let ret = unsafe { dpdk::rte_eth_bond_create(cname.as_ptr(), config.mode.raw(), socket_id) };
+    if ret < 0 {
+        bail!("Failed to create bond {}: error {}", name, ret);
+    }
+    let bond_id = ret as u16;
+    for (member, device) in members.iter().zip(config.members.iter()) {
+        let ret = unsafe { dpdk::rte_eth_bond_slave_add(bond_id, member.raw()) };
+        if ret != 0 {
+            bail!("Failed to add {} to bond {}: error {}", device, name, ret);
+        }
+    }
+    if config.mode != BondMode::Backup {
+        let ret = unsafe { dpdk::rte_eth_bond_xmit_policy_set(bond_id, config.hash.raw()) };
+        if ret != 0 {
+            bail!("Failed to set the hash of bond {}: error {}", name, ret);
+        }
+    }
+    log::info!(
+        "Created bond {} (Port {}) of {} in {:?} mode",
+        name,
+        bond_id,
+        config.members.join(", "),
+        config.mode
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "bonding"))]
+pub(crate) fn create(name: &str, _config: &BondConfig) -> Result<()> {
+    bail!("Bond {} requires the `bonding` feature", name)
+}
+
+/// Returns the members of `port_id`, empty if it is not a bond.
+pub(crate) fn members(port_id: PortId) -> Vec<PortId> {
+    #[cfg(feature = "bonding")]
+    {
+        let mut members = [0u16; MAX_MEMBERS];
+        let ret = unsafe {
+            dpdk::rte_eth_bond_slaves_get(port_id.raw(), members.as_mut_ptr(), MAX_MEMBERS as u16)
+        };
+        members
+            .iter()
+            .take(ret.max(0) as usize)
+            .map(|id| PortId(*id))
+            .collect()
+    }
+    #[cfg(not(feature = "bonding"))]
+    {
+        let _ = port_id;
+        vec![]
+    }
+}
+
+/// Returns the number of active members of `port_id`, `0` if it is not a bond.
+pub(crate) fn nb_active_members(port_id: PortId) -> usize {
+    #[cfg(feature = "bonding")]
+    {
+        let mut members = [0u16; MAX_MEMBERS];
+        let ret = unsafe {
+            dpdk::rte_eth_bond_active_slaves_get(
+                port_id.raw(),
+                members.as_mut_ptr(),
+                MAX_MEMBERS as u16,
+            )
+        };
+        ret.max(0) as usize
+    }
+    #[cfg(not(feature = "bonding"))]
+    {
+        let _ = port_id;
+        0
+    }
+}
+
+/// Flushes TX queue `txq` of LACP bond `port_id`, which sends pending LACPDUs.
+pub(crate) fn keepalive(port_id: PortId, txq: u16) {
+    unsafe { dpdk::rte_eth_tx_burst(port_id.raw(), txq, ptr::null_mut(), 0) };
+}
+
+#[cfg(feature = "bonding")]
+fn port_by_name(device: &str) -> Result<PortId> {
+    let mut port_id: u16 = 0;
+    let cname = CString::new(device)?;
+    let ret = unsafe { dpdk::rte_eth_dev_get_port_by_name(cname.as_ptr(), &mut port_id) };
+    if ret != 0 {
+        bail!("{} is not a probed device", device);
+    }
+    Ok(PortId(port_id))
+}
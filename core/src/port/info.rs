@@ -1,6 +1,7 @@
 use super::PortId;
 use crate::dpdk;
 
+use std::ffi::CStr;
 use std::mem;
 
 use anyhow::{bail, Result};
@@ -23,6 +24,21 @@ impl PortInfo {
         Ok(PortInfo { raw: dev_info })
     }
 
+    /// Returns the name of the driver of the port.
+    pub(crate) fn driver_name(&self) -> String {
+        if self.raw.driver_name.is_null() {
+            return String::new();
+        }
+        unsafe { CStr::from_ptr(self.raw.driver_name) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Returns the maximum number of RX and TX queues of the port.
+    pub(crate) fn max_queues(&self) -> (u16, u16) {
+        (self.raw.max_rx_queues, self.raw.max_tx_queues)
+    }
+
     /// Displays debug output for the raw device information.
     pub(crate) fn display(&self) {
         log::debug!("{:#?}", self.raw);
@@ -1,11 +1,13 @@
+pub(crate) mod bond;
 #[allow(dead_code)]
-mod info;
+pub(crate) mod info;
 pub(crate) mod statistics;
 
-use crate::config::PortMap;
+use crate::config::{BondMode, PortMap, SinkBehavior, SinkConfig};
 use crate::dpdk;
 use crate::lcore::{CoreId, SocketId};
 use crate::memory::mempool::Mempool;
+use crate::timebase;
 
 use self::info::PortInfo;
 
@@ -27,6 +29,9 @@ pub(crate) const SYMMETRIC_RSS_KEY: [u8; RSS_KEY_LEN] = [
 ];
 const RSS_RETA_SIZE: usize = 512;
 
+/// RSS redirection table, mapping RSS bucket IDs to RxQueueIds
+pub(crate) type Reta = [RxQueueId; RSS_RETA_SIZE];
+
 #[derive(Debug, Copy, Clone, Hash, Ord, Eq, PartialEq, PartialOrd)]
 pub(crate) struct PortId(pub(crate) u16);
 
@@ -74,32 +79,62 @@ pub(crate) struct Port {
     /// Mapping of receive queues to cores
     pub(crate) queue_map: BTreeMap<RxQueue, CoreId>,
 
+    /// Options of the sink queues
+    pub(crate) sinks: BTreeMap<RxQueue, SinkConfig>,
+
+    /// Servicing weight of the receive queues, in bursts per round
+    pub(crate) rx_weight: u32,
+
+    /// Number of TX queues, one per sink forwarding to this port
+    nb_txq: u16,
+
+    /// TX queue flushed by the main core if the port is an LACP bond, after the other TX queues
+    pub(crate) keepalive_txq: Option<u16>,
+
     /// Redirection table mapping RSS bucket IDs to RxQueueIds
-    pub(crate) reta: [RxQueueId; RSS_RETA_SIZE],
+    pub(crate) reta: Reta,
+
+    /// Per-port mode and offload options
+    options: PortOptions,
+}
+
+/// Per-port options, see [PortMap](PortMap).
+#[derive(Debug, Clone, Copy)]
+struct PortOptions {
+    promiscuous: Option<bool>,
+    mtu: Option<usize>,
+    rx_checksum: bool,
+    vlan_strip: bool,
+    scatter: bool,
+    rx_timestamp: bool,
 }
 
 impl Port {
-    pub(crate) fn new(port_map: &PortMap) -> Port {
+    /// Creates the port of `port_map`, with `nb_txq` TX queues for sinks forwarding to it.
+    pub(crate) fn new(port_map: &PortMap, nb_txq: u16) -> Port {
         let port_id = PortId::new_from_device(port_map.device.clone());
+        let keepalive_txq = port_map
+            .bond
+            .as_ref()
+            .filter(|bond| bond.mode == BondMode::Lacp)
+            .map(|_| nb_txq);
 
         let mut queue_map: BTreeMap<RxQueue, CoreId> = BTreeMap::new();
+        let mut sinks: BTreeMap<RxQueue, SinkConfig> = BTreeMap::new();
         let mut rx_core_ids = port_map.cores.to_vec();
         rx_core_ids.sort_unstable();
         rx_core_ids.dedup();
 
-        // TODO: display warning if cores do not match port socket
         // TODO: display warning and handle duplicate cores per port and across ports
         let mut q: u16 = 0;
-        let nb_buckets = if let Some(sink) = &port_map.sink {
-            queue_map.insert(
-                RxQueue::new(port_id, RxQueueId(q), RxQueueType::Sink),
-                CoreId(sink.core),
-            );
+        let mut nb_buckets = RSS_RETA_SIZE;
+        for sink in port_map.sink_configs() {
+            let rxqueue = RxQueue::new(port_id, RxQueueId(q), RxQueueType::Sink(sink.behavior));
+            queue_map.insert(rxqueue, CoreId(sink.core));
+            sinks.insert(rxqueue, sink.clone());
             q += 1;
-            sink.nb_buckets
-        } else {
-            RSS_RETA_SIZE
-        };
+            nb_buckets = cmp::min(nb_buckets, sink.nb_buckets);
+        }
 
         for core_id in rx_core_ids.iter() {
             queue_map.insert(
@@ -132,6 +167,11 @@ impl Port {
         for i in 0..nb_buckets {
             reta[i] = rx_queues[i % rx_queues.len()];
         }
+        // Remaining buckets are spread across the sink queues
+        let sink_queues: Vec<RxQueueId> = sinks.keys().map(|rxq| rxq.qid).collect();
+        for i in nb_buckets..RSS_RETA_SIZE {
+            reta[i] = sink_queues[(i - nb_buckets) % sink_queues.len()];
+        }
 
         log::debug!("{:?}", reta);
 
@@ -139,11 +179,24 @@ impl Port {
             id: port_id,
             device: port_map.device.clone(),
             queue_map,
+            sinks,
+            rx_weight: port_map.rx_weight,
+            nb_txq: nb_txq + keepalive_txq.is_some() as u16,
+            keepalive_txq,
             reta,
+            options: PortOptions {
+                promiscuous: port_map.promiscuous,
+                mtu: port_map.mtu,
+                rx_checksum: port_map.rx_checksum,
+                vlan_strip: port_map.vlan_strip,
+                scatter: port_map.scatter,
+                rx_timestamp: port_map.rx_timestamp,
+            },
         }
     }
 
-    /// Configure port and setup RX queues.
+    /// Configure port and setup RX queues. `mtu` and `promiscuous` apply unless overridden by the
+    /// port options, and mbufs are assumed to be sized for `mtu`.
     pub(crate) fn init(
         &self,
         mempools: &mut BTreeMap<SocketId, Mempool>,
@@ -151,7 +204,16 @@ impl Port {
         mtu: usize,
         promiscuous: bool,
     ) -> Result<()> {
-        self.configure(promiscuous, mtu)?;
+        let port_mtu = self.options.mtu.unwrap_or(mtu);
+        if port_mtu > mtu && !self.options.scatter {
+            bail!(
+                "Port {} MTU ({}) exceeds the mbuf size set by the online MTU ({}). Enable `scatter` or raise the online MTU.",
+                self.id,
+                port_mtu,
+                mtu
+            );
+        }
+        self.configure(self.options.promiscuous.unwrap_or(promiscuous), port_mtu)?;
 
         let mempool = mempools.get_mut(&self.id.socket_id()).unwrap();
         self.setup_queues(mempool, nb_rxd)?;
@@ -169,6 +231,36 @@ impl Port {
 
         self.disable_flow_ctrl();
         self.configure_rss_reta();
+        if self.rx_timestamp() && timebase::register_rx_timestamp() {
+            if let Err(error) = timebase::calibrate_port(self.id.raw()) {
+                log::warn!("{}, using software timestamps", error);
+            }
+        }
+    }
+
+    /// Returns the redirection table spreading all RSS buckets across the sink queues, `None` if
+    /// the port has no sink queues.
+    pub(crate) fn sink_reta(&self) -> Option<Reta> {
+        let sink_queues: Vec<RxQueueId> = self.sinks.keys().map(|rxq| rxq.qid).collect();
+        if sink_queues.is_empty() {
+            return None;
+        }
+        let mut reta = [RxQueueId(0); RSS_RETA_SIZE];
+        for (i, qid) in reta.iter_mut().enumerate() {
+            *qid = sink_queues[i % sink_queues.len()];
+        }
+        Some(reta)
+    }
+
+    /// Returns whether RX timestamp offload is requested and supported by the device.
+    fn rx_timestamp(&self) -> bool {
+        if !self.options.rx_timestamp {
+            return false;
+        }
+        let mut dev_info: dpdk::rte_eth_dev_info = unsafe { mem::zeroed() };
+        unsafe { dpdk::rte_eth_dev_info_get(self.id.raw(), &mut dev_info) };
+        let offload = dpdk::DEV_RX_OFFLOAD_TIMESTAMP as u64;
+        dev_info.rx_offload_capa & offload == offload
     }
 
     /// Flush flow rules and stop port
@@ -236,32 +328,7 @@ impl Port {
     /// Sets RSS redirection table to full RSS_RETA_SIZE entries
     fn configure_rss_reta(&self) {
         log::info!("Configuring RSS redirection table...");
-        const GROUP_SIZE: usize = dpdk::RTE_RETA_GROUP_SIZE as usize;
-        let capacity = RSS_RETA_SIZE / GROUP_SIZE;
-        let mut reta_conf: Vec<dpdk::rte_eth_rss_reta_entry64> = Vec::with_capacity(capacity);
-
-        for i in 0..capacity {
-            let mut reta_entry64: dpdk::rte_eth_rss_reta_entry64 = unsafe { mem::zeroed() };
-            reta_entry64.mask = u64::MAX;
-            let start = i * GROUP_SIZE;
-            let end = (i + 1) * GROUP_SIZE;
-            let entry64 = self.reta[start..end]
-                .iter()
-                .map(|q| q.raw())
-                .collect::<Vec<_>>();
-
-            //reta_slice.copy_from_slice(&self.reta[start..end]);
-            reta_entry64.reta = entry64.try_into().unwrap();
-            reta_conf.push(reta_entry64);
-        }
-
-        let ret = unsafe {
-            dpdk::rte_eth_dev_rss_reta_update(
-                self.id.raw(),
-                reta_conf.as_mut_ptr(),
-                RSS_RETA_SIZE as u16,
-            )
-        };
+        let ret = update_rss_reta(self.id, &self.reta);
         if ret != 0 {
             if ret == -95 {
                 log::warn!("Setting RSS redirection table is not supported for Port {}. Without a symmetrical key and more than one core, you will experience problems matching connections.", self.id);
@@ -296,16 +363,17 @@ impl Port {
         let max_rx_pkt_len = mtu_to_max_frame_len(mtu as u32);
         port_conf.rxmode.max_rx_pkt_len = cmp::max(dpdk::RTE_ETHER_MAX_LEN, max_rx_pkt_len);
 
-        // turns on VLAN stripping if supported
-        // TODO: Should probably disable this!
-        if dev_info.rx_offload_capa & dpdk::DEV_RX_OFFLOAD_VLAN_STRIP as u64 != 0 {
-            port_conf.rxmode.offloads |= dpdk::DEV_RX_OFFLOAD_VLAN_STRIP as u64;
-        }
+        port_conf.rxmode.offloads |= self.rx_offloads(&dev_info)?;
 
         {
             let nb_queues = self.queue_map.len() as u16;
             let ret = unsafe {
-                dpdk::rte_eth_dev_configure(self.id.raw(), nb_queues, 0, &port_conf as *const _)
+                dpdk::rte_eth_dev_configure(
+                    self.id.raw(),
+                    nb_queues,
+                    self.nb_txq,
+                    &port_conf as *const _,
+                )
             };
             if ret < 0 {
                 bail!("Failed to configure Port {}", self.id);
@@ -354,6 +422,52 @@ impl Port {
         Ok(())
     }
 
+    /// Returns the requested RX offloads. Errors if the device does not support one of them.
+    fn rx_offloads(&self, dev_info: &dpdk::rte_eth_dev_info) -> Result<u64> {
+        let requested = [
+            (
+                "rx_checksum",
+                self.options.rx_checksum,
+                dpdk::DEV_RX_OFFLOAD_CHECKSUM as u64,
+            ),
+            (
+                "vlan_strip",
+                self.options.vlan_strip,
+                dpdk::DEV_RX_OFFLOAD_VLAN_STRIP as u64,
+            ),
+            (
+                "scatter",
+                self.options.scatter,
+                dpdk::DEV_RX_OFFLOAD_SCATTER as u64,
+            ),
+        ];
+        let mut offloads = 0;
+        for (name, enabled, offload) in requested {
+            if !enabled {
+                continue;
+            }
+            if dev_info.rx_offload_capa & offload != offload {
+                bail!(
+                    "Port {} ({}) does not support the `{}` RX offload (supported offloads: {:#x})",
+                    self.id,
+                    self.device,
+                    name,
+                    dev_info.rx_offload_capa
+                );
+            }
+            log::info!("Enabling `{}` RX offload on Port {}", name, self.id);
+            offloads |= offload;
+        }
+        // Software timestamps are used where the offload is not supported
+        if self.rx_timestamp() && timebase::register_rx_timestamp() {
+            log::info!("Enabling `rx_timestamp` RX offload on Port {}", self.id);
+            offloads |= dpdk::DEV_RX_OFFLOAD_TIMESTAMP as u64;
+        } else if self.options.rx_timestamp {
+            log::info!("Port {} does not timestamp packets, using software timestamps", self.id);
+        }
+        Ok(offloads)
+    }
+
     fn setup_queues(&self, mempool: &mut Mempool, nb_rxd: usize) -> Result<()> {
         for rxqueue in self.queue_map.keys() {
             let ret = unsafe {
@@ -370,6 +484,20 @@ impl Port {
                 bail!("Failed to setup up RX queue {}", rxqueue);
             }
         }
+        for txq in 0..self.nb_txq {
+            let ret = unsafe {
+                dpdk::rte_eth_tx_queue_setup(
+                    self.id.raw(),
+                    txq,
+                    nb_rxd as u16,
+                    self.id.socket_id().raw(),
+                    ptr::null(),
+                )
+            };
+            if ret < 0 {
+                bail!("Failed to setup up TX queue {} of Port {}", txq, self.id);
+            }
+        }
         Ok(())
     }
 }
@@ -381,6 +509,35 @@ impl Drop for Port {
     }
 }
 
+/// Replaces the RSS redirection table of a started port, returning the DPDK return code.
+pub(crate) fn update_rss_reta(port_id: PortId, reta: &Reta) -> i32 {
+    const GROUP_SIZE: usize = dpdk::RTE_RETA_GROUP_SIZE as usize;
+    let capacity = RSS_RETA_SIZE / GROUP_SIZE;
+    let mut reta_conf: Vec<dpdk::rte_eth_rss_reta_entry64> = Vec::with_capacity(capacity);
+
+    for i in 0..capacity {
+        let mut reta_entry64: dpdk::rte_eth_rss_reta_entry64 = unsafe { mem::zeroed() };
+        reta_entry64.mask = u64::MAX;
+        let start = i * GROUP_SIZE;
+        let end = (i + 1) * GROUP_SIZE;
+        let entry64 = reta[start..end]
+            .iter()
+            .map(|q| q.raw())
+            .collect::<Vec<_>>();
+
+        reta_entry64.reta = entry64.try_into().unwrap();
+        reta_conf.push(reta_entry64);
+    }
+
+    unsafe {
+        dpdk::rte_eth_dev_rss_reta_update(
+            port_id.raw(),
+            reta_conf.as_mut_ptr(),
+            RSS_RETA_SIZE as u16,
+        )
+    }
+}
+
 fn mtu_to_frame_len(mtu: u32) -> u32 {
     mtu + dpdk::RTE_ETHER_HDR_LEN + dpdk::RTE_ETHER_CRC_LEN
 }
@@ -398,15 +555,15 @@ pub(crate) fn mtu_to_max_frame_len(mtu: u32) -> u32 {
 pub(crate) enum RxQueueType {
     /// Packets forwarded to processing pipeline
     Receive,
-    /// Throwaway
-    Sink,
+    /// Bypasses the processing pipeline, see [SinkBehavior](SinkBehavior)
+    Sink(SinkBehavior),
 }
 
 impl fmt::Display for RxQueueType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             RxQueueType::Receive => write!(f, "r"),
-            RxQueueType::Sink => write!(f, "s"),
+            RxQueueType::Sink(_) => write!(f, "s"),
         }
     }
 }
@@ -1,7 +1,9 @@
-use super::PortId;
+use super::{bond, PortId};
 use crate::dpdk;
+use crate::output::{Output, OutputKind};
 
 use indexmap::IndexMap;
+use itertools::Itertools;
 use std::ffi::CStr;
 use std::mem;
 
@@ -13,11 +15,42 @@ use tabled::{builder::Builder, Style, Table, TableIteratorExt, row, Concat, Pane
 pub(crate) struct PortStats {
     pub(crate) stats: IndexMap<String, u64>,
     pub(crate) port_id: PortId,
+    /// Members of the port if it is a bond, whose statistics are summed in `stats`
+    pub(crate) members: Vec<PortId>,
+    /// Number of active members of the bond
+    pub(crate) nb_active: usize,
 }
 
 impl PortStats {
-    /// Retrieve port statistics at current time
+    /// Retrieve port statistics at current time, summed over its members if it is a bond
     pub(crate) fn collect(port_id: PortId) -> Result<Self> {
+        let members = bond::members(port_id);
+        if members.is_empty() {
+            let stats = Self::xstats(port_id)?;
+            return Ok(PortStats {
+                stats,
+                port_id,
+                members,
+                nb_active: 0,
+            });
+        }
+        let mut stats: IndexMap<String, u64> = IndexMap::new();
+        for member in members.iter() {
+            for (label, value) in Self::xstats(*member)? {
+                *stats.entry(label).or_insert(0) += value;
+            }
+        }
+        let nb_active = bond::nb_active_members(port_id);
+        Ok(PortStats {
+            stats,
+            port_id,
+            members,
+            nb_active,
+        })
+    }
+
+    /// Retrieve the extended statistics of a device
+    fn xstats(port_id: PortId) -> Result<IndexMap<String, u64>> {
         // temporary table used to get number of available statistics
         let mut table: Vec<dpdk::rte_eth_xstat> = vec![];
         let len = unsafe { dpdk::rte_eth_xstats_get(port_id.raw(), table.as_mut_ptr(), 0) };
@@ -59,11 +92,11 @@ impl PortStats {
             let value = xstats[i as usize].value;
             stats.insert(label.to_string_lossy().into_owned(), value);
         }
-        Ok(PortStats { stats, port_id })
+        Ok(stats)
     }
 
-    /// Displays all statistics with keyword in list of keywords
-    pub(crate) fn display(&self, keywords: &[String]) {
+    /// Displays all statistics with keyword in list of keywords to `output`
+    pub(crate) fn display(&self, keywords: &[String], output: &Output) {
         // println!("Port {} statistics", self.port_id);
         let mut capture = self.display_capture_rate();
         let mut out_of_buffer = self.display_out_of_buffer_rate();
@@ -87,9 +120,19 @@ impl PortStats {
         table_keywords.with(Style::modern());
 
         let mut complete = row![capture, table_keywords];
-        complete.with(Panel::header(format!("Port {0} statistics", self.port_id)));
+        let header = if self.members.is_empty() {
+            format!("Port {0} statistics", self.port_id)
+        } else {
+            format!(
+                "Port {} statistics (bond of ports {}, {} active)",
+                self.port_id,
+                self.members.iter().format(", "),
+                self.nb_active
+            )
+        };
+        complete.with(Panel::header(header));
         complete.with(Style::modern());
-        println!("{complete}");
+        output.write(OutputKind::Statistics, complete);
     }
 
     /// Prints fraction of packets received in software.
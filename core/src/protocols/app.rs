@@ -0,0 +1,371 @@
+//! Lightweight application protocol identification.
+//!
+//! Flows are identified from the first bytes of their payloads using well-known protocol
+//! signatures, falling back to well-known server ports if no signature matches within the first
+//! few payloads of the flow. Identification is best-effort and is meant to scope rules to
+//! application protocols cheaply, not to replace full protocol parsing.
+//!
+//! Services on non-standard ports can be declared with [port
+//! hints](crate::config::AppPortConfig): a flow to or from a hinted port is identified as the
+//! hinted protocol right away. Hints can be changed at runtime, and apply to flows that are not
+//! identified yet.
+//!
+//! On UDP, DTLS records and RTP or RTCP packets are recognized from their headers. SRTP shares
+//! the RTP header and is identified as RTP. The payloads of these protocols are encrypted or
+//! media, so their flows can be left unscanned once identified, see
+//! [ScanConfig::skip_apps](crate::config::ScanConfig::skip_apps).
+//!
+//! The server name requested by TLS and HTTP clients, from the SNI of their ClientHello or their
+//! `Host` header, is read by [server_name](server_name).
+
+use crate::config::AppPortConfig;
+use crate::protocols::packet::tcp::TCP_PROTOCOL;
+use crate::protocols::packet::udp::UDP_PROTOCOL;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+
+/// Number of non-empty payloads checked for a signature before falling back to port heuristics.
+pub(crate) const MAX_IDENTIFY_PAYLOADS: u8 = 4;
+
+/// Identified application protocol of a flow.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AppProtocol {
+    Unknown,
+    Http,
+    Tls,
+    Quic,
+    Ssh,
+    Telnet,
+    Dns,
+    Dhcp,
+    Ntp,
+    Snmp,
+    Smtp,
+    Pop3,
+    Imap,
+    Ftp,
+    Smb,
+    Rdp,
+    Ldap,
+    Sip,
+    Rtsp,
+    Mqtt,
+    Mysql,
+    Postgres,
+    Redis,
+    Bittorrent,
+    Dtls,
+    Rtp,
+}
+
+impl fmt::Display for AppProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// Identifies the application protocol of `payload` from its content. `proto` is the transport
+/// protocol number.
+pub fn identify_signature(proto: usize, payload: &[u8]) -> Option<AppProtocol> {
+    let starts = |prefix: &[u8]| payload.starts_with(prefix);
+    let at = |offset: usize, bytes: &[u8]| payload.get(offset..offset + bytes.len()) == Some(bytes);
+    let contains = |needle: &[u8], within: usize| {
+        payload[..payload.len().min(within)]
+            .windows(needle.len())
+            .any(|w| w == needle)
+    };
+    if proto == UDP_PROTOCOL {
+        // Long header packet of QUIC version 1
+        if payload.len() >= 7 && payload[0] & 0xc0 == 0xc0 && at(1, &[0, 0, 0, 1]) {
+            return Some(AppProtocol::Quic);
+        }
+        if starts(b"SIP/2.0") || contains(b" sip:", 16) {
+            return Some(AppProtocol::Sip);
+        }
+        // Record of DTLS 1.0 or 1.2, whose content type is one of the TLS types
+        if payload.len() >= 13
+            && (0x14..=0x17).contains(&payload[0])
+            && (at(1, &[0xfe, 0xff]) || at(1, &[0xfe, 0xfd]))
+        {
+            return Some(AppProtocol::Dtls);
+        }
+        if is_rtp(payload) {
+            return Some(AppProtocol::Rtp);
+        }
+        return None;
+    }
+    if proto != TCP_PROTOCOL || payload.is_empty() {
+        return None;
+    }
+    const HTTP_METHODS: [&[u8]; 9] = [
+        b"GET ",
+        b"POST ",
+        b"PUT ",
+        b"HEAD ",
+        b"DELETE ",
+        b"OPTIONS ",
+        b"PATCH ",
+        b"CONNECT ",
+        b"HTTP/1.",
+    ];
+    if starts(b"RTSP/1.0") || contains(b" rtsp://", 16) {
+        Some(AppProtocol::Rtsp)
+    } else if starts(b"SIP/2.0") || contains(b" sip:", 16) {
+        Some(AppProtocol::Sip)
+    } else if HTTP_METHODS.iter().any(|method| starts(method)) {
+        Some(AppProtocol::Http)
+    } else if payload.len() >= 3 && payload[0] == 0x16 && payload[1] == 0x03 && payload[2] <= 0x04 {
+        // Handshake record
+        Some(AppProtocol::Tls)
+    } else if starts(b"SSH-") {
+        Some(AppProtocol::Ssh)
+    } else if starts(b"\x13BitTorrent protocol") {
+        Some(AppProtocol::Bittorrent)
+    } else if at(4, b"\xffSMB") || at(4, b"\xfeSMB") {
+        // NetBIOS session header followed by SMB1 or SMB2
+        Some(AppProtocol::Smb)
+    } else if payload[0] == 0x10 && at(2, b"\x00\x04MQTT") {
+        Some(AppProtocol::Mqtt)
+    } else if at(4, &[0, 3, 0, 0]) || at(4, &[0x04, 0xd2, 0x16, 0x2f]) {
+        // Startup message (protocol 3.0) or SSL request
+        Some(AppProtocol::Postgres)
+    } else if starts(b"220") && (contains(b"SMTP", 128) || contains(b"smtp", 128))
+        || starts(b"EHLO ")
+        || starts(b"HELO ")
+    {
+        Some(AppProtocol::Smtp)
+    } else if starts(b"220") && (contains(b"FTP", 128) || contains(b"ftp", 128))
+        || starts(b"USER ")
+    {
+        Some(AppProtocol::Ftp)
+    } else if starts(b"* OK") {
+        Some(AppProtocol::Imap)
+    } else if starts(b"+OK") {
+        Some(AppProtocol::Pop3)
+    } else if payload.len() >= 3 && payload[0] == 0xff && (0xfb..=0xfe).contains(&payload[1]) {
+        Some(AppProtocol::Telnet)
+    } else if payload[0] == b'*' && contains(b"\r\n$", 16) {
+        Some(AppProtocol::Redis)
+    } else {
+        None
+    }
+}
+
+/// Returns whether `payload` starts with an RTP header (RFC 3550) with a static or dynamic
+/// payload type, or with an RTCP header.
+fn is_rtp(payload: &[u8]) -> bool {
+    if payload.len() < 8 || payload[0] & 0xc0 != 0x80 {
+        return false;
+    }
+    // RTCP sender report, receiver report, source description, bye or application packet
+    if (200..=204).contains(&payload[1]) {
+        return true;
+    }
+    let nb_csrc = (payload[0] & 0x0f) as usize;
+    let payload_type = payload[1] & 0x7f;
+    payload.len() >= 12 + 4 * nb_csrc && (payload_type <= 34 || payload_type >= 96)
+}
+
+/// Identifies the application protocol of a flow from its ports.
+pub fn identify_port(proto: usize, ports: (u16, u16)) -> AppProtocol {
+    let is_port = |port: u16| ports.0 == port || ports.1 == port;
+    if proto == UDP_PROTOCOL {
+        if is_port(53) {
+            AppProtocol::Dns
+        } else if is_port(67) || is_port(68) {
+            AppProtocol::Dhcp
+        } else if is_port(123) {
+            AppProtocol::Ntp
+        } else if is_port(161) || is_port(162) {
+            AppProtocol::Snmp
+        } else if is_port(443) {
+            AppProtocol::Quic
+        } else if is_port(5060) {
+            AppProtocol::Sip
+        } else {
+            AppProtocol::Unknown
+        }
+    } else if proto == TCP_PROTOCOL {
+        if is_port(80) || is_port(8080) {
+            AppProtocol::Http
+        } else if is_port(443) {
+            AppProtocol::Tls
+        } else if is_port(22) {
+            AppProtocol::Ssh
+        } else if is_port(23) {
+            AppProtocol::Telnet
+        } else if is_port(53) {
+            AppProtocol::Dns
+        } else if is_port(25) || is_port(587) {
+            AppProtocol::Smtp
+        } else if is_port(110) {
+            AppProtocol::Pop3
+        } else if is_port(143) {
+            AppProtocol::Imap
+        } else if is_port(21) {
+            AppProtocol::Ftp
+        } else if is_port(445) || is_port(139) {
+            AppProtocol::Smb
+        } else if is_port(3389) {
+            AppProtocol::Rdp
+        } else if is_port(389) {
+            AppProtocol::Ldap
+        } else if is_port(554) {
+            AppProtocol::Rtsp
+        } else if is_port(1883) {
+            AppProtocol::Mqtt
+        } else if is_port(3306) {
+            AppProtocol::Mysql
+        } else if is_port(5432) {
+            AppProtocol::Postgres
+        } else if is_port(6379) {
+            AppProtocol::Redis
+        } else if is_port(5060) {
+            AppProtocol::Sip
+        } else {
+            AppProtocol::Unknown
+        }
+    } else {
+        AppProtocol::Unknown
+    }
+}
+
+/// Application protocols of server ports, shared by all cores.
+#[derive(Debug, Default)]
+pub(crate) struct PortHints {
+    ports: RwLock<HashMap<u16, AppProtocol>>,
+    /// Whether any hint is set, checked before taking the lock.
+    enabled: AtomicBool,
+}
+
+impl PortHints {
+    pub(crate) fn new() -> Self {
+        PortHints::default()
+    }
+
+    /// Replaces the hints with those of the runtime configuration.
+    pub(crate) fn configure(&self, hints: &[AppPortConfig]) {
+        let mut ports = self.ports.write().unwrap();
+        *ports = hints.iter().map(|hint| (hint.port, hint.app)).collect();
+        self.enabled.store(!ports.is_empty(), Ordering::Relaxed);
+    }
+
+    /// Returns the hinted protocol of a flow with `ports`. If both ports have a hint, the hint of
+    /// the lower port is returned.
+    #[inline]
+    pub(crate) fn get(&self, ports: (u16, u16)) -> Option<AppProtocol> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let hints = self.ports.read().unwrap();
+        let (low, high) = (ports.0.min(ports.1), ports.0.max(ports.1));
+        hints.get(&low).or_else(|| hints.get(&high)).copied()
+    }
+
+    /// Sets the protocol of `port`, returning its previous hint.
+    pub(crate) fn set(&self, port: u16, app: AppProtocol) -> Option<AppProtocol> {
+        let mut ports = self.ports.write().unwrap();
+        self.enabled.store(true, Ordering::Relaxed);
+        ports.insert(port, app)
+    }
+
+    /// Removes the hint of `port`, returning it.
+    pub(crate) fn remove(&self, port: u16) -> Option<AppProtocol> {
+        let mut ports = self.ports.write().unwrap();
+        let hint = ports.remove(&port);
+        self.enabled.store(!ports.is_empty(), Ordering::Relaxed);
+        hint
+    }
+
+    /// Returns the hints, by port.
+    pub(crate) fn list(&self) -> BTreeMap<u16, AppProtocol> {
+        self.ports
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(port, app)| (*port, *app))
+            .collect()
+    }
+}
+
+/// Identifies the application protocol of a single payload, without flow state.
+pub fn identify(proto: usize, ports: (u16, u16), payload: &[u8]) -> AppProtocol {
+    identify_signature(proto, payload).unwrap_or_else(|| identify_port(proto, ports))
+}
+
+/// Returns the server name requested by `payload`, lowercased: the SNI of a TLS ClientHello, or
+/// the `Host` header of an HTTP request without its port. `None` if the payload is neither, or
+/// does not name a server within its first record or headers.
+pub fn server_name(payload: &[u8]) -> Option<String> {
+    let name = match payload.first()? {
+        0x16 => tls_sni(payload)?,
+        _ => http_host(payload)?,
+    };
+    let name = std::str::from_utf8(name).ok()?.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(name.to_ascii_lowercase())
+}
+
+/// Returns the server name extension of the ClientHello starting `payload`.
+fn tls_sni(payload: &[u8]) -> Option<&[u8]> {
+    let u8_at = |offset: usize| payload.get(offset).map(|byte| *byte as usize);
+    let u16_at = |offset: usize| Some((u8_at(offset)? << 8) | u8_at(offset + 1)?);
+    // Record header, then handshake header of a ClientHello
+    if u8_at(5)? != 0x01 {
+        return None;
+    }
+    let end = payload.len().min(5 + u16_at(3)?);
+    // Client version and random
+    let mut offset = 5 + 4 + 2 + 32;
+    offset += 1 + u8_at(offset)?;
+    offset += 2 + u16_at(offset)?;
+    offset += 1 + u8_at(offset)?;
+    let extensions_end = end.min(offset + 2 + u16_at(offset)?);
+    offset += 2;
+    while offset + 4 <= extensions_end {
+        let (kind, len) = (u16_at(offset)?, u16_at(offset + 2)?);
+        offset += 4;
+        if kind == 0 {
+            // Server name list, whose first entry is a host name
+            if u8_at(offset + 2)? != 0 {
+                return None;
+            }
+            let name_len = u16_at(offset + 3)?;
+            return payload.get(offset + 5..(offset + 5 + name_len).min(offset + len));
+        }
+        offset += len;
+    }
+    None
+}
+
+/// Returns the `Host` header of the HTTP request starting `payload`, without its port.
+fn http_host(payload: &[u8]) -> Option<&[u8]> {
+    if identify_signature(TCP_PROTOCOL, payload) != Some(AppProtocol::Http) {
+        return None;
+    }
+    let headers_end = payload
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .unwrap_or(payload.len());
+    let host = payload[..headers_end]
+        .split(|byte| *byte == b'\n')
+        .skip(1)
+        .find_map(|line| {
+            let (name, value) = line.split_at(line.iter().position(|byte| *byte == b':')?);
+            name.eq_ignore_ascii_case(b"host").then(|| &value[1..])
+        })?;
+    let host = host.strip_suffix(b"\r").unwrap_or(host);
+    // Keep bracketed IPv6 literals whole
+    match host.iter().rposition(|byte| *byte == b':') {
+        Some(colon) if !host[colon..].contains(&b']') => Some(&host[..colon]),
+        _ => Some(host),
+    }
+}
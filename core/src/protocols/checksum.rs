@@ -0,0 +1,113 @@
+//! Software checksum verification.
+//!
+//! Verifies the IPv4 header checksum and the TCP and UDP checksums of IPv4 and IPv6 packets,
+//! for NICs or taps that do not validate checksums. Checksums that cannot be verified from the
+//! first segment of the packet, i.e. of fragments, truncated packets and IPv6 packets with
+//! extension headers, are reported as unchecked, as are UDP datagrams without a checksum.
+
+use crate::protocols::packet::ethernet::Ethernet;
+use crate::protocols::packet::ipv4::Ipv4;
+use crate::protocols::packet::ipv6::Ipv6;
+use crate::protocols::packet::tcp::TCP_PROTOCOL;
+use crate::protocols::packet::udp::UDP_PROTOCOL;
+use crate::protocols::packet::Packet;
+
+/// Offset of the checksum in a UDP header.
+const UDP_CHECKSUM_OFFSET: usize = 6;
+/// Mask of the more fragments flag and fragment offset of an IPv4 header.
+const IPV4_FRAGMENT_MASK: u16 = 0x3FFF;
+
+/// Result of a checksum verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    /// All checksums are correct.
+    Good,
+    /// At least one checksum is incorrect.
+    Bad,
+    /// The checksums could not be verified.
+    Unchecked,
+}
+
+/// Verifies the IP and L4 checksums of `eth`.
+pub fn verify(eth: &Ethernet) -> ChecksumStatus {
+    if let Ok(ipv4) = eth.parse_to::<Ipv4>() {
+        verify_ipv4(&ipv4)
+    } else if let Ok(ipv6) = eth.parse_to::<Ipv6>() {
+        verify_ipv6(&ipv6)
+    } else {
+        ChecksumStatus::Unchecked
+    }
+}
+
+fn verify_ipv4(ipv4: &Ipv4) -> ChecksumStatus {
+    let mbuf = ipv4.mbuf();
+    let header_offset = ipv4.next_header_offset() - ipv4.header_len();
+    match mbuf.get_data_slice(header_offset, ipv4.header_len()) {
+        Ok(header) if checksum(header, 0) != 0 => return ChecksumStatus::Bad,
+        Ok(_) => (),
+        Err(_) => return ChecksumStatus::Unchecked,
+    }
+    if ipv4.flags_to_fragment_offset() & IPV4_FRAGMENT_MASK != 0 {
+        return ChecksumStatus::Unchecked;
+    }
+    let len = match (ipv4.total_length() as usize).checked_sub(ipv4.header_len()) {
+        Some(len) => len,
+        None => return ChecksumStatus::Unchecked,
+    };
+    let mut pseudo = vec![];
+    pseudo.extend_from_slice(&ipv4.src_addr().octets());
+    pseudo.extend_from_slice(&ipv4.dst_addr().octets());
+    pseudo.extend_from_slice(&[0, ipv4.protocol()]);
+    pseudo.extend_from_slice(&(len as u16).to_be_bytes());
+    verify_segment(ipv4, len, &pseudo)
+}
+
+fn verify_ipv6(ipv6: &Ipv6) -> ChecksumStatus {
+    let len = ipv6.payload_length() as usize;
+    let mut pseudo = vec![];
+    pseudo.extend_from_slice(&ipv6.src_addr().octets());
+    pseudo.extend_from_slice(&ipv6.dst_addr().octets());
+    pseudo.extend_from_slice(&(len as u32).to_be_bytes());
+    pseudo.extend_from_slice(&[0, 0, 0, ipv6.next_header()]);
+    verify_segment(ipv6, len, &pseudo)
+}
+
+/// Verifies the TCP or UDP checksum of the `len` byte segment following `ip`, whose pseudo header
+/// is `pseudo`.
+fn verify_segment<'a>(ip: &impl Packet<'a>, len: usize, pseudo: &[u8]) -> ChecksumStatus {
+    let proto = match ip.next_header() {
+        Some(proto) if proto == TCP_PROTOCOL || proto == UDP_PROTOCOL => proto,
+        _ => return ChecksumStatus::Unchecked,
+    };
+    let segment = match ip.mbuf().get_data_slice(ip.next_header_offset(), len) {
+        Ok(segment) => segment,
+        Err(_) => return ChecksumStatus::Unchecked,
+    };
+    if proto == UDP_PROTOCOL
+        && segment.get(UDP_CHECKSUM_OFFSET..UDP_CHECKSUM_OFFSET + 2) == Some(&[0, 0])
+    {
+        // A zero UDP checksum means no checksum
+        return ChecksumStatus::Unchecked;
+    }
+    if checksum(segment, word_sum(pseudo)) == 0 {
+        ChecksumStatus::Good
+    } else {
+        ChecksumStatus::Bad
+    }
+}
+
+/// Returns the one's complement sum of the 16-bit words of `data`, not folded.
+pub(crate) fn word_sum(data: &[u8]) -> u32 {
+    data.chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+        .sum()
+}
+
+/// Returns the Internet checksum of `data`, starting from the partial sum `initial`.
+pub(crate) fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial as u64 + word_sum(data) as u64;
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,153 @@
+//! ICMP error correlation.
+//!
+//! ICMP and ICMPv6 error messages (destination unreachable, time exceeded, packet too big and
+//! parameter problem) embed the beginning of the datagram that caused them. The embedded IP header
+//! and transport ports identify the flow of the original datagram, so that the error can be
+//! attributed to that flow with
+//! [FilterCtx::correlate_icmp_error](crate::filter::FilterCtx::correlate_icmp_error), e.g. to
+//! detect scanning or path issues.
+//!
+//! ## Example
+//! ```
+//! let cb = |pkt: ZcFrame, filter_ctx: &FilterCtx| {
+//!     if let Ok(Some(error)) = IcmpError::new(&pkt) {
+//!         if let Some(flow) = filter_ctx.correlate_icmp_error(&error) {
+//!             println!("{:?} from {} on {}", error.kind, error.reporter, flow);
+//!         }
+//!     }
+//! };
+//! ```
+
+use crate::protocols::layer4::L4Context;
+use crate::protocols::parser::ParseError;
+use crate::protocols::packet::ethernet::Ethernet;
+use crate::protocols::packet::icmp::{Icmp, IcmpErrorKind};
+use crate::protocols::packet::tcp::TCP_PROTOCOL;
+use crate::protocols::packet::udp::UDP_PROTOCOL;
+use crate::protocols::packet::Packet;
+use crate::protocols::packet::{ipv4::Ipv4, ipv6::Ipv6};
+use crate::memory::mbuf::Mbuf;
+use crate::subscription::ZcFrame;
+
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{bail, Result};
+
+/// An ICMP error message and the transport-layer context of its embedded datagram.
+#[derive(Debug, Clone, Copy)]
+pub struct IcmpError {
+    /// Kind of error.
+    pub kind: IcmpErrorKind,
+    /// ICMP code of the error.
+    pub code: u8,
+    /// Address of the node that reported the error.
+    pub reporter: IpAddr,
+    /// Context of the embedded original datagram. Its payload offset and length are not
+    /// meaningful, as the embedded datagram is truncated. Its MAC addresses are those of the error
+    /// frame swapped, as the original datagram travelled in the opposite direction.
+    pub original: L4Context,
+}
+
+impl IcmpError {
+    /// Parses `mbuf` as an ICMP error message. Returns `Ok(None)` if the packet is not an ICMP
+    /// error, or if the embedded datagram is not TCP or UDP.
+    pub fn new(mbuf: &ZcFrame) -> Result<Option<Self>> {
+        let eth = match mbuf.parse_to::<Ethernet>() {
+            Ok(eth) => eth,
+            Err(_) => bail!(ParseError::NotEthernet),
+        };
+        if let Ok(ipv4) = eth.parse_to::<Ipv4>() {
+            let icmp = match ipv4.parse_to::<Icmp>() {
+                Ok(icmp) => icmp,
+                Err(_) => return Ok(None),
+            };
+            let kind = match icmp.error_kind() {
+                Some(kind) => kind,
+                None => return Ok(None),
+            };
+            let inner = match icmp.parse_to::<Ipv4>() {
+                Ok(inner) => inner,
+                Err(_) => bail!(ParseError::Malformed),
+            };
+            let proto = inner.protocol() as usize;
+            if proto != TCP_PROTOCOL && proto != UDP_PROTOCOL {
+                return Ok(None);
+            }
+            let (src_port, dst_port) = ports(inner.mbuf(), inner.next_header_offset())?;
+            Ok(Some(IcmpError {
+                kind,
+                code: icmp.code(),
+                reporter: IpAddr::V4(ipv4.src_addr()),
+                original: L4Context {
+                    src: SocketAddr::new(IpAddr::V4(inner.src_addr()), src_port),
+                    dst: SocketAddr::new(IpAddr::V4(inner.dst_addr()), dst_port),
+                    proto,
+                    offset: inner.next_header_offset(),
+                    length: 0,
+                    vlan_id: eth.get_last_vlan_id(),
+                    vlan_stack_hash: eth.vlan_stack_hash(),
+                    s_tag: eth.s_tag(),
+                    c_tag: eth.c_tag(),
+                    traffic_class: inner.type_of_service(),
+                    flow_label: None,
+                    src_mac: eth.dst(),
+                    dst_mac: eth.src(),
+                    bad_checksum: false,
+                },
+            }))
+        } else if let Ok(ipv6) = eth.parse_to::<Ipv6>() {
+            let icmp = match ipv6.parse_to::<Icmp>() {
+                Ok(icmp) => icmp,
+                Err(_) => return Ok(None),
+            };
+            let kind = match icmp.error_kind() {
+                Some(kind) => kind,
+                None => return Ok(None),
+            };
+            let inner = match icmp.parse_to::<Ipv6>() {
+                Ok(inner) => inner,
+                Err(_) => bail!(ParseError::Malformed),
+            };
+            let proto = inner.next_header() as usize;
+            if proto != TCP_PROTOCOL && proto != UDP_PROTOCOL {
+                return Ok(None);
+            }
+            let (src_port, dst_port) = ports(inner.mbuf(), inner.next_header_offset())?;
+            Ok(Some(IcmpError {
+                kind,
+                code: icmp.code(),
+                reporter: IpAddr::V6(ipv6.src_addr()),
+                original: L4Context {
+                    src: SocketAddr::new(IpAddr::V6(inner.src_addr()), src_port),
+                    dst: SocketAddr::new(IpAddr::V6(inner.dst_addr()), dst_port),
+                    proto,
+                    offset: inner.next_header_offset(),
+                    length: 0,
+                    vlan_id: eth.get_last_vlan_id(),
+                    vlan_stack_hash: eth.vlan_stack_hash(),
+                    s_tag: eth.s_tag(),
+                    c_tag: eth.c_tag(),
+                    traffic_class: inner.traffic_class(),
+                    flow_label: Some(inner.flow_label()),
+                    src_mac: eth.dst(),
+                    dst_mac: eth.src(),
+                    bad_checksum: false,
+                },
+            }))
+        } else {
+            bail!(ParseError::UnsupportedL3);
+        }
+    }
+}
+
+/// Reads the source and destination ports of the TCP or UDP header at `offset`. Only the first 8
+/// bytes of the transport header are guaranteed to be embedded in an ICMP error.
+fn ports(mbuf: &Mbuf, offset: usize) -> Result<(u16, u16)> {
+    match mbuf.get_data_slice(offset, 4) {
+        Ok(bytes) => Ok((
+            u16::from_be_bytes([bytes[0], bytes[1]]),
+            u16::from_be_bytes([bytes[2], bytes[3]]),
+        )),
+        Err(_) => bail!(ParseError::Malformed),
+    }
+}
@@ -1,3 +1,7 @@
 //! Protocol parsing and manipulation.
+pub mod app;
+pub mod checksum;
+pub mod icmp;
 pub mod packet;
 pub mod layer4;
+pub mod parser;
@@ -0,0 +1,461 @@
+//! Packet construction.
+//!
+//! [PacketBuilder](PacketBuilder) builds Ethernet frames layer by layer, e.g. to construct parser
+//! test inputs or synthetic traffic. Length fields and IPv4, TCP and UDP checksums are computed
+//! when the frame is built, either to bytes or, within the crate, to an mbuf that the parsers of
+//! [packet](crate::protocols::packet) and the filter read directly.
+//!
+//! ## Example
+//! A double-tagged TCP segment:
+//! ```
+//! let frame = PacketBuilder::ethernet(src_mac, dst_mac)
+//!     .s_tag(100)
+//!     .vlan(20)
+//!     .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+//!     .tcp(51234, 443)
+//!     .tcp_flags(PSH | ACK)
+//!     .payload(b"\x16\x03\x01")
+//!     .build();
+//! ```
+
+use crate::memory::mbuf::Mbuf;
+use crate::memory::mempool::Mempool;
+use crate::protocols::checksum::{checksum, word_sum};
+use crate::protocols::packet::tcp::TCP_PROTOCOL;
+use crate::protocols::packet::udp::UDP_PROTOCOL;
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use anyhow::Result;
+use pnet::datalink::MacAddr;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+/// Local experimental Ethernet type, used for frames without an IP layer.
+const ETHERTYPE_EXPERIMENTAL: u16 = 0x88B5;
+const TPID_802_1Q: u16 = 0x8100;
+const TPID_802_1AD: u16 = 0x88A8;
+/// IP protocol number reserved for experimentation, used for packets without a transport layer.
+const PROTOCOL_EXPERIMENTAL: u8 = 253;
+
+#[derive(Debug, Clone)]
+enum IpLayer {
+    V4 { src: Ipv4Addr, dst: Ipv4Addr },
+    V6 { src: Ipv6Addr, dst: Ipv6Addr },
+}
+
+#[derive(Debug, Clone)]
+enum L4Layer {
+    Tcp {
+        src_port: u16,
+        dst_port: u16,
+        seq_no: u32,
+        ack_no: u32,
+        flags: u8,
+        window: u16,
+    },
+    Udp {
+        src_port: u16,
+        dst_port: u16,
+    },
+}
+
+/// Builder of Ethernet frames.
+#[derive(Debug, Clone)]
+pub struct PacketBuilder {
+    src: MacAddr,
+    dst: MacAddr,
+    /// VLAN tags as `(TPID, TCI)`, outermost first.
+    vlans: Vec<(u16, u16)>,
+    ether_type: Option<u16>,
+    ip: Option<IpLayer>,
+    ttl: u8,
+    traffic_class: u8,
+    flow_label: u32,
+    protocol: Option<u8>,
+    l4: Option<L4Layer>,
+    payload: Vec<u8>,
+}
+
+impl PacketBuilder {
+    /// Starts an Ethernet frame from `src` to `dst`.
+    pub fn ethernet(src: MacAddr, dst: MacAddr) -> Self {
+        PacketBuilder {
+            src,
+            dst,
+            vlans: vec![],
+            ether_type: None,
+            ip: None,
+            ttl: 64,
+            traffic_class: 0,
+            flow_label: 0,
+            protocol: None,
+            l4: None,
+            payload: vec![],
+        }
+    }
+
+    /// Appends an 802.1Q VLAN tag with `vlan_id`.
+    pub fn vlan(mut self, vlan_id: u16) -> Self {
+        self.vlans.push((TPID_802_1Q, vlan_id & 0x0FFF));
+        self
+    }
+
+    /// Appends an 802.1ad service tag with `vlan_id`. Service tags precede customer tags.
+    pub fn s_tag(mut self, vlan_id: u16) -> Self {
+        self.vlans.push((TPID_802_1AD, vlan_id & 0x0FFF));
+        self
+    }
+
+    /// Overrides the Ethernet type following the VLAN tags. Defaults to the type of the IP layer.
+    pub fn ether_type(mut self, ether_type: u16) -> Self {
+        self.ether_type = Some(ether_type);
+        self
+    }
+
+    /// Adds an IPv4 layer from `src` to `dst`.
+    pub fn ipv4(mut self, src: Ipv4Addr, dst: Ipv4Addr) -> Self {
+        self.ip = Some(IpLayer::V4 { src, dst });
+        self
+    }
+
+    /// Adds an IPv6 layer from `src` to `dst`.
+    pub fn ipv6(mut self, src: Ipv6Addr, dst: Ipv6Addr) -> Self {
+        self.ip = Some(IpLayer::V6 { src, dst });
+        self
+    }
+
+    /// Sets the IPv4 time to live or IPv6 hop limit. Defaults to `64`.
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the IPv4 type of service or IPv6 traffic class. Defaults to `0`.
+    pub fn traffic_class(mut self, traffic_class: u8) -> Self {
+        self.traffic_class = traffic_class;
+        self
+    }
+
+    /// Sets the IPv6 flow label. Defaults to `0`.
+    pub fn flow_label(mut self, flow_label: u32) -> Self {
+        self.flow_label = flow_label & 0x000F_FFFF;
+        self
+    }
+
+    /// Overrides the IP protocol number. Defaults to the protocol of the transport layer.
+    pub fn protocol(mut self, protocol: u8) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Adds a TCP layer from `src_port` to `dst_port`.
+    pub fn tcp(mut self, src_port: u16, dst_port: u16) -> Self {
+        self.l4 = Some(L4Layer::Tcp {
+            src_port,
+            dst_port,
+            seq_no: 0,
+            ack_no: 0,
+            flags: 0,
+            window: u16::MAX,
+        });
+        self
+    }
+
+    /// Sets the TCP flags. Has no effect without a TCP layer.
+    pub fn tcp_flags(mut self, tcp_flags: u8) -> Self {
+        if let Some(L4Layer::Tcp { flags, .. }) = &mut self.l4 {
+            *flags = tcp_flags;
+        }
+        self
+    }
+
+    /// Sets the TCP sequence and acknowledgment numbers. Has no effect without a TCP layer.
+    pub fn tcp_seq(mut self, seq: u32, ack: u32) -> Self {
+        if let Some(L4Layer::Tcp { seq_no, ack_no, .. }) = &mut self.l4 {
+            *seq_no = seq;
+            *ack_no = ack;
+        }
+        self
+    }
+
+    /// Sets the TCP window size. Has no effect without a TCP layer.
+    pub fn tcp_window(mut self, window_size: u16) -> Self {
+        if let Some(L4Layer::Tcp { window, .. }) = &mut self.l4 {
+            *window = window_size;
+        }
+        self
+    }
+
+    /// Adds a UDP layer from `src_port` to `dst_port`.
+    pub fn udp(mut self, src_port: u16, dst_port: u16) -> Self {
+        self.l4 = Some(L4Layer::Udp { src_port, dst_port });
+        self
+    }
+
+    /// Sets the innermost payload.
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Serializes the frame.
+    pub fn build(&self) -> Vec<u8> {
+        let l4 = self.build_l4();
+        let mut frame = Vec::with_capacity(18 + 4 * self.vlans.len() + 40 + l4.len());
+        frame.extend_from_slice(&mac_octets(self.dst));
+        frame.extend_from_slice(&mac_octets(self.src));
+        for (tpid, tci) in self.vlans.iter() {
+            frame.extend_from_slice(&tpid.to_be_bytes());
+            frame.extend_from_slice(&tci.to_be_bytes());
+        }
+        let ether_type = self.ether_type.unwrap_or(match self.ip {
+            Some(IpLayer::V4 { .. }) => ETHERTYPE_IPV4,
+            Some(IpLayer::V6 { .. }) => ETHERTYPE_IPV6,
+            None => ETHERTYPE_EXPERIMENTAL,
+        });
+        frame.extend_from_slice(&ether_type.to_be_bytes());
+        match &self.ip {
+            Some(IpLayer::V4 { src, dst }) => {
+                let mut header = Vec::with_capacity(20);
+                header.push(0x45);
+                header.push(self.traffic_class);
+                header.extend_from_slice(&((20 + l4.len()) as u16).to_be_bytes());
+                header.extend_from_slice(&0_u16.to_be_bytes());
+                // Don't fragment
+                header.extend_from_slice(&0x4000_u16.to_be_bytes());
+                header.push(self.ttl);
+                header.push(self.ip_protocol());
+                header.extend_from_slice(&0_u16.to_be_bytes());
+                header.extend_from_slice(&src.octets());
+                header.extend_from_slice(&dst.octets());
+                let checksum = checksum(&header, 0);
+                header[10..12].copy_from_slice(&checksum.to_be_bytes());
+                frame.extend_from_slice(&header);
+            }
+            Some(IpLayer::V6 { src, dst }) => {
+                let version_to_flow_label =
+                    6 << 28 | (self.traffic_class as u32) << 20 | self.flow_label;
+                frame.extend_from_slice(&version_to_flow_label.to_be_bytes());
+                frame.extend_from_slice(&(l4.len() as u16).to_be_bytes());
+                frame.push(self.ip_protocol());
+                frame.push(self.ttl);
+                frame.extend_from_slice(&src.octets());
+                frame.extend_from_slice(&dst.octets());
+            }
+            None => (),
+        }
+        frame.extend_from_slice(&l4);
+        frame
+    }
+
+    /// Serializes the frame into an mbuf allocated from `mempool`. Fails if the mempool is
+    /// exhausted or the frame does not fit in an mbuf.
+    pub(crate) fn into_mbuf(self, mempool: &mut Mempool) -> Result<Mbuf> {
+        Mbuf::from_bytes(&self.build(), mempool.raw_mut())
+    }
+
+    fn ip_protocol(&self) -> u8 {
+        self.protocol.unwrap_or(match self.l4 {
+            Some(L4Layer::Tcp { .. }) => TCP_PROTOCOL as u8,
+            Some(L4Layer::Udp { .. }) => UDP_PROTOCOL as u8,
+            None => PROTOCOL_EXPERIMENTAL,
+        })
+    }
+
+    /// Serializes the transport layer and payload.
+    fn build_l4(&self) -> Vec<u8> {
+        let mut segment = vec![];
+        let checksum_offset = match &self.l4 {
+            Some(L4Layer::Tcp {
+                src_port,
+                dst_port,
+                seq_no,
+                ack_no,
+                flags,
+                window,
+            }) => {
+                segment.extend_from_slice(&src_port.to_be_bytes());
+                segment.extend_from_slice(&dst_port.to_be_bytes());
+                segment.extend_from_slice(&seq_no.to_be_bytes());
+                segment.extend_from_slice(&ack_no.to_be_bytes());
+                // Data offset of 5 words, no options
+                segment.push(5 << 4);
+                segment.push(*flags);
+                segment.extend_from_slice(&window.to_be_bytes());
+                segment.extend_from_slice(&0_u16.to_be_bytes());
+                segment.extend_from_slice(&0_u16.to_be_bytes());
+                Some(16)
+            }
+            Some(L4Layer::Udp { src_port, dst_port }) => {
+                segment.extend_from_slice(&src_port.to_be_bytes());
+                segment.extend_from_slice(&dst_port.to_be_bytes());
+                segment.extend_from_slice(&((8 + self.payload.len()) as u16).to_be_bytes());
+                segment.extend_from_slice(&0_u16.to_be_bytes());
+                Some(6)
+            }
+            None => None,
+        };
+        segment.extend_from_slice(&self.payload);
+        if let Some(offset) = checksum_offset {
+            let mut checksum = checksum(&segment, self.pseudo_header_sum(segment.len()));
+            if checksum == 0 && matches!(self.l4, Some(L4Layer::Udp { .. })) {
+                // A zero UDP checksum means no checksum
+                checksum = 0xFFFF;
+            }
+            segment[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+        }
+        segment
+    }
+
+    /// Returns the one's complement sum of the IP pseudo header of a `len` byte segment.
+    fn pseudo_header_sum(&self, len: usize) -> u32 {
+        let mut pseudo = vec![];
+        match &self.ip {
+            Some(IpLayer::V4 { src, dst }) => {
+                pseudo.extend_from_slice(&src.octets());
+                pseudo.extend_from_slice(&dst.octets());
+                pseudo.push(0);
+                pseudo.push(self.ip_protocol());
+                pseudo.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            Some(IpLayer::V6 { src, dst }) => {
+                pseudo.extend_from_slice(&src.octets());
+                pseudo.extend_from_slice(&dst.octets());
+                pseudo.extend_from_slice(&(len as u32).to_be_bytes());
+                pseudo.extend_from_slice(&[0, 0, 0, self.ip_protocol()]);
+            }
+            None => (),
+        }
+        word_sum(&pseudo)
+    }
+}
+
+fn mac_octets(mac: MacAddr) -> [u8; 6] {
+    [mac.0, mac.1, mac.2, mac.3, mac.4, mac.5]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::default_config;
+    use crate::protocols::checksum::{self, ChecksumStatus};
+    use crate::protocols::layer4::L4Context;
+    use crate::protocols::packet::ethernet::Ethernet;
+    use crate::protocols::packet::ipv4::Ipv4;
+    use crate::protocols::packet::ipv6::Ipv6;
+    use crate::protocols::packet::tcp::Tcp;
+    use crate::protocols::packet::udp::Udp;
+    use crate::protocols::packet::Packet;
+    use crate::testing::test_mempool;
+
+    const SRC_MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0x01);
+    const DST_MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0x02);
+    const PSH_ACK: u8 = 0x18;
+
+    fn mempool() -> Mempool {
+        test_mempool(&default_config().mempool).unwrap()
+    }
+
+    #[test]
+    fn tcp_ipv4_round_trip() {
+        let mut mempool = mempool();
+        let mbuf = PacketBuilder::ethernet(SRC_MAC, DST_MAC)
+            .s_tag(100)
+            .vlan(20)
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+            .ttl(32)
+            .traffic_class(0xB8)
+            .tcp(51234, 443)
+            .tcp_flags(PSH_ACK)
+            .tcp_seq(1000, 2000)
+            .tcp_window(512)
+            .payload(b"\x16\x03\x01")
+            .into_mbuf(&mut mempool)
+            .unwrap();
+
+        let eth = mbuf.parse_to::<Ethernet>().unwrap();
+        assert_eq!(eth.src(), SRC_MAC);
+        assert_eq!(eth.dst(), DST_MAC);
+        assert_eq!(eth.vlan_ids(), vec![100, 20]);
+        assert_eq!(eth.s_tag(), Some(100));
+        assert_eq!(eth.c_tag(), Some(20));
+        assert_eq!(eth.ether_type(), ETHERTYPE_IPV4);
+        assert_eq!(checksum::verify(&eth), ChecksumStatus::Good);
+
+        let ipv4 = eth.parse_to::<Ipv4>().unwrap();
+        assert_eq!(ipv4.src_addr(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(ipv4.dst_addr(), Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(ipv4.time_to_live(), 32);
+        assert_eq!(ipv4.type_of_service(), 0xB8);
+        assert_eq!(ipv4.protocol(), TCP_PROTOCOL as u8);
+        assert_eq!(ipv4.total_length(), 20 + 20 + 3);
+
+        let tcp = ipv4.parse_to::<Tcp>().unwrap();
+        assert_eq!((tcp.src_port(), tcp.dst_port()), (51234, 443));
+        assert_eq!((tcp.seq_no(), tcp.ack_no()), (1000, 2000));
+        assert_eq!(tcp.window(), 512);
+        assert!(tcp.psh() && tcp.ack() && !tcp.syn());
+
+        let ctx = L4Context::new(&mbuf).unwrap();
+        assert_eq!(ctx.src.port(), 51234);
+        assert_eq!(ctx.dst.port(), 443);
+        assert_eq!((ctx.s_tag, ctx.c_tag), (Some(100), Some(20)));
+        assert_eq!(mbuf.l4_payload(&ctx), Some(&b"\x16\x03\x01"[..]));
+    }
+
+    #[test]
+    fn udp_ipv6_round_trip() {
+        let mut mempool = mempool();
+        let src = "2001:db8::1".parse().unwrap();
+        let dst = "2001:db8::2".parse().unwrap();
+        let mbuf = PacketBuilder::ethernet(SRC_MAC, DST_MAC)
+            .ipv6(src, dst)
+            .traffic_class(0x20)
+            .flow_label(0x12345)
+            .udp(5353, 53)
+            .payload(b"query")
+            .into_mbuf(&mut mempool)
+            .unwrap();
+
+        let eth = mbuf.parse_to::<Ethernet>().unwrap();
+        assert!(eth.vlan_ids().is_empty());
+        assert_eq!(eth.ether_type(), ETHERTYPE_IPV6);
+        assert_eq!(checksum::verify(&eth), ChecksumStatus::Good);
+
+        let ipv6 = eth.parse_to::<Ipv6>().unwrap();
+        assert_eq!(ipv6.src_addr(), src);
+        assert_eq!(ipv6.dst_addr(), dst);
+        assert_eq!(ipv6.traffic_class(), 0x20);
+        assert_eq!(ipv6.flow_label(), 0x12345);
+        assert_eq!(ipv6.hop_limit(), 64);
+        assert_eq!(ipv6.next_header(), UDP_PROTOCOL as u8);
+        assert_eq!(ipv6.payload_length(), 8 + 5);
+
+        let udp = ipv6.parse_to::<Udp>().unwrap();
+        assert_eq!((udp.src_port(), udp.dst_port()), (5353, 53));
+        assert_eq!(udp.length(), 8 + 5);
+
+        let ctx = L4Context::new(&mbuf).unwrap();
+        assert_eq!(ctx.flow_label, Some(0x12345));
+        assert_eq!(mbuf.l4_payload(&ctx), Some(&b"query"[..]));
+    }
+
+    #[test]
+    fn frame_without_ip_round_trip() {
+        let mut mempool = mempool();
+        let mbuf = PacketBuilder::ethernet(SRC_MAC, DST_MAC)
+            .vlan(7)
+            .payload(b"raw")
+            .into_mbuf(&mut mempool)
+            .unwrap();
+
+        let eth = mbuf.parse_to::<Ethernet>().unwrap();
+        assert_eq!(eth.c_tag(), Some(7));
+        assert_eq!(eth.s_tag(), None);
+        assert_eq!(eth.ether_type(), ETHERTYPE_EXPERIMENTAL);
+        assert!(eth.parse_to::<Ipv4>().is_err());
+        assert!(eth.parse_to::<Ipv6>().is_err());
+        assert!(L4Context::new(&mbuf).is_err());
+    }
+}
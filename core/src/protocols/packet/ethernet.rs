@@ -13,17 +13,29 @@ const HDR_SIZE: usize = 14;
 // VLAN tag size and type
 const TAG_SIZE: usize = 4;
 const VLAN_802_1Q: usize = 0x8100;
+const VLAN_802_1AD: usize = 0x88a8;
+
+/// Returns whether `ether_type` is the TPID of a VLAN tag.
+#[inline]
+fn is_vlan_tpid(ether_type: usize) -> bool {
+    ether_type == VLAN_802_1Q || ether_type == VLAN_802_1AD
+}
 
 /// An Ethernet frame.
 ///
-/// On networks that support virtual LANs, the frame may include a VLAN tag after the source MAC
-/// address. Double-tagged frames (QinQ) are not yet supported.
+/// On networks that support virtual LANs, the frame may include VLAN tags after the source MAC
+/// address. Stacked tags (802.1ad or legacy QinQ with 802.1Q outer tags) are parsed in order, the
+/// outermost tag being the service tag (S-tag) and the innermost tag the customer tag (C-tag). If
+/// the NIC stripped the outer tag ([PortMap::vlan_strip](crate::config::PortMap::vlan_strip)), it
+/// is restored from the mbuf as the outermost tag.
 #[derive(Debug)]
 pub struct Ethernet<'a> {
     /// Fixed header.
     header: EthernetHeader,
     /// Possible VLAN headers
     vlan_headers: Vec<VlanHeader>,
+    /// VLAN ID of the outer tag stripped by the NIC.
+    stripped_vlan_id: Option<u16>,
     /// Offset to `header` from the start of `mbuf`.
     offset: usize,
     /// Packet buffer.
@@ -43,23 +55,62 @@ impl<'a> Ethernet<'a> {
         self.header.src
     }
 
-    /// Returns the encapsulated protocol identifier for untagged and single-tagged frames, and `0`
-    /// for incorrectly fornatted and (not yet supported) double-tagged frames,.
+    /// Returns the encapsulated protocol identifier, after any VLAN tags, and `0` for incorrectly
+    /// formatted frames.
     #[inline]
     pub fn ether_type(&self) -> u16 {
         self.next_header().unwrap_or(0) as u16
     }
 
+    /// Iterates over the VLAN IDs, outermost first, including a tag stripped by the NIC.
+    #[inline]
+    fn vlan_id_iter(&self) -> impl DoubleEndedIterator<Item = u16> + '_ {
+        self.stripped_vlan_id
+            .into_iter()
+            .chain(self.vlan_headers.iter().map(|elem| elem.get_vlan_id()))
+    }
+
     /// Get list of all VLAN IDs
     #[inline]
     pub fn vlan_ids(&self) -> Vec<u16> {
-        self.vlan_headers.iter().map(|elem| elem.get_vlan_id()).collect()
+        self.vlan_id_iter().collect()
     }
 
     /// Get last VLAN ID
     #[inline]
     pub fn get_last_vlan_id(&self) -> Option<u16> {
-        self.vlan_headers.last().map(|elem| elem.get_vlan_id())
+        self.vlan_id_iter().next_back()
+    }
+
+    /// Get the service tag (S-tag) VLAN ID, i.e. the outermost tag of a frame with stacked tags.
+    /// `None` for untagged and single-tagged frames.
+    #[inline]
+    pub fn s_tag(&self) -> Option<u16> {
+        if self.vlan_id_iter().nth(1).is_some() {
+            self.vlan_id_iter().next()
+        } else {
+            None
+        }
+    }
+
+    /// Get the customer tag (C-tag) VLAN ID, i.e. the innermost tag. `None` for untagged frames.
+    #[inline]
+    pub fn c_tag(&self) -> Option<u16> {
+        self.get_last_vlan_id()
+    }
+
+    /// Get FNV-1a hash of the VLAN ID stack, outermost first. `0` if there are no VLAN tags.
+    #[inline]
+    pub fn vlan_stack_hash(&self) -> u64 {
+        if self.vlan_headers.is_empty() && self.stripped_vlan_id.is_none() {
+            return 0;
+        }
+        self.vlan_id_iter().fold(0xcbf2_9ce4_8422_2325, |hash, vlan_id| {
+            vlan_id
+                .to_be_bytes()
+                .iter()
+                .fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100_0000_01b3))
+        })
     }
 }
 
@@ -91,13 +142,13 @@ impl<'a> Packet<'a> for Ethernet<'a> {
     {
         if let Ok(header) = outer.mbuf().get_data(0) {
             let current_header: EthernetHeader = unsafe { *header };
-            let vlan_headers = if u16::from(current_header.ether_type) as usize == VLAN_802_1Q {
+            let vlan_headers = if is_vlan_tpid(u16::from(current_header.ether_type) as usize) {
                 let mut vlans = vec![];
                 let mut offset = current_header.length();
                 loop {
                     let next: *const VlanHeader = outer.mbuf().get_data(offset).map_err(|_| anyhow!(PacketParseError::InvalidRead))?;
                     vlans.push(unsafe { *next });
-                    if u16::from(vlans.last().unwrap().ether_type) as usize == VLAN_802_1Q {
+                    if is_vlan_tpid(u16::from(vlans.last().unwrap().ether_type) as usize) {
                         offset += vlans.last().unwrap().length();
                     } else {
                         break vlans;
@@ -109,6 +160,7 @@ impl<'a> Packet<'a> for Ethernet<'a> {
             Ok(Ethernet {
                 header: unsafe { *header },
                 vlan_headers,
+                stripped_vlan_id: outer.mbuf().stripped_vlan_tci().map(|tci| tci & 0x0FFF),
                 offset: 0,
                 mbuf: outer.mbuf(),
             })
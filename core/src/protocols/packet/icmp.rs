@@ -0,0 +1,154 @@
+//! ICMP and ICMPv6 packet.
+
+use crate::memory::mbuf::Mbuf;
+use crate::protocols::packet::{Packet, PacketHeader, PacketParseError};
+use crate::utils::types::*;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+/// ICMP assigned protocol number.
+pub const ICMP_PROTOCOL: usize = 1;
+/// ICMPv6 assigned protocol number.
+pub const ICMPV6_PROTOCOL: usize = 58;
+const ICMP_HEADER_LEN: usize = 8;
+
+// Ethernet types of the datagrams embedded in error messages, see `Packet::next_header`.
+const IPV4_PROTOCOL: usize = 0x0800;
+const IPV6_PROTOCOL: usize = 0x86DD;
+
+/// Kind of an ICMP error message.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IcmpErrorKind {
+    /// Destination unreachable.
+    Unreachable,
+    /// Time (TTL or hop limit) exceeded.
+    TimeExceeded,
+    /// Packet too big (ICMPv6 only).
+    PacketTooBig,
+    /// Parameter problem.
+    ParameterProblem,
+}
+
+/// An ICMP or ICMPv6 packet.
+///
+/// Error messages embed the beginning of the original datagram, which can be parsed from the
+/// packet as an `Ipv4` or `Ipv6` packet.
+#[derive(Debug)]
+pub struct Icmp<'a> {
+    /// Fixed header.
+    header: IcmpHeader,
+    /// Whether the packet is ICMPv6.
+    is_v6: bool,
+    /// Offset to `header` from the start of `mbuf`.
+    offset: usize,
+    /// Packet buffer.
+    mbuf: &'a Mbuf,
+}
+
+impl<'a> Icmp<'a> {
+    /// Returns the message type.
+    #[inline]
+    pub fn icmp_type(&self) -> u8 {
+        self.header.icmp_type
+    }
+
+    /// Returns the message code.
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.header.code
+    }
+
+    /// Returns the ICMP checksum.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.header.checksum.into()
+    }
+
+    /// Returns the type-specific rest of the header.
+    #[inline]
+    pub fn rest_of_header(&self) -> u32 {
+        self.header.rest_of_header.into()
+    }
+
+    /// Returns whether the packet is ICMPv6.
+    #[inline]
+    pub fn is_v6(&self) -> bool {
+        self.is_v6
+    }
+
+    /// Returns the kind of error message, `None` if the message is not an error.
+    pub fn error_kind(&self) -> Option<IcmpErrorKind> {
+        match (self.is_v6, self.icmp_type()) {
+            (false, 3) | (true, 1) => Some(IcmpErrorKind::Unreachable),
+            (false, 11) | (true, 3) => Some(IcmpErrorKind::TimeExceeded),
+            (true, 2) => Some(IcmpErrorKind::PacketTooBig),
+            (false, 12) | (true, 4) => Some(IcmpErrorKind::ParameterProblem),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Packet<'a> for Icmp<'a> {
+    fn mbuf(&self) -> &Mbuf {
+        self.mbuf
+    }
+
+    fn header_len(&self) -> usize {
+        self.header.length()
+    }
+
+    fn next_header_offset(&self) -> usize {
+        self.offset + self.header_len()
+    }
+
+    /// Returns the Ethernet type of the embedded datagram for error messages, `None` otherwise.
+    fn next_header(&self) -> Option<usize> {
+        self.error_kind()?;
+        if self.is_v6 {
+            Some(IPV6_PROTOCOL)
+        } else {
+            Some(IPV4_PROTOCOL)
+        }
+    }
+
+    fn parse_from(outer: &'a impl Packet<'a>) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = outer.next_header_offset();
+        if let Ok(header) = outer.mbuf().get_data(offset) {
+            let is_v6 = match outer.next_header() {
+                Some(ICMP_PROTOCOL) => false,
+                Some(ICMPV6_PROTOCOL) => true,
+                _ => bail!(PacketParseError::InvalidProtocol),
+            };
+            Ok(Icmp {
+                header: unsafe { *header },
+                is_v6,
+                offset,
+                mbuf: outer.mbuf(),
+            })
+        } else {
+            bail!(PacketParseError::InvalidRead)
+        }
+    }
+}
+
+/// ICMP header.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct IcmpHeader {
+    icmp_type: u8,
+    code: u8,
+    checksum: u16be,
+    rest_of_header: u32be,
+}
+
+impl PacketHeader for IcmpHeader {
+    /// Header length measured in bytes. Equivalent to the payload offset.
+    fn length(&self) -> usize {
+        ICMP_HEADER_LEN
+    }
+}
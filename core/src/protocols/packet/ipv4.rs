@@ -8,7 +8,8 @@ use std::net::Ipv4Addr;
 
 use anyhow::{bail, Result};
 
-const IPV4_PROTOCOL: usize = 0x0800;
+/// IPv4 EtherType.
+pub const IPV4_PROTOCOL: usize = 0x0800;
 
 /// An IPv4 packet.
 ///
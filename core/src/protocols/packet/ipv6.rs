@@ -8,7 +8,8 @@ use std::net::Ipv6Addr;
 
 use anyhow::{bail, Result};
 
-const IPV6_PROTOCOL: usize = 0x86DD;
+/// IPv6 EtherType.
+pub const IPV6_PROTOCOL: usize = 0x86DD;
 const IPV6_HEADER_LEN: usize = 40;
 
 /// An IPv6 packet.
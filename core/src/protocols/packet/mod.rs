@@ -5,7 +5,9 @@
 //! [pnet::packet](https://docs.rs/pnet/latest/pnet/packet/index.html). Every packet type represents
 //! a single frame on the wire.
 
+pub mod builder;
 pub mod ethernet;
+pub mod icmp;
 pub mod ipv4;
 pub mod ipv6;
 pub mod tcp;
@@ -0,0 +1,431 @@
+//! Protocol parser registry.
+//!
+//! [L4Context::new](crate::protocols::layer4::L4Context::new) builds its context by walking the
+//! registered protocol parsers in order until one of them recognizes the packet. Retina registers
+//! parsers for TCP and UDP over IPv4 and IPv6. Additional L3, L4, or tunnel parsers can be added by
+//! implementing [ProtocolParser](ProtocolParser) and registering them with
+//! [register_parser](register_parser). User-registered parsers are tried before the built-in ones,
+//! in registration order, so that e.g. a tunnel parser can take precedence over the plain UDP one.
+//! Each core parses with its own snapshot of the registered parsers, which it replaces on the next
+//! packet after a parser is registered.
+//!
+//! ## Example
+//! ```
+//! struct GreParser;
+//!
+//! impl ProtocolParser for GreParser {
+//!     fn name(&self) -> &str {
+//!         "gre"
+//!     }
+//!
+//!     fn parse(&self, eth: &Ethernet) -> Result<Option<L4Context>> {
+//!         // Return Ok(None) if the packet is not GRE
+//!         ...
+//!     }
+//! }
+//!
+//! register_parser(GreParser);
+//! ```
+//!
+//! ## Tunnels
+//! Tunnel parsers, e.g. for VXLAN, GRE, IP-in-IP or GTP, must call [decapsulate](decapsulate)
+//! with the length of the tunnel headers before parsing each encapsulated layer, and return its
+//! error. Frames nested beyond the guards of the `[decap]` options of the runtime configuration
+//! (see [DecapConfig](crate::config::DecapConfig)) then fail with
+//! [ParseError::DecapDepth](ParseError::DecapDepth) or
+//! [ParseError::DecapBytes](ParseError::DecapBytes), and are counted against
+//! [DropReason::DecapLimit](DropReason::DecapLimit). The guards apply to each frame walked by the
+//! registry, whichever parser handles it.
+//!
+//! ## Errors
+//! Frames that cannot be parsed fail with a [ParseError](ParseError), which tells why they were
+//! rejected and maps to the [DropReason](crate::filter::drops::DropReason) they are counted
+//! against. It can be recovered from the returned error with [ParseError::of](ParseError::of), and
+//! frames rejected by [FilterCtx::parse_l4](crate::filter::FilterCtx::parse_l4) are delivered to
+//! the [parse error hooks](crate::hooks::Hooks::on_parse_error) along with the error.
+
+use crate::config::DecapConfig;
+use crate::filter::drops::DropReason;
+use crate::protocols::layer4::L4Context;
+use crate::protocols::packet::ethernet::Ethernet;
+use crate::protocols::packet::tcp::{Tcp, TCP_PROTOCOL};
+use crate::protocols::packet::udp::{Udp, UDP_PROTOCOL};
+use crate::protocols::packet::ipv4::{Ipv4, IPV4_PROTOCOL};
+use crate::protocols::packet::ipv6::{Ipv6, IPV6_PROTOCOL};
+use crate::protocols::packet::Packet;
+
+use std::cell::{Cell, RefCell};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use thiserror::Error;
+
+/// IPv6 fragment extension header.
+const IPV6_FRAGMENT: usize = 44;
+
+/// Fragment offset bits of the IPv4 flags and fragment offset field.
+const IPV4_FRAGMENT_OFFSET: u16 = 0x1fff;
+
+/// Reason a frame could not be parsed.
+#[derive(Error, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("Not Ethernet")]
+    NotEthernet,
+
+    /// No registered parser handles the L3 protocol of the frame.
+    #[error("Unsupported L3 protocol")]
+    UnsupportedL3,
+
+    /// The IP payload is neither TCP nor UDP. Carries the IP protocol number, or the IPv6 next
+    /// header.
+    #[error("Unsupported L4 protocol {0}")]
+    UnsupportedL4(u8),
+
+    /// Non-initial IPv4 fragment, or IPv6 fragment. Fragments are not reassembled, and only
+    /// initial IPv4 fragments carry the transport header.
+    #[error("Fragmented datagram")]
+    Fragmented,
+
+    /// The frame ends within a header.
+    #[error("Truncated header")]
+    TruncatedHeader,
+
+    /// The IP length is shorter than the headers it carries.
+    #[error("Bad length")]
+    BadLength,
+
+    #[error("Bad checksum")]
+    BadChecksum,
+
+    /// Malformed embedded datagram, e.g. in an ICMP error.
+    #[error("Malformed packet")]
+    Malformed,
+
+    /// The frame nests more encapsulation layers than allowed.
+    #[error("Too many encapsulation layers")]
+    DecapDepth,
+
+    /// The tunnel headers of the frame exceed the allowed number of bytes.
+    #[error("Too many tunnel header bytes")]
+    DecapBytes,
+}
+
+impl ParseError {
+    /// Returns the parse error carried by `error`, if any.
+    pub fn of(error: &anyhow::Error) -> Option<ParseError> {
+        error.downcast_ref::<ParseError>().copied()
+    }
+
+    /// Returns the drop reason frames rejected with this error are counted against.
+    pub fn drop_reason(&self) -> DropReason {
+        match self {
+            ParseError::NotEthernet => DropReason::NotEthernet,
+            ParseError::UnsupportedL3 => DropReason::NotIp,
+            ParseError::UnsupportedL4(_) => DropReason::NotTcpOrUdp,
+            ParseError::Fragmented
+            | ParseError::TruncatedHeader
+            | ParseError::BadLength
+            | ParseError::Malformed => DropReason::Malformed,
+            ParseError::BadChecksum => DropReason::BadChecksum,
+            ParseError::DecapDepth | ParseError::DecapBytes => DropReason::DecapLimit,
+        }
+    }
+
+    /// Returns the name of the error, as used in logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParseError::NotEthernet => "not_ethernet",
+            ParseError::UnsupportedL3 => "unsupported_l3",
+            ParseError::UnsupportedL4(_) => "unsupported_l4",
+            ParseError::Fragmented => "fragmented",
+            ParseError::TruncatedHeader => "truncated_header",
+            ParseError::BadLength => "bad_length",
+            ParseError::BadChecksum => "bad_checksum",
+            ParseError::Malformed => "malformed",
+            ParseError::DecapDepth => "decap_depth",
+            ParseError::DecapBytes => "decap_bytes",
+        }
+    }
+}
+
+/// A parser that builds an [L4Context](L4Context) from an Ethernet frame.
+pub trait ProtocolParser: Send + Sync {
+    /// Name of the parser, used in logs.
+    fn name(&self) -> &str;
+
+    /// Parses `eth`. Returns `Ok(None)` if the frame is not handled by this parser, in which case
+    /// the next parser is tried, and an error if the frame is handled but malformed or
+    /// unsupported. Errors should carry a [ParseError](ParseError), or a [DropReason](DropReason),
+    /// so that they are accounted for.
+    fn parse(&self, eth: &Ethernet) -> Result<Option<L4Context>>;
+}
+
+#[derive(Default, Clone)]
+struct Registry {
+    custom: Vec<Arc<dyn ProtocolParser>>,
+    builtin: Vec<Arc<dyn ProtocolParser>>,
+}
+
+/// Returns the registered parsers. The registry is replaced as a whole when a parser is
+/// registered, so that cores can keep parsing with their snapshot.
+fn registry() -> &'static RwLock<Arc<Registry>> {
+    static REGISTRY: OnceLock<RwLock<Arc<Registry>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        RwLock::new(Arc::new(Registry {
+            custom: vec![],
+            builtin: vec![Arc::new(Ipv4Parser), Arc::new(Ipv6Parser)],
+        }))
+    })
+}
+
+/// Generation of the registry, incremented when a parser is registered.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Snapshot of the registry the core parses with, along with its generation.
+    static SNAPSHOT: RefCell<Option<(u64, Arc<Registry>)>> = const { RefCell::new(None) };
+}
+
+/// Returns the current registry along with its generation.
+fn snapshot() -> Result<(u64, Arc<Registry>)> {
+    let registry = match registry().read() {
+        Ok(registry) => registry,
+        Err(_) => bail!("Protocol parser registry poisoned"),
+    };
+    Ok((GENERATION.load(Ordering::Acquire), Arc::clone(&registry)))
+}
+
+/// Registers `parser`. It is tried after previously registered parsers, and before the built-in
+/// parsers.
+pub fn register_parser(parser: impl ProtocolParser + 'static) {
+    log::info!("Registered protocol parser: {}", parser.name());
+    // The registry is only ever replaced, so a panic while holding the lock cannot corrupt it
+    let mut registry = registry().write().unwrap_or_else(PoisonError::into_inner);
+    let mut updated = Registry::clone(&registry);
+    updated.custom.push(Arc::new(parser));
+    *registry = Arc::new(updated);
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+static MAX_DECAP_DEPTH: AtomicUsize = AtomicUsize::new(4);
+static MAX_DECAP_BYTES: AtomicUsize = AtomicUsize::new(512);
+static NB_DECAP_DEPTH: AtomicU64 = AtomicU64::new(0);
+static NB_DECAP_BYTES: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Number of layers and header bytes decapsulated from the frame being parsed.
+    static DECAP: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+/// Applies the `[decap]` options of the runtime configuration.
+pub(crate) fn set_decap_limits(config: &DecapConfig) {
+    MAX_DECAP_DEPTH.store(config.max_depth, Ordering::Relaxed);
+    MAX_DECAP_BYTES.store(config.max_header_bytes, Ordering::Relaxed);
+}
+
+/// Records that the frame being parsed encapsulates another layer after `header_len` bytes of
+/// tunnel headers. Fails if the frame then exceeds the decapsulation guards, see
+/// [tunnels](self#tunnels).
+pub fn decapsulate(header_len: usize) -> Result<()> {
+    let (depth, bytes) = DECAP.with(|decap| {
+        let (depth, bytes) = decap.get();
+        let updated = (depth + 1, bytes + header_len);
+        decap.set(updated);
+        updated
+    });
+    let error = if depth > MAX_DECAP_DEPTH.load(Ordering::Relaxed) {
+        NB_DECAP_DEPTH.fetch_add(1, Ordering::Relaxed);
+        ParseError::DecapDepth
+    } else if bytes > MAX_DECAP_BYTES.load(Ordering::Relaxed) {
+        NB_DECAP_BYTES.fetch_add(1, Ordering::Relaxed);
+        ParseError::DecapBytes
+    } else {
+        return Ok(());
+    };
+    crate::log_limited!(
+        Duration::from_secs(10),
+        log::Level::Warn,
+        "Decapsulation guard triggered: {} layers, {} header bytes",
+        depth,
+        bytes
+    );
+    bail!(error);
+}
+
+/// Returns the number of frames rejected for nesting too many layers, and for exceeding the
+/// tunnel header bytes.
+pub fn decap_limit_counts() -> (u64, u64) {
+    (
+        NB_DECAP_DEPTH.load(Ordering::Relaxed),
+        NB_DECAP_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+/// Walks the registered parsers until one handles `eth`.
+/// Packets the NIC found a bad checksum in are flagged with
+/// [bad_checksum](L4Context::bad_checksum).
+pub(crate) fn parse(eth: &Ethernet) -> Result<L4Context> {
+    DECAP.with(|decap| decap.set((0, 0)));
+    let generation = GENERATION.load(Ordering::Acquire);
+    SNAPSHOT.with(|cached| {
+        if cached.borrow().as_ref().map(|(cached, _)| *cached) != Some(generation) {
+            // A parser parsing an encapsulated frame keeps the snapshot of the outer frame
+            if let Ok(mut cached) = cached.try_borrow_mut() {
+                *cached = Some(snapshot()?);
+            }
+        }
+        let cached = cached.borrow();
+        let (_, registry) = cached.as_ref().context("No protocol parser registry")?;
+        for parser in registry.custom.iter().chain(registry.builtin.iter()) {
+            if let Some(mut ctx) = parser.parse(eth)? {
+                ctx.bad_checksum |= eth.mbuf().has_bad_checksum();
+                return Ok(ctx);
+            }
+        }
+        bail!(ParseError::UnsupportedL3);
+    })
+}
+
+/* --------------------------------------------------------------------------------- */
+
+/// Built-in parser for TCP and UDP over IPv4.
+struct Ipv4Parser;
+
+impl ProtocolParser for Ipv4Parser {
+    fn name(&self) -> &str {
+        "ipv4"
+    }
+
+    fn parse(&self, eth: &Ethernet) -> Result<Option<L4Context>> {
+        if eth.next_header() != Some(IPV4_PROTOCOL) {
+            return Ok(None);
+        }
+        let ipv4 = match eth.parse_to::<Ipv4>() {
+            Ok(ipv4) => ipv4,
+            Err(_) => bail!(ParseError::TruncatedHeader),
+        };
+        if ipv4.flags_to_fragment_offset() & IPV4_FRAGMENT_OFFSET != 0 {
+            bail!(ParseError::Fragmented);
+        }
+        let (src_port, dst_port, proto, offset, header_len) = match ipv4.protocol() as usize {
+            TCP_PROTOCOL => {
+                let tcp = transport::<Tcp>(&ipv4)?;
+                (
+                    tcp.src_port(),
+                    tcp.dst_port(),
+                    TCP_PROTOCOL,
+                    tcp.next_header_offset(),
+                    tcp.header_len(),
+                )
+            }
+            UDP_PROTOCOL => {
+                let udp = transport::<Udp>(&ipv4)?;
+                (
+                    udp.src_port(),
+                    udp.dst_port(),
+                    UDP_PROTOCOL,
+                    udp.next_header_offset(),
+                    udp.header_len(),
+                )
+            }
+            _ => bail!(ParseError::UnsupportedL4(ipv4.protocol())),
+        };
+        let payload_size = match (ipv4.total_length() as usize)
+            .checked_sub(ipv4.header_len() + header_len)
+        {
+            Some(payload_size) => payload_size,
+            None => bail!(ParseError::BadLength),
+        };
+        Ok(Some(L4Context {
+            src: SocketAddr::new(IpAddr::V4(ipv4.src_addr()), src_port),
+            dst: SocketAddr::new(IpAddr::V4(ipv4.dst_addr()), dst_port),
+            proto,
+            offset,
+            length: payload_size,
+            vlan_id: eth.get_last_vlan_id(),
+            vlan_stack_hash: eth.vlan_stack_hash(),
+            s_tag: eth.s_tag(),
+            c_tag: eth.c_tag(),
+            traffic_class: ipv4.type_of_service(),
+            flow_label: None,
+            src_mac: eth.src(),
+            dst_mac: eth.dst(),
+            bad_checksum: false,
+        }))
+    }
+}
+
+/// Built-in parser for TCP and UDP over IPv6.
+struct Ipv6Parser;
+
+impl ProtocolParser for Ipv6Parser {
+    fn name(&self) -> &str {
+        "ipv6"
+    }
+
+    fn parse(&self, eth: &Ethernet) -> Result<Option<L4Context>> {
+        if eth.next_header() != Some(IPV6_PROTOCOL) {
+            return Ok(None);
+        }
+        let ipv6 = match eth.parse_to::<Ipv6>() {
+            Ok(ipv6) => ipv6,
+            Err(_) => bail!(ParseError::TruncatedHeader),
+        };
+        let (src_port, dst_port, proto, offset, header_len) = match ipv6.next_header() as usize {
+            TCP_PROTOCOL => {
+                let tcp = transport::<Tcp>(&ipv6)?;
+                (
+                    tcp.src_port(),
+                    tcp.dst_port(),
+                    TCP_PROTOCOL,
+                    tcp.next_header_offset(),
+                    tcp.header_len(),
+                )
+            }
+            UDP_PROTOCOL => {
+                let udp = transport::<Udp>(&ipv6)?;
+                (
+                    udp.src_port(),
+                    udp.dst_port(),
+                    UDP_PROTOCOL,
+                    udp.next_header_offset(),
+                    udp.header_len(),
+                )
+            }
+            IPV6_FRAGMENT => bail!(ParseError::Fragmented),
+            _ => bail!(ParseError::UnsupportedL4(ipv6.next_header())),
+        };
+        let payload_size = match (ipv6.payload_length() as usize).checked_sub(header_len) {
+            Some(payload_size) => payload_size,
+            None => bail!(ParseError::BadLength),
+        };
+        Ok(Some(L4Context {
+            src: SocketAddr::new(IpAddr::V6(ipv6.src_addr()), src_port),
+            dst: SocketAddr::new(IpAddr::V6(ipv6.dst_addr()), dst_port),
+            proto,
+            offset,
+            length: payload_size,
+            vlan_id: eth.get_last_vlan_id(),
+            vlan_stack_hash: eth.vlan_stack_hash(),
+            s_tag: eth.s_tag(),
+            c_tag: eth.c_tag(),
+            traffic_class: ipv6.traffic_class(),
+            flow_label: Some(ipv6.flow_label()),
+            src_mac: eth.src(),
+            dst_mac: eth.dst(),
+            bad_checksum: false,
+        }))
+    }
+}
+
+/// Parses the transport header following `ip`, whose protocol is already known to be that of
+/// `T`.
+fn transport<'a, T: Packet<'a>>(ip: &'a impl Packet<'a>) -> Result<T> {
+    match ip.parse_to::<T>() {
+        Ok(header) => Ok(header),
+        Err(_) => bail!(ParseError::TruncatedHeader),
+    }
+}
@@ -3,18 +3,28 @@
 //! The runtime initializes the DPDK environment abstraction layer, creates memory pools, launches
 //! the packet processing cores, and manages logging and display output.
 
+pub(crate) mod notify;
+pub(crate) mod signals;
+mod numa;
 mod online;
 use self::online::*;
+pub use self::notify::Readiness;
 
+use crate::bridge::AsyncBridge;
 use crate::config::*;
 use crate::dpdk;
 use crate::filter::FilterCtx;
+use crate::hooks::Hooks;
 use crate::lcore::SocketId;
 use crate::memory::mempool::Mempool;
+use crate::output::OutputKind;
 use crate::subscription::*;
+use crate::timebase;
 
 use std::collections::BTreeMap;
 use std::ffi::CString;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::{bail, Result};
@@ -30,6 +40,11 @@ where
     #[allow(dead_code)]
     mempools: BTreeMap<SocketId, Mempool>,
     online: OnlineRuntime<'a, S>,
+    hooks: Arc<Hooks>,
+    bridge: Arc<AsyncBridge>,
+    readiness: Readiness,
+    /// Flow state file and the context whose flow table is saved to it on shutdown.
+    flow_snapshot: Option<(PathBuf, FilterCtx)>,
     #[cfg(feature = "timing")]
     subscription: Arc<Subscription<'a, S>>,
 }
@@ -52,13 +67,37 @@ where
     /// let mut runtime = Runtime::new(config, filter, callback)?;
     /// ```
     pub fn new(
-        config: RuntimeConfig,
+        mut config: RuntimeConfig,
         cb: impl Fn(S, &FilterCtx) + 'a,
         filter_ctx: &FilterCtx
     ) -> Result<Self> {
         let subscription = Arc::new(Subscription::new(cb));
+        config.apply_instance()?;
+        config.mempool.validate()?;
+        config.log_sources();
+        filter_ctx.configure(&config)?;
+        let flow_snapshot = config.flow_snapshot.as_ref().map(|flow_snapshot| {
+            let path = PathBuf::from(&flow_snapshot.state_file);
+            if path.exists() {
+                match filter_ctx.restore_flows(&path) {
+                    Ok(nb_restored) => log::info!("Restored {} flows", nb_restored),
+                    Err(error) => log::error!("Failed to restore flows: {:?}", error),
+                }
+                if let Err(error) = fs::remove_file(&path) {
+                    log::error!("Failed to remove {}: {}", path.display(), error);
+                }
+            }
+            (path, filter_ctx.clone())
+        });
+        if let Some(online) = &mut config.online {
+            if online.numa.auto_assign {
+                numa::auto_assign(online, config.main_core);
+            }
+        }
 
-        println!("Initializing Retina runtime...");
+        filter_ctx
+            .output()
+            .write(OutputKind::Status, "Initializing Retina runtime...");
         log::info!("Initializing EAL...");
         dpdk::load_drivers();
         {
@@ -78,6 +117,7 @@ where
                 bail!("Failure initializing EAL");
             }
         }
+        timebase::calibrate();
 
         log::info!("Initializing Mempools...");
         let mut mempools = BTreeMap::new();
@@ -89,10 +129,12 @@ where
         };
         for socket_id in socket_ids {
             log::debug!("Socket ID: {}", socket_id);
-            let mempool = Mempool::new(&config.mempool, socket_id, mtu)?;
+            let mempool =
+                Mempool::new(&config.mempool, config.instance.as_deref(), socket_id, mtu)?;
             mempools.insert(socket_id, mempool);
         }
 
+        let readiness = Readiness::new();
         let online = config.online.as_ref().map(|cfg| {
             log::info!("Initializing Online Runtime...");
             let online_opts = OnlineOptions {
@@ -103,7 +145,8 @@ where
                 online_opts,
                 &mut mempools,
                 Arc::clone(&subscription),
-                filter_ctx
+                filter_ctx,
+                &readiness,
             )
         }).unwrap();
 
@@ -111,6 +154,10 @@ where
         Ok(Runtime {
             mempools,
             online,
+            hooks: filter_ctx.hooks_arc(),
+            bridge: filter_ctx.async_bridge_arc(),
+            readiness,
+            flow_snapshot,
             #[cfg(feature = "timing")]
             subscription,
         })
@@ -124,7 +171,18 @@ where
     /// runtime.run();
     /// ```
     pub fn run(&mut self) {
+        self.hooks.start();
         self.online.run();
+        // TODO: re-attach the next process to the memory pools and port queues of this one, with
+        // the same EAL file prefix, so that restarts stop missing packets
+        if let Some((path, filter_ctx)) = &self.flow_snapshot {
+            match filter_ctx.save_flows(path) {
+                Ok(nb_saved) => log::info!("Saved {} flows to {}", nb_saved, path.display()),
+                Err(error) => log::error!("Failed to save flows: {:?}", error),
+            }
+        }
+        self.hooks.stop();
+        self.bridge.stop();
         #[cfg(feature = "timing")]
         {
             self.subscription.timers.display_stats();
@@ -133,6 +191,12 @@ where
         log::info!("Done.");
     }
 
+    /// Returns a handle to wait until the runtime processes packets, i.e. its ports are started and
+    /// all RX cores are polling.
+    pub fn readiness(&self) -> Readiness {
+        self.readiness.clone()
+    }
+
     pub fn get_filter_ctxs_ref(&self) -> Vec<&FilterCtx> {
         self.online.rx_cores.values().map(|core| &core.filter_ctx).collect()
     }
@@ -0,0 +1,196 @@
+//! Readiness notification.
+//!
+//! The runtime is ready once its ports are started and all RX cores are polling their queues.
+//! Embedders can wait for it with the [Readiness](Readiness) handle returned by
+//! [Runtime::readiness](crate::Runtime::readiness), typically from another thread than the one
+//! calling [Runtime::run](crate::Runtime::run).
+//!
+//! With the `[notify]` options of the runtime configuration (see
+//! [NotifyConfig](crate::config::NotifyConfig)), the runtime also reports its state to the service
+//! manager over the `NOTIFY_SOCKET` datagram socket, following the `sd_notify` protocol:
+//! `READY=1` once ready, `WATCHDOG=1` from the main core at half the `WATCHDOG_USEC` interval, and
+//! `STOPPING=1` when the main core stops monitoring on shutdown. Nothing is sent if the runtime was
+//! not started by a service manager.
+//!
+//! ## Example
+//! ```
+//! let ready = runtime.readiness();
+//! thread::spawn(move || {
+//!     if ready.wait_ready(Some(Duration::from_secs(30))) {
+//!         println!("Processing packets");
+//!     }
+//! });
+//! runtime.run();
+//! ```
+
+use crate::config::NotifyConfig;
+
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+#[derive(Debug, Default)]
+struct ReadyState {
+    /// Number of RX cores launched by the runtime.
+    nb_cores: AtomicUsize,
+    /// Number of RX cores polling their queues.
+    nb_polling: AtomicUsize,
+    ready: Mutex<bool>,
+    changed: Condvar,
+}
+
+/// Handle to wait for the runtime to process packets, shared by the runtime and its cores.
+#[derive(Debug, Clone, Default)]
+pub struct Readiness {
+    state: Arc<ReadyState>,
+}
+
+impl Readiness {
+    pub(crate) fn new() -> Self {
+        Readiness::default()
+    }
+
+    /// Returns whether all RX cores are polling.
+    pub fn is_ready(&self) -> bool {
+        *self.state.ready.lock().unwrap()
+    }
+
+    /// Blocks until all RX cores are polling, or `timeout` elapsed if set. Returns whether the
+    /// runtime is ready.
+    pub fn wait_ready(&self, timeout: Option<Duration>) -> bool {
+        let ready = self.state.ready.lock().unwrap();
+        match timeout {
+            Some(timeout) => {
+                let (ready, _) = self
+                    .state
+                    .changed
+                    .wait_timeout_while(ready, timeout, |ready| !*ready)
+                    .unwrap();
+                *ready
+            }
+            None => *self.state.changed.wait_while(ready, |ready| !*ready).unwrap(),
+        }
+    }
+
+    /// Sets the number of RX cores the runtime launches.
+    pub(crate) fn expect_cores(&self, nb_cores: usize) {
+        self.state.nb_cores.store(nb_cores, Ordering::Relaxed);
+    }
+
+    /// Records that an RX core started polling. Called once by each core.
+    pub(crate) fn core_polling(&self) {
+        self.state.nb_polling.fetch_add(1, Ordering::Release);
+    }
+
+    /// Returns whether all RX cores started polling.
+    pub(crate) fn all_polling(&self) -> bool {
+        self.state.nb_polling.load(Ordering::Acquire) >= self.state.nb_cores.load(Ordering::Relaxed)
+    }
+
+    /// Marks the runtime ready and wakes up waiters.
+    pub(crate) fn set_ready(&self) {
+        *self.state.ready.lock().unwrap() = true;
+        self.state.changed.notify_all();
+    }
+}
+
+/// Service manager notifications of the main core.
+#[derive(Debug)]
+pub(crate) struct Notifier {
+    /// Notification socket and address, `None` if not started by a service manager.
+    socket: Option<(UnixDatagram, SocketAddr)>,
+    /// Watchdog ping interval, `None` if the watchdog is disabled.
+    watchdog: Option<Duration>,
+    last_ping: Instant,
+}
+
+impl Notifier {
+    /// Connects to the service manager from the environment if `config` is set.
+    pub(crate) fn new(config: Option<&NotifyConfig>) -> Self {
+        let mut notifier = Notifier {
+            socket: None,
+            watchdog: None,
+            last_ping: Instant::now(),
+        };
+        let config = match config {
+            Some(config) => config,
+            None => return notifier,
+        };
+        let path = match env::var("NOTIFY_SOCKET") {
+            Ok(path) => path,
+            Err(_) => {
+                log::info!("NOTIFY_SOCKET not set, service manager notifications disabled");
+                return notifier;
+            }
+        };
+        match Self::connect(&path) {
+            Ok(socket) => notifier.socket = Some(socket),
+            Err(error) => {
+                log::error!("Failed to open notification socket {}: {}", path, error);
+                return notifier;
+            }
+        }
+        if config.watchdog {
+            notifier.watchdog = watchdog_interval();
+        }
+        log::info!(
+            "Notifying service manager on {}, watchdog {:?}",
+            path,
+            notifier.watchdog
+        );
+        notifier
+    }
+
+    fn connect(path: &str) -> Result<(UnixDatagram, SocketAddr)> {
+        let addr = match path.strip_prefix('@') {
+            Some(abstract_name) => SocketAddr::from_abstract_name(abstract_name)?,
+            None => SocketAddr::from_pathname(path)?,
+        };
+        Ok((UnixDatagram::unbound()?, addr))
+    }
+
+    /// Notifies that the runtime is processing packets.
+    pub(crate) fn ready(&self) {
+        self.send("READY=1\nSTATUS=Processing packets");
+    }
+
+    /// Pings the watchdog if the ping interval elapsed.
+    pub(crate) fn watchdog(&mut self) {
+        if let Some(interval) = self.watchdog {
+            if self.last_ping.elapsed() >= interval {
+                self.send("WATCHDOG=1");
+                self.last_ping = Instant::now();
+            }
+        }
+    }
+
+    /// Notifies that the runtime is shutting down.
+    pub(crate) fn stopping(&self) {
+        self.send("STOPPING=1\nSTATUS=Shutting down");
+    }
+
+    fn send(&self, state: &str) {
+        if let Some((socket, addr)) = &self.socket {
+            if let Err(error) = socket.send_to_addr(state.as_bytes(), addr) {
+                log::warn!("Failed to notify service manager: {}", error);
+            }
+        }
+    }
+}
+
+/// Returns half of the watchdog timeout set by the service manager for this process.
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec / 2))
+}
@@ -0,0 +1,216 @@
+//! NUMA placement.
+//!
+//! A receive queue is served by the core that polls it and by the mempool its mbufs are allocated
+//! from. Either one residing on a different socket than the port crosses the inter-socket link for
+//! every packet, which can halve throughput without any other symptom. The placement of every
+//! queue is reported on initialization, and cross-NUMA assignments are reported as warnings or, in
+//! strict mode, errors.
+//!
+//! Cores can also be reassigned to the socket of their port before DPDK is initialized, using the
+//! topology exposed in sysfs.
+
+use crate::config::OnlineConfig;
+use crate::lcore::SocketId;
+use crate::memory::mempool::Mempool;
+use crate::port::{Port, PortId};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use tabled::builder::Builder;
+use tabled::{Panel, Style};
+
+const SYSFS_CPU: &str = "/sys/devices/system/cpu";
+const SYSFS_NODE: &str = "/sys/devices/system/node";
+const SYSFS_PCI: &str = "/sys/bus/pci/devices";
+
+/// Socket reported by DPDK when the socket of a device is unknown.
+const SOCKET_ID_ANY: SocketId = SocketId(u32::MAX);
+
+/// Replaces the cores of each port that reside on a different socket than the port with unused
+/// cores on the socket of the port. Cores are kept if the socket of the port is unknown or no
+/// unused core is left on it.
+pub(crate) fn auto_assign(online: &mut OnlineConfig, main_core: u32) {
+    let mut used: BTreeSet<u32> = BTreeSet::new();
+    used.insert(main_core);
+    for port in online.ports.iter() {
+        used.extend(port.cores.iter());
+        used.extend(port.sink_configs().map(|sink| sink.core));
+    }
+
+    for port in online.ports.iter_mut() {
+        let node = match device_node(&port.device) {
+            Some(node) => node,
+            None => {
+                log::warn!("Socket of {} unknown, cores not reassigned.", port.device);
+                continue;
+            }
+        };
+        let mut free: Vec<u32> = node_cpus(node)
+            .into_iter()
+            .filter(|cpu| !used.contains(cpu))
+            .rev()
+            .collect();
+        let sinks = port
+            .sink
+            .iter_mut()
+            .chain(port.sinks.iter_mut())
+            .map(|sink| &mut sink.core);
+        for core in port.cores.iter_mut().chain(sinks) {
+            match cpu_node(*core) {
+                Some(cpu_node) if cpu_node != node => (),
+                _ => continue,
+            }
+            match free.pop() {
+                Some(cpu) => {
+                    log::info!(
+                        "Reassigning {} from core {} to core {} on socket {}.",
+                        port.device,
+                        core,
+                        cpu,
+                        node
+                    );
+                    used.insert(cpu);
+                    *core = cpu;
+                }
+                None => log::warn!(
+                    "No unused core left on socket {} for {}, keeping core {}.",
+                    node,
+                    port.device,
+                    core
+                ),
+            }
+        }
+    }
+}
+
+/// Logs the socket of every port, receive queue core and mempool, and checks that they match.
+/// Fails on cross-NUMA assignments if `strict` is set.
+pub(crate) fn check(
+    ports: &BTreeMap<PortId, Port>,
+    mempools: &BTreeMap<SocketId, Mempool>,
+    strict: bool,
+) -> Result<()> {
+    let fmt_socket = |socket_id: SocketId| {
+        if socket_id == SOCKET_ID_ANY {
+            "unknown".to_string()
+        } else {
+            socket_id.to_string()
+        }
+    };
+
+    let mut builder = Builder::default();
+    builder.set_columns([
+        "Queue",
+        "Device",
+        "Port socket",
+        "Core",
+        "Core socket",
+        "Mempool",
+        "Mempool socket",
+    ]);
+    let mut issues = vec![];
+    for port in ports.values() {
+        let port_socket = port.id.socket_id();
+        let mempool = mempools.get(&port_socket);
+        for (rxqueue, core_id) in port.queue_map.iter() {
+            let core_socket = core_id.socket_id();
+            if port_socket != SOCKET_ID_ANY && core_socket != port_socket {
+                issues.push(format!(
+                    "Core {} (socket {}) polls {} of Port {} (socket {})",
+                    core_id, core_socket, rxqueue, port.id, port_socket
+                ));
+            }
+            let (mempool_name, mempool_socket) = match mempool {
+                Some(mempool) => (mempool.name().to_string(), fmt_socket(mempool.socket_id())),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            if let Some(mempool) = mempool {
+                if mempool.socket_id() != SOCKET_ID_ANY && mempool.socket_id() != core_socket {
+                    issues.push(format!(
+                        "Core {} (socket {}) receives {} into {} (socket {})",
+                        core_id,
+                        core_socket,
+                        rxqueue,
+                        mempool.name(),
+                        mempool.socket_id()
+                    ));
+                }
+            }
+            builder.add_record([
+                rxqueue.to_string(),
+                port.device.clone(),
+                fmt_socket(port_socket),
+                core_id.to_string(),
+                fmt_socket(core_socket),
+                mempool_name,
+                mempool_socket,
+            ]);
+        }
+        if port_socket == SOCKET_ID_ANY {
+            log::warn!(
+                "Socket of Port {} ({}) unknown, placement not checked.",
+                port.id,
+                port.device
+            );
+        }
+    }
+    let mut table = builder.build();
+    table.with(Panel::header("NUMA topology"));
+    table.with(Style::modern());
+    log::info!("\n{}", table);
+
+    if issues.is_empty() {
+        return Ok(());
+    }
+    if strict {
+        bail!("Cross-NUMA assignments: {}", issues.join("; "));
+    }
+    for issue in issues.iter() {
+        log::warn!("Cross-NUMA assignment: {}.", issue);
+    }
+    Ok(())
+}
+
+/// Returns the NUMA node of the PCI device `device`, `None` if unknown.
+fn device_node(device: &str) -> Option<u32> {
+    let node = fs::read_to_string(Path::new(SYSFS_PCI).join(device).join("numa_node")).ok()?;
+    node.trim().parse().ok()
+}
+
+/// Returns the NUMA node of `cpu`, `None` if unknown.
+fn cpu_node(cpu: u32) -> Option<u32> {
+    let entries = fs::read_dir(Path::new(SYSFS_CPU).join(format!("cpu{}", cpu))).ok()?;
+    entries.filter_map(|entry| entry.ok()).find_map(|entry| {
+        let name = entry.file_name();
+        name.to_str()?.strip_prefix("node")?.parse().ok()
+    })
+}
+
+/// Returns the CPUs of NUMA node `node`.
+fn node_cpus(node: u32) -> Vec<u32> {
+    let path = Path::new(SYSFS_NODE)
+        .join(format!("node{}", node))
+        .join("cpulist");
+    match fs::read_to_string(path) {
+        Ok(list) => parse_cpu_list(list.trim()),
+        Err(_) => vec![],
+    }
+}
+
+/// Parses a sysfs CPU list, such as `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Vec<u32> {
+    let mut cpus = vec![];
+    for range in list.split(',').filter(|range| !range.is_empty()) {
+        let bounds: Option<(u32, u32)> = match range.split_once('-') {
+            Some((start, end)) => start.parse().ok().zip(end.parse().ok()),
+            None => range.parse().ok().map(|cpu| (cpu, cpu)),
+        };
+        if let Some((start, end)) = bounds {
+            cpus.extend(start..=end);
+        }
+    }
+    cpus
+}
@@ -1,9 +1,16 @@
+use super::notify::Readiness;
+use super::numa;
 use crate::config::{OnlineConfig, RuntimeConfig};
 use crate::dpdk;
+use crate::lcore::handoff::Handoff;
 use crate::lcore::monitor::Monitor;
+use crate::lcore::queues::QueueRegistry;
+use crate::lcore::replay::Replayer;
 use crate::lcore::rx_core::RxCore;
+use crate::lcore::sink::{self, SinkTarget};
 use crate::lcore::{CoreId, SocketId};
 use crate::memory::mempool::Mempool;
+use crate::output::{Output, OutputKind};
 use crate::port::*;
 use crate::subscription::*;
 use crate::filter::FilterCtx;
@@ -21,7 +28,9 @@ where
     ports: BTreeMap<PortId, Port>,
     pub(crate) rx_cores: BTreeMap<CoreId, RxCore<'a, S>>,
     monitor: Monitor,
+    replayer: Option<Replayer>,
     options: OnlineOptions,
+    output: Arc<Output>,
 }
 
 impl<'a, S> OnlineRuntime<'a, S>
@@ -33,7 +42,8 @@ where
         options: OnlineOptions,
         mempools: &mut BTreeMap<SocketId, Mempool>,
         subscription: Arc<Subscription<'a, S>>,
-        filter_ctx: &FilterCtx
+        filter_ctx: &FilterCtx,
+        readiness: &Readiness,
     ) -> Self {
         // Set up signal handler
         let is_running = Arc::new(AtomicBool::new(true));
@@ -46,7 +56,14 @@ where
         log::info!("Initializing Ports...");
         let mut ports: BTreeMap<PortId, Port> = BTreeMap::new();
         for port_map in options.online.ports.iter() {
-            let port = Port::new(port_map);
+            if let Some(bond) = &port_map.bond {
+                bond::create(&port_map.device, bond).expect("Failed to create bonded port.");
+            }
+            let mut nb_txq = sink::nb_forwarding(&options.online.ports, &port_map.device);
+            if let Some(replay) = &options.online.replay {
+                nb_txq += (replay.port == port_map.device) as u16;
+            }
+            let port = Port::new(port_map, nb_txq);
             let socket_id = port.id.socket_id();
             mempools.entry(socket_id).or_insert_with(|| {
                 // Create a local mempool if user is not polling the port
@@ -56,7 +73,7 @@ where
                 } else {
                     Mempool::default_mtu()
                 };
-                Mempool::new(&config.mempool, socket_id, mtu)
+                Mempool::new(&config.mempool, config.instance.as_deref(), socket_id, mtu)
                     .expect("Unable to initialize local mempool")
             });
             port.init(
@@ -68,36 +85,119 @@ where
             .expect("Failed to initialize port.");
             ports.insert(port.id, port);
         }
+        numa::check(&ports, mempools, options.online.numa.strict)
+            .expect("Invalid NUMA placement.");
+        // The replay TX queue follows those of the forwarding sinks
+        let replayer = options.online.replay.as_ref().map(|replay| {
+            let queue = sink::nb_forwarding(&options.online.ports, &replay.port);
+            Replayer::new(replay, &ports, queue, mempools, Arc::clone(&is_running))
+                .expect("Invalid replay configuration.")
+        });
 
         log::info!("Initializing RX Cores...");
+        if options.online.benchmark {
+            log::warn!("Benchmark mode: received packets are counted and dropped");
+        }
         let mut rx_cores: BTreeMap<CoreId, RxCore<S>> = BTreeMap::new();
+        let mut queues = QueueRegistry::new();
         let mut core_map: BTreeMap<CoreId, Vec<RxQueue>> = BTreeMap::new();
+        let mut sinks: BTreeMap<RxQueue, SinkTarget> = BTreeMap::new();
+        let mut weights: BTreeMap<RxQueue, u32> = BTreeMap::new();
+        let mut next_txq: BTreeMap<PortId, u16> = BTreeMap::new();
         for (_port_id, port) in ports.iter() {
             for (rxqueue, core_id) in port.queue_map.iter() {
                 core_map
                     .entry(*core_id)
                     .or_insert_with(Vec::new)
                     .push(*rxqueue);
+                weights.insert(*rxqueue, port.rx_weight);
+            }
+            for (rxqueue, sink) in port.sinks.iter() {
+                let target = SinkTarget::new(sink, &ports, &mut next_txq)
+                    .expect("Invalid sink configuration.");
+                sinks.insert(*rxqueue, target);
             }
         }
+        let sflow = options
+            .online
+            .monitor
+            .as_ref()
+            .and_then(|monitor| monitor.sflow.clone());
         for (core_id, rxqueues) in core_map.into_iter() {
-            let rx_core = RxCore::new(
+            let core_sinks = rxqueues
+                .iter()
+                .filter_map(|rxqueue| Some((*rxqueue, sinks.get(rxqueue)?.clone())))
+                .collect();
+            let mut rx_core = RxCore::new(
                 core_id,
                 rxqueues,
                 Arc::clone(&subscription),
                 filter_ctx,
+                sflow.clone(),
                 Arc::clone(&is_running),
             );
+            rx_core.sinks = core_sinks;
+            rx_core.readiness = readiness.clone();
+            rx_core.benchmark = options.online.benchmark;
+            rx_core.queue_counters = rx_core
+                .rxqueues
+                .iter()
+                .map(|rxqueue| queues.register(*rxqueue))
+                .collect();
+            rx_core.weights = queue_weights(&rx_core.rxqueues, &weights);
             rx_cores.insert(core_id, rx_core);
         }
+        if let Some(software_rss) = &options.online.software_rss {
+            log::warn!("Software RSS (test mode): receive cores hand packets off to workers");
+            let rxqueues: Vec<RxQueue> = rx_cores
+                .values()
+                .flat_map(|rx_core| rx_core.rxqueues.iter())
+                .filter(|rxqueue| rxqueue.ty == RxQueueType::Receive)
+                .copied()
+                .collect();
+            let io_cores: Vec<CoreId> = rx_cores.keys().copied().collect();
+            let handoff = Handoff::new(software_rss, &rxqueues, &io_cores)
+                .expect("Invalid software RSS configuration.");
+            let handoff = Arc::new(handoff);
+            for rx_core in rx_cores.values_mut() {
+                if rx_core.rxqueues.iter().any(|rxqueue| rxqueue.ty == RxQueueType::Receive) {
+                    rx_core.handoff = Some(Arc::clone(&handoff));
+                }
+            }
+            for (worker, core_id) in handoff.workers().iter().enumerate() {
+                let mut rx_core = RxCore::new(
+                    *core_id,
+                    handoff.rxqueues(),
+                    Arc::clone(&subscription),
+                    filter_ctx,
+                    sflow.clone(),
+                    Arc::clone(&is_running),
+                );
+                rx_core.readiness = readiness.clone();
+                rx_core.handoff = Some(Arc::clone(&handoff));
+                rx_core.worker = Some(worker);
+                rx_core.weights = queue_weights(&rx_core.rxqueues, &weights);
+                rx_cores.insert(*core_id, rx_core);
+            }
+        }
 
-        let monitor = Monitor::new(config, &ports, Arc::clone(&is_running));
+        readiness.expect_cores(rx_cores.len());
+        let monitor = Monitor::new(
+            config,
+            &ports,
+            filter_ctx,
+            Arc::clone(&is_running),
+            readiness.clone(),
+            Arc::new(queues),
+        );
 
         OnlineRuntime {
             ports,
             rx_cores,
             monitor,
+            replayer,
             options,
+            output: filter_ctx.output_arc(),
         }
     }
 
@@ -122,9 +222,20 @@ where
             }
         }
 
+        let replay = self.replayer.take().and_then(|replayer| match replayer.spawn() {
+            Ok(handle) => Some(handle),
+            Err(error) => {
+                log::error!("Failed to start replay: {}", error);
+                None
+            }
+        });
+
         // run main thread
         self.run_main();
         unsafe { dpdk::rte_eal_mp_wait_lcore() };
+        if let Some(handle) = replay {
+            let _ = handle.join();
+        }
 
         log::info!("Exiting loop...");
         self.stop_ports();
@@ -135,7 +246,10 @@ where
         log::info!("Running main on Core {}", id);
         let start = Instant::now();
         self.monitor.run();
-        println!("Main done. Ran for {:?}", start.elapsed());
+        self.output.write(
+            OutputKind::Status,
+            format!("Main done. Ran for {:?}", start.elapsed()),
+        );
     }
 
     fn start_ports(&self) {
@@ -159,6 +273,14 @@ pub(crate) struct OnlineOptions {
     pub(crate) online: OnlineConfig
 }
 
+/// Returns the servicing weight of each queue of `rxqueues`, `1` if unknown.
+fn queue_weights(rxqueues: &[RxQueue], weights: &BTreeMap<RxQueue, u32>) -> Vec<u32> {
+    rxqueues
+        .iter()
+        .map(|rxqueue| weights.get(rxqueue).copied().unwrap_or(1))
+        .collect()
+}
+
 extern "C" fn launch_rx<S>(arg: *mut c_void) -> i32
 where
     S: Subscribable,
@@ -0,0 +1,195 @@
+//! Signal-driven state dumps and tracing.
+//!
+//! With the `signals` options of the monitor (see [SignalConfig](crate::config::SignalConfig)) and
+//! the `signal-dump` feature, the runtime can be inspected with signals when it looks stuck:
+//! - `SIGUSR1` dumps a [StateDump](StateDump): the cycles, drops and rule set generation of each
+//!   RX core, the software counters of each RX queue, the flow table occupancy and the depth of
+//!   the storage and async bridge queues. The dump is logged, and written as JSON to
+//!   `state-<timestamp>.json` in the monitor log directory if logging.
+//! - `SIGUSR2` toggles [packet tracing](crate::filter::trace) on the configured core. When tracing
+//!   is turned off, the recorded events are logged.
+//!
+//! The signal handlers only set a flag. The main core checks the flags between monitoring tasks,
+//! so a dump reflects the state of the RX cores and the flow table even if the callback is stuck,
+//! but not if the main core is.
+
+use crate::bridge::AsyncBridgeStats;
+use crate::filter::cycles::CoreCycles;
+use crate::filter::priority::ClassStats;
+use crate::filter::rule::RuleGenerations;
+use crate::filter::table::FlowTableStats;
+use crate::filter::FilterCtx;
+use crate::lcore::queues::QueueStats;
+use crate::timebase;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "signal-dump")]
+use anyhow::bail;
+use anyhow::Result;
+use serde::Serialize;
+
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+static TRACE_TOGGLED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "signal-dump")]
+extern "C" fn handle_signal(signal: libc::c_int) {
+    match signal {
+        libc::SIGUSR1 => DUMP_REQUESTED.store(true, Ordering::Relaxed),
+        libc::SIGUSR2 => TRACE_TOGGLED.store(true, Ordering::Relaxed),
+        _ => (),
+    }
+}
+
+/// Installs the `SIGUSR1` and `SIGUSR2` handlers.
+#[cfg(feature = "signal-dump")]
+pub(crate) fn install() -> Result<()> {
+    for signal in [libc::SIGUSR1, libc::SIGUSR2] {
+        let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            bail!("Failed to install handler of signal {}", signal);
+        }
+    }
+    log::info!("SIGUSR1 dumps the runtime state, SIGUSR2 toggles tracing");
+    Ok(())
+}
+
+#[cfg(not(feature = "signal-dump"))]
+pub(crate) fn install() -> Result<()> {
+    log::warn!("SIGUSR1 and SIGUSR2 are not handled without the `signal-dump` feature");
+    Ok(())
+}
+
+/// Returns whether a state dump was requested since the last call.
+pub(crate) fn take_dump_request() -> bool {
+    DUMP_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// Returns whether tracing was toggled since the last call.
+pub(crate) fn take_trace_toggle() -> bool {
+    TRACE_TOGGLED.swap(false, Ordering::Relaxed)
+}
+
+/// State of an RX core.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct CoreState {
+    core: u32,
+    cycles: CoreCycles,
+    /// Packets not inspected by software, by drop reason.
+    drops: BTreeMap<&'static str, u64>,
+    /// Rule set generation the core matches against.
+    rule_generation: Option<u64>,
+    tracing: bool,
+}
+
+/// Snapshot of the runtime state.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct StateDump {
+    /// Time of the dump, in nanoseconds since the UNIX epoch.
+    ts: u64,
+    rule_generations: RuleGenerations,
+    nb_rules: usize,
+    cores: Vec<CoreState>,
+    queues: Vec<QueueStats>,
+    flow_table: FlowTableStats,
+    /// Counters and backlog of each priority class of the storage queues.
+    storage_classes: Vec<ClassStats>,
+    async_bridge: Option<AsyncBridgeStats>,
+}
+
+impl StateDump {
+    pub(crate) fn collect(filter_ctx: &FilterCtx, queues: Vec<QueueStats>) -> Self {
+        let rule_generations = filter_ctx.rule_generations();
+        let drops = filter_ctx.drop_stats();
+        let cores = filter_ctx
+            .core_cycles()
+            .into_iter()
+            .map(|cycles| CoreState {
+                core: cycles.core,
+                cycles,
+                drops: drops
+                    .get(&Some(cycles.core))
+                    .map_or_else(BTreeMap::new, |counts| {
+                        counts
+                            .iter()
+                            .map(|(reason, count)| (reason.name(), count))
+                            .collect()
+                    }),
+                rule_generation: rule_generations.cores.get(&cycles.core).copied(),
+                tracing: filter_ctx.is_tracing(cycles.core),
+            })
+            .collect();
+        StateDump {
+            ts: timebase::now_ns(),
+            nb_rules: filter_ctx.rule_stats().nb_rules,
+            rule_generations,
+            cores,
+            queues,
+            flow_table: filter_ctx.flow_table_stats(),
+            storage_classes: filter_ctx.storage_class_stats(),
+            async_bridge: filter_ctx.async_bridge().stats(),
+        }
+    }
+
+    /// Logs the dump.
+    pub(crate) fn log(&self) {
+        log::info!(
+            "State dump: rule set generation {}, {} rules, {} flows tracked",
+            self.rule_generations.generation,
+            self.nb_rules,
+            self.flow_table.nb_entries
+        );
+        for core in self.cores.iter() {
+            log::info!(
+                "  core {}: {} pkts, {:.1}% busy, generation {}, drops {:?}{}",
+                core.core,
+                core.cycles.nb_pkts,
+                core.cycles.utilization() * 100.0,
+                core.rule_generation
+                    .map_or("-".into(), |generation| generation.to_string()),
+                core.drops,
+                if core.tracing { ", tracing" } else { "" }
+            );
+        }
+        for queue in self.queues.iter() {
+            log::info!(
+                "  queue p{}q{}: {} pkts, {} bytes, max burst {}",
+                queue.port,
+                queue.queue,
+                queue.nb_pkts,
+                queue.nb_bytes,
+                queue.max_burst
+            );
+        }
+        for class in self
+            .storage_classes
+            .iter()
+            .filter(|class| class.backlog > 0)
+        {
+            log::info!(
+                "  {} class {}: {} pkts waiting",
+                class.queue,
+                class.class,
+                class.backlog
+            );
+        }
+        if let Some(bridge) = &self.async_bridge {
+            log::info!(
+                "  async bridge: {}/{} events queued, {} handlers running",
+                bridge.depth,
+                bridge.capacity,
+                bridge.in_flight
+            );
+        }
+    }
+
+    /// Writes the dump as JSON to a new file in `directory`, and returns its path.
+    pub(crate) fn write(&self, directory: &Path) -> Result<PathBuf> {
+        let path = directory.join(format!("state-{}.json", self.ts));
+        fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(path)
+    }
+}
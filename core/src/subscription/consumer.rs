@@ -0,0 +1,109 @@
+//! Packet consumers attached while the runtime is running.
+//!
+//! In addition to the subscription callback fixed at [Runtime::new](crate::Runtime::new),
+//! closures can be attached to and detached from the [Consumers](Consumers) registry of a
+//! [FilterCtx](crate::filter::FilterCtx) at any time, e.g. to start a temporary high-detail capture
+//! during an incident. Every attached consumer is invoked on each received packet, on the RX core
+//! that received it, before the [pipeline stages](crate::subscription::pipeline) and the
+//! subscription callback.
+//!
+//! The consumer list is replaced as a whole on every change. RX cores keep a reference to the
+//! current list and only take the lock to pick up a new one, so attaching or detaching consumers
+//! does not stall packet processing.
+//!
+//! ## Example
+//! ```
+//! let consumers = filter_ctx.consumers();
+//! let id = consumers.attach(|mbuf| println!("{} bytes", mbuf.data_len()));
+//! // ...
+//! consumers.detach(id);
+//! ```
+
+use crate::memory::mbuf::Mbuf;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub(crate) type Consumer = Arc<dyn Fn(&Mbuf) + Send + Sync>;
+
+/// Identifies an attached consumer.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct ConsumerId(u64);
+
+/// Registry of packet consumers.
+#[derive(Default)]
+pub struct Consumers {
+    list: RwLock<Arc<Vec<(ConsumerId, Consumer)>>>,
+    /// Incremented every time the list changes.
+    generation: AtomicU64,
+    next_id: AtomicU64,
+}
+
+impl Consumers {
+    /// Creates an empty consumer registry.
+    pub fn new() -> Self {
+        Consumers::default()
+    }
+
+    /// Attaches `consumer`, which is invoked on every packet from now on. Returns its identifier.
+    pub fn attach(&self, consumer: impl Fn(&Mbuf) + Send + Sync + 'static) -> ConsumerId {
+        let id = ConsumerId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut list = self.list.write().unwrap();
+        let mut consumers = list.as_ref().clone();
+        consumers.push((id, Arc::new(consumer)));
+        *list = Arc::new(consumers);
+        self.generation.fetch_add(1, Ordering::Release);
+        log::info!("Attached packet consumer {}", id.0);
+        id
+    }
+
+    /// Detaches the consumer `id`. Returns `false` if it was not attached. RX cores may invoke the
+    /// consumer on a few more packets until they pick up the change.
+    pub fn detach(&self, id: ConsumerId) -> bool {
+        let mut list = self.list.write().unwrap();
+        if !list.iter().any(|(other, _)| *other == id) {
+            return false;
+        }
+        let consumers = list
+            .iter()
+            .filter(|(other, _)| *other != id)
+            .cloned()
+            .collect();
+        *list = Arc::new(consumers);
+        self.generation.fetch_add(1, Ordering::Release);
+        log::info!("Detached packet consumer {}", id.0);
+        true
+    }
+
+    /// Returns the number of attached consumers.
+    pub fn len(&self) -> usize {
+        self.list.read().unwrap().len()
+    }
+
+    /// Returns whether no consumer is attached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the current generation of the list.
+    #[inline]
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Returns the current list along with its generation.
+    pub(crate) fn snapshot(&self) -> (u64, Arc<Vec<(ConsumerId, Consumer)>>) {
+        let list = self.list.read().unwrap();
+        (self.generation(), Arc::clone(&list))
+    }
+}
+
+impl fmt::Debug for Consumers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Consumers")
+            .field("len", &self.len())
+            .field("generation", &self.generation())
+            .finish()
+    }
+}
@@ -5,8 +5,14 @@
 //! parameter and immutably borrows values from the environment. Built-in subscribable types can
 //! be customized within the framework to provide additional data to the callback if needed.
 
+pub mod consumer;
+pub mod payload_window;
+pub mod pipeline;
 pub mod zc_frame;
 
+pub use self::consumer::{ConsumerId, Consumers};
+pub use self::payload_window::PayloadWindow;
+pub use self::pipeline::{Pipeline, Stage, StageVerdict};
 pub use self::zc_frame::ZcFrame;
 
 use crate::{memory::mbuf::Mbuf, filter::FilterCtx};
@@ -24,6 +30,24 @@ pub trait Subscribable {
         subscription: &Subscription<Self>
     ) where
         Self: Sized;
+
+    /// Process a burst of incoming packets, in order of arrival. RX cores call this once per
+    /// burst.
+    ///
+    /// The default implementation calls [process_packet](Subscribable::process_packet) on each
+    /// packet. Implementations can override it for batch-level optimizations, such as prefetching
+    /// packet data or scanning several payloads at once.
+    fn process_batch(
+        mbufs: Vec<Mbuf>,
+        filter_ctx: &FilterCtx,
+        subscription: &Subscription<Self>
+    ) where
+        Self: Sized,
+    {
+        for mbuf in mbufs {
+            Self::process_packet(mbuf, filter_ctx, subscription);
+        }
+    }
 }
 
 /// A request for a callback on a subset of traffic specified by the filter.
@@ -53,7 +77,9 @@ where
     /// Invoke the callback on `S`.
     pub(crate) fn invoke(&self, obj: S, filter_ctx: &FilterCtx) {
         tsc_start!(t0);
+        alloc_start!(a0);
         (self.callback)(obj, filter_ctx);
+        alloc_record!(self.timers, "callback", a0);
         tsc_record!(self.timers, "callback", t0);
     }
 }
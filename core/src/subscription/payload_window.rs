@@ -0,0 +1,162 @@
+//! Matched transport-layer payloads with their flow context.
+//!
+//! This is a packet-level subscription for applications that only need the payloads that match
+//! the rules, without parsing protocols or managing the flow table themselves. For each packet,
+//! it parses the transport-layer context with
+//! [FilterCtx::parse_l4](crate::filter::FilterCtx::parse_l4), tracks its flow in the flow table,
+//! records it in the flow's traffic counters, and scans its payload with
+//! [FilterCtx::check_flow_match](crate::filter::FilterCtx::check_flow_match). The payloads of each
+//! received burst are scanned together with
+//! [FilterCtx::check_flow_match_batch](crate::filter::FilterCtx::check_flow_match_batch), so that
+//! they can be offloaded to the [matching backend](crate::filter::backend). The callback is
+//! invoked on the payloads that match, in order of arrival. Packets that fail to parse or carry no
+//! payload are not delivered. With [backfill](crate::filter::backfill), the packets of each flow
+//! that are not delivered are buffered until its first match, and stored before it is delivered.
+//!
+//! Like [ZcFrame](crate::subscription::ZcFrame), a `PayloadWindow` is zero-copy: the payload is
+//! borrowed from the packet buffer, which is freed when the window is dropped. All windows must be
+//! dropped before the runtime is dropped.
+//!
+//! ## Example
+//! Prints the matching payloads of each flow:
+//! ```
+//! let cb = |window: PayloadWindow, filter_ctx: &FilterCtx| {
+//!     let (src, dst) = window.addrs();
+//!     println!("{} -> {} at {}: {:?}", src, dst, window.ts, window.payload());
+//!     filter_ctx.tap_packet_as(window.mbuf(), Priority::ALERT);
+//! };
+//! let mut runtime = Runtime::new(config, cb, &filter_ctx).unwrap();
+//! runtime.run();
+//! ```
+
+use crate::filter::FilterCtx;
+use crate::memory::mbuf::Mbuf;
+use crate::protocols::layer4::Flow;
+use crate::subscription::{Subscribable, Subscription};
+
+use std::net::SocketAddr;
+
+/// A packet whose flow is tracked, before its payload is scanned.
+enum Tracked {
+    /// Packet with a payload.
+    Window(PayloadWindow),
+    /// Packet of a flow without payload.
+    Empty(Flow, Mbuf),
+}
+
+/// A matched transport-layer payload and its flow.
+#[derive(Debug)]
+pub struct PayloadWindow {
+    /// Flow of the packet.
+    pub flow: Flow,
+    /// Index in [Flow::addrs](Flow::addrs) of the sender of the packet, as in
+    /// [FlowSummary::directions](crate::hooks::FlowSummary::directions).
+    pub direction: usize,
+    /// Reception time of the packet, in nanoseconds since the UNIX epoch (see
+    /// [Mbuf::timestamp](crate::memory::mbuf::Mbuf::timestamp)).
+    pub ts: u64,
+    /// Offset of the payload in the packet.
+    offset: usize,
+    length: usize,
+    mbuf: Mbuf,
+}
+
+impl PayloadWindow {
+    /// Tracks the flow of `mbuf` and returns its payload, before it is scanned. `None` if the
+    /// packet fails to parse.
+    fn track(mbuf: Mbuf, filter_ctx: &FilterCtx) -> Option<Tracked> {
+        let ctx = filter_ctx.parse_l4(&mbuf).ok()?;
+        let flow = filter_ctx.track_flow(&ctx, mbuf.data_len());
+        let length = match mbuf.l4_payload(&ctx) {
+            Some(payload) if !payload.is_empty() => payload.len(),
+            _ => return Some(Tracked::Empty(flow, mbuf)),
+        };
+        Some(Tracked::Window(PayloadWindow {
+            flow,
+            direction: flow.direction(&ctx),
+            ts: mbuf.timestamp(),
+            offset: ctx.offset,
+            length,
+            mbuf,
+        }))
+    }
+
+    /// Delivers the window to the callback of `subscription` if its payload `matched`, after the
+    /// packets buffered for its flow are stored, and buffers it otherwise.
+    fn deliver(self, matched: bool, filter_ctx: &FilterCtx, subscription: &Subscription<Self>) {
+        match matched {
+            true => {
+                filter_ctx.flush_backfill(&self.flow);
+                subscription.invoke(self, filter_ctx);
+            }
+            false => filter_ctx.backfill_packet(&self.flow, &self.mbuf),
+        }
+    }
+
+    /// Returns the payload.
+    pub fn payload(&self) -> &[u8] {
+        &self.mbuf.data()[self.offset..self.offset + self.length]
+    }
+
+    /// Returns the socket addresses of the sender and the receiver of the packet.
+    pub fn addrs(&self) -> (SocketAddr, SocketAddr) {
+        let (first, second) = self.flow.addrs();
+        match self.direction {
+            0 => (first, second),
+            _ => (second, first),
+        }
+    }
+
+    /// Returns the packet, e.g. to store it with
+    /// [FilterCtx::tap_packet](crate::filter::FilterCtx::tap_packet).
+    pub fn mbuf(&self) -> &Mbuf {
+        &self.mbuf
+    }
+}
+
+impl Subscribable for PayloadWindow {
+
+    fn process_packet(
+        mbuf: Mbuf,
+        filter_ctx: &FilterCtx,
+        subscription: &Subscription<Self>,
+    ) {
+        match PayloadWindow::track(mbuf, filter_ctx) {
+            Some(Tracked::Window(window)) => {
+                let matched = filter_ctx.check_flow_match(&window.flow, window.payload());
+                window.deliver(matched, filter_ctx, subscription);
+            }
+            Some(Tracked::Empty(flow, mbuf)) => filter_ctx.backfill_packet(&flow, &mbuf),
+            None => {}
+        }
+    }
+
+    fn process_batch(
+        mbufs: Vec<Mbuf>,
+        filter_ctx: &FilterCtx,
+        subscription: &Subscription<Self>,
+    ) {
+        let tracked: Vec<Tracked> = mbufs
+            .into_iter()
+            .filter_map(|mbuf| PayloadWindow::track(mbuf, filter_ctx))
+            .collect();
+        let payloads: Vec<(Flow, &[u8])> = tracked
+            .iter()
+            .filter_map(|tracked| match tracked {
+                Tracked::Window(window) => Some((window.flow, window.payload())),
+                Tracked::Empty(..) => None,
+            })
+            .collect();
+        let mut matched = filter_ctx.check_flow_match_batch(&payloads).into_iter();
+        // Packets are buffered in order of arrival, windows and empty packets alike
+        for tracked in tracked.into_iter() {
+            match tracked {
+                Tracked::Window(window) => {
+                    let matched = matched.next().unwrap_or(false);
+                    window.deliver(matched, filter_ctx, subscription);
+                }
+                Tracked::Empty(flow, mbuf) => filter_ctx.backfill_packet(&flow, &mbuf),
+            }
+        }
+    }
+}
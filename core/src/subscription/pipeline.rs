@@ -0,0 +1,206 @@
+//! Configurable packet processing stages.
+//!
+//! Stages are user-defined steps, e.g. de-duplication, decapsulation or a cheap prefilter, that
+//! every received packet goes through on its RX core before the subscription callback. Each stage
+//! implements [Stage](Stage) and is registered on the [Pipeline](Pipeline) of a
+//! [FilterCtx](crate::filter::FilterCtx). A stage can let the packet through to the next stage or
+//! drop it, in which case it is counted as a [Stage](crate::filter::drops::DropReason::Stage) drop
+//! and the callback is not invoked.
+//!
+//! Stages run in registration order by default. The `[pipeline]` options of the runtime
+//! configuration (see [PipelineConfig](crate::config::PipelineConfig)) select which registered
+//! stages run and in which order, so stages can be added, removed or reordered without touching
+//! the callback.
+//!
+//! As with [consumers](crate::subscription::consumer), the active stage list is replaced as a
+//! whole on every change, and RX cores only take the lock to pick up a new one.
+//!
+//! ## Example
+//! ```
+//! struct Dedup;
+//!
+//! impl Stage for Dedup {
+//!     fn name(&self) -> &str {
+//!         "dedup"
+//!     }
+//!
+//!     fn process(&self, mbuf: &Mbuf, filter_ctx: &FilterCtx) -> StageVerdict {
+//!         // Return StageVerdict::Drop for duplicates
+//!         ...
+//!     }
+//! }
+//!
+//! filter_ctx.pipeline().register(Dedup);
+//! ```
+
+use crate::config::PipelineConfig;
+use crate::filter::FilterCtx;
+use crate::memory::mbuf::Mbuf;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Outcome of a stage on a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageVerdict {
+    /// The packet goes on to the next stage, or to the callback after the last stage.
+    Continue,
+    /// The packet is dropped.
+    Drop,
+}
+
+/// A packet processing stage.
+pub trait Stage: Send + Sync {
+    /// Name of the stage, used to order stages in the configuration and in logs.
+    fn name(&self) -> &str;
+
+    /// Processes `mbuf`, received on the RX core `filter_ctx` is attached to.
+    fn process(&self, mbuf: &Mbuf, filter_ctx: &FilterCtx) -> StageVerdict;
+}
+
+/// Packet counters of a stage.
+#[derive(Debug, Clone)]
+pub struct StageStats {
+    /// Name of the stage.
+    pub name: String,
+    /// Number of packets processed.
+    pub nb_pkts: u64,
+    /// Number of packets dropped.
+    pub nb_dropped: u64,
+}
+
+#[derive(Default)]
+struct StageCounters {
+    nb_pkts: AtomicU64,
+    nb_dropped: AtomicU64,
+}
+
+/// A registered stage and its counters.
+#[derive(Clone)]
+pub(crate) struct ActiveStage {
+    stage: Arc<dyn Stage>,
+    counters: Arc<StageCounters>,
+}
+
+impl ActiveStage {
+    /// Processes `mbuf`. Returns whether it goes on to the next stage.
+    #[inline]
+    pub(crate) fn run(&self, mbuf: &Mbuf, filter_ctx: &FilterCtx) -> bool {
+        self.counters.nb_pkts.fetch_add(1, Ordering::Relaxed);
+        match self.stage.process(mbuf, filter_ctx) {
+            StageVerdict::Continue => true,
+            StageVerdict::Drop => {
+                self.counters.nb_dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+}
+
+/// Registry and order of packet processing stages.
+#[derive(Default)]
+pub struct Pipeline {
+    registered: RwLock<Vec<ActiveStage>>,
+    /// Names of the stages to run, in order. `None` to run all stages in registration order.
+    order: RwLock<Option<Vec<String>>>,
+    active: RwLock<Arc<Vec<ActiveStage>>>,
+    /// Incremented every time the active stages change.
+    generation: AtomicU64,
+}
+
+impl Pipeline {
+    /// Creates an empty pipeline.
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    /// Registers `stage`. It runs after the previously registered stages, unless the configured
+    /// order says otherwise.
+    pub fn register(&self, stage: impl Stage + 'static) {
+        log::info!("Registered pipeline stage: {}", stage.name());
+        self.registered.write().unwrap().push(ActiveStage {
+            stage: Arc::new(stage),
+            counters: Arc::default(),
+        });
+        self.rebuild();
+    }
+
+    /// Applies the stage order of the runtime configuration.
+    pub(crate) fn configure(&self, config: &PipelineConfig) {
+        *self.order.write().unwrap() = Some(config.stages.clone());
+        self.rebuild();
+        let registered = self.registered.read().unwrap();
+        for name in config.stages.iter() {
+            if !registered.iter().any(|active| active.stage.name() == name) {
+                log::warn!("Pipeline stage {} is not registered yet", name);
+            }
+        }
+    }
+
+    /// Recomputes the active stages from the registered stages and the configured order.
+    fn rebuild(&self) {
+        let registered = self.registered.read().unwrap();
+        let active = match &*self.order.read().unwrap() {
+            Some(order) => order
+                .iter()
+                .filter_map(|name| {
+                    registered
+                        .iter()
+                        .find(|active| active.stage.name() == name)
+                        .cloned()
+                })
+                .collect(),
+            None => registered.clone(),
+        };
+        *self.active.write().unwrap() = Arc::new(active);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Returns whether no stage is active.
+    pub fn is_empty(&self) -> bool {
+        self.active.read().unwrap().is_empty()
+    }
+
+    /// Returns the counters of the active stages, in order.
+    pub fn stats(&self) -> Vec<StageStats> {
+        self.active
+            .read()
+            .unwrap()
+            .iter()
+            .map(|active| StageStats {
+                name: active.stage.name().to_string(),
+                nb_pkts: active.counters.nb_pkts.load(Ordering::Relaxed),
+                nb_dropped: active.counters.nb_dropped.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Returns the current generation of the active stages.
+    #[inline]
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Returns the active stages along with their generation.
+    pub(crate) fn snapshot(&self) -> (u64, Arc<Vec<ActiveStage>>) {
+        let active = self.active.read().unwrap();
+        (self.generation(), Arc::clone(&active))
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self
+            .active
+            .read()
+            .unwrap()
+            .iter()
+            .map(|active| active.stage.name().to_string())
+            .collect();
+        f.debug_struct("Pipeline")
+            .field("stages", &names)
+            .field("generation", &self.generation())
+            .finish()
+    }
+}
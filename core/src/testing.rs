@@ -0,0 +1,332 @@
+//! Rule set tests against reference captures.
+//!
+//! [run_pcap](run_pcap) replays the packets of a pcap or pcapng file through a
+//! [FilterCtx](crate::filter::FilterCtx) loaded with a rule set, the same way a packet callback
+//! does: each packet is parsed, its flow is added to the flow table, and its payload is checked
+//! with [FilterCtx::check_flow_match](crate::filter::FilterCtx::check_flow_match). The returned
+//! [TestReport](TestReport) lists the matching packets and flows, along with the rules that
+//! matched each flow, so that rules can be validated against reference captures before they are
+//! deployed, both in CI and by users of the crate. [run_packets](run_packets) replays frames built
+//! with a [PacketBuilder](crate::protocols::packet::builder::PacketBuilder) instead, for traffic
+//! that is easier to describe than to capture.
+//!
+//! The EAL is initialized without devices or hugepages on the first run, so tests can run on any
+//! host with DPDK installed. Packets are replayed as fast as possible: flows do not time out
+//! during a replay, whatever the timestamps of the capture.
+//!
+//! ## Example
+//! ```
+//! let rules = vec![Rule::new("(?i)user-agent: curl")];
+//! let report = testing::run_pcap("tests/http.pcap", rules, &default_config())?;
+//! report.expect_packets(&[3, 17])?;
+//! ```
+
+use crate::config::{MempoolConfig, RuntimeConfig};
+use crate::dpdk;
+use crate::filter::rule::Rule;
+use crate::filter::store::{StoreReader, StoredPacket};
+use crate::filter::FilterCtx;
+use crate::hooks::FlowSummary;
+use crate::lcore::SocketId;
+use crate::memory::mbuf::Mbuf;
+use crate::memory::mempool::Mempool;
+use crate::protocols::packet::builder::PacketBuilder;
+use crate::protocols::layer4::Flow;
+
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::CString;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use regex::bytes::RegexSet;
+use serde::Serialize;
+
+/// MTU of the test mempools, large enough for jumbo frames.
+const TEST_MTU: usize = 9000;
+
+/// Number of mbufs of a test mempool. Packets are freed right after they are checked.
+const TEST_CAPACITY: usize = 1023;
+
+/// Number of test mempools created so far, used to name them.
+static NB_MEMPOOLS: AtomicU64 = AtomicU64::new(0);
+
+/// A packet that matched a rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchedPacket {
+    /// Position of the packet in the capture, starting at `0`.
+    pub index: usize,
+    /// Timestamp of the packet in the capture.
+    pub ts: Duration,
+    /// Flow of the packet.
+    pub flow: Flow,
+}
+
+/// A flow with at least one matching packet.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchedFlow {
+    /// Flow key.
+    pub flow: Flow,
+    /// Number of matching packets of the flow.
+    pub nb_matched: usize,
+    /// Patterns of the rules that matched the flow, in order of first match. Empty if the flow
+    /// could not be tracked, e.g. because the flow table was full.
+    pub matched_rules: Vec<String>,
+}
+
+/// Outcome of a rule set on a capture.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TestReport {
+    /// Number of packets in the capture.
+    pub nb_packets: usize,
+    /// Number of packets that could not be parsed or did not fit in an mbuf.
+    pub nb_unparsed: usize,
+    /// Matching packets, in capture order.
+    pub packets: Vec<MatchedPacket>,
+    /// Matching flows, in order of first match.
+    pub flows: Vec<MatchedFlow>,
+}
+
+impl TestReport {
+    /// Returns the positions of the matching packets in the capture.
+    pub fn matched_indices(&self) -> Vec<usize> {
+        self.packets.iter().map(|packet| packet.index).collect()
+    }
+
+    /// Returns the patterns of the rules that matched any flow.
+    pub fn matched_rules(&self) -> BTreeSet<&str> {
+        self.flows
+            .iter()
+            .flat_map(|flow| flow.matched_rules.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Checks that exactly the packets at positions `expected` matched. The error lists the
+    /// missing and unexpected matches.
+    pub fn expect_packets(&self, expected: &[usize]) -> Result<()> {
+        let matched: BTreeSet<usize> = self.matched_indices().into_iter().collect();
+        let expected: BTreeSet<usize> = expected.iter().copied().collect();
+        let missing: Vec<&usize> = expected.difference(&matched).collect();
+        let unexpected: Vec<&usize> = matched.difference(&expected).collect();
+        if !missing.is_empty() || !unexpected.is_empty() {
+            bail!(
+                "Packet matches differ: missing [{}], unexpected [{}]",
+                missing.iter().format(", "),
+                unexpected.iter().format(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that exactly the rules with patterns `expected` matched. The error lists the
+    /// missing and unexpected rules.
+    pub fn expect_rules(&self, expected: &[&str]) -> Result<()> {
+        let matched = self.matched_rules();
+        let expected: BTreeSet<&str> = expected.iter().copied().collect();
+        let missing: Vec<&&str> = expected.difference(&matched).collect();
+        let unexpected: Vec<&&str> = matched.difference(&expected).collect();
+        if !missing.is_empty() || !unexpected.is_empty() {
+            bail!(
+                "Rule matches differ: missing [{}], unexpected [{}]",
+                missing.iter().format(", "),
+                unexpected.iter().format(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Replays the Ethernet capture `path`, pcap or pcapng, through a filter loaded with `rules` and
+/// configured with `config`, and returns the matching packets and flows.
+pub fn run_pcap(
+    path: impl AsRef<Path>,
+    rules: Vec<Rule>,
+    config: &RuntimeConfig,
+) -> Result<TestReport> {
+    let path = path.as_ref();
+    let mut mempool = test_mempool(&config.mempool)?;
+    let mut replay = Replay::new(rules, config)?;
+    let mut reader = StoreReader::open(path)?;
+    while let Some(StoredPacket { ts, data, .. }) = reader.next_packet()? {
+        match Mbuf::from_bytes(&data, mempool.raw_mut()) {
+            Ok(mbuf) => replay.packet(ts, mbuf),
+            Err(error) => {
+                let index = replay.nb_packets();
+                log::warn!("Packet {} of {} skipped: {}", index, path.display(), error);
+                replay.skip();
+            }
+        }
+    }
+    Ok(replay.finish())
+}
+
+/// Replays the frames built by `packets`, each with its timestamp, through a filter loaded with
+/// `rules` and configured with `config`, like [run_pcap](run_pcap) does with a capture.
+pub fn run_packets(
+    packets: Vec<(Duration, PacketBuilder)>,
+    rules: Vec<Rule>,
+    config: &RuntimeConfig,
+) -> Result<TestReport> {
+    let mut mempool = test_mempool(&config.mempool)?;
+    let mut replay = Replay::new(rules, config)?;
+    for (ts, packet) in packets.into_iter() {
+        match packet.into_mbuf(&mut mempool) {
+            Ok(mbuf) => replay.packet(ts, mbuf),
+            Err(error) => {
+                log::warn!("Packet {} skipped: {}", replay.nb_packets(), error);
+                replay.skip();
+            }
+        }
+    }
+    Ok(replay.finish())
+}
+
+/// A replay in progress.
+struct Replay {
+    filter_ctx: FilterCtx,
+    /// Summaries of the flows removed from the flow table.
+    summaries: Arc<Mutex<Vec<FlowSummary>>>,
+    report: TestReport,
+    /// Matching flows, in order of first match.
+    flows: Vec<Flow>,
+    nb_matched: HashMap<Flow, usize>,
+}
+
+impl Replay {
+    fn new(rules: Vec<Rule>, config: &RuntimeConfig) -> Result<Self> {
+        let filter_ctx = replay_filter(rules, config)?;
+        let summaries = Arc::new(Mutex::new(vec![]));
+        let ended = Arc::clone(&summaries);
+        filter_ctx
+            .hooks()
+            .on_flow_end(move |summary: &FlowSummary| ended.lock().unwrap().push(summary.clone()));
+        Ok(Replay {
+            filter_ctx,
+            summaries,
+            report: TestReport::default(),
+            flows: vec![],
+            nb_matched: HashMap::new(),
+        })
+    }
+
+    /// Returns the number of packets replayed or skipped so far, i.e. the index of the next one.
+    fn nb_packets(&self) -> usize {
+        self.report.nb_packets
+    }
+
+    /// Counts a packet that could not be replayed.
+    fn skip(&mut self) {
+        self.report.nb_packets += 1;
+        self.report.nb_unparsed += 1;
+    }
+
+    /// Processes `mbuf`, received at `ts`, the way a packet callback does.
+    fn packet(&mut self, ts: Duration, mbuf: Mbuf) {
+        let index = self.report.nb_packets;
+        self.report.nb_packets += 1;
+        let ctx = match self.filter_ctx.parse_l4(&mbuf) {
+            Ok(ctx) => ctx,
+            Err(_) => {
+                self.report.nb_unparsed += 1;
+                return;
+            }
+        };
+        let flow = self.filter_ctx.track_flow(&ctx, mbuf.data_len());
+        let payload = match mbuf.l4_payload(&ctx) {
+            Some(payload) => payload,
+            None => {
+                self.report.nb_unparsed += 1;
+                return;
+            }
+        };
+        if self.filter_ctx.check_flow_match(&flow, payload) {
+            self.report.packets.push(MatchedPacket { index, ts, flow });
+            let count = self.nb_matched.entry(flow).or_insert(0);
+            if *count == 0 {
+                self.flows.push(flow);
+            }
+            *count += 1;
+        }
+    }
+
+    /// Ends the replay and returns its report.
+    fn finish(mut self) -> TestReport {
+        // Removing the flows reports their matched rules to the end-of-flow hook
+        for flow in self.flows.iter() {
+            self.filter_ctx.remove_flow(flow);
+        }
+        let summaries = self.summaries.lock().unwrap();
+        self.report.flows = self
+            .flows
+            .iter()
+            .map(|flow| MatchedFlow {
+                flow: *flow,
+                nb_matched: self.nb_matched[flow],
+                matched_rules: summaries
+                    .iter()
+                    .find(|summary| summary.flow == *flow)
+                    .map(|summary| summary.matched_rules.clone())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        self.report
+    }
+}
+
+/// Creates a filter for a replay, loaded with `rules` and configured with `config`.
+pub(crate) fn replay_filter(rules: Vec<Rule>, config: &RuntimeConfig) -> Result<FilterCtx> {
+    // Flows are never pruned during a replay
+    let filter_ctx = FilterCtx::new(0, Duration::MAX, RegexSet::empty());
+    filter_ctx.configure(config)?;
+    filter_ctx.load_rules(rules)?;
+    Ok(filter_ctx)
+}
+
+/// Creates a small mempool without load shedding, with the other options of `config`,
+/// initializing the EAL if needed.
+pub(crate) fn test_mempool(config: &MempoolConfig) -> Result<Mempool> {
+    init_eal()?;
+    let mempool_config = MempoolConfig {
+        capacity: TEST_CAPACITY,
+        cache_size: 0,
+        shed_watermark: None,
+        resume_watermark: config.resume_watermark,
+    };
+    let instance = format!("test{}", NB_MEMPOOLS.fetch_add(1, Ordering::Relaxed));
+    Mempool::new(&mempool_config, Some(&instance), SocketId(0), TEST_MTU)
+}
+
+/// Initializes the EAL without devices or hugepages, once per process.
+fn init_eal() -> Result<()> {
+    static EAL: OnceLock<bool> = OnceLock::new();
+    let initialized = *EAL.get_or_init(|| {
+        dpdk::load_drivers();
+        let eal_params = [
+            "retina-testing".to_owned(),
+            "--no-pci".to_owned(),
+            "--no-huge".to_owned(),
+            "-m".to_owned(),
+            "128".to_owned(),
+            "-l".to_owned(),
+            "0".to_owned(),
+            "--file-prefix".to_owned(),
+            format!("retina-testing-{}", std::process::id()),
+            "--log-level=6".to_owned(),
+            "--no-telemetry".to_owned(),
+        ];
+        let args: Vec<CString> = eal_params
+            .iter()
+            .map(|arg| CString::new(arg.as_str()).unwrap())
+            .collect();
+        let ptrs: Vec<*mut u8> = args.iter().map(|arg| arg.as_ptr() as *mut u8).collect();
+        let ret = unsafe { dpdk::rte_eal_init(ptrs.len() as i32, ptrs.as_ptr() as *mut _) };
+        ret >= 0
+    });
+    if !initialized {
+        bail!("Failure initializing EAL");
+    }
+    Ok(())
+}
@@ -0,0 +1,349 @@
+//! Unified wall-clock timebase.
+//!
+//! Stored packets, alerts, journal records, flow summaries, trace records and statistics rows are
+//! all timestamped in nanoseconds since the UNIX epoch by this module, so that the outputs of a
+//! run can be correlated with each other and with external sources.
+//!
+//! Timestamps are read from the TSC, calibrated against `CLOCK_REALTIME` once the EAL is
+//! initialized, which is cheaper than a system call per packet. The monitor resynchronizes the
+//! timebase every [RESYNC_INTERVAL](RESYNC_INTERVAL), measuring the TSC frequency over each
+//! interval. Drift is corrected by speeding up or slowing down the timebase until it meets the
+//! system clock, so that timestamps never step backwards, unless the clock is more than a second
+//! off, e.g. after it was set. Before calibration, timestamps are read from the system clock.
+//!
+//! ## Hardware RX timestamps
+//! Ports that support RX timestamp offload, unless disabled with
+//! [PortMap::rx_timestamp](crate::config::PortMap), timestamp packets on reception. Each port clock
+//! is calibrated against the timebase when the port starts, and resynchronized with it like the
+//! TSC, so that hardware timestamps are converted to the same wall-clock time.
+//! [Mbuf::timestamp](crate::memory::mbuf::Mbuf::timestamp) returns the hardware timestamp of a
+//! packet if it has one, and the current time of the timebase otherwise, e.g. if the NIC does not
+//! support the offload or cannot read its clock. The tap, the capture, sink samples and payload
+//! windows are timestamped this way.
+//!
+//! ## Example
+//! ```
+//! for record in filter_ctx.dump_trace(core) {
+//!     let age = timebase::now_ns().saturating_sub(record.ts);
+//!     println!("{} ({} ns ago)", record, age);
+//! }
+//! ```
+
+use crate::dpdk;
+use crate::memory::mbuf::Mbuf;
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+
+/// Interval at which the monitor resynchronizes the timebase with the system clock.
+pub const RESYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Fractional bits of [MULT](MULT).
+const SHIFT: u32 = 32;
+
+/// Largest offset from the system clock corrected without stepping the timebase. Larger offsets,
+/// e.g. after the clock was set, step timestamps to the clock.
+const MAX_SLEW: Duration = Duration::from_secs(1);
+
+/// Interval over which the clock of a port is measured when it is calibrated.
+const PORT_CALIBRATION_INTERVAL: Duration = Duration::from_millis(100);
+
+const MAX_PORTS: usize = dpdk::RTE_MAX_ETHPORTS as usize;
+
+/// The TSC, against the system clock.
+static TSC: Clock = Clock::new();
+/// The clock of each port, against the TSC timebase.
+static PORTS: [Clock; MAX_PORTS] = [NO_CLOCK; MAX_PORTS];
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_CLOCK: Clock = Clock::new();
+
+/// Offset of the RX timestamp dynamic field in mbufs, and the offload flag set on packets that
+/// carry one, `0` until registered.
+static RX_TS_OFFSET: AtomicUsize = AtomicUsize::new(0);
+static RX_TS_FLAG: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy)]
+struct Calibration {
+    base_ticks: u64,
+    base_ns: u64,
+    mult: u64,
+}
+
+impl Calibration {
+    /// Returns the wall-clock time of clock value `ticks`.
+    #[inline]
+    fn ns(&self, ticks: u64) -> u64 {
+        let delta = ticks.wrapping_sub(self.base_ticks) as i64 as i128;
+        let offset = (delta * self.mult as i128) >> SHIFT;
+        (self.base_ns as i128 + offset).max(0) as u64
+    }
+}
+
+/// A clock calibrated against a reference clock.
+#[derive(Debug)]
+struct Clock {
+    /// Sequence number of the calibration, odd while it is being updated.
+    seq: AtomicU64,
+    /// Clock value and wall-clock time of the last synchronization, `0` before calibration.
+    base_ticks: AtomicU64,
+    base_ns: AtomicU64,
+    /// Nanoseconds per tick, in fixed point with [SHIFT](SHIFT) fractional bits.
+    mult: AtomicU64,
+    /// Nominal frequency of the clock, in Hz.
+    hz: AtomicU64,
+    /// Clock value and reference time of the last synchronization.
+    sync_ticks: AtomicU64,
+    sync_ns: AtomicU64,
+}
+
+impl Clock {
+    const fn new() -> Self {
+        Clock {
+            seq: AtomicU64::new(0),
+            base_ticks: AtomicU64::new(0),
+            base_ns: AtomicU64::new(0),
+            mult: AtomicU64::new(0),
+            hz: AtomicU64::new(0),
+            sync_ticks: AtomicU64::new(0),
+            sync_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads the current calibration, `None` before calibration.
+    #[inline]
+    fn calibration(&self) -> Option<Calibration> {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let calibration = Calibration {
+                base_ticks: self.base_ticks.load(Ordering::Relaxed),
+                base_ns: self.base_ns.load(Ordering::Relaxed),
+                mult: self.mult.load(Ordering::Relaxed),
+            };
+            if self.seq.load(Ordering::Acquire) == seq {
+                return (calibration.mult > 0).then_some(calibration);
+            }
+        }
+    }
+
+    /// Replaces the calibration. Called by a single thread at a time.
+    fn store(&self, calibration: Calibration) {
+        self.seq.fetch_add(1, Ordering::AcqRel);
+        self.base_ticks
+            .store(calibration.base_ticks, Ordering::Relaxed);
+        self.base_ns.store(calibration.base_ns, Ordering::Relaxed);
+        self.mult.store(calibration.mult, Ordering::Relaxed);
+        self.seq.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Calibrates the clock, running at `hz`, at clock value `ticks` and reference time `real`.
+    fn calibrate(&self, ticks: u64, real: u64, hz: u64) {
+        self.hz.store(hz, Ordering::Relaxed);
+        self.sync_ticks.store(ticks, Ordering::Relaxed);
+        self.sync_ns.store(real, Ordering::Relaxed);
+        self.store(Calibration {
+            base_ticks: ticks,
+            base_ns: real,
+            mult: mult(hz),
+        });
+    }
+
+    /// Resynchronizes the clock at clock value `ticks` with reference time `real`. Returns the
+    /// offset of the reference from the clock if it was reset. Called by a single thread at a time.
+    fn resync(&self, ticks: u64, real: u64) -> Option<i128> {
+        let current = self.calibration()?;
+        let estimate = current.ns(ticks);
+        let elapsed_ticks = ticks.wrapping_sub(self.sync_ticks.swap(ticks, Ordering::Relaxed));
+        let elapsed_ns = real.saturating_sub(self.sync_ns.swap(real, Ordering::Relaxed));
+        if real.abs_diff(estimate) > MAX_SLEW.as_nanos() as u64 {
+            self.store(Calibration {
+                base_ticks: ticks,
+                base_ns: real,
+                mult: mult(self.hz.load(Ordering::Relaxed)),
+            });
+            return Some(real as i128 - estimate as i128);
+        }
+        let hz = match (elapsed_ticks as u128 * 1_000_000_000).checked_div(elapsed_ns as u128) {
+            Some(hz) if hz > 0 => hz as u64,
+            _ => self.hz.load(Ordering::Relaxed),
+        };
+        // Run at the rate that meets the reference at the next resynchronization, within half and
+        // twice the measured rate
+        let interval_ns = RESYNC_INTERVAL.as_nanos();
+        let interval_ticks = (hz as u128 * interval_ns / 1_000_000_000).max(1);
+        let remaining_ns = (real as u128 + interval_ns).saturating_sub(estimate as u128);
+        let nominal = mult(hz);
+        let slewed = ((remaining_ns << SHIFT) / interval_ticks).min(u64::MAX as u128) as u64;
+        self.store(Calibration {
+            base_ticks: ticks,
+            base_ns: estimate,
+            mult: slewed.clamp(nominal / 2, nominal * 2),
+        });
+        None
+    }
+}
+
+fn system_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |ts| ts.as_nanos() as u64)
+}
+
+/// Returns the fixed point nanoseconds per cycle of a TSC running at `hz`.
+fn mult(hz: u64) -> u64 {
+    ((1_000_000_000_u128 << SHIFT) / hz.max(1) as u128) as u64
+}
+
+/// Calibrates the timebase against the system clock. Requires the EAL to be initialized.
+pub(crate) fn calibrate() {
+    let hz = unsafe { dpdk::rte_get_tsc_hz() };
+    if hz == 0 {
+        log::warn!("Unknown TSC frequency, timestamps are read from the system clock");
+        return;
+    }
+    let tsc = unsafe { dpdk::rte_rdtsc() };
+    TSC.calibrate(tsc, system_ns(), hz);
+    log::info!("Timebase calibrated, TSC at {} Hz", hz);
+}
+
+/// Resynchronizes the timebase with the system clock, then the port clocks with the timebase.
+/// Called by a single thread at a time.
+pub(crate) fn resync() {
+    let tsc = unsafe { dpdk::rte_rdtsc() };
+    if let Some(offset) = TSC.resync(tsc, system_ns()) {
+        log::warn!(
+            "System clock is {} ns off the timebase, resetting it",
+            offset
+        );
+    }
+    for (port_id, clock) in PORTS.iter().enumerate() {
+        if clock.calibration().is_none() {
+            continue;
+        }
+        let ticks = match read_port_clock(port_id as u16) {
+            Ok(ticks) => ticks,
+            Err(error) => {
+                log::debug!("{}", error);
+                continue;
+            }
+        };
+        if let Some(offset) = clock.resync(ticks, now_ns()) {
+            log::warn!(
+                "Clock of Port {} is {} ns off the timebase, resetting it",
+                port_id,
+                offset
+            );
+        }
+    }
+}
+
+/// Registers the RX timestamp dynamic field of mbufs. Returns whether packets can carry hardware
+/// timestamps.
+pub(crate) fn register_rx_timestamp() -> bool {
+    if RX_TS_FLAG.load(Ordering::Relaxed) != 0 {
+        return true;
+    }
+    let mut offset = 0;
+    let mut flag = 0;
+    let ret = unsafe { dpdk::rte_mbuf_dyn_rx_timestamp_register(&mut offset, &mut flag) };
+    if ret != 0 || offset < 0 || flag == 0 {
+        log::warn!("Failed to register the RX timestamp mbuf field: {}", ret);
+        return false;
+    }
+    RX_TS_OFFSET.store(offset as usize, Ordering::Relaxed);
+    RX_TS_FLAG.store(flag, Ordering::Relaxed);
+    true
+}
+
+fn read_port_clock(port_id: u16) -> Result<u64> {
+    let mut ticks = 0;
+    let ret = unsafe { dpdk::rte_eth_read_clock(port_id, &mut ticks) };
+    if ret != 0 {
+        bail!("Failed to read the clock of Port {}: {}", port_id, ret);
+    }
+    Ok(ticks)
+}
+
+/// Calibrates the clock of started port `port_id` against the timebase, measuring its frequency
+/// over a short interval. Until then, or if it fails, hardware timestamps of the port are ignored.
+pub(crate) fn calibrate_port(port_id: u16) -> Result<()> {
+    let clock = match PORTS.get(port_id as usize) {
+        Some(clock) => clock,
+        None => bail!("Port {} is beyond the port clocks", port_id),
+    };
+    let (start_ticks, start_ns) = (read_port_clock(port_id)?, now_ns());
+    thread::sleep(PORT_CALIBRATION_INTERVAL);
+    let (ticks, real) = (read_port_clock(port_id)?, now_ns());
+    let hz = match (ticks.wrapping_sub(start_ticks) as u128 * 1_000_000_000)
+        .checked_div(real.saturating_sub(start_ns) as u128)
+    {
+        Some(hz) if hz > 0 => hz as u64,
+        _ => bail!("Clock of Port {} does not advance", port_id),
+    };
+    clock.calibrate(ticks, real, hz);
+    log::info!(
+        "Hardware RX timestamps enabled on Port {}, clock at {} Hz",
+        port_id,
+        hz
+    );
+    Ok(())
+}
+
+/// Returns the hardware RX timestamp of `mbuf`, in nanoseconds since the UNIX epoch, `None` if the
+/// packet was not timestamped by the NIC or the clock of its port is not calibrated.
+#[inline]
+pub(crate) fn rx_timestamp(mbuf: &Mbuf) -> Option<u64> {
+    let flag = RX_TS_FLAG.load(Ordering::Relaxed);
+    let raw = mbuf.raw();
+    if flag == 0 || raw.ol_flags & flag == 0 {
+        return None;
+    }
+    let calibration = PORTS.get(raw.port as usize)?.calibration()?;
+    let offset = RX_TS_OFFSET.load(Ordering::Relaxed);
+    // Safety: the registered field is within the mbuf, and set on packets with the flag.
+    let ticks = unsafe {
+        let field = (raw as *const dpdk::rte_mbuf as *const u8).add(offset);
+        (field as *const u64).read_unaligned()
+    };
+    Some(calibration.ns(ticks))
+}
+
+/// Returns the current wall-clock time, in nanoseconds since the UNIX epoch.
+#[inline]
+pub fn now_ns() -> u64 {
+    match TSC.calibration() {
+        Some(calibration) => calibration.ns(unsafe { dpdk::rte_rdtsc() }),
+        None => system_ns(),
+    }
+}
+
+/// Returns the current wall-clock time, as the duration since the UNIX epoch.
+#[inline]
+pub fn now() -> Duration {
+    Duration::from_nanos(now_ns())
+}
+
+/// Returns the wall-clock time of TSC value `tsc`, e.g. of a
+/// [TraceRecord](crate::filter::trace::TraceRecord), in nanoseconds since the UNIX epoch.
+pub fn tsc_to_ns(tsc: u64) -> u64 {
+    match TSC.calibration() {
+        Some(calibration) => calibration.ns(tsc),
+        None => system_ns(),
+    }
+}
+
+/// Returns the wall-clock time of `instant`, in nanoseconds since the UNIX epoch.
+pub fn instant_ns(instant: Instant) -> u64 {
+    let now = Instant::now();
+    let ns = now_ns();
+    match instant.checked_duration_since(now) {
+        Some(ahead) => ns + ahead.as_nanos() as u64,
+        None => ns.saturating_sub(now.duration_since(instant).as_nanos() as u64),
+    }
+}
@@ -0,0 +1,120 @@
+//! Hot-path allocation profiling.
+//!
+//! With the `alloc-profile` feature, the global allocator is wrapped by a counting allocator that
+//! keeps per-thread counts of allocations and allocated bytes. Stages of the packet processing
+//! path instrumented with the `alloc_start!` and `alloc_record!` macros record the allocations
+//! made while they ran, which are reported next to the cycle timers in the timing dump
+//! (`alloc_stats.csv`). A stage that allocates on every packet shows up with a non-zero average per call.
+//!
+//! Counts are kept per thread, so that the allocations of other cores and of the main core are not
+//! attributed to a stage. Nested stages are counted in each enclosing stage, e.g. the callback is
+//! also counted in `process`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+thread_local! {
+    static NB_ALLOCS: Cell<u64> = const { Cell::new(0) };
+    static NB_BYTES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// System allocator that counts the allocations of each thread.
+struct CountingAllocator;
+
+impl CountingAllocator {
+    #[inline]
+    fn count(size: usize) {
+        // `try_with` fails once the thread-local storage is destroyed, on thread exit.
+        let _ = NB_ALLOCS.try_with(|count| count.set(count.get() + 1));
+        let _ = NB_BYTES.try_with(|count| count.set(count.get() + size as u64));
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        Self::count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Allocations made by the current thread.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AllocCount {
+    nb_allocs: u64,
+    nb_bytes: u64,
+}
+
+impl AllocCount {
+    /// Returns the allocations made by the current thread so far.
+    #[inline]
+    pub(crate) fn now() -> Self {
+        AllocCount {
+            nb_allocs: NB_ALLOCS.with(|count| count.get()),
+            nb_bytes: NB_BYTES.with(|count| count.get()),
+        }
+    }
+
+    /// Returns the allocations made by the current thread since `self`.
+    #[inline]
+    pub(crate) fn elapsed(&self) -> AllocCount {
+        let now = AllocCount::now();
+        AllocCount {
+            nb_allocs: now.nb_allocs - self.nb_allocs,
+            nb_bytes: now.nb_bytes - self.nb_bytes,
+        }
+    }
+}
+
+/// Allocation counters of an instrumented stage.
+#[derive(Debug, Default)]
+pub(crate) struct AllocCounter {
+    nb_calls: AtomicU64,
+    nb_allocs: AtomicU64,
+    nb_bytes: AtomicU64,
+    /// Largest number of allocations of a single call.
+    max_allocs: AtomicU64,
+}
+
+impl AllocCounter {
+    #[inline]
+    pub(crate) fn record(&self, count: AllocCount) {
+        self.nb_calls.fetch_add(1, Ordering::Relaxed);
+        self.nb_allocs.fetch_add(count.nb_allocs, Ordering::Relaxed);
+        self.nb_bytes.fetch_add(count.nb_bytes, Ordering::Relaxed);
+        self.max_allocs.fetch_max(count.nb_allocs, Ordering::Relaxed);
+    }
+
+    /// Returns calls, allocs, bytes, allocs per call, bytes per call and max allocs per call.
+    pub(crate) fn stats(&self) -> Vec<String> {
+        let nb_calls = self.nb_calls.load(Ordering::Relaxed);
+        let nb_allocs = self.nb_allocs.load(Ordering::Relaxed);
+        let nb_bytes = self.nb_bytes.load(Ordering::Relaxed);
+        let per_call = |value: u64| value as f64 / nb_calls.max(1) as f64;
+        vec![
+            format!("{}", nb_calls),
+            format!("{}", nb_allocs),
+            format!("{}", nb_bytes),
+            format!("{:.3}", per_call(nb_allocs)),
+            format!("{:.3}", per_call(nb_bytes)),
+            format!("{}", self.max_allocs.load(Ordering::Relaxed)),
+        ]
+    }
+}
@@ -19,3 +19,17 @@ macro_rules! tsc_record {
         );
     };
 }
+
+macro_rules! alloc_start {
+    ( $start:ident ) => {
+        #[cfg(feature = "alloc-profile")]
+        let $start = $crate::timing::alloc::AllocCount::now();
+    };
+}
+
+macro_rules! alloc_record {
+    ( $timers:expr, $stage:expr, $start:ident ) => {
+        #[cfg(feature = "alloc-profile")]
+        $timers.record_allocs($stage, $start.elapsed());
+    };
+}
@@ -2,3 +2,5 @@
 pub(crate) mod macros;
 #[cfg(feature = "timing")]
 pub(crate) mod timer;
+#[cfg(feature = "alloc-profile")]
+pub(crate) mod alloc;
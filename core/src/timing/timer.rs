@@ -9,12 +9,27 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
+#[cfg(feature = "alloc-profile")]
+use super::alloc::{AllocCount, AllocCounter};
+
 lazy_static::lazy_static! {
     static ref STATS: Vec<&'static str> =  vec!["name", "cnt", "rec", "avg", "min", "p05", "p25", "p50", "p75", "p95", "p99", "p999", "max"];
 }
 
+#[cfg(feature = "alloc-profile")]
+const ALLOC_STATS: [&str; 7] = [
+    "name", "calls", "allocs", "bytes", "allocs/call", "bytes/call", "max allocs",
+];
+
+/// Stages of the packet processing path instrumented for allocations.
+#[cfg(feature = "alloc-profile")]
+const ALLOC_STAGES: [&str; 4] = ["consumers", "pipeline", "process", "callback"];
+
 #[derive(Debug)]
-pub(crate) struct Timers(IndexMap<String, Mutex<CycleTimer>>);
+pub(crate) struct Timers(
+    IndexMap<String, Mutex<CycleTimer>>,
+    #[cfg(feature = "alloc-profile")] IndexMap<String, AllocCounter>,
+);
 
 impl Timers {
     pub(crate) fn new() -> Self {
@@ -39,7 +54,24 @@ impl Timers {
         init_hist(&mut timers, "builder");
         init_hist(&mut timers, "callback");
         init_hist(&mut timers, "remove_inactive");
-        Timers(timers)
+        Timers(
+            timers,
+            #[cfg(feature = "alloc-profile")]
+            ALLOC_STAGES
+                .iter()
+                .map(|stage| (stage.to_string(), AllocCounter::default()))
+                .collect(),
+        )
+    }
+
+    /// Records the allocations `count` of a call of `stage`.
+    #[cfg(feature = "alloc-profile")]
+    pub(crate) fn record_allocs(&self, stage: &str, count: AllocCount) {
+        if let Some(counter) = self.1.get(stage) {
+            counter.record(count);
+        } else {
+            log::error!("No allocation counter found for: {}", stage);
+        }
     }
 
     pub(crate) fn record(&self, which: &str, value: u64, sample: u64) {
@@ -69,6 +101,21 @@ impl Timers {
             table.add_row(Row::new(cells));
         }
         table.printstd();
+
+        #[cfg(feature = "alloc-profile")]
+        {
+            let mut table = Table::new();
+            table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+            let title = ALLOC_STATS.iter().map(|n| Cell::new(n)).collect::<Vec<_>>();
+            table.set_titles(Row::new(title));
+            for (name, counter) in self.1.iter() {
+                let mut stats = vec![name.to_owned()];
+                stats.extend(counter.stats());
+                let cells = stats.iter().map(|s| Cell::new(s)).collect();
+                table.add_row(Row::new(cells));
+            }
+            table.printstd();
+        }
     }
 
     pub(crate) fn dump_stats(&self) {
@@ -82,6 +129,22 @@ impl Timers {
         let json_fname = Path::new("cycle_vec.json").to_path_buf();
         vecs.dump_json(json_fname)
             .expect("Unable to dump to cycle vec data");
+
+        #[cfg(feature = "alloc-profile")]
+        self.dump_allocs(Path::new("alloc_stats.csv"))
+            .expect("Unable to dump to alloc stats");
+    }
+
+    #[cfg(feature = "alloc-profile")]
+    fn dump_allocs(&self, path: &Path) -> Result<()> {
+        let mut wtr = Writer::from_path(path)?;
+        wtr.write_record(ALLOC_STATS.iter())?;
+        for (name, counter) in self.1.iter() {
+            wtr.write_field(name)?;
+            wtr.write_record(counter.stats())?;
+        }
+        wtr.flush()?;
+        Ok(())
     }
 }
 
@@ -0,0 +1,110 @@
+//! Stable hashing.
+//!
+//! The hashers of `std` are randomly seeded (`RandomState`) or not guaranteed to produce the same
+//! values across Rust releases (`DefaultHasher`). Flows and rule patterns are instead hashed with
+//! the FxHash algorithm and a fixed seed, so that flow table and shard mappings are reproducible
+//! across runs and versions. The flow hash seed is set with
+//! [FlowKeyConfig::hash_seed](crate::config::FlowKeyConfig::hash_seed).
+
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Multiplier of the FxHash algorithm.
+const K: u64 = 0x517c_c1b7_2722_0a95;
+
+/// A fast, non-cryptographic hasher that only depends on its seed and input.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StableHasher {
+    hash: u64,
+}
+
+impl StableHasher {
+    /// Creates a hasher seeded with `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        StableHasher { hash: seed }
+    }
+
+    #[inline]
+    fn add_to_hash(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(K);
+    }
+}
+
+impl Hasher for StableHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in chunks.by_ref() {
+            let mut word = [0; 8];
+            word.copy_from_slice(chunk);
+            self.add_to_hash(u64::from_le_bytes(word));
+        }
+        for byte in chunks.remainder() {
+            self.add_to_hash(*byte as u64);
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.add_to_hash(i as u64);
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.add_to_hash(i as u64);
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.add_to_hash(i as u64);
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.add_to_hash(i);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.add_to_hash(i as u64);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// Returns the stable hash of `value` with `seed`.
+pub fn stable_hash<T: Hash + ?Sized>(value: &T, seed: u64) -> u64 {
+    let mut hasher = StableHasher::with_seed(seed);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hasher builder of the flow table. The seed is shared by all clones, so that it can be set
+/// after the flow table is created, while it is still empty.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FlowHashState {
+    seed: Arc<AtomicU64>,
+}
+
+impl FlowHashState {
+    pub(crate) fn seed(&self) -> u64 {
+        self.seed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_seed(&self, seed: u64) {
+        self.seed.store(seed, Ordering::Relaxed);
+    }
+}
+
+impl BuildHasher for FlowHashState {
+    type Hasher = StableHasher;
+
+    #[inline]
+    fn build_hasher(&self) -> StableHasher {
+        StableHasher::with_seed(self.seed())
+    }
+}
@@ -1,4 +1,5 @@
 //! Utility modules.
 
 pub mod base64;
+pub mod hash;
 pub mod types;